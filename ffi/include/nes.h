@@ -0,0 +1,117 @@
+/* Generated by cbindgen from ffi/src/lib.rs, don't edit. */
+
+#ifndef NES_H
+#define NES_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define NES_SCREEN_WIDTH 256
+
+#define NES_SCREEN_HEIGHT 240
+
+// Bits of the buttons given to `nes_set_input`.
+#define NES_BUTTON_A (1 << 0)
+
+#define NES_BUTTON_B (1 << 1)
+
+#define NES_BUTTON_SELECT (1 << 2)
+
+#define NES_BUTTON_START (1 << 3)
+
+#define NES_BUTTON_UP (1 << 4)
+
+#define NES_BUTTON_DOWN (1 << 5)
+
+#define NES_BUTTON_LEFT (1 << 6)
+
+#define NES_BUTTON_RIGHT (1 << 7)
+
+// A console and the buffers handed out to the caller, opaque to C.
+typedef struct NesHandle NesHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a console without a cartridge, free it with `nes_free`.
+struct NesHandle *nes_new(void);
+
+// Free a console and everything returned for it.
+//
+// # Safety
+//
+// `nes` is NULL or a handle from `nes_new`, it can't be used afterwards.
+void nes_free(struct NesHandle *nes);
+
+// Insert a cartridge from the contents of an iNES file and power on.
+//
+// # Safety
+//
+// `rom` points to `len` readable bytes, they're copied.
+bool nes_load_rom(struct NesHandle *nes, const uint8_t *rom, size_t len);
+
+// Run until the next frame is complete, nothing happens without a cartridge.
+//
+// # Safety
+//
+// `nes` is a handle from `nes_new`.
+void nes_step_frame(struct NesHandle *nes);
+
+// The last complete frame as RGBA, `NES_SCREEN_WIDTH` * `NES_SCREEN_HEIGHT` * 4 bytes. Valid
+// until the next call to `nes_frame_rgba` or `nes_free`.
+//
+// # Safety
+//
+// `nes` is a handle from `nes_new`.
+const uint8_t *nes_frame_rgba(struct NesHandle *nes);
+
+// The last complete frame as indices into the system palette, `NES_SCREEN_WIDTH` *
+// `NES_SCREEN_HEIGHT` bytes. Valid until the next call to `nes_step_frame`, `nes_load_rom` or
+// `nes_free`.
+//
+// # Safety
+//
+// `nes` is a handle from `nes_new`.
+const uint8_t *nes_frame(const struct NesHandle *nes);
+
+// Hold the buttons, `NES_BUTTON_*` bits, on the controller of player 0 or 1 from the next frame
+// on.
+//
+// # Safety
+//
+// `nes` is a handle from `nes_new`.
+bool nes_set_input(struct NesHandle *nes, size_t player, uint8_t buttons);
+
+// Save the state of the console into `buffer` if it fits in `capacity` bytes. Returns the size of
+// the state either way, call with a NULL buffer to find out how much room it needs. Returns 0 on
+// failure.
+//
+// # Safety
+//
+// `buffer` is NULL or points to `capacity` writable bytes.
+size_t nes_save_state(struct NesHandle *nes, uint8_t *buffer, size_t capacity);
+
+// Restore a state from `nes_save_state`, of the cartridge that's inserted.
+//
+// # Safety
+//
+// `state` points to `len` readable bytes.
+bool nes_load_state(struct NesHandle *nes, const uint8_t *state, size_t len);
+
+// Message of the last failure, NULL if nothing failed yet. Valid until the next failure or
+// `nes_free`.
+//
+// # Safety
+//
+// `nes` is a handle from `nes_new`.
+const char *nes_last_error(const struct NesHandle *nes);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NES_H */
@@ -0,0 +1,266 @@
+/// C interface to the emulator, for frontends that aren't written in Rust.
+///
+/// Build with `cargo build --release -p nes-ffi` for a shared (libnes_ffi.so) and a static
+/// library, the declarations are in include/nes.h. A frontend creates a console, inserts a
+/// cartridge and then steps it a frame at a time:
+///
+/// ```c
+/// NesHandle *nes = nes_new();
+/// if (!nes_load_rom(nes, rom, rom_len)) {
+///     fprintf(stderr, "%s\n", nes_last_error(nes));
+/// }
+/// for (;;) {
+///     nes_set_input(nes, 0, NES_BUTTON_START);
+///     nes_step_frame(nes);
+///     draw(nes_frame_rgba(nes));
+/// }
+/// nes_free(nes);
+/// ```
+///
+/// Every function but `nes_new` takes a handle from `nes_new` that hasn't been freed. Functions
+/// that can fail return false or 0 and keep a message for `nes_last_error`.
+use nes::controller::ControllerState;
+use nes::ines::NesFile;
+use nes::Nes;
+use std::ffi::CString;
+use std::fmt::Display;
+use std::os::raw::c_char;
+use std::ptr;
+use std::slice;
+
+pub const NES_SCREEN_WIDTH: usize = 256;
+pub const NES_SCREEN_HEIGHT: usize = 240;
+
+/// Bits of the buttons given to `nes_set_input`.
+pub const NES_BUTTON_A: u8 = 1 << 0;
+pub const NES_BUTTON_B: u8 = 1 << 1;
+pub const NES_BUTTON_SELECT: u8 = 1 << 2;
+pub const NES_BUTTON_START: u8 = 1 << 3;
+pub const NES_BUTTON_UP: u8 = 1 << 4;
+pub const NES_BUTTON_DOWN: u8 = 1 << 5;
+pub const NES_BUTTON_LEFT: u8 = 1 << 6;
+pub const NES_BUTTON_RIGHT: u8 = 1 << 7;
+
+/// A console and the buffers handed out to the caller, opaque to C.
+pub struct NesHandle {
+    nes: Nes,
+
+    /// The frame returned by `nes_frame_rgba`.
+    rgba: Vec<u8>,
+
+    /// Message of the last failure.
+    error: Option<CString>,
+}
+
+impl NesHandle {
+    /// Keep the message for `nes_last_error`, returns false for the caller to return.
+    fn fail(&mut self, err: impl Display) -> bool {
+        // Messages don't contain NULs, drop it rather than fail to report it.
+        self.error = CString::new(err.to_string()).ok();
+        false
+    }
+}
+
+/// Create a console without a cartridge, free it with `nes_free`.
+#[no_mangle]
+pub extern "C" fn nes_new() -> *mut NesHandle {
+    Box::into_raw(Box::new(NesHandle {
+        nes: Nes::new(),
+        rgba: Vec::new(),
+        error: None,
+    }))
+}
+
+/// Free a console and everything returned for it.
+///
+/// # Safety
+///
+/// `nes` is NULL or a handle from `nes_new`, it can't be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn nes_free(nes: *mut NesHandle) {
+    if !nes.is_null() {
+        drop(Box::from_raw(nes));
+    }
+}
+
+/// Insert a cartridge from the contents of an iNES file and power on.
+///
+/// # Safety
+///
+/// `rom` points to `len` readable bytes, they're copied.
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_rom(nes: *mut NesHandle, rom: *const u8, len: usize) -> bool {
+    let handle = &mut *nes;
+    match NesFile::from_bytes(slice::from_raw_parts(rom, len)) {
+        Ok(cartridge) => {
+            handle.nes.insert_cartridge(cartridge);
+            true
+        }
+        Err(err) => handle.fail(err),
+    }
+}
+
+/// Run until the next frame is complete, nothing happens without a cartridge.
+///
+/// # Safety
+///
+/// `nes` is a handle from `nes_new`.
+#[no_mangle]
+pub unsafe extern "C" fn nes_step_frame(nes: *mut NesHandle) {
+    (*nes).nes.step_frame();
+}
+
+/// The last complete frame as RGBA, `NES_SCREEN_WIDTH` * `NES_SCREEN_HEIGHT` * 4 bytes. Valid
+/// until the next call to `nes_frame_rgba` or `nes_free`.
+///
+/// # Safety
+///
+/// `nes` is a handle from `nes_new`.
+#[no_mangle]
+pub unsafe extern "C" fn nes_frame_rgba(nes: *mut NesHandle) -> *const u8 {
+    let handle = &mut *nes;
+    handle.rgba = handle.nes.frame_rgba();
+    handle.rgba.as_ptr()
+}
+
+/// The last complete frame as indices into the system palette, `NES_SCREEN_WIDTH` *
+/// `NES_SCREEN_HEIGHT` bytes. Valid until the next call to `nes_step_frame`, `nes_load_rom` or
+/// `nes_free`.
+///
+/// # Safety
+///
+/// `nes` is a handle from `nes_new`.
+#[no_mangle]
+pub unsafe extern "C" fn nes_frame(nes: *const NesHandle) -> *const u8 {
+    (*nes).nes.frame().as_ptr()
+}
+
+/// Hold the buttons, `NES_BUTTON_*` bits, on the controller of player 0 or 1 from the next frame
+/// on.
+///
+/// # Safety
+///
+/// `nes` is a handle from `nes_new`.
+#[no_mangle]
+pub unsafe extern "C" fn nes_set_input(nes: *mut NesHandle, player: usize, buttons: u8) -> bool {
+    let handle = &mut *nes;
+    if player > 1 {
+        return handle.fail(format!("Invalid player {}, expected 0 or 1.", player));
+    }
+
+    handle.nes.set_input(player, ControllerState(buttons));
+    true
+}
+
+/// Save the state of the console into `buffer` if it fits in `capacity` bytes. Returns the size of
+/// the state either way, call with a NULL buffer to find out how much room it needs. Returns 0 on
+/// failure.
+///
+/// # Safety
+///
+/// `buffer` is NULL or points to `capacity` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nes_save_state(
+    nes: *mut NesHandle,
+    buffer: *mut u8,
+    capacity: usize,
+) -> usize {
+    let handle = &mut *nes;
+    let state = match handle.nes.cpu().map(|cpu| cpu.save_state()) {
+        Some(Ok(state)) => state,
+        Some(Err(err)) => {
+            handle.fail(err);
+            return 0;
+        }
+        None => {
+            handle.fail("No cartridge inserted.");
+            return 0;
+        }
+    };
+
+    if !buffer.is_null() && state.len() <= capacity {
+        ptr::copy_nonoverlapping(state.as_ptr(), buffer, state.len());
+    }
+
+    state.len()
+}
+
+/// Restore a state from `nes_save_state`, of the cartridge that's inserted.
+///
+/// # Safety
+///
+/// `state` points to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_state(nes: *mut NesHandle, state: *const u8, len: usize) -> bool {
+    let handle = &mut *nes;
+    let result = match handle.nes.cpu_mut() {
+        Some(cpu) => cpu.load_state(slice::from_raw_parts(state, len)),
+        None => return handle.fail("No cartridge inserted."),
+    };
+
+    match result {
+        Ok(()) => true,
+        Err(err) => handle.fail(err),
+    }
+}
+
+/// Message of the last failure, NULL if nothing failed yet. Valid until the next failure or
+/// `nes_free`.
+///
+/// # Safety
+///
+/// `nes` is a handle from `nes_new`.
+#[no_mangle]
+pub unsafe extern "C" fn nes_last_error(nes: *const NesHandle) -> *const c_char {
+    match &(*nes).error {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::ines::test_util;
+    use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_ffi() {
+        assert_eq!(
+            (NES_SCREEN_WIDTH, NES_SCREEN_HEIGHT),
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        );
+        assert_eq!(NES_BUTTON_START, ControllerState::START);
+        assert_eq!(NES_BUTTON_RIGHT, ControllerState::RIGHT);
+
+        let rom = test_util::spinning_nrom_file();
+        unsafe {
+            let nes = nes_new();
+            assert!(nes_last_error(nes).is_null());
+            assert_eq!(nes_save_state(nes, ptr::null_mut(), 0), 0);
+            assert!(!nes_load_rom(nes, rom.as_ptr(), 8));
+            assert!(!CStr::from_ptr(nes_last_error(nes)).to_bytes().is_empty());
+
+            assert!(nes_load_rom(nes, rom.as_ptr(), rom.len()));
+
+            assert!(nes_set_input(nes, 1, NES_BUTTON_A));
+            assert!(!nes_set_input(nes, 2, NES_BUTTON_A));
+            nes_step_frame(nes);
+            assert!(!nes_frame_rgba(nes).is_null());
+            assert!(!nes_frame(nes).is_null());
+
+            let size = nes_save_state(nes, ptr::null_mut(), 0);
+            let mut state = vec![0; size];
+            assert_eq!(nes_save_state(nes, state.as_mut_ptr(), state.len()), size);
+
+            nes_step_frame(nes);
+            assert_eq!((*nes).nes.cpu().unwrap().ppu.frame_count(), 2);
+            assert!(nes_load_state(nes, state.as_ptr(), state.len()));
+            assert_eq!((*nes).nes.cpu().unwrap().ppu.frame_count(), 1);
+            assert!(!nes_load_state(nes, state.as_ptr(), 4));
+
+            nes_free(nes);
+        }
+    }
+}
@@ -0,0 +1,12 @@
+#![no_main]
+/// Any bytes as a ROM, parsing either fails or gives sizes that match the header.
+/// Run with `cargo fuzz run ines` from the repository's root.
+use libfuzzer_sys::fuzz_target;
+use nes::ines::NesFile;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(nes_file) = NesFile::from_bytes(data) {
+        assert_eq!(nes_file.prg_rom.len(), data[4] as usize * 0x4000);
+        assert_eq!(nes_file.chr_rom.len(), data[5] as usize * 0x2000);
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+/// Any ROM the parser accepts can be inserted, the console powering on with it.
+/// Run with `cargo fuzz run power_on` from the repository's root.
+use libfuzzer_sys::fuzz_target;
+use nes::ines::NesFile;
+use nes::Nes;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(nes_file) = NesFile::from_bytes(data) {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(nes_file);
+    }
+});
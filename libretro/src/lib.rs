@@ -0,0 +1,503 @@
+/// A libretro core, for running the emulator in frontends such as RetroArch with their video,
+/// audio and input drivers, save states, rewind and run-ahead.
+///
+/// Build with `cargo build --release -p nes-libretro`, then e.g.
+/// `retroarch -L target/release/libnes_libretro.so game.nes`. Cheats from the frontend take Game
+/// Genie and raw codes, several joined with "+".
+use nes::audio::Resampler;
+use nes::cheats::Cheats;
+use nes::controller::ControllerState;
+use nes::ines::NesFile;
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH, SYSTEM_PALETTE};
+use nes::region::Region;
+use nes::Nes;
+use std::cell::{Cell, RefCell};
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::sync::{Arc, Mutex};
+use std::{ptr, slice};
+
+mod sys;
+
+use sys::*;
+
+const SAMPLE_RATE: u32 = 44100;
+
+/// Size of the console's RAM, mirrored up to $1FFF.
+const RAM_SIZE: usize = 0x800;
+
+/// Room left in serialized states for the parts that vary in size, e.g. sprites being fetched.
+const SERIALIZE_SLACK: usize = 1024;
+
+/// The NES buttons by libretro joypad ID.
+const BUTTONS: [(c_uint, u8); 8] = [
+    (RETRO_DEVICE_ID_JOYPAD_A, ControllerState::A),
+    (RETRO_DEVICE_ID_JOYPAD_B, ControllerState::B),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, ControllerState::SELECT),
+    (RETRO_DEVICE_ID_JOYPAD_START, ControllerState::START),
+    (RETRO_DEVICE_ID_JOYPAD_UP, ControllerState::UP),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, ControllerState::DOWN),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, ControllerState::LEFT),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, ControllerState::RIGHT),
+];
+
+/// What the frontend handed over, set before a game is loaded.
+#[derive(Clone, Copy, Default)]
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+/// The loaded game.
+struct Core {
+    nes: Nes,
+
+    /// The iNES file, inserted again on reset.
+    rom: Vec<u8>,
+
+    /// Audio of the current frame, interleaved stereo.
+    samples: Arc<Mutex<Vec<i16>>>,
+
+    /// The frame as XRGB8888.
+    video: Vec<u32>,
+}
+
+impl Core {
+    fn new(rom: Vec<u8>) -> Result<Self, String> {
+        let mut core = Core {
+            nes: Nes::new(),
+            rom,
+            samples: Arc::default(),
+            video: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        };
+        core.power_on()?;
+
+        Ok(core)
+    }
+
+    /// Insert the cartridge again, keeping the cheats.
+    fn power_on(&mut self) -> Result<(), String> {
+        let cartridge = NesFile::from_bytes(&self.rom).map_err(|err| err.to_string())?;
+        let cheats = self
+            .nes
+            .cpu_mut()
+            .map(|cpu| std::mem::take(&mut cpu.cheats))
+            .unwrap_or_default();
+        self.nes.insert_cartridge(cartridge);
+        self.samples.lock().unwrap().clear();
+
+        let samples = self.samples.clone();
+        if let Some(cpu) = self.nes.cpu_mut() {
+            let mut resampler = Resampler::new(cpu.apu.sample_rate(), SAMPLE_RATE);
+            cpu.cheats = cheats;
+            cpu.apu.on_sample(move |sample| {
+                if let Some(sample) = resampler.push(sample) {
+                    let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                    samples.lock().unwrap().extend_from_slice(&[sample, sample]);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    fn run(&mut self, callbacks: Callbacks) {
+        if let (Some(input_poll), Some(input_state)) = (callbacks.input_poll, callbacks.input_state)
+        {
+            unsafe { input_poll() };
+            for port in 0..2 {
+                let mut buttons = ControllerState(0);
+                for &(id, button) in &BUTTONS {
+                    let pressed = unsafe { input_state(port, RETRO_DEVICE_JOYPAD, 0, id) } != 0;
+                    buttons.set(button, pressed);
+                }
+                self.nes.set_input(port as usize, buttons);
+            }
+        }
+
+        self.nes.step_frame();
+
+        for (pixel, &index) in self.video.iter_mut().zip(self.nes.frame()) {
+            let (r, g, b) = SYSTEM_PALETTE[(index & 0x3F) as usize];
+            *pixel = u32::from_be_bytes([0, r, g, b]);
+        }
+        if let Some(video_refresh) = callbacks.video_refresh {
+            unsafe {
+                video_refresh(
+                    self.video.as_ptr() as *const c_void,
+                    SCREEN_WIDTH as c_uint,
+                    SCREEN_HEIGHT as c_uint,
+                    SCREEN_WIDTH * 4,
+                )
+            };
+        }
+
+        let mut samples = self.samples.lock().unwrap();
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            unsafe { audio_sample_batch(samples.as_ptr(), samples.len() / 2) };
+        }
+        samples.clear();
+    }
+}
+
+thread_local! {
+    static CALLBACKS: Cell<Callbacks> = Cell::default();
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn set_callbacks(set: impl FnOnce(&mut Callbacks)) {
+    CALLBACKS.with(|callbacks| {
+        let mut updated = callbacks.get();
+        set(&mut updated);
+        callbacks.set(updated);
+    });
+}
+
+/// Run `f` on the loaded game, `default` without one.
+fn with_core<T>(default: T, f: impl FnOnce(&mut Core) -> T) -> T {
+    CORE.with(|core| core.borrow_mut().as_mut().map_or(default, f))
+}
+
+/// Region of the loaded game, NTSC when there's none.
+fn region() -> Region {
+    with_core(Region::default(), |core| {
+        core.nes.cpu().map_or(Region::default(), |cpu| cpu.region())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: Option<RetroEnvironment>) {
+    set_callbacks(|callbacks| callbacks.environment = callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: Option<RetroVideoRefresh>) {
+    set_callbacks(|callbacks| callbacks.video_refresh = callback);
+}
+
+/// Unused, audio is handed over a frame at a time through the batch callback.
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: Option<RetroAudioSample>) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: Option<RetroAudioSampleBatch>) {
+    set_callbacks(|callbacks| callbacks.audio_sample_batch = callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: Option<RetroInputPoll>) {
+    set_callbacks(|callbacks| callbacks.input_poll = callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: Option<RetroInputState>) {
+    set_callbacks(|callbacks| callbacks.input_state = callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    retro_unload_game();
+}
+
+/// # Safety
+///
+/// `info` points to a writable `retro_system_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: b"nes\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"nes\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+///
+/// `info` points to a writable `retro_system_av_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: SCREEN_WIDTH as c_uint,
+            base_height: SCREEN_HEIGHT as c_uint,
+            max_width: SCREEN_WIDTH as c_uint,
+            max_height: SCREEN_HEIGHT as c_uint,
+            aspect_ratio: 4.0 / 3.0,
+        },
+        timing: RetroSystemTiming {
+            fps: region().frame_rate(),
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+/// Only standard controllers are supported.
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+/// Power cycle the console, there's no soft reset.
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(Ok(()), Core::power_on).unwrap_or_else(|err| eprintln!("Failed to reset: {}", err));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = CALLBACKS.with(Cell::get);
+    with_core((), |core| core.run(callbacks));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(0, |core| match core.nes.cpu().map(|cpu| cpu.save_state()) {
+        Some(Ok(state)) => state.len() + SERIALIZE_SLACK,
+        _ => 0,
+    })
+}
+
+/// States are padded with zeros to the size asked for, which loading ignores.
+///
+/// # Safety
+///
+/// `data` points to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let data = slice::from_raw_parts_mut(data as *mut u8, size);
+    with_core(false, |core| {
+        match core.nes.cpu().map(|cpu| cpu.save_state()) {
+            Some(Ok(state)) if state.len() <= size => {
+                let (state_data, padding) = data.split_at_mut(state.len());
+                state_data.copy_from_slice(&state);
+                padding.fill(0);
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+/// # Safety
+///
+/// `data` points to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let state = slice::from_raw_parts(data as *const u8, size);
+    with_core(false, |core| match core.nes.cpu_mut() {
+        Some(cpu) => match cpu.load_state(state) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("Failed to load the state: {}", err);
+                false
+            }
+        },
+        None => false,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_core((), |core| {
+        if let Some(cpu) = core.nes.cpu_mut() {
+            cpu.cheats = Cheats::default();
+        }
+    });
+}
+
+/// # Safety
+///
+/// `code` is a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, enabled: bool, code: *const c_char) {
+    let code = CStr::from_ptr(code).to_string_lossy();
+    with_core((), |core| {
+        let cpu = match core.nes.cpu_mut() {
+            Some(cpu) => cpu,
+            None => return,
+        };
+
+        for code in code.split('+') {
+            match code.parse() {
+                Ok(cheat) => {
+                    let index = cpu.cheats.add(cheat);
+                    cpu.cheats.set_enabled(index, enabled);
+                }
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+    });
+}
+
+/// # Safety
+///
+/// `game` is NULL or points to a `retro_game_info` with the contents of the file.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+
+    let callbacks = CALLBACKS.with(Cell::get);
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    let format_supported = callbacks.environment.is_some_and(|environment| {
+        environment(
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+            &mut format as *mut c_uint as *mut c_void,
+        )
+    });
+    if !format_supported {
+        eprintln!("The frontend doesn't support XRGB8888.");
+        return false;
+    }
+
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+    match Core::new(rom) {
+        Ok(core) => {
+            CORE.with(|loaded| *loaded.borrow_mut() = Some(core));
+            true
+        }
+        Err(err) => {
+            eprintln!("Failed to load the game: {}", err);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    match region() {
+        Region::Ntsc => RETRO_REGION_NTSC,
+        Region::Pal | Region::Dendy => RETRO_REGION_PAL,
+    }
+}
+
+/// The console's RAM, for the frontend's cheat search and achievements.
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != RETRO_MEMORY_SYSTEM_RAM {
+        return ptr::null_mut();
+    }
+
+    with_core(ptr::null_mut(), |core| match core.nes.cpu_mut() {
+        Some(cpu) => cpu.memory.as_mut_ptr() as *mut c_void,
+        None => ptr::null_mut(),
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id == RETRO_MEMORY_SYSTEM_RAM {
+        with_core(0, |_| RAM_SIZE)
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::ines::test_util;
+
+    thread_local! {
+        static FRAMES: Cell<usize> = const { Cell::new(0) };
+        static AUDIO_FRAMES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        cmd == RETRO_ENVIRONMENT_SET_PIXEL_FORMAT
+            && *(data as *const c_uint) == RETRO_PIXEL_FORMAT_XRGB8888
+    }
+
+    unsafe extern "C" fn video_refresh(_: *const c_void, width: c_uint, height: c_uint, _: usize) {
+        assert_eq!((width, height), (256, 240));
+        FRAMES.with(|frames| frames.set(frames.get() + 1));
+    }
+
+    unsafe extern "C" fn audio_sample_batch(_: *const i16, frames: usize) -> usize {
+        AUDIO_FRAMES.with(|audio_frames| audio_frames.set(audio_frames.get() + frames));
+        frames
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    unsafe extern "C" fn input_state(port: c_uint, _: c_uint, _: c_uint, id: c_uint) -> i16 {
+        (port == 0 && id == RETRO_DEVICE_ID_JOYPAD_START) as i16
+    }
+
+    #[test]
+    fn test_core() {
+        retro_set_environment(Some(environment));
+        retro_set_video_refresh(Some(video_refresh));
+        retro_set_audio_sample_batch(Some(audio_sample_batch));
+        retro_set_input_poll(Some(input_poll));
+        retro_set_input_state(Some(input_state));
+        retro_init();
+
+        let rom = test_util::spinning_nrom_file();
+        let game = RetroGameInfo {
+            path: ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: ptr::null(),
+        };
+        assert!(unsafe { retro_load_game(&game) });
+        assert_eq!(retro_get_memory_size(RETRO_MEMORY_SYSTEM_RAM), RAM_SIZE);
+
+        unsafe { retro_cheat_set(0, true, b"0300:42+0301:43\0".as_ptr() as *const c_char) };
+
+        retro_run();
+        assert_eq!(FRAMES.with(Cell::get), 1);
+        assert!(AUDIO_FRAMES.with(Cell::get) > 0);
+
+        with_core((), |core| {
+            let cpu = core.nes.cpu_mut().unwrap();
+            assert_eq!(
+                cpu.controllers[0].state(),
+                ControllerState(ControllerState::START)
+            );
+            assert_eq!(cpu.read(0x0301), 0x43);
+        });
+
+        let ram = retro_get_memory_data(RETRO_MEMORY_SYSTEM_RAM);
+        let mut state = vec![0xFFu8; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+        retro_run();
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+        assert_eq!(retro_get_memory_data(RETRO_MEMORY_SYSTEM_RAM), ram);
+        with_core((), |core| {
+            assert_eq!(core.nes.cpu().unwrap().ppu.frame_count(), 1)
+        });
+
+        retro_cheat_reset();
+        retro_reset();
+        retro_unload_game();
+        assert!(retro_get_memory_data(RETRO_MEMORY_SYSTEM_RAM).is_null());
+        retro_deinit();
+    }
+}
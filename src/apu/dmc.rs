@@ -0,0 +1,282 @@
+use crate::region::Region;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Timer periods in CPU cycles for each rate (NTSC).
+const RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// The same for the slower PAL clock.
+const PAL_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// Delta modulation channel, plays 1-bit delta encoded samples from CPU memory.
+///
+/// The memory reader can't access the bus itself, the CPU polls `dma_address()` and stalls while
+/// it fetches the byte.
+/// See http://wiki.nesdev.com/w/index.php/APU_DMC.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Dmc {
+    /// Raise an IRQ when a non-looping sample ends.
+    irq_enabled: bool,
+
+    /// Set when a sample finished with IRQs enabled.
+    irq_flag: bool,
+
+    /// Restart the sample when it ends.
+    looping: bool,
+
+    /// Timer period in CPU cycles.
+    timer_period: u16,
+
+    timer: u16,
+
+    /// 7-bit output level.
+    output_level: u8,
+
+    /// Address of the start of the sample.
+    sample_address: u16,
+
+    /// Length of the sample in bytes.
+    sample_length: u16,
+
+    /// Address of the next byte to fetch.
+    current_address: u16,
+
+    /// Bytes left to fetch in the sample.
+    bytes_remaining: u16,
+
+    /// Byte fetched by the memory reader, waiting to be played.
+    sample_buffer: Option<u8>,
+
+    /// Bits being played.
+    shift_register: u8,
+
+    /// Bits left in the shift register.
+    bits_remaining: u8,
+
+    /// The output level is held when there was no sample to play.
+    silence: bool,
+
+    /// Selects the rate table.
+    region: Region,
+}
+
+impl Dmc {
+    const IRQ_ENABLED_MASK: u8 = 0b1000_0000;
+    const LOOP_MASK: u8 = 0b0100_0000;
+    const RATE_MASK: u8 = 0b0000_1111;
+    const OUTPUT_LEVEL_MASK: u8 = 0b0111_1111;
+
+    pub fn new() -> Self {
+        Dmc {
+            timer_period: RATES[0],
+            sample_address: 0xC000,
+            sample_length: 1,
+            bits_remaining: 8,
+            silence: true,
+            ..Dmc::default()
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Handle a write to one of the channel's four registers.
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value & Self::IRQ_ENABLED_MASK != 0;
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
+
+                self.looping = value & Self::LOOP_MASK != 0;
+                let rates = if self.region.pal_apu() {
+                    &PAL_RATES
+                } else {
+                    &RATES
+                };
+                self.timer_period = rates[(value & Self::RATE_MASK) as usize];
+            }
+            1 => self.output_level = value & Self::OUTPUT_LEVEL_MASK,
+            2 => self.sample_address = 0xC000 + value as u16 * 64,
+            3 => self.sample_length = value as u16 * 16 + 1,
+            _ => (),
+        }
+    }
+
+    /// Enable or disable the channel through $4015.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    /// Whether there are still bytes of the sample to fetch.
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Address the memory reader wants to fetch, if the sample buffer needs refilling.
+    pub fn dma_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    /// Receive the byte fetched from `dma_address()`.
+    pub fn dma_complete(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+
+        // The address wraps around to $8000.
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift_register & 0x01 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Current output level, 0 to 127.
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+impl fmt::Display for Dmc {
+    /// e.g. "period 428  sample $C000+$0100 at $C042 (190 left) loop  output 64".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "period {}  sample ${:04X}+${:04X} at ${:04X} ({} left){}{}{}  output {}",
+            self.timer_period,
+            self.sample_address,
+            self.sample_length,
+            self.current_address,
+            self.bytes_remaining,
+            if self.looping { " loop" } else { "" },
+            if self.irq_enabled { " irq" } else { "" },
+            if self.irq_flag { " pending" } else { "" },
+            self.output()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the channel for a number of CPU cycles, serving DMA requests from `memory` mirrored
+    /// from $C000.
+    fn run(dmc: &mut Dmc, memory: &[u8], cycles: u64) -> u32 {
+        let mut fetches = 0;
+        for _ in 0..cycles {
+            if let Some(addr) = dmc.dma_address() {
+                dmc.dma_complete(memory[(addr - 0xC000) as usize % memory.len()]);
+                fetches += 1;
+            }
+            dmc.clock_timer();
+        }
+        fetches
+    }
+
+    #[test]
+    fn test_sample_playback() {
+        let memory = [0xFF, 0x00];
+        let mut dmc = Dmc::new();
+
+        // Fastest rate, 1 byte sample at $C000 with IRQ.
+        dmc.write(0, Dmc::IRQ_ENABLED_MASK | 0x0F);
+        dmc.write(1, 0x40);
+        dmc.write(2, 0x00);
+        dmc.write(3, 0x00);
+        dmc.set_enabled(true);
+
+        // The first byte is fetched straight away.
+        assert_eq!(run(&mut dmc, &memory, 1), 1);
+        assert!(!dmc.is_active());
+        assert!(dmc.irq_flag());
+
+        // After the initial silent byte, the 1s bits ramp the output up.
+        run(&mut dmc, &memory, 54 * 16);
+        assert_eq!(dmc.output(), 0x40 + 16);
+    }
+
+    #[test]
+    fn test_looping() {
+        let memory = [0xFF, 0x00];
+        let mut dmc = Dmc::new();
+
+        // Fastest rate, looping, 17 bytes.
+        dmc.write(0, Dmc::IRQ_ENABLED_MASK | Dmc::LOOP_MASK | 0x0F);
+        dmc.write(3, 0x01);
+        dmc.set_enabled(true);
+
+        let fetches = run(&mut dmc, &memory, 54 * 8 * 40);
+        assert!(fetches >= 40);
+        assert!(dmc.is_active());
+        assert!(!dmc.irq_flag());
+    }
+}
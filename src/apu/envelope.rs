@@ -0,0 +1,87 @@
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Envelope generator, produces either a constant volume or a decaying saw.
+/// See http://wiki.nesdev.com/w/index.php/APU_Envelope.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Envelope {
+    /// Restart the decay on the next clock.
+    start: bool,
+
+    /// Restart the decay when it reaches 0, shares the bit with the length counter halt.
+    looping: bool,
+
+    /// Output the volume directly instead of the decay level.
+    constant_volume: bool,
+
+    /// Constant volume, also the period of the divider.
+    volume: u8,
+
+    divider: u8,
+
+    /// Current decay level, 15 down to 0.
+    decay: u8,
+}
+
+impl Envelope {
+    const LOOP_MASK: u8 = 0b0010_0000;
+    const CONSTANT_VOLUME_MASK: u8 = 0b0001_0000;
+    const VOLUME_MASK: u8 = 0b0000_1111;
+
+    /// Handle a write to the channel's first register.
+    pub fn write(&mut self, value: u8) {
+        self.looping = value & Self::LOOP_MASK != 0;
+        self.constant_volume = value & Self::CONSTANT_VOLUME_MASK != 0;
+        self.volume = value & Self::VOLUME_MASK;
+    }
+
+    /// Restart the envelope, done when the channel's last register is written.
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Clocked by the frame counter every quarter frame.
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    /// Current volume, 0 to 15.
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+impl fmt::Display for Envelope {
+    /// e.g. "volume 15" or "decay 12/15 loop".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.constant_volume {
+            write!(f, "volume {}", self.volume)
+        } else {
+            write!(
+                f,
+                "decay {}/{}{}",
+                self.decay,
+                self.volume,
+                if self.looping { " loop" } else { "" }
+            )
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use crate::region::Region;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// CPU cycles at which each step of the sequence happens (NTSC).
+const STEP_CYCLES: [u64; 4] = [7457, 14913, 22371, 29829];
+
+/// Last step of the 5-step sequence (NTSC).
+const FIVE_STEP_LAST_CYCLE: u64 = 37281;
+
+/// The same for the slower PAL clock.
+const PAL_STEP_CYCLES: [u64; 4] = [8313, 16627, 24939, 33252];
+const PAL_FIVE_STEP_LAST_CYCLE: u64 = 41565;
+
+/// Clocks produced by the frame counter on a given cycle.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameClock {
+    /// Clock envelopes and the triangle's linear counter.
+    pub quarter: bool,
+
+    /// Clock length counters and sweep units.
+    pub half: bool,
+}
+
+impl FrameClock {
+    const NONE: FrameClock = FrameClock {
+        quarter: false,
+        half: false,
+    };
+    const QUARTER: FrameClock = FrameClock {
+        quarter: true,
+        half: false,
+    };
+    const HALF: FrameClock = FrameClock {
+        quarter: true,
+        half: true,
+    };
+}
+
+/// The APU frame counter.
+///
+/// Generates the quarter and half frame clocks driving the envelopes, length counters and sweep
+/// units, and optionally an IRQ at the end of each sequence.
+/// See http://wiki.nesdev.com/w/index.php/APU_Frame_Counter.
+#[derive(Default, Deserialize, Serialize)]
+pub struct FrameCounter {
+    /// 5-step sequence instead of the 4-step sequence.
+    five_step: bool,
+
+    /// Prevents the frame IRQ flag from being set.
+    irq_inhibit: bool,
+
+    /// Set at the end of the 4-step sequence.
+    irq_flag: bool,
+
+    /// CPU cycles since the start of the sequence.
+    cycle: u64,
+
+    /// Writes to $4017 take effect after a short delay: (cycles remaining, value written).
+    pending_write: Option<(u8, u8)>,
+
+    /// Selects the step cycles.
+    region: Region,
+}
+
+impl FrameCounter {
+    const MODE_MASK: u8 = 0b1000_0000;
+    const IRQ_INHIBIT_MASK: u8 = 0b0100_0000;
+
+    /// Handle a write to $4017. `odd_cycle` is whether the write happens on an odd CPU cycle.
+    pub fn write(&mut self, value: u8, odd_cycle: bool) {
+        self.irq_inhibit = value & Self::IRQ_INHIBIT_MASK != 0;
+        if self.irq_inhibit {
+            self.irq_flag = false;
+        }
+
+        // The sequence is reset 3 or 4 cycles after the write depending on the CPU cycle.
+        let delay = if odd_cycle { 4 } else { 3 };
+        self.pending_write = Some((delay, value));
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Cycles of the steps and of the last step of the 5-step sequence.
+    fn step_cycles(&self) -> (&'static [u64; 4], u64) {
+        if self.region.pal_apu() {
+            (&PAL_STEP_CYCLES, PAL_FIVE_STEP_LAST_CYCLE)
+        } else {
+            (&STEP_CYCLES, FIVE_STEP_LAST_CYCLE)
+        }
+    }
+
+    /// Whether the frame IRQ flag is set.
+    pub fn irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    /// Clear the frame IRQ flag.
+    pub fn clear_irq_flag(&mut self) {
+        self.irq_flag = false;
+    }
+
+    /// Advance a CPU cycle, returns the clocks to send to the channels.
+    pub fn tick(&mut self) -> FrameClock {
+        if let Some((delay, value)) = self.pending_write {
+            if delay == 1 {
+                self.pending_write = None;
+                self.five_step = value & Self::MODE_MASK != 0;
+                self.cycle = 0;
+
+                // Entering 5-step mode immediately clocks all the units.
+                if self.five_step {
+                    return FrameClock::HALF;
+                }
+                return FrameClock::NONE;
+            }
+
+            self.pending_write = Some((delay - 1, value));
+        }
+
+        self.cycle += 1;
+        let (step_cycles, five_step_last_cycle) = self.step_cycles();
+
+        if self.five_step {
+            match self.cycle {
+                c if c == step_cycles[0] || c == step_cycles[2] => FrameClock::QUARTER,
+                c if c == step_cycles[1] => FrameClock::HALF,
+                c if c == five_step_last_cycle => {
+                    self.cycle = 0;
+                    FrameClock::HALF
+                }
+                _ => FrameClock::NONE,
+            }
+        } else {
+            let last = step_cycles[3];
+
+            // The IRQ flag is set for the last three cycles of the sequence.
+            if (last - 1..=last + 1).contains(&self.cycle) && !self.irq_inhibit {
+                self.irq_flag = true;
+            }
+
+            match self.cycle {
+                c if c == step_cycles[0] || c == step_cycles[2] => FrameClock::QUARTER,
+                c if c == step_cycles[1] || c == last => FrameClock::HALF,
+                c if c == last + 1 => {
+                    self.cycle = 0;
+                    FrameClock::NONE
+                }
+                _ => FrameClock::NONE,
+            }
+        }
+    }
+}
+
+impl fmt::Display for FrameCounter {
+    /// e.g. "4-step  cycle 7460 (step 1)  irq pending".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let steps = if self.five_step { 5 } else { 4 };
+        let step = self
+            .step_cycles()
+            .0
+            .iter()
+            .filter(|&&cycle| cycle <= self.cycle)
+            .count();
+
+        write!(f, "{}-step  cycle {} (step {})", steps, self.cycle, step)?;
+
+        if self.irq_inhibit {
+            write!(f, "  irq inhibited")?;
+        }
+        if self.irq_flag {
+            write!(f, "  irq pending")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a whole sequence, returns the cycles of (quarter, half) clocks.
+    fn run_sequence(counter: &mut FrameCounter, cycles: u64) -> (Vec<u64>, Vec<u64>) {
+        let mut quarters = vec![];
+        let mut halves = vec![];
+
+        for cycle in 1..=cycles {
+            let clock = counter.tick();
+            if clock.quarter {
+                quarters.push(cycle);
+            }
+            if clock.half {
+                halves.push(cycle);
+            }
+        }
+
+        (quarters, halves)
+    }
+
+    #[test]
+    fn test_four_step() {
+        let mut counter = FrameCounter::default();
+
+        let (quarters, halves) = run_sequence(&mut counter, 29830);
+        assert_eq!(quarters, vec![7457, 14913, 22371, 29829]);
+        assert_eq!(halves, vec![14913, 29829]);
+        assert!(counter.irq_flag());
+
+        // The sequence repeats.
+        let (quarters, _) = run_sequence(&mut counter, 29830);
+        assert_eq!(quarters, vec![7457, 14913, 22371, 29829]);
+    }
+
+    #[test]
+    fn test_five_step() {
+        let mut counter = FrameCounter::default();
+        counter.write(FrameCounter::MODE_MASK, false);
+
+        // Units are clocked as soon as the write takes effect.
+        let (quarters, halves) = run_sequence(&mut counter, 3);
+        assert_eq!(quarters, vec![3]);
+        assert_eq!(halves, vec![3]);
+
+        let (quarters, halves) = run_sequence(&mut counter, 37281);
+        assert_eq!(quarters, vec![7457, 14913, 22371, 37281]);
+        assert_eq!(halves, vec![14913, 37281]);
+        assert!(!counter.irq_flag());
+    }
+
+    #[test]
+    fn test_pal() {
+        let mut counter = FrameCounter::default();
+        counter.set_region(Region::Pal);
+
+        let (quarters, halves) = run_sequence(&mut counter, 33253);
+        assert_eq!(quarters, vec![8313, 16627, 24939, 33252]);
+        assert_eq!(halves, vec![16627, 33252]);
+        assert!(counter.irq_flag());
+
+        // Dendy keeps the NTSC sequence.
+        counter.set_region(Region::Dendy);
+        let (quarters, _) = run_sequence(&mut counter, 29830);
+        assert_eq!(quarters, vec![7457, 14913, 22371, 29829]);
+    }
+
+    #[test]
+    fn test_irq_inhibit() {
+        let mut counter = FrameCounter::default();
+        counter.write(FrameCounter::IRQ_INHIBIT_MASK, true);
+
+        run_sequence(&mut counter, 29834);
+        assert!(!counter.irq_flag());
+    }
+}
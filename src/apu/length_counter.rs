@@ -0,0 +1,69 @@
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Lengths loaded from the upper 5 bits of the channel's last register.
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Length counter, silences a channel after a number of half frames.
+/// See http://wiki.nesdev.com/w/index.php/APU_Length_Counter.
+#[derive(Default, Deserialize, Serialize)]
+pub struct LengthCounter {
+    /// Enabled through $4015, the counter is held at 0 while disabled.
+    enabled: bool,
+
+    /// Stops the counter from decrementing.
+    halt: bool,
+
+    counter: u8,
+}
+
+impl LengthCounter {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn set_halt(&mut self, halt: bool) {
+        self.halt = halt;
+    }
+
+    /// Load the counter from the upper 5 bits of a register write.
+    pub fn load(&mut self, value: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(value >> 3) as usize];
+        }
+    }
+
+    /// Clocked by the frame counter every half frame.
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    /// The channel is silenced once the counter reaches 0.
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+impl fmt::Display for LengthCounter {
+    /// e.g. "length 10", "length 10 halted" or "length disabled".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.enabled {
+            return write!(f, "length disabled");
+        }
+
+        write!(
+            f,
+            "length {}{}",
+            self.counter,
+            if self.halt { " halted" } else { "" }
+        )
+    }
+}
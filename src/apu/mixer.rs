@@ -0,0 +1,51 @@
+/// Sound generated by a cartridge, e.g. the VRC6 or the FDS, mixed in with the APU channels.
+pub trait ExpansionAudio: Send {
+    /// Advance the expansion by a single CPU cycle.
+    fn tick(&mut self) {}
+
+    /// Current output on the same scale as `mix`, where the loudest APU output is about 1.0.
+    fn output(&self) -> f32;
+}
+
+/// Mix the channel levels into a sample between 0.0 and 1.0.
+///
+/// The channels are mixed through two resistor networks whose output is nonlinear in the channel
+/// levels, approximated with the formulas from http://wiki.nesdev.com/w/index.php/APU_Mixer.
+///
+/// The pulses and triangle are 0 to 15, the noise is 0 to 15 and the DMC is 0 to 127.
+pub fn mix(pulse_1: u8, pulse_2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulse = pulse_1 as f32 + pulse_2 as f32;
+    let pulse_out = if pulse == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse + 100.0)
+    };
+
+    let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    let tnd_out = if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    };
+
+    pulse_out + tnd_out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix() {
+        assert_eq!(mix(0, 0, 0, 0, 0), 0.0);
+        assert!((mix(15, 15, 15, 15, 127) - 1.0).abs() < 0.01);
+
+        // Two pulses together are quieter than the sum of each alone.
+        assert!(mix(15, 15, 0, 0, 0) < 2.0 * mix(15, 0, 0, 0, 0));
+
+        // The DMC level attenuates the triangle and noise.
+        let triangle = mix(0, 0, 15, 0, 0);
+        let triangle_with_dmc = mix(0, 0, 15, 0, 127) - mix(0, 0, 0, 0, 127);
+        assert!(triangle_with_dmc < triangle);
+    }
+}
@@ -0,0 +1,515 @@
+/// This file contains the APU logic.
+/// Used http://wiki.nesdev.com/w/index.php/APU as a reference.
+///
+/// The APU is clocked once per CPU cycle. The frame counter drives the envelopes, length
+/// counters and sweep units of the channels at fixed points of a ~60Hz sequence. PAL consoles
+/// have a slower CPU clock and their own period tables to stay close to the same pitches.
+mod dmc;
+mod envelope;
+mod frame_counter;
+mod length_counter;
+mod mixer;
+mod noise;
+mod pulse;
+mod triangle;
+
+use crate::region::Region;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use dmc::Dmc;
+use frame_counter::{FrameClock, FrameCounter};
+use noise::Noise;
+use pulse::{Pulse, PulseChannel};
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+use triangle::Triangle;
+
+pub use mixer::ExpansionAudio;
+
+/// First register of the APU in the CPU address space.
+pub const REGISTERS_START: u16 = 0x4000;
+
+/// Last channel register.
+pub const CHANNEL_REGISTERS_END: u16 = 0x4013;
+
+/// Channel enable and length counter status.
+pub const STATUS: u16 = 0x4015;
+
+/// Frame counter mode and IRQ inhibit.
+pub const FRAME_COUNTER: u16 = 0x4017;
+
+/// The sound channels of the APU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+/// Every channel, in the order of `Channel`.
+pub const CHANNELS: [Channel; 5] = [
+    Channel::Pulse1,
+    Channel::Pulse2,
+    Channel::Triangle,
+    Channel::Noise,
+    Channel::Dmc,
+];
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "pulse1" => Ok(Channel::Pulse1),
+            "pulse2" => Ok(Channel::Pulse2),
+            "triangle" => Ok(Channel::Triangle),
+            "noise" => Ok(Channel::Noise),
+            "dmc" => Ok(Channel::Dmc),
+            _ => Err(format!("Unknown channel \"{}\".", name)),
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    /// The name `from_str` parses, e.g. "pulse1".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::Dmc => "dmc",
+        })
+    }
+}
+
+/// Called with every sample, once per CPU cycle.
+pub type SampleCallback = Box<dyn FnMut(f32) + Send>;
+
+/// State of the APU.
+#[derive(Deserialize, Serialize)]
+pub struct Apu {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    frame_counter: FrameCounter,
+
+    /// Sound generated by the cartridge.
+    #[serde(skip)]
+    expansion: Option<Box<dyn ExpansionAudio>>,
+
+    #[serde(skip)]
+    on_sample: Vec<SampleCallback>,
+
+    /// Samples are generated without calling `on_sample`.
+    #[serde(skip)]
+    sample_callbacks_paused: bool,
+
+    /// Channels left out of the mix, indexed by `Channel`.
+    #[serde(skip)]
+    muted: [bool; 5],
+
+    /// Only this channel is mixed, overrides the muted channels.
+    #[serde(skip)]
+    solo: Option<Channel>,
+
+    /// Number of CPU cycles since power up.
+    cycles: u64,
+
+    region: Region,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu::new()
+    }
+}
+
+impl Apu {
+    const PULSE_1_MASK: u8 = 0b0000_0001;
+    const PULSE_2_MASK: u8 = 0b0000_0010;
+    const TRIANGLE_MASK: u8 = 0b0000_0100;
+    const NOISE_MASK: u8 = 0b0000_1000;
+    const DMC_MASK: u8 = 0b0001_0000;
+    const FRAME_IRQ_MASK: u8 = 0b0100_0000;
+    const DMC_IRQ_MASK: u8 = 0b1000_0000;
+
+    pub fn new() -> Self {
+        Apu {
+            pulse_1: Pulse::new(PulseChannel::One),
+            pulse_2: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::default(),
+            expansion: None,
+            on_sample: vec![],
+            sample_callbacks_paused: false,
+            muted: [false; 5],
+            solo: None,
+            cycles: 0,
+            region: Region::default(),
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Switch to the period tables and frame counter sequence of another region.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+        self.frame_counter.set_region(region);
+    }
+
+    /// Samples per second, one per CPU cycle.
+    pub fn sample_rate(&self) -> f64 {
+        self.region.timing().cpu_hz()
+    }
+
+    /// Mix in sound generated by the cartridge.
+    pub fn set_expansion_audio(&mut self, expansion: Box<dyn ExpansionAudio>) {
+        self.expansion = Some(expansion);
+    }
+
+    /// Register a callback receiving the mixed output every CPU cycle.
+    ///
+    /// Any number of callbacks can be registered, e.g. the audio device and a dump.
+    pub fn on_sample<F>(&mut self, callback: F)
+    where
+        F: FnMut(f32) + Send + 'static,
+    {
+        self.on_sample.push(Box::new(callback));
+    }
+
+    /// Stop or resume calling the `on_sample` callbacks, e.g. for frames that will be thrown away.
+    pub fn pause_sample_callbacks(&mut self, paused: bool) {
+        self.sample_callbacks_paused = paused;
+    }
+
+    /// Take over the callbacks, expansion audio and mixing settings of another APU, e.g. the one a
+    /// save state replaced.
+    pub fn take_callbacks(&mut self, other: &mut Apu) {
+        self.expansion = other.expansion.take();
+        self.on_sample = core::mem::take(&mut other.on_sample);
+        self.sample_callbacks_paused = other.sample_callbacks_paused;
+        self.muted = other.muted;
+        self.solo = other.solo;
+    }
+
+    /// Leave a channel out of the mix, the channel itself keeps running.
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    /// Only mix the given channel, or go back to the muted channels with `None`.
+    pub fn set_solo(&mut self, solo: Option<Channel>) {
+        self.solo = solo;
+    }
+
+    pub fn solo(&self) -> Option<Channel> {
+        self.solo
+    }
+
+    /// Whether the channel is part of the mix given the mute and solo settings.
+    pub fn is_audible(&self, channel: Channel) -> bool {
+        match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.muted[channel as usize],
+        }
+    }
+
+    /// Current mixed output of all the channels, roughly between 0.0 and 1.0.
+    pub fn sample(&self) -> f32 {
+        let level = |channel, output| {
+            if self.is_audible(channel) {
+                output
+            } else {
+                0
+            }
+        };
+
+        let sample = mixer::mix(
+            level(Channel::Pulse1, self.pulse_1.output()),
+            level(Channel::Pulse2, self.pulse_2.output()),
+            level(Channel::Triangle, self.triangle.output()),
+            level(Channel::Noise, self.noise.output()),
+            level(Channel::Dmc, self.dmc.output()),
+        );
+
+        // Soloing an APU channel also silences the cartridge.
+        match &self.expansion {
+            Some(expansion) if self.solo.is_none() => sample + expansion.output(),
+            _ => sample,
+        }
+    }
+
+    /// Read a register as the CPU would, with all the side effects.
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        let value = self.peek_register(addr);
+
+        // Reading the status acknowledges the frame interrupt.
+        if addr == STATUS {
+            self.frame_counter.clear_irq_flag();
+        }
+
+        value
+    }
+
+    /// Read a register without any side effects, used when logging.
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        match addr {
+            STATUS => {
+                (self.pulse_1.length_counter.is_active() as u8)
+                    | (self.pulse_2.length_counter.is_active() as u8) << 1
+                    | (self.triangle.length_counter.is_active() as u8) << 2
+                    | (self.noise.length_counter.is_active() as u8) << 3
+                    | (self.dmc.is_active() as u8) << 4
+                    | if self.frame_counter.irq_flag() {
+                        Self::FRAME_IRQ_MASK
+                    } else {
+                        0
+                    }
+                    | if self.dmc.irq_flag() {
+                        Self::DMC_IRQ_MASK
+                    } else {
+                        0
+                    }
+            }
+            _ => 0,
+        }
+    }
+
+    /// Write a register as the CPU would.
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        trace!("${:04X} = ${:02X}", addr, value);
+        let register = addr & 0x3;
+
+        match addr {
+            0x4000..=0x4003 => self.pulse_1.write(register, value),
+            0x4004..=0x4007 => self.pulse_2.write(register, value),
+            0x4008..=0x400B => self.triangle.write(register, value),
+            0x400C..=0x400F => self.noise.write(register, value),
+            0x4010..=0x4013 => self.dmc.write(register, value),
+            STATUS => {
+                self.pulse_1
+                    .length_counter
+                    .set_enabled(value & Self::PULSE_1_MASK != 0);
+                self.pulse_2
+                    .length_counter
+                    .set_enabled(value & Self::PULSE_2_MASK != 0);
+                self.triangle
+                    .length_counter
+                    .set_enabled(value & Self::TRIANGLE_MASK != 0);
+                self.noise
+                    .length_counter
+                    .set_enabled(value & Self::NOISE_MASK != 0);
+
+                // Also acknowledges the DMC interrupt.
+                self.dmc.set_enabled(value & Self::DMC_MASK != 0);
+            }
+            FRAME_COUNTER => {
+                let odd_cycle = self.cycles % 2 == 1;
+                self.frame_counter.write(value, odd_cycle);
+            }
+            _ => (),
+        }
+    }
+
+    /// Advance the APU by a single CPU cycle.
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+
+        // The pulse timers are clocked every APU cycle, which is every other CPU cycle.
+        if self.cycles.is_multiple_of(2) {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        if let Some(expansion) = &mut self.expansion {
+            expansion.tick();
+        }
+
+        let clock = self.frame_counter.tick();
+        self.clock_units(clock);
+
+        if !self.on_sample.is_empty() && !self.sample_callbacks_paused {
+            let sample = self.sample();
+            for on_sample in self.on_sample.iter_mut() {
+                on_sample(sample);
+            }
+        }
+    }
+
+    /// Whether the APU is asserting the CPU IRQ line, from the frame counter or the DMC.
+    pub fn irq(&self) -> bool {
+        self.frame_irq() || self.dmc_irq()
+    }
+
+    /// Whether the frame counter is asserting the IRQ line.
+    pub fn frame_irq(&self) -> bool {
+        self.frame_counter.irq_flag()
+    }
+
+    /// Whether the DMC is asserting the IRQ line.
+    pub fn dmc_irq(&self) -> bool {
+        self.dmc.irq_flag()
+    }
+
+    /// Address the DMC needs fetched from the CPU bus, if any.
+    pub fn dmc_dma_address(&self) -> Option<u16> {
+        self.dmc.dma_address()
+    }
+
+    /// Hand the byte fetched from `dmc_dma_address()` to the DMC.
+    pub fn dmc_dma_complete(&mut self, value: u8) {
+        self.dmc.dma_complete(value);
+    }
+
+    /// Clock the units driven by the frame counter.
+    fn clock_units(&mut self, clock: FrameClock) {
+        if clock.quarter {
+            self.pulse_1.envelope.clock();
+            self.pulse_2.envelope.clock();
+            self.triangle.clock_linear_counter();
+            self.noise.envelope.clock();
+        }
+
+        if clock.half {
+            self.pulse_1.length_counter.clock();
+            self.pulse_1.clock_sweep();
+            self.pulse_2.length_counter.clock();
+            self.pulse_2.clock_sweep();
+            self.triangle.length_counter.clock();
+            self.noise.length_counter.clock();
+        }
+    }
+}
+
+impl fmt::Display for Apu {
+    /// The state of each channel and the frame counter, a line each.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Pulse 1   {}", self.pulse_1)?;
+        writeln!(f, "Pulse 2   {}", self.pulse_2)?;
+        writeln!(f, "Triangle  {}", self.triangle)?;
+        writeln!(f, "Noise     {}", self.noise)?;
+        writeln!(f, "DMC       {}", self.dmc)?;
+        write!(f, "Frame     {}", self.frame_counter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mute_and_solo() {
+        let mut apu = Apu::new();
+
+        // Load the DMC output level so there is something to hear.
+        apu.write_register(0x4011, 0x7F);
+        assert!(apu.sample() > 0.0);
+
+        // The triangle idles in the middle of its waveform.
+        apu.set_muted(Channel::Triangle, true);
+        apu.set_muted(Channel::Dmc, true);
+        assert!(!apu.is_audible(Channel::Dmc));
+        assert_eq!(apu.sample(), 0.0);
+
+        // Solo overrides the mute.
+        apu.set_solo(Some(Channel::Dmc));
+        assert!(apu.sample() > 0.0);
+        assert!(!apu.is_audible(Channel::Pulse1));
+
+        apu.set_solo(Some(Channel::Pulse2));
+        assert_eq!(apu.sample(), 0.0);
+    }
+
+    #[test]
+    fn test_length_counter_status() {
+        let mut apu = Apu::new();
+        apu.write_register(FRAME_COUNTER, 0x40);
+
+        // Loading while disabled has no effect.
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(apu.read_register(STATUS), 0x00);
+
+        apu.write_register(STATUS, 0x0F);
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(0x400F, 0x08);
+        assert_eq!(apu.read_register(STATUS), 0x09);
+
+        // A length of 254 half frames lasts a little over two seconds.
+        for _ in 0..29830 * 126 {
+            apu.tick();
+        }
+        assert_eq!(apu.read_register(STATUS), 0x09);
+
+        // Allow for the delay of the frame counter write.
+        for _ in 0..29830 + 4 {
+            apu.tick();
+        }
+        assert_eq!(apu.read_register(STATUS), 0x00);
+
+        // Reading the status clears the frame interrupt flag.
+        apu.write_register(STATUS, 0x00);
+        apu.write_register(FRAME_COUNTER, 0x00);
+        for _ in 0..29830 + 4 {
+            apu.tick();
+        }
+        assert_eq!(apu.read_register(STATUS), 0x40);
+        assert_eq!(apu.read_register(STATUS), 0x00);
+
+        // Disabling clears the counter.
+        apu.write_register(STATUS, 0x0F);
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(STATUS, 0x00);
+        assert_eq!(apu.read_register(STATUS), 0x00);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut apu = Apu::new();
+        apu.write_register(STATUS, 0x01);
+        apu.write_register(0x4000, 0xBF);
+        apu.write_register(0x4001, 0x9A);
+        apu.write_register(0x4002, 0xFD);
+        apu.write_register(0x4003, 0x08);
+        for _ in 0..7460 {
+            apu.tick();
+        }
+
+        let display = apu.to_string();
+        let lines: Vec<&str> = display.lines().collect();
+        assert_eq!(
+            lines[0],
+            "Pulse 1   period $0FD  duty 2  volume 15  length 254 halted  \
+             sweep down period 1 shift 2  output 0"
+        );
+        assert_eq!(
+            lines[1],
+            "Pulse 2   period $000  duty 0  decay 0/0  length disabled  sweep off  output 0"
+        );
+        assert_eq!(lines[5], "Frame     4-step  cycle 7460 (step 1)");
+    }
+}
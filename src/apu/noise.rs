@@ -0,0 +1,150 @@
+/// Noise channel.
+/// See http://wiki.nesdev.com/w/index.php/APU_Noise.
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::region::Region;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Timer periods in CPU cycles for each period index (NTSC).
+const PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+/// The same for the slower PAL clock.
+const PAL_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+#[derive(Deserialize, Serialize)]
+pub struct Noise {
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+
+    /// Feed back from bit 6 instead of bit 1, producing a short metallic loop.
+    mode: bool,
+
+    /// 15 bit linear feedback shift register, the output is silenced while bit 0 is set.
+    shift_register: u16,
+
+    /// Period of the timer in CPU cycles.
+    timer_period: u16,
+
+    timer: u16,
+
+    /// Selects the period table.
+    region: Region,
+}
+
+impl Noise {
+    const LENGTH_HALT_MASK: u8 = 0b0010_0000;
+    const MODE_MASK: u8 = 0b1000_0000;
+    const PERIOD_MASK: u8 = 0b0000_1111;
+
+    pub fn new() -> Self {
+        Noise {
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+            mode: false,
+            shift_register: 1,
+            timer_period: PERIODS[0],
+            timer: 0,
+            region: Region::default(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    /// Handle a write to one of the channel's four registers.
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.envelope.write(value);
+                self.length_counter
+                    .set_halt(value & Self::LENGTH_HALT_MASK != 0);
+            }
+            2 => {
+                self.mode = value & Self::MODE_MASK != 0;
+                let periods = if self.region.pal_apu() {
+                    &PAL_PERIODS
+                } else {
+                    &PERIODS
+                };
+                self.timer_period = periods[(value & Self::PERIOD_MASK) as usize];
+            }
+            3 => {
+                self.length_counter.load(value);
+                self.envelope.restart();
+            }
+            _ => (),
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.timer_period - 1;
+
+        let tap = if self.mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 0x01;
+        self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+
+    /// Current output level, 0 to 15.
+    pub fn output(&self) -> u8 {
+        if !self.length_counter.is_active() || self.shift_register & 0x01 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl fmt::Display for Noise {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "period {}  {}  {}  {}  output {}",
+            self.timer_period,
+            if self.mode { "short" } else { "long" },
+            self.envelope,
+            self.length_counter,
+            self.output()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_length() {
+        // The long mode repeats every 32767 steps, the short mode every 93 (or 31).
+        for &(mode, length) in [(0x00, 32767), (0x80, 93)].iter() {
+            let mut noise = Noise::new();
+            noise.write(2, mode);
+
+            let start = noise.shift_register;
+            let mut steps = 0;
+            loop {
+                for _ in 0..PERIODS[0] {
+                    noise.clock_timer();
+                }
+                steps += 1;
+
+                if noise.shift_register == start {
+                    break;
+                }
+            }
+
+            assert_eq!(steps, length);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+/// Pulse (square wave) channel.
+/// See http://wiki.nesdev.com/w/index.php/APU_Pulse.
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Waveforms of the 4 duty cycles: 12.5%, 25%, 50% and 25% negated.
+const DUTY_SEQUENCES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// Which of the two pulse channels, they differ in how the sweep unit negates.
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum PulseChannel {
+    /// Negates using ones' complement (subtracts one more).
+    One,
+
+    /// Negates using two's complement.
+    Two,
+}
+
+/// Sweep unit, periodically bends the pitch up or down.
+/// See http://wiki.nesdev.com/w/index.php/APU_Sweep.
+#[derive(Default, Deserialize, Serialize)]
+struct Sweep {
+    enabled: bool,
+
+    /// Period of the divider in half frames, minus one.
+    period: u8,
+
+    negate: bool,
+
+    /// How far to shift the timer period to get the change amount.
+    shift: u8,
+
+    divider: u8,
+
+    /// Reload the divider on the next clock.
+    reload: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Pulse {
+    channel: PulseChannel,
+
+    pub envelope: Envelope,
+    pub length_counter: LengthCounter,
+    sweep: Sweep,
+
+    /// Selected duty cycle.
+    duty: u8,
+
+    /// Position within the duty sequence.
+    sequence_step: u8,
+
+    /// Period of the timer in APU cycles (11 bits).
+    timer_period: u16,
+
+    timer: u16,
+}
+
+impl Pulse {
+    const DUTY_SHIFT: u8 = 6;
+    const LENGTH_HALT_MASK: u8 = 0b0010_0000;
+
+    const SWEEP_ENABLED_MASK: u8 = 0b1000_0000;
+    const SWEEP_PERIOD_MASK: u8 = 0b0111_0000;
+    const SWEEP_NEGATE_MASK: u8 = 0b0000_1000;
+    const SWEEP_SHIFT_MASK: u8 = 0b0000_0111;
+
+    /// Periods above this can't be played.
+    const MAX_PERIOD: u16 = 0x7FF;
+
+    /// Periods below this are too high to be played.
+    const MIN_PERIOD: u16 = 8;
+
+    pub fn new(channel: PulseChannel) -> Self {
+        Pulse {
+            channel,
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+            sweep: Sweep::default(),
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+        }
+    }
+
+    /// Handle a write to one of the channel's four registers.
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> Self::DUTY_SHIFT;
+                self.envelope.write(value);
+                self.length_counter
+                    .set_halt(value & Self::LENGTH_HALT_MASK != 0);
+            }
+            1 => {
+                self.sweep.enabled = value & Self::SWEEP_ENABLED_MASK != 0;
+                self.sweep.period = (value & Self::SWEEP_PERIOD_MASK) >> 4;
+                self.sweep.negate = value & Self::SWEEP_NEGATE_MASK != 0;
+                self.sweep.shift = value & Self::SWEEP_SHIFT_MASK;
+                self.sweep.reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x700) | value as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x0FF) | ((value & 0x07) as u16) << 8;
+                self.length_counter.load(value);
+                self.envelope.restart();
+                self.sequence_step = 0;
+            }
+            _ => (),
+        }
+    }
+
+    /// Clocked every APU cycle (every other CPU cycle).
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocked by the frame counter every half frame.
+    pub fn clock_sweep(&mut self) {
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.is_muted()
+        {
+            self.timer_period = self.sweep_target_period();
+        }
+
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    /// The period the sweep unit is moving towards.
+    ///
+    /// Computed continuously, even when the sweep is disabled it can mute the channel.
+    fn sweep_target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep.shift;
+
+        if !self.sweep.negate {
+            return self.timer_period + change;
+        }
+
+        match self.channel {
+            PulseChannel::One => self.timer_period.saturating_sub(change + 1),
+            PulseChannel::Two => self.timer_period.saturating_sub(change),
+        }
+    }
+
+    /// The channel is silenced if the period is out of range, regardless of the sweep being enabled.
+    fn is_muted(&self) -> bool {
+        self.timer_period < Self::MIN_PERIOD || self.sweep_target_period() > Self::MAX_PERIOD
+    }
+
+    /// Current output level, 0 to 15.
+    pub fn output(&self) -> u8 {
+        let sequence = DUTY_SEQUENCES[self.duty as usize];
+
+        if !self.length_counter.is_active()
+            || self.is_muted()
+            || sequence[self.sequence_step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl fmt::Display for Sweep {
+    /// e.g. "sweep off" or "sweep down period 1 shift 2".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.enabled {
+            return write!(f, "sweep off");
+        }
+
+        write!(
+            f,
+            "sweep {} period {} shift {}",
+            if self.negate { "down" } else { "up" },
+            self.period,
+            self.shift
+        )
+    }
+}
+
+impl fmt::Display for Pulse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "period ${:03X}  duty {}  {}  {}  {}  output {}",
+            self.timer_period,
+            self.duty,
+            self.envelope,
+            self.length_counter,
+            self.sweep,
+            self.output()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_negate() {
+        let mut pulse_1 = Pulse::new(PulseChannel::One);
+        let mut pulse_2 = Pulse::new(PulseChannel::Two);
+
+        for pulse in [&mut pulse_1, &mut pulse_2].iter_mut() {
+            // Enabled, period 0, negate, shift 1.
+            pulse.write(1, 0b1000_1001);
+            pulse.write(2, 0x00);
+            pulse.write(3, 0x01);
+            pulse.clock_sweep();
+        }
+
+        assert_eq!(pulse_1.timer_period, 0x100 - 0x80 - 1);
+        assert_eq!(pulse_2.timer_period, 0x100 - 0x80);
+    }
+
+    #[test]
+    fn test_sweep_mutes() {
+        let mut pulse = Pulse::new(PulseChannel::One);
+        pulse.length_counter.set_enabled(true);
+
+        // Constant volume 15, 50% duty.
+        pulse.write(0, 0b1001_1111);
+        pulse.write(2, 0xFF);
+        pulse.write(3, 0x07);
+
+        // Sweep disabled but the target period overflows.
+        pulse.write(1, 0b0000_0001);
+
+        for _ in 0..16 {
+            for _ in 0..=0x7FF {
+                pulse.clock_timer();
+            }
+            assert_eq!(pulse.output(), 0);
+        }
+
+        // A shift of 0 with negate doesn't overflow.
+        pulse.write(1, 0b0000_1000);
+        let mut outputs = vec![];
+        for _ in 0..8 {
+            for _ in 0..=0x7FF {
+                pulse.clock_timer();
+            }
+            outputs.push(pulse.output());
+        }
+        assert_eq!(outputs.iter().filter(|&&output| output == 15).count(), 4);
+    }
+}
@@ -0,0 +1,179 @@
+/// Triangle channel.
+/// See http://wiki.nesdev.com/w/index.php/APU_Triangle.
+use crate::apu::length_counter::LengthCounter;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// The 32 step triangle waveform.
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct Triangle {
+    pub length_counter: LengthCounter,
+
+    /// Halts the length counter and keeps reloading the linear counter.
+    control: bool,
+
+    /// Value the linear counter is reloaded with.
+    linear_counter_reload: u8,
+
+    /// Finer grained length counter, clocked every quarter frame.
+    linear_counter: u8,
+
+    /// Reload the linear counter on the next clock.
+    linear_counter_reload_flag: bool,
+
+    /// Position within the sequence.
+    sequence_step: u8,
+
+    /// Period of the timer in CPU cycles (11 bits).
+    timer_period: u16,
+
+    timer: u16,
+}
+
+impl Triangle {
+    const CONTROL_MASK: u8 = 0b1000_0000;
+    const LINEAR_COUNTER_MASK: u8 = 0b0111_1111;
+
+    /// Below this period the triangle is ultrasonic.
+    const MIN_PERIOD: u16 = 2;
+
+    /// Handle a write to one of the channel's four registers.
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = value & Self::CONTROL_MASK != 0;
+                self.linear_counter_reload = value & Self::LINEAR_COUNTER_MASK;
+                self.length_counter.set_halt(self.control);
+            }
+            2 => self.timer_period = (self.timer_period & 0x700) | value as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x0FF) | ((value & 0x07) as u16) << 8;
+                self.length_counter.load(value);
+                self.linear_counter_reload_flag = true;
+            }
+            _ => (),
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+
+            // The sequencer only advances while both counters are non-zero.
+            if self.linear_counter > 0 && self.length_counter.is_active() {
+                self.sequence_step = (self.sequence_step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocked by the frame counter every quarter frame.
+    pub fn clock_linear_counter(&mut self) {
+        if self.linear_counter_reload_flag {
+            self.linear_counter = self.linear_counter_reload;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_counter_reload_flag = false;
+        }
+    }
+
+    /// Current output level, 0 to 15.
+    ///
+    /// Silencing the channel doesn't change the output, the sequencer simply stops where it is.
+    pub fn output(&self) -> u8 {
+        // Games use tiny periods to silence the channel. The real output is an ultrasonic wave
+        // which averages to the middle of the waveform, output that directly to avoid aliasing.
+        if self.timer_period < Self::MIN_PERIOD {
+            return 7;
+        }
+
+        SEQUENCE[self.sequence_step as usize]
+    }
+}
+
+impl fmt::Display for Triangle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "period ${:03X}  step {}  linear {}/{}{}  {}  output {}",
+            self.timer_period,
+            self.sequence_step,
+            self.linear_counter,
+            self.linear_counter_reload,
+            if self.control { " control" } else { "" },
+            self.length_counter,
+            self.output()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Triangle playing with the given period and linear counter.
+    fn triangle(period: u16, linear_counter: u8) -> Triangle {
+        let mut triangle = Triangle::default();
+        triangle.length_counter.set_enabled(true);
+
+        triangle.write(0, linear_counter);
+        triangle.write(2, period as u8);
+        triangle.write(3, 0x08 | (period >> 8) as u8);
+        triangle.clock_linear_counter();
+
+        triangle
+    }
+
+    #[test]
+    fn test_sequence() {
+        let mut triangle = triangle(0x10, 0x7F);
+
+        let mut outputs = vec![];
+        for _ in 0..32 {
+            for _ in 0..=0x10 {
+                triangle.clock_timer();
+            }
+            outputs.push(triangle.output());
+        }
+
+        assert_eq!(outputs[0], 14);
+        assert_eq!(outputs[14], 0);
+        assert_eq!(outputs[15], 0);
+        assert_eq!(outputs[31], 15);
+    }
+
+    #[test]
+    fn test_linear_counter() {
+        let mut triangle = triangle(0x10, 2);
+
+        triangle.clock_linear_counter();
+        triangle.clock_linear_counter();
+        let output = triangle.output();
+
+        // Silenced, the sequencer holds its position.
+        for _ in 0..0x100 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.output(), output);
+    }
+
+    #[test]
+    fn test_ultrasonic() {
+        let mut triangle = triangle(0x01, 0x7F);
+        for _ in 0..5 {
+            triangle.clock_timer();
+        }
+
+        assert_eq!(triangle.output(), 7);
+    }
+}
@@ -0,0 +1,156 @@
+/// Audio output.
+///
+/// The APU produces a sample every CPU cycle, these are resampled down to the rate of the output
+/// device and queued in a buffer the device drains from its own thread.
+mod resampler;
+mod wav;
+
+#[cfg(feature = "audio")]
+mod output;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(feature = "audio")]
+pub use output::{AudioOutput, AudioSettings};
+pub use resampler::{Resampler, ResamplerQuality};
+pub use wav::WavDump;
+
+/// Queue of samples shared between the emulator and the audio device.
+///
+/// The capacity bounds the latency, samples produced while the queue is full are dropped.
+#[derive(Clone)]
+pub struct SampleBuffer {
+    queue: Arc<Mutex<Queue>>,
+    capacity: usize,
+}
+
+struct Queue {
+    samples: VecDeque<f32>,
+
+    /// Counted for `AudioHealth`.
+    dropped: u64,
+    missed: u64,
+    underruns: u64,
+
+    /// A sample was pushed, the device finding the buffer empty before is just the startup.
+    started: bool,
+
+    /// The device found the buffer empty last time, the underrun was already counted.
+    empty: bool,
+}
+
+/// How well the emulator keeps up with the audio device, see `SampleBuffer::health()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AudioHealth {
+    /// Samples waiting for the device.
+    pub queued: usize,
+    pub capacity: usize,
+
+    /// Samples thrown away since the start because the buffer was full, the emulator ran ahead.
+    pub dropped: u64,
+
+    /// Samples the device asked for since the start while the buffer was empty, the emulator fell
+    /// behind and the sound crackles.
+    pub missed: u64,
+
+    /// Times the device found the buffer empty after playing started, however many samples each
+    /// time missed. Many of them mean the latency or the device's buffer is too short.
+    pub underruns: u64,
+}
+
+impl AudioHealth {
+    /// How full the buffer is, from 0 to 1.
+    pub fn fill(&self) -> f64 {
+        self.queued as f64 / self.capacity as f64
+    }
+}
+
+impl SampleBuffer {
+    /// Buffer holding at most `latency` worth of samples at the given rate.
+    pub fn with_latency(sample_rate: u32, latency: Duration) -> Self {
+        let capacity = (sample_rate as f64 * latency.as_secs_f64()).ceil() as usize;
+
+        SampleBuffer {
+            queue: Arc::new(Mutex::new(Queue {
+                samples: VecDeque::with_capacity(capacity),
+                dropped: 0,
+                missed: 0,
+                underruns: 0,
+                started: false,
+                empty: false,
+            })),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Queue a sample, dropping it if the buffer is full.
+    pub fn push(&self, sample: f32) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.started = true;
+        if queue.samples.len() < self.capacity {
+            queue.samples.push_back(sample);
+        } else {
+            queue.dropped += 1;
+        }
+    }
+
+    /// Take the oldest sample, if there is one.
+    pub fn pop(&self) -> Option<f32> {
+        let mut queue = self.queue.lock().unwrap();
+        let sample = queue.samples.pop_front();
+        if sample.is_none() {
+            queue.missed += 1;
+            if queue.started && !queue.empty {
+                queue.underruns += 1;
+            }
+        }
+        queue.empty = sample.is_none();
+        sample
+    }
+
+    pub fn health(&self) -> AudioHealth {
+        let queue = self.queue.lock().unwrap();
+        AudioHealth {
+            queued: queue.samples.len(),
+            capacity: self.capacity,
+            dropped: queue.dropped,
+            missed: queue.missed,
+            underruns: queue.underruns,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_buffer() {
+        let buffer = SampleBuffer::with_latency(1000, Duration::from_millis(2));
+        let consumer = buffer.clone();
+
+        // Waiting for the first sample isn't an underrun.
+        assert_eq!(consumer.pop(), None);
+
+        buffer.push(0.1);
+        buffer.push(0.2);
+        buffer.push(0.3);
+
+        assert_eq!(buffer.health().fill(), 1.0);
+        assert_eq!(consumer.pop(), Some(0.1));
+        assert_eq!(consumer.pop(), Some(0.2));
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(consumer.pop(), None);
+
+        let health = buffer.health();
+        assert_eq!((health.queued, health.dropped, health.missed), (0, 1, 3));
+        assert_eq!(health.underruns, 1);
+
+        buffer.push(0.4);
+        assert_eq!(consumer.pop(), Some(0.4));
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(buffer.health().underruns, 2);
+    }
+}
@@ -0,0 +1,177 @@
+/// Plays the APU output through the default audio device using cpal.
+use crate::apu::Apu;
+use crate::audio::{Resampler, ResamplerQuality, SampleBuffer};
+use anyhow::{anyhow, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    BufferSize, FromSample, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig,
+    SupportedBufferSize, SupportedStreamConfig,
+};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// How the device is opened, the defaults are the device's own.
+#[derive(Clone, Copy, Debug)]
+pub struct AudioSettings {
+    /// Bounds how far the emulator can run ahead of the device.
+    pub latency: Duration,
+
+    /// Output rate in Hz.
+    pub sample_rate: Option<u32>,
+
+    /// Frames the device asks for at a time. Shorter buffers lower the latency but underrun on
+    /// slower machines.
+    pub buffer_size: Option<u32>,
+
+    pub resampler: ResamplerQuality,
+}
+
+/// An open audio stream, sound stops when dropped.
+pub struct AudioOutput {
+    _stream: Stream,
+    buffer: SampleBuffer,
+}
+
+impl AudioOutput {
+    /// Open the default output device and feed it the APU's samples.
+    ///
+    /// A sample rate or buffer size the device doesn't support falls back to its default with a
+    /// warning.
+    pub fn start(apu: &mut Apu, settings: &AudioSettings) -> Result<Self> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| anyhow!("No audio output device available."))?;
+
+        let supported_config = match settings.sample_rate {
+            Some(sample_rate) => supported_config(&device, sample_rate)?,
+            None => device.default_output_config()?,
+        };
+        let sample_format = supported_config.sample_format();
+        let buffer_size = match (settings.buffer_size, supported_config.buffer_size()) {
+            (None, _) => BufferSize::Default,
+            (Some(size), SupportedBufferSize::Range { min, max }) if size < *min || size > *max => {
+                warn!(
+                    "The audio device doesn't support a buffer of {} frames, only {} to {}.",
+                    size, min, max
+                );
+                BufferSize::Default
+            }
+            (Some(size), _) => BufferSize::Fixed(size),
+        };
+        let mut config: StreamConfig = supported_config.into();
+        config.buffer_size = buffer_size;
+
+        info!(
+            "Audio output at {}Hz with {}ms latency, {} buffer and {} resampling.",
+            config.sample_rate.0,
+            settings.latency.as_millis(),
+            match config.buffer_size {
+                BufferSize::Fixed(size) => format!("a {} frame", size),
+                BufferSize::Default => "the default".to_string(),
+            },
+            settings.resampler
+        );
+
+        let buffer = SampleBuffer::with_latency(config.sample_rate.0, settings.latency);
+
+        let stream = match sample_format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, buffer.clone())?,
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, buffer.clone())?,
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, buffer.clone())?,
+            format => return Err(anyhow!("Unsupported sample format {}.", format)),
+        };
+        stream.play()?;
+
+        let mut resampler =
+            Resampler::with_quality(apu.sample_rate(), config.sample_rate.0, settings.resampler);
+        apu.on_sample({
+            let buffer = buffer.clone();
+            move |sample| {
+                if let Some(sample) = resampler.push(sample) {
+                    buffer.push(sample);
+                }
+            }
+        });
+
+        Ok(AudioOutput {
+            _stream: stream,
+            buffer,
+        })
+    }
+
+    /// The buffer between the emulator and the device, e.g. to watch its health.
+    pub fn buffer(&self) -> &SampleBuffer {
+        &self.buffer
+    }
+}
+
+impl Drop for AudioOutput {
+    /// Report the underruns so the settings can be tuned.
+    fn drop(&mut self) {
+        let health = self.buffer.health();
+        if health.underruns > 0 {
+            warn!(
+                "The audio buffer ran empty {} times, {} samples missed. A higher audio.latency or \
+                 audio.buffer_size avoids the crackling.",
+                health.underruns, health.missed
+            );
+        } else {
+            info!("No audio underruns, {} samples dropped.", health.dropped);
+        }
+    }
+}
+
+/// The device's config at `sample_rate`, or its default one if it doesn't support the rate.
+fn supported_config(device: &cpal::Device, sample_rate: u32) -> Result<SupportedStreamConfig> {
+    let default = device.default_output_config()?;
+    let rate = SampleRate(sample_rate);
+
+    let supported = device.supported_output_configs()?.find(|range| {
+        range.channels() == default.channels()
+            && range.sample_format() == default.sample_format()
+            && (range.min_sample_rate()..=range.max_sample_rate()).contains(&rate)
+    });
+
+    match supported {
+        Some(range) => Ok(range.with_sample_rate(rate)),
+        None => {
+            warn!(
+                "The audio device doesn't support {}Hz, using {}Hz.",
+                sample_rate,
+                default.sample_rate().0
+            );
+            Ok(default)
+        }
+    }
+}
+
+/// Build a stream writing the same sample to every channel of a frame.
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    buffer: SampleBuffer,
+) -> Result<Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+
+    // Hold the last sample when the emulator falls behind rather than popping.
+    let mut last = 0.0;
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            for frame in data.chunks_mut(channels) {
+                last = buffer.pop().unwrap_or(last);
+                for channel in frame.iter_mut() {
+                    *channel = T::from_sample(last);
+                }
+            }
+        },
+        |err| error!("Audio stream error: {}", err),
+        None,
+    )?;
+
+    Ok(stream)
+}
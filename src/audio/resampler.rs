@@ -0,0 +1,274 @@
+/// Resamples the APU output down to the rate of an output device.
+///
+/// How much of the content above the output's Nyquist frequency is filtered out before it aliases
+/// depends on the `ResamplerQuality`. The result is then passed through a high-pass filter like
+/// the one in the console to remove the DC offset of the mixer.
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// Trades aliasing, heard as harsh or out of tune high notes, for CPU time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResamplerQuality {
+    /// Keep one input sample per output sample.
+    Fast,
+
+    /// Average the input samples each output sample covers.
+    #[default]
+    Average,
+
+    /// Average to twice the output rate, then low-pass filter just under the output's Nyquist
+    /// frequency before keeping every other sample.
+    High,
+}
+
+impl FromStr for ResamplerQuality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fast" => Ok(ResamplerQuality::Fast),
+            "average" => Ok(ResamplerQuality::Average),
+            "high" => Ok(ResamplerQuality::High),
+            _ => Err(format!(
+                "Unknown resampler quality \"{}\", expected fast, average or high.",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ResamplerQuality {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ResamplerQuality::Fast => "fast",
+            ResamplerQuality::Average => "average",
+            ResamplerQuality::High => "high",
+        };
+        f.write_str(name)
+    }
+}
+
+pub struct Resampler {
+    quality: ResamplerQuality,
+
+    /// Input samples per output sample, per intermediate sample at high quality.
+    ratio: f64,
+
+    /// Input samples covered by the output sample being built.
+    position: f64,
+
+    sum: f32,
+    count: u32,
+
+    /// Low-pass filters at high quality, a fourth order Butterworth as two second order stages.
+    low_pass: Vec<Biquad>,
+
+    /// Whether the next intermediate sample is dropped at high quality.
+    skip: bool,
+
+    high_pass: HighPass,
+}
+
+/// Cut off frequency of the console's first high-pass filter.
+const HIGH_PASS_CUTOFF: f64 = 90.0;
+
+/// Cut off of the high quality low-pass filter, as a fraction of the output rate.
+const LOW_PASS_CUTOFF: f64 = 0.45;
+
+/// Q of the two stages of a fourth order Butterworth filter.
+const BUTTERWORTH_Q: [f64; 2] = [0.541_196_1, 1.306_563];
+
+impl Resampler {
+    /// Resample from `input_rate`, the rate of the APU, to `output_rate`.
+    pub fn new(input_rate: f64, output_rate: u32) -> Self {
+        Resampler::with_quality(input_rate, output_rate, ResamplerQuality::default())
+    }
+
+    pub fn with_quality(input_rate: f64, output_rate: u32, quality: ResamplerQuality) -> Self {
+        let (ratio, low_pass) = match quality {
+            ResamplerQuality::High => {
+                let intermediate_rate = 2.0 * output_rate as f64;
+                let cutoff = LOW_PASS_CUTOFF * output_rate as f64;
+                let low_pass = BUTTERWORTH_Q
+                    .iter()
+                    .map(|&q| Biquad::low_pass(intermediate_rate, cutoff, q))
+                    .collect();
+                (input_rate / intermediate_rate, low_pass)
+            }
+            _ => (input_rate / output_rate as f64, Vec::new()),
+        };
+
+        Resampler {
+            quality,
+            ratio,
+            position: 0.0,
+            sum: 0.0,
+            count: 0,
+            low_pass,
+            skip: false,
+            high_pass: HighPass::new(output_rate, HIGH_PASS_CUTOFF),
+        }
+    }
+
+    /// Feed a sample from the APU, returns an output sample once enough input has been seen.
+    pub fn push(&mut self, sample: f32) -> Option<f32> {
+        self.sum += sample;
+        self.count += 1;
+        self.position += 1.0;
+
+        if self.position < self.ratio {
+            return None;
+        }
+
+        let value = match self.quality {
+            ResamplerQuality::Fast => sample,
+            _ => self.sum / self.count as f32,
+        };
+        self.position -= self.ratio;
+        self.sum = 0.0;
+        self.count = 0;
+
+        // The filters run at the intermediate rate, only every other sample is kept.
+        if !self.low_pass.is_empty() {
+            let value = self
+                .low_pass
+                .iter_mut()
+                .fold(value, |value, filter| filter.filter(value));
+            self.skip = !self.skip;
+            if self.skip {
+                return None;
+            }
+            return Some(self.high_pass.filter(value));
+        }
+
+        Some(self.high_pass.filter(value))
+    }
+}
+
+/// Second order filter, see https://www.w3.org/TR/audio-eq-cookbook/.
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+
+    /// The last two inputs and outputs, the latest first.
+    inputs: [f32; 2],
+    outputs: [f32; 2],
+}
+
+impl Biquad {
+    fn low_pass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+
+        Biquad {
+            b0: ((1.0 - cos) / 2.0 / a0) as f32,
+            b1: ((1.0 - cos) / a0) as f32,
+            b2: ((1.0 - cos) / 2.0 / a0) as f32,
+            a1: (-2.0 * cos / a0) as f32,
+            a2: ((1.0 - alpha) / a0) as f32,
+            inputs: [0.0; 2],
+            outputs: [0.0; 2],
+        }
+    }
+
+    fn filter(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.b1 * self.inputs[0] + self.b2 * self.inputs[1]
+            - self.a1 * self.outputs[0]
+            - self.a2 * self.outputs[1];
+        self.inputs = [input, self.inputs[0]];
+        self.outputs = [output, self.outputs[0]];
+
+        output
+    }
+}
+
+/// First order high-pass filter.
+struct HighPass {
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl HighPass {
+    fn new(sample_rate: u32, cutoff: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate as f64;
+
+        HighPass {
+            alpha: (rc / (rc + dt)) as f32,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    fn filter(&mut self, input: f32) -> f32 {
+        let output = self.alpha * (self.previous_output + input - self.previous_input);
+        self.previous_input = input;
+        self.previous_output = output;
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The APU produces a sample every CPU cycle (NTSC).
+    const APU_SAMPLE_RATE: f64 = 1_789_773.0;
+
+    #[test]
+    fn test_rate() {
+        let mut resampler = Resampler::new(APU_SAMPLE_RATE, 48000);
+
+        let samples = (0..APU_SAMPLE_RATE as usize)
+            .filter_map(|_| resampler.push(0.5))
+            .count();
+
+        assert!((47999..=48000).contains(&samples));
+    }
+
+    #[test]
+    fn test_removes_dc() {
+        let mut resampler = Resampler::new(APU_SAMPLE_RATE, 48000);
+
+        let last = (0..APU_SAMPLE_RATE as usize)
+            .filter_map(|_| resampler.push(0.5))
+            .last()
+            .unwrap();
+
+        assert!(last.abs() < 0.001);
+    }
+
+    #[test]
+    fn test_quality() {
+        // A 30kHz tone aliases to 18kHz at 48kHz, each quality should let less of it through.
+        let rms = |quality| {
+            let mut resampler = Resampler::with_quality(APU_SAMPLE_RATE, 48000, quality);
+            let output: Vec<f32> = (0..APU_SAMPLE_RATE as usize / 10)
+                .map(|i| (2.0 * PI * 30_000.0 * i as f64 / APU_SAMPLE_RATE).sin() as f32)
+                .filter_map(|sample| resampler.push(sample))
+                .skip(480)
+                .collect();
+
+            assert!((4300..=4320).contains(&output.len()));
+            (output.iter().map(|sample| sample * sample).sum::<f32>() / output.len() as f32).sqrt()
+        };
+
+        let fast = rms(ResamplerQuality::Fast);
+        let average = rms(ResamplerQuality::Average);
+        let high = rms(ResamplerQuality::High);
+        assert!(fast > 0.5);
+        assert!(average < fast);
+        assert!(high < average / 4.0);
+
+        assert_eq!("High".parse(), Ok(ResamplerQuality::High));
+        assert!("best".parse::<ResamplerQuality>().is_err());
+    }
+}
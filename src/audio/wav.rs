@@ -0,0 +1,115 @@
+/// Writes the mixed APU output to a WAV file.
+use crate::audio::Resampler;
+use anyhow::Result;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use tracing::error;
+
+/// Sample rate of the dump.
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Update the header about once a second, so the file is playable even if the emulator is killed.
+const FLUSH_INTERVAL: u32 = SAMPLE_RATE;
+
+pub struct WavDump {
+    /// Dropped when writing fails, the rest of the run is not recorded.
+    writer: Option<WavWriter<BufWriter<File>>>,
+
+    resampler: Resampler,
+
+    /// Samples written since the header was last updated.
+    unflushed: u32,
+}
+
+impl WavDump {
+    /// Create a mono 16 bit dump at the given path, overwriting any existing file. `apu_rate` is
+    /// the rate the samples are pushed at.
+    pub fn create<P: AsRef<Path>>(path: P, apu_rate: f64) -> Result<Self> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+
+        Ok(WavDump {
+            writer: Some(WavWriter::create(path, spec)?),
+            resampler: Resampler::new(apu_rate, SAMPLE_RATE),
+            unflushed: 0,
+        })
+    }
+
+    /// Feed a sample from the APU.
+    pub fn push(&mut self, sample: f32) {
+        let sample = match self.resampler.push(sample) {
+            Some(sample) => sample,
+            None => return,
+        };
+
+        if let Some(writer) = &mut self.writer {
+            if let Err(err) = Self::write(writer, sample, &mut self.unflushed) {
+                error!("Failed to write audio dump, stopping: {}", err);
+                self.writer = None;
+            }
+        }
+    }
+
+    fn write(
+        writer: &mut WavWriter<BufWriter<File>>,
+        sample: f32,
+        unflushed: &mut u32,
+    ) -> Result<()> {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        writer.write_sample(sample)?;
+
+        *unflushed += 1;
+        if *unflushed >= FLUSH_INTERVAL {
+            writer.flush()?;
+            *unflushed = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Write out the remaining samples and the final header.
+    pub fn finalize(mut self) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::Apu;
+
+    #[test]
+    fn test_dump() -> Result<()> {
+        let apu_rate = Apu::new().sample_rate();
+        let path = std::env::temp_dir().join("nes_test_dump.wav");
+        let mut dump = WavDump::create(&path, apu_rate)?;
+
+        // Half a second of a 1kHz square wave.
+        let half_period = (apu_rate / 2000.0) as usize;
+        for i in 0..apu_rate as usize / 2 {
+            dump.push(if (i / half_period).is_multiple_of(2) {
+                0.0
+            } else {
+                0.5
+            });
+        }
+        dump.finalize()?;
+
+        let reader = hound::WavReader::open(&path)?;
+        assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+        assert!((22049..=22050).contains(&reader.duration()));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
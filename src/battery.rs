@@ -0,0 +1,272 @@
+/// Battery backed RAM, where games keep their saves, stored next to the ROM as "<rom>.sav" like
+/// other emulators do so the files can be swapped between them.
+///
+/// The RAM is written when it changed: every `FLUSH_INTERVAL` while running, once running stops
+/// and, after `flush_on_exit`, when the process panics or is interrupted. Writes go to a temporary
+/// file renamed over the save, so a crash or power loss halfway through leaves the last save whole.
+use crate::cpu::Cpu;
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// How long a change to the RAM waits before it's written, games often write a save in parts.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Saves written when the process panics or is interrupted.
+static EXIT_FLUSHES: Mutex<Vec<Weak<Mutex<Pending>>>> = Mutex::new(Vec::new());
+static EXIT_HOOKS: Once = Once::new();
+
+/// The RAM as of the last frame, shared with the exit hooks.
+struct Pending {
+    path: PathBuf,
+    ram: Vec<u8>,
+
+    /// Changed since it was last written.
+    dirty: bool,
+}
+
+impl Pending {
+    fn flush(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        write_atomic(&self.path, &self.ram)
+            .with_context(|| format!("Failed to write \"{}\"", self.path.display()))?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+pub struct BatterySave {
+    pending: Arc<Mutex<Pending>>,
+    last_flush: Instant,
+}
+
+impl BatterySave {
+    pub fn new(rom_path: &str) -> Self {
+        BatterySave {
+            pending: Arc::new(Mutex::new(Pending {
+                path: Path::new(rom_path).with_extension("sav"),
+                ram: Vec::new(),
+                dirty: false,
+            })),
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// Keep the save in `dir` instead of next to the ROM.
+    pub fn in_directory(self, dir: &Path) -> Self {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            let file_name = pending.path.file_name().unwrap_or_default().to_owned();
+            pending.path = dir.join(file_name);
+        }
+        self
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.pending.lock().unwrap().path.clone()
+    }
+
+    /// Load the save into `cpu`'s PRG RAM if there's one, before running.
+    pub fn load(&mut self, cpu: &mut Cpu) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        match fs::read(&pending.path) {
+            Ok(save) => {
+                let prg_ram = cpu.prg_ram_mut();
+                if save.len() != prg_ram.len() {
+                    warn!(
+                        "\"{}\" is {} bytes, the cartridge has {} bytes of RAM",
+                        pending.path.display(),
+                        save.len(),
+                        prg_ram.len()
+                    );
+                }
+                let len = save.len().min(prg_ram.len());
+                prg_ram[..len].copy_from_slice(&save[..len]);
+                info!("Loaded battery save \"{}\"", pending.path.display());
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read \"{}\"", pending.path.display()))
+            }
+        }
+
+        pending.ram = cpu.prg_ram().to_vec();
+        pending.dirty = false;
+        Ok(())
+    }
+
+    /// Take the RAM's changes, writing them once `FLUSH_INTERVAL` has passed since the last write.
+    pub fn after_frame(&mut self, cpu: &Cpu) -> Result<()> {
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            return self.flush(cpu);
+        }
+
+        self.update(cpu);
+        Ok(())
+    }
+
+    /// Write the RAM now if it changed, e.g. once running stops.
+    pub fn flush(&mut self, cpu: &Cpu) -> Result<()> {
+        self.last_flush = Instant::now();
+        self.update(cpu);
+        self.pending.lock().unwrap().flush()
+    }
+
+    fn update(&self, cpu: &Cpu) {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.ram != cpu.prg_ram() {
+            pending.ram.clear();
+            pending.ram.extend_from_slice(cpu.prg_ram());
+            pending.dirty = true;
+        }
+    }
+
+    /// Also write the RAM as of the last frame if the process panics, or is interrupted by SIGINT,
+    /// SIGTERM or SIGHUP on Unix. Interrupting twice quits without waiting.
+    pub fn flush_on_exit(&self) {
+        EXIT_FLUSHES
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&self.pending));
+
+        EXIT_HOOKS.call_once(|| {
+            let hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |panic| {
+                hook(panic);
+                flush_all();
+            }));
+
+            #[cfg(unix)]
+            signals::install(|signal| {
+                flush_all();
+                std::process::exit(128 + signal);
+            });
+        });
+    }
+}
+
+/// Flush the saves registered with `flush_on_exit`, skipping any locked by a panicking thread.
+fn flush_all() {
+    let flushes = match EXIT_FLUSHES.try_lock() {
+        Ok(flushes) => flushes,
+        Err(_) => return,
+    };
+
+    for pending in flushes.iter().filter_map(Weak::upgrade) {
+        if let Ok(mut pending) = pending.try_lock() {
+            if let Err(err) = pending.flush() {
+                error!("Failed to save the battery RAM: {:#}", err);
+            }
+        }
+    }
+}
+
+/// Replace `path` with `data` all at once, through a temporary file next to it.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+}
+
+/// Signal handlers can't do much safely, they pass the signal through a pipe to a thread that
+/// does the work.
+#[cfg(unix)]
+mod signals {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+    use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+    use std::thread;
+    use tracing::warn;
+
+    static PIPE: AtomicI32 = AtomicI32::new(-1);
+    static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_signal(signal: libc::c_int) {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            unsafe { libc::_exit(128 + signal) };
+        }
+
+        let byte = signal as u8;
+        unsafe {
+            libc::write(
+                PIPE.load(Ordering::SeqCst),
+                &byte as *const u8 as *const libc::c_void,
+                1,
+            )
+        };
+    }
+
+    /// Call `on_exit` with the signal when interrupted.
+    pub fn install(on_exit: fn(i32)) {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            warn!("Failed to catch signals, saves are only written while running");
+            return;
+        }
+        PIPE.store(fds[1], Ordering::SeqCst);
+
+        let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+        thread::spawn(move || {
+            let mut signal = [0];
+            if reader.read_exact(&mut signal).is_ok() {
+                on_exit(signal[0] as i32);
+            }
+        });
+
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            unsafe { libc::signal(signal, on_signal as *const () as libc::sighandler_t) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::NesFile;
+
+    #[test]
+    fn test_battery_save() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("nes-battery-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let new_cpu = || NesFile::new("test/nestest.nes".to_string()).map(Cpu::new);
+
+        let mut save = BatterySave::new("roms/nestest.nes").in_directory(&dir);
+        assert_eq!(save.path(), dir.join("nestest.sav"));
+
+        // Nothing to load, or to write until the game writes.
+        let mut cpu = new_cpu()?;
+        save.load(&mut cpu)?;
+        save.flush(&cpu)?;
+        assert!(!save.path().exists());
+
+        // Written once the interval has passed.
+        cpu.write(0x6000, 0x42);
+        save.after_frame(&cpu)?;
+        assert!(!save.path().exists());
+        save.flush(&cpu)?;
+        assert_eq!(fs::read(save.path())?[..2], [0x42, 0]);
+        assert!(!dir.join("nestest.sav.tmp").exists());
+
+        let mut cpu = new_cpu()?;
+        BatterySave::new("nestest.nes")
+            .in_directory(&dir)
+            .load(&mut cpu)?;
+        fs::remove_dir_all(&dir)?;
+        assert_eq!(cpu.peek(0x6000), 0x42);
+        Ok(())
+    }
+}
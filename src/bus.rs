@@ -0,0 +1,19 @@
+/// What the CPU is wired to on a machine other than the NES, for reusing it as a plain 6502.
+///
+/// With a bus plugged in, see `Cpu::with_bus`, every access the CPU makes goes through it: operands,
+/// the stack at $0100-$01FF and the vectors. The NES's PPU, APU and controllers are left out. A
+/// 6510's I/O port at $00 and $01 is the bus's to implement too.
+pub trait Bus: Send {
+    /// Read a byte, with any side effects of the device behind it.
+    fn read(&mut self, addr: u16) -> u8;
+
+    /// Read a byte without side effects, for the trace log and the debugger.
+    fn peek(&self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, value: u8);
+
+    /// Whether the IRQ line is asserted, it's polled after every instruction.
+    fn irq(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,21 @@
+/// Serialize large byte arrays, boxed or not, serde only handles arrays of up to 32 elements.
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::convert::TryFrom;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S: Serializer, T: Borrow<[u8; N]>, const N: usize>(
+    array: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(array.borrow())
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>, T: TryFrom<Vec<u8>>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    let bytes = Vec::<u8>::deserialize(deserializer)?;
+    let length = bytes.len();
+    T::try_from(bytes).map_err(|_| D::Error::invalid_length(length, &"a byte array"))
+}
@@ -0,0 +1,273 @@
+/// Cheat codes, Game Genie codes and raw Pro Action Replay style codes.
+///
+/// Both replace what the CPU reads from an address: codes for ROM patch the game as the Game Genie
+/// does, optionally only when the ROM holds an expected value, and codes for RAM freeze it at a
+/// value. Memory isn't modified so disabling a code restores the game and save states stay clean.
+/// See http://wiki.nesdev.com/w/index.php/Game_Genie.
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+/// Letters of Game Genie codes, each stands for its index.
+const GAME_GENIE_LETTERS: &str = "APZLGITYEOXUKSVN";
+
+/// Start of the ROM, lower addresses are RAM and registers.
+const ROM_START: u16 = 0x8000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    /// As it was entered, e.g. "SXIOPO" or "0075:09".
+    pub code: String,
+
+    pub address: u16,
+    pub value: u8,
+
+    /// Only replace the value when the ROM holds this one, the same address is often used by
+    /// several banks.
+    pub compare: Option<u8>,
+
+    pub enabled: bool,
+}
+
+impl Cheat {
+    /// Decode a 6 or 8 letter Game Genie code.
+    fn game_genie(code: &str) -> Option<Self> {
+        let n: Vec<u16> = code
+            .chars()
+            .map(|c| GAME_GENIE_LETTERS.find(c).map(|n| n as u16))
+            .collect::<Option<_>>()?;
+        if n.len() != 6 && n.len() != 8 {
+            return None;
+        }
+
+        let address = ROM_START
+            | (n[3] & 7) << 12
+            | (n[5] & 7) << 8
+            | (n[4] & 8) << 8
+            | (n[2] & 7) << 4
+            | (n[1] & 8) << 4
+            | (n[4] & 7)
+            | (n[3] & 8);
+
+        // Bit 3 of the value comes from the last letter, the 6th letter of 8 letter codes goes
+        // into the compare value instead.
+        let last = *n.last()?;
+        let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7) | (last & 8);
+        let compare = if n.len() == 8 {
+            Some((n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8))
+        } else {
+            None
+        };
+
+        Some(Cheat {
+            code: code.to_string(),
+            address,
+            value: value as u8,
+            compare: compare.map(|compare| compare as u8),
+            enabled: true,
+        })
+    }
+
+    /// Decode a raw code, "AAAAVV" like Pro Action Replay, "AAAA:VV" or "AAAA?CC:VV" like FCEUX.
+    fn raw(code: &str) -> Option<Self> {
+        let hex_byte = |digits: &str| {
+            if digits.len() == 2 {
+                u8::from_str_radix(digits, 16).ok()
+            } else {
+                None
+            }
+        };
+
+        let (target, value) = match code.split_once(':') {
+            Some(parts) => parts,
+            None if code.len() == 6 && code.is_char_boundary(4) => code.split_at(4),
+            None => return None,
+        };
+        let (address, compare) = match target.split_once('?') {
+            Some((address, compare)) => (address, Some(hex_byte(compare)?)),
+            None => (target, None),
+        };
+        if address.len() != 4 {
+            return None;
+        }
+
+        Some(Cheat {
+            code: code.to_string(),
+            address: u16::from_str_radix(address, 16).ok()?,
+            value: hex_byte(value)?,
+            compare,
+            enabled: true,
+        })
+    }
+}
+
+impl FromStr for Cheat {
+    type Err = String;
+
+    /// A Game Genie code or a raw code, upper or lower case, starting enabled.
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let code = code.trim().to_ascii_uppercase();
+        Cheat::game_genie(&code)
+            .or_else(|| Cheat::raw(&code))
+            .ok_or_else(|| format!("Invalid cheat code \"{}\".", code))
+    }
+}
+
+impl fmt::Display for Cheat {
+    /// e.g. "ZEXPYGLA   $94A7 = 02 if 03  on".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<10} ${:04X} = {:02X}",
+            self.code, self.address, self.value
+        )?;
+        if let Some(compare) = self.compare {
+            write!(f, " if {:02X}", compare)?;
+        }
+
+        write!(f, "  {}", if self.enabled { "on" } else { "off" })
+    }
+}
+
+/// The cheats entered, applied to the CPU's reads while enabled.
+#[derive(Clone, Debug, Default)]
+pub struct Cheats(Vec<Cheat>);
+
+impl Cheats {
+    /// Returns the cheat's index, used to refer to it later.
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.0.push(cheat);
+        self.0.len() - 1
+    }
+
+    /// Returns the removed cheat, later cheats move down an index.
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index < self.0.len() {
+            Some(self.0.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Returns false if there's no cheat at the index.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.0.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> {
+        self.0.iter()
+    }
+
+    /// What the CPU reads at `address`, where memory holds `value`.
+    pub fn apply(&self, address: u16, value: u8) -> u8 {
+        self.0
+            .iter()
+            .find(|cheat| {
+                cheat.enabled
+                    && cheat.address == address
+                    && cheat.compare.is_none_or(|compare| compare == value)
+            })
+            .map_or(value, |cheat| cheat.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::ines;
+
+    #[test]
+    fn test_decode() {
+        let cheat: Cheat = "SXIOPO".parse().unwrap();
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.compare),
+            (0x91D9, 0xAD, None)
+        );
+
+        let cheat: Cheat = "zexpygla".parse().unwrap();
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.compare),
+            (0x94A7, 0x02, Some(0x03))
+        );
+        assert_eq!(cheat.to_string(), "ZEXPYGLA   $94A7 = 02 if 03  on");
+
+        let cheat: Cheat = "007509".parse().unwrap();
+        assert_eq!((cheat.address, cheat.value), (0x0075, 0x09));
+        assert_eq!(
+            "0075:09".parse(),
+            Ok(Cheat {
+                code: "0075:09".to_string(),
+                ..cheat
+            })
+        );
+
+        let cheat: Cheat = "C123?4C:EA".parse().unwrap();
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.compare),
+            (0xC123, 0xEA, Some(0x4C))
+        );
+
+        for code in &["SXIOP", "SXIOPOA", "0075:9", "75:09", "0075?1:09", "BBBB"] {
+            assert!(code.parse::<Cheat>().is_err(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut cheats = Cheats::default();
+        let freeze = cheats.add("0075:09".parse().unwrap());
+        cheats.add("C123?4C:EA".parse().unwrap());
+
+        assert_eq!(cheats.apply(0x0075, 0x03), 0x09);
+        assert_eq!(cheats.apply(0x0076, 0x03), 0x03);
+        assert_eq!(cheats.apply(0xC123, 0x4C), 0xEA);
+        assert_eq!(cheats.apply(0xC123, 0x20), 0x20);
+
+        assert!(cheats.set_enabled(freeze, false));
+        assert_eq!(cheats.apply(0x0075, 0x03), 0x03);
+        assert!(!cheats.set_enabled(2, false));
+
+        assert_eq!(
+            cheats.remove(freeze).map(|cheat| cheat.address),
+            Some(0x0075)
+        );
+        assert_eq!(cheats.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_cpu() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        // LDA #$01, STA $0300.
+        cpu.memory[0x0200..0x0205].copy_from_slice(&[0xA9, 0x01, 0x8D, 0x00, 0x03]);
+        cpu.program_counter = 0x0200;
+
+        // Patching the operand changes what's executed, memory is untouched.
+        cpu.cheats.add("0201:05".parse().unwrap());
+        cpu.cheats.add("0300:07".parse().unwrap());
+        cpu.step_instruction()?;
+        cpu.step_instruction()?;
+        assert_eq!(cpu.memory[0x0300], 0x05);
+        assert_eq!(cpu.memory[0x0201], 0x01);
+
+        // Frozen for the CPU.
+        assert_eq!(cpu.read(0x0300), 0x07);
+        assert_eq!(cpu.peek(0x0300), 0x07);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+/// Master clock of the console, divided down for the CPU and the PPU. The APU runs off the CPU's
+/// clock. See https://www.nesdev.org/wiki/Cycle_reference_chart.
+///
+/// NTSC consoles divide 21.477272 MHz by 12 for the CPU and by 4 for the PPU, three dots per CPU
+/// cycle. PAL consoles divide 26.601712 MHz by 16 and by 5, so there are 3.2 dots per CPU cycle:
+/// every fifth cycle the PPU runs an extra dot. Dendy clones divide the PAL clock by 15 for the CPU,
+/// back to three dots per cycle.
+///
+/// Overclocking gives the CPU extra scanlines once vertical blank starts. The PPU and the APU are
+/// paused during them, so to everything but the CPU's own loops they never happened: the picture,
+/// the sound and the timing between the components stay the same, games just have more time to
+/// finish their frame.
+use crate::ppu::DOTS_PER_SCANLINE;
+use serde::{Deserialize, Serialize};
+
+/// How the master clock is divided.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Timing {
+    /// Frequency of the master clock, in Hz.
+    pub master_hz: u64,
+
+    /// Master cycles per CPU cycle.
+    pub cpu_divider: u64,
+
+    /// Master cycles per PPU dot.
+    pub ppu_divider: u64,
+}
+
+impl Timing {
+    pub const NTSC: Timing = Timing {
+        master_hz: 21_477_272,
+        cpu_divider: 12,
+        ppu_divider: 4,
+    };
+
+    pub const PAL: Timing = Timing {
+        master_hz: 26_601_712,
+        cpu_divider: 16,
+        ppu_divider: 5,
+    };
+
+    pub const DENDY: Timing = Timing {
+        master_hz: 26_601_712,
+        cpu_divider: 15,
+        ppu_divider: 5,
+    };
+
+    /// CPU cycles per second.
+    pub fn cpu_hz(&self) -> f64 {
+        self.master_hz as f64 / self.cpu_divider as f64
+    }
+}
+
+/// When the PPU runs the dots it's owed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Scheduling {
+    /// After every CPU cycle, the PPU never lags behind.
+    #[default]
+    Interleaved,
+
+    /// Only when something depends on the PPU: its registers are accessed, DMA or the end of an
+    /// instruction, where the NMI is polled. Fewer switches between the components for the same
+    /// results.
+    CatchUp,
+}
+
+/// Keeps the PPU in step with the CPU, turning CPU cycles into PPU dots.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Clock {
+    timing: Timing,
+
+    /// Master cycles the CPU is ahead of the PPU, less than a dot.
+    remainder: u64,
+
+    /// Dots the PPU is owed when catching up.
+    pending_dots: u64,
+
+    /// Master cycles the CPU has left to run on its own, see `overclock`.
+    overclock: u64,
+}
+
+impl Clock {
+    pub fn new(timing: Timing) -> Self {
+        Clock {
+            timing,
+            remainder: 0,
+            pending_dots: 0,
+            overclock: 0,
+        }
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// The CPU ran a cycle, returns the dots the PPU has to run now.
+    pub fn cpu_cycle(&mut self, scheduling: Scheduling) -> u64 {
+        let master = self.remainder + self.timing.cpu_divider;
+        let dots = master / self.timing.ppu_divider;
+        self.remainder = master % self.timing.ppu_divider;
+
+        match scheduling {
+            Scheduling::Interleaved => dots,
+            Scheduling::CatchUp => {
+                self.pending_dots += dots;
+                0
+            }
+        }
+    }
+
+    /// Give the CPU the time of `scanlines` extra scanlines, with the PPU and the APU paused.
+    pub fn overclock(&mut self, scanlines: u16) {
+        self.overclock += scanlines as u64 * DOTS_PER_SCANLINE as u64 * self.timing.ppu_divider;
+    }
+
+    /// Use up a CPU cycle of the overclock. When it returns true the cycle is the CPU's alone,
+    /// `cpu_cycle` isn't called for it.
+    pub fn overclocked_cycle(&mut self) -> bool {
+        if self.overclock < self.timing.cpu_divider {
+            return false;
+        }
+
+        self.overclock -= self.timing.cpu_divider;
+        true
+    }
+
+    /// The dots the PPU is owed, for it to catch up with the CPU.
+    pub fn catch_up(&mut self) -> u64 {
+        core::mem::take(&mut self.pending_dots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dots(timing: Timing, scheduling: Scheduling, cycles: usize) -> Vec<u64> {
+        let mut clock = Clock::new(timing);
+        (0..cycles).map(|_| clock.cpu_cycle(scheduling)).collect()
+    }
+
+    #[test]
+    fn test_ratios() {
+        assert_eq!(dots(Timing::NTSC, Scheduling::Interleaved, 5), [3; 5]);
+        assert_eq!(
+            dots(Timing::PAL, Scheduling::Interleaved, 5),
+            [3, 3, 3, 3, 4]
+        );
+        assert_eq!(
+            dots(Timing::PAL, Scheduling::Interleaved, 1000)
+                .iter()
+                .sum::<u64>(),
+            3200
+        );
+        assert_eq!((Timing::NTSC.cpu_hz() / 1000.0).round(), 1790.0);
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let mut clock = Clock::new(Timing::PAL);
+        for _ in 0..10 {
+            assert_eq!(clock.cpu_cycle(Scheduling::CatchUp), 0);
+        }
+        assert_eq!(clock.catch_up(), 32);
+        assert_eq!(clock.catch_up(), 0);
+    }
+
+    #[test]
+    fn test_overclock() {
+        let cycles = |clock: &mut Clock| (0..).take_while(|_| clock.overclocked_cycle()).count();
+        let mut clock = Clock::new(Timing::NTSC);
+
+        // 113 2/3 cycles per scanline, the fraction is carried over to the next one.
+        clock.overclock(1);
+        assert_eq!(cycles(&mut clock), 113);
+        clock.overclock(2);
+        assert_eq!(cycles(&mut clock), 228);
+        assert_eq!(cycles(&mut clock), 0);
+    }
+}
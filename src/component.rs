@@ -0,0 +1,199 @@
+/// Lifecycle shared by the parts of the console, so the console, the save states and the debugger
+/// can handle them the same way.
+///
+/// The CPU owns the PPU and the APU and passes power on and reset down to them. Cartridges will
+/// implement it too once mappers have state of their own.
+use crate::apu::{self, Apu};
+use crate::cpu::Cpu;
+use crate::ppu::Ppu;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use anyhow::Result;
+#[cfg(feature = "std")]
+use serde::{de::DeserializeOwned, Serialize};
+
+pub trait Component {
+    /// Short name, e.g. "ppu".
+    fn name(&self) -> &'static str;
+
+    /// Back to the state right after power on. The cartridge stays inserted, callbacks and
+    /// settings are kept.
+    fn power_on(&mut self);
+
+    /// Press the reset button. See https://www.nesdev.org/wiki/CPU_power_up_state and
+    /// https://www.nesdev.org/wiki/PPU_power_up_state for what's kept.
+    fn reset(&mut self);
+
+    /// Advance by the component's smallest step: a dot for the PPU, a cycle for the APU and an
+    /// instruction for the CPU.
+    fn tick(&mut self);
+
+    /// Snapshot the state.
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Result<Vec<u8>>;
+
+    /// Restore a snapshot from `save_state`, callbacks and settings are kept.
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<()>;
+}
+
+#[cfg(feature = "std")]
+fn save<T: Serialize>(component: &T) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(component)?)
+}
+
+#[cfg(feature = "std")]
+fn load<T: DeserializeOwned>(state: &[u8]) -> Result<T> {
+    Ok(bincode::deserialize(state)?)
+}
+
+impl Component for Cpu {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn power_on(&mut self) {
+        Cpu::power_on(self);
+    }
+
+    fn reset(&mut self) {
+        Cpu::reset(self);
+    }
+
+    /// Unknown opcodes are handled by the policy, failing leaves the CPU where it is.
+    fn tick(&mut self) {
+        let _ = self.step_instruction();
+    }
+
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Result<Vec<u8>> {
+        Cpu::save_state(self)
+    }
+
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        Cpu::load_state(self, state)
+    }
+}
+
+impl Component for Ppu {
+    fn name(&self) -> &'static str {
+        "ppu"
+    }
+
+    fn power_on(&mut self) {
+        let mut ppu = self.powered_on();
+        ppu.take_callbacks(self);
+        *self = ppu;
+    }
+
+    fn reset(&mut self) {
+        Ppu::reset(self);
+    }
+
+    fn tick(&mut self) {
+        Ppu::tick(self);
+    }
+
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Result<Vec<u8>> {
+        save(self)
+    }
+
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut ppu: Ppu = load(state)?;
+        ppu.take_callbacks(self);
+        *self = ppu;
+        Ok(())
+    }
+}
+
+impl Component for Apu {
+    fn name(&self) -> &'static str {
+        "apu"
+    }
+
+    fn power_on(&mut self) {
+        let mut apu = Apu::new();
+        apu.set_region(self.region());
+        apu.take_callbacks(self);
+        *self = apu;
+    }
+
+    /// Silences the channels, the rest carries on.
+    fn reset(&mut self) {
+        self.write_register(apu::STATUS, 0);
+    }
+
+    fn tick(&mut self) {
+        Apu::tick(self);
+    }
+
+    #[cfg(feature = "std")]
+    fn save_state(&self) -> Result<Vec<u8>> {
+        save(self)
+    }
+
+    #[cfg(feature = "std")]
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut apu: Apu = load(state)?;
+        apu.take_callbacks(self);
+        *self = apu;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::NesFile;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    fn cpu() -> Cpu {
+        Cpu::new(NesFile::new("test/nestest.nes".to_string()).unwrap())
+    }
+
+    #[test]
+    fn test_power_on() {
+        let mut cpu = cpu();
+        let frames = Arc::new(AtomicUsize::new(0));
+        let counter = frames.clone();
+        cpu.ppu.on_frame_complete(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+
+        cpu.memory[0x0200..0x0203].copy_from_slice(&[0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+        cpu.a = 0x42;
+        cpu.run_frame();
+
+        Component::power_on(&mut cpu);
+        assert_eq!((cpu.a, cpu.memory[0x0200], cpu.cycles), (0, 0, 7));
+        assert_eq!(cpu.program_counter, 0xC004);
+        assert_eq!(cpu.ppu.frame_count(), 0);
+
+        // The callbacks are still there.
+        cpu.memory[0x0300..0x0303].copy_from_slice(&[0x4C, 0x00, 0x03]);
+        cpu.program_counter = 0x0300;
+        cpu.run_frame();
+        assert_eq!(frames.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_components() -> Result<()> {
+        let mut cpu = cpu();
+        let names: Vec<&str> = cpu.components().iter().map(|c| c.name()).collect();
+        assert_eq!(names, ["ppu", "apu"]);
+
+        for component in cpu.components() {
+            let state = component.save_state()?;
+            component.tick();
+            component.load_state(&state)?;
+            assert_eq!(component.save_state()?, state);
+        }
+        Ok(())
+    }
+}
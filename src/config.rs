@@ -0,0 +1,399 @@
+/// Configuration file, a TOML file of optional sections.
+///
+/// Anything left out keeps its default. Flags on the command line take precedence over the file,
+/// and a `[games.<name>]` table takes precedence over the rest of the file for that game:
+///
+///   [video]
+///   scale = 4
+///
+///   [games.smb.emulation]
+///   overclock = 20
+///
+/// Games are named after their file without the extension, or the CRC32 of their ROM in
+/// hexadecimal, e.g. `[games.3337EC46]`.
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub paths: PathsConfig,
+
+    /// Key bindings of each player's controller.
+    pub input: InputConfig,
+
+    pub video: VideoConfig,
+    pub audio: AudioConfig,
+    pub emulation: EmulationConfig,
+
+    /// Cheat codes entered at startup, each a `[[cheats]]` table.
+    pub cheats: Vec<CheatConfig>,
+
+    /// Memory writes, each a `[[pokes]]` table.
+    pub pokes: Vec<PokeConfig>,
+
+    /// Settings for single games, on top of the ones above.
+    pub games: BTreeMap<String, GameConfig>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Where ROMs given by a relative path are looked for when they aren't in the working
+    /// directory.
+    pub roms: Option<PathBuf>,
+
+    /// Where the save state slots are kept, next to the ROM by default.
+    pub states: Option<PathBuf>,
+
+    /// Where battery saves are kept, next to the ROM by default.
+    pub saves: Option<PathBuf>,
+
+    /// No-Intro DAT file `nes info` looks ROMs up in.
+    pub database: Option<PathBuf>,
+}
+
+/// The values are the same as the flags of the same name.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    pub filter: Option<String>,
+    pub scale: Option<usize>,
+    pub aspect_ratio: Option<String>,
+    pub overscan: Option<String>,
+    pub pacing: Option<String>,
+    pub palette: Option<String>,
+    pub hue: Option<f64>,
+    pub saturation: Option<f64>,
+    pub brightness: Option<f64>,
+    pub contrast: Option<f64>,
+    pub gamma: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// Target latency in milliseconds.
+    pub latency: Option<u64>,
+
+    /// Output rate in Hz and device buffer length in frames, the device's defaults otherwise.
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+
+    pub resampler: Option<String>,
+    pub mute: Option<Vec<String>>,
+    pub solo: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmulationConfig {
+    pub region: Option<String>,
+    pub overclock: Option<u16>,
+    pub speed: Option<f64>,
+    pub rewind_seconds: Option<f64>,
+    pub vs_ppu: Option<String>,
+    pub dip_switches: Option<String>,
+    pub ram_pattern: Option<String>,
+    pub unofficial_opcode: Option<String>,
+}
+
+/// Settings of a game, anything left out comes from the rest of the file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameConfig {
+    pub video: VideoConfig,
+    pub audio: AudioConfig,
+    pub emulation: EmulationConfig,
+
+    /// Entered on top of the cheats for every game.
+    pub cheats: Vec<CheatConfig>,
+
+    /// Written on top of the pokes for every game.
+    pub pokes: Vec<PokeConfig>,
+}
+
+impl VideoConfig {
+    /// Fill in what isn't set from `fallback`.
+    fn or(self, fallback: VideoConfig) -> Self {
+        VideoConfig {
+            filter: self.filter.or(fallback.filter),
+            scale: self.scale.or(fallback.scale),
+            aspect_ratio: self.aspect_ratio.or(fallback.aspect_ratio),
+            overscan: self.overscan.or(fallback.overscan),
+            pacing: self.pacing.or(fallback.pacing),
+            palette: self.palette.or(fallback.palette),
+            hue: self.hue.or(fallback.hue),
+            saturation: self.saturation.or(fallback.saturation),
+            brightness: self.brightness.or(fallback.brightness),
+            contrast: self.contrast.or(fallback.contrast),
+            gamma: self.gamma.or(fallback.gamma),
+        }
+    }
+}
+
+impl AudioConfig {
+    fn or(self, fallback: AudioConfig) -> Self {
+        AudioConfig {
+            latency: self.latency.or(fallback.latency),
+            sample_rate: self.sample_rate.or(fallback.sample_rate),
+            buffer_size: self.buffer_size.or(fallback.buffer_size),
+            resampler: self.resampler.or(fallback.resampler),
+            mute: self.mute.or(fallback.mute),
+            solo: self.solo.or(fallback.solo),
+        }
+    }
+}
+
+impl EmulationConfig {
+    fn or(self, fallback: EmulationConfig) -> Self {
+        EmulationConfig {
+            region: self.region.or(fallback.region),
+            overclock: self.overclock.or(fallback.overclock),
+            speed: self.speed.or(fallback.speed),
+            rewind_seconds: self.rewind_seconds.or(fallback.rewind_seconds),
+            vs_ppu: self.vs_ppu.or(fallback.vs_ppu),
+            dip_switches: self.dip_switches.or(fallback.dip_switches),
+            ram_pattern: self.ram_pattern.or(fallback.ram_pattern),
+            unofficial_opcode: self.unofficial_opcode.or(fallback.unofficial_opcode),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub player1: ButtonBindings,
+    pub player2: ButtonBindings,
+
+    /// Keys of the window's own actions, e.g. saving a state.
+    pub hotkeys: HotkeyBindings,
+}
+
+/// Name of the key bound to each button, e.g. "X", "Return" or "Up".
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ButtonBindings {
+    pub a: Option<String>,
+    pub b: Option<String>,
+    pub select: Option<String>,
+    pub start: Option<String>,
+    pub up: Option<String>,
+    pub down: Option<String>,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+/// Name of the key bound to each of the window's actions, like `ButtonBindings`. Binding a key
+/// here takes it from the action it had by default.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HotkeyBindings {
+    pub fast_forward: Option<String>,
+    pub fast_forward_toggle: Option<String>,
+    pub slower: Option<String>,
+    pub faster: Option<String>,
+    pub normal_speed: Option<String>,
+    pub pause: Option<String>,
+    pub frame_advance: Option<String>,
+    pub save_state: Option<String>,
+    pub load_state: Option<String>,
+    pub next_slot: Option<String>,
+    pub rewind: Option<String>,
+    pub trace: Option<String>,
+    pub gif_capture: Option<String>,
+    pub screenshot: Option<String>,
+    pub stats: Option<String>,
+    pub next_palette: Option<String>,
+    pub input_display: Option<String>,
+    pub coin_1: Option<String>,
+    pub coin_2: Option<String>,
+    pub service: Option<String>,
+    pub mute_pulse1: Option<String>,
+    pub mute_pulse2: Option<String>,
+    pub mute_triangle: Option<String>,
+    pub mute_noise: Option<String>,
+    pub mute_dmc: Option<String>,
+    pub next_solo: Option<String>,
+}
+
+/// A Game Genie or raw code, e.g. "SXIOPO" or "0075:09", left disabled with `enabled = false`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CheatConfig {
+    pub code: String,
+
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+/// A memory write, e.g. "$0075=09", made at power on or with `every_frame = true` after every
+/// frame.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PokeConfig {
+    pub poke: String,
+
+    #[serde(default)]
+    pub every_frame: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Config {
+    /// $XDG_CONFIG_HOME/nes/config.toml, or ~/.config/nes/config.toml.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config_home.join("nes").join("config.toml"))
+    }
+
+    /// Load the file at the default path, if there's one.
+    pub fn load_default() -> Result<Self> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config \"{}\"", path.display()))?;
+
+        Self::parse(&contents)
+            .with_context(|| format!("Failed to parse config \"{}\"", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// The settings of a game, `name` being its file name without the extension.
+    pub fn game(&self, name: &str, checksum: u32) -> GameConfig {
+        let mut settings = GameConfig {
+            video: self.video.clone(),
+            audio: self.audio.clone(),
+            emulation: self.emulation.clone(),
+            cheats: self.cheats.clone(),
+            pokes: self.pokes.clone(),
+        };
+
+        let game = self
+            .games
+            .get(name)
+            .or_else(|| self.games.get(&format!("{:08X}", checksum)));
+        if let Some(game) = game.cloned() {
+            settings.video = game.video.or(settings.video);
+            settings.audio = game.audio.or(settings.audio);
+            settings.emulation = game.emulation.or(settings.emulation);
+            settings.cheats.extend(game.cheats);
+            settings.pokes.extend(game.pokes);
+        }
+
+        settings
+    }
+
+    /// Find a ROM given on the command line, in the working directory or the ROM directory.
+    pub fn rom_path(&self, rom: &str) -> PathBuf {
+        let path = PathBuf::from(rom);
+        match &self.paths.roms {
+            Some(roms) if path.is_relative() && !path.exists() => roms.join(path),
+            _ => path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!(Config::parse("")?, Config::default());
+
+        let config = Config::parse(
+            r#"
+            [input.player2]
+            a = "K"
+            start = "Space"
+            "#,
+        )?;
+        assert_eq!(config.input.player2.a, Some("K".to_string()));
+        assert_eq!(config.input.player2.b, None);
+        assert_eq!(config.input.player1, ButtonBindings::default());
+
+        let config = Config::parse("[input.hotkeys]\nsave_state = \"F1\"")?;
+        assert_eq!(config.input.hotkeys.save_state, Some("F1".to_string()));
+        assert!(Config::parse("[input.hotkeys]\nsave = \"F1\"").is_err());
+
+        let config = Config::parse(
+            r#"
+            [[cheats]]
+            code = "SXIOPO"
+
+            [[cheats]]
+            code = "0075:09"
+            enabled = false
+            "#,
+        )?;
+        assert_eq!(config.cheats[0].code, "SXIOPO");
+        assert!(config.cheats[0].enabled);
+        assert!(!config.cheats[1].enabled);
+
+        let config = Config::parse(
+            r#"
+            [[pokes]]
+            poke = "$0075=09"
+
+            [[pokes]]
+            poke = "4016=01"
+            every_frame = true
+            "#,
+        )?;
+        assert_eq!(config.pokes[0].poke, "$0075=09");
+        assert!(!config.pokes[0].every_frame);
+        assert!(config.pokes[1].every_frame);
+
+        let config = Config::parse(
+            r#"
+            [video]
+            filter = "ntsc"
+            scale = 2
+
+            [emulation]
+            region = "pal"
+
+            [games.smb.video]
+            scale = 4
+
+            [games.3337EC46]
+            emulation = { overclock = 20 }
+            cheats = [{ code = "SXIOPO" }]
+            pokes = [{ poke = "0300=01" }]
+            "#,
+        )?;
+        let smb = config.game("smb", 0);
+        assert_eq!(smb.video.scale, Some(4));
+        assert_eq!(smb.video.filter, Some("ntsc".to_string()));
+        assert_eq!(smb.emulation.region, Some("pal".to_string()));
+        let by_checksum = config.game("other", 0x3337_EC46);
+        assert_eq!(by_checksum.video.scale, Some(2));
+        assert_eq!(by_checksum.emulation.overclock, Some(20));
+        assert_eq!(by_checksum.cheats.len(), 1);
+        assert_eq!(by_checksum.pokes.len(), 1);
+        assert_eq!(config.game("other", 0).emulation.overclock, None);
+
+        // Typos are errors rather than silently ignored.
+        assert!(Config::parse("[input.player1]\nstrat = \"Space\"").is_err());
+
+        Ok(())
+    }
+}
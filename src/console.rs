@@ -0,0 +1,271 @@
+/// The console as a whole, for embedding the emulator without knowing how it's put together.
+use crate::component::Component;
+use crate::controller::ControllerState;
+use crate::cpu::{Cpu, Stop};
+use crate::ines::NesFile;
+use crate::observer::{Instruction, MemoryAccess, Observers};
+use crate::ppu::{self, SCREEN_HEIGHT, SCREEN_WIDTH};
+use alloc::vec::Vec;
+
+/// What's shown without a cartridge.
+static BLANK_FRAME: [u8; SCREEN_WIDTH * SCREEN_HEIGHT] = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+/// A NES with two standard controllers plugged in.
+///
+/// It's `Send`, so consoles can be moved to other threads and many run in parallel, which is why
+/// the callbacks have to be `Send` too.
+#[derive(Default)]
+pub struct Nes {
+    /// The running console, there's nothing to run until a cartridge is inserted.
+    cpu: Option<Cpu>,
+
+    /// Buttons held on each controller, kept when the cartridge is swapped.
+    input: [ControllerState; 2],
+
+    /// Callbacks registered before a cartridge is inserted, moved to the console when it is.
+    observers: Observers,
+}
+
+impl Nes {
+    pub fn new() -> Self {
+        Nes::default()
+    }
+
+    /// Insert a cartridge and power on, the program starts at its reset vector. Anything running
+    /// before is lost.
+    pub fn insert_cartridge(&mut self, cartridge: NesFile) {
+        let mut cpu = Cpu::new(cartridge);
+        cpu.observers = core::mem::take(self.observers());
+        self.cpu = Some(cpu);
+    }
+
+    /// Press the reset button. Without a cartridge nothing happens.
+    pub fn reset(&mut self) {
+        if let Some(cpu) = &mut self.cpu {
+            Component::reset(cpu);
+        }
+    }
+
+    /// Turn the console off and on again, the cartridge stays in.
+    pub fn power_cycle(&mut self) {
+        if let Some(cpu) = &mut self.cpu {
+            Component::power_on(cpu);
+        }
+    }
+
+    /// Run until the next frame is complete, or a breakpoint is reached. Without a cartridge
+    /// nothing happens.
+    pub fn step_frame(&mut self) -> Stop {
+        match &mut self.cpu {
+            Some(cpu) => {
+                for (controller, &state) in cpu.controllers.iter_mut().zip(&self.input) {
+                    controller.set_state(state);
+                }
+                cpu.run_frame()
+            }
+            None => Stop::FrameComplete,
+        }
+    }
+
+    /// The last complete frame as palette indices, `SCREEN_WIDTH` by `SCREEN_HEIGHT`.
+    pub fn frame(&self) -> &[u8] {
+        match &self.cpu {
+            Some(cpu) => cpu.ppu.frame(),
+            None => &BLANK_FRAME,
+        }
+    }
+
+    /// The last complete frame as RGBA.
+    pub fn frame_rgba(&self) -> Vec<u8> {
+        ppu::to_rgba(self.frame())
+    }
+
+    /// Hold the buttons on the controller of player 0 or 1 from the next frame on.
+    pub fn set_input(&mut self, player: usize, buttons: ControllerState) {
+        self.input[player] = buttons;
+    }
+
+    /// Call `callback` before each instruction is executed, kept when the cartridge is swapped.
+    pub fn on_instruction<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Instruction) + Send + 'static,
+    {
+        self.observers().on_instruction(callback);
+    }
+
+    /// Call `callback` with each completed frame of palette indices.
+    pub fn on_frame<F>(&mut self, callback: F)
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.observers().on_frame(callback);
+    }
+
+    /// Call `callback` for every read and write the CPU makes.
+    pub fn on_memory_access<F>(&mut self, callback: F)
+    where
+        F: FnMut(&MemoryAccess) + Send + 'static,
+    {
+        self.observers().on_memory_access(callback);
+    }
+
+    fn observers(&mut self) -> &mut Observers {
+        match &mut self.cpu {
+            Some(cpu) => &mut cpu.observers,
+            None => &mut self.observers,
+        }
+    }
+
+    /// The console's components, when a cartridge is inserted.
+    pub fn cpu(&self) -> Option<&Cpu> {
+        self.cpu.as_ref()
+    }
+
+    pub fn cpu_mut(&mut self) -> Option<&mut Cpu> {
+        self.cpu.as_mut()
+    }
+}
+
+/// A console already running, e.g. set up with cheats and breakpoints.
+impl From<Cpu> for Nes {
+    fn from(cpu: Cpu) -> Self {
+        Nes {
+            cpu: Some(cpu),
+            ..Nes::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::test_util;
+
+    /// LDA #$05, STA $0300, JMP $C002.
+    const STORE_AND_SPIN: [u8; 8] = [0xA9, 0x05, 0x8D, 0x00, 0x03, 0x4C, 0x02, 0xC0];
+
+    #[test]
+    fn test_nes() -> anyhow::Result<()> {
+        let mut nes = Nes::new();
+        assert_eq!(nes.step_frame(), Stop::FrameComplete);
+        assert!(nes.frame().iter().all(|&pixel| pixel == 0));
+
+        nes.insert_cartridge(test_util::spinning_nrom());
+
+        nes.set_input(1, ControllerState(ControllerState::START));
+
+        assert_eq!(nes.step_frame(), Stop::FrameComplete);
+        assert_eq!(nes.frame_rgba().len(), SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+
+        let cpu = nes.cpu().unwrap();
+        assert_eq!(cpu.ppu.frame_count(), 1);
+        assert_eq!(
+            cpu.controllers[1].state(),
+            ControllerState(ControllerState::START)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_vector() {
+        // LDA #$02, STA $0300, JMP $C015 from $C010, $C000 is BRK.
+        let rom =
+            test_util::nrom_file_at(&[0xA9, 0x02, 0x8D, 0x00, 0x03, 0x4C, 0x15, 0xC0], 0xC010);
+
+        let mut nes = Nes::new();
+        nes.insert_cartridge(NesFile::from_bytes(&rom).unwrap());
+        assert_eq!(nes.cpu().unwrap().program_counter, 0xC010);
+
+        nes.step_frame();
+        assert_eq!(nes.cpu().unwrap().memory[0x0300], 0x02);
+
+        // Powering on again starts there too.
+        nes.cpu_mut().unwrap().memory[0x0300] = 0x00;
+        nes.power_cycle();
+        assert_eq!(nes.cpu().unwrap().program_counter, 0xC010);
+        nes.step_frame();
+        assert_eq!(nes.cpu().unwrap().memory[0x0300], 0x02);
+    }
+
+    #[test]
+    fn test_threads() -> anyhow::Result<()> {
+        fn assert_send<T: Send>() {}
+        assert_send::<Nes>();
+        assert_send::<crate::env::NesEnv>();
+
+        let rom = test_util::nrom_file(&STORE_AND_SPIN);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let rom = rom.clone();
+                std::thread::spawn(move || {
+                    let mut nes = Nes::new();
+                    nes.insert_cartridge(NesFile::from_bytes(&rom).unwrap());
+
+                    for _ in 0..3 {
+                        assert_eq!(nes.step_frame(), Stop::FrameComplete);
+                    }
+                    let cpu = nes.cpu().unwrap();
+                    (cpu.memory[0x0300], cpu.cycles)
+                })
+            })
+            .collect();
+
+        let results: Vec<(u8, u64)> = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(results[0].0, 0x05);
+        assert!(results.iter().all(|&result| result == results[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_observers() -> anyhow::Result<()> {
+        use crate::observer::AccessKind;
+        use std::sync::Arc;
+        use std::sync::Mutex;
+
+        let instructions = Arc::new(Mutex::new(Vec::new()));
+        let accesses = Arc::new(Mutex::new(Vec::new()));
+        let frames = Arc::new(Mutex::new(0));
+
+        // Registered before and after inserting the cartridge.
+        let mut nes = Nes::new();
+        let i = instructions.clone();
+        nes.on_instruction(move |instruction| i.lock().unwrap().push(*instruction));
+
+        nes.insert_cartridge(test_util::nrom(&STORE_AND_SPIN));
+
+        let a = accesses.clone();
+        nes.on_memory_access(move |access| {
+            if access.address == 0x0300 {
+                a.lock().unwrap().push(*access);
+            }
+        });
+        let f = frames.clone();
+        nes.on_frame(move |frame| {
+            assert_eq!(frame.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+            *f.lock().unwrap() += 1;
+        });
+
+        nes.step_frame();
+        assert_eq!(*frames.lock().unwrap(), 1);
+
+        let instructions = instructions.lock().unwrap();
+        assert_eq!(
+            (instructions[0].address, instructions[0].opcode),
+            (0xC000, 0xA9)
+        );
+        assert_eq!((instructions[1].address, instructions[1].a), (0xC002, 0x05));
+        assert!(instructions[1].cycles > instructions[0].cycles);
+
+        let accesses = accesses.lock().unwrap();
+        assert!(accesses
+            .iter()
+            .all(|access| access.kind == AccessKind::Write && access.value == 0x05));
+        assert!(accesses.len() > 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+
+/// Every button with its name in the config and scripts.
+pub const BUTTONS: [(&str, u8); 8] = [
+    ("a", ControllerState::A),
+    ("b", ControllerState::B),
+    ("select", ControllerState::SELECT),
+    ("start", ControllerState::START),
+    ("up", ControllerState::UP),
+    ("down", ControllerState::DOWN),
+    ("left", ControllerState::LEFT),
+    ("right", ControllerState::RIGHT),
+];
+
+/// Buttons held on a controller, one bit per button in the order they are shifted out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ControllerState(pub u8);
+
+impl ControllerState {
+    pub const A: u8 = 0b0000_0001;
+    pub const B: u8 = 0b0000_0010;
+    pub const SELECT: u8 = 0b0000_0100;
+    pub const START: u8 = 0b0000_1000;
+    pub const UP: u8 = 0b0001_0000;
+    pub const DOWN: u8 = 0b0010_0000;
+    pub const LEFT: u8 = 0b0100_0000;
+    pub const RIGHT: u8 = 0b1000_0000;
+
+    /// The button with its name in the config, e.g. "start".
+    pub fn button(name: &str) -> Option<u8> {
+        BUTTONS
+            .iter()
+            .find(|&&(button_name, _)| button_name == name)
+            .map(|&(_, button)| button)
+    }
+
+    /// Press or release the buttons in the mask.
+    pub fn set(&mut self, buttons: u8, pressed: bool) {
+        if pressed {
+            self.0 |= buttons;
+        } else {
+            self.0 &= !buttons;
+        }
+    }
+}
+
+/// Standard controller, read serially through $4016 and $4017.
+/// See http://wiki.nesdev.com/w/index.php/Standard_controller.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Controller {
+    /// Buttons currently held, set by the frontend.
+    state: ControllerState,
+
+    /// While set the shift register is continuously reloaded with the buttons.
+    strobe: bool,
+
+    /// Buttons latched for reading, shifted out one bit per read.
+    shift_register: u8,
+
+    /// Bits shifted out since the last reload.
+    reads: u8,
+}
+
+impl Controller {
+    /// The upper bits aren't driven by the controller, they keep the last value on the bus which
+    /// is usually the high byte of the address ($40).
+    const OPEN_BUS: u8 = 0x40;
+
+    pub fn state(&self) -> ControllerState {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: ControllerState) {
+        self.state = state;
+    }
+
+    /// Write to $4016, bit 0 controls the strobe.
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        if self.strobe {
+            self.reload();
+        }
+    }
+
+    fn reload(&mut self) {
+        self.shift_register = self.state.0;
+        self.reads = 0;
+    }
+
+    /// Read the next button, with all the side effects.
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+
+        let value = self.peek();
+        if !self.strobe && self.reads < 8 {
+            self.shift_register >>= 1;
+            self.reads += 1;
+        }
+
+        value
+    }
+
+    /// Value the next read would return.
+    pub fn peek(&self) -> u8 {
+        // Official controllers return 1 once all the buttons have been read.
+        let bit = if self.strobe {
+            self.state.0 & 0x01
+        } else if self.reads >= 8 {
+            1
+        } else {
+            self.shift_register & 0x01
+        };
+
+        Self::OPEN_BUS | bit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shift_out() {
+        let mut controller = Controller::default();
+        controller.set_state(ControllerState(ControllerState::A | ControllerState::START));
+
+        controller.write(1);
+        controller.write(0);
+
+        let bits: Vec<u8> = (0..10).map(|_| controller.read() & 0x01).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_strobe_held() {
+        let mut controller = Controller::default();
+        controller.set_state(ControllerState(ControllerState::A));
+        controller.write(1);
+
+        // While strobing, A is returned on every read.
+        for _ in 0..10 {
+            assert_eq!(controller.read(), 0x41);
+        }
+
+        controller.set_state(ControllerState(ControllerState::B));
+        assert_eq!(controller.read(), 0x40);
+    }
+}
@@ -2,20 +2,193 @@
 /// Used http://nesdev.com/6502_cpu.txt as a reference.
 ///
 /// The NMOS 65xx processors have 256 bytes of stack memory ranging from $0100 to $01FF.
-use log::info;
-use std::convert::From;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::From;
+use tracing::trace;
+#[cfg(feature = "std")]
+use tracing::{error, warn};
 
+use crate::apu::{self, Apu};
+#[cfg(feature = "mos6502")]
+use crate::bus::Bus;
+use crate::cheats::Cheats;
+use crate::clock::{Clock, Scheduling, Timing};
+use crate::component::Component;
+use crate::controller::Controller;
+#[cfg(feature = "std")]
+use crate::debugger::{
+    self, Breakpoints, CallStack, Entry, EventLog, InterruptSource, Profiler, RegisterWrite,
+    Symbols, Tracer, Watches,
+};
+use crate::env::SplitMix64;
+use crate::mapper::Mapper;
+use crate::observer::{AccessKind, Instruction, MemoryAccess, Observers};
 use crate::opcode::{self, *};
+use crate::poke::Poke;
+use crate::ppu::Ppu;
+use crate::region::Region;
+use crate::vs_system::VsSystem;
+use crate::zapper::Zapper;
+use serde::{Deserialize, Serialize};
 
 const MEMORY_SIZE_MAX: usize = 0xffff + 1;
 pub type AddressSpace = [u8; MEMORY_SIZE_MAX];
 
+/// Why running stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    FrameComplete,
+
+    /// The next instruction is at a breakpoint.
+    Breakpoint,
+
+    /// The next opcode isn't implemented and the policy is to fail.
+    UnknownOpcode(DecodeError),
+
+    /// The next instruction jumps to itself and `detect_traps` is set.
+    Trapped,
+
+    /// The CPU has run up to `cycle_limit`.
+    CycleLimit,
+}
+
+/// What the CPU does with an opcode it doesn't implement.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnknownOpcodePolicy {
+    /// Stop running, `step_instruction` returns the error. Running again fails again.
+    #[default]
+    Fail,
+
+    /// Log it and carry on as if it was a one byte NOP.
+    Skip,
+
+    /// Jam like the real CPU does on its KIL opcodes: no more instructions run until reset, the
+    /// PPU and APU carry on.
+    Halt,
+}
+
+impl core::str::FromStr for UnknownOpcodePolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "fail" => Ok(UnknownOpcodePolicy::Fail),
+            "skip" => Ok(UnknownOpcodePolicy::Skip),
+            "halt" => Ok(UnknownOpcodePolicy::Halt),
+            _ => Err(format!("Unknown opcode policy \"{}\".", name)),
+        }
+    }
+}
+
+/// What the CPU does with unofficial opcodes, the ones that work on the NMOS 6502 but were never
+/// documented. Some games rely on them, often by accident.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnofficialOpcodePolicy {
+    /// Execute them like any other.
+    #[default]
+    Run,
+
+    /// Log each one executed with its address.
+    Log,
+
+    /// Stop in the debugger before executing them, like at a breakpoint.
+    Break,
+}
+
+impl core::str::FromStr for UnofficialOpcodePolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "run" => Ok(UnofficialOpcodePolicy::Run),
+            "log" => Ok(UnofficialOpcodePolicy::Log),
+            "break" => Ok(UnofficialOpcodePolicy::Break),
+            _ => Err(format!("Unknown unofficial opcode policy \"{}\".", name)),
+        }
+    }
+}
+
+/// What the console's RAM holds at power on. The real console's RAM comes up with whatever its
+/// cells settle to, mostly but not always the same on a console, and a few games read it before
+/// writing it, e.g. to seed their random numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RamPattern {
+    #[default]
+    Zero,
+
+    /// Every byte $FF.
+    Ones,
+
+    /// Four bytes of $00 then four of $FF, repeating, like FCEUX.
+    Alternating,
+
+    /// Pseudo-random bytes, the same ones every time for a seed.
+    Random(u64),
+}
+
+impl RamPattern {
+    /// Fill `ram` with the pattern.
+    pub fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamPattern::Zero => ram.fill(0),
+            RamPattern::Ones => ram.fill(0xFF),
+            RamPattern::Alternating => {
+                for (i, byte) in ram.iter_mut().enumerate() {
+                    *byte = if i & 4 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+            RamPattern::Random(seed) => {
+                let mut rng = SplitMix64(seed);
+                for chunk in ram.chunks_mut(8) {
+                    let bytes = rng.next().to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+impl core::str::FromStr for RamPattern {
+    type Err = String;
+
+    /// "zero", "ones", "alternating", or "random" optionally followed by a decimal seed, e.g.
+    /// "random:42". The seed is 0 without one.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.split_once(':') {
+            Some(("random", seed)) => seed
+                .parse()
+                .map(RamPattern::Random)
+                .map_err(|_| format!("Invalid RAM pattern seed \"{}\".", seed)),
+            Some(_) => Err(format!("Unknown RAM pattern \"{}\".", name)),
+            None => match name {
+                "zero" => Ok(RamPattern::Zero),
+                "ones" => Ok(RamPattern::Ones),
+                "alternating" => Ok(RamPattern::Alternating),
+                "random" => Ok(RamPattern::Random(0)),
+                _ => Err(format!("Unknown RAM pattern \"{}\".", name)),
+            },
+        }
+    }
+}
+
+/// Vectors to set when loading a raw program, `None` keeps what the program has at $FFFA-$FFFF.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vectors {
+    pub nmi: Option<u16>,
+    pub reset: Option<u16>,
+    pub irq: Option<u16>,
+}
+
 /// State of the CPU.
 ///
 /// For simplicity, we store the bank fixed to the CPU for now. As we build to a more advanced
 /// structure we will move this outside of the CPU (e.g. bank switching).
 ///
 /// TODO: Make these structs with certain operations available on them.
+#[derive(Deserialize, Serialize)]
 pub struct Cpu {
     /// Program counter.
     ///
@@ -41,66 +214,1053 @@ pub struct Cpu {
 
     /// Memory.
     ///
-    /// Limited to NROM thus only has 64 kibibytes.
-    pub memory: AddressSpace,
+    /// Limited to NROM thus only has 64 kibibytes. Boxed to keep the CPU cheap to move. Save states
+    /// store it after the rest of the console, leaving the ROM out, see `save_state()`.
+    #[serde(skip, default = "Cpu::empty_memory")]
+    pub memory: Box<AddressSpace>,
+
+    /// The mapper's registers, whether the cartridge's RAM at $6000-$7FFF can be read and
+    /// written.
+    pub mapper: Mapper,
+
+    /// Picture processing unit.
+    pub ppu: Ppu,
+
+    /// Audio processing unit.
+    pub apu: Apu,
+
+    /// Divides the master clock between the CPU and the PPU.
+    pub clock: Clock,
+
+    /// When the PPU catches up with the CPU.
+    #[serde(skip)]
+    pub scheduling: Scheduling,
+
+    /// Extra scanlines of CPU time after each vertical blank starts, see `Clock::overclock`.
+    #[serde(skip)]
+    pub overclock_scanlines: u16,
+
+    /// Controllers plugged into the two ports.
+    pub controllers: [Controller; 2],
+
+    /// The cabinet's DIP switches and coin slots when running on a VS System.
+    pub vs_system: Option<VsSystem>,
+
+    /// Zapper plugged into the second port instead of a controller.
+    #[serde(skip)]
+    pub zapper: Option<Zapper>,
+
+    /// Cheat codes replacing what's read from memory.
+    #[serde(skip)]
+    pub cheats: Cheats,
+
+    /// Written through the bus after every frame, see `poke`.
+    #[serde(skip)]
+    pub pokes: Vec<Poke>,
+
+    /// Addresses running stops at, before executing the instruction.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub breakpoints: Breakpoints,
+
+    /// Names of addresses, used by the debugger and the trace log.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub symbols: Symbols,
+
+    /// Trace log of the executed instructions.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub tracer: Tracer,
+
+    /// Execution counts of the addresses and opcodes.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub profiler: Profiler,
+
+    /// Writes to the PPU's and APU's registers with when in the frame they happened.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub events: EventLog,
+
+    /// Calls and interrupts the CPU hasn't returned from. Loading a state keeps it rather than
+    /// starting empty, which would make every return from then on unmatched.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub call_stack: CallStack,
+
+    /// Expressions shown after every frame.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub watches: Watches,
+
+    /// Script reacting to the emulation.
+    #[cfg(feature = "scripting")]
+    #[serde(skip)]
+    pub script: Option<Box<crate::script::ScriptHost>>,
+
+    /// Callbacks of embedders watching the console run.
+    #[serde(skip)]
+    pub observers: Observers,
+
+    /// Breakpoint running last stopped at.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    stopped_at: Option<u16>,
+
+    /// What to do with opcodes that aren't implemented.
+    #[serde(skip)]
+    pub unknown_opcode: UnknownOpcodePolicy,
+
+    /// What to do with unofficial opcodes.
+    #[serde(skip)]
+    pub unofficial_opcode: UnofficialOpcodePolicy,
+
+    /// What RAM holds at power on, see `set_ram_pattern`.
+    #[serde(skip)]
+    pub(crate) ram_pattern: RamPattern,
+
+    /// Stop running at instructions jumping to themselves, see `opcode::is_trap`. Meant for test
+    /// ROMs, games spin like that waiting for the NMI.
+    #[serde(skip)]
+    pub detect_traps: bool,
+
+    /// Stop running once `cycles` reaches it, e.g. to run a fixed amount of time headless.
+    #[serde(skip)]
+    pub cycle_limit: Option<u64>,
+
+    /// Halted on an unknown opcode, until reset.
+    jammed: bool,
+
+    /// Cycles the CPU is suspended for by an OAM DMA, clocked with the rest of the instruction
+    /// that started it.
+    dma_cycles: u64,
+
+    /// The whole address space is RAM, without the PPU, APU and controllers' registers and their
+    /// interrupts. Set for raw programs, see `load_raw_program`.
+    #[serde(skip)]
+    pub(crate) flat_memory: bool,
+
+    /// CRC32 of the cartridge's ROM, see `NesFile::checksum()`. Save states only load into the
+    /// ROM they were taken with.
+    #[serde(skip)]
+    pub(crate) rom_checksum: u32,
+
+    /// What the CPU is wired to instead of the console, see `with_bus`.
+    #[cfg(feature = "mos6502")]
+    #[serde(skip)]
+    pub bus: Option<Box<dyn Bus>>,
+
+    /// ADC and SBC work in binary coded decimal when the decimal flag is set, like on a 6502. The
+    /// NES's CPU ignores the flag so it's off for it.
+    #[cfg(feature = "mos6502")]
+    #[serde(skip)]
+    pub decimal_mode: bool,
 
     pub cycles: u64,
 }
 
 impl Cpu {
+    /// Internal RAM, mirrored up to $1FFF.
+    const RAM_SIZE: usize = 0x0800;
+
+    /// The cartridge's RAM, battery backed on cartridges that keep saves in it.
+    const PRG_RAM_START: u16 = 0x6000;
+    const PRG_RAM_END: u16 = 0x7FFF;
+
     const FIRST_16_KB_OF_ROM: usize = 0x8000;
+
+    /// The mapper's registers are written through the ROM.
+    const ROM_START: u16 = 0x8000;
     const LAST_16_KB_OF_ROM: usize = 0xC000;
 
-    /// Create a new CPU from a NesFile.
+    /// PPU registers are mirrored throughout this range.
+    const PPU_REGISTERS_START: u16 = 0x2000;
+    const PPU_REGISTERS_END: u16 = 0x3FFF;
+
+    /// Writing a page number here copies that page into the PPU's OAM.
+    const OAM_DMA: u16 = 0x4014;
+
+    /// Number of cycles the CPU is suspended for during OAM DMA, one more on odd cycles.
+    const OAM_DMA_CYCLES: u64 = 513;
+
+    /// Writes strobe both controllers, reads shift out the first controller's buttons.
+    const CONTROLLER_1: u16 = 0x4016;
+
+    /// Reads shift out the second controller's buttons, writes go to the APU frame counter.
+    const CONTROLLER_2: u16 = 0x4017;
+
+    /// Number of cycles the CPU is stalled for while the DMC fetches a sample byte.
+    const DMC_DMA_CYCLES: u64 = 4;
+
+    /// Address of the NMI vector.
+    const NMI_VECTOR: usize = 0xFFFA;
+
+    /// Address of the reset vector.
+    const RESET_VECTOR: usize = 0xFFFC;
+
+    /// Address of the IRQ vector.
+    pub(crate) const IRQ_VECTOR: usize = 0xFFFE;
+
+    /// Number of cycles to enter an interrupt handler.
+    const INTERRUPT_CYCLES: u64 = 7;
+
+    /// Cycles of BRK or an IRQ before the vector is fetched, a NMI by then hijacks them.
+    const HIJACK_CYCLES: u64 = 4;
+
+    /// CLI, SEI and PLP, their change to the interrupt disable flag is seen an instruction late.
+    const DELAYED_INTERRUPT_DISABLE: [u8; 3] = [0x58, 0x78, 0x28];
+
+    /// Create a new CPU from a NesFile, powered on and starting at the reset vector.
     ///
     /// TODO: This is a little leaky, the CPU shouldn't know about the NES File Format but instead a
     /// third-party service should know about both the NES File Format and the CPU to initialize the
     /// state of the CPU and let it run.
     pub fn new(nes_file: crate::ines::NesFile) -> Self {
+        let rom_checksum = nes_file.checksum();
+        let mut cpu = Cpu::power_up(nes_file.chr_rom, nes_file.mirroring);
+        cpu.rom_checksum = rom_checksum;
+        cpu.set_region(nes_file.region);
+        cpu.set_vs_system(nes_file.vs_ppu.map(VsSystem::new));
+
+        // NROM-128 mirrors its 16 KiB into both halves, NROM-256 fills them with 32 KiB.
+        if nes_file.prg_rom.len() == Cpu::LAST_16_KB_OF_ROM - Cpu::FIRST_16_KB_OF_ROM {
+            cpu.memory[Cpu::FIRST_16_KB_OF_ROM..Cpu::LAST_16_KB_OF_ROM]
+                .copy_from_slice(&nes_file.prg_rom);
+            cpu.memory[Cpu::LAST_16_KB_OF_ROM..].copy_from_slice(&nes_file.prg_rom);
+        } else {
+            cpu.memory[Cpu::FIRST_16_KB_OF_ROM..].copy_from_slice(&nes_file.prg_rom);
+        }
+
+        cpu.power_on();
+
+        cpu
+    }
+
+    /// Like `new` but starting at $C000 instead of the reset vector, where nestest runs its tests
+    /// without a screen, as its log expects.
+    pub fn nestest(nes_file: crate::ines::NesFile) -> Self {
+        let mut cpu = Cpu::new(nes_file);
+        cpu.program_counter = 0xc000;
+        cpu
+    }
+
+    /// Load a flat 6502 binary at `address` instead of a cartridge, e.g. Klaus Dormann's
+    /// functional tests or a small hand-written program. Bytes past $FFFF are left out.
+    ///
+    /// All 64 KiB are RAM, the console's registers aren't mapped and its interrupts never fire.
+    /// The program starts at the reset vector, after `vectors` are written.
+    pub fn load_raw_program(program: &[u8], address: u16, vectors: Vectors) -> Self {
+        let mut cpu = Cpu::power_up(Vec::new(), crate::ines::Mirroring::Horizontal);
+        cpu.flat_memory = true;
+
+        let start = address as usize;
+        let len = program.len().min(MEMORY_SIZE_MAX - start);
+        cpu.memory[start..start + len].copy_from_slice(&program[..len]);
+
+        for (vector, addr) in [
+            (Cpu::NMI_VECTOR, vectors.nmi),
+            (Cpu::RESET_VECTOR, vectors.reset),
+            (Cpu::IRQ_VECTOR, vectors.irq),
+        ] {
+            if let Some(addr) = addr {
+                cpu.memory[vector..vector + 2].copy_from_slice(&addr.to_le_bytes());
+            }
+        }
+
+        cpu.program_counter = cpu.read_vector(Cpu::RESET_VECTOR);
+        cpu.tick(Cpu::INTERRUPT_CYCLES);
+
+        cpu
+    }
+
+    /// A 6502 wired to `bus` rather than a console, starting at the reset vector it reads.
+    ///
+    /// The PPU and APU are still clocked but unreachable, their interrupts never fire. Only the
+    /// bus's IRQ line interrupts.
+    #[cfg(feature = "mos6502")]
+    pub fn with_bus(bus: Box<dyn Bus>, decimal_mode: bool) -> Self {
+        let mut cpu = Cpu::power_up(Vec::new(), crate::ines::Mirroring::Horizontal);
+        cpu.flat_memory = true;
+        cpu.bus = Some(bus);
+        cpu.decimal_mode = decimal_mode;
+
+        cpu.program_counter = cpu.read_vector(Cpu::RESET_VECTOR);
+        cpu.tick(Cpu::INTERRUPT_CYCLES);
+
+        cpu
+    }
+
+    fn empty_memory() -> Box<AddressSpace> {
+        Box::new([0; MEMORY_SIZE_MAX])
+    }
+
+    /// The start of memory save states store: all of it for raw programs, what's below the ROM
+    /// for cartridges.
+    #[cfg(feature = "std")]
+    pub(crate) fn saved_memory(&self) -> &[u8] {
+        if self.flat_memory {
+            &self.memory[..]
+        } else {
+            &self.memory[..Cpu::FIRST_16_KB_OF_ROM]
+        }
+    }
+
+    /// The console as it powers up, before the program is loaded.
+    fn power_up(chr_rom: Vec<u8>, mirroring: crate::ines::Mirroring) -> Self {
         // Power up state derived from http://wiki.nesdev.com/w/index.php/CPU_power_up_state.
-        let mut cpu = Cpu {
-            // Hard coded to start at ROM.
-            program_counter: 0xc000,
+        Cpu {
+            // Set from the reset vector once the program is loaded.
+            program_counter: 0,
             stack: Stack::new(),
             status: ProcessorStatus::new(),
             a: 0,
             x: 0,
             y: 0,
-            memory: [0; MEMORY_SIZE_MAX],
-            cycles: 7,
-        };
+            memory: Cpu::empty_memory(),
+            mapper: Mapper::default(),
+            ppu: Ppu::new(chr_rom, mirroring),
+            apu: Apu::new(),
+            clock: Clock::new(Timing::NTSC),
+            scheduling: Scheduling::default(),
+            overclock_scanlines: 0,
+            controllers: Default::default(),
+            vs_system: None,
+            zapper: None,
+            cheats: Cheats::default(),
+            pokes: Vec::new(),
+            #[cfg(feature = "std")]
+            breakpoints: Breakpoints::default(),
+            #[cfg(feature = "std")]
+            symbols: Symbols::default(),
+            #[cfg(feature = "std")]
+            tracer: Tracer::default(),
+            #[cfg(feature = "std")]
+            profiler: Profiler::default(),
+            #[cfg(feature = "std")]
+            events: EventLog::default(),
+            #[cfg(feature = "std")]
+            call_stack: CallStack::default(),
+            #[cfg(feature = "std")]
+            watches: Watches::default(),
+            #[cfg(feature = "scripting")]
+            script: None,
+            observers: Observers::default(),
+            #[cfg(feature = "std")]
+            stopped_at: None,
+            unknown_opcode: UnknownOpcodePolicy::default(),
+            unofficial_opcode: UnofficialOpcodePolicy::default(),
+            ram_pattern: RamPattern::default(),
+            detect_traps: false,
+            cycle_limit: None,
+            jammed: false,
+            dma_cycles: 0,
+            flat_memory: false,
+            rom_checksum: 0,
+            #[cfg(feature = "mos6502")]
+            bus: None,
+            #[cfg(feature = "mos6502")]
+            decimal_mode: false,
+            cycles: 0,
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.ppu.region()
+    }
 
-        cpu.memory[Cpu::FIRST_16_KB_OF_ROM..Cpu::LAST_16_KB_OF_ROM]
-            .copy_from_slice(&nes_file.prg_rom);
-        cpu.memory[Cpu::LAST_16_KB_OF_ROM..].copy_from_slice(&nes_file.prg_rom);
+    /// Switch the clocks, the PPU's frame and the APU's tables to another region.
+    pub fn set_region(&mut self, region: Region) {
+        self.clock = Clock::new(region.timing());
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
 
-        cpu
+    pub fn ram_pattern(&self) -> RamPattern {
+        self.ram_pattern
+    }
+
+    /// Fill RAM with the pattern now and at every power on, RAM holding a raw program is left
+    /// alone.
+    pub fn set_ram_pattern(&mut self, pattern: RamPattern) {
+        self.ram_pattern = pattern;
+        if !self.flat_memory {
+            pattern.fill(&mut self.memory[..Cpu::RAM_SIZE]);
+        }
+    }
+
+    /// Run on a VS System cabinet, or on the NES with `None`.
+    pub fn set_vs_system(&mut self, vs_system: Option<VsSystem>) {
+        self.ppu
+            .set_vs_ppu(vs_system.as_ref().map(|vs_system| vs_system.ppu));
+        self.vs_system = vs_system;
+    }
+
+    /// Turn the console off and on: the registers, RAM and the other components are back to how
+    /// they power on, and the program starts again at the reset vector. The cartridge,
+    /// callbacks and settings are kept.
+    pub fn power_on(&mut self) {
+        self.stack = Stack::new();
+        self.status = ProcessorStatus::new();
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        // A raw program is in RAM, it would be lost.
+        if !self.flat_memory {
+            self.ram_pattern.fill(&mut self.memory[..Cpu::RAM_SIZE]);
+        }
+        for component in self.components() {
+            component.power_on();
+        }
+        self.mapper.power_on();
+        self.clock = Clock::new(self.clock.timing());
+        self.jammed = false;
+        self.dma_cycles = 0;
+        self.cycles = 0;
+        #[cfg(feature = "std")]
+        {
+            self.call_stack = CallStack::default();
+            self.stopped_at = None;
+        }
+
+        self.program_counter = self.read_vector(Cpu::RESET_VECTOR);
+        self.tick(Cpu::INTERRUPT_CYCLES);
+        self.sync_ppu();
     }
 
-    /// Start running!
-    pub fn run(&mut self) {
+    /// Press the reset button: the program restarts at the reset vector, RAM is kept.
+    /// See http://wiki.nesdev.com/w/index.php/CPU_power_up_state.
+    pub fn reset(&mut self) {
+        self.program_counter = self.read_vector(Cpu::RESET_VECTOR);
+
+        // The stack pointer is decremented as if pushing, without writing.
+        self.stack.stack_pointer = self.stack.stack_pointer.wrapping_sub(3);
+        self.status.interrupt_disable = true;
+        for component in self.components() {
+            component.reset();
+        }
+        self.jammed = false;
+
+        self.tick(Cpu::INTERRUPT_CYCLES);
+        self.sync_ppu();
+    }
+
+    /// The components clocked along with the CPU.
+    pub fn components(&mut self) -> [&mut dyn Component; 2] {
+        [&mut self.ppu, &mut self.apu]
+    }
+
+    /// Start running! Only stops at a breakpoint, an unknown opcode, a trap or the cycle limit.
+    pub fn run(&mut self) -> Stop {
         loop {
-            let operation = opcode::next(self);
-            info!(
-                "{:X}  {}  \tA:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP: {:02X} CYC: {}",
-                self.program_counter,
-                &operation.dump(self),
-                self.a,
-                self.x,
-                self.y,
-                u8::from(self.status.clone()),
-                self.stack.as_stack_offset(),
-                self.cycles
-            );
-
-            operation.execute(self);
+            if self.at_breakpoint() {
+                return Stop::Breakpoint;
+            }
+            if self.detect_traps && opcode::is_trap(self) {
+                return Stop::Trapped;
+            }
+            if self.at_cycle_limit() {
+                return Stop::CycleLimit;
+            }
+            if let Err(err) = self.step_instruction() {
+                return Stop::UnknownOpcode(err);
+            }
+        }
+    }
+
+    /// Run until the PPU completes a frame, or a breakpoint, an unknown opcode, a trap or the cycle
+    /// limit is reached.
+    pub fn run_frame(&mut self) -> Stop {
+        let frame = self.ppu.frame_count();
+        let _span = tracing::debug_span!("frame", number = frame).entered();
+        while self.ppu.frame_count() == frame {
+            if self.at_breakpoint() {
+                return Stop::Breakpoint;
+            }
+            if self.detect_traps && opcode::is_trap(self) {
+                return Stop::Trapped;
+            }
+            if self.at_cycle_limit() {
+                return Stop::CycleLimit;
+            }
+            if let Err(err) = self.step_instruction() {
+                return Stop::UnknownOpcode(err);
+            }
+        }
+
+        let pokes = core::mem::take(&mut self.pokes);
+        for poke in &pokes {
+            self.write(poke.address, poke.value);
+        }
+        self.pokes = pokes;
+
+        Stop::FrameComplete
+    }
+
+    /// Whether running should stop before the next instruction.
+    ///
+    /// After stopping, the instruction at the breakpoint runs when running again.
+    #[cfg(feature = "std")]
+    fn at_breakpoint(&mut self) -> bool {
+        let unofficial = self.unofficial_opcode == UnofficialOpcodePolicy::Break;
+        if !self.breakpoints.is_active() && !unofficial {
+            return false;
+        }
+
+        let pc = self.program_counter;
+        if self.stopped_at.take() == Some(pc) {
+            return false;
+        }
+        let stop = self.breakpoints.take_entered().is_some()
+            || self.breakpoints.contains(pc)
+            || self.breakpoints.returned(pc, self.call_stack.depth())
+            || (unofficial && !opcode::table::info(self.peek(pc)).official);
+        if !stop {
+            return false;
+        }
+
+        self.breakpoints.cancel_return();
+        self.stopped_at = Some(pc);
+        true
+    }
+
+    /// Breakpoints are part of the debugger, which needs `std`.
+    #[cfg(not(feature = "std"))]
+    fn at_breakpoint(&mut self) -> bool {
+        false
+    }
+
+    fn at_cycle_limit(&self) -> bool {
+        matches!(self.cycle_limit, Some(limit) if self.cycles >= limit)
+    }
+
+    /// Whether the CPU halted on an unknown opcode, see `UnknownOpcodePolicy::Halt`.
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    /// Fetch, trace, profile and execute the next instruction, then report it to the script.
+    ///
+    /// Unknown opcodes are handled as `unknown_opcode` says, only failing returns an error.
+    pub fn step_instruction(&mut self) -> Result<(), DecodeError> {
+        if self.jammed {
+            self.tick(1);
+            self.sync_ppu();
+            return Ok(());
+        }
+
+        #[cfg(feature = "std")]
+        if self.unofficial_opcode == UnofficialOpcodePolicy::Log {
+            let pc = self.program_counter;
+            let info = opcode::table::info(self.peek(pc));
+            if !info.official {
+                warn!("Unofficial opcode {} at ${:04X}", info, pc);
+            }
+        }
+
+        let operation = match opcode::next(self) {
+            Ok(operation) => operation,
+            Err(err) => match self.unknown_opcode {
+                UnknownOpcodePolicy::Fail => return Err(err),
+                UnknownOpcodePolicy::Skip => {
+                    #[cfg(feature = "std")]
+                    warn!("{}, skipped.", err);
+                    opcode::skip(&err)
+                }
+                UnknownOpcodePolicy::Halt => {
+                    #[cfg(feature = "std")]
+                    warn!("{}, halted until reset.", err);
+                    self.jammed = true;
+                    self.tick(1);
+                    self.sync_ppu();
+                    return Ok(());
+                }
+            },
+        };
+
+        if self.observers.watches_instructions() {
+            let instruction = Instruction {
+                address: self.program_counter,
+                opcode: self.peek(self.program_counter),
+                a: self.a,
+                x: self.x,
+                y: self.y,
+                status: u8::from(self.status.clone()),
+                stack_pointer: self.stack.as_stack_offset(),
+                cycles: self.cycles,
+            };
+            self.observers.instruction(&instruction);
+        }
+
+        #[cfg(feature = "std")]
+        self.profile_and_trace(&*operation);
+        // The same lines as the trace log, for subscribers, e.g. RUST_LOG=nes::cpu=trace.
+        trace!("{}", self.trace(&*operation));
+
+        let frame = self.ppu.frame_count();
+        self.step(operation);
+        if self.observers.watches_frames() && self.ppu.frame_count() != frame {
+            self.observers.frame(self.ppu.frame());
+        }
+
+        #[cfg(feature = "scripting")]
+        self.script_accesses();
+
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    fn profile_and_trace(&mut self, operation: &dyn Operation) {
+        if self.profiler.is_enabled() {
+            let pc = self.program_counter;
+            self.profiler.record(pc, self.peek(pc));
+        }
+
+        if self.tracer.is_enabled() {
+            let line = self.tracer.format().line(self, operation);
+            if let Err(err) = self.tracer.write(&line) {
+                error!("Failed to write the trace, stopped tracing: {}", err);
+                let _ = self.tracer.set_enabled(false);
+            }
+        }
+    }
+
+    /// Describe the operation about to be executed and the state of the CPU.
+    pub fn trace(&self, operation: &dyn Operation) -> String {
+        format!(
+            "{:X}  {}  \tA:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP: {:02X} PPU:{:3},{:3} CYC: {}",
+            self.program_counter,
+            &operation.dump(self),
+            self.a,
+            self.x,
+            self.y,
+            u8::from(self.status.clone()),
+            self.stack.as_stack_offset(),
+            self.ppu.scanline(),
+            self.ppu.dot(),
+            self.cycles
+        )
+    }
+
+    /// Execute a single operation and keep the rest of the system in sync with it.
+    pub fn step(&mut self, operation: Box<dyn Operation>) {
+        let cycles_before = self.cycles;
+        #[cfg(feature = "std")]
+        let pc = self.program_counter;
+        let opcode = self.peek(self.program_counter);
+        let interrupt_disable = self.status.interrupt_disable;
+        operation.execute(self);
+
+        // The operation has already accounted for its cycles, catch the PPU up to where the
+        // interrupts are polled, then to the end of the instruction.
+        let elapsed = self.cycles - cycles_before;
+        self.cycles = cycles_before;
+        let poll_cycle = Cpu::interrupt_poll_cycle(opcode, elapsed);
+        self.tick(poll_cycle);
+        self.sync_ppu();
+
+        let mut nmi = self.poll_nmi();
+        if nmi && opcode == Brk::OPCODE {
+            // The NMI hijacks BRK, whose pushes are done: only the vector changes. The NMI itself
+            // is serviced.
+            self.program_counter = self.read_vector(Cpu::NMI_VECTOR);
+            nmi = false;
+        }
+
+        // CLI, SEI and PLP change the flag on their last cycle, after the poll.
+        let interrupt_disable = if Cpu::DELAYED_INTERRUPT_DISABLE.contains(&opcode) {
+            interrupt_disable
+        } else {
+            self.status.interrupt_disable
+        };
+        let irq = self.irq_line() && !interrupt_disable;
+
+        #[cfg(feature = "std")]
+        {
+            self.call_stack
+                .after_instruction(opcode, pc, self.program_counter);
+            if opcode == Brk::OPCODE {
+                self.breakpoints.enter(InterruptSource::Brk(pc));
+            }
+        }
+
+        // An OAM DMA suspends the CPU once the instruction is done.
+        let dma_cycles = core::mem::take(&mut self.dma_cycles);
+        self.tick(elapsed - poll_cycle + dma_cycles);
+        self.sync_ppu();
+
+        // Interrupts arriving after the poll wait for the end of the next instruction.
+        if nmi {
+            self.nmi();
+        } else if irq {
+            self.interrupt(Cpu::IRQ_VECTOR);
+        }
+    }
+
+    /// The cycle of an instruction interrupts are polled at the end of, see
+    /// https://www.nesdev.org/wiki/CPU_interrupts.
+    ///
+    /// Usually the second to last, so an interrupt asserted on the last cycle waits for the next
+    /// instruction. Taken branches that stay on the page only poll before fetching their operand,
+    /// BRK only checks for a NMI hijacking it.
+    fn interrupt_poll_cycle(opcode: u8, cycles: u64) -> u64 {
+        let is_branch = opcode & 0x1F == 0x10;
+        match opcode {
+            Brk::OPCODE => Cpu::HIJACK_CYCLES,
+            _ if is_branch && cycles == 3 => 1,
+            _ => cycles.saturating_sub(1),
+        }
+    }
+
+    /// Advance the clock by the given number of CPU cycles.
+    fn tick(&mut self, cycles: u64) {
+        let mut remaining = cycles;
+        while remaining > 0 {
+            if !self.clock.overclocked_cycle() {
+                let dots = self.clock.cpu_cycle(self.scheduling);
+                self.run_ppu(dots);
+                self.apu.tick();
+            }
+
+            self.cycles += 1;
+            remaining -= 1;
+
+            // The DMC fetches its samples through the CPU bus, stalling the CPU.
+            if let Some(addr) = self.apu.dmc_dma_address() {
+                let value = self.read(addr);
+                self.apu.dmc_dma_complete(value);
+                remaining += Cpu::DMC_DMA_CYCLES;
+            }
+        }
+    }
+
+    /// Run the dots the PPU is owed when catching up, see `Scheduling::CatchUp`.
+    fn sync_ppu(&mut self) {
+        let dots = self.clock.catch_up();
+        self.run_ppu(dots);
+    }
+
+    /// Run the PPU, adding the overclock's scanlines when vertical blank starts.
+    fn run_ppu(&mut self, dots: u64) {
+        let frame = self.ppu.frame_count();
+        for _ in 0..dots {
+            self.ppu.tick();
+        }
+
+        if self.ppu.frame_count() != frame && self.overclock_scanlines > 0 {
+            self.clock.overclock(self.overclock_scanlines);
+        }
+    }
+
+    /// Enter the non-maskable interrupt handler.
+    fn nmi(&mut self) {
+        self.interrupt(Cpu::NMI_VECTOR);
+    }
+
+    /// Whether the PPU is requesting a NMI, acknowledging it.
+    fn poll_nmi(&mut self) -> bool {
+        self.ppu.poll_nmi() && !self.flat_memory
+    }
+
+    /// Whether the IRQ line is asserted, whatever the interrupt disable flag.
+    ///
+    /// The line is level triggered, it stays asserted until the source is acknowledged.
+    fn irq_line(&self) -> bool {
+        #[cfg(feature = "mos6502")]
+        let bus_irq = self.bus.as_ref().is_some_and(|bus| bus.irq());
+        #[cfg(not(feature = "mos6502"))]
+        let bus_irq = false;
+
+        (self.apu.irq() && !self.flat_memory) || bus_irq
+    }
+
+    /// What requested the interrupt through the vector, the first IRQ source if there are several.
+    #[cfg(feature = "std")]
+    fn interrupt_source(&self, vector: usize) -> InterruptSource {
+        if vector == Cpu::NMI_VECTOR {
+            InterruptSource::Vblank
+        } else if self.apu.frame_irq() {
+            InterruptSource::FrameCounter
+        } else if self.apu.dmc_irq() {
+            InterruptSource::Dmc
+        } else {
+            InterruptSource::Bus
+        }
+    }
+
+    /// Push the return address and status then jump through the vector.
+    ///
+    /// A NMI arriving during the first cycles of an IRQ hijacks it, the NMI handler is entered.
+    fn interrupt(&mut self, vector: usize) {
+        self.push_addr(self.program_counter);
+
+        // The B flag is only set when pushed by BRK or PHP.
+        let mut status = self.status.clone();
+        status.b_flag = false;
+        self.push(u8::from(status));
+
+        self.status.interrupt_disable = true;
+        self.tick(Cpu::HIJACK_CYCLES);
+        self.sync_ppu();
+        let vector = if vector == Cpu::IRQ_VECTOR && self.poll_nmi() {
+            Cpu::NMI_VECTOR
+        } else {
+            vector
+        };
+
+        #[cfg(feature = "std")]
+        let return_address = self.program_counter;
+        self.program_counter = self.read_vector(vector);
+
+        #[cfg(feature = "std")]
+        {
+            let entry = if vector == Cpu::NMI_VECTOR {
+                Entry::Nmi
+            } else {
+                Entry::Irq
+            };
+            self.call_stack
+                .interrupt(entry, self.program_counter, return_address);
+            self.breakpoints.enter(self.interrupt_source(vector));
+        }
+
+        self.tick(Cpu::INTERRUPT_CYCLES - Cpu::HIJACK_CYCLES);
+    }
+
+    /// The address in one of the vectors at the end of the address space.
+    pub(crate) fn read_vector(&mut self, vector: usize) -> u16 {
+        #[cfg(feature = "mos6502")]
+        if let Some(bus) = &mut self.bus {
+            let vector = vector as u16;
+            return bytes_to_addr(bus.read(vector), bus.read(vector + 1));
+        }
+
+        bytes_to_addr(self.memory[vector], self.memory[vector + 1])
+    }
+
+    /// Push a byte on the stack.
+    pub fn push(&mut self, value: u8) {
+        #[cfg(feature = "mos6502")]
+        if let Some(bus) = &mut self.bus {
+            bus.write(self.stack.address(), value);
+            self.stack.stack_pointer = self.stack.stack_pointer.wrapping_sub(1);
+            return;
+        }
+
+        self.stack.push(&mut self.memory, value);
+    }
+
+    /// Push an address on the stack, high byte first.
+    pub fn push_addr(&mut self, addr: u16) {
+        let (pcl, pch) = addr_to_bytes(addr);
+        self.push(pch);
+        self.push(pcl);
+    }
+
+    /// Pull a byte from the stack.
+    pub fn pop(&mut self) -> u8 {
+        #[cfg(feature = "mos6502")]
+        if let Some(bus) = &mut self.bus {
+            self.stack.stack_pointer = self.stack.stack_pointer.wrapping_add(1);
+            return bus.read(self.stack.address());
+        }
+
+        self.stack.pop(&mut self.memory)
+    }
+
+    /// Pull an address from the stack, as the low and high bytes.
+    pub fn pop_addr(&mut self) -> (u8, u8) {
+        let pcl = self.pop();
+        let pch = self.pop();
+        (pcl, pch)
+    }
+
+    /// Read a byte as the CPU would, with any side effects on the other components.
+    pub fn read(&mut self, addr: u16) -> u8 {
+        if let Cpu::PPU_REGISTERS_START..=Cpu::PPU_REGISTERS_END | Cpu::CONTROLLER_2 = addr {
+            self.sync_ppu();
+        }
+
+        let value = match addr {
+            #[cfg(feature = "mos6502")]
+            _ if self.bus.is_some() => self.bus.as_mut().map_or(0, |bus| bus.read(addr)),
+            _ if self.flat_memory => self.cheats.apply(addr, self.memory[addr as usize]),
+            Cpu::PPU_REGISTERS_START..=Cpu::PPU_REGISTERS_END => self.ppu.read_register(addr),
+            apu::STATUS => self.apu.read_register(addr),
+            Cpu::CONTROLLER_1 => {
+                let value = self.controllers[0].read();
+                self.vs_inputs(addr, value)
+            }
+            Cpu::CONTROLLER_2 => {
+                let value = match &self.zapper {
+                    Some(zapper) => zapper.read(&self.ppu),
+                    None => self.controllers[1].read(),
+                };
+                self.vs_inputs(addr, value)
+            }
+            Cpu::PRG_RAM_START..=Cpu::PRG_RAM_END if !self.mapper.prg_ram().readable() => {
+                Cpu::open_bus(addr)
+            }
+            _ => self.cheats.apply(addr, self.memory[addr as usize]),
+        };
+
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &mut self.script {
+            script.record_read(addr, value);
+        }
+
+        if self.observers.watches_memory() {
+            self.observers.memory_access(&MemoryAccess {
+                kind: AccessKind::Read,
+                address: addr,
+                value,
+            });
+        }
+
+        value
+    }
+
+    /// Read a byte without any side effects, used when logging.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            #[cfg(feature = "mos6502")]
+            _ if self.bus.is_some() => self.bus.as_ref().map_or(0, |bus| bus.peek(addr)),
+            _ if self.flat_memory => self.cheats.apply(addr, self.memory[addr as usize]),
+            Cpu::PPU_REGISTERS_START..=Cpu::PPU_REGISTERS_END => self.ppu.peek_register(addr),
+            apu::STATUS => self.apu.peek_register(addr),
+            Cpu::CONTROLLER_1 => self.vs_inputs(addr, self.controllers[0].peek()),
+            Cpu::CONTROLLER_2 => {
+                let value = match &self.zapper {
+                    Some(zapper) => zapper.read(&self.ppu),
+                    None => self.controllers[1].peek(),
+                };
+                self.vs_inputs(addr, value)
+            }
+            Cpu::PRG_RAM_START..=Cpu::PRG_RAM_END if !self.mapper.prg_ram().readable() => {
+                Cpu::open_bus(addr)
+            }
+            _ => self.cheats.apply(addr, self.memory[addr as usize]),
+        }
+    }
+
+    /// A VS System's cabinet puts its DIP switches, service button and coins on the bits of the
+    /// controller ports that are open bus on the NES.
+    fn vs_inputs(&self, addr: u16, value: u8) -> u8 {
+        match &self.vs_system {
+            Some(vs_system) if addr == Cpu::CONTROLLER_1 => {
+                value & 0x01 | vs_system.read_4016(self.cycles)
+            }
+            Some(vs_system) => value & 0x01 | vs_system.read_4017(),
+            None => value,
+        }
+    }
+
+    /// What's left on the data bus when nothing answers a read. The CPU doesn't keep track of it,
+    /// it's usually the address's high byte as most such reads are absolute.
+    fn open_bus(addr: u16) -> u8 {
+        (addr >> 8) as u8
+    }
+
+    /// The cartridge's RAM at $6000-$7FFF, whether or not the mapper lets the CPU reach it.
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.memory[Cpu::PRG_RAM_START as usize..=Cpu::PRG_RAM_END as usize]
+    }
+
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.memory[Cpu::PRG_RAM_START as usize..=Cpu::PRG_RAM_END as usize]
+    }
+
+    /// Read `len` bytes from `addr` without any side effects, wrapping around the address space.
+    ///
+    /// Registers read as the CPU would see them rather than what's behind them in `memory`.
+    pub fn peek_range(&self, addr: u16, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|offset| self.peek(addr.wrapping_add(offset as u16)))
+            .collect()
+    }
+
+    /// Write a byte as the CPU would.
+    pub fn write(&mut self, addr: u16, value: u8) {
+        #[cfg(feature = "scripting")]
+        if let Some(script) = &mut self.script {
+            script.record_write(addr, value);
+        }
+
+        if self.observers.watches_memory() {
+            self.observers.memory_access(&MemoryAccess {
+                kind: AccessKind::Write,
+                address: addr,
+                value,
+            });
+        }
+
+        if let Cpu::PPU_REGISTERS_START..=Cpu::PPU_REGISTERS_END | Cpu::OAM_DMA = addr {
+            self.sync_ppu();
+        }
+
+        #[cfg(feature = "std")]
+        if self.events.is_enabled() && debugger::is_logged(addr) {
+            // Caught up for the PPU's registers already, the APU's need it for the position.
+            self.sync_ppu();
+            let write = RegisterWrite {
+                scanline: self.ppu.scanline(),
+                dot: self.ppu.dot(),
+                address: addr,
+                value,
+            };
+            if let Err(err) = self.events.record(self.ppu.frame_count(), write) {
+                error!(
+                    "Failed to write the register writes, stopped recording: {}",
+                    err
+                );
+                let _ = self.events.set_enabled(false);
+            }
+        }
+
+        match addr {
+            #[cfg(feature = "mos6502")]
+            _ if self.bus.is_some() => {
+                if let Some(bus) = &mut self.bus {
+                    bus.write(addr, value);
+                }
+            }
+            _ if self.flat_memory => self.memory[addr as usize] = value,
+            Cpu::PPU_REGISTERS_START..=Cpu::PPU_REGISTERS_END => {
+                self.ppu.write_register(addr, value)
+            }
+            Cpu::OAM_DMA => {
+                // The page is read through the bus like any other read, registers and cheats
+                // included.
+                let start = u16::from(value) << 8;
+                let mut page = [0; 0x100];
+                for (offset, byte) in page.iter_mut().enumerate() {
+                    *byte = self.read(start + offset as u16);
+                }
+                self.ppu.oam_dma(&page);
+
+                // Clocked at the end of the instruction, see `step`.
+                self.dma_cycles += Cpu::OAM_DMA_CYCLES + self.cycles % 2;
+            }
+            Cpu::CONTROLLER_1 => {
+                for controller in self.controllers.iter_mut() {
+                    controller.write(value);
+                }
+            }
+            apu::REGISTERS_START..=apu::CHANNEL_REGISTERS_END
+            | apu::STATUS
+            | apu::FRAME_COUNTER => self.apu.write_register(addr, value),
+            Cpu::PRG_RAM_START..=Cpu::PRG_RAM_END if !self.mapper.prg_ram().writable() => {}
+            Cpu::ROM_START..=0xFFFF if self.mapper.has_registers() => {
+                self.mapper.write_register(addr, value)
+            }
+            _ => self.memory[addr as usize] = value,
         }
     }
 }
 
 /// A 8 bit register that has the processor state.
 /// TODO: Expand this.
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct ProcessorStatus {
     /// Carry (C) Flag
     pub carry: bool,
@@ -129,7 +1289,7 @@ impl ProcessorStatus {
     pub const ZERO_MASK: u8 = 0b0000_0010;
     pub const INTERRUPT_DISABLE_MASK: u8 = 0b0000_0100;
     pub const DECIMAL_MASK: u8 = 0b0000_1000;
-    pub const B_FLAG_MASK: u8 = 0b0010_0000;
+    pub const B_FLAG_MASK: u8 = 0b0001_0000;
     pub const OVERFLOW_MASK: u8 = 0b0100_0000;
     pub const NEGATIVE_MASK: u8 = 0b1000_0000;
 
@@ -180,121 +1340,492 @@ impl From<u8> for ProcessorStatus {
             interrupt_disable: src & ProcessorStatus::INTERRUPT_DISABLE_MASK
                 == ProcessorStatus::INTERRUPT_DISABLE_MASK,
             decimal: src & ProcessorStatus::DECIMAL_MASK == ProcessorStatus::DECIMAL_MASK,
-            b_flag: src & ProcessorStatus::B_FLAG_MASK == ProcessorStatus::B_FLAG_MASK,
+            // Only pushed copies of the status have the B flag, pulling it back ignores it.
+            b_flag: false,
             overflow: src & ProcessorStatus::OVERFLOW_MASK == ProcessorStatus::OVERFLOW_MASK,
             negative: src & ProcessorStatus::NEGATIVE_MASK == ProcessorStatus::NEGATIVE_MASK,
         }
     }
 }
 
-/// Stack starts at 0x1000.
+/// The stack, in page one ($0100-$01FF) growing down.
+#[derive(Deserialize, Serialize)]
 pub struct Stack {
-    // Address of the next free element in the stack (absolute address).
-    stack_pointer: usize,
+    /// Offset in page one of the next free byte, wraps around within the page.
+    stack_pointer: u8,
 }
 
 impl Stack {
+    const PAGE: u16 = 0x0100;
+
     fn new() -> Self {
         Stack {
-            stack_pointer: 0x1000 + 0xFD,
+            stack_pointer: 0xFD,
         }
     }
 
-    /// Returns the expected value in cpu register which is an offset to $1000.
-    fn as_stack_offset(&self) -> u8 {
-        (self.stack_pointer - 0x1000) as u8
+    /// Returns the expected value in cpu register which is an offset to $0100.
+    pub fn as_stack_offset(&self) -> u8 {
+        self.stack_pointer
     }
 
-    pub fn push_addr(&mut self, memory: &mut AddressSpace, addr: u16) {
-        let (pcl, pch) = addr_to_bytes(addr);
-
-        memory[self.stack_pointer] = pch;
-        memory[self.stack_pointer - 1] = pcl;
+    /// Set the register, e.g. for TXS.
+    pub fn set_stack_offset(&mut self, offset: u8) {
+        self.stack_pointer = offset;
+    }
 
-        self.stack_pointer -= 2;
+    /// Where the stack pointer points in memory.
+    fn address(&self) -> u16 {
+        Stack::PAGE | self.stack_pointer as u16
     }
 
     pub fn push(&mut self, memory: &mut AddressSpace, value: u8) {
-        memory[self.stack_pointer] = value;
-        self.stack_pointer -= 1;
+        memory[self.address() as usize] = value;
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
     pub fn pop(&mut self, memory: &mut AddressSpace) -> u8 {
-        let value = memory[self.stack_pointer + 1];
-
-        self.stack_pointer += 1;
-
-        value
-    }
-
-    pub fn pop_addr(&mut self, memory: &mut AddressSpace) -> (u8, u8) {
-        let pcl = memory[self.stack_pointer + 1];
-        let pch = memory[self.stack_pointer + 2];
-
-        self.stack_pointer += 2;
-
-        (pcl, pch)
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        memory[self.address() as usize]
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ines;
+    use crate::ppu::DOTS_PER_SCANLINE;
+    use crate::vs_system::VsPpu;
+    use crate::{golden, ines};
     use anyhow::Result;
-    use std::fs::File;
-    use std::io::prelude::*;
-    use std::io::BufReader;
 
+    const NESTEST: &str = "test/nestest.nes";
     const LOG_FILENAME: &str = "test/nestest.log";
-    const WORKING_UP_TO_LINE: u32 = 73;
 
     #[test]
-    fn test_until_fail() -> Result<()> {
+    fn test_frame_irq() -> Result<()> {
         let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
         let mut cpu = Cpu::new(nes_file);
-        let f = File::open(LOG_FILENAME)?;
-        let f = BufReader::new(f);
 
-        let mut counter = 1u32;
+        // Spin on JMP $0200 with interrupts enabled, the handler is at $0300.
+        cpu.memory[0x0200..0x0203].copy_from_slice(&[0x4C, 0x00, 0x02]);
+        cpu.memory[Cpu::IRQ_VECTOR] = 0x00;
+        cpu.memory[Cpu::IRQ_VECTOR + 1] = 0x03;
+        cpu.program_counter = 0x0200;
+        cpu.status.interrupt_disable = false;
+        cpu.write(apu::FRAME_COUNTER, 0x00);
 
-        for line in f.lines() {
-            let line = line.unwrap();
-            let operation = opcode::next(&cpu);
+        let start = cpu.cycles;
+        while cpu.program_counter == 0x0200 {
+            let operation = opcode::next(&cpu).unwrap();
+            cpu.step(operation);
+        }
 
-            let operation_output = format!("{:04X}  {}", cpu.program_counter, operation.dump(&cpu));
-            if !line.contains(&operation_output) {
-                println!("Expected output: {}", line);
-                println!("Received output: {}", operation_output);
-                panic!("Mismatch in operation state at {}.", counter);
+        // The flag is raised at the end of the 4-step sequence.
+        assert_eq!(cpu.program_counter, 0x0300);
+        assert!(cpu.status.interrupt_disable);
+        assert!((29830..29830 + 20).contains(&(cpu.cycles - start)));
+
+        // Acknowledging through $4015 releases the line.
+        assert_eq!(cpu.read(apu::STATUS) & 0x40, 0x40);
+        assert!(!cpu.apu.irq());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vs_system() -> Result<()> {
+        let mut cpu = Cpu::new(ines::NesFile::new(NESTEST.to_string())?);
+        let mut vs_system = VsSystem::new(VsPpu::Rc2c05(1));
+        vs_system.dip_switches = 0xFF;
+        cpu.set_vs_system(Some(vs_system));
+        assert_eq!(cpu.ppu.vs_ppu(), Some(VsPpu::Rc2c05(1)));
+
+        // The DIP switches and a coin take the place of the open bus.
+        cpu.vs_system.as_mut().unwrap().insert_coin(0, cpu.cycles);
+        assert_eq!(cpu.read(Cpu::CONTROLLER_1) & 0xFE, 0b0011_1000);
+        assert_eq!(cpu.peek(Cpu::CONTROLLER_2) & 0xFE, 0b1111_1100);
+
+        cpu.set_vs_system(None);
+        assert_eq!(cpu.ppu.vs_ppu(), None);
+        assert_eq!(cpu.read(Cpu::CONTROLLER_1) & 0xFE, 0x40);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_raw_program() {
+        // LDA #$42, STA $2000, JMP $0205.
+        let program = [0xA9, 0x42, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x02];
+        let vectors = Vectors {
+            reset: Some(0x0200),
+            irq: Some(0x0300),
+            ..Vectors::default()
+        };
+        let mut cpu = Cpu::load_raw_program(&program, 0x0200, vectors);
+        assert_eq!(cpu.program_counter, 0x0200);
+        assert_eq!(
+            cpu.peek_range(0xFFFA, 6),
+            vec![0, 0, 0x00, 0x02, 0x00, 0x03]
+        );
+
+        // $2000 is RAM rather than the PPU's register.
+        cpu.detect_traps = true;
+        assert_eq!(cpu.run(), Stop::Trapped);
+        assert_eq!(cpu.program_counter, 0x0205);
+        assert_eq!(cpu.read(0x2000), 0x42);
+
+        // Past the end of the address space.
+        let cpu = Cpu::load_raw_program(&[0xEA; 4], 0xFFFE, Vectors::default());
+        assert_eq!(cpu.peek_range(0xFFFC, 4), vec![0, 0, 0xEA, 0xEA]);
+        assert_eq!(cpu.peek(0), 0);
+    }
+
+    #[cfg(feature = "mos6502")]
+    #[test]
+    fn test_bus() {
+        struct Ram {
+            memory: Vec<u8>,
+        }
+
+        impl Bus for Ram {
+            fn read(&mut self, addr: u16) -> u8 {
+                self.memory[addr as usize]
+            }
+
+            fn peek(&self, addr: u16) -> u8 {
+                self.memory[addr as usize]
             }
 
-            let cpu_state_output = format!(
-                "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
-                cpu.a,
-                cpu.x,
-                cpu.y,
-                u8::from(cpu.status.clone()),
-                cpu.stack.as_stack_offset(),
-            );
-
-            let cyc = format!("CYC:{}", cpu.cycles);
-
-            if !line.contains(&cpu_state_output) || !line.contains(&cyc) {
-                println!("Expected output: {}", line);
-                println!("Received output: {} __ {}", cpu_state_output, cyc);
-                panic!("Mismatch in cpu state at {}.", counter);
+            fn write(&mut self, addr: u16, value: u8) {
+                self.memory[addr as usize] = value;
             }
+        }
 
-            operation.execute(&mut cpu);
+        // LDA #$42, STA $2000, JSR $0300, JMP $0208, with RTS at $0300.
+        let mut ram = Ram {
+            memory: vec![0; MEMORY_SIZE_MAX],
+        };
+        ram.memory[0x0200..0x020B].copy_from_slice(&[
+            0xA9, 0x42, 0x8D, 0x00, 0x20, 0x20, 0x00, 0x03, 0x4C, 0x08, 0x02,
+        ]);
+        ram.memory[0x0300] = 0x60;
+        ram.memory[Cpu::RESET_VECTOR..Cpu::RESET_VECTOR + 2].copy_from_slice(&[0x00, 0x02]);
 
-            counter += 1;
+        let mut cpu = Cpu::with_bus(Box::new(ram), true);
+        assert_eq!(cpu.program_counter, 0x0200);
+        cpu.detect_traps = true;
+        assert_eq!(cpu.run(), Stop::Trapped);
 
-            // Don't test further than this line.
-            if counter == WORKING_UP_TO_LINE {
-                break;
-            }
+        // The return address was pushed in page one.
+        assert_eq!(cpu.peek(0x2000), 0x42);
+        assert_eq!(cpu.peek_range(0x01FC, 2), vec![0x07, 0x02]);
+        assert!(cpu.memory.iter().all(|&byte| byte == 0));
+    }
+
+    /// Execute the next instruction, return where the CPU went.
+    fn step(cpu: &mut Cpu) -> u16 {
+        let operation = opcode::next(cpu).unwrap();
+        cpu.step(operation);
+        cpu.program_counter
+    }
+
+    /// Step until the frame IRQ is asserted with interrupts disabled, spinning on JMP $0200.
+    fn assert_frame_irq(cpu: &mut Cpu) {
+        cpu.memory[0x0200..0x0203].copy_from_slice(&[0x4C, 0x00, 0x02]);
+        cpu.memory[Cpu::IRQ_VECTOR..Cpu::IRQ_VECTOR + 2].copy_from_slice(&[0x00, 0x03]);
+        cpu.program_counter = 0x0200;
+        cpu.status.interrupt_disable = true;
+        cpu.write(apu::FRAME_COUNTER, 0x00);
+        while !cpu.apu.irq() {
+            step(cpu);
+        }
+    }
+
+    #[test]
+    fn test_interrupt_disable_delay() -> Result<()> {
+        let mut cpu = Cpu::new(ines::NesFile::new(NESTEST.to_string())?);
+
+        // CLI, NOP: the IRQ is taken after the NOP.
+        assert_frame_irq(&mut cpu);
+        cpu.memory[0x0400..0x0402].copy_from_slice(&[0x58, 0xEA]);
+        cpu.program_counter = 0x0400;
+        assert_eq!(step(&mut cpu), 0x0401);
+        assert_eq!(step(&mut cpu), 0x0300);
+        assert_eq!(cpu.peek_range(cpu.stack.address() + 2, 2), vec![0x02, 0x04]);
+
+        // SEI with the line asserted: the IRQ is still taken after it.
+        cpu.memory[0x0400] = 0x78;
+        cpu.program_counter = 0x0400;
+        cpu.status.interrupt_disable = false;
+        assert_eq!(step(&mut cpu), 0x0300);
+        Ok(())
+    }
+
+    #[test]
+    fn test_brk() -> Result<()> {
+        let mut cpu = Cpu::new(ines::NesFile::new(NESTEST.to_string())?);
+        cpu.memory[Cpu::IRQ_VECTOR..Cpu::IRQ_VECTOR + 2].copy_from_slice(&[0x00, 0x03]);
+        cpu.memory[Cpu::NMI_VECTOR..Cpu::NMI_VECTOR + 2].copy_from_slice(&[0x00, 0x05]);
+        cpu.memory[0x0300] = 0x40;
+
+        // BRK skips a byte, RTI returns past it.
+        cpu.memory[0x0400] = 0x00;
+        cpu.program_counter = 0x0400;
+        cpu.status.interrupt_disable = false;
+        assert_eq!(step(&mut cpu), 0x0300);
+        let status = cpu.peek(cpu.stack.address() + 1);
+        // The B flag, bit 4.
+        assert_ne!(status & 0x10, 0);
+        assert!(cpu.status.interrupt_disable);
+        assert_eq!(step(&mut cpu), 0x0402);
+        assert!(!cpu.status.interrupt_disable);
+
+        // A NMI starting within BRK's first 4 cycles (12 dots) hijacks it.
+        cpu.write(0x2000, 0x80);
+        cpu.memory[0x0200..0x0203].copy_from_slice(&[0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+        let vblank = 241 * DOTS_PER_SCANLINE as u32 + 1;
+        let dots_to_vblank = |cpu: &Cpu| {
+            let now = cpu.ppu.scanline() as u32 * DOTS_PER_SCANLINE as u32 + cpu.ppu.dot() as u32;
+            vblank.wrapping_sub(now)
+        };
+        while dots_to_vblank(&cpu) > 12 {
+            step(&mut cpu);
+        }
+        cpu.program_counter = 0x0400;
+        assert_eq!(step(&mut cpu), 0x0500);
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupt_poll_cycle() {
+        // LDA #, a taken branch on the page, one crossing it, BRK.
+        assert_eq!(Cpu::interrupt_poll_cycle(0xA9, 2), 1);
+        assert_eq!(Cpu::interrupt_poll_cycle(0xD0, 3), 1);
+        assert_eq!(Cpu::interrupt_poll_cycle(0xD0, 4), 3);
+        assert_eq!(Cpu::interrupt_poll_cycle(0x00, 7), 4);
+    }
+
+    #[test]
+    fn test_unknown_opcode_policy() -> Result<()> {
+        let mut cpu = Cpu::new(ines::NesFile::new(NESTEST.to_string())?);
+
+        // NOP, then $02 which isn't implemented, then NOP.
+        cpu.memory[0x0200..0x0203].copy_from_slice(&[0xEA, 0x02, 0xEA]);
+        let unknown = DecodeError {
+            opcode: 0x02,
+            address: 0x0201,
+        };
+
+        cpu.program_counter = 0x0200;
+        assert_eq!(cpu.run(), Stop::UnknownOpcode(unknown));
+        assert_eq!(cpu.program_counter, 0x0201);
+        assert_eq!(cpu.step_instruction(), Err(unknown));
+
+        cpu.unknown_opcode = UnknownOpcodePolicy::Skip;
+        let cycles = cpu.cycles;
+        cpu.step_instruction()?;
+        assert_eq!((cpu.program_counter, cpu.cycles - cycles), (0x0202, 2));
+
+        // Jammed, time passes but the program doesn't run until reset.
+        cpu.unknown_opcode = UnknownOpcodePolicy::Halt;
+        cpu.program_counter = 0x0201;
+        let frame = cpu.ppu.frame_count();
+        assert_eq!(cpu.run_frame(), Stop::FrameComplete);
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.program_counter, 0x0201);
+        assert_eq!(cpu.ppu.frame_count(), frame + 1);
+
+        cpu.reset();
+        assert!(!cpu.is_jammed());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prg_ram_control() {
+        let mut cpu = Cpu::new(ines::test_util::spinning_nrom());
+        cpu.mapper = Mapper::mmc3();
+        cpu.write(0x6000, 0x42);
+
+        // The RAM protect register, through the ROM that's left alone.
+        let rom = cpu.peek(0xA001);
+        cpu.write(0xA001, 0xC0);
+        assert_eq!(cpu.peek(0xA001), rom);
+
+        // Protected from writes.
+        cpu.write(0x6000, 0x24);
+        assert_eq!(cpu.read(0x6000), 0x42);
+
+        // Disabled, reads are open bus and writes are dropped. The RAM is kept.
+        cpu.write(0xA001, 0x00);
+        cpu.write(0x7FFF, 0x24);
+        assert_eq!((cpu.read(0x6000), cpu.peek(0x7FFF)), (0x60, 0x7F));
+        assert_eq!(cpu.prg_ram()[0], 0x42);
+        assert_eq!(cpu.prg_ram()[0x1FFF], 0);
+
+        // Enabled again at power on.
+        cpu.power_on();
+        assert_eq!(cpu.read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        // LDA #$02, STA $4014, NOP.
+        let mut cpu = Cpu::new(ines::test_util::nrom(&[0xA9, 0x02, 0x8D, 0x14, 0x40, 0xEA]));
+        for (offset, byte) in cpu.memory[0x0200..0x0300].iter_mut().enumerate() {
+            *byte = offset as u8;
+        }
+        // Read through the bus, so cheats apply.
+        cpu.cheats.add("0205:55".parse().unwrap());
+
+        let dots = |cpu: &Cpu| {
+            cpu.ppu.frame_count() * 262 * DOTS_PER_SCANLINE as u64
+                + cpu.ppu.scanline() as u64 * DOTS_PER_SCANLINE as u64
+                + cpu.ppu.dot() as u64
+        };
+
+        step(&mut cpu);
+        let (cycles, start) = (cpu.cycles, dots(&cpu));
+        step(&mut cpu);
+        let oam = |cpu: &mut Cpu, addr: u8| {
+            cpu.write(0x2003, addr);
+            cpu.peek(0x2004)
+        };
+        assert_eq!((oam(&mut cpu, 0x04), oam(&mut cpu, 0x05)), (0x04, 0x55));
+        cpu.write(0x2003, 0x00);
+
+        // The CPU is suspended after the STA's 4 cycles, the PPU runs all along.
+        let elapsed = cpu.cycles - cycles;
+        assert!(elapsed == 4 + 513 || elapsed == 4 + 514, "{}", elapsed);
+        assert_eq!(dots(&cpu) - start, elapsed * 3);
+
+        // A write from outside an instruction is clocked with the next one.
+        cpu.write(Cpu::OAM_DMA, 0x02);
+        let (cycles, start) = (cpu.cycles, dots(&cpu));
+        step(&mut cpu);
+        let elapsed = cpu.cycles - cycles;
+        assert!(elapsed == 2 + 513 || elapsed == 2 + 514, "{}", elapsed);
+        assert_eq!(dots(&cpu) - start, elapsed * 3);
+    }
+
+    #[test]
+    fn test_ram_pattern() -> Result<()> {
+        let mut cpu = Cpu::new(ines::NesFile::new(NESTEST.to_string())?);
+        assert_eq!(cpu.memory[..Cpu::RAM_SIZE], [0; Cpu::RAM_SIZE]);
+
+        cpu.set_ram_pattern("alternating".parse().unwrap());
+        assert_eq!(cpu.memory[..9], [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0]);
+
+        // The same bytes for a seed at every power on, other bytes for another seed.
+        cpu.set_ram_pattern("random:7".parse().unwrap());
+        let random = cpu.memory[..Cpu::RAM_SIZE].to_vec();
+        assert!(random.iter().any(|&byte| byte != random[0]));
+        cpu.memory[0x0300] = !random[0x0300];
+        cpu.power_on();
+        assert_eq!(cpu.memory[..Cpu::RAM_SIZE], random[..]);
+        cpu.set_ram_pattern(RamPattern::Random(8));
+        assert_ne!(cpu.memory[..Cpu::RAM_SIZE], random[..]);
+
+        assert_eq!("ones".parse(), Ok(RamPattern::Ones));
+        assert_eq!("random".parse(), Ok(RamPattern::Random(0)));
+        for name in &["0xff", "random:", "random:x", "zero:1"] {
+            assert!(name.parse::<RamPattern>().is_err(), "{}", name);
         }
         Ok(())
     }
+
+    #[test]
+    fn test_detect_traps() -> Result<()> {
+        let mut cpu = Cpu::new(ines::NesFile::new(NESTEST.to_string())?);
+        cpu.detect_traps = true;
+
+        // BEQ * falls through while Z is clear, BNE * doesn't.
+        cpu.memory[0x0200..0x0204].copy_from_slice(&[0xF0, 0xFE, 0xD0, 0xFE]);
+        cpu.program_counter = 0x0200;
+        cpu.status.zero = false;
+        assert_eq!(cpu.run(), Stop::Trapped);
+        assert_eq!(cpu.program_counter, 0x0202);
+
+        cpu.memory[0x0300..0x0303].copy_from_slice(&[0x4C, 0x00, 0x03]);
+        cpu.program_counter = 0x0300;
+        assert_eq!(cpu.run_frame(), Stop::Trapped);
+        assert_eq!(cpu.run_frame(), Stop::Trapped);
+
+        cpu.detect_traps = false;
+        assert_eq!(cpu.run_frame(), Stop::FrameComplete);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cycle_limit() -> Result<()> {
+        let mut cpu = Cpu::new(ines::NesFile::new(NESTEST.to_string())?);
+        let limit = cpu.cycles + 100;
+        cpu.cycle_limit = Some(limit);
+        assert_eq!(cpu.run(), Stop::CycleLimit);
+        assert!((limit..limit + 7).contains(&cpu.cycles));
+        assert_eq!(cpu.run_frame(), Stop::CycleLimit);
+
+        // Raising the limit carries on from there.
+        cpu.cycle_limit = Some(limit + 50);
+        assert_eq!(cpu.run_frame(), Stop::CycleLimit);
+        assert!(cpu.cycles >= limit + 50);
+        Ok(())
+    }
+
+    #[test]
+    fn test_catch_up_scheduling() -> Result<()> {
+        let mut interleaved = Cpu::new(ines::NesFile::new(NESTEST.to_string())?);
+        let mut catch_up = Cpu::new(ines::NesFile::new(NESTEST.to_string())?);
+        catch_up.scheduling = Scheduling::CatchUp;
+
+        // The PPU is in the same place whenever the CPU could look at it.
+        let state = |cpu: &Cpu| {
+            (
+                cpu.program_counter,
+                cpu.cycles,
+                cpu.ppu.scanline(),
+                cpu.ppu.dot(),
+            )
+        };
+        let lines = std::fs::read_to_string(LOG_FILENAME)?.lines().count();
+        for _ in 0..lines {
+            interleaved.step_instruction()?;
+            catch_up.step_instruction()?;
+            assert_eq!(state(&catch_up), state(&interleaved));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_overclock() {
+        let mut cpu = Cpu::new(ines::NesFile::new(NESTEST.to_string()).unwrap());
+        cpu.memory[0x0200..0x0203].copy_from_slice(&[0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+
+        let frame_cycles = |cpu: &mut Cpu| {
+            let start = cpu.cycles;
+            cpu.run_frame();
+            cpu.cycles - start
+        };
+        frame_cycles(&mut cpu);
+        let normal = frame_cycles(&mut cpu);
+
+        // 10 scanlines of 113 2/3 cycles from the end of this frame on. Frames end with the JMP
+        // that crosses into vertical blank, so they're a few cycles off.
+        cpu.overclock_scanlines = 10;
+        frame_cycles(&mut cpu);
+        let overclocked = frame_cycles(&mut cpu);
+        assert!((1130..=1143).contains(&(overclocked - normal)));
+        assert_eq!(cpu.ppu.scanline(), 241);
+    }
+
+    /// The CPU matches the whole log, official and unofficial opcodes.
+    #[test]
+    fn test_nestest() -> Result<()> {
+        // Automated mode starts at $C000 instead of the reset vector.
+        let cpu = golden::compare(NESTEST, Some(0xC000), LOG_FILENAME, None)?;
+
+        // The official and unofficial opcode tests leave their error codes here.
+        assert_eq!((cpu.memory[0x02], cpu.memory[0x03]), (0, 0));
+        Ok(())
+    }
 }
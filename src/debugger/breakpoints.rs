@@ -0,0 +1,189 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// Addresses the CPU stops at before executing the instruction there, and interrupts it stops at
+/// before the first instruction of their handler.
+#[derive(Clone, Debug, Default)]
+pub struct Breakpoints {
+    addresses: BTreeSet<u16>,
+
+    /// Stop once the CPU returns from a call, for stepping over or out of subroutines.
+    returning: Option<Return>,
+
+    interrupts: BTreeSet<Interrupt>,
+
+    /// A caught interrupt was entered, the CPU stops before the next instruction.
+    entered: Option<InterruptSource>,
+
+    /// The interrupt the CPU last stopped at, None when it stopped for anything else.
+    caught: Option<InterruptSource>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+    Brk,
+}
+
+impl FromStr for Interrupt {
+    type Err = String;
+
+    fn from_str(interrupt: &str) -> Result<Self, Self::Err> {
+        match interrupt.to_ascii_lowercase().as_str() {
+            "nmi" => Ok(Interrupt::Nmi),
+            "irq" => Ok(Interrupt::Irq),
+            "brk" => Ok(Interrupt::Brk),
+            _ => Err(format!(
+                "Expected \"nmi\", \"irq\" or \"brk\", got \"{}\".",
+                interrupt
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Interrupt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Interrupt::Nmi => "NMI",
+            Interrupt::Irq => "IRQ",
+            Interrupt::Brk => "BRK",
+        })
+    }
+}
+
+/// What made the CPU enter an interrupt handler.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InterruptSource {
+    /// The PPU's NMI at the start of vertical blank.
+    Vblank,
+
+    /// The APU's frame counter, in its 4 step mode.
+    FrameCounter,
+
+    /// The DMC finished a sample without looping.
+    Dmc,
+
+    /// A device on the external bus, see `Cpu::bus`.
+    Bus,
+
+    /// The BRK instruction at the address.
+    Brk(u16),
+}
+
+impl InterruptSource {
+    pub fn interrupt(self) -> Interrupt {
+        match self {
+            InterruptSource::Vblank => Interrupt::Nmi,
+            InterruptSource::FrameCounter | InterruptSource::Dmc | InterruptSource::Bus => {
+                Interrupt::Irq
+            }
+            InterruptSource::Brk(_) => Interrupt::Brk,
+        }
+    }
+}
+
+impl fmt::Display for InterruptSource {
+    /// e.g. "NMI from the PPU's vertical blank" or "BRK at $C123".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterruptSource::Vblank => write!(f, "NMI from the PPU's vertical blank"),
+            InterruptSource::FrameCounter => write!(f, "IRQ from the APU's frame counter"),
+            InterruptSource::Dmc => write!(f, "IRQ from the DMC"),
+            InterruptSource::Bus => write!(f, "IRQ from the bus"),
+            InterruptSource::Brk(address) => write!(f, "BRK at ${:04X}", address),
+        }
+    }
+}
+
+/// Where a call returns to and how deep the call stack is once it has.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Return {
+    address: u16,
+    depth: usize,
+}
+
+impl Breakpoints {
+    /// Returns false if there already was a breakpoint at the address.
+    pub fn add(&mut self, addr: u16) -> bool {
+        self.addresses.insert(addr)
+    }
+
+    /// Returns false if there was no breakpoint at the address.
+    pub fn remove(&mut self, addr: u16) -> bool {
+        self.addresses.remove(&addr)
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        self.addresses.contains(&addr)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Addresses in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.addresses.iter().copied()
+    }
+
+    /// Stop once the CPU is at `address` with at most `depth` frames on the call stack, i.e. the
+    /// call returned rather than a recursive call reaching the same address.
+    pub fn return_to(&mut self, address: u16, depth: usize) {
+        self.returning = Some(Return { address, depth });
+    }
+
+    /// Whether the CPU at `pc` with `depth` frames on the call stack returned, see `return_to()`.
+    pub fn returned(&self, pc: u16, depth: usize) -> bool {
+        matches!(self.returning, Some(target) if target.address == pc && depth <= target.depth)
+    }
+
+    /// Forget the call being returned from, e.g. when stopping at a breakpoint inside it.
+    pub fn cancel_return(&mut self) {
+        self.returning = None;
+    }
+
+    /// Stop, or not, as the interrupt's handler is entered.
+    pub fn catch(&mut self, interrupt: Interrupt, enabled: bool) {
+        if enabled {
+            self.interrupts.insert(interrupt);
+        } else {
+            self.interrupts.remove(&interrupt);
+        }
+    }
+
+    pub fn catches(&self, interrupt: Interrupt) -> bool {
+        self.interrupts.contains(&interrupt)
+    }
+
+    /// The interrupts caught, NMI first.
+    pub fn interrupts(&self) -> impl Iterator<Item = Interrupt> + '_ {
+        self.interrupts.iter().copied()
+    }
+
+    /// The CPU entered an interrupt's handler, it stops before the next instruction if the
+    /// interrupt is caught.
+    pub fn enter(&mut self, source: InterruptSource) {
+        if self.catches(source.interrupt()) {
+            self.entered = Some(source);
+        }
+    }
+
+    /// The caught interrupt entered since the last call, if any. It's `caught()` until the next
+    /// call.
+    pub fn take_entered(&mut self) -> Option<InterruptSource> {
+        self.caught = self.entered.take();
+        self.caught
+    }
+
+    /// The interrupt `take_entered()` last returned.
+    pub fn caught(&self) -> Option<InterruptSource> {
+        self.caught
+    }
+
+    /// Whether there's anything to check before each instruction.
+    pub fn is_active(&self) -> bool {
+        !self.addresses.is_empty() || self.returning.is_some() || self.entered.is_some()
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+/// Shadow call stack, follows JSR/RTS and interrupts to show how the CPU got where it is.
+///
+/// The real stack only holds return addresses mixed in with pushed data, so the calls are tracked
+/// as they happen instead. Games sometimes return without a matching call, e.g. jump tables that
+/// push an address and RTS to it, or drop frames by pulling the return address, and loading a
+/// state keeps the calls from before it. Those returns are logged and the stack resynchronizes
+/// with the return address when it can.
+use tracing::warn;
+
+/// Deepest call chain tracked, the real stack can't hold more return addresses than this.
+const MAX_DEPTH: usize = 128;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+const BRK: u8 = 0x00;
+
+/// How a frame was entered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Entry {
+    Jsr,
+    Nmi,
+    Irq,
+    Brk,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub entry: Entry,
+
+    /// Address of the routine or handler.
+    pub target: u16,
+
+    /// Where execution continues once the frame returns.
+    pub return_address: u16,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CallStack {
+    /// Outermost frame first.
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    /// Follow an executed instruction: `pc` is where it was and `next_pc` where the CPU went.
+    pub fn after_instruction(&mut self, opcode: u8, pc: u16, next_pc: u16) {
+        match (opcode, CallStack::return_address(opcode, pc)) {
+            (JSR, Some(return_address)) => self.push(Frame {
+                entry: Entry::Jsr,
+                target: next_pc,
+                return_address,
+            }),
+            (BRK, Some(return_address)) => self.push(Frame {
+                entry: Entry::Brk,
+                target: next_pc,
+                return_address,
+            }),
+            (RTS, _) | (RTI, _) => self.pop(pc, next_pc),
+            _ => (),
+        }
+    }
+
+    /// Where the instruction at `pc` returns to if it's a call, JSR or BRK.
+    pub fn return_address(opcode: u8, pc: u16) -> Option<u16> {
+        match opcode {
+            JSR => Some(pc.wrapping_add(3)),
+            BRK => Some(pc.wrapping_add(2)),
+            _ => None,
+        }
+    }
+
+    /// The CPU entered an interrupt handler, it will return to `return_address`.
+    pub fn interrupt(&mut self, entry: Entry, handler: u16, return_address: u16) {
+        self.push(Frame {
+            entry,
+            target: handler,
+            return_address,
+        });
+    }
+
+    /// Number of frames, 0 outside of any call.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Innermost frame first.
+    pub fn frames(&self) -> impl Iterator<Item = &Frame> {
+        self.frames.iter().rev()
+    }
+
+    fn push(&mut self, frame: Frame) {
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    /// Return from the frame the CPU returned to, dropping any frames it skipped.
+    fn pop(&mut self, pc: u16, return_address: u16) {
+        let depth = self
+            .frames
+            .iter()
+            .rposition(|frame| frame.return_address == return_address);
+
+        match depth {
+            Some(depth) => {
+                let skipped = self.frames.len() - 1 - depth;
+                if skipped > 0 {
+                    warn!(
+                        "Return at ${:04X} to ${:04X} skipped {} frame(s)",
+                        pc, return_address, skipped
+                    );
+                }
+                self.frames.truncate(depth);
+            }
+            None => warn!(
+                "Return at ${:04X} to ${:04X} doesn't match any call",
+                pc, return_address
+            ),
+        }
+    }
+}
+
+impl fmt::Display for CallStack {
+    /// A line per frame, innermost first, e.g. "#0  in $C5F5, returns to $C003".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, frame) in self.frames().enumerate() {
+            let location = match frame.entry {
+                Entry::Jsr => format!("${:04X}", frame.target),
+                Entry::Nmi => format!("NMI handler ${:04X}", frame.target),
+                Entry::Irq => format!("IRQ handler ${:04X}", frame.target),
+                Entry::Brk => format!("BRK handler ${:04X}", frame.target),
+            };
+            writeln!(
+                f,
+                "#{}  in {}, returns to ${:04X}",
+                i, location, frame.return_address
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_stack() {
+        let mut stack = CallStack::default();
+        stack.after_instruction(JSR, 0xC000, 0xC100);
+        stack.after_instruction(JSR, 0xC105, 0xC200);
+        stack.interrupt(Entry::Nmi, 0xC300, 0xC210);
+        assert_eq!(
+            stack.to_string(),
+            "#0  in NMI handler $C300, returns to $C210\n\
+             #1  in $C200, returns to $C108\n\
+             #2  in $C100, returns to $C003\n"
+        );
+
+        stack.after_instruction(RTI, 0xC310, 0xC210);
+        stack.after_instruction(RTS, 0xC220, 0xC108);
+        assert_eq!(stack.frames().count(), 1);
+
+        // A jump table returning to a pushed address.
+        stack.after_instruction(RTS, 0xC120, 0xD000);
+        assert_eq!(stack.frames().count(), 1);
+
+        // The routine at $C100 pulled a return address and returned straight to the top level.
+        stack.after_instruction(JSR, 0xD000, 0xC200);
+        stack.after_instruction(RTS, 0xC201, 0xC003);
+        assert_eq!(stack.frames().count(), 0);
+    }
+}
@@ -0,0 +1,256 @@
+/// Register write event log, every write to the PPU's and APU's registers with the scanline and
+/// dot it happened on.
+///
+/// Raster effects depend on when in the frame the PPU's registers are written, e.g. a scroll split
+/// after sprite 0 hit, and sound drivers on the order of their writes. The writes of the last
+/// complete frame and of the frame so far are kept for the debugger, and every frame can be
+/// written to a file as it completes. Frames start at vertical blank like the PPU's frame count,
+/// so the NMI handler's writes come first.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+/// nesdev's names of $2000 to $2007 and $4000 to $4017, None for the unused ones.
+const PPU_REGISTERS: [&str; 8] = [
+    "PPUCTRL",
+    "PPUMASK",
+    "PPUSTATUS",
+    "OAMADDR",
+    "OAMDATA",
+    "PPUSCROLL",
+    "PPUADDR",
+    "PPUDATA",
+];
+const APU_REGISTERS: [Option<&str>; 0x18] = [
+    Some("SQ1_VOL"),
+    Some("SQ1_SWEEP"),
+    Some("SQ1_LO"),
+    Some("SQ1_HI"),
+    Some("SQ2_VOL"),
+    Some("SQ2_SWEEP"),
+    Some("SQ2_LO"),
+    Some("SQ2_HI"),
+    Some("TRI_LINEAR"),
+    None,
+    Some("TRI_LO"),
+    Some("TRI_HI"),
+    Some("NOISE_VOL"),
+    None,
+    Some("NOISE_LO"),
+    Some("NOISE_HI"),
+    Some("DMC_FREQ"),
+    Some("DMC_RAW"),
+    Some("DMC_START"),
+    Some("DMC_LEN"),
+    Some("OAMDMA"),
+    Some("SND_CHN"),
+    Some("JOY1"),
+    Some("FRAME_CNT"),
+];
+
+/// Whether writes to `address` are logged: the PPU's registers with their mirrors up to $3FFF,
+/// then the APU's and I/O registers up to $4017.
+pub fn is_logged(address: u16) -> bool {
+    matches!(address, 0x2000..=0x4017)
+}
+
+/// The register's name, e.g. "PPUSCROLL".
+fn register_name(address: u16) -> Option<&'static str> {
+    match address {
+        0x2000..=0x3FFF => Some(PPU_REGISTERS[(address & 7) as usize]),
+        0x4000..=0x4017 => APU_REGISTERS[(address - 0x4000) as usize],
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterWrite {
+    pub scanline: u16,
+    pub dot: u16,
+    pub address: u16,
+    pub value: u8,
+}
+
+impl fmt::Display for RegisterWrite {
+    /// e.g. " 30 283  $2005 = 00  PPUSCROLL".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:3} {:3}  ${:04X} = {:02X}  {}",
+            self.scanline,
+            self.dot,
+            self.address,
+            self.value,
+            register_name(self.address).unwrap_or("")
+        )
+    }
+}
+
+/// The writes of one frame, in the order they happened.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameEvents {
+    /// The PPU's frame count, see `Ppu::frame_count()`.
+    pub frame: u64,
+    pub writes: Vec<RegisterWrite>,
+}
+
+impl fmt::Display for FrameEvents {
+    /// A heading with the frame then a line per write.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Frame {}, {} writes", self.frame, self.writes.len())?;
+        writeln!(f, " SL DOT")?;
+        self.writes
+            .iter()
+            .try_for_each(|write| writeln!(f, "{}", write))
+    }
+}
+
+/// Records the register writes while enabled, it can be switched on and off at any time.
+#[derive(Default)]
+pub struct EventLog {
+    enabled: bool,
+
+    /// The frame being run and the last complete one.
+    current: FrameEvents,
+    last: Option<FrameEvents>,
+
+    /// Where each frame is written as it completes.
+    out: Option<BufWriter<Box<dyn Write + Send>>>,
+}
+
+impl EventLog {
+    /// Write every frame to a new file, starting disabled.
+    pub fn create(path: &str) -> io::Result<Self> {
+        Ok(EventLog::new(Box::new(File::create(path)?)))
+    }
+
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        EventLog {
+            out: Some(BufWriter::new(out)),
+            ..EventLog::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Start or stop recording, switching off ends the frame so far.
+    pub fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+        if self.enabled && !enabled {
+            self.end_frame()?;
+        }
+        self.enabled = enabled;
+
+        Ok(())
+    }
+
+    /// Record a write made in `frame`, the frame before is complete when a new one starts.
+    pub fn record(&mut self, frame: u64, write: RegisterWrite) -> io::Result<()> {
+        let result = if frame != self.current.frame {
+            self.end_frame()
+        } else {
+            Ok(())
+        };
+
+        self.current.frame = frame;
+        self.current.writes.push(write);
+        result
+    }
+
+    /// Complete the frame so far, writing it out. Nothing happens without any writes.
+    pub fn end_frame(&mut self) -> io::Result<()> {
+        if self.current.writes.is_empty() {
+            return Ok(());
+        }
+
+        let frame = FrameEvents {
+            frame: self.current.frame,
+            writes: Vec::new(),
+        };
+        let frame = std::mem::replace(&mut self.current, frame);
+        let result = match &mut self.out {
+            Some(out) => write!(out, "{}", frame).and_then(|()| out.flush()),
+            None => Ok(()),
+        };
+        self.last = Some(frame);
+        result
+    }
+
+    /// The writes of the frame so far.
+    pub fn current(&self) -> &FrameEvents {
+        &self.current
+    }
+
+    /// The writes of the last complete frame, if any were recorded.
+    pub fn last(&self) -> Option<&FrameEvents> {
+        self.last.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::ines;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects everything written to it, shared with the test.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_event_log() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+        let output = Output::default();
+        cpu.events = EventLog::new(Box::new(output.clone()));
+
+        // LDA #$1E, STA $2001, STA $4015, STA $0300, then JMP $0200.
+        cpu.memory[0x0200..0x020C].copy_from_slice(&[
+            0xA9, 0x1E, 0x8D, 0x01, 0x20, 0x8D, 0x15, 0x40, 0x8D, 0x00, 0x03, 0x4C,
+        ]);
+        cpu.memory[0x020C..0x020E].copy_from_slice(&[0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+
+        // Not recorded while disabled.
+        cpu.run_frame();
+        cpu.events.set_enabled(true)?;
+        cpu.run_frame();
+        cpu.run_frame();
+
+        // The frame before the one being run, each STA in turn.
+        let last = cpu.events.last().unwrap();
+        assert_eq!(last.frame, cpu.ppu.frame_count() - 2);
+        let addresses: Vec<u16> = last.writes.iter().map(|write| write.address).collect();
+        assert!(addresses.contains(&0x2001) && addresses.contains(&0x4015));
+        assert!(addresses.windows(2).all(|pair| pair[0] != pair[1]));
+        assert_eq!(cpu.events.current().frame, last.frame + 1);
+
+        cpu.events.set_enabled(false)?;
+        let output = String::from_utf8(output.0.lock().unwrap().clone())?;
+        assert_eq!(output.matches("Frame ").count(), 2);
+        assert!(output.contains("$2001 = 1E  PPUMASK"));
+        assert!(output.contains("$4015 = 1E  SND_CHN"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_names() {
+        assert_eq!(register_name(0x2005), Some("PPUSCROLL"));
+        assert_eq!(register_name(0x3FFE), Some("PPUADDR"));
+        assert_eq!(register_name(0x4009), None);
+        assert_eq!(register_name(0x4017), Some("FRAME_CNT"));
+        assert!(is_logged(0x4014) && !is_logged(0x4018) && !is_logged(0x1FFF));
+    }
+}
@@ -0,0 +1,1237 @@
+/// Interactive debugger on the terminal, entered when the CPU stops at a breakpoint.
+///
+/// Commands are read from stdin while the emulation is stopped, an empty line repeats the last
+/// command.
+use crate::apu::Channel;
+use crate::cheats::Cheat;
+use crate::component::Component;
+use crate::cpu::{Cpu, Stop, UnofficialOpcodePolicy};
+use crate::disasm;
+use crate::opcode;
+use crate::ppu;
+#[cfg(feature = "scripting")]
+use crate::script;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use tracing::{error, info};
+
+mod breakpoints;
+mod call_stack;
+mod events;
+mod profiler;
+mod symbols;
+mod trace;
+pub mod views;
+mod watch;
+
+pub use breakpoints::{Breakpoints, Interrupt, InterruptSource};
+pub use call_stack::{CallStack, Entry};
+pub use events::{is_logged, EventLog, FrameEvents, RegisterWrite};
+pub use profiler::Profiler;
+pub use symbols::{fceux_symbol_files, Symbols};
+pub use trace::{TraceFormat, Tracer};
+pub use watch::{Watch, WatchValue, Watches};
+
+const HELP: &str = "Commands:
+  break ADDR   (b)  Stop before executing the instruction at ADDR
+  delete ADDR  (d)  Remove the breakpoint at ADDR
+  list         (l)  List the breakpoints
+  catch [nmi|irq|brk] [on|off]
+                    Stop as the interrupt's handler is entered and show what requested it, e.g.
+                    the PPU's vertical blank. Toggles by default, lists the interrupts caught
+                    without one
+  x ADDR [LEN] (hexdump)
+                    Show LEN bytes from ADDR as the CPU reads them
+  set ADDR VAL      Write the byte VAL to ADDR as the CPU would
+  disasm [ADDR] [COUNT]
+               (u)  Disassemble COUNT instructions from ADDR, the program counter by default
+  trace [on|off]
+               (t)  Switch the trace log on or off, toggles by default
+  events [on|off]   Show the PPU and APU register writes of the last frame and of this one so
+                    far, with their scanline and dot, or start or stop recording them
+  unofficial [run|log|break]
+                    Show or set what happens with unofficial opcodes: run them, log each one
+                    executed with its address or stop before executing them
+  profile [on|off|reset]
+               (p)  Show the hot addresses and opcodes, or start, stop or clear counting
+  nametables FILE [viewport]
+               (nt) Save the four nametables as a PNG, outlining the screen with viewport
+  patterns FILE [PALETTE]
+               (pt) Save both pattern tables as a PNG, drawn with palette 0 to 7
+  oam [all] [FILE]  List the visible sprites in OAM, or all 64, and save them as a transparent
+                    PNG
+  palettes     (pal) Show the colours of the eight palettes
+  apu               Show the state of the sound channels and the frame counter
+  mute CHANNEL [on|off]
+                    Leave pulse1, pulse2, triangle, noise or dmc out of the mix, toggles by
+                    default
+  solo CHANNEL|off  Only mix one channel, or go back to mixing the unmuted ones
+  cheat [add CODE|on N|off N|delete N]
+                    List the cheats, or add a Game Genie or raw (AAAA:VV) code, enable,
+                    disable or delete one
+  watch [add EXPR|delete N]
+               (w)  List the watches with their values, or add an expression logged after every
+                    frame, e.g. score:w or [#enemies + index] & #F0, or delete one
+  backtrace    (bt) Show the calls and interrupts that led to the current address
+  reset             Press the reset button, RAM is kept
+  power             Turn the console off and on again
+  step         (s)  Execute one instruction
+  next         (n)  Execute one instruction, running a called subroutine (JSR or BRK) until it
+                    returns
+  finish       (fin) Run until the current subroutine or interrupt handler returns
+  continue     (c)  Resume running
+  quit         (q)  Exit the emulator
+  help         (h)  Show this message
+Addresses and values are hexadecimal, e.g. C000, $C000 or 0xC000. Addresses can also be labels
+from the symbol files.";
+
+/// Bytes shown when no length is given.
+const HEXDUMP_LEN: usize = 64;
+
+/// Bytes shown on each line of a hex dump.
+const HEXDUMP_WIDTH: usize = 16;
+
+/// Instructions disassembled when no count is given.
+const DISASM_COUNT: usize = 10;
+
+/// What the emulation does after the debugger.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resume {
+    Continue,
+    Quit,
+
+    /// The program jumps to itself, see `Cpu::detect_traps`.
+    Trapped,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Break(u16),
+    Delete(u16),
+    List,
+    Catch {
+        interrupt: Option<Interrupt>,
+        enabled: Option<bool>,
+    },
+    Hexdump {
+        address: u16,
+        len: usize,
+    },
+    Set {
+        address: u16,
+        value: u8,
+    },
+    Disassemble {
+        address: Option<u16>,
+        count: usize,
+    },
+    Trace(Option<bool>),
+    Events(Option<bool>),
+    Unofficial(Option<UnofficialOpcodePolicy>),
+    Profile(ProfileAction),
+    Cheat(CheatAction),
+    Watch(WatchAction),
+    Backtrace,
+    Nametables {
+        path: String,
+        viewport: bool,
+    },
+    Patterns {
+        path: String,
+        palette: u8,
+    },
+    Oam {
+        all: bool,
+        path: Option<String>,
+    },
+    Palettes,
+    Apu,
+    Mute {
+        channel: Channel,
+        muted: Option<bool>,
+    },
+    Solo(Option<Channel>),
+    Reset,
+    Power,
+    Step,
+    Next,
+    Finish,
+    Continue,
+    Quit,
+    Help,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProfileAction {
+    Report,
+    Enable(bool),
+    Reset,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CheatAction {
+    List,
+    Add(Cheat),
+    Enable(usize, bool),
+    Delete(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum WatchAction {
+    List,
+    Add(Watch),
+    Delete(usize),
+}
+
+impl Command {
+    /// Parse a line, addresses can be given by the symbols' names.
+    pub fn parse(line: &str, symbols: &Symbols) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let mut address = || {
+            words
+                .next()
+                .ok_or_else(|| format!("\"{}\" needs an address.", name))
+                .and_then(|word| symbols.resolve(word))
+        };
+        let path = |word: Option<&str>| {
+            word.map(str::to_string)
+                .ok_or_else(|| format!("\"{}\" needs a file.", name))
+        };
+
+        match name {
+            "break" | "b" => Ok(Command::Break(address()?)),
+            "delete" | "d" => Ok(Command::Delete(address()?)),
+            "list" | "l" => Ok(Command::List),
+            "catch" => {
+                let interrupt = words.next().map(str::parse).transpose()?;
+                let enabled = match words.next() {
+                    Some("on") => Some(true),
+                    Some("off") => Some(false),
+                    Some(value) => {
+                        return Err(format!("Expected \"on\" or \"off\", got \"{}\".", value))
+                    }
+                    None => None,
+                };
+
+                Ok(Command::Catch { interrupt, enabled })
+            }
+            "x" | "hexdump" => {
+                let address = address()?;
+                let len = parse_count(words.next(), HEXDUMP_LEN)?;
+
+                Ok(Command::Hexdump { address, len })
+            }
+            "set" => {
+                let address = address()?;
+                let value = words
+                    .next()
+                    .ok_or_else(|| "\"set\" needs a value.".to_string())
+                    .and_then(parse_value)?;
+
+                Ok(Command::Set { address, value })
+            }
+            "disasm" | "u" => {
+                let address = words.next().map(|word| symbols.resolve(word)).transpose()?;
+                let count = parse_count(words.next(), DISASM_COUNT)?;
+
+                Ok(Command::Disassemble { address, count })
+            }
+            "trace" | "t" => match words.next() {
+                Some("on") => Ok(Command::Trace(Some(true))),
+                Some("off") => Ok(Command::Trace(Some(false))),
+                Some(value) => Err(format!("Expected \"on\" or \"off\", got \"{}\".", value)),
+                None => Ok(Command::Trace(None)),
+            },
+            "events" => match words.next() {
+                Some("on") => Ok(Command::Events(Some(true))),
+                Some("off") => Ok(Command::Events(Some(false))),
+                Some(value) => Err(format!("Expected \"on\" or \"off\", got \"{}\".", value)),
+                None => Ok(Command::Events(None)),
+            },
+            "unofficial" => match words.next() {
+                Some(policy) => match policy.parse() {
+                    Ok(policy) => Ok(Command::Unofficial(Some(policy))),
+                    Err(_) => Err(format!(
+                        "Expected \"run\", \"log\" or \"break\", got \"{}\".",
+                        policy
+                    )),
+                },
+                None => Ok(Command::Unofficial(None)),
+            },
+            "profile" | "p" => match words.next() {
+                None => Ok(Command::Profile(ProfileAction::Report)),
+                Some("on") => Ok(Command::Profile(ProfileAction::Enable(true))),
+                Some("off") => Ok(Command::Profile(ProfileAction::Enable(false))),
+                Some("reset") => Ok(Command::Profile(ProfileAction::Reset)),
+                Some(value) => Err(format!(
+                    "Expected \"on\", \"off\" or \"reset\", got \"{}\".",
+                    value
+                )),
+            },
+            "cheat" => {
+                let action = words.next();
+                let mut index = || {
+                    let index = words
+                        .next()
+                        .ok_or_else(|| "Which cheat? See \"cheat\".".to_string())?;
+                    index
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid cheat \"{}\".", index))
+                };
+
+                let action = match action {
+                    None => CheatAction::List,
+                    Some("add") => CheatAction::Add(
+                        words
+                            .next()
+                            .ok_or_else(|| "\"cheat add\" needs a code.".to_string())?
+                            .parse()?,
+                    ),
+                    Some("on") => CheatAction::Enable(index()?, true),
+                    Some("off") => CheatAction::Enable(index()?, false),
+                    Some("delete") => CheatAction::Delete(index()?),
+                    Some(value) => {
+                        return Err(format!(
+                            "Expected \"add\", \"on\", \"off\" or \"delete\", got \"{}\".",
+                            value
+                        ))
+                    }
+                };
+
+                Ok(Command::Cheat(action))
+            }
+            "watch" | "w" => {
+                let action = match words.next() {
+                    None => WatchAction::List,
+                    Some("add") => {
+                        let expression = words.collect::<Vec<_>>().join(" ");
+                        if expression.is_empty() {
+                            return Err("\"watch add\" needs an expression.".to_string());
+                        }
+                        WatchAction::Add(Watch::parse(&expression, symbols)?)
+                    }
+                    Some("delete") => {
+                        let index = words
+                            .next()
+                            .ok_or_else(|| "Which watch? See \"watch\".".to_string())?;
+                        WatchAction::Delete(
+                            index
+                                .parse()
+                                .map_err(|_| format!("Invalid watch \"{}\".", index))?,
+                        )
+                    }
+                    Some(value) => {
+                        return Err(format!(
+                            "Expected \"add\" or \"delete\", got \"{}\".",
+                            value
+                        ))
+                    }
+                };
+
+                Ok(Command::Watch(action))
+            }
+            "nametables" | "nt" => {
+                let path = path(words.next())?;
+                let viewport = match words.next() {
+                    None => false,
+                    Some("viewport") => true,
+                    Some(value) => {
+                        return Err(format!("Expected \"viewport\", got \"{}\".", value))
+                    }
+                };
+
+                Ok(Command::Nametables { path, viewport })
+            }
+            "patterns" | "pt" => {
+                let path = path(words.next())?;
+                let palette = match words.next() {
+                    Some(palette) => palette
+                        .parse()
+                        .ok()
+                        .filter(|&palette| palette < ppu::PALETTES)
+                        .ok_or_else(|| format!("Invalid palette \"{}\".", palette))?,
+                    None => 0,
+                };
+
+                Ok(Command::Patterns { path, palette })
+            }
+            "oam" => {
+                let mut all = false;
+                let mut path = None;
+                for word in words.by_ref() {
+                    match word {
+                        "all" => all = true,
+                        _ if path.is_none() => path = Some(word.to_string()),
+                        _ => return Err(format!("Unexpected \"{}\".", word)),
+                    }
+                }
+
+                Ok(Command::Oam { all, path })
+            }
+            "palettes" | "pal" => Ok(Command::Palettes),
+            "apu" => Ok(Command::Apu),
+            "mute" => {
+                let channel = words
+                    .next()
+                    .ok_or_else(|| "\"mute\" needs a channel.".to_string())?
+                    .parse()?;
+                let muted = match words.next() {
+                    Some("on") => Some(true),
+                    Some("off") => Some(false),
+                    Some(value) => {
+                        return Err(format!("Expected \"on\" or \"off\", got \"{}\".", value))
+                    }
+                    None => None,
+                };
+
+                Ok(Command::Mute { channel, muted })
+            }
+            "solo" => match words.next() {
+                Some("off") => Ok(Command::Solo(None)),
+                Some(channel) => Ok(Command::Solo(Some(channel.parse()?))),
+                None => Err("\"solo\" needs a channel or \"off\".".to_string()),
+            },
+            "backtrace" | "bt" => Ok(Command::Backtrace),
+            "reset" => Ok(Command::Reset),
+            "power" => Ok(Command::Power),
+            "step" | "s" => Ok(Command::Step),
+            "next" | "n" => Ok(Command::Next),
+            "finish" | "fin" => Ok(Command::Finish),
+            "continue" | "c" => Ok(Command::Continue),
+            "quit" | "q" => Ok(Command::Quit),
+            "help" | "h" => Ok(Command::Help),
+            _ => Err(format!("Unknown command \"{}\", try \"help\".", name)),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    /// Parse a line without any symbols.
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        Command::parse(line, &Symbols::default())
+    }
+}
+
+/// Parse a hexadecimal address, optionally prefixed by "$" or "0x".
+pub fn parse_address(value: &str) -> Result<u16, String> {
+    let digits = value
+        .strip_prefix('$')
+        .or_else(|| value.strip_prefix("0x"))
+        .unwrap_or(value);
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address \"{}\".", value))
+}
+
+/// Parse a hexadecimal byte, with the same prefixes as addresses.
+fn parse_value(value: &str) -> Result<u8, String> {
+    parse_address(value)
+        .ok()
+        .and_then(|value| u8::try_from(value).ok())
+        .ok_or_else(|| format!("Invalid value \"{}\".", value))
+}
+
+/// Parse an optional decimal count.
+fn parse_count(value: Option<&str>, default: usize) -> Result<usize, String> {
+    match value {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("Invalid count \"{}\".", value)),
+        None => Ok(default),
+    }
+}
+
+/// Wrap up when the emulator exits: write out the rest of the trace log and the register writes,
+/// and show the profile.
+pub fn finish(cpu: &mut Cpu) {
+    if let Err(err) = cpu.tracer.flush() {
+        error!("Failed to write the trace: {}", err);
+    }
+    if let Err(err) = cpu.events.end_frame() {
+        error!("Failed to write the register writes: {}", err);
+    }
+
+    if cpu.profiler.is_enabled() {
+        print!("{}", cpu.profiler.report(cpu));
+    }
+}
+
+/// Run a frame, prompting at each breakpoint reached. The script sees the frame start and end.
+pub fn run_frame(cpu: &mut Cpu) -> Resume {
+    #[cfg(feature = "scripting")]
+    cpu.script_event(script::Event::FrameStart);
+
+    loop {
+        match cpu.run_frame() {
+            Stop::FrameComplete => break,
+            Stop::Breakpoint => {
+                if prompt(cpu) == Resume::Quit {
+                    return Resume::Quit;
+                }
+            }
+            Stop::UnknownOpcode(err) => {
+                error!("{}, stopped running.", err);
+                return Resume::Quit;
+            }
+            Stop::Trapped => return Resume::Trapped,
+            Stop::CycleLimit => return Resume::Quit,
+        }
+    }
+
+    #[cfg(feature = "scripting")]
+    cpu.script_event(script::Event::FrameEnd);
+
+    if cpu.watches.log && !cpu.watches.is_empty() {
+        let values: Vec<String> = cpu
+            .watches
+            .values(cpu)
+            .map(|value| value.to_string())
+            .collect();
+        info!("Frame {}: {}", cpu.ppu.frame_count(), values.join(", "));
+    }
+
+    Resume::Continue
+}
+
+/// Read and execute commands from the terminal until the user continues or quits.
+pub fn prompt(cpu: &mut Cpu) -> Resume {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut lines = stdin.lock().lines();
+    let mut last_command: Option<Command> = None;
+
+    // Everything up to here is in the trace log.
+    let _ = cpu.tracer.flush();
+
+    println!("Stopped at {}", cpu.symbols.describe(cpu.program_counter));
+    if let Some(source) = cpu.breakpoints.caught() {
+        println!("Caught the {}", source);
+    }
+    print_location(cpu, &mut stdout);
+
+    loop {
+        print!("(nes) ");
+        let _ = stdout.flush();
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            // Nothing more to read, e.g. stdin was closed.
+            _ => return Resume::Quit,
+        };
+
+        let command = if line.trim().is_empty() {
+            match &last_command {
+                Some(command) => command.clone(),
+                None => continue,
+            }
+        } else {
+            match Command::parse(&line, &cpu.symbols) {
+                Ok(command) => command,
+                Err(err) => {
+                    println!("{}", err);
+                    continue;
+                }
+            }
+        };
+        last_command = Some(command.clone());
+
+        if let Some(resume) = execute(cpu, command, &mut stdout) {
+            return resume;
+        }
+    }
+}
+
+/// Execute a command, returns how to resume if the command leaves the debugger.
+pub fn execute(cpu: &mut Cpu, command: Command, out: &mut impl Write) -> Option<Resume> {
+    // Output is best effort, the terminal may be gone.
+    let _ = match command {
+        Command::Break(addr) => {
+            let location = cpu.symbols.describe(addr);
+            if cpu.breakpoints.add(addr) {
+                writeln!(out, "Breakpoint at {}", location)
+            } else {
+                writeln!(out, "Already a breakpoint at {}", location)
+            }
+        }
+        Command::Delete(addr) => {
+            let location = cpu.symbols.describe(addr);
+            if cpu.breakpoints.remove(addr) {
+                writeln!(out, "Deleted breakpoint at {}", location)
+            } else {
+                writeln!(out, "No breakpoint at {}", location)
+            }
+        }
+        Command::List => {
+            if cpu.breakpoints.is_empty() {
+                writeln!(out, "No breakpoints")
+            } else {
+                cpu.breakpoints
+                    .iter()
+                    .try_for_each(|addr| writeln!(out, "{}", cpu.symbols.describe(addr)))
+            }
+        }
+        Command::Catch {
+            interrupt: None, ..
+        } => {
+            let interrupts: Vec<String> = cpu
+                .breakpoints
+                .interrupts()
+                .map(|interrupt| interrupt.to_string())
+                .collect();
+            if interrupts.is_empty() {
+                writeln!(out, "Not catching any interrupts")
+            } else {
+                writeln!(out, "Catching {}", interrupts.join(", "))
+            }
+        }
+        Command::Catch {
+            interrupt: Some(interrupt),
+            enabled,
+        } => {
+            let enabled = enabled.unwrap_or(!cpu.breakpoints.catches(interrupt));
+            cpu.breakpoints.catch(interrupt, enabled);
+            writeln!(
+                out,
+                "Catching {} {}",
+                interrupt,
+                if enabled { "on" } else { "off" }
+            )
+        }
+        Command::Hexdump { address, len } => cpu
+            .peek_range(address, len)
+            .chunks(HEXDUMP_WIDTH)
+            .enumerate()
+            .try_for_each(|(row, bytes)| {
+                let address = address.wrapping_add((row * HEXDUMP_WIDTH) as u16);
+                writeln!(out, "{}", hexdump_line(address, bytes))
+            }),
+        Command::Set { address, value } => {
+            cpu.write(address, value);
+            writeln!(out, "${:04X} = ${:02X}", address, value)
+        }
+        Command::Disassemble { address, count } => {
+            let address = address.unwrap_or(cpu.program_counter);
+            let label = |addr| cpu.symbols.label(addr);
+            disasm::disassemble(|addr| cpu.peek(addr), address, count)
+                .iter()
+                .try_for_each(|line| {
+                    if let Some(label) = label(line.address) {
+                        writeln!(out, "{}:", label)?;
+                    }
+                    writeln!(out, "{}", line.listing(label))
+                })
+        }
+        Command::Trace(enabled) => {
+            let enabled = enabled.unwrap_or(!cpu.tracer.is_enabled());
+            match cpu.tracer.set_enabled(enabled) {
+                Ok(()) if enabled => writeln!(out, "Tracing on"),
+                Ok(()) => writeln!(out, "Tracing off"),
+                Err(err) => writeln!(out, "Failed to write the trace: {}", err),
+            }
+        }
+        Command::Events(None) => {
+            let events = &cpu.events;
+            if !events.is_enabled() && events.last().is_none() {
+                writeln!(out, "No register writes recorded, see \"events on\"")
+            } else {
+                events
+                    .last()
+                    .into_iter()
+                    .chain(Some(events.current()).filter(|frame| !frame.writes.is_empty()))
+                    .try_for_each(|frame| write!(out, "{}", frame))
+            }
+        }
+        Command::Events(Some(enabled)) => match cpu.events.set_enabled(enabled) {
+            Ok(()) => writeln!(
+                out,
+                "Recording register writes {}",
+                if enabled { "on" } else { "off" }
+            ),
+            Err(err) => writeln!(out, "Failed to write the events: {}", err),
+        },
+        Command::Unofficial(policy) => {
+            if let Some(policy) = policy {
+                cpu.unofficial_opcode = policy;
+            }
+            writeln!(
+                out,
+                "Unofficial opcodes {}",
+                match cpu.unofficial_opcode {
+                    UnofficialOpcodePolicy::Run => "run",
+                    UnofficialOpcodePolicy::Log => "are logged",
+                    UnofficialOpcodePolicy::Break => "stop the CPU",
+                }
+            )
+        }
+        Command::Profile(ProfileAction::Report) => write!(out, "{}", cpu.profiler.report(cpu)),
+        Command::Profile(ProfileAction::Enable(enabled)) => {
+            cpu.profiler.set_enabled(enabled);
+            writeln!(out, "Profiling {}", if enabled { "on" } else { "off" })
+        }
+        Command::Profile(ProfileAction::Reset) => {
+            cpu.profiler.reset();
+            writeln!(out, "Cleared the profile")
+        }
+        Command::Cheat(CheatAction::List) => {
+            if cpu.cheats.is_empty() {
+                writeln!(out, "No cheats")
+            } else {
+                cpu.cheats
+                    .iter()
+                    .enumerate()
+                    .try_for_each(|(i, cheat)| writeln!(out, "{:2}  {}", i, cheat))
+            }
+        }
+        Command::Cheat(CheatAction::Add(cheat)) => {
+            let line = cheat.to_string();
+            let index = cpu.cheats.add(cheat);
+            writeln!(out, "{:2}  {}", index, line)
+        }
+        Command::Cheat(CheatAction::Enable(index, enabled)) => {
+            if cpu.cheats.set_enabled(index, enabled) {
+                writeln!(
+                    out,
+                    "Cheat {} {}",
+                    index,
+                    if enabled { "on" } else { "off" }
+                )
+            } else {
+                writeln!(out, "No cheat {}", index)
+            }
+        }
+        Command::Cheat(CheatAction::Delete(index)) => match cpu.cheats.remove(index) {
+            Some(cheat) => writeln!(out, "Deleted cheat {}", cheat.code),
+            None => writeln!(out, "No cheat {}", index),
+        },
+        Command::Watch(WatchAction::List) => {
+            if cpu.watches.is_empty() {
+                writeln!(out, "No watches")
+            } else {
+                cpu.watches
+                    .values(cpu)
+                    .enumerate()
+                    .try_for_each(|(i, value)| writeln!(out, "{:2}  {}", i, value))
+            }
+        }
+        Command::Watch(WatchAction::Add(watch)) => {
+            let index = cpu.watches.add(watch);
+            let value = cpu.watches.values(cpu).nth(index);
+            writeln!(out, "{:2}  {}", index, value.unwrap())
+        }
+        Command::Watch(WatchAction::Delete(index)) => match cpu.watches.remove(index) {
+            Some(watch) => writeln!(out, "Deleted watch {}", watch.text),
+            None => writeln!(out, "No watch {}", index),
+        },
+        Command::Nametables { path, viewport } => {
+            match views::save_nametables(cpu, &path, viewport) {
+                Ok(()) => writeln!(out, "Saved the nametables to \"{}\"", path),
+                Err(err) => writeln!(out, "Failed to save \"{}\": {:#}", path, err),
+            }
+        }
+        Command::Patterns { path, palette } => {
+            match views::save_pattern_tables(cpu, &path, palette) {
+                Ok(()) => writeln!(out, "Saved the pattern tables to \"{}\"", path),
+                Err(err) => writeln!(out, "Failed to save \"{}\": {:#}", path, err),
+            }
+        }
+        Command::Oam { all, path } => views::print_oam(cpu, out, all).and_then(|()| match path {
+            Some(path) => match views::save_sprites(cpu, &path) {
+                Ok(()) => writeln!(out, "Saved the sprites to \"{}\"", path),
+                Err(err) => writeln!(out, "Failed to save \"{}\": {:#}", path, err),
+            },
+            None => Ok(()),
+        }),
+        Command::Palettes => views::print_palettes(cpu, out),
+        Command::Apu => writeln!(out, "{}", cpu.apu),
+        Command::Mute { channel, muted } => {
+            let muted = muted.unwrap_or(!cpu.apu.is_muted(channel));
+            cpu.apu.set_muted(channel, muted);
+            writeln!(
+                out,
+                "{} {}",
+                if muted { "Muted" } else { "Unmuted" },
+                channel
+            )
+        }
+        Command::Solo(solo) => {
+            cpu.apu.set_solo(solo);
+            match solo {
+                Some(channel) => writeln!(out, "Only mixing {}", channel),
+                None => writeln!(out, "Solo off"),
+            }
+        }
+        Command::Backtrace => {
+            if cpu.call_stack.frames().next().is_none() {
+                writeln!(out, "At ${:04X}, not in a call", cpu.program_counter)
+            } else {
+                writeln!(out, "At ${:04X}", cpu.program_counter)
+                    .and_then(|()| write!(out, "{}", cpu.call_stack))
+            }
+        }
+        Command::Reset => {
+            Component::reset(cpu);
+            print_location(cpu, out);
+            Ok(())
+        }
+        Command::Power => {
+            Component::power_on(cpu);
+            print_location(cpu, out);
+            Ok(())
+        }
+        Command::Step => match cpu.step_instruction() {
+            Ok(()) => {
+                print_caught(cpu, out);
+                print_location(cpu, out);
+                Ok(())
+            }
+            Err(err) => writeln!(out, "{}", err),
+        },
+        Command::Next => {
+            let pc = cpu.program_counter;
+            match CallStack::return_address(cpu.peek(pc), pc) {
+                Some(return_address) => {
+                    cpu.breakpoints
+                        .return_to(return_address, cpu.call_stack.depth());
+                    return Some(Resume::Continue);
+                }
+                None => return execute(cpu, Command::Step, out),
+            }
+        }
+        Command::Finish => match cpu.call_stack.frames().next() {
+            Some(frame) => {
+                let depth = cpu.call_stack.depth() - 1;
+                cpu.breakpoints.return_to(frame.return_address, depth);
+                return Some(Resume::Continue);
+            }
+            None => writeln!(out, "At ${:04X}, not in a call", cpu.program_counter),
+        },
+        Command::Continue => return Some(Resume::Continue),
+        Command::Quit => return Some(Resume::Quit),
+        Command::Help => writeln!(out, "{}", HELP),
+    };
+
+    None
+}
+
+/// A row of a hex dump, e.g. "C000  4C F5 C5 60  |L..`|".
+fn hexdump_line(address: u16, bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let text: String = bytes
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+
+    format!(
+        "{:04X}  {:<width$}  |{}|",
+        address,
+        hex.join(" "),
+        text,
+        width = HEXDUMP_WIDTH * 3 - 1
+    )
+}
+
+/// The next instruction, the registers and the zero page, e.g. for automation once the program is
+/// done.
+pub fn print_state(cpu: &Cpu, out: &mut impl Write) -> io::Result<()> {
+    print_location(cpu, out);
+    for (row, bytes) in cpu.peek_range(0, 0x100).chunks(HEXDUMP_WIDTH).enumerate() {
+        writeln!(out, "{}", hexdump_line((row * HEXDUMP_WIDTH) as u16, bytes))?;
+    }
+    Ok(())
+}
+
+/// Show the caught interrupt stepped into, if any.
+fn print_caught(cpu: &mut Cpu, out: &mut impl Write) {
+    if let Some(source) = cpu.breakpoints.take_entered() {
+        let _ = writeln!(out, "Caught the {}", source);
+    }
+}
+
+/// Show the next instruction and the registers.
+fn print_location(cpu: &Cpu, out: &mut impl Write) {
+    let _ = match opcode::next(cpu) {
+        Ok(operation) => writeln!(out, "{}", cpu.trace(&*operation)),
+        Err(err) => writeln!(out, "{}", err),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("b C000".parse(), Ok(Command::Break(0xC000)));
+        assert_eq!("break $c5f5".parse(), Ok(Command::Break(0xC5F5)));
+        assert_eq!("d 0x10".parse(), Ok(Command::Delete(0x10)));
+        assert_eq!("  list ".parse(), Ok(Command::List));
+        assert!("break".parse::<Command>().is_err());
+        assert!("break 10000".parse::<Command>().is_err());
+        assert!("jump".parse::<Command>().is_err());
+
+        assert_eq!(
+            "x 0".parse(),
+            Ok(Command::Hexdump {
+                address: 0,
+                len: HEXDUMP_LEN
+            })
+        );
+        assert_eq!(
+            "hexdump $2000 8".parse(),
+            Ok(Command::Hexdump {
+                address: 0x2000,
+                len: 8
+            })
+        );
+        assert_eq!(
+            "set 0300 $ff".parse(),
+            Ok(Command::Set {
+                address: 0x0300,
+                value: 0xFF
+            })
+        );
+        assert!("set 0300 100".parse::<Command>().is_err());
+        assert!("set 0300".parse::<Command>().is_err());
+
+        assert_eq!("t".parse(), Ok(Command::Trace(None)));
+        assert_eq!("trace off".parse(), Ok(Command::Trace(Some(false))));
+        assert!("trace maybe".parse::<Command>().is_err());
+        assert_eq!("events".parse(), Ok(Command::Events(None)));
+        assert_eq!("events on".parse(), Ok(Command::Events(Some(true))));
+        assert!("events all".parse::<Command>().is_err());
+        assert_eq!("bt".parse(), Ok(Command::Backtrace));
+        assert_eq!("power".parse(), Ok(Command::Power));
+        assert_eq!("n".parse(), Ok(Command::Next));
+        assert_eq!("finish".parse(), Ok(Command::Finish));
+        assert_eq!(
+            "catch IRQ off".parse(),
+            Ok(Command::Catch {
+                interrupt: Some(Interrupt::Irq),
+                enabled: Some(false)
+            })
+        );
+        assert!("catch reset".parse::<Command>().is_err());
+        assert_eq!(
+            "unofficial break".parse(),
+            Ok(Command::Unofficial(Some(UnofficialOpcodePolicy::Break)))
+        );
+        assert!("unofficial stop".parse::<Command>().is_err());
+        assert_eq!(
+            "mute dmc off".parse(),
+            Ok(Command::Mute {
+                channel: Channel::Dmc,
+                muted: Some(false)
+            })
+        );
+        assert_eq!(
+            "solo triangle".parse(),
+            Ok(Command::Solo(Some(Channel::Triangle)))
+        );
+        assert_eq!("solo off".parse(), Ok(Command::Solo(None)));
+        assert!("mute".parse::<Command>().is_err());
+        assert!("mute bass".parse::<Command>().is_err());
+        assert!("solo".parse::<Command>().is_err());
+        assert!("catch nmi maybe".parse::<Command>().is_err());
+        assert_eq!("p".parse(), Ok(Command::Profile(ProfileAction::Report)));
+        assert_eq!(
+            "profile reset".parse(),
+            Ok(Command::Profile(ProfileAction::Reset))
+        );
+
+        assert_eq!(
+            "pt tiles.png 5".parse(),
+            Ok(Command::Patterns {
+                path: "tiles.png".to_string(),
+                palette: 5
+            })
+        );
+        assert!("pt tiles.png 8".parse::<Command>().is_err());
+
+        let mut symbols = Symbols::default();
+        symbols.add(0xC5F5, "reset");
+        assert_eq!(
+            Command::parse("b reset", &symbols),
+            Ok(Command::Break(0xC5F5))
+        );
+        assert!("b reset".parse::<Command>().is_err());
+
+        assert_eq!("cheat".parse(), Ok(Command::Cheat(CheatAction::List)));
+        assert_eq!(
+            "cheat add sxiopo".parse(),
+            Ok(Command::Cheat(CheatAction::Add("SXIOPO".parse().unwrap())))
+        );
+        assert_eq!(
+            "cheat off 1".parse(),
+            Ok(Command::Cheat(CheatAction::Enable(1, false)))
+        );
+        assert!("cheat add QQQQQQ".parse::<Command>().is_err());
+        assert!("cheat delete".parse::<Command>().is_err());
+        assert_eq!("w".parse(), Ok(Command::Watch(WatchAction::List)));
+        assert_eq!(
+            "watch add 0075 + #1".parse(),
+            Ok(Command::Watch(WatchAction::Add(
+                Watch::parse("0075 + #1", &Symbols::default()).unwrap()
+            )))
+        );
+        assert_eq!(
+            "watch delete 2".parse(),
+            Ok(Command::Watch(WatchAction::Delete(2)))
+        );
+        assert!("watch add".parse::<Command>().is_err());
+        assert!("watch add 0075 +".parse::<Command>().is_err());
+        assert!("nametables".parse::<Command>().is_err());
+        assert_eq!(
+            "nt map.png viewport".parse(),
+            Ok(Command::Nametables {
+                path: "map.png".to_string(),
+                viewport: true
+            })
+        );
+        assert!("nt map.png screen".parse::<Command>().is_err());
+        assert_eq!(
+            "oam".parse(),
+            Ok(Command::Oam {
+                all: false,
+                path: None
+            })
+        );
+        assert_eq!(
+            "oam all sprites.png".parse(),
+            Ok(Command::Oam {
+                all: true,
+                path: Some("sprites.png".to_string())
+            })
+        );
+        assert!("oam a.png b.png".parse::<Command>().is_err());
+
+        assert_eq!(
+            "u".parse(),
+            Ok(Command::Disassemble {
+                address: None,
+                count: DISASM_COUNT
+            })
+        );
+        assert_eq!(
+            "disasm c000 3".parse(),
+            Ok(Command::Disassemble {
+                address: Some(0xC000),
+                count: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        // NOPs leading to a JMP $0200 loop.
+        cpu.memory[0x0200..0x0205].copy_from_slice(&[0xEA, 0xEA, 0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+
+        let mut out = Vec::new();
+        execute(&mut cpu, Command::Break(0x0201), &mut out);
+        execute(&mut cpu, Command::Break(0x0202), &mut out);
+        execute(&mut cpu, Command::Delete(0x0202), &mut out);
+
+        assert_eq!(cpu.run(), Stop::Breakpoint);
+        assert_eq!(cpu.program_counter, 0x0201);
+
+        // Running again executes the instruction at the breakpoint first.
+        assert_eq!(cpu.run(), Stop::Breakpoint);
+        assert_eq!(cpu.program_counter, 0x0201);
+
+        execute(&mut cpu, Command::Step, &mut out);
+        assert_eq!(cpu.program_counter, 0x0202);
+
+        out.clear();
+        execute(&mut cpu, Command::List, &mut out);
+        assert_eq!(String::from_utf8_lossy(&out), "$0201\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_and_finish() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        // JSR $0210, NOP, JMP $0200, with $0210 calling $0220 before returning.
+        cpu.memory[0x0200..0x0207].copy_from_slice(&[0x20, 0x10, 0x02, 0xEA, 0x4C, 0x00, 0x02]);
+        cpu.memory[0x0210..0x0214].copy_from_slice(&[0x20, 0x20, 0x02, 0x60]);
+        cpu.memory[0x0220..0x0222].copy_from_slice(&[0xEA, 0x60]);
+        cpu.program_counter = 0x0200;
+
+        let run_to = |cpu: &mut Cpu, command| {
+            assert_eq!(
+                execute(cpu, command, &mut Vec::new()),
+                Some(Resume::Continue)
+            );
+            assert_eq!(cpu.run(), Stop::Breakpoint);
+            (cpu.program_counter, cpu.call_stack.depth())
+        };
+
+        // Over the call, then like stepping.
+        assert_eq!(run_to(&mut cpu, Command::Next), (0x0203, 0));
+        let mut out = Vec::new();
+        assert_eq!(execute(&mut cpu, Command::Next, &mut out), None);
+        assert_eq!(cpu.program_counter, 0x0204);
+        out.clear();
+        execute(&mut cpu, Command::Finish, &mut out);
+        assert_eq!(String::from_utf8_lossy(&out), "At $0204, not in a call\n");
+
+        cpu.breakpoints.add(0x0220);
+        assert_eq!(cpu.run(), Stop::Breakpoint);
+        assert_eq!(cpu.call_stack.depth(), 2);
+        assert_eq!(run_to(&mut cpu, Command::Finish), (0x0213, 1));
+        assert_eq!(run_to(&mut cpu, Command::Finish), (0x0203, 0));
+
+        // A breakpoint inside the call stops there instead, for good.
+        execute(&mut cpu, Command::Step, &mut out);
+        execute(&mut cpu, Command::Step, &mut out);
+        assert_eq!(run_to(&mut cpu, Command::Next), (0x0220, 2));
+        cpu.breakpoints.remove(0x0220);
+        assert_eq!(cpu.run_frame(), Stop::FrameComplete);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_catch() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        // LDA #$80, STA $2000 to enable the NMI, then JMP to itself. BRK at $0210.
+        cpu.memory[0x0200..0x0208]
+            .copy_from_slice(&[0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x02]);
+        cpu.memory[0x0210] = 0x00;
+        cpu.program_counter = 0x0200;
+
+        let mut out = Vec::new();
+        for line in &["catch nmi", "catch brk on", "catch irq off"] {
+            execute(&mut cpu, line.parse().unwrap(), &mut out);
+        }
+        out.clear();
+        execute(&mut cpu, "catch".parse().unwrap(), &mut out);
+        assert_eq!(String::from_utf8_lossy(&out), "Catching NMI, BRK\n");
+
+        assert_eq!(cpu.run(), Stop::Breakpoint);
+        assert_eq!(cpu.breakpoints.caught(), Some(InterruptSource::Vblank));
+        let frame = *cpu.call_stack.frames().next().unwrap();
+        assert_eq!(frame.entry, Entry::Nmi);
+        assert_eq!(cpu.program_counter, frame.target);
+
+        execute(&mut cpu, "catch nmi".parse().unwrap(), &mut out);
+        cpu.program_counter = 0x0210;
+        out.clear();
+        execute(&mut cpu, Command::Step, &mut out);
+        assert!(String::from_utf8_lossy(&out).starts_with("Caught the BRK at $0210\n"));
+        assert_eq!(cpu.call_stack.frames().next().unwrap().entry, Entry::Brk);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unofficial_opcodes() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        // NOP, then the unofficial NOP $1A, then JMP $0200.
+        cpu.memory[0x0200..0x0205].copy_from_slice(&[0xEA, 0x1A, 0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+
+        let mut out = Vec::new();
+        execute(&mut cpu, "unofficial break".parse().unwrap(), &mut out);
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "Unofficial opcodes stop the CPU\n"
+        );
+        assert_eq!(cpu.run(), Stop::Breakpoint);
+        assert_eq!(cpu.program_counter, 0x0201);
+        let cycles = cpu.cycles;
+
+        // Once per execution, it runs when running again.
+        assert_eq!(cpu.run(), Stop::Breakpoint);
+        assert_eq!((cpu.program_counter, cpu.cycles), (0x0201, cycles + 7));
+
+        execute(
+            &mut cpu,
+            Command::Unofficial(Some(UnofficialOpcodePolicy::Run)),
+            &mut out,
+        );
+        assert_eq!(cpu.run_frame(), Stop::FrameComplete);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mute_and_solo() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        let mut out = Vec::new();
+        execute(&mut cpu, "mute noise".parse().unwrap(), &mut out);
+        assert!(cpu.apu.is_muted(Channel::Noise) && !cpu.apu.is_audible(Channel::Noise));
+        execute(&mut cpu, "mute noise".parse().unwrap(), &mut out);
+        assert!(cpu.apu.is_audible(Channel::Noise));
+
+        execute(&mut cpu, "solo pulse2".parse().unwrap(), &mut out);
+        assert!(cpu.apu.is_audible(Channel::Pulse2) && !cpu.apu.is_audible(Channel::Pulse1));
+        execute(&mut cpu, "solo off".parse().unwrap(), &mut out);
+        assert!(cpu.apu.is_audible(Channel::Pulse1));
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "Muted noise\nUnmuted noise\nOnly mixing pulse2\nSolo off\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        let mut out = Vec::new();
+        for (address, value) in [(0x0300, 0x4E), (0x0301, 0x45), (0x0302, 0x53)] {
+            execute(&mut cpu, Command::Set { address, value }, &mut out);
+        }
+
+        out.clear();
+        let len = HEXDUMP_WIDTH + 2;
+        execute(
+            &mut cpu,
+            Command::Hexdump {
+                address: 0x0300,
+                len,
+            },
+            &mut out,
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "0300  4E 45 53 00 00 00 00 00 00 00 00 00 00 00 00 00  |NES.............|\n\
+             0310  00 00                                            |..|\n"
+        );
+
+        // Write only PPU registers read back what was last on the bus, not what's in `memory`.
+        execute(
+            &mut cpu,
+            Command::Set {
+                address: 0x2001,
+                value: 0x1E,
+            },
+            &mut out,
+        );
+        assert_eq!(cpu.peek_range(0x2009, 1), vec![0x1E]);
+        assert_eq!(cpu.memory[0x2009], 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+/// Counts how often each address and opcode is executed.
+///
+/// The hot addresses show where a game spends its time, e.g. waiting for vertical blank, and the
+/// opcode histogram which instructions are worth optimizing in the emulator.
+use crate::cpu::Cpu;
+use crate::disasm;
+use crate::opcode::table;
+use std::fmt::Write;
+
+/// Entries shown in each part of the report.
+pub const REPORT_ENTRIES: usize = 20;
+
+pub struct Profiler {
+    enabled: bool,
+
+    /// Instructions executed at each address.
+    addresses: Vec<u64>,
+
+    /// Instructions executed of each opcode.
+    opcodes: Vec<u64>,
+
+    instructions: u64,
+}
+
+impl Profiler {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Start or stop counting, the counts so far are kept.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Count the instruction about to be executed.
+    pub fn record(&mut self, address: u16, opcode: u8) {
+        self.addresses[address as usize] += 1;
+        self.opcodes[opcode as usize] += 1;
+        self.instructions += 1;
+    }
+
+    pub fn reset(&mut self) {
+        *self = Profiler {
+            enabled: self.enabled,
+            ..Profiler::default()
+        };
+    }
+
+    /// The most executed addresses, most executed first.
+    pub fn hot_addresses(&self) -> Vec<(u16, u64)> {
+        top(&self.addresses)
+            .into_iter()
+            .map(|(address, count)| (address as u16, count))
+            .collect()
+    }
+
+    /// The most executed opcodes, most executed first.
+    pub fn hot_opcodes(&self) -> Vec<(u8, u64)> {
+        top(&self.opcodes)
+            .into_iter()
+            .map(|(opcode, count)| (opcode as u8, count))
+            .collect()
+    }
+
+    /// The hot addresses with the instruction currently there, then the opcode histogram.
+    pub fn report(&self, cpu: &Cpu) -> String {
+        let mut report = String::new();
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+
+        let _ = writeln!(report, "Instructions: {}", self.instructions);
+
+        let _ = writeln!(report, "Hot addresses:");
+        for (address, count) in self.hot_addresses() {
+            let line = disasm::decode(|addr| cpu.peek(addr), address);
+            let _ = writeln!(
+                report,
+                "  {:04X}  {:>12} {:5.1}%  {}",
+                address,
+                count,
+                percent(count),
+                line.instruction_with_labels(|addr| cpu.symbols.label(addr))
+            );
+        }
+
+        let _ = writeln!(report, "Opcodes:");
+        for (opcode, count) in self.hot_opcodes() {
+            let info = table::info(opcode);
+            let _ = writeln!(
+                report,
+                "  {:02X}  {:>12} {:5.1}%  {} {:?}",
+                opcode,
+                count,
+                percent(count),
+                info,
+                info.addressing
+            );
+        }
+
+        report
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler {
+            enabled: false,
+            addresses: vec![0; 0x10000],
+            opcodes: vec![0; 0x100],
+            instructions: 0,
+        }
+    }
+}
+
+/// Indices of the largest non-zero counts, largest first.
+fn top(counts: &[u64]) -> Vec<(usize, u64)> {
+    let mut entries: Vec<(usize, u64)> = counts
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|&(_, count)| count > 0)
+        .collect();
+
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries.truncate(REPORT_ENTRIES);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines;
+
+    #[test]
+    fn test_profiler() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        // A NOP then JMP $0200.
+        cpu.memory[0x0200..0x0204].copy_from_slice(&[0xEA, 0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+
+        cpu.step_instruction()?;
+        cpu.profiler.set_enabled(true);
+        for _ in 0..5 {
+            cpu.step_instruction()?;
+        }
+
+        assert_eq!(cpu.profiler.hot_addresses(), vec![(0x0201, 3), (0x0200, 2)]);
+        assert_eq!(cpu.profiler.hot_opcodes(), vec![(0x4C, 3), (0xEA, 2)]);
+
+        let report = cpu.profiler.report(&cpu);
+        assert!(report.contains("  0201             3  60.0%  JMP $0200\n"));
+
+        cpu.profiler.reset();
+        assert!(cpu.profiler.is_enabled());
+        assert!(cpu.profiler.hot_addresses().is_empty());
+
+        Ok(())
+    }
+}
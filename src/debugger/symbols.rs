@@ -0,0 +1,185 @@
+/// Names of addresses from an assembler or another debugger, shown instead of the raw addresses
+/// and accepted wherever an address is.
+///
+/// Lines of these formats are read, anything else is skipped:
+///   FCEUX's .nl files: "$C000#reset#comment", with "$0300/10#buffer#" for arrays.
+///   ld65's label files (-Ln): "al 00C000 .reset".
+///   ca65/ld65 debug files (--dbgfile): "sym id=0,name="reset",...,val=0xC000,type=lab".
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Bank files looked for, more than the mappers supported so far have.
+const NL_BANKS: usize = 16;
+
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    /// The first name given to each address.
+    labels: BTreeMap<u16, String>,
+
+    addresses: HashMap<String, u16>,
+}
+
+impl Symbols {
+    /// Add the symbols in a file, returns how many were found.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read symbols \"{}\"", path.display()))?;
+
+        Ok(self.parse(&contents))
+    }
+
+    /// Add the symbols in any of the formats, returns how many were found.
+    pub fn parse(&mut self, contents: &str) -> usize {
+        let mut count = 0;
+        for line in contents.lines() {
+            let line = line.trim();
+            let symbol = if let Some(line) = line.strip_prefix('$') {
+                parse_nl(line)
+            } else if let Some(line) = line.strip_prefix("al ") {
+                parse_vice(line)
+            } else if let Some(line) = line.strip_prefix("sym") {
+                parse_dbg(line)
+            } else {
+                None
+            };
+
+            if let Some((address, name)) = symbol {
+                self.add(address, name);
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    pub fn add(&mut self, address: u16, name: &str) {
+        self.labels
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    /// An address or a name. Names take precedence over hexadecimal addresses without a prefix,
+    /// e.g. a label "add".
+    pub fn resolve(&self, value: &str) -> Result<u16, String> {
+        let prefixed = value.starts_with('$') || value.starts_with("0x");
+        match self.address(value) {
+            Some(address) if !prefixed => Ok(address),
+            _ => super::parse_address(value)
+                .map_err(|_| format!("Invalid address or unknown label \"{}\".", value)),
+        }
+    }
+
+    /// An address with its name if it has one, e.g. "$C000 (reset)".
+    pub fn describe(&self, address: u16) -> String {
+        match self.label(address) {
+            Some(label) => format!("${:04X} ({})", address, label),
+            None => format!("${:04X}", address),
+        }
+    }
+}
+
+/// FCEUX's symbol files next to the ROM that exist, the RAM's and each 16 KiB PRG ROM bank's.
+pub fn fceux_symbol_files(rom_path: &str) -> Vec<String> {
+    let banks = (0..NL_BANKS).map(|bank| format!("{:X}", bank));
+    std::iter::once("ram".to_string())
+        .chain(banks)
+        .map(|suffix| format!("{}.{}.nl", rom_path, suffix))
+        .filter(|path| Path::new(path).exists())
+        .collect()
+}
+
+/// "C000#reset#comment" or "0300/10#buffer#", the address after the "$".
+fn parse_nl(line: &str) -> Option<(u16, &str)> {
+    let mut fields = line.split('#');
+    let address = fields.next()?.split('/').next()?;
+    let name = fields.next()?.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((u16::from_str_radix(address, 16).ok()?, name))
+}
+
+/// "00C000 .reset", after the "al ".
+fn parse_vice(line: &str) -> Option<(u16, &str)> {
+    let mut fields = line.split_whitespace();
+    let address = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let name = fields.next()?.trim_start_matches('.');
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((address as u16, name))
+}
+
+/// "\tid=0,name="reset",addrsize=absolute,...,val=0xC000,type=lab", after the "sym". Only labels
+/// are used, the other symbols are constants.
+fn parse_dbg(line: &str) -> Option<(u16, &str)> {
+    let mut name = None;
+    let mut value = None;
+    let mut label = false;
+
+    for field in line.trim().split(',') {
+        let (key, field_value) = field.split_once('=')?;
+        match key {
+            "name" => name = Some(field_value.trim_matches('"')),
+            "val" => value = u16::from_str_radix(field_value.strip_prefix("0x")?, 16).ok(),
+            "type" => label = field_value == "lab",
+            _ => (),
+        }
+    }
+
+    if label {
+        Some((value?, name?))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut symbols = Symbols::default();
+        let count = symbols.parse(
+            "$C000#reset#Entry point\n\
+             $0300/10#buffer#\n\
+             $C004##no name\n\
+             al 00C123 .nmi\n\
+             al 00C123 .vblank\n\
+             version\tmajor=2,minor=0\n\
+             sym\tid=0,name=\"main_loop\",addrsize=absolute,size=1,scope=0,def=3,val=0xC010,type=lab\n\
+             sym\tid=1,name=\"PPUCTRL\",addrsize=absolute,scope=0,def=4,val=0x2000,type=equ\n",
+        );
+
+        assert_eq!(count, 5);
+        assert_eq!(symbols.label(0xC000), Some("reset"));
+        assert_eq!(symbols.label(0x0300), Some("buffer"));
+        assert_eq!(symbols.label(0xC004), None);
+        assert_eq!(symbols.label(0xC010), Some("main_loop"));
+        assert_eq!(symbols.address("PPUCTRL"), None);
+
+        // The first name is shown, both are accepted.
+        assert_eq!(symbols.label(0xC123), Some("nmi"));
+        assert_eq!(symbols.resolve("vblank"), Ok(0xC123));
+
+        assert_eq!(symbols.resolve("reset"), Ok(0xC000));
+        assert_eq!(symbols.resolve("$C5F5"), Ok(0xC5F5));
+        assert!(symbols.resolve("start").is_err());
+        assert_eq!(symbols.describe(0xC000), "$C000 (reset)");
+    }
+}
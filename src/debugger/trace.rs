@@ -0,0 +1,221 @@
+/// Trace logs, a line per executed instruction written to a file or stdout.
+///
+/// Lines can follow the format of other emulators' trace loggers, so logs can be compared with
+/// theirs line by line.
+use crate::cpu::Cpu;
+use crate::disasm;
+use crate::opcode::Operation;
+use crate::ppu::SCANLINES_PER_FRAME;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+/// How each instruction is written to the log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    /// Close to nestest's reference log, the same lines the debugger shows.
+    Nestest,
+
+    /// Like FCEUX's trace logger, e.g.
+    /// "A:00 X:00 Y:00 S:FD P:nvUbdIzc  $C000:4C F5 C5  JMP $C5F5".
+    Fceux,
+
+    /// Like Mesen's trace logger, e.g.
+    /// "C000  4C F5 C5  JMP $C5F5  A:00 X:00 Y:00 P:24 SP:FD CYC:21  SL:0   FC:0 CPU Cycle:7".
+    Mesen,
+}
+
+impl TraceFormat {
+    /// The line for `operation`, about to be executed.
+    pub fn line(self, cpu: &Cpu, operation: &dyn Operation) -> String {
+        let status = u8::from(cpu.status.clone());
+        let stack = cpu.stack.as_stack_offset();
+
+        match self {
+            TraceFormat::Nestest => cpu.trace(operation),
+            TraceFormat::Fceux => {
+                let line = disasm::decode(|addr| cpu.peek(addr), cpu.program_counter);
+                format!(
+                    "A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}  ${:04X}:{:<9} {}",
+                    cpu.a,
+                    cpu.x,
+                    cpu.y,
+                    stack,
+                    status_flags(status),
+                    line.address,
+                    line.hex_bytes(),
+                    line.instruction_with_labels(|addr| cpu.symbols.label(addr))
+                )
+            }
+            TraceFormat::Mesen => {
+                let line = disasm::decode(|addr| cpu.peek(addr), cpu.program_counter);
+
+                // Mesen counts the pre-render scanline as -1.
+                let scanline = match cpu.ppu.scanline() {
+                    scanline if scanline == SCANLINES_PER_FRAME - 1 => -1,
+                    scanline => scanline as i32,
+                };
+
+                format!(
+                    "{:04X}  {:<8}  {:<15} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} \
+                     CYC:{:<3} SL:{:<3} FC:{} CPU Cycle:{}",
+                    line.address,
+                    line.hex_bytes(),
+                    line.instruction_with_labels(|addr| cpu.symbols.label(addr)),
+                    cpu.a,
+                    cpu.x,
+                    cpu.y,
+                    status,
+                    stack,
+                    cpu.ppu.dot(),
+                    scanline,
+                    cpu.ppu.frame_count(),
+                    cpu.cycles
+                )
+            }
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "nestest" => Ok(TraceFormat::Nestest),
+            "fceux" => Ok(TraceFormat::Fceux),
+            "mesen" => Ok(TraceFormat::Mesen),
+            _ => Err(format!("Unknown trace format \"{}\".", value)),
+        }
+    }
+}
+
+/// The status flags as letters, upper case when set, e.g. "nvUbdIzc".
+fn status_flags(status: u8) -> String {
+    "NVUBDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, flag)| {
+            if status & (0x80 >> i) != 0 {
+                flag
+            } else {
+                flag.to_ascii_lowercase()
+            }
+        })
+        .collect()
+}
+
+/// Writes the trace log while enabled, it can be switched on and off at any time.
+pub struct Tracer {
+    format: TraceFormat,
+    out: BufWriter<Box<dyn Write + Send>>,
+    enabled: bool,
+}
+
+impl Tracer {
+    /// Trace to stdout, starting disabled.
+    pub fn stdout(format: TraceFormat) -> Self {
+        Tracer::new(format, Box::new(io::stdout()))
+    }
+
+    /// Trace to a new file, starting disabled.
+    pub fn create(format: TraceFormat, path: &str) -> io::Result<Self> {
+        Ok(Tracer::new(format, Box::new(File::create(path)?)))
+    }
+
+    pub fn new(format: TraceFormat, out: Box<dyn Write + Send>) -> Self {
+        Tracer {
+            format,
+            out: BufWriter::new(out),
+            enabled: false,
+        }
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Switch tracing on or off, everything traced so far is flushed when switched off.
+    pub fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+        self.enabled = enabled;
+        if !enabled {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.out, "{}", line)
+    }
+
+    /// Write out the buffered lines, e.g. before stopping in the debugger.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Tracer::stdout(TraceFormat::Nestest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    /// Collects everything written to it, shared with the test.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_formats() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::nestest(nes_file);
+
+        let output = Output::default();
+        cpu.tracer = Tracer::new(TraceFormat::Fceux, Box::new(output.clone()));
+
+        // Not traced while disabled.
+        cpu.step_instruction()?;
+        cpu.tracer.set_enabled(true)?;
+        cpu.step_instruction()?;
+        cpu.tracer.set_enabled(false)?;
+        cpu.step_instruction()?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output.0.lock().unwrap()),
+            "A:00 X:00 Y:00 S:FD P:nvUbdIzc  $C5F5:A2 00     LDX #$00\n"
+        );
+
+        let operation = crate::opcode::next(&cpu)?;
+        assert_eq!(
+            TraceFormat::Mesen.line(&cpu, &*operation),
+            "C5F9  86 10     STX $10         A:00 X:00 Y:00 P:26 SP:FD CYC:45  SL:0   FC:0 \
+             CPU Cycle:15"
+        );
+
+        assert_eq!("mesen".parse(), Ok(TraceFormat::Mesen));
+        assert!("nintendulator".parse::<TraceFormat>().is_err());
+
+        Ok(())
+    }
+}
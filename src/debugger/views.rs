@@ -0,0 +1,75 @@
+/// Debugger views of the PPU's memory, as images or text.
+use crate::cpu::Cpu;
+use crate::ppu::{
+    NAMETABLES_HEIGHT, NAMETABLES_WIDTH, PATTERN_TABLE_SIZE, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+use crate::video::image;
+use anyhow::Result;
+use std::io::{self, Write};
+
+/// Colour of the outline of the screen on the nametables, a bright red.
+const VIEWPORT_COLOUR: u8 = 0x16;
+
+/// Save the four nametables as a PNG, with the screen outlined if `viewport` is set.
+pub fn save_nametables(cpu: &Cpu, path: &str, viewport: bool) -> Result<()> {
+    let mut nametables = cpu.ppu.render_nametables();
+    if viewport {
+        cpu.ppu.draw_viewport(&mut nametables, VIEWPORT_COLOUR);
+    }
+    image::save_png(path, NAMETABLES_WIDTH, NAMETABLES_HEIGHT, &nametables)
+}
+
+/// Save both pattern tables side by side as a PNG, drawn with one of the eight palettes.
+pub fn save_pattern_tables(cpu: &Cpu, path: &str, palette: u8) -> Result<()> {
+    let left = cpu.ppu.render_pattern_table(0, palette);
+    let right = cpu.ppu.render_pattern_table(1, palette);
+
+    let image: Vec<u8> = left
+        .chunks(PATTERN_TABLE_SIZE)
+        .zip(right.chunks(PATTERN_TABLE_SIZE))
+        .flat_map(|(left, right)| left.iter().chain(right))
+        .copied()
+        .collect();
+
+    image::save_png(path, PATTERN_TABLE_SIZE * 2, PATTERN_TABLE_SIZE, &image)
+}
+
+/// List the sprites in OAM, hidden sprites (below the screen) are left out unless `all` is set.
+pub fn print_oam(cpu: &Cpu, out: &mut impl Write, all: bool) -> io::Result<()> {
+    let sprites: Vec<_> = cpu
+        .ppu
+        .oam_entries()
+        .into_iter()
+        .filter(|sprite| all || sprite.y < 0xEF)
+        .collect();
+
+    if sprites.is_empty() {
+        return writeln!(out, "No visible sprites");
+    }
+
+    sprites
+        .iter()
+        .try_for_each(|sprite| writeln!(out, "{}", sprite))
+}
+
+/// Save the sprites as a PNG the size of the screen, transparent where there are none.
+pub fn save_sprites(cpu: &Cpu, path: &str) -> Result<()> {
+    let sprites = cpu.ppu.render_sprites();
+    image::save_png_layer(path, SCREEN_WIDTH, SCREEN_HEIGHT, &sprites)
+}
+
+/// Show the colours of the eight palettes, e.g. "BG0  0F 16 27 18".
+pub fn print_palettes(cpu: &Cpu, out: &mut impl Write) -> io::Result<()> {
+    let palettes = cpu.ppu.palette_ram();
+
+    palettes.chunks(4).enumerate().try_for_each(|(i, colours)| {
+        let name = if i < 4 {
+            format!("BG{}", i)
+        } else {
+            format!("SP{}", i - 4)
+        };
+        let colours: Vec<String> = colours.iter().map(|c| format!("{:02X}", c)).collect();
+
+        writeln!(out, "{}  {}", name, colours.join(" "))
+    })
+}
@@ -0,0 +1,337 @@
+/// Watch expressions, values shown after every frame to follow a game's variables without
+/// stopping it.
+///
+/// An address or label stands for the byte there, "ADDR:w" for the little endian word. Literals
+/// start with "#", so "#label" is the label's address. Brackets read the byte at a computed
+/// address and "+ - * / % & | ^ << >>" work as in C, e.g. "score:w", "[#enemies + index]" or
+/// "(0075 & #F0) >> #4". Like everywhere in the debugger numbers are hexadecimal. Memory is
+/// peeked, watching registers doesn't disturb them.
+use super::Symbols;
+use crate::cpu::Cpu;
+use std::fmt;
+
+/// The expressions being watched.
+#[derive(Clone, Debug)]
+pub struct Watches {
+    watches: Vec<Watch>,
+
+    /// Log the values after every frame, frontends showing them on screen turn it off.
+    pub log: bool,
+}
+
+impl Default for Watches {
+    fn default() -> Self {
+        Watches {
+            watches: Vec::new(),
+            log: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watch {
+    /// As it was entered.
+    pub text: String,
+
+    expression: Expression,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expression {
+    Literal(i64),
+    Byte(Box<Expression>),
+    Word(u16),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+/// Operators from the loosest binding to the tightest, as in C.
+const PRECEDENCE: [&[(&str, Operator)]; 6] = [
+    &[("|", Operator::Or)],
+    &[("^", Operator::Xor)],
+    &[("&", Operator::And)],
+    &[("<<", Operator::ShiftLeft), (">>", Operator::ShiftRight)],
+    &[("+", Operator::Add), ("-", Operator::Subtract)],
+    &[
+        ("*", Operator::Multiply),
+        ("/", Operator::Divide),
+        ("%", Operator::Remainder),
+    ],
+];
+
+impl Watch {
+    /// Parse an expression, addresses can be given by the symbols' names.
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Self, String> {
+        let mut parser = Parser {
+            rest: text.trim(),
+            symbols,
+        };
+        let expression = parser.expression(0)?;
+        if !parser.rest.is_empty() {
+            return Err(format!("Unexpected \"{}\" in \"{}\".", parser.rest, text));
+        }
+
+        Ok(Watch {
+            text: text.trim().to_string(),
+            expression,
+        })
+    }
+
+    /// The value now, None when dividing by zero.
+    pub fn evaluate(&self, cpu: &Cpu) -> Option<i64> {
+        self.expression.evaluate(cpu)
+    }
+}
+
+impl Expression {
+    fn evaluate(&self, cpu: &Cpu) -> Option<i64> {
+        match self {
+            Expression::Literal(value) => Some(*value),
+            Expression::Byte(address) => Some(cpu.peek(address.evaluate(cpu)? as u16).into()),
+            Expression::Word(address) => {
+                let low = cpu.peek(*address);
+                let high = cpu.peek(address.wrapping_add(1));
+                Some(u16::from_le_bytes([low, high]).into())
+            }
+            Expression::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(cpu)?, right.evaluate(cpu)?);
+                match operator {
+                    Operator::Add => Some(left.wrapping_add(right)),
+                    Operator::Subtract => Some(left.wrapping_sub(right)),
+                    Operator::Multiply => Some(left.wrapping_mul(right)),
+                    Operator::Divide => left.checked_div(right),
+                    Operator::Remainder => left.checked_rem(right),
+                    Operator::And => Some(left & right),
+                    Operator::Or => Some(left | right),
+                    Operator::Xor => Some(left ^ right),
+                    Operator::ShiftLeft => Some(left.wrapping_shl(right as u32)),
+                    Operator::ShiftRight => Some(left.wrapping_shr(right as u32)),
+                }
+            }
+        }
+    }
+}
+
+/// Recursive descent over the expression's text, one precedence level at a time.
+struct Parser<'a> {
+    rest: &'a str,
+    symbols: &'a Symbols,
+}
+
+impl<'a> Parser<'a> {
+    fn expression(&mut self, level: usize) -> Result<Expression, String> {
+        if level == PRECEDENCE.len() {
+            return self.operand();
+        }
+
+        let mut expression = self.expression(level + 1)?;
+        while let Some(&(symbol, operator)) = PRECEDENCE[level]
+            .iter()
+            .find(|(symbol, _)| self.rest.starts_with(symbol))
+        {
+            self.advance(symbol.len());
+            let right = self.expression(level + 1)?;
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(right));
+        }
+
+        Ok(expression)
+    }
+
+    fn operand(&mut self) -> Result<Expression, String> {
+        if self.eat("(") {
+            let expression = self.expression(0)?;
+            self.expect(")")?;
+            return Ok(expression);
+        }
+        if self.eat("[") {
+            let address = self.expression(0)?;
+            self.expect("]")?;
+            return Ok(Expression::Byte(Box::new(address)));
+        }
+
+        let literal = self.eat("#");
+        let word = self.word();
+        if word.is_empty() {
+            return Err(match self.rest.chars().next() {
+                Some(c) => format!("Expected an address or a value, got \"{}\".", c),
+                None => "Expected an address or a value.".to_string(),
+            });
+        }
+
+        let address = self.symbols.resolve(word)?;
+        if literal {
+            Ok(Expression::Literal(address.into()))
+        } else if self.eat(":w") {
+            Ok(Expression::Word(address))
+        } else {
+            Ok(Expression::Byte(Box::new(Expression::Literal(
+                address.into(),
+            ))))
+        }
+    }
+
+    /// An address, label or value, up to the next operator.
+    fn word(&mut self) -> &'a str {
+        let len = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "_@.$".contains(c)))
+            .unwrap_or(self.rest.len());
+        let word = &self.rest[..len];
+        self.advance(len);
+        word
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.rest.starts_with(token);
+        if found {
+            self.advance(token.len());
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("Expected \"{}\".", token))
+        }
+    }
+
+    /// Skip `len` bytes and the spaces after them.
+    fn advance(&mut self, len: usize) {
+        self.rest = self.rest[len..].trim_start();
+    }
+}
+
+impl Watches {
+    /// Returns the watch's index, used to remove it later.
+    pub fn add(&mut self, watch: Watch) -> usize {
+        self.watches.push(watch);
+        self.watches.len() - 1
+    }
+
+    /// Returns the removed watch, later watches move down an index.
+    pub fn remove(&mut self, index: usize) -> Option<Watch> {
+        if index < self.watches.len() {
+            Some(self.watches.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watch> {
+        self.watches.iter()
+    }
+
+    /// Each watch with its value now, e.g. "score:w = $04D2".
+    pub fn values<'a>(&'a self, cpu: &'a Cpu) -> impl Iterator<Item = WatchValue<'a>> {
+        self.watches.iter().map(move |watch| WatchValue {
+            text: &watch.text,
+            value: watch.evaluate(cpu),
+        })
+    }
+}
+
+/// A watch's value, see `Watches::values()`.
+pub struct WatchValue<'a> {
+    pub text: &'a str,
+    pub value: Option<i64>,
+}
+
+impl fmt::Display for WatchValue<'_> {
+    /// Bytes in 2 hex digits, words in 4, e.g. "0075 = $09" or "[0300 / #0] = ?".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value {
+            Some(value) if value < 0 => write!(f, "{} = -${:02X}", self.text, -value),
+            Some(value) if value <= 0xFF => write!(f, "{} = ${:02X}", self.text, value),
+            Some(value) => write!(f, "{} = ${:04X}", self.text, value),
+            None => write!(f, "{} = ?", self.text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines;
+
+    #[test]
+    fn test_evaluate() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+        cpu.memory[0x0075] = 0x93;
+        cpu.memory[0x0076] = 0x04;
+        cpu.symbols.add(0x0075, "score");
+
+        let value = |text: &str| Watch::parse(text, &cpu.symbols).unwrap().evaluate(&cpu);
+        assert_eq!(value("0075"), Some(0x93));
+        assert_eq!(value("score:w"), Some(0x0493));
+        assert_eq!(value("(score & #F0) >> #4"), Some(0x09));
+        assert_eq!(value("$0076 + #1 * #2"), Some(0x06));
+        assert_eq!(value("[#score + #1]"), Some(0x04));
+        assert_eq!(value("#0 - 0076"), Some(-4));
+        assert_eq!(value("score / #0"), None);
+
+        for text in &[
+            "",
+            "score +",
+            "(0075",
+            "[0075",
+            "0075 0076",
+            "nowhere",
+            "#G",
+        ] {
+            assert!(Watch::parse(text, &cpu.symbols).is_err(), "{}", text);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_values() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+        cpu.memory[0x0300] = 0x12;
+
+        let mut watches = Watches::default();
+        for text in &["0300", "0300:w + #100", "0300 - #13", "0300 % #0"] {
+            watches.add(Watch::parse(text, &cpu.symbols).unwrap());
+        }
+        let values: Vec<String> = watches
+            .values(&cpu)
+            .map(|value| value.to_string())
+            .collect();
+        assert_eq!(
+            values,
+            [
+                "0300 = $12",
+                "0300:w + #100 = $0112",
+                "0300 - #13 = -$01",
+                "0300 % #0 = ?"
+            ]
+        );
+
+        assert_eq!(
+            watches.remove(0).map(|watch| watch.text),
+            Some("0300".to_string())
+        );
+        assert!(watches.remove(3).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+/// Deterministic mode: the same ROM, inputs and seed give the same frames on every run and every
+/// machine, which netplay, movies and hashing frames in CI all rely on.
+///
+/// What could make two runs differ, and how each is handled:
+///
+/// - RAM at power on. Real RAM comes up holding noise and a few games read it before writing it.
+///   It's filled with a `RamPattern`, in deterministic mode random bytes from the seed. The PPU's
+///   nametables, OAM, palette and CHR RAM start zeroed, and reads of addresses nothing answers
+///   return what's in the address space rather than a decaying open bus.
+/// - The host's clock. The console never reads it: the PPU's open bus decays after a number of
+///   frames, not of milliseconds. Frontends pace frames by the clock, but inputs are set in
+///   between frames so replaying them per frame replays the run, see `movie`. Scripts could read
+///   the clock with Rhai's `timestamp()`, it's unavailable in deterministic mode.
+/// - Floating point. The APU mixes and the frontends resample in `f32`, only for the sound,
+///   nothing the console does reads it back. Frames don't depend on how the host rounds, the
+///   samples can differ in their last bits between machines.
+/// - Save states. Everything later frames depend on is saved, so loading a state, rewinding, run
+///   ahead and netplay's rollback carry on exactly. The settings aren't: the region, overclocking,
+///   cheats, pokes, RAM pattern and DIP switches have to match, netplay compares them.
+/// - Battery saves. The cartridge's RAM would start as the last run left it, so deterministic
+///   mode neither loads the ROM's `.sav` nor writes it.
+/// - Scheduling. Catching the PPU up runs it in bigger steps but it's in the same place whenever
+///   the CPU looks, the frames are the same as interleaving it.
+/// - Inputs movies don't record, i.e. the Zapper's aim and trigger.
+use crate::cpu::{Cpu, RamPattern};
+
+/// Set the console up to run deterministically from `seed`, before powering it on. Only the
+/// RAM pattern depends on the seed, frontends also keep scripts off the clock and leave the
+/// battery save alone.
+pub fn apply(cpu: &mut Cpu, seed: u64) {
+    cpu.set_ram_pattern(RamPattern::Random(seed));
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::controller::ControllerState;
+    use crate::cpu::UnknownOpcodePolicy;
+    use crate::ines::NesFile;
+    use alloc::vec::Vec;
+
+    /// Hash of each frame, with inputs that change every frame.
+    fn run(cpu: &mut Cpu, frames: u64) -> Vec<u64> {
+        (0..frames)
+            .map(|_| {
+                let frame = cpu.ppu.frame_count();
+                cpu.controllers[0].set_state(ControllerState((frame * 37) as u8));
+                cpu.run_frame();
+                cpu.ppu
+                    .frame()
+                    .iter()
+                    .chain(&cpu.memory[..0x800])
+                    .fold(0, |hash: u64, &byte| hash.rotate_left(5) ^ byte as u64)
+            })
+            .collect()
+    }
+
+    fn new_cpu(seed: u64) -> anyhow::Result<Cpu> {
+        let mut cpu = Cpu::new(NesFile::new("test/nestest.nes".to_string())?);
+        cpu.unknown_opcode = UnknownOpcodePolicy::Skip;
+        apply(&mut cpu, seed);
+        cpu.power_on();
+        Ok(cpu)
+    }
+
+    #[test]
+    fn test_deterministic() -> anyhow::Result<()> {
+        let mut first = new_cpu(1)?;
+        let mut second = new_cpu(1)?;
+        assert_eq!(run(&mut first, 10), run(&mut second, 10));
+
+        // Carries on the same from a save state.
+        let state = first.save_state()?;
+        let expected = run(&mut first, 10);
+        second.load_state(&state)?;
+        assert_eq!(run(&mut second, 10), expected);
+
+        // Even one taken while jammed.
+        first.unknown_opcode = UnknownOpcodePolicy::Halt;
+        first.memory[0x0200] = 0x02;
+        first.program_counter = 0x0200;
+        first.run_frame();
+        assert!(first.is_jammed());
+        let state = first.save_state()?;
+        second.load_state(&state)?;
+        assert!(second.is_jammed());
+
+        assert_ne!(new_cpu(2)?.memory[..0x800], new_cpu(1)?.memory[..0x800]);
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+/// Disassembler, decodes memory into a listing without executing anything.
+///
+/// Works on any memory through a read function, e.g. a ROM dump or what the CPU sees on its bus.
+use crate::opcode::table::{self, Addressing, OpcodeInfo};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A decoded instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub address: u16,
+
+    /// The opcode followed by the operand.
+    pub bytes: Vec<u8>,
+
+    pub info: OpcodeInfo,
+}
+
+impl Line {
+    /// Address of the instruction after this one.
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.bytes.len() as u16)
+    }
+
+    /// Operand in assembler syntax, e.g. "($10),Y", with the addresses that have a label replaced
+    /// by it, e.g. "(pointer),Y".
+    pub fn operand_with_labels<'a>(&self, label: impl Fn(u16) -> Option<&'a str>) -> String {
+        let byte = || self.bytes[1];
+        let word = || u16::from_le_bytes([self.bytes[1], self.bytes[2]]);
+        let zero_page = || match label(byte() as u16) {
+            Some(label) => label.to_string(),
+            None => format!("${:02X}", byte()),
+        };
+        let absolute = |address: u16| match label(address) {
+            Some(label) => label.to_string(),
+            None => format!("${:04X}", address),
+        };
+
+        match self.info.addressing {
+            Addressing::Implied => String::new(),
+            Addressing::Accumulator => "A".to_string(),
+            Addressing::Immediate => format!("#${:02X}", byte()),
+            Addressing::ZeroPage => zero_page(),
+            Addressing::ZeroPageX => format!("{},X", zero_page()),
+            Addressing::ZeroPageY => format!("{},Y", zero_page()),
+            Addressing::Absolute => absolute(word()),
+            Addressing::AbsoluteX => format!("{},X", absolute(word())),
+            Addressing::AbsoluteY => format!("{},Y", absolute(word())),
+            Addressing::Indirect => format!("({})", absolute(word())),
+            Addressing::IndirectX => format!("({},X)", zero_page()),
+            Addressing::IndirectY => format!("({}),Y", zero_page()),
+            // The branch target, relative to the next instruction.
+            Addressing::Relative => absolute(self.next_address().wrapping_add(byte() as i8 as u16)),
+        }
+    }
+
+    /// The bytes in hexadecimal, e.g. "4C F5 C5".
+    pub fn hex_bytes(&self) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        bytes.join(" ")
+    }
+
+    /// Mnemonic and operand, e.g. "JMP $C5F5" or with labels "JMP main_loop".
+    pub fn instruction_with_labels<'a>(&self, label: impl Fn(u16) -> Option<&'a str>) -> String {
+        format!("{} {}", self.info, self.operand_with_labels(label))
+            .trim_end()
+            .to_string()
+    }
+
+    /// The line as shown by `Display`, using labels in the operand.
+    pub fn listing<'a>(&self, label: impl Fn(u16) -> Option<&'a str>) -> String {
+        let padding = if self.info.official { " " } else { "" };
+
+        format!(
+            "{:04X}  {:<8} {}{}",
+            self.address,
+            self.hex_bytes(),
+            padding,
+            self.instruction_with_labels(label)
+        )
+    }
+}
+
+impl fmt::Display for Line {
+    /// Formatted like nestest's log, e.g. "C000  4C F5 C5  JMP $C5F5". Unofficial opcodes take
+    /// the space before the mnemonic for their "*".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.listing(|_| None))
+    }
+}
+
+/// Decode the instruction at `address`.
+pub fn decode(read: impl Fn(u16) -> u8, address: u16) -> Line {
+    let info = table::info(read(address));
+    let bytes = (0..info.bytes())
+        .map(|offset| read(address.wrapping_add(offset)))
+        .collect();
+
+    Line {
+        address,
+        bytes,
+        info,
+    }
+}
+
+/// Decode `count` consecutive instructions starting at `address`.
+pub fn disassemble(read: impl Fn(u16) -> u8, address: u16, count: usize) -> Vec<Line> {
+    let mut lines = Vec::with_capacity(count);
+    let mut address = address;
+    for _ in 0..count {
+        let line = decode(&read, address);
+        address = line.next_address();
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Decode a block of code loaded at `origin`, e.g. a PRG ROM bank. The last instruction may be
+/// cut off, missing bytes read as 0.
+pub fn disassemble_bytes(bytes: &[u8], origin: u16) -> Vec<Line> {
+    let read = |address: u16| {
+        bytes
+            .get(address.wrapping_sub(origin) as usize)
+            .copied()
+            .unwrap_or(0)
+    };
+
+    let mut lines = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let line = decode(read, origin.wrapping_add(offset as u16));
+        offset += line.bytes.len();
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_bytes() {
+        let code = [
+            0x4C, 0xF5, 0xC5, // JMP $C5F5
+            0xA9, 0x10, // LDA #$10
+            0xB1, 0x20, // LDA ($20),Y
+            0xD0, 0xFA, // BNE back to the JMP
+            0x0A, // ASL A
+            0xEA, // NOP
+            0x04, 0x44, // *NOP $44
+        ];
+
+        let listing: Vec<String> = disassemble_bytes(&code, 0xC000)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+
+        assert_eq!(
+            listing,
+            vec![
+                "C000  4C F5 C5  JMP $C5F5",
+                "C003  A9 10     LDA #$10",
+                "C005  B1 20     LDA ($20),Y",
+                "C007  D0 FA     BNE $C003",
+                "C009  0A        ASL A",
+                "C00A  EA        NOP",
+                "C00B  04 44    *NOP $44",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let code = [
+            0x4C, 0xF5, 0xC5, // JMP $C5F5
+            0xB1, 0x20, // LDA ($20),Y
+            0xD0, 0xFC, // BNE back to the LDA
+        ];
+        let label = |address| match address {
+            0xC5F5 => Some("main_loop"),
+            0x0020 => Some("pointer"),
+            0xC003 => Some("copy"),
+            _ => None,
+        };
+
+        let listing: Vec<String> = disassemble_bytes(&code, 0xC000)
+            .iter()
+            .map(|line| line.listing(label))
+            .collect();
+
+        assert_eq!(
+            listing,
+            vec![
+                "C000  4C F5 C5  JMP main_loop",
+                "C003  B1 20     LDA (pointer),Y",
+                "C005  D0 FC     BNE copy",
+            ]
+        );
+    }
+}
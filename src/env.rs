@@ -0,0 +1,270 @@
+/// Environment for reinforcement learning in the style of OpenAI Gym: reset to start an episode,
+/// then step with the buttons to hold and get back the screen, the RAM and whether the episode
+/// is over.
+///
+/// Episodes are deterministic for a seed. The seed picks what RAM holds at power on, as in
+/// `deterministic`, and how many frames without input start each episode (`noop_max`), which
+/// keeps agents from memorizing a single sequence of inputs.
+use crate::controller::ControllerState;
+use crate::deterministic;
+use crate::ines::NesFile;
+use crate::Nes;
+use alloc::boxed::Box;
+
+/// Size of the console's RAM, mirrored up to $1FFF.
+pub const RAM_SIZE: usize = 0x800;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvOptions {
+    /// Frames each step holds the buttons for, only the last frame is returned.
+    pub frame_skip: u32,
+
+    /// Most frames without input at the start of an episode, a random number up to this many are
+    /// run on reset.
+    pub noop_max: u32,
+
+    /// Episodes end after this many frames, including the ones at the start without input.
+    pub max_frames: Option<u64>,
+
+    pub seed: u64,
+}
+
+impl Default for EnvOptions {
+    fn default() -> Self {
+        EnvOptions {
+            frame_skip: 1,
+            noop_max: 0,
+            max_frames: None,
+            seed: 0,
+        }
+    }
+}
+
+/// What the agent sees after a reset or a step.
+#[derive(Debug, PartialEq)]
+pub struct Step<'a> {
+    /// Palette indices, `SCREEN_WIDTH` by `SCREEN_HEIGHT`.
+    pub frame: &'a [u8],
+
+    pub ram: &'a [u8],
+    pub done: bool,
+}
+
+type DonePredicate = Box<dyn FnMut(&[u8]) -> bool + Send>;
+
+pub struct NesEnv {
+    nes: Nes,
+
+    /// Inserted again on every reset.
+    cartridge: NesFile,
+
+    options: EnvOptions,
+    rng: SplitMix64,
+
+    /// Ends the episode when true for the RAM, e.g. when the game's lives counter hits zero.
+    done_when: Option<DonePredicate>,
+
+    /// Frames run in the current episode.
+    frames: u64,
+}
+
+impl NesEnv {
+    /// The console is powered on by `reset()`.
+    pub fn new(cartridge: NesFile, options: EnvOptions) -> Self {
+        NesEnv {
+            nes: Nes::new(),
+            cartridge,
+            rng: SplitMix64(options.seed),
+            options,
+            done_when: None,
+            frames: 0,
+        }
+    }
+
+    /// End episodes once `done` returns true for the RAM after a frame.
+    pub fn done_when<F>(&mut self, done: F)
+    where
+        F: FnMut(&[u8]) -> bool + Send + 'static,
+    {
+        self.done_when = Some(Box::new(done));
+    }
+
+    /// Restart the sequence of episodes, the same seed gives the same episodes.
+    pub fn seed(&mut self, seed: u64) {
+        self.options.seed = seed;
+        self.rng = SplitMix64(seed);
+    }
+
+    /// Power cycle the console and run the frames without input starting the episode.
+    pub fn reset(&mut self) -> Step<'_> {
+        self.nes.insert_cartridge(self.cartridge.clone());
+        if let Some(cpu) = self.nes.cpu_mut() {
+            deterministic::apply(cpu, self.options.seed);
+        }
+        self.frames = 0;
+
+        let noops = self.rng.next() % (self.options.noop_max as u64 + 1);
+        let done = self.run(ControllerState(0), noops);
+        self.observe(done)
+    }
+
+    /// Hold the buttons of the first controller for `frame_skip` frames, or until the episode is
+    /// over.
+    pub fn step(&mut self, buttons: ControllerState) -> Step<'_> {
+        let done = self.run(buttons, self.options.frame_skip.max(1) as u64);
+        self.observe(done)
+    }
+
+    /// Frames run since the last reset.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    /// The console, e.g. to register observers. Without a cartridge until the first reset.
+    pub fn nes(&mut self) -> &mut Nes {
+        &mut self.nes
+    }
+
+    /// Run up to `frames` frames, returns whether the episode is over.
+    fn run(&mut self, buttons: ControllerState, frames: u64) -> bool {
+        self.nes.set_input(0, buttons);
+
+        let mut done = self.is_done();
+        for _ in 0..frames {
+            if done {
+                break;
+            }
+
+            self.nes.step_frame();
+            self.frames += 1;
+            done = self.is_done();
+        }
+
+        done
+    }
+
+    fn is_done(&mut self) -> bool {
+        if self
+            .options
+            .max_frames
+            .is_some_and(|max_frames| self.frames >= max_frames)
+        {
+            return true;
+        }
+
+        match &mut self.done_when {
+            Some(done_when) => done_when(ram(&self.nes)),
+            None => false,
+        }
+    }
+
+    fn observe(&self, done: bool) -> Step<'_> {
+        Step {
+            frame: self.nes.frame(),
+            ram: ram(&self.nes),
+            done,
+        }
+    }
+}
+
+/// The console's RAM, empty without a cartridge.
+fn ram(nes: &Nes) -> &[u8] {
+    match nes.cpu() {
+        Some(cpu) => &cpu.memory[..RAM_SIZE],
+        None => &[],
+    }
+}
+
+/// Small, fast generator, see https://prng.di.unimi.it/splitmix64.c.
+#[derive(Clone, Debug)]
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::test_util::nrom_file_at;
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    /// Copies the A button of the first controller to $00 in a loop, from $C010. $00 is $F1 while
+    /// it's held and $F0 otherwise, RAM holds anything before.
+    fn cartridge() -> NesFile {
+        let rom = nrom_file_at(
+            &[
+                0xA9, 0x01, 0x8D, 0x16, 0x40, // Strobe the controllers.
+                0xA9, 0x00, 0x8D, 0x16, 0x40, //
+                0xAD, 0x16, 0x40, 0x29, 0x01, // Read the A button.
+                0x09, 0xF0, 0x85, 0x00, // Store it to $00.
+                0x4C, 0x10, 0xC0, // Loop.
+            ],
+            0xC010,
+        );
+        NesFile::from_bytes(&rom).unwrap()
+    }
+
+    #[test]
+    fn test_env() {
+        let options = EnvOptions {
+            frame_skip: 4,
+            noop_max: 30,
+            max_frames: Some(200),
+            seed: 7,
+        };
+        let mut env = NesEnv::new(cartridge(), options.clone());
+        env.done_when(|ram| ram[0] == 0xF1);
+
+        let step = env.reset();
+        assert_eq!(step.frame.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+        assert_eq!(step.ram.len(), RAM_SIZE);
+        assert!(!step.done);
+        let noops = env.frame_count();
+        assert!(noops <= 30);
+
+        let step = env.step(ControllerState(0));
+        assert!(!step.done);
+        assert_eq!(env.frame_count(), noops + 4);
+
+        let step = env.step(ControllerState(ControllerState::A));
+        assert!(step.done);
+        assert_eq!(step.ram[0], 0xF1);
+        assert_eq!(env.frame_count(), noops + 5);
+
+        // The same seed gives the same episodes.
+        let mut other = NesEnv::new(cartridge(), options);
+        env.seed(7);
+        for _ in 0..3 {
+            env.reset();
+            other.reset();
+            assert_eq!(env.frame_count(), other.frame_count());
+        }
+
+        env.reset();
+        while !env.step(ControllerState(0)).done {}
+        assert_eq!(env.frame_count(), 200);
+    }
+
+    #[test]
+    fn test_ram_seed() {
+        let ram = |seed| {
+            let options = EnvOptions {
+                seed,
+                ..EnvOptions::default()
+            };
+            NesEnv::new(cartridge(), options).reset().ram[..0x100].to_vec()
+        };
+
+        // RAM at power on is random for the seed, the same on every run.
+        assert_eq!(ram(7), ram(7));
+        assert_ne!(ram(7), ram(8));
+        assert!(ram(7).iter().any(|&byte| byte != 0));
+    }
+}
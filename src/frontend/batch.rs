@@ -0,0 +1,253 @@
+/// Runs every ROM in a directory headless for a number of frames, spread over a pool of threads,
+/// and reports how far each one got: a quick look at which games the emulator can run.
+///
+/// The report is JSON, one entry per ROM sorted by path, so reports of different builds can be
+/// diffed.
+use super::hash_frames;
+use anyhow::{Context, Result};
+use nes::cpu::{Cpu, Stop};
+use nes::ines::NesFile;
+use serde::Serialize;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+use tracing::info;
+
+/// How a ROM's run ended.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// All the frames ran.
+    Ok,
+
+    /// The file couldn't be read or its header isn't supported.
+    LoadFailed,
+
+    /// The CPU reached an opcode that isn't implemented.
+    UnknownOpcode,
+
+    /// The emulator panicked, a bug to look into.
+    Panicked,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RomReport {
+    pub path: PathBuf,
+    pub status: Status,
+
+    /// What went wrong, unless it ran fine.
+    pub error: Option<String>,
+
+    /// Frames completed before stopping.
+    pub frames: u32,
+
+    /// Some frame wasn't a single colour, the game drew something.
+    pub rendered: bool,
+
+    /// See `hash_frames`, of the last complete frame.
+    pub last_frame_hash: String,
+
+    pub milliseconds: u128,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub ok: usize,
+    pub load_failed: usize,
+    pub unknown_opcode: usize,
+    pub panicked: usize,
+
+    /// ROMs that drew something, however they ended.
+    pub rendered: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Frames each ROM was given.
+    pub frames: u32,
+    pub summary: Summary,
+    pub roms: Vec<RomReport>,
+}
+
+/// The ".nes" files in `dir`, sorted.
+pub fn find_roms(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut roms = vec![];
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read \"{}\"", dir.display()))?
+    {
+        let path = entry?.path();
+        let is_rom = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("nes"));
+        if is_rom && path.is_file() {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms)
+}
+
+/// Run a ROM for `frames` frames.
+pub fn run_rom(path: &Path, frames: u32) -> RomReport {
+    let start = Instant::now();
+    let mut report = RomReport {
+        path: path.to_path_buf(),
+        status: Status::Ok,
+        error: None,
+        frames: 0,
+        rendered: false,
+        last_frame_hash: String::new(),
+        milliseconds: 0,
+    };
+
+    let nes_file = fs::read(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|rom| NesFile::from_bytes(&rom));
+    match nes_file {
+        Ok(nes_file) => {
+            // Panics are reported as a status instead of taking the whole batch down.
+            let run = panic::catch_unwind(AssertUnwindSafe(|| {
+                run_frames(Cpu::new(nes_file), frames, &mut report)
+            }));
+            if let Err(panic) = run {
+                report.status = Status::Panicked;
+                report.error = panic
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| panic.downcast_ref::<String>().cloned());
+            }
+        }
+        Err(err) => {
+            report.status = Status::LoadFailed;
+            report.error = Some(format!("{:#}", err));
+        }
+    }
+
+    report.milliseconds = start.elapsed().as_millis();
+    report
+}
+
+fn run_frames(mut cpu: Cpu, frames: u32, report: &mut RomReport) {
+    for _ in 0..frames {
+        if let Stop::UnknownOpcode(err) = cpu.run_frame() {
+            report.status = Status::UnknownOpcode;
+            report.error = Some(err.to_string());
+            break;
+        }
+
+        let frame = cpu.ppu.frame();
+        report.frames += 1;
+        report.rendered |= frame.iter().any(|&pixel| pixel != frame[0]);
+    }
+    report.last_frame_hash = format!("{:016x}", hash_frames::hash_frame(cpu.ppu.frame()));
+}
+
+/// Run each ROM on `jobs` threads, the reports are in the same order as `roms`.
+pub fn run_roms(roms: &[PathBuf], frames: u32, jobs: usize) -> Vec<RomReport> {
+    let next = AtomicUsize::new(0);
+    let reports = Mutex::new(vec![None; roms.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, roms.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = roms.get(index) else {
+                    break;
+                };
+
+                let report = run_rom(path, frames);
+                info!("{}: {:?}", path.display(), report.status);
+                reports.lock().unwrap()[index] = Some(report);
+            });
+        }
+    });
+
+    reports
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+impl Report {
+    pub fn new(frames: u32, roms: Vec<RomReport>) -> Self {
+        let mut summary = Summary::default();
+        for rom in &roms {
+            match rom.status {
+                Status::Ok => summary.ok += 1,
+                Status::LoadFailed => summary.load_failed += 1,
+                Status::UnknownOpcode => summary.unknown_opcode += 1,
+                Status::Panicked => summary.panicked += 1,
+            }
+            if rom.rendered {
+                summary.rendered += 1;
+            }
+        }
+
+        Report {
+            frames,
+            summary,
+            roms,
+        }
+    }
+}
+
+/// Run the ROMs in `dir` and write the report to `output`, or stdout.
+pub fn run(dir: &Path, frames: u32, jobs: usize, output: Option<&str>) -> Result<()> {
+    let roms = find_roms(dir)?;
+    info!("Running {} ROMs on {} threads", roms.len(), jobs);
+
+    let report = Report::new(frames, run_roms(&roms, frames, jobs));
+    let json = serde_json::to_string_pretty(&report)?;
+    match output {
+        Some(path) => {
+            fs::write(path, json + "\n").with_context(|| format!("Failed to write \"{}\"", path))?
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::ines::test_util::nrom_file;
+
+    #[test]
+    fn test_batch() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("nes-batch-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+
+        // Two NOPs from the reset vector and then a JAM opcode.
+        fs::write(dir.join("jam.nes"), nrom_file(&[0xEA, 0xEA, 0x02]))?;
+        fs::write(dir.join("broken.nes"), b"NES\x1A")?;
+        fs::write(dir.join("notes.txt"), b"")?;
+
+        let roms = find_roms(&dir)?;
+        assert_eq!(roms, vec![dir.join("broken.nes"), dir.join("jam.nes")]);
+
+        let report = Report::new(2, run_roms(&roms, 2, 4));
+        fs::remove_dir_all(&dir)?;
+
+        assert_eq!(report.roms[0].status, Status::LoadFailed);
+        assert_eq!(report.roms[1].status, Status::UnknownOpcode);
+        assert!(report.roms[1].error.as_ref().unwrap().contains("C002"));
+        assert_eq!(
+            report.summary,
+            Summary {
+                load_failed: 1,
+                unknown_opcode: 1,
+                ..Summary::default()
+            }
+        );
+
+        let json = serde_json::to_value(&report)?;
+        assert_eq!(json["roms"][1]["status"], "unknown_opcode");
+        Ok(())
+    }
+}
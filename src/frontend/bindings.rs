@@ -0,0 +1,132 @@
+/// Maps keys onto the buttons of each player's controller.
+///
+/// Keys are identified by name so the bindings don't depend on the windowing library, e.g. the
+/// window uses the names of winit's virtual key codes.
+use anyhow::{anyhow, Result};
+use nes::config::{ButtonBindings, InputConfig};
+use nes::controller::BUTTONS;
+use std::collections::HashMap;
+
+/// Default keys of each player, in the order of `BUTTONS`.
+const DEFAULT_KEYS: [[&str; 8]; 2] = [
+    ["X", "Z", "RShift", "Return", "Up", "Down", "Left", "Right"],
+    ["O", "U", "Key7", "Key8", "I", "K", "J", "L"],
+];
+
+/// Names of winit's virtual key codes, the keys that can be bound, separated by spaces.
+const KEY_NAMES: &str =
+    "Key1 Key2 Key3 Key4 Key5 Key6 Key7 Key8 Key9 Key0 A B C D E F G H I J K L M N O P Q R S T \
+    U V W X Y Z Escape F1 F2 F3 F4 F5 F6 F7 F8 F9 F10 F11 F12 F13 F14 F15 F16 F17 F18 F19 F20 \
+    F21 F22 F23 F24 Snapshot Scroll Pause Insert Home Delete End PageDown PageUp Left Up \
+    Right Down Back Return Space Compose Caret Numlock Numpad0 Numpad1 Numpad2 Numpad3 \
+    Numpad4 Numpad5 Numpad6 Numpad7 Numpad8 Numpad9 NumpadAdd NumpadDivide NumpadDecimal \
+    NumpadComma NumpadEnter NumpadEquals NumpadMultiply NumpadSubtract AbntC1 AbntC2 \
+    Apostrophe Apps Asterisk At Ax Backslash Calculator Capital Colon Comma Convert Equals \
+    Grave Kana Kanji LAlt LBracket LControl LShift LWin Mail MediaSelect MediaStop Minus Mute \
+    MyComputer NavigateForward NavigateBackward NextTrack NoConvert OEM102 Period PlayPause \
+    Plus Power PrevTrack RAlt RBracket RControl RShift RWin Semicolon Slash Sleep Stop Sysrq \
+    Tab Underline Unlabeled VolumeDown VolumeUp Wake WebBack WebFavorites WebForward WebHome \
+    WebRefresh WebSearch WebStop Yen Copy Paste Cut";
+
+pub struct KeyBindings {
+    /// Player and button bound to each key.
+    keys: HashMap<String, (usize, u8)>,
+}
+
+impl KeyBindings {
+    /// Bindings from the config, buttons left out keep their default key. Fails on keys winit
+    /// doesn't name and on keys bound to two buttons, including a default one.
+    pub fn new(config: &InputConfig) -> Result<Self> {
+        let mut keys = HashMap::new();
+
+        for (player, bindings) in [&config.player1, &config.player2].iter().enumerate() {
+            for (i, &(name, button)) in BUTTONS.iter().enumerate() {
+                let key = configured_key(bindings, name).unwrap_or(DEFAULT_KEYS[player][i]);
+                if !KEY_NAMES.split(' ').any(|known| known == key) {
+                    return Err(anyhow!(
+                        "Unknown key \"{}\" for player {}'s {}, keys are named like winit's virtual key codes, e.g. \"Space\" or \"Key1\".",
+                        key,
+                        player + 1,
+                        name
+                    ));
+                }
+
+                if let Some((other_player, other_button)) =
+                    keys.insert(key.to_string(), (player, button))
+                {
+                    return Err(anyhow!(
+                        "Key \"{}\" is bound to both player {}'s {} and player {}'s {}.",
+                        key,
+                        other_player + 1,
+                        button_name(other_button),
+                        player + 1,
+                        name
+                    ));
+                }
+            }
+        }
+
+        Ok(KeyBindings { keys })
+    }
+
+    /// Player and button bound to the key.
+    pub fn button(&self, key: &str) -> Option<(usize, u8)> {
+        self.keys.get(key).copied()
+    }
+}
+
+fn button_name(button: u8) -> &'static str {
+    BUTTONS
+        .iter()
+        .find(|&&(_, other)| other == button)
+        .map_or("", |&(name, _)| name)
+}
+
+fn configured_key<'a>(bindings: &'a ButtonBindings, name: &str) -> Option<&'a str> {
+    let key = match name {
+        "a" => &bindings.a,
+        "b" => &bindings.b,
+        "select" => &bindings.select,
+        "start" => &bindings.start,
+        "up" => &bindings.up,
+        "down" => &bindings.down,
+        "left" => &bindings.left,
+        "right" => &bindings.right,
+        _ => &None,
+    };
+
+    key.as_deref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::controller::ControllerState;
+
+    #[test]
+    fn test_bindings() -> Result<()> {
+        let bindings = KeyBindings::new(&InputConfig::default())?;
+        assert_eq!(bindings.button("X"), Some((0, ControllerState::A)));
+        assert_eq!(bindings.button("L"), Some((1, ControllerState::RIGHT)));
+        assert_eq!(bindings.button("Q"), None);
+
+        let mut config = InputConfig::default();
+        config.player1.a = Some("Space".to_string());
+        let bindings = KeyBindings::new(&config)?;
+        assert_eq!(bindings.button("Space"), Some((0, ControllerState::A)));
+        assert_eq!(bindings.button("X"), None);
+        assert_eq!(bindings.button("Z"), Some((0, ControllerState::B)));
+
+        // Player 2's A is on O by default.
+        config.player1.a = Some("O".to_string());
+        let error = KeyBindings::new(&config).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Key \"O\" is bound to both player 1's a and player 2's a."
+        );
+
+        config.player1.a = Some("Spacebar".to_string());
+        assert!(KeyBindings::new(&config).is_err());
+        Ok(())
+    }
+}
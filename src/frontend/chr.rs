@@ -0,0 +1,141 @@
+/// Exports CHR as a PNG tile sheet, for ROM hacking and for checking what's in the pattern tables:
+/// the CHR ROM of a file, or the CHR RAM in a save state.
+use anyhow::{anyhow, Context, Result};
+use nes::cpu::Cpu;
+use nes::ines::HeaderInfo;
+use nes::ppu::{self, PALETTES, PATTERN_TABLE_SIZE};
+use nes::video::image;
+use std::convert::TryInto;
+use std::str::FromStr;
+
+/// Black, dark grey, light grey and white.
+const GREYSCALE: [u8; 4] = [0x0F, 0x00, 0x10, 0x30];
+
+/// The colours tiles are drawn with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Palette {
+    /// Four colours of the NES palette.
+    Colours([u8; 4]),
+
+    /// One of the PPU's eight palettes, the first four for the background.
+    Ppu(u8),
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::Colours(GREYSCALE)
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Either a palette number like "4" or four colours like "0F,16,27,18".
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(palette) = value.parse::<u8>() {
+            if palette < PALETTES {
+                return Ok(Palette::Ppu(palette));
+            }
+            return Err(format!(
+                "Invalid palette {}, palettes go from 0 to {}.",
+                palette,
+                PALETTES - 1
+            ));
+        }
+
+        let colours = value
+            .split(',')
+            .map(|colour| match u8::from_str_radix(colour.trim(), 16) {
+                Ok(colour) if colour < 0x40 => Ok(colour),
+                _ => Err(format!("Invalid colour \"{}\", from 00 to 3F.", colour)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let colours = colours
+            .try_into()
+            .map_err(|_| format!("Expected 4 colours in \"{}\".", value))?;
+        Ok(Palette::Colours(colours))
+    }
+}
+
+impl Palette {
+    /// The colours, reading the PPU's palettes from `cpu`.
+    fn colours(&self, cpu: Option<&Cpu>) -> Result<[u8; 4]> {
+        match (*self, cpu) {
+            (Palette::Colours(colours), _) => Ok(colours),
+            (Palette::Ppu(palette), Some(cpu)) => {
+                let start = palette as usize * 4;
+                let mut colours = [0; 4];
+                colours.copy_from_slice(&cpu.ppu.palette_ram()[start..start + 4]);
+                Ok(colours)
+            }
+            (Palette::Ppu(_), None) => Err(anyhow!(
+                "The PPU's palettes are only set in a save state, pass colours instead."
+            )),
+        }
+    }
+}
+
+/// The CHR ROM of an iNES file, shorter if the file is truncated.
+pub fn chr_rom(file: &[u8]) -> Result<&[u8]> {
+    let header = HeaderInfo::parse(file)?;
+    let rom = header.rom_data(file);
+    let chr_rom = &rom[header.prg_rom_size.min(rom.len())..];
+    if chr_rom.is_empty() {
+        return Err(anyhow!(
+            "The ROM has CHR RAM rather than CHR ROM, export it from a save state."
+        ));
+    }
+
+    Ok(chr_rom)
+}
+
+/// Save the ROM's CHR ROM, or the CHR the PPU of `cpu` sees, as a PNG.
+pub fn save(file: &[u8], cpu: Option<&Cpu>, palette: Palette, path: &str) -> Result<()> {
+    let chr = match cpu {
+        Some(cpu) => cpu.ppu.chr(),
+        None => chr_rom(file)?,
+    };
+    let image = ppu::render_chr(chr, palette.colours(cpu)?);
+
+    image::save_png(
+        path,
+        PATTERN_TABLE_SIZE,
+        image.len() / PATTERN_TABLE_SIZE,
+        &image,
+    )
+    .with_context(|| format!("Failed to write \"{}\"", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palette() {
+        assert_eq!("5".parse(), Ok(Palette::Ppu(5)));
+        assert_eq!(
+            "0F,16, 27,18".parse(),
+            Ok(Palette::Colours([0x0F, 0x16, 0x27, 0x18]))
+        );
+        assert!("8".parse::<Palette>().is_err());
+        assert!("0F,16,27".parse::<Palette>().is_err());
+        assert!("0F,16,27,40".parse::<Palette>().is_err());
+
+        assert_eq!(Palette::default().colours(None).unwrap(), GREYSCALE);
+        assert!(Palette::Ppu(0).colours(None).is_err());
+    }
+
+    #[test]
+    fn test_chr_rom() -> Result<()> {
+        let mut rom = Vec::from(&b"NES\x1A\x01\x01"[..]);
+        rom.resize(16 + 0x4000, 0);
+        rom.resize(16 + 0x6000, 0xFF);
+        assert_eq!(chr_rom(&rom)?, &[0xFF; 0x2000][..]);
+
+        // CHR RAM.
+        rom[5] = 0;
+        rom.truncate(16 + 0x4000);
+        assert!(chr_rom(&rom).is_err());
+        Ok(())
+    }
+}
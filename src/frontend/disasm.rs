@@ -0,0 +1,179 @@
+/// Disassembles a ROM without running it: its PRG ROM bank by bank, or a range of addresses as
+/// the CPU sees them at power on or in a save state.
+///
+/// Lines are formatted like the debugger's `disasm` and the trace log, with a "label:" line
+/// before each labelled address.
+use anyhow::{anyhow, Result};
+use nes::debugger::Symbols;
+use nes::disasm::{self, Line};
+use nes::ines::HeaderInfo;
+use std::io::{self, Write};
+
+/// Size of a PRG ROM bank, as the header counts them.
+pub const BANK_SIZE: usize = 0x4000;
+
+/// Names given to the addresses in the vectors at the end of the address space.
+const VECTORS: [(u16, &str); 3] = [(0xFFFA, "nmi"), (0xFFFC, "reset"), (0xFFFE, "irq")];
+
+/// The PRG ROM of an iNES file split in banks, the last one may be short in a truncated file.
+pub fn prg_banks(file: &[u8]) -> Result<Vec<&[u8]>> {
+    let header = HeaderInfo::parse(file)?;
+    let rom = header.rom_data(file);
+    let prg_rom = &rom[..header.prg_rom_size.min(rom.len())];
+    if prg_rom.is_empty() {
+        return Err(anyhow!("The ROM has no PRG ROM."));
+    }
+
+    Ok(prg_rom.chunks(BANK_SIZE).collect())
+}
+
+/// Where a bank is usually mapped: the last bank is fixed at $C000, the others are switched in at
+/// $8000.
+pub fn bank_origin(bank: usize, banks: usize) -> u16 {
+    if bank + 1 == banks {
+        0xC000
+    } else {
+        0x8000
+    }
+}
+
+/// Name the targets of the NMI, reset and IRQ vectors, unless they already have a name.
+pub fn add_vector_labels(symbols: &mut Symbols, read: impl Fn(u16) -> u8) {
+    for &(vector, name) in &VECTORS {
+        let target = u16::from_le_bytes([read(vector), read(vector.wrapping_add(1))]);
+        symbols.add(target, name);
+    }
+}
+
+/// Name the vector targets of a ROM, read from the end of its last bank.
+pub fn add_rom_vector_labels(symbols: &mut Symbols, file: &[u8]) -> Result<()> {
+    let banks = prg_banks(file)?;
+    let last = banks[banks.len() - 1];
+    let origin = 0u16.wrapping_sub(last.len() as u16);
+    add_vector_labels(symbols, |address| {
+        last.get(address.wrapping_sub(origin) as usize)
+            .copied()
+            .unwrap_or(0)
+    });
+    Ok(())
+}
+
+/// Decode from `start` up to the instruction containing `end`, without wrapping around the
+/// address space.
+pub fn disassemble_range(read: impl Fn(u16) -> u8, start: u16, end: u16) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut address = start as u32;
+    while address <= end as u32 {
+        let line = disasm::decode(&read, address as u16);
+        address += line.bytes.len() as u32;
+        lines.push(line);
+    }
+    lines
+}
+
+/// Write the listing, naming addresses and operands with `symbols`.
+pub fn write_listing(out: &mut impl Write, lines: &[Line], symbols: &Symbols) -> io::Result<()> {
+    let label = |address| symbols.label(address);
+    for line in lines {
+        if let Some(label) = label(line.address) {
+            writeln!(out, "{}:", label)?;
+        }
+        writeln!(out, "{}", line.listing(label))?;
+    }
+    Ok(())
+}
+
+/// Write the listing of each of `banks`, all of them if empty, under a header per bank. `origin`
+/// overrides where the banks are mapped.
+pub fn write_banks(
+    out: &mut impl Write,
+    file: &[u8],
+    banks: &[usize],
+    origin: Option<u16>,
+    symbols: &Symbols,
+) -> Result<()> {
+    let prg_banks = prg_banks(file)?;
+    let selected: Vec<usize> = if banks.is_empty() {
+        (0..prg_banks.len()).collect()
+    } else {
+        banks.to_vec()
+    };
+
+    for (i, &bank) in selected.iter().enumerate() {
+        let bytes = prg_banks.get(bank).ok_or_else(|| {
+            anyhow!(
+                "Invalid bank {}, the ROM has {} banks of PRG ROM.",
+                bank,
+                prg_banks.len()
+            )
+        })?;
+        let origin = origin.unwrap_or_else(|| bank_origin(bank, prg_banks.len()));
+
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "; Bank {} at ${:04X}", bank, origin)?;
+        write_listing(out, &disasm::disassemble_bytes(bytes, origin), symbols)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An NROM-256 ROM: a loop at the start of bank 0, the reset vector pointing at $C000 where
+    /// bank 1 starts with "JMP $8000".
+    fn rom() -> Vec<u8> {
+        let mut rom = Vec::from(&b"NES\x1A\x02\x00"[..]);
+        rom.resize(16, 0);
+
+        let mut prg_rom = vec![0xEA; 2 * BANK_SIZE];
+        prg_rom[..2].copy_from_slice(&[0xD0, 0xFE]);
+        prg_rom[BANK_SIZE..BANK_SIZE + 3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg_rom[2 * BANK_SIZE - 4..2 * BANK_SIZE - 2].copy_from_slice(&[0x00, 0xC0]);
+        rom.extend_from_slice(&prg_rom);
+        rom
+    }
+
+    #[test]
+    fn test_banks() -> Result<()> {
+        let rom = rom();
+        let banks = prg_banks(&rom)?;
+        assert_eq!(banks.len(), 2);
+        assert_eq!((bank_origin(0, 2), bank_origin(1, 2)), (0x8000, 0xC000));
+
+        let mut symbols = Symbols::default();
+        symbols.add(0x8000, "main");
+        add_vector_labels(&mut symbols, |address| {
+            banks[1][address.wrapping_sub(0xC000) as usize]
+        });
+        assert_eq!(symbols.label(0xC000), Some("reset"));
+
+        let mut out = vec![];
+        write_banks(&mut out, &rom, &[1, 0], None, &symbols)?;
+        let listing = String::from_utf8(out)?;
+        assert!(listing.starts_with("; Bank 1 at $C000\nreset:\nC000  4C 00 80  JMP main\n"));
+        assert!(listing.contains("\n\n; Bank 0 at $8000\nmain:\n8000  D0 FE     BNE main\n"));
+
+        assert!(write_banks(&mut vec![], &rom, &[2], None, &symbols).is_err());
+        assert!(prg_banks(b"NES\x1A").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_disassemble_range() {
+        let code = [0xA9, 0x10, 0x4C, 0x00, 0xC0, 0xEA];
+        let read = |address: u16| code[address.wrapping_sub(0xC000) as usize % code.len()];
+
+        let addresses = |start, end| -> Vec<u16> {
+            disassemble_range(read, start, end)
+                .iter()
+                .map(|line| line.address)
+                .collect()
+        };
+        assert_eq!(addresses(0xC000, 0xC000), vec![0xC000]);
+        assert_eq!(addresses(0xC000, 0xC003), vec![0xC000, 0xC002]);
+        assert_eq!(addresses(0xC000, 0xC005), vec![0xC000, 0xC002, 0xC005]);
+    }
+}
@@ -0,0 +1,91 @@
+/// Hashes each frame while running headless, to catch rendering regressions without keeping
+/// screenshots: store the output of a known good build and diff it against later ones.
+///
+/// The hashes are FNV-1a (64 bit) of the palette indices the PPU outputs, so they don't depend on
+/// the video filter.
+use nes::cpu::{Cpu, Stop};
+use nes::opcode::DecodeError;
+
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// FNV-1a, continuing from `hash`.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Hash of a single frame.
+pub fn hash_frame(frame: &[u8]) -> u64 {
+    fnv1a(FNV_OFFSET_BASIS, frame)
+}
+
+/// Run a frame, there's nothing to hash once the CPU fails.
+fn run_frame(cpu: &mut Cpu) -> Result<&[u8], DecodeError> {
+    match cpu.run_frame() {
+        Stop::UnknownOpcode(err) => Err(err),
+        _ => Ok(cpu.ppu.frame()),
+    }
+}
+
+/// Run `frames` frames, returning the hash of each one.
+pub fn hash_frames(cpu: &mut Cpu, frames: u32) -> Result<Vec<u64>, DecodeError> {
+    (0..frames)
+        .map(|_| run_frame(cpu).map(hash_frame))
+        .collect()
+}
+
+/// Hash of every frame one after the other, any change to any of them changes it.
+pub fn combined_hash(cpu: &mut Cpu, frames: u32) -> Result<u64, DecodeError> {
+    (0..frames).try_fold(FNV_OFFSET_BASIS, |hash, _| Ok(fnv1a(hash, run_frame(cpu)?)))
+}
+
+/// Print "<frame> <hash>" for each frame, or only the combined hash if `combined`.
+pub fn run(mut cpu: Cpu, frames: u32, combined: bool) -> Result<(), DecodeError> {
+    if combined {
+        println!("{:016x}", combined_hash(&mut cpu, frames)?);
+        return Ok(());
+    }
+
+    for (frame, hash) in hash_frames(&mut cpu, frames)?.iter().enumerate() {
+        println!("{} {:016x}", frame + 1, hash);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::ines::test_util::spinning_nrom;
+    use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(hash_frame(b""), FNV_OFFSET_BASIS);
+        assert_eq!(hash_frame(b"a"), 0xAF63_DC4C_8601_EC8C);
+        assert_eq!(hash_frame(b"foobar"), 0x8594_4171_F739_67E8);
+
+        let mut frame = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let hash = hash_frame(&frame);
+        frame[SCREEN_WIDTH * 100 + 50] = 0x21;
+        assert_ne!(hash_frame(&frame), hash);
+    }
+
+    #[test]
+    fn test_hash_frames() -> Result<(), DecodeError> {
+        let hashes = hash_frames(&mut Cpu::new(spinning_nrom()), 4)?;
+        assert_eq!(hashes, hash_frames(&mut Cpu::new(spinning_nrom()), 4)?);
+        assert_eq!(hashes.len(), 4);
+
+        let combined = combined_hash(&mut Cpu::new(spinning_nrom()), 4)?;
+        let mut cpu = Cpu::new(spinning_nrom());
+        let mut expected = FNV_OFFSET_BASIS;
+        for _ in 0..4 {
+            cpu.run_frame();
+            expected = fnv1a(expected, cpu.ppu.frame());
+        }
+        assert_eq!(combined, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+/// Runs the console as fast as possible without a window or an audio device.
+///
+/// Output is only available through the registered callbacks, e.g. an audio dump, and the files
+/// written once running stops.
+use nes::battery::BatterySave;
+use nes::cpu::Cpu;
+use nes::debugger::{self, Resume};
+use nes::movie::MovieSession;
+use nes::netplay::Session;
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes::stats::Stats;
+use nes::video::{image, VideoFilter};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::time::Instant;
+use tracing::{debug, error, info};
+
+/// Size of the console's internal RAM, mirrored up to $1FFF.
+const RAM_SIZE: usize = 0x800;
+
+/// Files written once running stops, for scripts comparing runs.
+#[derive(Clone, Debug, Default)]
+pub struct Dumps {
+    /// The internal RAM, $0000 to $07FF.
+    pub ram: Option<String>,
+
+    /// The last frame as a PNG.
+    pub screenshot: Option<String>,
+
+    /// The next instruction, the registers and the zero page, as printed after a trap.
+    pub state: Option<String>,
+}
+
+impl Dumps {
+    pub fn write(&self, cpu: &Cpu) -> Result<()> {
+        if let Some(path) = &self.ram {
+            fs::write(path, cpu.peek_range(0, RAM_SIZE))
+                .with_context(|| format!("Failed to write \"{}\"", path))?;
+        }
+        if let Some(path) = &self.screenshot {
+            image::save_png(path, SCREEN_WIDTH, SCREEN_HEIGHT, cpu.ppu.frame())
+                .with_context(|| format!("Failed to write \"{}\"", path))?;
+        }
+        if let Some(path) = &self.state {
+            let mut out = BufWriter::new(
+                File::create(path).with_context(|| format!("Failed to create \"{}\"", path))?,
+            );
+            debugger::print_state(cpu, &mut out)
+                .and_then(|()| out.flush())
+                .with_context(|| format!("Failed to write \"{}\"", path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Run until `frames` frames have run, forever without a limit, logging each completed frame.
+/// When playing a movie, stop once it has finished. `cpu.cycle_limit` stops it too, and so does
+/// losing the other player in netplay.
+///
+/// Breakpoints enter the debugger, quitting it stops running. With `cpu.detect_traps` a trap stops
+/// running too, the state is printed and true returned. The dumps and the battery save are written
+/// however it stopped.
+pub fn run(
+    mut cpu: Cpu,
+    mut video_filter: VideoFilter,
+    mut movie: Option<MovieSession>,
+    mut netplay: Option<Session>,
+    mut battery: Option<BatterySave>,
+    frames: Option<u32>,
+    dumps: &Dumps,
+) -> Result<bool> {
+    cpu.ppu.on_frame_complete(move |frame| {
+        let (width, height) = video_filter.output_size();
+        let picture = video_filter.apply(frame);
+
+        debug!(
+            "Frame complete, {}x{} ({} bytes).",
+            width,
+            height,
+            picture.len()
+        );
+    });
+
+    let mut stats = Stats::new(cpu.region().frame_rate());
+    let mut frame = 0;
+    let resume = loop {
+        if frames.is_some_and(|frames| frame >= frames) {
+            break Resume::Continue;
+        }
+        if let Some(movie) = &mut movie {
+            if movie.is_finished() {
+                break Resume::Continue;
+            }
+            movie.before_frame(&mut cpu.controllers);
+        }
+        if let Some(netplay) = &mut netplay {
+            if let Err(err) = netplay.before_frame(&mut cpu) {
+                error!("Netplay stopped: {:#}", err);
+                break Resume::Continue;
+            }
+        }
+
+        let started = Instant::now();
+        let resume = debugger::run_frame(&mut cpu);
+        if resume != Resume::Continue {
+            break resume;
+        }
+        stats.record_frame(started.elapsed());
+        if let Some(netplay) = &netplay {
+            netplay.after_frame(&mut cpu);
+        }
+        frame += 1;
+
+        if let Some(battery) = &mut battery {
+            if let Err(err) = battery.after_frame(&cpu) {
+                error!("Failed to save the battery RAM: {:#}", err);
+            }
+        }
+    };
+
+    debugger::finish(&mut cpu);
+    if let Some(battery) = &mut battery {
+        battery.flush(&cpu)?;
+    }
+    info!("Stopped after {} frames, {} cycles", frame, cpu.cycles);
+    let report = stats.report();
+    info!(
+        "Ran at {:.1} fps on average, lately {}",
+        report.average_fps, report
+    );
+    dumps.write(&cpu)?;
+
+    if resume != Resume::Trapped {
+        return Ok(false);
+    }
+
+    info!("Trapped at ${:04X}", cpu.program_counter);
+    if let Err(err) = debugger::print_state(&cpu, &mut io::stdout()) {
+        debug!("Failed to print the state: {}", err);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::ines::{test_util, NesFile};
+
+    #[test]
+    fn test_reset_vector() -> Result<()> {
+        // LDA #$02, STA $0300, JMP $C015 from $C010, $C000 is BRK.
+        let rom =
+            test_util::nrom_file_at(&[0xA9, 0x02, 0x8D, 0x00, 0x03, 0x4C, 0x15, 0xC0], 0xC010);
+        let cpu = Cpu::new(NesFile::from_bytes(&rom)?);
+
+        let path = std::env::temp_dir().join(format!("nes-headless-{}.ram", std::process::id()));
+        let dumps = Dumps {
+            ram: Some(path.to_string_lossy().into_owned()),
+            ..Dumps::default()
+        };
+        assert!(!run(
+            cpu,
+            VideoFilter::Rgb,
+            None,
+            None,
+            None,
+            Some(1),
+            &dumps
+        )?);
+
+        let ram = fs::read(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(ram[0x0300], 0x02);
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+/// Maps keys onto the window's own actions, the ones that aren't a controller's buttons.
+///
+/// Keys are named like in `KeyBindings`, and can be changed in the config's `[input.hotkeys]`.
+use nes::apu::Channel;
+use nes::config::HotkeyBindings;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Hotkey {
+    /// Fast forward while held.
+    FastForward,
+
+    /// Toggle fast forwarding.
+    FastForwardToggle,
+
+    /// Step through the preset speeds.
+    Slower,
+    Faster,
+
+    /// Back to the console's speed.
+    NormalSpeed,
+
+    /// Toggle pausing the emulation.
+    Pause,
+
+    /// Run exactly one frame while paused.
+    FrameAdvance,
+
+    /// Save and load the state in the selected slot.
+    SaveState,
+    LoadState,
+
+    /// Select the next save state slot.
+    NextSlot,
+
+    /// Rewind while held.
+    Rewind,
+
+    /// Switch the trace log on or off.
+    Trace,
+
+    /// Save the captured frames as a GIF.
+    GifCapture,
+
+    /// Save the last frame as a PNG.
+    Screenshot,
+
+    /// Show or hide the performance statistics in the title.
+    Stats,
+
+    /// Switch to the next palette.
+    NextPalette,
+
+    /// Show or hide the buttons held on each controller.
+    InputDisplay,
+
+    /// Drop a coin in the VS System's first or second slot.
+    Coin1,
+    Coin2,
+
+    /// The VS System's service button, adds a credit while held.
+    Service,
+
+    /// Mute or unmute a sound channel.
+    Mute(Channel),
+
+    /// Only mix the next channel, then all of them again after the last.
+    NextSolo,
+}
+
+/// Every hotkey with its default key.
+const DEFAULT_KEYS: [(Hotkey, &str); 26] = [
+    (Hotkey::FastForward, "Tab"),
+    (Hotkey::FastForwardToggle, "Grave"),
+    (Hotkey::Slower, "Minus"),
+    (Hotkey::Faster, "Equals"),
+    (Hotkey::NormalSpeed, "Key0"),
+    (Hotkey::Pause, "P"),
+    (Hotkey::FrameAdvance, "Backslash"),
+    (Hotkey::SaveState, "F5"),
+    (Hotkey::LoadState, "F7"),
+    (Hotkey::NextSlot, "F6"),
+    (Hotkey::Rewind, "Back"),
+    (Hotkey::Trace, "F8"),
+    (Hotkey::GifCapture, "F9"),
+    (Hotkey::Screenshot, "F12"),
+    (Hotkey::Stats, "F10"),
+    (Hotkey::NextPalette, "F3"),
+    (Hotkey::InputDisplay, "F4"),
+    (Hotkey::Coin1, "Key5"),
+    (Hotkey::Coin2, "Key6"),
+    (Hotkey::Service, "Key9"),
+    (Hotkey::Mute(Channel::Pulse1), "Numpad1"),
+    (Hotkey::Mute(Channel::Pulse2), "Numpad2"),
+    (Hotkey::Mute(Channel::Triangle), "Numpad3"),
+    (Hotkey::Mute(Channel::Noise), "Numpad4"),
+    (Hotkey::Mute(Channel::Dmc), "Numpad5"),
+    (Hotkey::NextSolo, "Numpad0"),
+];
+
+pub struct Hotkeys {
+    /// Hotkey bound to each key.
+    keys: HashMap<String, Hotkey>,
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Hotkeys::new(&HotkeyBindings::default())
+    }
+}
+
+impl Hotkeys {
+    /// Bindings from the config, hotkeys left out keep their default key unless another hotkey
+    /// was bound to it.
+    pub fn new(config: &HotkeyBindings) -> Self {
+        let mut keys = HashMap::new();
+
+        for &(hotkey, default_key) in &DEFAULT_KEYS {
+            if configured_key(config, hotkey).is_none() {
+                keys.insert(default_key.to_string(), hotkey);
+            }
+        }
+        for &(hotkey, _) in &DEFAULT_KEYS {
+            if let Some(key) = configured_key(config, hotkey) {
+                keys.insert(key.to_string(), hotkey);
+            }
+        }
+
+        Hotkeys { keys }
+    }
+
+    /// Hotkey bound to the key.
+    pub fn hotkey(&self, key: &str) -> Option<Hotkey> {
+        self.keys.get(key).copied()
+    }
+}
+
+fn configured_key(bindings: &HotkeyBindings, hotkey: Hotkey) -> Option<&str> {
+    let key = match hotkey {
+        Hotkey::FastForward => &bindings.fast_forward,
+        Hotkey::FastForwardToggle => &bindings.fast_forward_toggle,
+        Hotkey::Slower => &bindings.slower,
+        Hotkey::Faster => &bindings.faster,
+        Hotkey::NormalSpeed => &bindings.normal_speed,
+        Hotkey::Pause => &bindings.pause,
+        Hotkey::FrameAdvance => &bindings.frame_advance,
+        Hotkey::SaveState => &bindings.save_state,
+        Hotkey::LoadState => &bindings.load_state,
+        Hotkey::NextSlot => &bindings.next_slot,
+        Hotkey::Rewind => &bindings.rewind,
+        Hotkey::Trace => &bindings.trace,
+        Hotkey::GifCapture => &bindings.gif_capture,
+        Hotkey::Screenshot => &bindings.screenshot,
+        Hotkey::Stats => &bindings.stats,
+        Hotkey::NextPalette => &bindings.next_palette,
+        Hotkey::InputDisplay => &bindings.input_display,
+        Hotkey::Coin1 => &bindings.coin_1,
+        Hotkey::Coin2 => &bindings.coin_2,
+        Hotkey::Service => &bindings.service,
+        Hotkey::Mute(Channel::Pulse1) => &bindings.mute_pulse1,
+        Hotkey::Mute(Channel::Pulse2) => &bindings.mute_pulse2,
+        Hotkey::Mute(Channel::Triangle) => &bindings.mute_triangle,
+        Hotkey::Mute(Channel::Noise) => &bindings.mute_noise,
+        Hotkey::Mute(Channel::Dmc) => &bindings.mute_dmc,
+        Hotkey::NextSolo => &bindings.next_solo,
+    };
+
+    key.as_deref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hotkeys() {
+        let hotkeys = Hotkeys::default();
+        assert_eq!(hotkeys.hotkey("F5"), Some(Hotkey::SaveState));
+        assert_eq!(hotkeys.hotkey("Back"), Some(Hotkey::Rewind));
+        assert_eq!(hotkeys.hotkey("Q"), None);
+        assert_eq!(
+            hotkeys.hotkey("Numpad3"),
+            Some(Hotkey::Mute(Channel::Triangle))
+        );
+
+        // Moving save to F7 takes it from loading, which moves to F1.
+        let config = HotkeyBindings {
+            save_state: Some("F7".to_string()),
+            load_state: Some("F1".to_string()),
+            pause: Some("Space".to_string()),
+            ..Default::default()
+        };
+        let hotkeys = Hotkeys::new(&config);
+        assert_eq!(hotkeys.hotkey("F7"), Some(Hotkey::SaveState));
+        assert_eq!(hotkeys.hotkey("F1"), Some(Hotkey::LoadState));
+        assert_eq!(hotkeys.hotkey("F5"), None);
+        assert_eq!(hotkeys.hotkey("Space"), Some(Hotkey::Pause));
+        assert_eq!(hotkeys.hotkey("P"), None);
+        assert_eq!(hotkeys.hotkey("F6"), Some(Hotkey::NextSlot));
+    }
+}
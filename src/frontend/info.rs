@@ -0,0 +1,284 @@
+/// Describes a ROM without running it: what its header says, its checksums and, given a DAT file
+/// from No-Intro or a similar project, the game it is.
+///
+/// DAT files identify NES games by the CRC32 of their PRG and CHR ROM, without the header. Both the
+/// XML (Logiqx) and the clrmamepro formats are read.
+use anyhow::{Context, Result};
+use nes::ines::{self, HeaderInfo, NesFile};
+use nes::region::Region;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+
+/// Game names by the CRC32 of their ROM.
+#[derive(Debug, Default, PartialEq)]
+pub struct Database {
+    games: HashMap<u32, String>,
+}
+
+impl Database {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("Failed to read \"{}\"", path))?;
+        Ok(Self::parse(&contents))
+    }
+
+    /// Entries that can't be read are skipped.
+    pub fn parse(contents: &str) -> Self {
+        let games = if contents.trim_start().starts_with('<') {
+            parse_xml(contents)
+        } else {
+            parse_clrmamepro(contents)
+        };
+
+        Database {
+            games: games.into_iter().collect(),
+        }
+    }
+
+    pub fn find(&self, crc32: u32) -> Option<&str> {
+        self.games.get(&crc32).map(String::as_str)
+    }
+}
+
+/// `<game name="..."> <rom ... crc="..."/> </game>`, one name per ROM.
+fn parse_xml(contents: &str) -> Vec<(u32, String)> {
+    let mut games = vec![];
+    for game in contents.split("<game ").skip(1) {
+        let name = match attribute(game, "name") {
+            Some(name) => unescape(name),
+            None => continue,
+        };
+
+        for rom in game.split("<rom ").skip(1) {
+            if let Some(crc) = attribute(rom, "crc").and_then(parse_crc) {
+                games.push((crc, name.clone()));
+            }
+        }
+    }
+    games
+}
+
+/// The value of `name="value"` in a tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+    let start = tag.find(&format!("{}=\"", name))? + name.len() + 2;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// `game ( name "..." rom ( name "..." crc ... ) )`.
+fn parse_clrmamepro(contents: &str) -> Vec<(u32, String)> {
+    let tokens = tokenize(contents);
+    let mut games = vec![];
+    let mut name = None;
+    let mut depth = 0;
+    for (i, &token) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1).copied();
+        match token {
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            "name" if depth == 1 => name = next.map(str::to_string),
+            "crc" if depth == 2 => {
+                if let (Some(name), Some(crc)) = (&name, next.and_then(parse_crc)) {
+                    games.push((crc, name.clone()));
+                }
+            }
+            _ => (),
+        }
+    }
+    games
+}
+
+/// Words, quoted strings without their quotes and parentheses.
+fn tokenize(contents: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut rest = contents.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            '(' | ')' => 1,
+            '"' => {
+                let len = rest[1..].find('"').map_or(rest.len(), |end| end + 2);
+                tokens.push(rest[1..len.max(2) - 1].trim_end_matches('"'));
+                rest = rest[len.min(rest.len())..].trim_start();
+                continue;
+            }
+            _ => rest
+                .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                .unwrap_or(rest.len()),
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    tokens
+}
+
+fn parse_crc(crc: &str) -> Option<u32> {
+    u32::from_str_radix(crc, 16).ok()
+}
+
+fn mapper_name(mapper: u16) -> Option<&'static str> {
+    Some(match mapper {
+        0 => "NROM",
+        1 => "MMC1",
+        2 => "UxROM",
+        3 => "CNROM",
+        4 => "MMC3",
+        5 => "MMC5",
+        7 => "AxROM",
+        9 => "MMC2",
+        10 => "MMC4",
+        11 => "Color Dreams",
+        66 => "GxROM",
+        69 => "FME-7",
+        71 => "Camerica",
+        _ => return None,
+    })
+}
+
+fn kib(size: usize) -> String {
+    if size.is_multiple_of(1024) {
+        format!("{} KiB", size / 1024)
+    } else {
+        format!("{} bytes", size)
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// The description printed by `nes info`.
+pub fn describe(path: &str, file: &[u8], database: Option<&Database>) -> Result<String> {
+    let header = HeaderInfo::parse(file)?;
+    let rom = header.rom_data(file);
+    let crc32 = ines::crc32(rom);
+
+    let mut out = String::new();
+    let mut line = |name: &str, value: &dyn std::fmt::Display| {
+        writeln!(out, "{:<12}{}", name, value).unwrap();
+    };
+
+    line("File", &path);
+    line("Format", &if header.nes2 { "NES 2.0" } else { "iNES" });
+    let mapper = match mapper_name(header.mapper) {
+        Some(name) => format!("{} ({})", header.mapper, name),
+        None => header.mapper.to_string(),
+    };
+    if header.nes2 {
+        line(
+            "Mapper",
+            &format!("{}, submapper {}", mapper, header.submapper),
+        );
+    } else {
+        line("Mapper", &mapper);
+    }
+    line("PRG ROM", &kib(header.prg_rom_size));
+    line("CHR ROM", &kib(header.chr_rom_size));
+    line("Mirroring", &format!("{:?}", header.mirroring));
+    line("Battery", &yes_no(header.battery));
+    line("Trainer", &yes_no(header.trainer));
+    line("VS System", &yes_no(header.vs_system));
+    line(
+        "Region",
+        &header
+            .region
+            .map_or("Any".to_string(), |region: Region| region.to_string()),
+    );
+    line("CRC32", &format!("{:08X}", crc32));
+    line("SHA-1", &hex(&Sha1::digest(rom)));
+    line("File CRC32", &format!("{:08X}", ines::crc32(file)));
+    line(
+        "Supported",
+        &match NesFile::from_bytes(file) {
+            Ok(_) => "yes".to_string(),
+            Err(err) => format!("no, {}", err),
+        },
+    );
+    if let Some(database) = database {
+        line(
+            "Game",
+            &database.find(crc32).unwrap_or("not in the database"),
+        );
+    }
+
+    Ok(out)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// Print the description of the ROM at `path`.
+pub fn run(path: &str, database: Option<&str>) -> Result<()> {
+    let file = fs::read(path).with_context(|| format!("Failed to read \"{}\"", path))?;
+    let database = database.map(Database::load).transpose()?;
+    print!("{}", describe(path, &file, database.as_ref())?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_database() {
+        let xml = Database::parse(
+            r#"<?xml version="1.0"?>
+            <datafile>
+                <game name="Tom &amp; Jerry (USA)">
+                    <description>Tom &amp; Jerry (USA)</description>
+                    <rom name="Tom &amp; Jerry (USA).nes" size="131072" crc="4b6e1ab5" sha1="00"/>
+                </game>
+                <game name="nestest"><rom name="nestest.nes" crc="XYZ"/></game>
+            </datafile>"#,
+        );
+        assert_eq!(xml.find(0x4B6E_1AB5), Some("Tom & Jerry (USA)"));
+        assert_eq!(xml.games.len(), 1);
+
+        let clrmamepro = Database::parse(
+            r#"clrmamepro (
+                name "Nintendo - NES"
+            )
+
+            game (
+                name "Tom & Jerry (USA)"
+                description "Tom & Jerry (USA)"
+                rom ( name "Tom & Jerry (USA).nes" size 131072 crc 4B6E1AB5 sha1 00 )
+            )"#,
+        );
+        assert_eq!(clrmamepro, xml);
+    }
+
+    #[test]
+    fn test_describe() -> Result<()> {
+        let file = fs::read("test/nestest.nes")?;
+        let crc32 = NesFile::from_bytes(&file)?.checksum();
+        let mut database = Database::default();
+        database.games.insert(crc32, "nestest".to_string());
+
+        let description = describe("nestest.nes", &file, Some(&database))?;
+        assert!(description.contains("Format      iNES\n"));
+        assert!(description.contains("Mapper      0 (NROM)\n"));
+        assert!(description.contains("PRG ROM     16 KiB\n"));
+        assert!(description.contains(&format!("CRC32       {:08X}\n", crc32)));
+        assert!(description.contains("Supported   yes\n"));
+        assert!(description.ends_with("Game        nestest\n"));
+
+        assert!(describe("empty", b"", None).is_err());
+        Ok(())
+    }
+}
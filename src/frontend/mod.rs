@@ -0,0 +1,26 @@
+/// Frontends drive the emulation and present its output.
+///
+/// The core knows nothing about them, they only use the public API of the CPU, PPU and APU.
+pub mod batch;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub mod bindings;
+pub mod chr;
+pub mod disasm;
+pub mod hash_frames;
+pub mod headless;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub mod hotkeys;
+pub mod info;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub mod pacing;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub mod rewind;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub mod runahead;
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub mod scaler;
+#[cfg(feature = "stream")]
+pub mod stream;
+
+#[cfg(feature = "gui")]
+pub mod window;
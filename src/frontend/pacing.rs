@@ -0,0 +1,252 @@
+/// Keeps the emulation running at the console's frame rate.
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Frames per second of the NTSC console.
+pub const NTSC_FRAME_RATE: f64 = 60.0988;
+
+/// Speeds stepped through by `slower()` and `faster()`.
+const SPEED_PRESETS: [f64; 7] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+/// Give up on catching up after falling this many frames behind, e.g. after the window was dragged.
+const MAX_FRAMES_BEHIND: u32 = 4;
+
+/// How the frame rate is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PacingStrategy {
+    /// Sleep until each frame is due.
+    Sleep,
+
+    /// Let presenting the picture block on the display's refresh, running as many frames per
+    /// refresh as the refresh rate and speed call for.
+    Vsync,
+}
+
+impl FromStr for PacingStrategy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "sleep" => Ok(PacingStrategy::Sleep),
+            "vsync" => Ok(PacingStrategy::Vsync),
+            _ => Err(format!("Unknown pacing strategy \"{}\".", name)),
+        }
+    }
+}
+
+pub struct FramePacer {
+    strategy: PacingStrategy,
+
+    /// Frames per second of the console.
+    frame_rate: f64,
+
+    /// Multiplier of the console's frame rate, fractions run in slow motion.
+    speed: f64,
+
+    /// Refresh rate of the display when pacing with vsync.
+    refresh_rate: f64,
+
+    /// When the next frame is due when sleeping.
+    next_frame: Instant,
+
+    /// Fractional frames owed when pacing with vsync.
+    frames_owed: f64,
+
+    /// Run uncapped, presenting one of every `frame_skip + 1` frames. With vsync presenting still
+    /// waits for the display, skipping frames is the only way to go faster.
+    ///
+    /// The audio buffer drops the samples it can't hold, so audio keeps its pitch but skips.
+    fast_forward: bool,
+
+    /// Frames emulated but not presented for every presented frame when fast forwarding.
+    frame_skip: u32,
+}
+
+impl FramePacer {
+    pub fn new(strategy: PacingStrategy, speed: f64) -> Self {
+        FramePacer {
+            strategy,
+            frame_rate: NTSC_FRAME_RATE,
+            speed,
+            refresh_rate: NTSC_FRAME_RATE,
+            next_frame: Instant::now(),
+            frames_owed: 0.0,
+            fast_forward: false,
+            frame_skip: 0,
+        }
+    }
+
+    /// Pace a console with another frame rate, e.g. a PAL one.
+    pub fn set_frame_rate(&mut self, frame_rate: f64) {
+        self.frame_rate = frame_rate;
+        self.reset();
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+        self.reset();
+    }
+
+    /// Pace from now on, forgetting about any frames owed, e.g. after being paused.
+    pub fn reset(&mut self) {
+        self.next_frame = Instant::now();
+        self.frames_owed = 0.0;
+    }
+
+    /// Step down to the next slower preset speed.
+    pub fn slower(&mut self) {
+        let speed = SPEED_PRESETS
+            .iter()
+            .rev()
+            .find(|&&preset| preset < self.speed)
+            .unwrap_or(&SPEED_PRESETS[0]);
+        self.set_speed(*speed);
+    }
+
+    /// Step up to the next faster preset speed.
+    pub fn faster(&mut self) {
+        let speed = SPEED_PRESETS
+            .iter()
+            .find(|&&preset| preset > self.speed)
+            .unwrap_or(&SPEED_PRESETS[SPEED_PRESETS.len() - 1]);
+        self.set_speed(*speed);
+    }
+
+    /// Frames to skip presenting when fast forwarding.
+    pub fn set_frame_skip(&mut self, frame_skip: u32) {
+        self.frame_skip = frame_skip;
+    }
+
+    /// Uncap the frame rate.
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        if self.fast_forward && !fast_forward {
+            // Don't try to catch up with the time spent fast forwarding.
+            self.reset();
+        }
+
+        self.fast_forward = fast_forward;
+    }
+
+    pub fn strategy(&self) -> PacingStrategy {
+        self.strategy
+    }
+
+    /// Refresh rate of the display the picture is presented on, only used with vsync.
+    pub fn set_refresh_rate(&mut self, refresh_rate: f64) {
+        self.refresh_rate = refresh_rate;
+    }
+
+    /// Duration of a frame at the current speed.
+    fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / (self.frame_rate * self.speed))
+    }
+
+    /// Wait until the next frame is due, returns how many frames to emulate before presenting.
+    pub fn next(&mut self) -> u32 {
+        if self.fast_forward {
+            return self.frame_skip + 1;
+        }
+
+        match self.strategy {
+            PacingStrategy::Sleep => {
+                let now = Instant::now();
+                if now < self.next_frame {
+                    thread::sleep(self.next_frame - now);
+                } else if now - self.next_frame > self.frame_duration() * MAX_FRAMES_BEHIND {
+                    self.next_frame = now;
+                }
+
+                self.next_frame += self.frame_duration();
+                1
+            }
+            PacingStrategy::Vsync => {
+                self.frames_owed += self.frame_rate * self.speed / self.refresh_rate;
+                self.frames_owed = self.frames_owed.min(MAX_FRAMES_BEHIND as f64);
+
+                let frames = self.frames_owed.floor();
+                self.frames_owed -= frames;
+                frames as u32
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sleep() {
+        let mut pacer = FramePacer::new(PacingStrategy::Sleep, 4.0);
+
+        let start = Instant::now();
+        for _ in 0..12 {
+            assert_eq!(pacer.next(), 1);
+        }
+
+        // The first frame is due immediately.
+        let expected = Duration::from_secs_f64(11.0 / (NTSC_FRAME_RATE * 4.0));
+        assert!(start.elapsed() >= expected);
+    }
+
+    #[test]
+    fn test_fast_forward() {
+        let mut pacer = FramePacer::new(PacingStrategy::Sleep, 1.0);
+        pacer.set_frame_skip(3);
+        pacer.set_fast_forward(true);
+
+        // Nowhere near the 3 seconds it would take paced.
+        let start = Instant::now();
+        let frames: u32 = (0..60).map(|_| pacer.next()).sum();
+        assert_eq!(frames, 240);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_speed_presets() {
+        let mut pacer = FramePacer::new(PacingStrategy::Vsync, 0.3);
+
+        pacer.slower();
+        assert_eq!(pacer.speed(), 0.25);
+        pacer.faster();
+        pacer.faster();
+        assert_eq!(pacer.speed(), 1.0);
+
+        // Quarter speed runs a frame every fourth refresh.
+        pacer.slower();
+        pacer.slower();
+        let frames: u32 = (0..8).map(|_| pacer.next()).sum();
+        assert_eq!(frames, 2);
+
+        for _ in 0..10 {
+            pacer.faster();
+        }
+        assert_eq!(pacer.speed(), 8.0);
+    }
+
+    #[test]
+    fn test_vsync() {
+        let mut pacer = FramePacer::new(PacingStrategy::Vsync, 1.0);
+
+        // A 120Hz display runs a frame every other refresh.
+        pacer.set_refresh_rate(NTSC_FRAME_RATE * 2.0);
+        let frames: Vec<u32> = (0..4).map(|_| pacer.next()).collect();
+        assert_eq!(frames, vec![0, 1, 0, 1]);
+
+        // Double speed on a matching display runs two frames per refresh.
+        let mut pacer = FramePacer::new(PacingStrategy::Vsync, 2.0);
+        assert_eq!(pacer.next(), 2);
+
+        // A PAL console on a 60Hz display skips every sixth refresh.
+        let mut pacer = FramePacer::new(PacingStrategy::Vsync, 1.0);
+        pacer.set_frame_rate(50.0);
+        pacer.set_refresh_rate(60.0);
+        let frames: u32 = (0..60).map(|_| pacer.next()).sum();
+        assert_eq!(frames, 50);
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+/// Rewinding through the last few seconds of play.
+///
+/// A compressed save state is captured every few frames into a bounded ring buffer, rewinding
+/// loads them back from the newest to the oldest.
+use nes::cpu::Cpu;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+pub struct Rewind {
+    /// Frames in between snapshots.
+    interval: u32,
+
+    /// Maximum number of snapshots kept, the oldest are dropped first.
+    capacity: usize,
+
+    /// Compressed save states, oldest first.
+    snapshots: VecDeque<Vec<u8>>,
+
+    /// Frames run since the last snapshot.
+    frames: u32,
+}
+
+impl Rewind {
+    /// Keep `seconds` worth of snapshots taken every `interval` frames, at `frame_rate` frames per
+    /// second.
+    pub fn new(seconds: f64, interval: u32, frame_rate: f64) -> Self {
+        let interval = interval.max(1);
+        let frames = seconds.max(0.0) * frame_rate;
+
+        Rewind {
+            interval,
+            capacity: (frames / interval as f64).round() as usize,
+            snapshots: VecDeque::new(),
+            frames: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Call after every frame run forwards, takes a snapshot when it's due.
+    pub fn push(&mut self, cpu: &Cpu) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        self.frames += 1;
+        if self.frames < self.interval {
+            return Ok(());
+        }
+        self.frames = 0;
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&cpu.save_state()?)?;
+
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(encoder.finish()?);
+
+        Ok(())
+    }
+
+    /// Go back to the newest snapshot and drop it. Returns false when there is nothing left.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> Result<bool> {
+        let snapshot = match self.snapshots.pop_back() {
+            Some(snapshot) => snapshot,
+            None => return Ok(false),
+        };
+
+        let mut state = Vec::new();
+        DeflateDecoder::new(&snapshot[..]).read_to_end(&mut state)?;
+        cpu.load_state(&state)?;
+
+        self.frames = 0;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pacing::NTSC_FRAME_RATE;
+    use nes::ines;
+
+    #[test]
+    fn test_rewind() -> Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        // Spin on JMP $0200.
+        cpu.memory[0x0200..0x0203].copy_from_slice(&[0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+
+        // Half a second, every 10 frames.
+        let mut rewind = Rewind::new(0.5, 10, NTSC_FRAME_RATE);
+        let mut frame_counts = vec![];
+        for _ in 0..100 {
+            cpu.run_frame();
+            rewind.push(&cpu)?;
+
+            if cpu.ppu.frame_count().is_multiple_of(10) {
+                frame_counts.push(cpu.ppu.frame_count());
+            }
+        }
+
+        // Only the newest snapshots are kept.
+        assert_eq!(rewind.snapshots.len(), 3);
+        for &frame_count in frame_counts.iter().rev().take(3) {
+            assert!(rewind.step_back(&mut cpu)?);
+            assert_eq!(cpu.ppu.frame_count(), frame_count);
+        }
+        assert!(!rewind.step_back(&mut cpu)?);
+
+        Ok(())
+    }
+}
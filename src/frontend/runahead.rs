@@ -0,0 +1,130 @@
+use anyhow::Result;
+/// Run-ahead, hides the game's own input lag.
+///
+/// Most games take a frame or two to react to input. Each frame is run for real without being
+/// shown, then the console runs a few frames further with the same input and shows the last one.
+/// A save state from before the speculative frames rolls them back, so new input is always
+/// applied to the real state.
+use nes::cpu::Cpu;
+use nes::debugger::{self, Resume};
+
+pub struct RunAhead {
+    /// Frames emulated ahead of the real state.
+    frames: u32,
+
+    /// Save state of the real state, kept to reuse the allocation.
+    state: Vec<u8>,
+}
+
+impl RunAhead {
+    pub fn new(frames: u32) -> Self {
+        RunAhead {
+            frames,
+            state: Vec::new(),
+        }
+    }
+
+    /// Run a frame, presenting the picture from `frames` frames ahead.
+    ///
+    /// Only the real frame's audio is played, the speculative frames would be heard twice. The
+    /// debugger is only entered for breakpoints in the real frame, and only the real frame is traced,
+    /// profiled, has its register writes recorded and is seen by the script.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Result<Resume> {
+        if self.frames == 0 {
+            return Ok(debugger::run_frame(cpu));
+        }
+
+        cpu.ppu.pause_frame_callback(true);
+        let resume = debugger::run_frame(cpu);
+        cpu.ppu.pause_frame_callback(false);
+
+        cpu.save_state_into(&mut self.state)?;
+
+        let breakpoints = std::mem::take(&mut cpu.breakpoints);
+        let tracer = std::mem::take(&mut cpu.tracer);
+        let profiler = std::mem::take(&mut cpu.profiler);
+        let events = std::mem::take(&mut cpu.events);
+        let observers = std::mem::take(&mut cpu.observers);
+        let call_stack = cpu.call_stack.clone();
+        #[cfg(feature = "scripting")]
+        let script = cpu.script.take();
+        cpu.apu.pause_sample_callbacks(true);
+        for frame in 1..=self.frames {
+            cpu.ppu.pause_frame_callback(frame != self.frames);
+            cpu.run_frame();
+        }
+        cpu.ppu.pause_frame_callback(false);
+        cpu.apu.pause_sample_callbacks(false);
+        cpu.breakpoints = breakpoints;
+        cpu.tracer = tracer;
+        cpu.profiler = profiler;
+        cpu.events = events;
+        cpu.observers = observers;
+        #[cfg(feature = "scripting")]
+        {
+            cpu.script = script;
+        }
+
+        cpu.load_state(&self.state)?;
+        cpu.call_stack = call_stack;
+        Ok(resume)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::ines;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    fn spinning_cpu() -> Result<Cpu> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        // Spin on JMP $0200, with rendering on.
+        cpu.memory[0x0200..0x0203].copy_from_slice(&[0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+        cpu.write(0x2001, 0x1E);
+
+        Ok(cpu)
+    }
+
+    #[test]
+    fn test_run_ahead() -> Result<()> {
+        let mut expected = spinning_cpu()?;
+        let mut cpu = spinning_cpu()?;
+
+        let presented = Arc::new(Mutex::new(vec![]));
+        cpu.ppu.on_frame_complete({
+            let presented = presented.clone();
+            move |frame| presented.lock().unwrap().push(frame.to_vec())
+        });
+
+        let samples = Arc::new(Mutex::new(0));
+        cpu.apu.on_sample({
+            let samples = samples.clone();
+            move |_| *samples.lock().unwrap() += 1
+        });
+
+        let start = cpu.cycles;
+        let mut run_ahead = RunAhead::new(2);
+        for _ in 0..3 {
+            run_ahead.run_frame(&mut cpu)?;
+            expected.run_frame();
+        }
+
+        // The real state isn't affected, one frame is presented for each frame run.
+        assert_eq!(cpu.cycles, expected.cycles);
+        assert_eq!(cpu.ppu.frame_count(), expected.ppu.frame_count());
+        assert_eq!(presented.lock().unwrap().len(), 3);
+        assert_eq!(*samples.lock().unwrap(), cpu.cycles - start);
+
+        // The last picture presented is two frames ahead.
+        expected.run_frame();
+        expected.run_frame();
+        assert_eq!(presented.lock().unwrap()[2], expected.ppu.frame());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+/// Scales the picture for display: overscan cropping, pixel aspect correction and nearest
+/// neighbour integer scaling.
+use nes::ppu::SCREEN_WIDTH;
+use std::str::FromStr;
+
+/// Shape of the pixels on the display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AspectRatio {
+    /// Square pixels, the picture is 256x240.
+    Square,
+
+    /// The 8:7 pixels of an NTSC television.
+    Ntsc,
+}
+
+impl AspectRatio {
+    /// Width of a pixel relative to its height.
+    fn pixel_aspect_ratio(self) -> f64 {
+        match self {
+            AspectRatio::Square => 1.0,
+            AspectRatio::Ntsc => 8.0 / 7.0,
+        }
+    }
+}
+
+impl FromStr for AspectRatio {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "1:1" => Ok(AspectRatio::Square),
+            "8:7" => Ok(AspectRatio::Ntsc),
+            _ => Err(format!("Unknown aspect ratio \"{}\".", name)),
+        }
+    }
+}
+
+/// Lines and columns hidden at each edge, televisions didn't show the whole picture.
+///
+/// Columns are in console pixels, they are scaled to match wider filtered pictures.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl FromStr for Overscan {
+    type Err = String;
+
+    /// Either a single number of lines for the top and bottom, or "top,bottom,left,right".
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let values = value
+            .split(',')
+            .map(|value| value.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("Invalid overscan \"{}\".", value))?;
+
+        match values[..] {
+            [lines] => Ok(Overscan {
+                top: lines,
+                bottom: lines,
+                ..Overscan::default()
+            }),
+            [top, bottom, left, right] => Ok(Overscan {
+                top,
+                bottom,
+                left,
+                right,
+            }),
+            _ => Err(format!(
+                "Overscan should be \"lines\" or \"top,bottom,left,right\", got \"{}\".",
+                value
+            )),
+        }
+    }
+}
+
+pub struct Scaler {
+    /// Integer scale of each line.
+    scale: usize,
+
+    aspect_ratio: AspectRatio,
+    overscan: Overscan,
+}
+
+impl Scaler {
+    pub fn new(scale: usize, aspect_ratio: AspectRatio, overscan: Overscan) -> Self {
+        Scaler {
+            scale: scale.max(1),
+            aspect_ratio,
+            overscan,
+        }
+    }
+
+    /// Columns cropped from the left and right of a picture of the given width.
+    fn cropped_columns(&self, width: usize) -> (usize, usize) {
+        (
+            self.overscan.left * width / SCREEN_WIDTH,
+            self.overscan.right * width / SCREEN_WIDTH,
+        )
+    }
+
+    /// Dimensions of the scaled picture given the dimensions of the input.
+    pub fn output_size(&self, (width, height): (usize, usize)) -> (usize, usize) {
+        let (left, right) = self.cropped_columns(width);
+        let width = width.saturating_sub(left + right).max(1);
+        let height = height
+            .saturating_sub(self.overscan.top + self.overscan.bottom)
+            .max(1);
+
+        let scaled_width =
+            width as f64 * self.scale as f64 * self.aspect_ratio.pixel_aspect_ratio();
+        (scaled_width.round() as usize, height * self.scale)
+    }
+
+    /// Point on the console's screen shown at a point of the scaled picture.
+    pub fn screen_position(&self, (x, y): (usize, usize), size: (usize, usize)) -> (usize, usize) {
+        let (width, height) = size;
+        let (output_width, _) = self.output_size(size);
+        let (left, right) = self.cropped_columns(width);
+        let cropped_width = width.saturating_sub(left + right).max(1);
+
+        let picture_x = left + x * cropped_width / output_width;
+        let picture_y = self.overscan.top + y / self.scale;
+
+        (
+            (picture_x * SCREEN_WIDTH / width).min(SCREEN_WIDTH - 1),
+            picture_y.min(height - 1),
+        )
+    }
+
+    /// Scale a RGBA picture into `output`, which must be `output_size()`.
+    pub fn apply(&self, picture: &[u8], size: (usize, usize), output: &mut [u8]) {
+        let (width, height) = size;
+        let (output_width, output_height) = self.output_size(size);
+        let (left, right) = self.cropped_columns(width);
+        let cropped_width = width.saturating_sub(left + right).max(1);
+
+        // Nearest neighbour, the source column of each output column.
+        let columns: Vec<usize> = (0..output_width)
+            .map(|x| (left + x * cropped_width / output_width).min(width - 1))
+            .collect();
+
+        for (y, output_row) in output
+            .chunks_exact_mut(output_width * 4)
+            .take(output_height)
+            .enumerate()
+        {
+            let source_y = (self.overscan.top + y / self.scale).min(height - 1);
+            let source_row = &picture[source_y * width * 4..(source_y + 1) * width * 4];
+
+            for (pixel, &x) in output_row.chunks_exact_mut(4).zip(&columns) {
+                pixel.copy_from_slice(&source_row[x * 4..x * 4 + 4]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::ppu::SCREEN_HEIGHT;
+
+    #[test]
+    fn test_output_size() {
+        let size = (SCREEN_WIDTH, SCREEN_HEIGHT);
+
+        let scaler = Scaler::new(2, AspectRatio::Square, Overscan::default());
+        assert_eq!(scaler.output_size(size), (512, 480));
+
+        let scaler = Scaler::new(1, AspectRatio::Ntsc, "8".parse().unwrap());
+        assert_eq!(scaler.output_size(size), (293, 224));
+
+        // Columns are scaled to match a wider picture.
+        let scaler = Scaler::new(1, AspectRatio::Square, "0,0,8,8".parse().unwrap());
+        assert_eq!(scaler.output_size((512, 240)), (480, 240));
+    }
+
+    #[test]
+    fn test_screen_position() {
+        let scaler = Scaler::new(2, AspectRatio::Square, "8,8,8,8".parse().unwrap());
+        let size = (SCREEN_WIDTH, SCREEN_HEIGHT);
+
+        assert_eq!(scaler.screen_position((0, 0), size), (8, 8));
+        assert_eq!(scaler.screen_position((21, 11), size), (18, 13));
+    }
+
+    #[test]
+    fn test_apply() {
+        // 4x3 picture where each pixel's red is its index.
+        let picture: Vec<u8> = (0..12).flat_map(|i| vec![i, 0, 0, 0xFF]).collect();
+
+        let scaler = Scaler::new(2, AspectRatio::Square, "1".parse().unwrap());
+        let size = scaler.output_size((4, 3));
+        assert_eq!(size, (8, 2));
+
+        let mut output = vec![0; size.0 * size.1 * 4];
+        scaler.apply(&picture, (4, 3), &mut output);
+
+        // Only the middle line remains, doubled in both directions.
+        let red: Vec<u8> = output.chunks(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(red, vec![4, 4, 5, 5, 6, 6, 7, 7, 4, 4, 5, 5, 6, 6, 7, 7]);
+    }
+
+    #[test]
+    fn test_invalid_overscan() {
+        assert!("1,2".parse::<Overscan>().is_err());
+        assert!("a".parse::<Overscan>().is_err());
+    }
+}
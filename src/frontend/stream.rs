@@ -0,0 +1,196 @@
+/// Streams the game over WebSocket and takes the input back, for remote play or watching a
+/// headless server from a browser. stream/index.html is a viewer.
+///
+/// The first message to a client is text, JSON with the screen's "width" and "height", the audio's
+/// "sample_rate" and the "palette" as red, green and blue for each colour. The rest are binary,
+/// the first byte says what follows:
+///   0, a frame: palette indices, zlib compressed
+///   1, audio: signed 16 bit little endian mono samples
+/// Clients send two bytes whenever the buttons change: the player and the `ControllerState` bits.
+///
+/// One client is served at a time and the game is paused while nobody is connected. Frames are
+/// dropped while the connection can't keep up, the audio isn't.
+use crate::frontend::pacing::{FramePacer, PacingStrategy};
+use anyhow::{anyhow, Result};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use nes::audio::Resampler;
+use nes::controller::ControllerState;
+use nes::cpu::Cpu;
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH, SYSTEM_PALETTE};
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::Mutex;
+use tracing::{info, warn};
+use tungstenite::{Error, Message};
+
+/// Rate of the audio sent to clients.
+pub const SAMPLE_RATE: u32 = 48_000;
+
+const FRAME: u8 = 0;
+const AUDIO: u8 = 1;
+
+/// Serve clients one after the other, forever.
+pub fn run(mut cpu: Cpu, listener: TcpListener) -> Result<()> {
+    info!("Streaming on {}", listener.local_addr()?);
+
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    let mut resampler = Resampler::new(cpu.apu.sample_rate(), SAMPLE_RATE);
+    let output = samples.clone();
+    cpu.apu.on_sample(move |sample| {
+        if let Some(sample) = resampler.push(sample) {
+            output
+                .lock()
+                .unwrap()
+                .push((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+        }
+    });
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr()?;
+        info!("Client {} connected", peer);
+
+        // A client going away shouldn't stop the server.
+        match serve_client(&mut cpu, stream, &samples) {
+            Ok(()) => info!("Client {} disconnected", peer),
+            Err(err) => warn!("Client {} failed: {}", peer, err),
+        }
+
+        for controller in &mut cpu.controllers {
+            controller.set_state(ControllerState::default());
+        }
+    }
+
+    Ok(())
+}
+
+/// Run the game for the client until it disconnects.
+fn serve_client(cpu: &mut Cpu, stream: TcpStream, samples: &Mutex<Vec<i16>>) -> Result<()> {
+    let mut socket = tungstenite::accept(stream).map_err(|err| anyhow!("{}", err))?;
+    socket.send(Message::Text(hello()))?;
+    socket.get_mut().set_nonblocking(true)?;
+
+    samples.lock().unwrap().clear();
+    let mut pacer = FramePacer::new(PacingStrategy::Sleep, 1.0);
+    pacer.set_frame_rate(cpu.region().frame_rate());
+    let mut backlogged = false;
+
+    loop {
+        loop {
+            match socket.read() {
+                Ok(Message::Binary(input)) => {
+                    if let [player @ 0..=1, buttons] = input[..] {
+                        cpu.controllers[player as usize].set_state(ControllerState(buttons));
+                    }
+                }
+                Ok(_) => {}
+                Err(Error::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(Error::ConnectionClosed) => return Ok(()),
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        for _ in 0..pacer.next() {
+            cpu.run_frame();
+        }
+
+        if !backlogged {
+            let mut frame = ZlibEncoder::new(vec![FRAME], Compression::fast());
+            frame.write_all(cpu.ppu.frame())?;
+            socket.write(Message::Binary(frame.finish()?))?;
+        }
+
+        let mut audio = vec![AUDIO];
+        for sample in samples.lock().unwrap().drain(..) {
+            audio.extend_from_slice(&sample.to_le_bytes());
+        }
+        socket.write(Message::Binary(audio))?;
+
+        backlogged = match socket.flush() {
+            Ok(()) => false,
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => true,
+            Err(err) => return Err(err.into()),
+        };
+    }
+}
+
+/// What clients need to know before the first frame.
+fn hello() -> String {
+    let palette: Vec<String> = SYSTEM_PALETTE
+        .iter()
+        .map(|(r, g, b)| format!("{},{},{}", r, g, b))
+        .collect();
+
+    format!(
+        r#"{{"width":{},"height":{},"sample_rate":{},"palette":[{}]}}"#,
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        SAMPLE_RATE,
+        palette.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use nes::ines::test_util::spinning_nrom;
+    use std::io::Read;
+    use std::thread;
+
+    #[test]
+    fn test_stream() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let client = thread::spawn(move || -> Result<Vec<Message>> {
+            let (mut socket, _) = tungstenite::connect(format!("ws://{}", addr))?;
+            socket.send(Message::Binary(vec![1, ControllerState::START]))?;
+
+            let mut messages = Vec::new();
+            for _ in 0..6 {
+                messages.push(socket.read()?);
+            }
+
+            socket.close(None)?;
+            loop {
+                match socket.read() {
+                    Ok(_) => {}
+                    Err(Error::ConnectionClosed) => return Ok(messages),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        });
+
+        let mut cpu = Cpu::new(spinning_nrom());
+        let samples = Mutex::new(Vec::new());
+        let (stream, _) = listener.accept()?;
+        serve_client(&mut cpu, stream, &samples)?;
+
+        let messages = client.join().unwrap()?;
+        let hello = messages[0].to_text()?;
+        assert!(hello.starts_with(r#"{"width":256,"height":240,"sample_rate":48000,"palette":["#));
+
+        let frame = messages[1..]
+            .iter()
+            .find_map(|message| match message {
+                Message::Binary(data) if data[0] == FRAME => Some(data),
+                _ => None,
+            })
+            .unwrap();
+        let mut pixels = Vec::new();
+        ZlibDecoder::new(&frame[1..]).read_to_end(&mut pixels)?;
+        assert_eq!(pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+
+        assert!(messages
+            .iter()
+            .any(|message| matches!(message, Message::Binary(data) if data[0] == AUDIO)));
+        assert_eq!(
+            cpu.controllers[1].state(),
+            ControllerState(ControllerState::START)
+        );
+        Ok(())
+    }
+}
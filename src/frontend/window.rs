@@ -0,0 +1,499 @@
+use crate::frontend::bindings::KeyBindings;
+use crate::frontend::hotkeys::{Hotkey, Hotkeys};
+use crate::frontend::pacing::{FramePacer, PacingStrategy};
+use crate::frontend::rewind::Rewind;
+use crate::frontend::runahead::RunAhead;
+use crate::frontend::scaler::Scaler;
+use anyhow::Result;
+/// Shows the emulator in a window using winit and pixels.
+///
+/// The emulation runs in between redraws, paced by the `FramePacer`.
+use nes::apu::CHANNELS;
+use nes::battery::BatterySave;
+use nes::controller::ControllerState;
+use nes::cpu::Cpu;
+use nes::debugger::{self, Resume};
+use nes::movie::MovieSession;
+use nes::netplay::Session;
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH, SYSTEM_PALETTE};
+use nes::savestate::{SaveSlots, SaveStateSource, SLOTS};
+use nes::stats::{Report, Stats};
+use nes::video::capture::GifCapture;
+use nes::video::font;
+use nes::video::input_display;
+use nes::video::palette::PaletteCycle;
+use nes::video::{image, VideoFilter};
+use pixels::{PixelsBuilder, SurfaceTexture};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, Event, KeyboardInput, MouseButton, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::WindowBuilder;
+
+/// Distance of the watches from the top left corner, inside what most televisions show.
+const WATCHES_MARGIN: i32 = 8;
+
+/// How often the statistics in the title are refreshed.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Open a window and run the emulator until it is closed.
+///
+/// While playing over the network pausing, rewinding and loading states are off, they would take
+/// the console out of step with the other player's.
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    mut cpu: Cpu,
+    video_filter: VideoFilter,
+    mut palettes: PaletteCycle,
+    mut pacer: FramePacer,
+    gif_capture: GifCapture,
+    scaler: Scaler,
+    bindings: KeyBindings,
+    hotkeys: Hotkeys,
+    mut movie: Option<MovieSession>,
+    mut netplay: Option<Session>,
+    mut battery: Option<BatterySave>,
+    save_slots: SaveSlots,
+    mut rewind: Rewind,
+    mut run_ahead: RunAhead,
+    mut stats: Stats,
+    mut show_stats: bool,
+    mut show_input: bool,
+) -> Result<()> {
+    let picture_size = video_filter.output_size();
+    let (width, height) = scaler.output_size(picture_size);
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title(title(pacer.speed(), false, None))
+        .with_inner_size(LogicalSize::new(width as f64, height as f64))
+        .build(&event_loop)?;
+
+    let mut pixels = {
+        let size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(size.width, size.height, &window);
+        PixelsBuilder::new(width as u32, height as u32, surface_texture)
+            .enable_vsync(pacer.strategy() == PacingStrategy::Vsync)
+            .build()?
+    };
+
+    if let Some(millihertz) = window
+        .current_monitor()
+        .and_then(|monitor| monitor.refresh_rate_millihertz())
+    {
+        pacer.set_refresh_rate(millihertz as f64 / 1000.0);
+    }
+
+    // Filter and scale each frame as it completes, ready for the next redraw. Pixels only scales
+    // further by whole multiples when the window is larger.
+    let picture = Arc::new(Mutex::new(vec![0; width * height * 4]));
+    let video_filter = Arc::new(Mutex::new(video_filter));
+    let gif_capture = Arc::new(Mutex::new(gif_capture));
+    let scaler = Arc::new(scaler);
+    #[cfg(feature = "scripting")]
+    let overlay = cpu.script.as_ref().map(|script| script.overlay());
+
+    // The buttons held during the frame being run, while they're shown.
+    let input_shown: Arc<Mutex<Option<[ControllerState; 2]>>> = Arc::new(Mutex::new(None));
+
+    // The watches' values after the last frame, drawn when they aren't logged.
+    let watches_shown: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
+
+    cpu.ppu.on_frame_complete({
+        let picture = picture.clone();
+        let video_filter = video_filter.clone();
+        let scaler = scaler.clone();
+        let gif_capture = gif_capture.clone();
+        let input_shown = input_shown.clone();
+        let watches_shown = watches_shown.clone();
+        move |frame| {
+            // The script's text, the watches and the input display are drawn over the game before
+            // filtering.
+            let mut overlaid: Option<Vec<u8>> = None;
+            #[cfg(feature = "scripting")]
+            if let Some(overlay) = &overlay {
+                let overlaid = overlaid.get_or_insert_with(|| frame.to_vec());
+                for text in overlay.lock().unwrap().iter() {
+                    font::draw_text(overlaid, text.x, text.y, &text.text);
+                }
+            }
+            let watches = watches_shown.lock().unwrap();
+            if !watches.is_empty() {
+                let overlaid = overlaid.get_or_insert_with(|| frame.to_vec());
+                font::draw_text(overlaid, WATCHES_MARGIN, WATCHES_MARGIN, &watches);
+            }
+            if let Some(states) = &*input_shown.lock().unwrap() {
+                input_display::draw_input(overlaid.get_or_insert_with(|| frame.to_vec()), states);
+            }
+            let frame = overlaid.as_deref().unwrap_or(frame);
+
+            let filtered = video_filter.lock().unwrap().apply(frame);
+            scaler.apply(&filtered, picture_size, &mut picture.lock().unwrap());
+            gif_capture.lock().unwrap().push(frame);
+        }
+    });
+
+    let mut fast_forward_held = false;
+    let mut fast_forward_toggled = false;
+
+    let mut slot = 0;
+    let mut rewinding = false;
+
+    let mut paused = false;
+    let mut advance_frame = false;
+
+    let mut stats_shown = Instant::now();
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::CloseRequested => {
+                finish(&mut cpu, &movie, &mut battery);
+                *control_flow = ControlFlow::Exit;
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                let pressed = state == ElementState::Pressed;
+                let key = format!("{:?}", key);
+
+                if let Some((player, button)) = bindings.button(&key) {
+                    let mut state = cpu.controllers[player].state();
+                    state.set(button, pressed);
+                    cpu.controllers[player].set_state(state);
+                }
+
+                let hotkey = match hotkeys.hotkey(&key) {
+                    Some(Hotkey::Pause | Hotkey::Rewind | Hotkey::LoadState)
+                        if netplay.is_some() =>
+                    {
+                        None
+                    }
+                    hotkey => hotkey,
+                };
+
+                match hotkey {
+                    Some(Hotkey::FastForward) => fast_forward_held = pressed,
+                    Some(Hotkey::Rewind) => rewinding = pressed && rewind.is_enabled(),
+                    Some(Hotkey::FastForwardToggle) if pressed => {
+                        fast_forward_toggled = !fast_forward_toggled
+                    }
+                    Some(Hotkey::Slower) if pressed => pacer.slower(),
+                    Some(Hotkey::Faster) if pressed => pacer.faster(),
+                    Some(Hotkey::NormalSpeed) if pressed => pacer.set_speed(1.0),
+                    Some(Hotkey::Pause) if pressed => {
+                        paused = !paused;
+                        pacer.reset();
+
+                        // Only wake up for input while paused.
+                        *control_flow = if paused {
+                            ControlFlow::Wait
+                        } else {
+                            ControlFlow::Poll
+                        };
+                    }
+                    Some(Hotkey::FrameAdvance) if pressed && paused => advance_frame = true,
+                    Some(Hotkey::GifCapture) if pressed => save_gif(&gif_capture.lock().unwrap()),
+                    Some(Hotkey::Screenshot) if pressed => {
+                        save_screenshot(&cpu, &video_filter.lock().unwrap())
+                    }
+                    Some(Hotkey::NextPalette) if pressed => match palettes.switch() {
+                        Ok((source, colours)) => {
+                            let mut video_filter = video_filter.lock().unwrap();
+                            if video_filter.set_palette(colours) {
+                                info!("Switched to the {} palette", source);
+                                gif_capture.lock().unwrap().set_palette(colours);
+
+                                // Redraw now rather than on the next frame, which doesn't come
+                                // while paused.
+                                let filtered = video_filter.apply(cpu.ppu.frame());
+                                scaler.apply(&filtered, picture_size, &mut picture.lock().unwrap());
+                            } else {
+                                warn!("The NTSC filter makes its own colours.");
+                            }
+                        }
+                        Err(err) => error!("Failed to load the palette: {:#}", err),
+                    },
+                    Some(Hotkey::Trace) if pressed => {
+                        let enabled = !cpu.tracer.is_enabled();
+                        match cpu.tracer.set_enabled(enabled) {
+                            Ok(()) => info!("Tracing {}", if enabled { "on" } else { "off" }),
+                            Err(err) => error!("Failed to write the trace: {}", err),
+                        }
+                    }
+                    Some(Hotkey::SaveState) if pressed => {
+                        if let Err(err) = save_slots.save(&mut cpu, slot) {
+                            error!("Failed to save state: {:#}", err);
+                        }
+                    }
+                    Some(Hotkey::LoadState) if pressed => {
+                        match save_slots.load(&mut cpu, &SaveStateSource::Slot(slot)) {
+                            Ok(()) => seek_movie(&cpu, &mut movie),
+                            Err(err) => error!("Failed to load state: {:#}", err),
+                        }
+                    }
+                    Some(Hotkey::Coin1 | Hotkey::Coin2) if pressed => {
+                        let cycles = cpu.cycles;
+                        if let Some(vs_system) = &mut cpu.vs_system {
+                            vs_system.insert_coin((hotkey == Some(Hotkey::Coin2)) as usize, cycles);
+                        }
+                    }
+                    Some(Hotkey::Service) => {
+                        if let Some(vs_system) = &mut cpu.vs_system {
+                            vs_system.service = pressed;
+                        }
+                    }
+                    Some(Hotkey::Mute(channel)) if pressed => {
+                        let muted = !cpu.apu.is_muted(channel);
+                        cpu.apu.set_muted(channel, muted);
+                        info!("{} {}", if muted { "Muted" } else { "Unmuted" }, channel);
+                    }
+                    Some(Hotkey::NextSolo) if pressed => {
+                        let solo = match cpu.apu.solo() {
+                            None => Some(CHANNELS[0]),
+                            Some(channel) => CHANNELS.get(channel as usize + 1).copied(),
+                        };
+                        cpu.apu.set_solo(solo);
+                        match solo {
+                            Some(channel) => info!("Only mixing {}", channel),
+                            None => info!("Solo off"),
+                        }
+                    }
+                    Some(Hotkey::Stats) if pressed => show_stats = !show_stats,
+                    Some(Hotkey::InputDisplay) if pressed => show_input = !show_input,
+                    Some(Hotkey::NextSlot) if pressed => {
+                        slot = (slot + 1) % SLOTS;
+                        info!("Selected save state slot {}", slot);
+                    }
+                    _ => (),
+                }
+
+                pacer.set_fast_forward(fast_forward_held || fast_forward_toggled);
+
+                if pressed
+                    && matches!(
+                        hotkey,
+                        Some(
+                            Hotkey::Slower
+                                | Hotkey::Faster
+                                | Hotkey::NormalSpeed
+                                | Hotkey::Pause
+                                | Hotkey::Stats
+                        )
+                    )
+                {
+                    let report = show_stats.then(|| stats.report());
+                    window.set_title(&title(pacer.speed(), paused, report.as_ref()));
+                }
+            }
+            // The Zapper is aimed with the mouse and fired with the left button.
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(zapper) = &mut cpu.zapper {
+                    let position = pixels
+                        .window_pos_to_pixel((position.x as f32, position.y as f32))
+                        .ok()
+                        .map(|position| scaler.screen_position(position, picture_size));
+                    zapper.set_position(position);
+                }
+            }
+            WindowEvent::CursorLeft { .. } => {
+                if let Some(zapper) = &mut cpu.zapper {
+                    zapper.set_position(None);
+                }
+            }
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state,
+                ..
+            } => {
+                if let Some(zapper) = &mut cpu.zapper {
+                    zapper.set_trigger(state == ElementState::Pressed);
+                }
+            }
+            WindowEvent::Resized(size) => {
+                if let Err(err) = pixels.resize_surface(size.width, size.height) {
+                    error!("Failed to resize the window: {}", err);
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => (),
+        },
+        Event::MainEventsCleared => {
+            let frames = if paused {
+                std::mem::take(&mut advance_frame) as u32
+            } else {
+                pacer.next()
+            };
+
+            for _ in 0..frames {
+                let started = Instant::now();
+                let resume = if rewinding {
+                    // Go back a snapshot and run a frame from there to show it.
+                    match rewind.step_back(&mut cpu) {
+                        Ok(true) => {
+                            seek_movie(&cpu, &mut movie);
+                            if let Some(movie) = &mut movie {
+                                movie.before_frame(&mut cpu.controllers);
+                            }
+                            debugger::run_frame(&mut cpu)
+                        }
+                        Ok(false) => Resume::Continue,
+                        Err(err) => {
+                            error!("Failed to rewind: {:#}", err);
+                            Resume::Continue
+                        }
+                    }
+                } else {
+                    if let Some(movie) = &mut movie {
+                        movie.before_frame(&mut cpu.controllers);
+                    }
+                    if let Some(session) = &mut netplay {
+                        if let Err(err) = session.before_frame(&mut cpu) {
+                            error!("Netplay stopped, playing on alone: {:#}", err);
+                            netplay = None;
+                        }
+                    }
+                    *input_shown.lock().unwrap() = show_input
+                        .then(|| [cpu.controllers[0].state(), cpu.controllers[1].state()]);
+
+                    let resume = run_ahead.run_frame(&mut cpu).unwrap_or_else(|err| {
+                        error!("Failed to run ahead: {:#}", err);
+                        Resume::Continue
+                    });
+                    if let Some(session) = &netplay {
+                        session.after_frame(&mut cpu);
+                    }
+                    if !cpu.watches.log {
+                        *watches_shown.lock().unwrap() = cpu
+                            .watches
+                            .values(&cpu)
+                            .map(|value| value.to_string())
+                            .collect::<Vec<_>>()
+                            .join("\n");
+                    }
+
+                    if let Err(err) = rewind.push(&cpu) {
+                        error!("Failed to capture a rewind snapshot: {:#}", err);
+                    }
+                    if let Some(battery) = &mut battery {
+                        if let Err(err) = battery.after_frame(&cpu) {
+                            error!("Failed to save the battery RAM: {:#}", err);
+                        }
+                    }
+
+                    resume
+                };
+
+                stats.record_frame(started.elapsed());
+
+                // Quit from the debugger.
+                if resume == Resume::Quit {
+                    finish(&mut cpu, &movie, &mut battery);
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+            }
+
+            if show_stats && stats_shown.elapsed() >= STATS_INTERVAL {
+                stats_shown = Instant::now();
+                window.set_title(&title(pacer.speed(), paused, Some(&stats.report())));
+            }
+
+            pixels.frame_mut().copy_from_slice(&picture.lock().unwrap());
+            window.request_redraw();
+        }
+        Event::RedrawRequested(_) => {
+            if let Err(err) = pixels.render() {
+                error!("Failed to render: {}", err);
+                *control_flow = ControlFlow::Exit;
+            }
+        }
+        _ => (),
+    });
+}
+
+/// Window title, showing the speed when it isn't the console's, whether it's paused and the
+/// performance statistics when shown.
+fn title(speed: f64, paused: bool, stats: Option<&Report>) -> String {
+    let mut title = "nes".to_string();
+    if speed != 1.0 {
+        title += &format!(" ({}x)", speed);
+    }
+    if paused {
+        title += " [paused]";
+    }
+    if let Some(stats) = stats {
+        title += &format!(" - {}", stats);
+    }
+
+    title
+}
+
+/// Save the movie if one is being recorded, the battery RAM and wrap up the debugger, the event
+/// loop exits without dropping anything.
+/// Carry the movie on from the frame the console went to, stopping it if the frame isn't part of
+/// it.
+fn seek_movie(cpu: &Cpu, movie: &mut Option<MovieSession>) {
+    if let Some(session) = movie {
+        if let Err(err) = session.seek(cpu) {
+            error!("Stopped the movie: {:#}", err);
+            if let Err(err) = session.finish() {
+                error!("Failed to save the movie: {}", err);
+            }
+            *movie = None;
+        }
+    }
+}
+
+fn finish(cpu: &mut Cpu, movie: &Option<MovieSession>, battery: &mut Option<BatterySave>) {
+    debugger::finish(cpu);
+
+    if let Some(battery) = battery {
+        if let Err(err) = battery.flush(cpu) {
+            error!("Failed to save the battery RAM: {:#}", err);
+        }
+    }
+
+    if let Some(movie) = movie {
+        if let Err(err) = movie.finish() {
+            error!("Failed to save the movie: {}", err);
+        }
+    }
+}
+
+/// Save the captured frames to a new file in the working directory.
+fn save_gif(gif_capture: &GifCapture) {
+    let path = format!("nes-{}.gif", timestamp());
+
+    match gif_capture.save(&path) {
+        Ok(()) => info!("Saved GIF capture to \"{}\"", path),
+        Err(err) => error!("Failed to save GIF capture: {}", err),
+    }
+}
+
+/// Save the last frame to a new PNG in the working directory, in the colours on screen.
+fn save_screenshot(cpu: &Cpu, video_filter: &VideoFilter) {
+    let path = format!("nes-{}.png", timestamp());
+    let palette = video_filter.palette().unwrap_or(&SYSTEM_PALETTE);
+
+    match image::save_png_with(&path, SCREEN_WIDTH, SCREEN_HEIGHT, cpu.ppu.frame(), palette) {
+        Ok(()) => info!("Saved screenshot to \"{}\"", path),
+        Err(err) => error!("Failed to save screenshot: {}", err),
+    }
+}
+
+/// Seconds since the Unix epoch, naming the files saved.
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,256 @@
+/// Golden trace tests: run a ROM, log the state before each instruction like nestest.log does and
+/// compare it with a stored log, reporting the fields that differ.
+///
+/// A regression trace for another ROM is one line in the tests below, e.g.
+///   golden_trace!(test_game_intro, "test/game.nes", 5000);
+/// compares the first 5000 instructions from the reset vector with test/golden/test_game_intro.log,
+/// `start = 0xC000` before the count starts elsewhere. Run the test once with UPDATE_GOLDEN=1 to
+/// write the log, and again after changes that are meant to alter it.
+use crate::cpu::Cpu;
+use crate::ines::NesFile;
+use crate::opcode::{self, Operation};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+
+/// Where `golden_trace!` keeps its logs.
+pub const GOLDEN_DIR: &str = "test/golden";
+
+/// Set to write the logs instead of comparing with them.
+const UPDATE_VAR: &str = "UPDATE_GOLDEN";
+
+/// One line of a trace, split into the fields that are compared.
+#[derive(Debug, PartialEq)]
+pub struct LogLine {
+    fields: Vec<(&'static str, String)>,
+}
+
+impl LogLine {
+    const REGISTERS: [&'static str; 7] = ["A", "X", "Y", "P", "SP", "PPU", "CYC"];
+
+    /// e.g. "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD
+    /// PPU:  0, 21 CYC:7", the instruction takes up the first 48 columns.
+    pub fn parse(line: &str) -> Result<Self> {
+        if line.len() < 48 {
+            bail!("Line too short for a trace: \"{}\"", line);
+        }
+        let (instruction, registers) = line.split_at(48);
+
+        let mut fields = vec![
+            ("PC", instruction[..4].to_string()),
+            ("instruction", instruction[6..].trim_end().to_string()),
+        ];
+        for (i, &name) in Self::REGISTERS.iter().enumerate() {
+            let missing = || anyhow!("No {} in \"{}\"", name, line);
+
+            let start = registers.find(&format!("{}:", name)).ok_or_else(missing)? + name.len() + 1;
+            let end = match Self::REGISTERS.get(i + 1) {
+                Some(next) => registers.find(&format!(" {}:", next)).ok_or_else(missing)?,
+                None => registers.len(),
+            };
+            fields.push((name, registers[start..end].trim().to_string()));
+        }
+
+        Ok(LogLine { fields })
+    }
+
+    /// The line the CPU logs before executing `operation`, in nestest.log's format.
+    pub fn format(cpu: &Cpu, operation: &dyn Operation) -> String {
+        format!(
+            "{:04X}  {:<42}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:3},{:3} CYC:{}",
+            cpu.program_counter,
+            operation.dump(cpu).trim_end(),
+            cpu.a,
+            cpu.x,
+            cpu.y,
+            u8::from(cpu.status.clone()),
+            cpu.stack.as_stack_offset(),
+            cpu.ppu.scanline(),
+            cpu.ppu.dot(),
+            cpu.cycles
+        )
+    }
+
+    /// The fields that differ, e.g. "  A: expected "7F", got "6F"".
+    pub fn diff(&self, observed: &LogLine) -> Vec<String> {
+        self.fields
+            .iter()
+            .zip(&observed.fields)
+            .filter(|(expected, observed)| expected != observed)
+            .map(|((name, expected), (_, observed))| {
+                format!(
+                    "  {}: expected \"{}\", got \"{}\"",
+                    name, expected, observed
+                )
+            })
+            .collect()
+    }
+
+    fn address(&self) -> &str {
+        &self.fields[0].1
+    }
+
+    fn instruction(&self) -> &str {
+        &self.fields[1].1
+    }
+}
+
+/// Power on with the ROM, starting at `start` instead of the reset vector if given.
+fn power_on(rom: &str, start: Option<u16>) -> Result<Cpu> {
+    let nes_file =
+        NesFile::new(rom.to_string()).with_context(|| format!("Failed to load {}", rom))?;
+    let mut cpu = Cpu::new(nes_file);
+    if let Some(start) = start {
+        cpu.program_counter = start;
+    }
+    Ok(cpu)
+}
+
+fn next(cpu: &Cpu, line: usize) -> Result<Box<dyn Operation>> {
+    opcode::next(cpu).map_err(|err| anyhow!("Line {}: {}.", line, err))
+}
+
+/// Run the ROM for `instructions` instructions and log each one.
+pub fn trace(rom: &str, start: Option<u16>, instructions: usize) -> Result<Vec<String>> {
+    let mut cpu = power_on(rom, start)?;
+
+    let mut lines = Vec::with_capacity(instructions);
+    for i in 0..instructions {
+        let operation = next(&cpu, i + 1)?;
+        lines.push(LogLine::format(&cpu, operation.as_ref()));
+        cpu.step(operation);
+    }
+    Ok(lines)
+}
+
+/// Run the ROM alongside the golden log, for its first `instructions` lines or all of them.
+/// Stops at the first line that differs. Returns the CPU after the last instruction.
+pub fn compare(
+    rom: &str,
+    start: Option<u16>,
+    golden: &str,
+    instructions: Option<usize>,
+) -> Result<Cpu> {
+    let log = fs::read_to_string(golden).with_context(|| {
+        format!(
+            "Failed to read {}, run with {}=1 to write it",
+            golden, UPDATE_VAR
+        )
+    })?;
+    let mut cpu = power_on(rom, start)?;
+
+    let lines = log.lines().take(instructions.unwrap_or(usize::MAX));
+    for (i, line) in lines.enumerate() {
+        let expected = LogLine::parse(line).with_context(|| format!("{}:{}", golden, i + 1))?;
+
+        let operation = next(&cpu, i + 1).with_context(|| {
+            format!("Expected {} {}", expected.address(), expected.instruction())
+        })?;
+
+        let observed = LogLine::parse(&LogLine::format(&cpu, operation.as_ref()))?;
+        let diff = expected.diff(&observed);
+        if !diff.is_empty() {
+            bail!(
+                "{}:{}: mismatch before {} {}\n{}",
+                golden,
+                i + 1,
+                expected.address(),
+                expected.instruction(),
+                diff.join("\n")
+            );
+        }
+
+        cpu.step(operation);
+    }
+
+    Ok(cpu)
+}
+
+/// Compare with the golden log, or write it when UPDATE_GOLDEN is set.
+pub fn check(rom: &str, start: Option<u16>, golden: &str, instructions: usize) -> Result<()> {
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        let mut log = trace(rom, start, instructions)?.join("\n");
+        log.push('\n');
+        fs::create_dir_all(GOLDEN_DIR)?;
+        fs::write(golden, log).with_context(|| format!("Failed to write {}", golden))?;
+        return Ok(());
+    }
+
+    compare(rom, start, golden, Some(instructions))?;
+    Ok(())
+}
+
+/// A test comparing the first instructions of a ROM with test/golden/<test name>.log, starting at
+/// the reset vector unless an address is given.
+macro_rules! golden_trace {
+    ($name:ident, $rom:expr, $instructions:expr) => {
+        golden_trace!(@test $name, $rom, None, $instructions);
+    };
+    ($name:ident, $rom:expr, start = $start:expr, $instructions:expr) => {
+        golden_trace!(@test $name, $rom, Some($start), $instructions);
+    };
+    (@test $name:ident, $rom:expr, $start:expr, $instructions:expr) => {
+        #[test]
+        fn $name() -> anyhow::Result<()> {
+            $crate::golden::check(
+                $rom,
+                $start,
+                concat!("test/golden/", stringify!($name), ".log"),
+                $instructions,
+            )
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_line() -> Result<()> {
+        let line = LogLine::parse(
+            "C7F3  EA        NOP                             A:6F X:00 Y:00 P:6F SP:FB PPU:  1,256 CYC:199",
+        )?;
+        let fields: Vec<&str> = line
+            .fields
+            .iter()
+            .map(|(_, value)| value.as_str())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "C7F3",
+                "EA        NOP",
+                "6F",
+                "00",
+                "00",
+                "6F",
+                "FB",
+                "1,256",
+                "199"
+            ]
+        );
+
+        let other = LogLine::parse(
+            "C7F3  EA        NOP                             A:6F X:00 Y:00 P:6D SP:FB PPU:  1,259 CYC:200",
+        )?;
+        assert_eq!(
+            line.diff(&other),
+            [
+                "  P: expected \"6F\", got \"6D\"",
+                "  PPU: expected \"1,256\", got \"1,259\"",
+                "  CYC: expected \"199\", got \"200\"",
+            ]
+        );
+
+        assert!(LogLine::parse("C7F3  EA        NOP").is_err());
+        Ok(())
+    }
+
+    // The start of automated mode, cpu::tests::test_nestest compares the whole log.
+    golden_trace!(
+        test_nestest_automated,
+        "test/nestest.nes",
+        start = 0xC000,
+        72
+    );
+}
@@ -1,15 +1,42 @@
+use crate::region::Region;
+use crate::vs_system::VsPpu;
+use alloc::format;
+use alloc::vec::Vec;
 use anyhow::{anyhow, Result};
-use log::debug;
-use std::fs::File;
-use std::io::Read;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
 
 /// iNes Structure.
+#[derive(Clone)]
 pub struct NesFile {
     // Header for the given file.
     // header: Header,
 
     // PrgRom buffer.
     pub prg_rom: Vec<u8>,
+
+    // ChrRom buffer. Empty if the cartridge uses CHR RAM instead.
+    pub chr_rom: Vec<u8>,
+
+    /// How the nametables are mirrored.
+    pub mirroring: Mirroring,
+
+    /// Television standard from the header. Few dumps set it, so it can be overridden.
+    pub region: Region,
+
+    /// The cartridge keeps its RAM at $6000-$7FFF powered, games save to it.
+    pub battery: bool,
+
+    /// The PPU of the VS System cabinet the game runs on, `None` on the NES.
+    pub vs_ppu: Option<VsPpu>,
+}
+
+/// Nametable mirroring hard wired on the cartridge.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
 }
 
 /// Header of a iNes ROM
@@ -18,6 +45,23 @@ struct Header {
     ///
     /// This is used by the CPU.
     prg_rom_multiple_size: u8,
+
+    /// CHR Rom Size in multiples of 8kB.
+    ///
+    /// This is used by the PPU.
+    chr_rom_multiple_size: u8,
+
+    /// Flags 6, contains the mirroring and the lower nibble of the mapper number.
+    flags_6: u8,
+
+    /// Flags 7, contains the console type and the upper nibble of the mapper number.
+    flags_7: u8,
+
+    /// Flags 9, contains the TV system.
+    flags_9: u8,
+
+    /// The VS System's PPU and hardware, only in NES 2.0 headers.
+    vs_type: u8,
     // TODO: More flags :)
 }
 
@@ -28,6 +72,23 @@ impl Header {
     /// 16 KiB is the multiple.
     const PRG_ROM_MULTIPLE: usize = 16384;
 
+    /// 8 KiB is the multiple.
+    const CHR_ROM_MULTIPLE: usize = 8192;
+
+    const VERTICAL_MIRRORING_MASK: u8 = 0b0000_0001;
+    const TRAINER_MASK: u8 = 0b0000_0100;
+    const FOUR_SCREEN_MASK: u8 = 0b0000_1000;
+
+    const PAL_MASK: u8 = 0b0000_0001;
+
+    const VS_SYSTEM_MASK: u8 = 0b0000_0001;
+
+    /// VS System boards, the same as NROM unless they have more ROM than it.
+    const VS_MAPPER: u8 = 99;
+
+    /// Byte of NES 2.0 headers with the VS System's PPU and hardware.
+    const VS_TYPE_BYTE: usize = 13;
+
     /// Construct a header struct from the raw 16 header bytes.
     fn new(header: [u8; Self::HEADER_SIZE_BYTES]) -> Result<Self> {
         if header[0] != b'N' || header[1] != b'E' || header[2] != b'S' || header[3] != 0x1A {
@@ -36,14 +97,58 @@ impl Header {
 
         let result = Header {
             prg_rom_multiple_size: header[4],
+            chr_rom_multiple_size: header[5],
+            flags_6: header[6],
+            flags_7: header[7],
+            flags_9: header[9],
+            vs_type: header[Self::VS_TYPE_BYTE],
         };
 
-        for &byte in &header[6..] {
-            if byte != 0 {
+        // Only mapper 0 (NROM), or the VS System's NROM, without a trainer is supported.
+        let mapper = (result.flags_7 & 0xF0) | (result.flags_6 >> 4);
+        let is_vs = result.flags_7 & Self::VS_SYSTEM_MASK != 0;
+        if mapper != 0 && !(is_vs && mapper == Self::VS_MAPPER) {
+            return Err(anyhow!("Unsupported mapper {}, only NROM is.", mapper));
+        }
+        if result.flags_6 & Self::TRAINER_MASK != 0 {
+            return Err(anyhow!("Unsupported nes file format."));
+        }
+
+        // NES 2.0 is only needed for the VS System's PPU.
+        let nes2 = result.flags_7 & HeaderInfo::NES2_MASK == HeaderInfo::NES2_ID;
+        for (i, &byte) in header.iter().enumerate().skip(7) {
+            let allowed = match i {
+                7 if is_vs && nes2 => 0xF0 | Self::VS_SYSTEM_MASK | HeaderInfo::NES2_ID,
+                7 => 0xF0 | Self::VS_SYSTEM_MASK,
+                9 if !nes2 => Self::PAL_MASK,
+                Self::VS_TYPE_BYTE if is_vs && nes2 => 0xFF,
+                _ => 0,
+            };
+            if byte & !allowed != 0 {
                 return Err(anyhow!("Unsupported nes file format."));
             }
         }
 
+        // The other hardware has protection chips or two consoles.
+        if result.vs_type >> 4 != 0 {
+            return Err(anyhow!(
+                "Unsupported VS System hardware {}, only the UniSystem without protection is.",
+                result.vs_type >> 4
+            ));
+        }
+        if is_vs && VsPpu::from_header(result.vs_type).is_none() {
+            return Err(anyhow!("Unknown VS System PPU {}.", result.vs_type & 0x0F));
+        }
+
+        // NROM has 16 or 32 KiB of PRG ROM and at most 8 KiB of CHR ROM.
+        if !(1..=2).contains(&result.prg_rom_multiple_size) || result.chr_rom_multiple_size > 1 {
+            return Err(anyhow!(
+                "Unsupported ROM sizes for NROM, {} KiB PRG and {} KiB CHR.",
+                result.prg_rom_multiple_size as usize * 16,
+                result.chr_rom_multiple_size as usize * 8
+            ));
+        }
+
         Ok(result)
     }
 
@@ -51,19 +156,173 @@ impl Header {
     fn get_prg_rom_size(&self) -> usize {
         self.prg_rom_multiple_size as usize * Self::PRG_ROM_MULTIPLE
     }
+
+    /// Return the chr rom size in bytes.
+    fn get_chr_rom_size(&self) -> usize {
+        self.chr_rom_multiple_size as usize * Self::CHR_ROM_MULTIPLE
+    }
+
+    fn get_mirroring(&self) -> Mirroring {
+        Self::mirroring(self.flags_6)
+    }
+
+    fn mirroring(flags_6: u8) -> Mirroring {
+        if flags_6 & Self::FOUR_SCREEN_MASK != 0 {
+            Mirroring::FourScreen
+        } else if flags_6 & Self::VERTICAL_MIRRORING_MASK != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+
+    /// The cabinet's PPU on the VS System. iNES headers don't say which, the RP2C03 shows the usual
+    /// colours.
+    fn get_vs_ppu(&self) -> Option<VsPpu> {
+        if self.flags_7 & Self::VS_SYSTEM_MASK == 0 {
+            return None;
+        }
+        VsPpu::from_header(self.vs_type)
+    }
+
+    fn has_battery(&self) -> bool {
+        self.flags_6 & HeaderInfo::BATTERY_MASK != 0
+    }
+
+    /// iNES only tells PAL apart, Dendy has to be picked by hand.
+    fn get_region(&self) -> Region {
+        if self.flags_9 & Self::PAL_MASK != 0 {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+}
+
+/// Everything the header describes, read without the restrictions of `NesFile` so unsupported
+/// ROMs can be inspected too. See https://www.nesdev.org/wiki/INES and
+/// https://www.nesdev.org/wiki/NES_2.0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeaderInfo {
+    /// NES 2.0 rather than the original iNES format.
+    pub nes2: bool,
+
+    pub mapper: u16,
+
+    /// Variant of the mapper, only in NES 2.0 headers.
+    pub submapper: u8,
+
+    /// Sizes in bytes.
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+
+    pub mirroring: Mirroring,
+
+    /// The cartridge keeps its RAM powered.
+    pub battery: bool,
+
+    /// 512 bytes between the header and PRG ROM, loaded at $7000.
+    pub trainer: bool,
+
+    /// For the VS System arcade cabinets rather than the NES.
+    pub vs_system: bool,
+
+    /// `None` when the game runs on any region.
+    pub region: Option<Region>,
+}
+
+impl HeaderInfo {
+    const BATTERY_MASK: u8 = 0b0000_0010;
+    const NES2_MASK: u8 = 0b0000_1100;
+    const NES2_ID: u8 = 0b0000_1000;
+    const TRAINER_SIZE: usize = 512;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < Header::HEADER_SIZE_BYTES || &bytes[..4] != b"NES\x1A" {
+            return Err(anyhow!("Not an iNES file."));
+        }
+
+        let flags_6 = bytes[6];
+        let flags_7 = bytes[7];
+        let nes2 = flags_7 & Self::NES2_MASK == Self::NES2_ID;
+
+        let mut mapper = ((flags_7 & 0xF0) | (flags_6 >> 4)) as u16;
+        let mut submapper = 0;
+        let (prg_rom_size, chr_rom_size, region) = if nes2 {
+            mapper |= ((bytes[8] & 0x0F) as u16) << 8;
+            submapper = bytes[8] >> 4;
+
+            let region = match bytes[12] & 0b11 {
+                0 => Some(Region::Ntsc),
+                1 => Some(Region::Pal),
+                2 => None,
+                _ => Some(Region::Dendy),
+            };
+            (
+                Self::nes2_rom_size(bytes[4], bytes[9] & 0x0F, Header::PRG_ROM_MULTIPLE),
+                Self::nes2_rom_size(bytes[5], bytes[9] >> 4, Header::CHR_ROM_MULTIPLE),
+                region,
+            )
+        } else {
+            let region = if bytes[9] & Header::PAL_MASK != 0 {
+                Region::Pal
+            } else {
+                Region::Ntsc
+            };
+            (
+                bytes[4] as usize * Header::PRG_ROM_MULTIPLE,
+                bytes[5] as usize * Header::CHR_ROM_MULTIPLE,
+                Some(region),
+            )
+        };
+
+        Ok(HeaderInfo {
+            nes2,
+            mapper,
+            submapper,
+            prg_rom_size,
+            chr_rom_size,
+            mirroring: Header::mirroring(flags_6),
+            battery: flags_6 & Self::BATTERY_MASK != 0,
+            trainer: flags_6 & Header::TRAINER_MASK != 0,
+            vs_system: flags_7 & Header::VS_SYSTEM_MASK != 0,
+            region,
+        })
+    }
+
+    /// NES 2.0 sizes: the high nibble in byte 9 extends the size, unless it's $F and the low
+    /// byte is an exponent and a multiplier.
+    fn nes2_rom_size(low: u8, high: u8, unit: usize) -> usize {
+        if high == 0x0F {
+            (1usize << (low >> 2)) * ((low & 0b11) as usize * 2 + 1)
+        } else {
+            ((high as usize) << 8 | low as usize) * unit
+        }
+    }
+
+    /// The PRG and CHR ROM of the file, what ROM databases identify games by. Shorter if the file
+    /// is truncated.
+    pub fn rom_data<'a>(&self, file: &'a [u8]) -> &'a [u8] {
+        let start = Header::HEADER_SIZE_BYTES + if self.trainer { Self::TRAINER_SIZE } else { 0 };
+        let end = start + self.prg_rom_size + self.chr_rom_size;
+        &file[start.min(file.len())..end.min(file.len())]
+    }
 }
 
 impl NesFile {
+    #[cfg(feature = "std")]
     pub fn new(filename: String) -> Result<Self> {
         debug!("Parsing filename {}", filename);
 
-        let mut f = File::open(&filename)?;
+        NesFile::from_bytes(&std::fs::read(&filename)?)
+    }
 
+    /// Parse a ROM already in memory, e.g. one that didn't come from a file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut rest = bytes;
         let header = {
-            // Initialized immediately after.
-            let mut header_raw: [u8; Header::HEADER_SIZE_BYTES] =
-                unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-            f.read_exact(&mut header_raw)?;
+            let mut header_raw = [0u8; Header::HEADER_SIZE_BYTES];
+            header_raw.copy_from_slice(take(&mut rest, Header::HEADER_SIZE_BYTES)?);
 
             debug!("Received header: {:x?}", &header_raw);
 
@@ -71,18 +330,183 @@ impl NesFile {
         };
 
         let prg_rom = {
-            let mut buffer = vec![0; header.get_prg_rom_size()];
-
             debug!("Rom size is: {}", &header.get_prg_rom_size());
 
-            f.take(header.get_prg_rom_size() as u64)
-                .read_exact(&mut buffer)?;
+            let buffer = take(&mut rest, header.get_prg_rom_size())?.to_vec();
 
             debug!("Received prg rom: {:x?}", &buffer);
 
             buffer
         };
 
-        Ok(NesFile { prg_rom })
+        let chr_rom = {
+            debug!("Chr rom size is: {}", &header.get_chr_rom_size());
+
+            take(&mut rest, header.get_chr_rom_size())?.to_vec()
+        };
+
+        Ok(NesFile {
+            prg_rom,
+            chr_rom,
+            mirroring: header.get_mirroring(),
+            region: header.get_region(),
+            battery: header.has_battery(),
+            vs_ppu: header.get_vs_ppu(),
+        })
+    }
+
+    /// CRC32 of the PRG and CHR ROM, identifies the game regardless of the header.
+    pub fn checksum(&self) -> u32 {
+        crc32(self.prg_rom.iter().chain(&self.chr_rom))
+    }
+
+    /// MD5 of the PRG and CHR ROM, FCEUX identifies games with it.
+    pub fn md5(&self) -> [u8; 16] {
+        let mut context = md5::Context::new();
+        context.consume(&self.prg_rom);
+        context.consume(&self.chr_rom);
+        context.compute().0
+    }
+}
+
+/// Split `len` bytes off the front of `bytes`.
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if bytes.len() < len {
+        return Err(anyhow!(
+            "Truncated nes file, missing {} bytes.",
+            len - bytes.len()
+        ));
+    }
+
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken)
+}
+
+/// Standard CRC32 (as used by zip and ROM databases).
+pub fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+/// Small cartridges for tests. The frontends' tests and the other crates' get them through the
+/// `test-util` feature.
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util {
+    use super::NesFile;
+    use alloc::vec::Vec;
+
+    /// JMP $C000.
+    const SPIN: [u8; 3] = [0x4C, 0x00, 0xC0];
+
+    /// The iNES file of an NROM running `program` from $C000, where the reset vector points, with
+    /// CHR RAM.
+    pub fn nrom_file(program: &[u8]) -> Vec<u8> {
+        nrom_file_at(program, 0xC000)
+    }
+
+    /// Like `nrom_file` but with `program` and the reset vector at `start`, from $C000 up.
+    pub fn nrom_file_at(program: &[u8], start: u16) -> Vec<u8> {
+        let offset = (start - 0xC000) as usize;
+        let mut prg_rom = [0; 0x4000];
+        prg_rom[offset..offset + program.len()].copy_from_slice(program);
+        prg_rom[0x3FFC..0x3FFE].copy_from_slice(&start.to_le_bytes());
+
+        let mut rom = Vec::from(&b"NES\x1A\x01\x00"[..]);
+        rom.resize(16, 0);
+        rom.extend_from_slice(&prg_rom);
+        rom
+    }
+
+    pub fn nrom(program: &[u8]) -> NesFile {
+        NesFile::from_bytes(&nrom_file(program)).unwrap()
+    }
+
+    /// Spins in place at $C000.
+    pub fn spinning_nrom_file() -> Vec<u8> {
+        nrom_file(&SPIN)
+    }
+
+    pub fn spinning_nrom() -> NesFile {
+        nrom(&SPIN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_from_bytes() -> Result<()> {
+        let rom = std::fs::read("test/nestest.nes")?;
+        let nes_file = NesFile::from_bytes(&rom)?;
+        assert_eq!(nes_file.prg_rom.len(), 0x4000);
+        assert_eq!(nes_file.chr_rom.len(), 0x2000);
+
+        assert!(NesFile::from_bytes(&rom[..rom.len() - 1]).is_err());
+        assert!(NesFile::from_bytes(&rom[..8]).is_err());
+
+        assert_eq!(nes_file.region, Region::Ntsc);
+        let mut pal = rom.clone();
+        pal[9] = 1;
+        assert_eq!(NesFile::from_bytes(&pal)?.region, Region::Pal);
+
+        assert!(!nes_file.battery);
+        let mut battery = rom.clone();
+        battery[6] |= 0b0000_0010;
+        assert!(NesFile::from_bytes(&battery)?.battery);
+
+        let mut mmc1 = rom.clone();
+        mmc1[6] |= 0x10;
+        assert_eq!(
+            NesFile::from_bytes(&mmc1).err().map(|err| err.to_string()),
+            Some("Unsupported mapper 1, only NROM is.".to_string())
+        );
+
+        let mut nes2 = rom.clone();
+        nes2[6] |= 0b0000_0010;
+        nes2[7] = 0b0001_1000;
+        nes2[8] = 0x21;
+        nes2[12] = 3;
+        let info = HeaderInfo::parse(&nes2)?;
+        assert!(info.nes2 && info.battery);
+        assert_eq!((info.mapper, info.submapper), (0x110, 2));
+        assert_eq!((info.prg_rom_size, info.chr_rom_size), (0x4000, 0x2000));
+        assert_eq!(info.region, Some(Region::Dendy));
+        assert_eq!(crc32(info.rom_data(&nes2)), nes_file.checksum());
+        assert!(NesFile::from_bytes(&nes2).is_err());
+
+        // VS System, on mapper 99 and NES 2.0 for the PPU.
+        assert_eq!(nes_file.vs_ppu, None);
+        let mut vs = rom.clone();
+        vs[6] |= 0x30;
+        vs[7] = 0x61;
+        assert_eq!(NesFile::from_bytes(&vs)?.vs_ppu, Some(VsPpu::Rp2c03));
+        vs[7] |= 0b0000_1000;
+        vs[13] = 0x08;
+        assert_eq!(NesFile::from_bytes(&vs)?.vs_ppu, Some(VsPpu::Rc2c05(1)));
+        assert!(HeaderInfo::parse(&vs)?.vs_system);
+        vs[13] = 0x10;
+        assert!(NesFile::from_bytes(&vs).is_err());
+
+        // Without PRG ROM there's nothing to run.
+        let mut empty = rom[..16].to_vec();
+        empty[4] = 0;
+        assert!(NesFile::from_bytes(&empty).is_err());
+
+        Ok(())
     }
 }
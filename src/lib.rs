@@ -0,0 +1,66 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+/// NES emulator core, the console and the tools built around it.
+///
+/// `Nes` is the simplest way in: insert a cartridge, set the controllers and step frames. The
+/// components underneath (`cpu::Cpu`, `ppu::Ppu`, `apu::Apu`) are public for frontends that need
+/// more control, e.g. the debugger, save states or callbacks for the video and audio. Embedders
+/// can watch it run through `observer`.
+///
+/// Without the default `std` feature only the core is built, for no_std targets with an
+/// allocator: the console, its components, cheats and observers. Loading ROMs from files, save
+/// states, netplay, the debugger and the audio and video tools need `std`. The `mos6502` feature lets the CPU
+/// run on other machines' buses, with decimal mode.
+extern crate alloc;
+
+pub mod apu;
+#[cfg(feature = "std")]
+pub mod audio;
+#[cfg(feature = "std")]
+pub mod battery;
+#[cfg(feature = "mos6502")]
+pub mod bus;
+mod byte_array;
+pub mod cheats;
+pub mod clock;
+pub mod component;
+#[cfg(feature = "std")]
+pub mod config;
+mod console;
+pub mod controller;
+pub mod cpu;
+#[cfg(feature = "std")]
+pub mod debugger;
+pub mod deterministic;
+pub mod disasm;
+pub mod env;
+#[cfg(all(test, feature = "std"))]
+mod golden;
+pub mod ines;
+pub mod mapper;
+#[cfg(feature = "std")]
+pub mod movie;
+#[cfg(feature = "std")]
+pub mod netplay;
+pub mod observer;
+pub mod opcode;
+#[cfg(feature = "std")]
+pub mod patch;
+pub mod poke;
+pub mod ppu;
+pub mod region;
+#[cfg(feature = "std")]
+pub mod savestate;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod stats;
+pub mod test_rom;
+#[cfg(feature = "std")]
+pub mod video;
+pub mod vs_system;
+pub mod zapper;
+
+pub use console::Nes;
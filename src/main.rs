@@ -1,31 +1,1164 @@
-use anyhow::Result;
-use clap::Clap;
-use log::info;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{AppSettings, ArgSettings, Clap};
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+use tracing::{info, warn};
 
-mod cpu;
-mod ines;
-mod opcode;
+#[cfg(feature = "scripting")]
+use nes::script;
+#[cfg(feature = "server")]
+use nes::server;
+use nes::{
+    apu, audio, battery, cheats, config, cpu, debugger, deterministic, ines, movie, netplay, patch,
+    poke, region, savestate, test_rom, video, vs_system,
+};
+#[cfg(feature = "gui")]
+use nes::{stats, zapper};
+
+mod frontend;
 
 /// Basic emulator for the NES.
 #[derive(Clap)]
 #[clap(version = "0.0.1", author = "Justin Phu. <justinqphu@gmail.com>")]
+#[clap(setting = AppSettings::SubcommandsNegateReqs)]
 struct Opts {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Nes rom to test.
+    #[clap(setting = ArgSettings::Required)]
+    rom: Option<String>,
+
+    /// TOML configuration file, see src/config.rs. Defaults to ~/.config/nes/config.toml when it
+    /// exists, the flags below take precedence over it.
+    #[clap(long)]
+    config: Option<String>,
+
+    /// IPS or BPS patch applied to the rom when loading it, e.g. a translation. Can be repeated,
+    /// they're applied in order.
+    #[clap(long = "patch")]
+    patches: Vec<String>,
+
+    /// Television standard of the console, overrides the one in the ROM's header.
+    #[clap(long, possible_values = &["ntsc", "pal", "dendy"])]
+    region: Option<region::Region>,
+
+    /// Extra scanlines of CPU time after each vertical blank starts, reduces slowdown in games that
+    /// lag. The picture and sound are unchanged [default: 0].
+    #[clap(long)]
+    overclock: Option<u16>,
+
+    /// Run as fast as possible without a window or audio device.
+    #[clap(long)]
+    headless: bool,
+
+    /// Video filter applied to each frame [default: rgb].
+    #[clap(long, possible_values = &["rgb", "ntsc"])]
+    video_filter: Option<video::VideoFilter>,
+
+    /// Colours of the rgb filter: default, nestopia, fceux, composite, generated or the path of a
+    /// .pal file. F3 switches between them while running.
+    #[clap(long)]
+    palette: Option<video::palette::PaletteSource>,
+
+    /// Turn the hues of the generated palette, in degrees. Giving any of its knobs picks it.
+    #[clap(long, allow_hyphen_values = true)]
+    hue: Option<f64>,
+
+    /// Colour of the generated palette, 0 for greys [default: 1].
+    #[clap(long)]
+    saturation: Option<f64>,
+
+    /// Added to the levels of the generated palette, black is 0 and white 1 [default: 0].
+    #[clap(long, allow_hyphen_values = true)]
+    brightness: Option<f64>,
+
+    /// Scales the levels of the generated palette [default: 1].
+    #[clap(long)]
+    contrast: Option<f64>,
+
+    /// Power the levels of the generated palette are raised to [default: 1.22].
+    #[clap(long)]
+    gamma: Option<f64>,
+
+    /// Leave a channel out of the audio, can be repeated.
+    #[clap(long, possible_values = &["pulse1", "pulse2", "triangle", "noise", "dmc"])]
+    mute: Vec<apu::Channel>,
+
+    /// Only play a single channel.
+    #[clap(long, possible_values = &["pulse1", "pulse2", "triangle", "noise", "dmc"])]
+    solo: Option<apu::Channel>,
+
+    /// How the frame rate is kept [default: vsync].
+    #[cfg(feature = "gui")]
+    #[clap(long, possible_values = &["vsync", "sleep"])]
+    pacing: Option<frontend::pacing::PacingStrategy>,
+
+    /// Speed relative to the console, e.g. 2.0 runs twice as fast and 0.25 in slow motion
+    /// [default: 1.0].
+    #[cfg(feature = "gui")]
+    #[clap(long)]
+    speed: Option<f64>,
+
+    /// Frames to skip presenting for every presented frame while fast forwarding.
+    #[cfg(feature = "gui")]
+    #[clap(long, default_value = "0")]
+    frame_skip: u32,
+
+    /// Scale of the picture in the window [default: 3].
+    #[cfg(feature = "gui")]
+    #[clap(long)]
+    scale: Option<usize>,
+
+    /// Shape of the pixels, 8:7 matches an NTSC television [default: 1:1].
+    #[cfg(feature = "gui")]
+    #[clap(long, possible_values = &["1:1", "8:7"])]
+    aspect_ratio: Option<frontend::scaler::AspectRatio>,
+
+    /// Lines hidden at the top and bottom, or "top,bottom,left,right" [default: 0].
+    #[cfg(feature = "gui")]
+    #[clap(long)]
+    overscan: Option<frontend::scaler::Overscan>,
+
+    /// Plug a Zapper aimed with the mouse into the second port.
+    #[cfg(feature = "gui")]
+    #[clap(long)]
+    zapper: bool,
+
+    /// Seconds of play kept for rewinding, 0 disables rewinding [default: 30].
+    #[cfg(feature = "gui")]
+    #[clap(long)]
+    rewind_seconds: Option<f64>,
+
+    /// Frames in between rewind snapshots.
+    #[cfg(feature = "gui")]
+    #[clap(long, default_value = "5")]
+    rewind_interval: u32,
+
+    /// Frames to emulate ahead of the shown picture, hides the game's input lag.
+    #[cfg(feature = "gui")]
+    #[clap(long, default_value = "0")]
+    run_ahead: u32,
+
+    /// Show the frame rate, frame times and audio buffer in the window title, F10 toggles them.
+    #[cfg(feature = "gui")]
+    #[clap(long)]
+    show_stats: bool,
+
+    /// Draw the buttons held on each controller over the picture, F4 toggles them.
+    #[cfg(feature = "gui")]
+    #[clap(long)]
+    show_input: bool,
+
+    /// Draw the watch expressions' values over the picture rather than logging them.
+    #[cfg(feature = "gui")]
+    #[clap(long)]
+    show_watches: bool,
+
+    /// Seconds of video kept for GIF captures.
+    #[cfg(feature = "gui")]
+    #[clap(long, default_value = "10")]
+    gif_seconds: f64,
+
+    /// Run as a VS System cabinet with this PPU, for dumps whose header doesn't say which:
+    /// "2c03", "2c04-1" to "2c04-4" or "2c05-1" to "2c05-5".
+    #[clap(long)]
+    vs_ppu: Option<vs_system::VsPpu>,
+
+    /// The VS System's DIP switches, "1" for on and "0" for off from switch 1 to 8, e.g.
+    /// "01000000" [default: all off].
+    #[clap(long)]
+    dip_switches: Option<vs_system::DipSwitches>,
+
+    /// What RAM holds at power on: "zero", "ones", "alternating" ($00 and $FF every 4 bytes) or
+    /// "random", the same bytes every time for a seed given as e.g. "random:42" [default: zero].
+    #[clap(long)]
+    ram_pattern: Option<cpu::RamPattern>,
+
+    /// The same ROM, inputs and seed always give the same frames: RAM at power on holds random
+    /// bytes from the seed unless "--ram-pattern" says otherwise, scripts can't read the clock and
+    /// the battery save is neither loaded nor written.
+    #[clap(long)]
+    deterministic: bool,
+
+    /// Seed of the deterministic mode [default: 0].
+    #[clap(long, requires = "deterministic")]
+    seed: Option<u64>,
+
+    /// Run a test ROM reporting at $6000, like blargg's, print its result and exit with 1 if it
+    /// didn't pass.
+    #[clap(long)]
+    test_rom: bool,
+
+    /// Play back the inputs of a movie (or FCEUX's .fm2), headless runs stop at its end.
+    #[clap(long)]
+    play: Option<String>,
+
+    /// Record the inputs to a movie (or FCEUX's .fm2), saved when the window is closed. With
+    /// --load-state the movie starts from the state and carries it, FM2 movies can't.
+    #[cfg(feature = "gui")]
+    #[clap(long, conflicts_with_all = &["play", "host", "connect"])]
+    record: Option<String>,
+
+    /// Host a game for a second player on an address, e.g. "0.0.0.0:4002", waiting for them to
+    /// connect before starting. The host plays with controller 1.
+    #[clap(long, conflicts_with_all = &["play", "connect"])]
+    host: Option<String>,
+
+    /// Join a game hosted on an address as the second player, with the same ROM and settings.
+    #[clap(long, conflicts_with = "play")]
+    connect: Option<String>,
+
+    /// Frames in between pressing a button and the game seeing it when hosting, more hides a
+    /// slower connection [default: 2].
+    #[clap(long, requires = "host")]
+    input_delay: Option<u32>,
+
+    /// Frames the game may run ahead of the second player's input when hosting, guessing it and
+    /// rolling back when the guess was wrong. 0 waits for their input every frame [default: 0].
+    #[clap(long, requires = "host")]
+    rollback_frames: Option<u32>,
+
+    /// Stop in the debugger before executing the instruction at an address or label, can be
+    /// repeated.
+    #[clap(long = "break")]
+    breakpoints: Vec<String>,
+
+    /// Stop in the debugger as the "nmi", "irq" or "brk" handler is entered, can be repeated.
+    #[clap(long = "catch")]
+    interrupts: Vec<debugger::Interrupt>,
+
+    /// Log the value of an address, label or expression after every frame, e.g. "score:w" or
+    /// "[#enemies + index]", can be repeated. See "help" in the debugger.
+    #[clap(long = "watch")]
+    watches: Vec<String>,
+
+    /// Labels from FCEUX .nl files or ld65 label or debug files, can be repeated. FCEUX's files
+    /// next to the ROM ("<rom>.ram.nl", "<rom>.0.nl", ...) are always loaded.
+    #[clap(long)]
+    symbols: Vec<String>,
+
+    /// Game Genie or raw (AAAA:VV) cheat code, can be repeated.
+    #[clap(long = "cheat")]
+    cheats: Vec<cheats::Cheat>,
+
+    /// Write a value to memory at power on, e.g. "$0075=09", can be repeated.
+    #[clap(long = "poke")]
+    pokes: Vec<poke::Poke>,
+
+    /// Write a value to memory after every frame, e.g. "$0075=09", can be repeated.
+    #[clap(long = "poke-every-frame")]
+    frame_pokes: Vec<poke::Poke>,
+
+    /// Start with the trace log on, it can be toggled in the debugger or with F8 in the window.
+    #[clap(long)]
+    trace: bool,
+
+    /// Write the trace log to a file instead of stdout.
+    #[clap(long)]
+    trace_file: Option<String>,
+
+    /// Format of the trace log's lines.
+    #[clap(long, default_value = "nestest", possible_values = &["nestest", "fceux", "mesen"])]
+    trace_format: debugger::TraceFormat,
+
+    /// Record every write to the PPU's and APU's registers with its scanline and dot, writing each
+    /// frame's to a file. "events" in the debugger shows the last frame's.
+    #[clap(long)]
+    event_log: Option<String>,
+
+    /// What to do with opcodes the CPU doesn't implement: stop, skip them as NOPs or jam until
+    /// reset.
+    #[clap(long, default_value = "fail", possible_values = &["fail", "skip", "halt"])]
+    unknown_opcode: cpu::UnknownOpcodePolicy,
+
+    /// What to do with unofficial opcodes: run them, log each one executed with its address, or
+    /// stop in the debugger before executing them [default: run].
+    #[clap(long, possible_values = &["run", "log", "break"])]
+    unofficial_opcode: Option<cpu::UnofficialOpcodePolicy>,
+
+    /// Stop after running this many frames.
+    #[clap(long, requires = "headless")]
+    frames: Option<u32>,
+
+    /// Stop after running this many CPU cycles.
+    #[clap(long, requires = "headless")]
+    cycles: Option<u64>,
+
+    /// Write the internal RAM ($0000-$07FF) to a file once stopped.
+    #[clap(long, requires = "headless")]
+    dump_ram: Option<String>,
+
+    /// Save the last frame as a PNG once stopped.
+    #[clap(long, requires = "headless")]
+    screenshot: Option<String>,
+
+    /// Write the next instruction, the registers and the zero page to a file once stopped.
+    #[clap(long, requires = "headless")]
+    dump_state: Option<String>,
+
+    /// Stop at the first instruction jumping to itself, e.g. "JMP *" ending a test ROM, print the
+    /// state and exit with this code.
+    #[clap(long, requires = "headless")]
+    trap_exit_code: Option<i32>,
+
+    /// Count the executed addresses and opcodes, the report is shown on exit.
+    #[clap(long)]
+    profile: bool,
+
+    /// Rhai script run alongside the game, see src/script.rs for its API.
+    #[cfg(feature = "scripting")]
+    #[clap(long)]
+    script: Option<String>,
+
+    /// Load a save state at startup, "slot0" to "slot9" or a file.
+    #[clap(long)]
+    load_state: Option<savestate::SaveStateSource>,
+
+    /// Write the audio to a WAV file while running.
+    #[clap(long)]
+    dump_audio: Option<String>,
+
+    /// Run the control server on an address, e.g. "127.0.0.1:4000", instead of a frontend. See
+    /// src/server.rs for its commands.
+    #[cfg(feature = "server")]
+    #[clap(long)]
+    serve: Option<String>,
+
+    /// Stream the game over WebSocket on an address, e.g. "127.0.0.1:4001", instead of a
+    /// frontend. stream/index.html plays it in a browser.
+    #[cfg(feature = "stream")]
+    #[clap(long)]
+    stream: Option<String>,
+
+    /// Target audio latency in milliseconds [default: 60].
+    #[cfg(feature = "audio")]
+    #[clap(long)]
+    audio_latency: Option<u64>,
+
+    /// Audio output rate in Hz, the device's default otherwise.
+    #[cfg(feature = "audio")]
+    #[clap(long)]
+    audio_sample_rate: Option<u32>,
+
+    /// Frames the audio device asks for at a time, lower for less latency if it doesn't underrun.
+    #[cfg(feature = "audio")]
+    #[clap(long)]
+    audio_buffer_size: Option<u32>,
+
+    /// How the audio is resampled for the device: fast, average or high [default: average].
+    #[cfg(feature = "audio")]
+    #[clap(long)]
+    resampler: Option<audio::ResamplerQuality>,
+}
+
+// Tools run instead of the emulator, a doc comment would replace the description above.
+#[derive(Clap)]
+enum Command {
+    /// Run headless and print a hash of each frame, to compare with the output of earlier builds.
+    HashFrames(HashFrames),
+
+    /// Print the ROM's header, checksums and name in a ROM database, without running it.
+    Info(Info),
+
+    /// Disassemble the ROM's PRG banks, or a range of addresses at power on or in a save state.
+    Disasm(Disasm),
+
+    /// Run every ROM in a directory headless and write a JSON report of how far each one got.
+    Batch(Batch),
+
+    /// Save the ROM's CHR ROM, or the CHR RAM in a save state, as a PNG tile sheet.
+    Chr(Chr),
+
+    /// Save the four nametables as a PNG, after running some frames or in a save state.
+    Nametables(Nametables),
+
+    /// List the sprites in OAM and save them as a PNG, after running some frames or in a save
+    /// state.
+    Oam(Oam),
+}
+
+#[derive(Clap)]
+struct HashFrames {
+    /// Nes rom to run.
+    rom: String,
+
+    /// Frames to run.
+    #[clap(long, default_value = "600")]
+    frames: u32,
+
+    /// Only print one hash, of all the frames.
+    #[clap(long = "final")]
+    combined: bool,
+
+    /// Fill RAM at power on with random bytes from this seed, see "--deterministic".
+    #[clap(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Clap)]
+struct Info {
+    /// Nes rom to describe.
+    rom: String,
+
+    /// No-Intro DAT file (XML or clrmamepro) to look the ROM up in, `paths.database` in the config
+    /// file by default.
+    #[clap(long)]
+    database: Option<String>,
+}
+
+#[derive(Clap)]
+struct Disasm {
+    /// Nes rom to disassemble.
+    rom: String,
+
+    /// PRG ROM bank (16 KiB) to disassemble, can be repeated [default: all of them].
+    #[clap(long)]
+    bank: Vec<usize>,
+
+    /// Address the banks are mapped at [default: $C000 for the last bank, $8000 for the others].
+    #[clap(long, parse(try_from_str = debugger::parse_address))]
+    origin: Option<u16>,
+
+    /// Disassemble the memory the CPU sees instead of the banks, from this address or label
+    /// [default: the program counter].
+    #[clap(long)]
+    from: Option<String>,
+
+    /// Last address or label of the range [default: $FFFF].
+    #[clap(long)]
+    to: Option<String>,
+
+    /// Disassemble the range in a save state, "slot0" to "slot9" or a file, rather than at power
+    /// on.
+    #[clap(long)]
+    state: Option<savestate::SaveStateSource>,
+
+    /// Label the targets of the NMI, reset and IRQ vectors.
+    #[clap(long)]
+    vectors: bool,
+
+    /// Labels like `--symbols` when running, FCEUX's files next to the ROM are always loaded.
+    #[clap(long)]
+    symbols: Vec<String>,
+
+    /// Write the listing to a file instead of stdout.
+    #[clap(long)]
+    output: Option<String>,
+}
+
+#[derive(Clap)]
+struct Batch {
+    /// Directory of nes roms.
+    dir: String,
+
+    /// Frames to run each rom for.
+    #[clap(long, default_value = "600")]
+    frames: u32,
+
+    /// Roms run at the same time [default: the number of CPUs].
+    #[clap(long)]
+    jobs: Option<usize>,
+
+    /// Write the report to a file instead of stdout.
+    #[clap(long)]
+    output: Option<String>,
+}
+
+#[derive(Clap)]
+struct Chr {
+    /// Nes rom to export the tiles of.
+    rom: String,
+
+    /// PNG to write, 16 tiles wide with each pattern table below the last.
+    output: String,
+
+    /// Four colours like "0F,16,27,18", or one of the PPU's palettes from 0 to 7 with `--state`
+    /// [default: greyscale].
+    #[clap(long)]
+    palette: Option<frontend::chr::Palette>,
+
+    /// Export the CHR the PPU sees in a save state, "slot0" to "slot9" or a file, e.g. for CHR
+    /// RAM.
+    #[clap(long)]
+    state: Option<savestate::SaveStateSource>,
+}
+
+#[derive(Clap)]
+struct Nametables {
+    /// Nes rom to run.
     rom: String,
+
+    /// PNG to write, 512x480 with the four nametables in a 2x2 grid.
+    output: String,
+
+    #[clap(flatten)]
+    snapshot: Snapshot,
+
+    /// Outline the screen at the current scroll position.
+    #[clap(long)]
+    viewport: bool,
+}
+
+#[derive(Clap)]
+struct Oam {
+    /// Nes rom to run.
+    rom: String,
+
+    #[clap(flatten)]
+    snapshot: Snapshot,
+
+    /// List all 64 sprites, including the ones hidden below the screen.
+    #[clap(long)]
+    all: bool,
+
+    /// Save the sprites as a PNG the size of the screen, transparent where there are none.
+    #[clap(long)]
+    png: Option<String>,
+}
+
+/// Where the console is when a view of its memory is taken.
+#[derive(Clap)]
+struct Snapshot {
+    /// Start from a save state, "slot0" to "slot9" or a file, instead of power on.
+    #[clap(long)]
+    state: Option<savestate::SaveStateSource>,
+
+    /// Frames to run first.
+    #[clap(long, default_value = "0")]
+    frames: u32,
 }
 
 fn main() -> Result<()> {
-    env_logger::init();
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
 
     let opts: Opts = Opts::parse();
 
-    info!("Loading ROM \"{}\"", &opts.rom);
+    if let Some(command) = opts.command {
+        return run_command(command, &opts.config);
+    }
+
+    let config = match &opts.config {
+        Some(path) => config::Config::load(path)?,
+        None => config::Config::load_default()?,
+    };
 
-    let nes_file = ines::NesFile::new(opts.rom)?;
+    // Required unless there's a command.
+    let rom_path = config.rom_path(opts.rom.as_ref().unwrap());
+    let game_name = rom_path
+        .file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let rom_path = rom_path.to_string_lossy().into_owned();
+    info!("Loading ROM \"{}\"", rom_path);
 
+    let mut rom =
+        std::fs::read(&rom_path).with_context(|| format!("Failed to read \"{}\"", rom_path))?;
+    for path in &opts.patches {
+        rom = patch::apply_file(&rom, path)?;
+        info!("Applied patch \"{}\"", path);
+    }
+    let mut nes_file = ines::NesFile::from_bytes(&rom)?;
+    let game = config.game(&game_name, nes_file.checksum());
+
+    if let Some(region) = setting(opts.region, &game.emulation.region, "emulation.region")? {
+        nes_file.region = region;
+    }
+    info!("Running as {}", nes_file.region);
+    if let Some(vs_ppu) = setting(opts.vs_ppu, &game.emulation.vs_ppu, "emulation.vs_ppu")? {
+        nes_file.vs_ppu = Some(vs_ppu);
+    }
+    let rom = movie::RomId::new(&rom_path, &nes_file);
+
+    if opts.test_rom {
+        let report = test_rom::run(nes_file, test_rom::DEFAULT_CYCLE_BUDGET);
+        println!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
+
+    #[cfg_attr(not(feature = "gui"), allow(unused_mut))]
+    let mut movie = match &opts.play {
+        Some(path) => Some(movie::MovieSession::play(&rom, path)?),
+        None => None,
+    };
+
+    let has_battery = nes_file.battery;
     let mut cpu = cpu::Cpu::new(nes_file);
 
-    cpu.run();
+    if let Some(vs_system) = &mut cpu.vs_system {
+        info!("Running on a VS System with a {}", vs_system.ppu);
+        let dip_switches = setting(
+            opts.dip_switches,
+            &game.emulation.dip_switches,
+            "emulation.dip_switches",
+        )?;
+        vs_system.dip_switches = dip_switches.unwrap_or_default().0;
+    }
+
+    let ram_pattern = setting(
+        opts.ram_pattern,
+        &game.emulation.ram_pattern,
+        "emulation.ram_pattern",
+    )?;
+    match ram_pattern {
+        Some(ram_pattern) => cpu.set_ram_pattern(ram_pattern),
+        None if opts.deterministic => deterministic::apply(&mut cpu, opts.seed.unwrap_or(0)),
+        None => {}
+    }
+
+    for entry in &game.cheats {
+        let mut cheat: cheats::Cheat = entry.code.parse().map_err(anyhow::Error::msg)?;
+        cheat.enabled = entry.enabled;
+        cpu.cheats.add(cheat);
+    }
+    for cheat in opts.cheats {
+        cpu.cheats.add(cheat);
+    }
+
+    let mut power_on_pokes = Vec::new();
+    for entry in &game.pokes {
+        let poke: poke::Poke = entry.poke.parse().map_err(anyhow::Error::msg)?;
+        if entry.every_frame {
+            cpu.pokes.push(poke);
+        } else {
+            power_on_pokes.push(poke);
+        }
+    }
+    power_on_pokes.extend(opts.pokes);
+    cpu.pokes.extend(opts.frame_pokes);
+
+    for path in debugger::fceux_symbol_files(&rom_path)
+        .iter()
+        .chain(&opts.symbols)
+    {
+        let count = cpu.symbols.load(path)?;
+        info!("Loaded {} symbols from \"{}\"", count, path);
+    }
+
+    for addr in &opts.breakpoints {
+        cpu.breakpoints
+            .add(cpu.symbols.resolve(addr).map_err(anyhow::Error::msg)?);
+    }
+    for &interrupt in &opts.interrupts {
+        cpu.breakpoints.catch(interrupt, true);
+    }
+    for expression in &opts.watches {
+        let watch = debugger::Watch::parse(expression, &cpu.symbols).map_err(anyhow::Error::msg)?;
+        cpu.watches.add(watch);
+    }
+
+    cpu.tracer = match &opts.trace_file {
+        Some(path) => debugger::Tracer::create(opts.trace_format, path)
+            .with_context(|| format!("Failed to create \"{}\"", path))?,
+        None => debugger::Tracer::stdout(opts.trace_format),
+    };
+    cpu.overclock_scanlines = opts.overclock.or(game.emulation.overclock).unwrap_or(0);
+    cpu.unknown_opcode = opts.unknown_opcode;
+    cpu.unofficial_opcode = setting(
+        opts.unofficial_opcode,
+        &game.emulation.unofficial_opcode,
+        "emulation.unofficial_opcode",
+    )?
+    .unwrap_or_default();
+    cpu.detect_traps = opts.trap_exit_code.is_some();
+    cpu.tracer.set_enabled(opts.trace)?;
+    cpu.profiler.set_enabled(opts.profile);
+    if let Some(path) = &opts.event_log {
+        cpu.events = debugger::EventLog::create(path)
+            .with_context(|| format!("Failed to create \"{}\"", path))?;
+        cpu.events.set_enabled(true)?;
+    }
+
+    #[cfg(feature = "scripting")]
+    if let Some(path) = &opts.script {
+        info!("Running script \"{}\"", path);
+        cpu.script = Some(Box::new(script::ScriptHost::load(
+            path,
+            opts.deterministic,
+        )?));
+    }
+
+    let mut save_slots = savestate::SaveSlots::new(&rom_path);
+    if let Some(dir) = &config.paths.states {
+        save_slots = save_slots.in_directory(dir);
+    }
+
+    // A deterministic run can't start from what the last run saved, nor leave a save behind.
+    let battery = if has_battery && !opts.deterministic {
+        let mut battery = battery::BatterySave::new(&rom_path);
+        if let Some(dir) = &config.paths.saves {
+            battery = battery.in_directory(dir);
+        }
+        battery.load(&mut cpu)?;
+        battery.flush_on_exit();
+        Some(battery)
+    } else {
+        None
+    };
+
+    // After the battery save so they win, a loaded state replaces them.
+    for poke in &power_on_pokes {
+        cpu.write(poke.address, poke.value);
+    }
+
+    if let Some(source) = &opts.load_state {
+        save_slots.load(&mut cpu, source)?;
+    }
+    // A movie recorded from a save state starts from it, even with another state loaded.
+    if let Some(movie) = &mut movie {
+        movie.restore_start(&mut cpu)?;
+    }
+    // Counted from the state the run starts in.
+    cpu.cycle_limit = opts.cycles.map(|cycles| cpu.cycles + cycles);
+
+    let mute = match (opts.mute.is_empty(), &game.audio.mute) {
+        (true, Some(channels)) => channels
+            .iter()
+            .map(|channel| parse_setting(channel, "audio.mute"))
+            .collect::<Result<_>>()?,
+        _ => opts.mute,
+    };
+    for channel in mute {
+        cpu.apu.set_muted(channel, true);
+    }
+    cpu.apu
+        .set_solo(setting(opts.solo, &game.audio.solo, "audio.solo")?);
+
+    // Turning any knob of the generated palette picks it, made for the region's PPU.
+    let (generator, knobs_turned) = palette_generator(
+        [
+            opts.hue,
+            opts.saturation,
+            opts.brightness,
+            opts.contrast,
+            opts.gamma,
+        ],
+        &game.video,
+        cpu.region(),
+    );
+
+    // A VS System's PPU outputs RGB, in its own colours.
+    let mut video_filter = match setting(opts.video_filter, &game.video.filter, "video.filter")? {
+        Some(filter) => filter,
+        None => match cpu.ppu.vs_ppu() {
+            Some(vs_ppu) => video::VideoFilter::Palette(Box::new(vs_ppu.palette())),
+            None => video::VideoFilter::Rgb,
+        },
+    };
+    let palette = match setting(opts.palette, &game.video.palette, "video.palette")? {
+        Some(video::palette::PaletteSource::Generated(_)) => {
+            Some(video::palette::PaletteSource::Generated(generator))
+        }
+        None if knobs_turned => Some(video::palette::PaletteSource::Generated(generator)),
+        source => source,
+    };
+    if let Some(source) = &palette {
+        if !video_filter.set_palette(source.load()?) {
+            warn!("The NTSC filter makes its own colours, the palette is left unused.");
+        }
+    }
+
+    if let Some(path) = &opts.dump_audio {
+        info!("Dumping audio to \"{}\"", path);
+
+        let mut dump = audio::WavDump::create(path, cpu.apu.sample_rate())?;
+        cpu.apu.on_sample(move |sample| dump.push(sample));
+    }
+
+    #[cfg(feature = "server")]
+    if let Some(addr) = &opts.serve {
+        let listener = std::net::TcpListener::bind(addr)
+            .with_context(|| format!("Failed to listen on \"{}\"", addr))?;
+        return server::Server::new(nes::Nes::from(cpu)).serve(listener);
+    }
+
+    #[cfg(feature = "stream")]
+    if let Some(addr) = &opts.stream {
+        let listener = std::net::TcpListener::bind(addr)
+            .with_context(|| format!("Failed to listen on \"{}\"", addr))?;
+        return frontend::stream::run(cpu, listener);
+    }
+
+    // Last, both consoles have to be ready to run for their settings to be compared.
+    let netplay = match (&opts.host, &opts.connect) {
+        (Some(addr), _) => {
+            let latency = netplay::Latency {
+                input_delay: opts.input_delay.unwrap_or(netplay::DEFAULT_INPUT_DELAY),
+                rollback_frames: opts.rollback_frames.unwrap_or(0),
+            };
+            Some(netplay::Session::host(addr.as_str(), &rom, &cpu, latency)?)
+        }
+        (None, Some(addr)) => Some(netplay::Session::join(addr.as_str(), &rom, &cpu)?),
+        (None, None) => None,
+    };
+
+    let dumps = frontend::headless::Dumps {
+        ram: opts.dump_ram,
+        screenshot: opts.screenshot,
+        state: opts.dump_state,
+    };
+
+    if opts.headless {
+        return run_headless(
+            cpu,
+            video_filter,
+            movie,
+            netplay,
+            battery,
+            opts.frames,
+            &dumps,
+            opts.trap_exit_code,
+        );
+    }
+
+    // Kept until running stops, the sound stops when it's dropped.
+    #[cfg(feature = "audio")]
+    #[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+    let audio_output = audio::AudioOutput::start(
+        &mut cpu.apu,
+        &audio::AudioSettings {
+            latency: std::time::Duration::from_millis(
+                opts.audio_latency.or(game.audio.latency).unwrap_or(60),
+            ),
+            sample_rate: opts.audio_sample_rate.or(game.audio.sample_rate),
+            buffer_size: opts.audio_buffer_size.or(game.audio.buffer_size),
+            resampler: setting(opts.resampler, &game.audio.resampler, "audio.resampler")?
+                .unwrap_or_default(),
+        },
+    )?;
+
+    #[cfg(feature = "gui")]
+    {
+        let speed = opts.speed.or(game.emulation.speed).unwrap_or(1.0);
+        if speed <= 0.0 {
+            bail!("Speed must be positive, got {}.", speed);
+        }
+
+        let pacing = setting(opts.pacing, &game.video.pacing, "video.pacing")?
+            .unwrap_or(frontend::pacing::PacingStrategy::Vsync);
+        let mut pacer = frontend::pacing::FramePacer::new(pacing, speed);
+        pacer.set_frame_skip(opts.frame_skip);
+        let frame_rate = cpu.region().frame_rate();
+        pacer.set_frame_rate(frame_rate);
+
+        let mut gif_capture = video::capture::GifCapture::new(opts.gif_seconds, frame_rate);
+        if let Some(colours) = video_filter.palette() {
+            gif_capture.set_palette(*colours);
+        }
+
+        #[cfg_attr(not(feature = "audio"), allow(unused_mut))]
+        let mut stats = stats::Stats::new(frame_rate);
+        #[cfg(feature = "audio")]
+        stats.watch_audio(audio_output.buffer().clone());
+
+        let scaler = frontend::scaler::Scaler::new(
+            opts.scale.or(game.video.scale).unwrap_or(3),
+            setting(
+                opts.aspect_ratio,
+                &game.video.aspect_ratio,
+                "video.aspect_ratio",
+            )?
+            .unwrap_or(frontend::scaler::AspectRatio::Square),
+            setting(opts.overscan, &game.video.overscan, "video.overscan")?.unwrap_or_default(),
+        );
+        // Rewinding is off in netplay, no need to keep snapshots for it.
+        let rewind_seconds = match netplay {
+            Some(_) => 0.0,
+            None => opts
+                .rewind_seconds
+                .or(game.emulation.rewind_seconds)
+                .unwrap_or(30.0),
+        };
+
+        let bindings = frontend::bindings::KeyBindings::new(&config.input)?;
+        let hotkeys = frontend::hotkeys::Hotkeys::new(&config.input.hotkeys);
+
+        // Recording after loading a state starts the movie from it.
+        if let Some(path) = opts.record {
+            let start = match opts.load_state {
+                Some(_) => movie::MovieStart::SaveState(cpu.save_state()?),
+                None => movie::MovieStart::PowerOn,
+            };
+            info!("Recording movie to \"{}\"", path);
+            movie = Some(movie::MovieSession::record(rom, path, start, &cpu)?);
+        }
+
+        if opts.zapper {
+            cpu.zapper = Some(zapper::Zapper::default());
+        }
+        cpu.watches.log = !opts.show_watches;
+
+        frontend::window::run(
+            cpu,
+            video_filter,
+            video::palette::PaletteCycle::new(palette),
+            pacer,
+            gif_capture,
+            scaler,
+            bindings,
+            hotkeys,
+            movie,
+            netplay,
+            battery,
+            save_slots,
+            frontend::rewind::Rewind::new(rewind_seconds, opts.rewind_interval, frame_rate),
+            frontend::runahead::RunAhead::new(opts.run_ahead),
+            stats,
+            opts.show_stats,
+            opts.show_input,
+        )?;
+    }
+
+    #[cfg(not(feature = "gui"))]
+    run_headless(
+        cpu,
+        video_filter,
+        movie,
+        netplay,
+        battery,
+        opts.frames,
+        &dumps,
+        opts.trap_exit_code,
+    )?;
 
     Ok(())
 }
+
+/// The flag if given, otherwise the value from the config file.
+fn setting<T>(flag: Option<T>, config: &Option<String>, key: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match (flag, config) {
+        (Some(value), _) => Ok(Some(value)),
+        (None, Some(value)) => parse_setting(value, key).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+/// The generated palette for the region's PPU with the knobs turned by the flags or the config,
+/// and whether any were. The flags are hue, saturation, brightness, contrast and gamma.
+fn palette_generator(
+    flags: [Option<f64>; 5],
+    config: &config::VideoConfig,
+    region: region::Region,
+) -> (video::palette::PaletteGenerator, bool) {
+    let configured = [
+        config.hue,
+        config.saturation,
+        config.brightness,
+        config.contrast,
+        config.gamma,
+    ];
+    let knobs: Vec<Option<f64>> = flags
+        .iter()
+        .zip(&configured)
+        .map(|(flag, configured)| flag.or(*configured))
+        .collect();
+
+    // The hue turns from where the region's PPU puts them.
+    let defaults = video::palette::PaletteGenerator::new(region);
+    let generator = video::palette::PaletteGenerator {
+        hue: defaults.hue + knobs[0].unwrap_or(0.0),
+        saturation: knobs[1].unwrap_or(defaults.saturation),
+        brightness: knobs[2].unwrap_or(defaults.brightness),
+        contrast: knobs[3].unwrap_or(defaults.contrast),
+        gamma: knobs[4].unwrap_or(defaults.gamma),
+    };
+
+    (generator, knobs.iter().any(Option::is_some))
+}
+
+/// Parse a value from the config file, `key` names it in errors.
+fn parse_setting<T>(value: &str, key: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|err| anyhow!("Invalid {} in the config: {}", key, err))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_headless(
+    cpu: cpu::Cpu,
+    video_filter: video::VideoFilter,
+    movie: Option<movie::MovieSession>,
+    netplay: Option<netplay::Session>,
+    battery: Option<battery::BatterySave>,
+    frames: Option<u32>,
+    dumps: &frontend::headless::Dumps,
+    trap_exit_code: Option<i32>,
+) -> Result<()> {
+    if frontend::headless::run(cpu, video_filter, movie, netplay, battery, frames, dumps)? {
+        std::process::exit(trap_exit_code.unwrap_or(0));
+    }
+    Ok(())
+}
+
+fn run_command(command: Command, config_path: &Option<String>) -> Result<()> {
+    match command {
+        Command::HashFrames(args) => {
+            let mut cpu = cpu::Cpu::new(ines::NesFile::new(args.rom)?);
+            if let Some(seed) = args.seed {
+                deterministic::apply(&mut cpu, seed);
+            }
+            frontend::hash_frames::run(cpu, args.frames, args.combined)?;
+        }
+        Command::Info(args) => {
+            let config = load_config(config_path)?;
+            let database = args.database.or_else(|| {
+                let path = config.paths.database.as_ref()?;
+                Some(path.to_string_lossy().into_owned())
+            });
+            let rom = config.rom_path(&args.rom);
+            frontend::info::run(&rom.to_string_lossy(), database.as_deref())?;
+        }
+        Command::Disasm(args) => run_disasm(args, &load_config(config_path)?)?,
+        Command::Chr(args) => run_chr(args, &load_config(config_path)?)?,
+        Command::Nametables(args) => {
+            let cpu = snapshot_cpu(&args.rom, &args.snapshot, &load_config(config_path)?)?;
+            debugger::views::save_nametables(&cpu, &args.output, args.viewport)?;
+        }
+        Command::Oam(args) => {
+            let cpu = snapshot_cpu(&args.rom, &args.snapshot, &load_config(config_path)?)?;
+            debugger::views::print_oam(&cpu, &mut io::stdout(), args.all)?;
+            if let Some(path) = &args.png {
+                debugger::views::save_sprites(&cpu, path)?;
+            }
+        }
+        Command::Batch(args) => {
+            let jobs = args.jobs.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |jobs| jobs.get())
+            });
+            frontend::batch::run(
+                std::path::Path::new(&args.dir),
+                args.frames,
+                jobs,
+                args.output.as_deref(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn load_config(path: &Option<String>) -> Result<config::Config> {
+    match path {
+        Some(path) => config::Config::load(path),
+        None => config::Config::load_default(),
+    }
+}
+
+fn run_chr(args: Chr, config: &config::Config) -> Result<()> {
+    let rom_path = config.rom_path(&args.rom).to_string_lossy().into_owned();
+    let file =
+        std::fs::read(&rom_path).with_context(|| format!("Failed to read \"{}\"", rom_path))?;
+
+    let cpu = match &args.state {
+        Some(source) => {
+            let mut cpu = cpu::Cpu::new(ines::NesFile::from_bytes(&file)?);
+            load_state(&mut cpu, &rom_path, source, config)?;
+            Some(cpu)
+        }
+        None => None,
+    };
+
+    frontend::chr::save(
+        &file,
+        cpu.as_ref(),
+        args.palette.unwrap_or_default(),
+        &args.output,
+    )
+}
+
+/// Load the ROM and run it to the snapshot.
+fn snapshot_cpu(rom: &str, snapshot: &Snapshot, config: &config::Config) -> Result<cpu::Cpu> {
+    let rom_path = config.rom_path(rom).to_string_lossy().into_owned();
+    let mut cpu = cpu::Cpu::new(ines::NesFile::new(rom_path.clone())?);
+    if let Some(source) = &snapshot.state {
+        load_state(&mut cpu, &rom_path, source, config)?;
+    }
+
+    for frame in 0..snapshot.frames {
+        if let cpu::Stop::UnknownOpcode(err) = cpu.run_frame() {
+            bail!("Stopped after {} frames: {}", frame, err);
+        }
+    }
+    Ok(cpu)
+}
+
+/// Load a save state of the ROM at `rom_path`, looking for slots in `paths.states`.
+fn load_state(
+    cpu: &mut cpu::Cpu,
+    rom_path: &str,
+    source: &savestate::SaveStateSource,
+    config: &config::Config,
+) -> Result<()> {
+    let mut save_slots = savestate::SaveSlots::new(rom_path);
+    if let Some(dir) = &config.paths.states {
+        save_slots = save_slots.in_directory(dir);
+    }
+    save_slots.load(cpu, source)
+}
+
+fn run_disasm(args: Disasm, config: &config::Config) -> Result<()> {
+    let rom_path = config.rom_path(&args.rom).to_string_lossy().into_owned();
+    let file =
+        std::fs::read(&rom_path).with_context(|| format!("Failed to read \"{}\"", rom_path))?;
+
+    let mut symbols = debugger::Symbols::default();
+    for path in debugger::fceux_symbol_files(&rom_path)
+        .iter()
+        .chain(&args.symbols)
+    {
+        symbols.load(path)?;
+    }
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create \"{}\"", path))?,
+        )),
+        None => Box::new(io::stdout()),
+    };
+
+    if args.from.is_none() && args.to.is_none() && args.state.is_none() {
+        if args.vectors {
+            frontend::disasm::add_rom_vector_labels(&mut symbols, &file)?;
+        }
+        frontend::disasm::write_banks(&mut out, &file, &args.bank, args.origin, &symbols)?;
+        return Ok(out.flush()?);
+    }
+
+    let mut cpu = cpu::Cpu::new(ines::NesFile::from_bytes(&file)?);
+    if let Some(source) = &args.state {
+        load_state(&mut cpu, &rom_path, source, config)?;
+    }
+    if args.vectors {
+        frontend::disasm::add_vector_labels(&mut symbols, |addr| cpu.peek(addr));
+    }
+
+    let resolve = |value: &Option<String>, default| match value {
+        Some(value) => symbols.resolve(value).map_err(anyhow::Error::msg),
+        None => Ok(default),
+    };
+    let from = resolve(&args.from, cpu.program_counter)?;
+    let to = resolve(&args.to, 0xFFFF)?;
+    if from > to {
+        bail!(
+            "The range ends at ${:04X}, before it starts at ${:04X}.",
+            to,
+            from
+        );
+    }
+
+    let lines = frontend::disasm::disassemble_range(|addr| cpu.peek(addr), from, to);
+    frontend::disasm::write_listing(&mut out, &lines, &symbols)?;
+    Ok(out.flush()?)
+}
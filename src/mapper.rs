@@ -0,0 +1,189 @@
+/// The mapper's registers at $8000-$FFFF, as far as they control the cartridge's RAM at
+/// $6000-$7FFF.
+///
+/// Boards with a mapper can switch the RAM off, or only its writes, so a game crashing as the power
+/// goes down doesn't overwrite the saves in it. Some games turn it off on purpose and misbehave if
+/// it keeps working, e.g. they check it reads back as open bus. Only NROM is loaded from iNES files
+/// and its RAM is always on, the others are set on the CPU by hand.
+/// See https://www.nesdev.org/wiki/MMC1#PRG_bank_(internal,_$E000-$FFFF) and
+/// https://www.nesdev.org/wiki/MMC3#PRG_RAM_protect_($A001-$BFFF,_odd).
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum Mapper {
+    /// No registers, writes to the ROM go to the address space as before.
+    #[default]
+    Nrom,
+
+    /// The MMC1B and later, bit 4 of the PRG bank register disables the RAM.
+    Mmc1 {
+        /// The bits written so far, serially from bit 0. The marker bit shifts out on the fifth.
+        shift: u8,
+        prg_bank: u8,
+    },
+
+    /// Only its RAM protect register.
+    Mmc3 { ram_protect: u8 },
+}
+
+/// Whether the cartridge's RAM can be read and written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PrgRamControl {
+    /// Reads and writes reach the RAM, otherwise reads are open bus and writes are dropped.
+    pub enabled: bool,
+
+    /// Writes are dropped, reads still work.
+    pub write_protected: bool,
+}
+
+impl Mapper {
+    /// Bit 7 of a write to the MMC1 resets its shift register.
+    const MMC1_RESET_MASK: u8 = 0b1000_0000;
+
+    /// Empty shift register of the MMC1, the marker is shifted out by the fifth write.
+    const MMC1_SHIFT_EMPTY: u8 = 0b1_0000;
+
+    /// The MMC1's PRG bank register is written through $E000-$FFFF.
+    const MMC1_PRG_BANK: u16 = 0xE000;
+
+    const MMC1_RAM_DISABLE_MASK: u8 = 0b0001_0000;
+
+    const MMC3_RAM_ENABLE_MASK: u8 = 0b1000_0000;
+    const MMC3_WRITE_PROTECT_MASK: u8 = 0b0100_0000;
+
+    /// An MMC1 as it powers on, with its RAM enabled.
+    pub fn mmc1() -> Self {
+        Mapper::Mmc1 {
+            shift: Self::MMC1_SHIFT_EMPTY,
+            prg_bank: 0,
+        }
+    }
+
+    /// An MMC3 as it powers on, with its RAM enabled and writable.
+    pub fn mmc3() -> Self {
+        Mapper::Mmc3 {
+            ram_protect: Self::MMC3_RAM_ENABLE_MASK,
+        }
+    }
+
+    /// The registers go back to how they power on.
+    pub fn power_on(&mut self) {
+        *self = match self {
+            Mapper::Nrom => Mapper::Nrom,
+            Mapper::Mmc1 { .. } => Mapper::mmc1(),
+            Mapper::Mmc3 { .. } => Mapper::mmc3(),
+        };
+    }
+
+    /// Whether the mapper takes writes to $8000-$FFFF instead of the address space.
+    pub fn has_registers(&self) -> bool {
+        *self != Mapper::Nrom
+    }
+
+    /// A write to $8000-$FFFF.
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match self {
+            Mapper::Nrom => {}
+            Mapper::Mmc1 { shift, .. } if value & Self::MMC1_RESET_MASK != 0 => {
+                *shift = Self::MMC1_SHIFT_EMPTY;
+            }
+            Mapper::Mmc1 { shift, prg_bank } => {
+                let complete = *shift & 1 != 0;
+                *shift = (*shift >> 1) | ((value & 1) << 4);
+                if complete {
+                    if addr >= Self::MMC1_PRG_BANK {
+                        *prg_bank = *shift;
+                    }
+                    *shift = Self::MMC1_SHIFT_EMPTY;
+                }
+            }
+            // The protect register is at the odd addresses of $A000-$BFFF.
+            Mapper::Mmc3 { ram_protect } => {
+                if let 0xA000..=0xBFFF = addr {
+                    if addr & 1 == 1 {
+                        *ram_protect = value;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn prg_ram(&self) -> PrgRamControl {
+        match *self {
+            Mapper::Nrom => PrgRamControl {
+                enabled: true,
+                write_protected: false,
+            },
+            Mapper::Mmc1 { prg_bank, .. } => PrgRamControl {
+                enabled: prg_bank & Self::MMC1_RAM_DISABLE_MASK == 0,
+                write_protected: false,
+            },
+            Mapper::Mmc3 { ram_protect } => PrgRamControl {
+                enabled: ram_protect & Self::MMC3_RAM_ENABLE_MASK != 0,
+                write_protected: ram_protect & Self::MMC3_WRITE_PROTECT_MASK != 0,
+            },
+        }
+    }
+}
+
+impl PrgRamControl {
+    pub fn readable(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn writable(&self) -> bool {
+        self.enabled && !self.write_protected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write the 5 bits of `value` to the MMC1 one at a time, from bit 0.
+    fn write_mmc1(mapper: &mut Mapper, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mapper.write_register(addr, value >> bit & 1);
+        }
+    }
+
+    #[test]
+    fn test_mmc1() {
+        let mut mapper = Mapper::mmc1();
+        assert!(mapper.prg_ram().writable());
+
+        write_mmc1(&mut mapper, 0xE000, 0x10);
+        assert!(!mapper.prg_ram().readable());
+        write_mmc1(&mut mapper, 0xFFFF, 0x0F);
+        assert!(mapper.prg_ram().writable());
+
+        // Other registers, or a reset halfway, leave the PRG bank alone.
+        write_mmc1(&mut mapper, 0x8000, 0x10);
+        mapper.write_register(0xE000, 1);
+        mapper.write_register(0xE000, 0x80);
+        write_mmc1(&mut mapper, 0xE000, 0x00);
+        assert!(mapper.prg_ram().readable());
+
+        write_mmc1(&mut mapper, 0xE000, 0x10);
+        mapper.power_on();
+        assert_eq!(mapper, Mapper::mmc1());
+    }
+
+    #[test]
+    fn test_mmc3() {
+        let mut mapper = Mapper::mmc3();
+        assert!(mapper.prg_ram().writable());
+
+        mapper.write_register(0xA001, 0xC0);
+        let control = mapper.prg_ram();
+        assert!(control.readable() && !control.writable());
+
+        mapper.write_register(0xBFFF, 0x40);
+        assert!(!mapper.prg_ram().readable());
+
+        // Even addresses and the other registers aren't the protect register.
+        mapper.write_register(0xA000, 0x80);
+        mapper.write_register(0xC001, 0x80);
+        assert!(!mapper.prg_ram().readable());
+    }
+}
@@ -0,0 +1,312 @@
+/// FCEUX's FM2 text movie format, used to exchange movies with FCEUX and TASVideos.
+///
+/// Only the standard controllers are supported, no Four Score, Zapper or commands other than the
+/// initial power on. Movies start from power on, FCEUX's save states and ours can't be loaded by
+/// the other emulator.
+/// See http://fceux.com/web/help/fm2.html.
+use crate::controller::ControllerState;
+use crate::movie::{Movie, MovieStart, RomChecksum, RomId};
+use anyhow::{anyhow, Context, Result};
+use std::io::{BufRead, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The only version of the format.
+const VERSION: u32 = 3;
+
+/// Buttons in the order they're written, from bit 7 to bit 0 of the controller state.
+const BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+/// Port types in the header.
+const PORT_NONE: u32 = 0;
+const PORT_GAMEPAD: u32 = 1;
+
+/// Commands a frame can start with, powering on is implied at the start of a movie.
+const COMMAND_RESET: u8 = 0b01;
+const COMMAND_POWER: u8 = 0b10;
+
+/// Prefix of base64 encoded values, they may also be written in hex.
+const BASE64_PREFIX: &str = "base64:";
+
+/// Read a FM2 movie.
+pub fn read(reader: impl BufRead) -> Result<Movie> {
+    let mut movie = Movie::new(RomChecksum::Md5([0; 16]));
+    let mut ports = [PORT_GAMEPAD, PORT_GAMEPAD];
+
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end();
+
+        let result = if line.starts_with('|') {
+            read_frame(&mut movie, ports, line)
+        } else if line.is_empty() {
+            Ok(())
+        } else {
+            read_header(&mut movie, &mut ports, line)
+        };
+
+        result.with_context(|| format!("Invalid FM2 movie on line {}", line_number + 1))?;
+    }
+
+    Ok(movie)
+}
+
+/// Handle a "key value" header line.
+fn read_header(movie: &mut Movie, ports: &mut [u32; 2], line: &str) -> Result<()> {
+    let (key, value) = match line.find(' ') {
+        Some(space) => (&line[..space], &line[space + 1..]),
+        None => (line, ""),
+    };
+
+    let number = || {
+        value
+            .parse::<u32>()
+            .map_err(|_| anyhow!("Expected a number for \"{}\".", key))
+    };
+
+    match key {
+        "version" if number()? != VERSION => {
+            return Err(anyhow!("Unsupported version {}.", value));
+        }
+        "rerecordCount" => movie.rerecord_count = number()?,
+        "palFlag" if number()? != 0 => return Err(anyhow!("PAL movies aren't supported.")),
+        "binary" if number()? != 0 => return Err(anyhow!("Binary movies aren't supported.")),
+        "fourscore" if number()? != 0 => {
+            return Err(anyhow!("Four Score movies aren't supported."));
+        }
+        "port0" | "port1" => {
+            let port = number()?;
+            if port != PORT_NONE && port != PORT_GAMEPAD {
+                return Err(anyhow!("Only standard controllers are supported."));
+            }
+            ports[(key == "port1") as usize] = port;
+        }
+        "romChecksum" => {
+            let md5 = decode(value)?;
+            if md5.len() != 16 {
+                return Err(anyhow!("Invalid ROM checksum."));
+            }
+
+            let mut checksum = [0; 16];
+            checksum.copy_from_slice(&md5);
+            movie.rom_checksum = RomChecksum::Md5(checksum);
+        }
+        "savestate" => {
+            return Err(anyhow!(
+                "Movies starting from FCEUX's save states aren't supported."
+            ));
+        }
+        // Informational, or only used by FCEUX.
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Handle a "|commands|port0|port1|port2|" input line.
+fn read_frame(movie: &mut Movie, ports: [u32; 2], line: &str) -> Result<()> {
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < 5 {
+        return Err(anyhow!("Expected the commands and three ports."));
+    }
+
+    let commands = fields[1]
+        .parse::<u8>()
+        .map_err(|_| anyhow!("Invalid commands \"{}\".", fields[1]))?;
+    let powering_on = movie.frames.is_empty() && commands & !(COMMAND_RESET | COMMAND_POWER) == 0;
+    if commands != 0 && !powering_on {
+        return Err(anyhow!("Commands aren't supported."));
+    }
+
+    let mut states = [ControllerState::default(); 2];
+    for (state, (&port, field)) in states.iter_mut().zip(ports.iter().zip(&fields[2..4])) {
+        if port == PORT_GAMEPAD {
+            *state = read_controller(field)?;
+        }
+    }
+
+    movie.frames.push(states);
+    Ok(())
+}
+
+/// Buttons from a "RLDUTSBA" field, released buttons are spaces or dots.
+fn read_controller(field: &str) -> Result<ControllerState> {
+    if field.len() != BUTTONS.len() {
+        return Err(anyhow!("Invalid controller \"{}\".", field));
+    }
+
+    Ok(ControllerState(field.bytes().fold(0, |state, button| {
+        state << 1 | (button != b'.' && button != b' ') as u8
+    })))
+}
+
+fn write_controller(state: ControllerState) -> String {
+    BUTTONS
+        .iter()
+        .enumerate()
+        .map(|(i, &button)| {
+            if state.0 & (0x80 >> i) != 0 {
+                button as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// Decode a "base64:" or hex ("0x") value.
+fn decode(value: &str) -> Result<Vec<u8>> {
+    if let Some(encoded) = value.strip_prefix(BASE64_PREFIX) {
+        return base64::decode(encoded).map_err(|_| anyhow!("Invalid base64 \"{}\".", value));
+    }
+
+    let hex = value.strip_prefix("0x").unwrap_or(value);
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| anyhow!("Invalid hex \"{}\".", value))
+        })
+        .collect()
+}
+
+/// Write a FM2 movie of `rom`.
+pub fn write(movie: &Movie, rom: &RomId, mut writer: impl Write) -> Result<()> {
+    if movie.start != MovieStart::PowerOn {
+        return Err(anyhow!("FM2 movies can't start from a save state."));
+    }
+
+    writeln!(writer, "version {}", VERSION)?;
+    writeln!(writer, "emuVersion 0")?;
+    writeln!(writer, "rerecordCount {}", movie.rerecord_count)?;
+    writeln!(writer, "palFlag 0")?;
+    writeln!(writer, "romFilename {}", rom.name)?;
+    writeln!(
+        writer,
+        "romChecksum {}{}",
+        BASE64_PREFIX,
+        base64::encode(rom.md5)
+    )?;
+    writeln!(writer, "guid {}", guid())?;
+    writeln!(writer, "fourscore 0")?;
+    writeln!(writer, "microphone 0")?;
+    writeln!(writer, "port0 {}", PORT_GAMEPAD)?;
+    writeln!(writer, "port1 {}", PORT_GAMEPAD)?;
+    writeln!(writer, "port2 0")?;
+    writeln!(writer, "FDS 0")?;
+    writeln!(writer, "NewPPU 0")?;
+
+    for [controller_1, controller_2] in &movie.frames {
+        writeln!(
+            writer,
+            "|0|{}|{}||",
+            write_controller(*controller_1),
+            write_controller(*controller_2)
+        )?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Unique identifier of a new movie, formatted like "452DE2C3-EF43-2FA9-77AC-0677FC51543B".
+fn guid() -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+    let hex: String = md5::compute(time.to_le_bytes())
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOVIE: &str = "version 3
+emuVersion 22020
+rerecordCount 42
+palFlag 0
+romFilename smb
+romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==
+guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B
+fourscore 0
+port0 1
+port1 0
+port2 0
+comment author someone
+|2|........|||
+|0|R......A|||
+|0|...UT...|||
+";
+
+    #[test]
+    fn test_read() {
+        let movie = read(MOVIE.as_bytes()).unwrap();
+
+        assert_eq!(movie.rerecord_count, 42);
+        assert_eq!(movie.start, MovieStart::PowerOn);
+        assert_eq!(
+            movie.rom_checksum,
+            RomChecksum::Md5([
+                0x8E, 0x36, 0x30, 0x18, 0x6E, 0x35, 0xD4, 0x77, 0x23, 0x1B, 0xF8, 0xFD, 0x50, 0xE5,
+                0x4C, 0xDD
+            ])
+        );
+        assert_eq!(
+            movie.frames,
+            vec![
+                [ControllerState(0), ControllerState(0)],
+                [
+                    ControllerState(ControllerState::RIGHT | ControllerState::A),
+                    ControllerState(0)
+                ],
+                [
+                    ControllerState(ControllerState::UP | ControllerState::START),
+                    ControllerState(0)
+                ],
+            ]
+        );
+
+        assert!(read("version 3\n|0|........|||\n|1|........|||\n".as_bytes()).is_err());
+        assert!(read("version 3\nport1 2\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut movie = read(MOVIE.as_bytes()).unwrap();
+        movie.frames[0][1] = ControllerState(ControllerState::SELECT | ControllerState::LEFT);
+
+        let rom = RomId {
+            name: "smb".to_string(),
+            crc32: 0,
+            md5: match movie.rom_checksum {
+                RomChecksum::Md5(md5) => md5,
+                _ => unreachable!(),
+            },
+        };
+
+        let mut bytes = Vec::new();
+        write(&movie, &rom, &mut bytes).unwrap();
+
+        assert!(String::from_utf8_lossy(&bytes).contains("\n|0|........|.L...S..||\n"));
+        assert_eq!(read(&bytes[..]).unwrap(), movie);
+
+        // Neither emulator can load the other's save states.
+        movie.start = MovieStart::SaveState(vec![0xAB; 5]);
+        assert!(write(&movie, &rom, Vec::new()).is_err());
+        let state = format!("{}savestate 0xABAB\n", MOVIE);
+        assert!(read(state.as_bytes()).is_err());
+    }
+}
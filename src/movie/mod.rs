@@ -0,0 +1,549 @@
+/// Input movies, the controller states of every frame so a run can be replayed exactly.
+///
+/// The emulator is deterministic, so replaying the same inputs from the same starting point gives
+/// the same run. The starting point is powering on, or a save state stored in the movie so a run
+/// can be recorded from the middle of a game. Movies are stored in our own compact format, or
+/// FCEUX's FM2 format when the file ends in ".fm2".
+use crate::controller::{Controller, ControllerState};
+use crate::cpu::Cpu;
+use crate::ines::NesFile;
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use tracing::{info, warn};
+
+pub mod fm2;
+
+/// Identifies the ROM a movie is recorded with.
+pub struct RomId {
+    /// File name without the extension.
+    pub name: String,
+
+    /// See `NesFile::checksum()`.
+    pub crc32: u32,
+
+    /// See `NesFile::md5()`.
+    pub md5: [u8; 16],
+}
+
+impl RomId {
+    pub fn new(path: &str, nes_file: &NesFile) -> Self {
+        RomId {
+            name: Path::new(path)
+                .file_stem()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            crc32: nes_file.checksum(),
+            md5: nes_file.md5(),
+        }
+    }
+}
+
+/// Checksum of the ROM a movie was recorded with, each format uses a different one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RomChecksum {
+    Crc32(u32),
+    Md5([u8; 16]),
+}
+
+impl RomChecksum {
+    pub fn matches(&self, rom: &RomId) -> bool {
+        match *self {
+            RomChecksum::Crc32(crc32) => crc32 == rom.crc32,
+            RomChecksum::Md5(md5) => md5 == rom.md5,
+        }
+    }
+}
+
+/// Where a movie starts from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MovieStart {
+    /// The console is powered on with the ROM inserted.
+    PowerOn,
+
+    /// A save state taken by the emulator that recorded the movie.
+    SaveState(Vec<u8>),
+}
+
+/// Controller states for each frame, recorded from power on or a save state.
+///
+/// Stored as a small header followed by one byte per controller per frame, numbers are little
+/// endian:
+///
+/// | Bytes | Content                                          |
+/// |-------|--------------------------------------------------|
+/// | 7     | Magic "NESMOV\x1A"                               |
+/// | 1     | Format version                                   |
+/// | 1     | Checksum, 0 for CRC32 or 1 for MD5               |
+/// | 4/16  | Checksum of the ROM                              |
+/// | 4     | Rerecord count                                   |
+/// | 1     | Start, 0 for power on or 1 for a save state      |
+/// | 4 + n | Length and contents of the save state, if any    |
+/// | 4     | Number of frames                                 |
+/// | 2 * n | Controller 1 and 2 for each frame                |
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub rom_checksum: RomChecksum,
+
+    /// Times the movie was rewound and recorded over while being made.
+    pub rerecord_count: u32,
+
+    pub start: MovieStart,
+
+    /// Buttons held on both controllers during each frame.
+    pub frames: Vec<[ControllerState; 2]>,
+}
+
+impl Movie {
+    const MAGIC: &'static [u8; 7] = b"NESMOV\x1A";
+    const VERSION: u8 = 2;
+
+    pub fn new(rom_checksum: RomChecksum) -> Self {
+        Movie {
+            rom_checksum,
+            rerecord_count: 0,
+            start: MovieStart::PowerOn,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Load a movie, in the FM2 format if the file ends in ".fm2".
+    pub fn load(path: &str) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        if is_fm2(path) {
+            fm2::read(reader)
+        } else {
+            Movie::read(reader)
+        }
+    }
+
+    /// Save a movie, in the FM2 format if the file ends in ".fm2".
+    pub fn save(&self, path: &str, rom: &RomId) -> Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        if is_fm2(path) {
+            fm2::write(self, rom, writer)
+        } else {
+            self.write(writer)
+        }
+    }
+
+    pub fn read(mut reader: impl Read) -> Result<Self> {
+        let mut header = [0; 9];
+        reader.read_exact(&mut header)?;
+
+        if &header[..7] != Self::MAGIC {
+            return Err(anyhow!("Not a movie file."));
+        }
+        if header[7] != Self::VERSION {
+            return Err(anyhow!("Unsupported movie version {}.", header[7]));
+        }
+
+        let rom_checksum = match header[8] {
+            0 => RomChecksum::Crc32(read_u32(&mut reader)?),
+            1 => {
+                let mut md5 = [0; 16];
+                reader.read_exact(&mut md5)?;
+                RomChecksum::Md5(md5)
+            }
+            checksum => return Err(anyhow!("Unsupported movie checksum {}.", checksum)),
+        };
+
+        let rerecord_count = read_u32(&mut reader)?;
+
+        let mut start = [0];
+        reader.read_exact(&mut start)?;
+        let start = match start[0] {
+            0 => MovieStart::PowerOn,
+            1 => {
+                let mut state = vec![0; read_u32(&mut reader)? as usize];
+                reader.read_exact(&mut state)?;
+                MovieStart::SaveState(state)
+            }
+            start => return Err(anyhow!("Unsupported movie start {}.", start)),
+        };
+
+        let mut inputs = vec![0; read_u32(&mut reader)? as usize * 2];
+        reader.read_exact(&mut inputs)?;
+
+        Ok(Movie {
+            rom_checksum,
+            rerecord_count,
+            start,
+            frames: inputs
+                .chunks(2)
+                .map(|frame| [ControllerState(frame[0]), ControllerState(frame[1])])
+                .collect(),
+        })
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> Result<()> {
+        writer.write_all(Self::MAGIC)?;
+        writer.write_all(&[Self::VERSION])?;
+
+        match self.rom_checksum {
+            RomChecksum::Crc32(crc32) => {
+                writer.write_all(&[0])?;
+                writer.write_all(&crc32.to_le_bytes())?;
+            }
+            RomChecksum::Md5(md5) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&md5)?;
+            }
+        }
+
+        writer.write_all(&self.rerecord_count.to_le_bytes())?;
+
+        match &self.start {
+            MovieStart::PowerOn => writer.write_all(&[0])?,
+            MovieStart::SaveState(state) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&(state.len() as u32).to_le_bytes())?;
+                writer.write_all(state)?;
+            }
+        }
+
+        writer.write_all(&(self.frames.len() as u32).to_le_bytes())?;
+        for [controller_1, controller_2] in &self.frames {
+            writer.write_all(&[controller_1.0, controller_2.0])?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn is_fm2(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("fm2"))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// A movie being recorded from or played back into the controllers, frame by frame.
+pub enum MovieSession {
+    Recording {
+        movie: Movie,
+        path: String,
+        rom: RomId,
+
+        /// The console's frame count before the movie's first frame, see `seek()`.
+        first_frame: u64,
+    },
+    Playing {
+        movie: Movie,
+        frame: usize,
+        first_frame: u64,
+    },
+}
+
+impl MovieSession {
+    /// Record the controllers into a new movie saved to `path` by `finish()`. Starting from a save
+    /// state, it's the console's state before the first frame recorded, see `Cpu::save_state()`.
+    /// FM2 movies can't start from one, FCEUX has its own save states.
+    pub fn record(rom: RomId, path: String, start: MovieStart, cpu: &Cpu) -> Result<Self> {
+        if is_fm2(&path) && start != MovieStart::PowerOn {
+            return Err(anyhow!(
+                "FM2 movies can't start from a save state, record without loading one or to another format."
+            ));
+        }
+
+        let rom_checksum = if is_fm2(&path) {
+            RomChecksum::Md5(rom.md5)
+        } else {
+            RomChecksum::Crc32(rom.crc32)
+        };
+
+        Ok(MovieSession::Recording {
+            movie: Movie {
+                start,
+                ..Movie::new(rom_checksum)
+            },
+            path,
+            rom,
+            first_frame: cpu.ppu.frame_count(),
+        })
+    }
+
+    /// Play back the movie at `path`, warning when it was recorded with a different ROM.
+    pub fn play(rom: &RomId, path: &str) -> Result<Self> {
+        let movie = Movie::load(path)?;
+        if !movie.rom_checksum.matches(rom) {
+            warn!("Movie was recorded with a different ROM.");
+        }
+
+        info!("Playing movie \"{}\" ({} frames)", path, movie.frames.len());
+        Ok(MovieSession::Playing {
+            movie,
+            frame: 0,
+            first_frame: 0,
+        })
+    }
+
+    /// Put the console where a movie being played starts, call before the first frame. Movies
+    /// starting from power on leave it as it is.
+    pub fn restore_start(&mut self, cpu: &mut Cpu) -> Result<()> {
+        if let MovieSession::Playing {
+            movie, first_frame, ..
+        } = self
+        {
+            if let MovieStart::SaveState(state) = &movie.start {
+                cpu.load_state(state)
+                    .map_err(|err| anyhow!("Failed to load the movie's save state: {:#}", err))?;
+                info!("Movie starts from a save state");
+            }
+            *first_frame = cpu.ppu.frame_count();
+        }
+
+        Ok(())
+    }
+
+    /// Call when the console went to another frame, i.e. rewinding or loading a state. Recording
+    /// drops the frames after it and counts a rerecord, playing carries on from it. Fails when the
+    /// frame isn't part of the movie.
+    pub fn seek(&mut self, cpu: &Cpu) -> Result<()> {
+        let (movie, first_frame) = match self {
+            MovieSession::Recording {
+                movie, first_frame, ..
+            }
+            | MovieSession::Playing {
+                movie, first_frame, ..
+            } => (movie, *first_frame),
+        };
+
+        let frame_count = cpu.ppu.frame_count();
+        let index = frame_count
+            .checked_sub(first_frame)
+            .map(|index| index as usize)
+            .filter(|&index| index <= movie.frames.len())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Frame {} isn't part of the movie, it has frames {} to {}.",
+                    frame_count,
+                    first_frame,
+                    first_frame + movie.frames.len() as u64
+                )
+            })?;
+
+        match self {
+            MovieSession::Recording { movie, .. } => {
+                movie.frames.truncate(index);
+                movie.rerecord_count += 1;
+            }
+            MovieSession::Playing { frame, .. } => *frame = index,
+        }
+
+        Ok(())
+    }
+
+    /// Call before running each frame, records or sets the controllers.
+    pub fn before_frame(&mut self, controllers: &mut [Controller; 2]) {
+        match self {
+            MovieSession::Recording { movie, .. } => {
+                movie
+                    .frames
+                    .push([controllers[0].state(), controllers[1].state()]);
+            }
+            MovieSession::Playing { movie, frame, .. } => {
+                if let Some(states) = movie.frames.get(*frame) {
+                    controllers[0].set_state(states[0]);
+                    controllers[1].set_state(states[1]);
+                    *frame += 1;
+
+                    if *frame == movie.frames.len() {
+                        info!("Movie finished after {} frames", frame);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether every frame of a movie being played has been played.
+    pub fn is_finished(&self) -> bool {
+        match self {
+            MovieSession::Recording { .. } => false,
+            MovieSession::Playing { movie, frame, .. } => *frame >= movie.frames.len(),
+        }
+    }
+
+    /// Save the movie being recorded.
+    pub fn finish(&self) -> Result<()> {
+        if let MovieSession::Recording {
+            movie, path, rom, ..
+        } = self
+        {
+            movie.save(path, rom)?;
+            info!("Saved movie \"{}\" ({} frames)", path, movie.frames.len());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::UnknownOpcodePolicy;
+
+    #[test]
+    fn test_round_trip() {
+        let mut movie = Movie::new(RomChecksum::Crc32(0xDEAD_BEEF));
+        movie.frames.push([ControllerState(0), ControllerState(0)]);
+        movie.frames.push([
+            ControllerState(ControllerState::A | ControllerState::RIGHT),
+            ControllerState(ControllerState::START),
+        ]);
+
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 9 + 4 + 4 + 1 + 4 + 2 * 2);
+
+        assert_eq!(Movie::read(&bytes[..]).unwrap(), movie);
+        assert!(Movie::read(&bytes[1..]).is_err());
+
+        movie.rom_checksum = RomChecksum::Md5([7; 16]);
+        movie.rerecord_count = 12;
+        movie.start = MovieStart::SaveState(vec![1, 2, 3]);
+
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes).unwrap();
+        assert_eq!(Movie::read(&bytes[..]).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_record_and_play() -> Result<()> {
+        let rom = RomId {
+            name: String::new(),
+            crc32: 0,
+            md5: [0; 16],
+        };
+
+        let cpu = Cpu::new(NesFile::new("test/nestest.nes".to_string())?);
+        let mut controllers: [Controller; 2] = Default::default();
+        let mut recording = MovieSession::record(rom, String::new(), MovieStart::PowerOn, &cpu)?;
+
+        for buttons in &[0, ControllerState::A, ControllerState::B] {
+            controllers[0].set_state(ControllerState(*buttons));
+            recording.before_frame(&mut controllers);
+        }
+
+        let movie = match recording {
+            MovieSession::Recording { movie, .. } => movie,
+            _ => unreachable!(),
+        };
+        let mut playing = MovieSession::Playing {
+            movie,
+            frame: 0,
+            first_frame: 0,
+        };
+
+        let mut controllers: [Controller; 2] = Default::default();
+        for buttons in &[0, ControllerState::A, ControllerState::B] {
+            assert!(!playing.is_finished());
+            playing.before_frame(&mut controllers);
+            assert_eq!(controllers[0].state(), ControllerState(*buttons));
+        }
+        assert!(playing.is_finished());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_state_start() -> Result<()> {
+        let nes_file = NesFile::new("test/nestest.nes".to_string())?;
+        let rom = RomId::new("nestest.nes", &nes_file);
+        let mut cpu = Cpu::new(nes_file);
+        for _ in 0..10 {
+            cpu.run_frame();
+        }
+
+        // Record a few frames from the middle of the run.
+        let start = MovieStart::SaveState(cpu.save_state()?);
+        let fm2_rom = RomId::new(
+            "nestest.nes",
+            &NesFile::new("test/nestest.nes".to_string())?,
+        );
+        assert!(MovieSession::record(fm2_rom, "run.fm2".to_string(), start.clone(), &cpu).is_err());
+        let mut recording = MovieSession::record(rom, String::new(), start, &cpu)?;
+        let ram = cpu.peek_range(0, 0x800);
+        for _ in 0..5 {
+            recording.before_frame(&mut cpu.controllers);
+            cpu.run_frame();
+        }
+        let movie = match recording {
+            MovieSession::Recording { movie, .. } => movie,
+            _ => unreachable!(),
+        };
+
+        let mut bytes = Vec::new();
+        movie.write(&mut bytes)?;
+        let mut playing = MovieSession::Playing {
+            movie: Movie::read(&bytes[..])?,
+            frame: 0,
+            first_frame: 0,
+        };
+
+        // Playing it back starts where recording did, not from power on.
+        let mut cpu = Cpu::new(NesFile::new("test/nestest.nes".to_string())?);
+        playing.restore_start(&mut cpu)?;
+        assert_eq!(cpu.peek_range(0, 0x800), ram);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_seek() -> Result<()> {
+        let nes_file = NesFile::new("test/nestest.nes".to_string())?;
+        let rom = RomId::new("nestest.nes", &nes_file);
+        let mut cpu = Cpu::new(nes_file);
+        cpu.unknown_opcode = UnknownOpcodePolicy::Skip;
+        cpu.run_frame();
+
+        let mut recording = MovieSession::record(rom, String::new(), MovieStart::PowerOn, &cpu)?;
+        let mut state = Vec::new();
+        for frame in 0..5 {
+            if frame == 2 {
+                state = cpu.save_state()?;
+            }
+            cpu.controllers[0].set_state(ControllerState(frame));
+            recording.before_frame(&mut cpu.controllers);
+            cpu.run_frame();
+        }
+
+        // Recording over the last 3 frames drops them.
+        cpu.load_state(&state)?;
+        recording.seek(&cpu)?;
+        let (movie, first_frame) = match &recording {
+            MovieSession::Recording {
+                movie, first_frame, ..
+            } => (movie, *first_frame),
+            _ => unreachable!(),
+        };
+        assert_eq!(movie.frames.len(), 2);
+        assert_eq!(movie.rerecord_count, 1);
+
+        // Playing carries on from the frame loaded.
+        let mut playing = MovieSession::Playing {
+            movie: movie.clone(),
+            frame: 0,
+            first_frame,
+        };
+        playing.seek(&cpu)?;
+        assert!(playing.is_finished());
+
+        // A state from before the movie isn't part of it.
+        let mut cpu = Cpu::new(NesFile::new("test/nestest.nes".to_string())?);
+        cpu.unknown_opcode = UnknownOpcodePolicy::Skip;
+        assert!(playing.seek(&cpu).is_err());
+        cpu.run_frame();
+        playing.seek(&cpu)?;
+        assert!(!playing.is_finished());
+
+        Ok(())
+    }
+}
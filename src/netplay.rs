@@ -0,0 +1,772 @@
+/// Netplay, two players on two machines each running the same game over TCP.
+///
+/// The emulation is deterministic, so both consoles stay in sync as long as they start the same
+/// and see the same inputs on the same frames. Every frame each side sends its controller to the
+/// other. Inputs are applied `input_delay` frames after they're sent, which hides the round trip
+/// as long as it's shorter than the delay.
+///
+/// In lockstep each side waits for the other's input before running a frame, so a slow
+/// connection slows the game down. With rollback, GGPO style, it guesses the other player holds
+/// the same buttons as last time and runs on, up to `rollback_frames` frames ahead. When the
+/// guess turns out wrong, it loads the save state from before that frame and runs the frames
+/// again, out of sight, with the right inputs.
+///
+/// The host is player 1 and the guest player 2. Before starting, the guest sends its `Settings`
+/// as a JSON line and the host accepts it, with the `Latency` both sides use, or rejects it with
+/// the differences. Frames then go both ways as 13 bytes, little endian:
+///
+/// | Bytes | Content                                                       |
+/// |-------|---------------------------------------------------------------|
+/// | 4     | Frame the input is for                                        |
+/// | 1     | Buttons held                                                  |
+/// | 4     | Latest frame whose inputs are all known, $FFFFFFFF for none   |
+/// | 4     | CRC32 of the RAM at its start, to notice the consoles drifted |
+use crate::controller::ControllerState;
+use crate::cpu::Cpu;
+use crate::ines::crc32;
+use crate::movie::RomId;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Frames in between sending an input and running it, unless the host picks another.
+pub const DEFAULT_INPUT_DELAY: u32 = 2;
+
+/// How long to wait for the other player before giving up.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const PROTOCOL: &str = "nes-netplay";
+const VERSION: u32 = 3;
+
+const MESSAGE_SIZE: usize = 13;
+
+/// Sent as the frame of the checksum before there's one.
+const NO_CHECKSUM: u32 = u32::MAX;
+
+/// Size of the console's internal RAM, mirrored up to $1FFF.
+const RAM_SIZE: usize = 0x800;
+
+/// Everything that has to match for the two consoles to run the same.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Settings {
+    /// MD5 of the ROM in hex, see `NesFile::md5()`.
+    pub rom_md5: String,
+
+    pub region: String,
+    pub overclock_scanlines: u16,
+
+    /// The enabled cheats, e.g. "$0075 = 09".
+    pub cheats: Vec<String>,
+
+    /// The pokes written after every frame, e.g. "$0075=09". The ones at power on are in the RAM
+    /// checksum.
+    pub pokes: Vec<String>,
+
+    pub dip_switches: Option<u8>,
+
+    /// CRC32 of the RAM and the cartridge's RAM before starting, differs when only one side
+    /// loaded a save state or a battery save.
+    pub ram_checksum: u32,
+}
+
+impl Settings {
+    /// The settings of `cpu`, about to start running `rom`.
+    pub fn new(rom: &RomId, cpu: &Cpu) -> Self {
+        Settings {
+            rom_md5: rom.md5.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            region: cpu.region().to_string(),
+            overclock_scanlines: cpu.overclock_scanlines,
+            cheats: cpu
+                .cheats
+                .iter()
+                .filter(|cheat| cheat.enabled)
+                .map(|cheat| match cheat.compare {
+                    Some(compare) => format!(
+                        "${:04X} = {:02X} if {:02X}",
+                        cheat.address, cheat.value, compare
+                    ),
+                    None => format!("${:04X} = {:02X}", cheat.address, cheat.value),
+                })
+                .collect(),
+            pokes: cpu.pokes.iter().map(ToString::to_string).collect(),
+            dip_switches: cpu
+                .vs_system
+                .as_ref()
+                .map(|vs_system| vs_system.dip_switches),
+            ram_checksum: ram_checksum(cpu),
+        }
+    }
+
+    /// What differs between the host's settings and the guest's, empty when they match.
+    pub fn differences(&self, guest: &Settings) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.rom_md5 != guest.rom_md5 {
+            differences.push("the ROM differs".to_string());
+        }
+        if self.region != guest.region {
+            differences.push(format!(
+                "the region is {} for the host, {} for the guest",
+                self.region, guest.region
+            ));
+        }
+        if self.overclock_scanlines != guest.overclock_scanlines {
+            differences.push(format!(
+                "overclocking is {} scanlines for the host, {} for the guest",
+                self.overclock_scanlines, guest.overclock_scanlines
+            ));
+        }
+        if self.cheats != guest.cheats {
+            differences.push("the cheats differ".to_string());
+        }
+        if self.pokes != guest.pokes {
+            differences.push("the pokes differ".to_string());
+        }
+        if self.dip_switches != guest.dip_switches {
+            differences.push("the DIP switches differ".to_string());
+        }
+        if self.ram_checksum != guest.ram_checksum {
+            differences.push(
+                "the RAM differs, from a save state or battery save only one side has".to_string(),
+            );
+        }
+        differences
+    }
+}
+
+/// How far behind the players' inputs the consoles run, picked by the host.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Latency {
+    /// Frames in between pressing a button and the game seeing it, on both sides.
+    pub input_delay: u32,
+
+    /// Frames run ahead of the other player's input by guessing it, 0 for lockstep.
+    pub rollback_frames: u32,
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency {
+            input_delay: DEFAULT_INPUT_DELAY,
+            rollback_frames: 0,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Hello {
+    protocol: String,
+    version: u32,
+    settings: Settings,
+}
+
+#[derive(Deserialize, Serialize)]
+enum Reply {
+    Accepted(Latency),
+    Rejected { reason: String },
+}
+
+/// A player's input for a frame.
+struct Message {
+    frame: u32,
+    input: ControllerState,
+
+    /// The sender's checksum of the RAM at the start of `checksum_frame`.
+    checksum_frame: u32,
+    checksum: u32,
+}
+
+impl Message {
+    fn encode(&self) -> [u8; MESSAGE_SIZE] {
+        let mut bytes = [0; MESSAGE_SIZE];
+        bytes[..4].copy_from_slice(&self.frame.to_le_bytes());
+        bytes[4] = self.input.0;
+        bytes[5..9].copy_from_slice(&self.checksum_frame.to_le_bytes());
+        bytes[9..].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8; MESSAGE_SIZE]) -> Self {
+        let u32_at = |at: usize| {
+            u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+        };
+        Message {
+            frame: u32_at(0),
+            input: ControllerState(bytes[4]),
+            checksum_frame: u32_at(5),
+            checksum: u32_at(9),
+        }
+    }
+}
+
+/// A frame that has been run, kept until the other player's input for it is known.
+struct RunFrame {
+    /// The other player's input it was run with, a guess until theirs arrives.
+    remote: ControllerState,
+
+    /// CRC32 of the RAM at its start.
+    checksum: u32,
+
+    /// Save state from its start, for rolling back to when the guess was wrong.
+    state: Option<Vec<u8>>,
+}
+
+/// A game with another player, connected and ready to run.
+pub struct Session {
+    writer: TcpStream,
+
+    /// The other player's messages, read on a thread so guessing doesn't wait for them.
+    messages: Receiver<Message>,
+
+    /// Controller 1 for the host, 2 for the guest.
+    local_player: usize,
+
+    latency: Latency,
+
+    /// Frames run so far, the next frame to run.
+    frame: u32,
+
+    /// Oldest frame kept in `local`, `remote` and `run`, the frames before are settled.
+    base: u32,
+
+    /// The local player's inputs from `base`, up to the frame they were last sent for.
+    local: VecDeque<ControllerState>,
+
+    /// The other player's inputs from `base` up to `remote_end`, the frames received.
+    remote: VecDeque<ControllerState>,
+    remote_end: u32,
+
+    /// The other player's latest input, the guess for the frames they haven't sent yet.
+    last_remote: ControllerState,
+
+    /// The frames run from `base`.
+    run: VecDeque<RunFrame>,
+
+    /// The earliest frame run with a wrong guess, to roll back to before the next one.
+    mispredicted: Option<u32>,
+
+    /// Checksums of the frames whose inputs are all known, ours up to `checked_end` and the other
+    /// player's, compared as both arrive.
+    checksums: VecDeque<(u32, u32)>,
+    remote_checksums: VecDeque<(u32, u32)>,
+    checked_end: u32,
+
+    /// Our last settled frame and its checksum, sent with each input.
+    latest_checksum: Option<(u32, u32)>,
+
+    /// Frames run again after a wrong guess.
+    rolled_back_frames: u64,
+
+    /// What the local player held before the frame, put back after it so the frontend's input
+    /// isn't mixed with the other player's.
+    local_state: ControllerState,
+}
+
+impl Session {
+    /// Wait on `addr` for a guest and check they're running the same game as `cpu`.
+    pub fn host(
+        addr: impl ToSocketAddrs,
+        rom: &RomId,
+        cpu: &Cpu,
+        latency: Latency,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr).context("Failed to listen for the other player")?;
+        info!("Waiting for the other player on {}", listener.local_addr()?);
+        Session::accept(&listener, rom, cpu, latency)
+    }
+
+    /// Accept a guest on `listener`, rejecting them if their settings differ.
+    pub fn accept(
+        listener: &TcpListener,
+        rom: &RomId,
+        cpu: &Cpu,
+        latency: Latency,
+    ) -> Result<Self> {
+        let (stream, peer) = listener.accept()?;
+        let (mut reader, mut writer) = split(stream)?;
+
+        let hello: Hello = read_json(&mut reader)?;
+        if hello.protocol != PROTOCOL || hello.version != VERSION {
+            write_json(
+                &mut writer,
+                &Reply::Rejected {
+                    reason: format!("the host runs netplay version {}", VERSION),
+                },
+            )?;
+            bail!("{} runs an incompatible netplay version.", peer);
+        }
+
+        let differences = Settings::new(rom, cpu).differences(&hello.settings);
+        if !differences.is_empty() {
+            let reason = differences.join(", ");
+            write_json(
+                &mut writer,
+                &Reply::Rejected {
+                    reason: reason.clone(),
+                },
+            )?;
+            bail!("Rejected {}: {}.", peer, reason);
+        }
+
+        write_json(&mut writer, &Reply::Accepted(latency))?;
+        info!("Playing with {} as player 1", peer);
+        Session::new(reader, writer, 0, latency)
+    }
+
+    /// Connect to a host at `addr`, who checks we're running the same game as them.
+    pub fn join(addr: impl ToSocketAddrs, rom: &RomId, cpu: &Cpu) -> Result<Self> {
+        let stream = TcpStream::connect(addr).context("Failed to connect to the other player")?;
+        let peer = stream.peer_addr()?;
+        let (mut reader, mut writer) = split(stream)?;
+
+        write_json(
+            &mut writer,
+            &Hello {
+                protocol: PROTOCOL.to_string(),
+                version: VERSION,
+                settings: Settings::new(rom, cpu),
+            },
+        )?;
+
+        match read_json(&mut reader)? {
+            Reply::Accepted(latency) => {
+                info!("Playing with {} as player 2", peer);
+                Session::new(reader, writer, 1, latency)
+            }
+            Reply::Rejected { reason } => Err(anyhow!("The host rejected us: {}.", reason)),
+        }
+    }
+
+    fn new(
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+        local_player: usize,
+        latency: Latency,
+    ) -> Result<Self> {
+        // From here on only `before_frame` gives up waiting.
+        reader.get_ref().set_read_timeout(None)?;
+
+        // Nobody has pressed anything yet for the first frames.
+        let delay = || (0..latency.input_delay).map(|_| ControllerState::default());
+        Ok(Session {
+            writer,
+            messages: read_messages(reader),
+            local_player,
+            latency,
+            frame: 0,
+            base: 0,
+            local: delay().collect(),
+            remote: delay().collect(),
+            remote_end: latency.input_delay,
+            last_remote: ControllerState::default(),
+            run: VecDeque::new(),
+            mispredicted: None,
+            checksums: VecDeque::new(),
+            remote_checksums: VecDeque::new(),
+            checked_end: 0,
+            latest_checksum: None,
+            rolled_back_frames: 0,
+            local_state: ControllerState::default(),
+        })
+    }
+
+    /// The controller played on this side, 0 for the host and 1 for the guest.
+    pub fn local_player(&self) -> usize {
+        self.local_player
+    }
+
+    pub fn latency(&self) -> Latency {
+        self.latency
+    }
+
+    /// Frames run a second time since the start, after guessing the other player's input wrong.
+    pub fn rolled_back_frames(&self) -> u64 {
+        self.rolled_back_frames
+    }
+
+    /// Call before running each frame. Sends the local player's input, read from the first
+    /// controller, and sets both controllers for the frame.
+    ///
+    /// Waits for the other player's input in lockstep, or when it's `rollback_frames` frames
+    /// late. Rolls the console back and runs the frames again when an earlier guess was wrong.
+    pub fn before_frame(&mut self, cpu: &mut Cpu) -> Result<()> {
+        let frame = self.frame;
+
+        self.local_state = cpu.controllers[0].state();
+        self.local.push_back(self.local_state);
+        let (checksum_frame, checksum) = self.latest_checksum.unwrap_or((NO_CHECKSUM, 0));
+        let message = Message {
+            frame: frame + self.latency.input_delay,
+            input: self.local_state,
+            checksum_frame,
+            checksum,
+        };
+        // When the other player has gone, waiting for their input says so. Until then the frames
+        // they already sent can still run.
+        if let Err(err) = self.writer.write_all(&message.encode()) {
+            debug!(
+                "Failed to send the input for frame {}: {}",
+                message.frame, err
+            );
+        }
+
+        while let Ok(message) = self.messages.try_recv() {
+            self.receive(message)?;
+        }
+        while frame >= self.remote_end + self.latency.rollback_frames {
+            let message = self
+                .messages
+                .recv_timeout(TIMEOUT)
+                .map_err(|err| match err {
+                    RecvTimeoutError::Timeout => anyhow!("The other player stopped responding."),
+                    RecvTimeoutError::Disconnected => anyhow!("Lost the other player."),
+                })?;
+            self.receive(message)?;
+        }
+
+        if let Some(mispredicted) = self.mispredicted.take() {
+            self.roll_back(cpu, mispredicted)?;
+        }
+
+        let remote = self.remote_input(frame);
+        self.run.push_back(RunFrame {
+            remote,
+            checksum: ram_checksum(cpu),
+            state: self.save_if_guessed(cpu, frame)?,
+        });
+        self.settle()?;
+
+        self.set_controllers(cpu, frame, remote);
+        self.frame += 1;
+        Ok(())
+    }
+
+    /// Call after running each frame, gives the first controller back to the local player.
+    pub fn after_frame(&self, cpu: &mut Cpu) {
+        cpu.controllers[0].set_state(self.local_state);
+    }
+
+    /// Take the other player's input for the next frame they haven't sent, noting when a frame
+    /// already run guessed it wrong.
+    fn receive(&mut self, message: Message) -> Result<()> {
+        if message.frame != self.remote_end {
+            bail!(
+                "The other player sent frame {} when frame {} was expected.",
+                message.frame,
+                self.remote_end
+            );
+        }
+
+        if let Some(run) = self.run.get((self.remote_end - self.base) as usize) {
+            if run.remote != message.input && self.mispredicted.is_none() {
+                self.mispredicted = Some(self.remote_end);
+            }
+        }
+        self.remote.push_back(message.input);
+        self.last_remote = message.input;
+        self.remote_end += 1;
+
+        if message.checksum_frame != NO_CHECKSUM {
+            self.remote_checksums
+                .push_back((message.checksum_frame, message.checksum));
+        }
+        Ok(())
+    }
+
+    /// Load the state from the start of the `from` frame and run the frames up to the current
+    /// one again with the inputs known now, without the video, audio or debugger seeing them.
+    fn roll_back(&mut self, cpu: &mut Cpu, from: u32) -> Result<()> {
+        let state = self.run[(from - self.base) as usize]
+            .state
+            .take()
+            .ok_or_else(|| anyhow!("No save state to roll back to frame {}.", from))?;
+        cpu.load_state(&state)?;
+
+        let breakpoints = std::mem::take(&mut cpu.breakpoints);
+        let tracer = std::mem::take(&mut cpu.tracer);
+        let profiler = std::mem::take(&mut cpu.profiler);
+        let observers = std::mem::take(&mut cpu.observers);
+        #[cfg(feature = "scripting")]
+        let script = cpu.script.take();
+        cpu.ppu.pause_frame_callback(true);
+        cpu.apu.pause_sample_callbacks(true);
+
+        for frame in from..self.frame {
+            let remote = self.remote_input(frame);
+            if frame != from {
+                let state = self.save_if_guessed(cpu, frame)?;
+                let run = &mut self.run[(frame - self.base) as usize];
+                run.checksum = ram_checksum(cpu);
+                run.state = state;
+            }
+            self.run[(frame - self.base) as usize].remote = remote;
+
+            self.set_controllers(cpu, frame, remote);
+            cpu.run_frame();
+        }
+
+        cpu.ppu.pause_frame_callback(false);
+        cpu.apu.pause_sample_callbacks(false);
+        cpu.breakpoints = breakpoints;
+        cpu.tracer = tracer;
+        cpu.profiler = profiler;
+        cpu.observers = observers;
+        #[cfg(feature = "scripting")]
+        {
+            cpu.script = script;
+        }
+
+        self.rolled_back_frames += (self.frame - from) as u64;
+        Ok(())
+    }
+
+    /// Compare the checksums of the frames whose inputs are now all known and forget them, they
+    /// can't be rolled back to any more.
+    fn settle(&mut self) -> Result<()> {
+        let settled = self.remote_end.min(self.frame);
+        while self.checked_end <= settled {
+            let run = &self.run[(self.checked_end - self.base) as usize];
+            self.latest_checksum = Some((self.checked_end, run.checksum));
+            self.checksums.push_back((self.checked_end, run.checksum));
+            self.checked_end += 1;
+        }
+
+        // Both sides settle frames in order, skip the ones only one side has sent a checksum for.
+        while let (Some(&(frame, checksum)), Some(&(remote_frame, remote_checksum))) =
+            (self.checksums.front(), self.remote_checksums.front())
+        {
+            if frame < remote_frame {
+                self.checksums.pop_front();
+            } else if frame > remote_frame {
+                self.remote_checksums.pop_front();
+            } else {
+                if checksum != remote_checksum {
+                    bail!("Out of sync with the other player at frame {}.", frame);
+                }
+                self.checksums.pop_front();
+                self.remote_checksums.pop_front();
+            }
+        }
+
+        while self.base < settled {
+            self.local.pop_front();
+            self.remote.pop_front();
+            self.run.pop_front();
+            self.base += 1;
+        }
+        Ok(())
+    }
+
+    /// The other player's input for a frame, or the guess when it hasn't arrived.
+    fn remote_input(&self, frame: u32) -> ControllerState {
+        if frame < self.remote_end {
+            self.remote[(frame - self.base) as usize]
+        } else {
+            self.last_remote
+        }
+    }
+
+    /// A save state to roll back to, when the frame starting now runs with a guess.
+    fn save_if_guessed(&self, cpu: &Cpu, frame: u32) -> Result<Option<Vec<u8>>> {
+        if frame < self.remote_end {
+            return Ok(None);
+        }
+        cpu.save_state().map(Some)
+    }
+
+    fn set_controllers(&self, cpu: &mut Cpu, frame: u32, remote: ControllerState) {
+        let mut states = [remote; 2];
+        states[self.local_player] = self.local[(frame - self.base) as usize];
+        cpu.controllers[0].set_state(states[0]);
+        cpu.controllers[1].set_state(states[1]);
+    }
+}
+
+impl Drop for Session {
+    /// Also stops the thread reading the other player's messages.
+    fn drop(&mut self) {
+        let _ = self.writer.shutdown(Shutdown::Both);
+    }
+}
+
+/// Buffered reads and unbuffered writes on the same connection.
+fn split(stream: TcpStream) -> Result<(BufReader<TcpStream>, TcpStream)> {
+    // Every frame sends a small message, don't let Nagle hold them back.
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    Ok((BufReader::new(stream.try_clone()?), stream))
+}
+
+/// Read the other player's messages on a thread until the connection fails or closes, the
+/// messages received before are still taken.
+fn read_messages(mut reader: BufReader<TcpStream>) -> Receiver<Message> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        let mut bytes = [0; MESSAGE_SIZE];
+        if let Err(err) = reader.read_exact(&mut bytes) {
+            debug!("Stopped reading the other player's inputs: {}", err);
+            break;
+        }
+        if sender.send(Message::decode(&bytes)).is_err() {
+            break;
+        }
+    });
+    receiver
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(reader: &mut BufReader<TcpStream>) -> Result<T> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        bail!("The other player disconnected.");
+    }
+    serde_json::from_str(&line).context("Invalid handshake from the other player")
+}
+
+fn write_json(writer: &mut TcpStream, value: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// CRC32 of the internal RAM and the cartridge's RAM, where games keep their state.
+fn ram_checksum(cpu: &Cpu) -> u32 {
+    crc32(cpu.memory[..RAM_SIZE].iter().chain(cpu.prg_ram()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::NesFile;
+
+    fn new_cpu() -> Result<(RomId, Cpu)> {
+        let nes_file = NesFile::new("test/nestest.nes".to_string())?;
+        Ok((RomId::new("nestest.nes", &nes_file), Cpu::new(nes_file)))
+    }
+
+    /// Run `frames` frames holding `input(frame)`, returning what both controllers held.
+    fn play(
+        session: &mut Session,
+        cpu: &mut Cpu,
+        frames: u32,
+        input: impl Fn(u32) -> u8,
+    ) -> Result<Vec<[ControllerState; 2]>> {
+        let mut seen = Vec::new();
+        for frame in 0..frames {
+            cpu.controllers[0].set_state(ControllerState(input(frame)));
+            session.before_frame(cpu)?;
+            seen.push([cpu.controllers[0].state(), cpu.controllers[1].state()]);
+            cpu.run_frame();
+            session.after_frame(cpu);
+        }
+        Ok(seen)
+    }
+
+    #[test]
+    fn test_lockstep() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        // Each side holds a different button, both see the same inputs delayed by 2 frames.
+        let guest = thread::spawn(move || -> Result<Vec<[ControllerState; 2]>> {
+            let (rom, mut cpu) = new_cpu()?;
+            let mut session = Session::join(addr, &rom, &cpu)?;
+            assert_eq!(session.local_player(), 1);
+            play(&mut session, &mut cpu, 4, |_| ControllerState::B)
+        });
+
+        let (rom, mut cpu) = new_cpu()?;
+        let mut session = Session::accept(&listener, &rom, &cpu, Latency::default())?;
+        let seen = play(&mut session, &mut cpu, 4, |_| ControllerState::A)?;
+
+        let none = ControllerState::default();
+        let both = [
+            ControllerState(ControllerState::A),
+            ControllerState(ControllerState::B),
+        ];
+        assert_eq!(seen, [[none, none], [none, none], both, both]);
+        assert_eq!(guest.join().unwrap()?, seen);
+        assert_eq!(session.rolled_back_frames(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let latency = Latency {
+            input_delay: 0,
+            rollback_frames: 8,
+        };
+
+        // nestest's menu moves with the first controller, so the RAM depends on the host's inputs.
+        let host_input = |frame| match frame % 8 {
+            0..=1 => ControllerState::DOWN,
+            _ => 0,
+        };
+        let guest_input = |frame| match frame % 12 {
+            3 => ControllerState::UP,
+            _ => 0,
+        };
+
+        // The guest runs ahead while the host is late, guessing its inputs wrong.
+        let guest = thread::spawn(move || -> Result<(u64, u32)> {
+            let (rom, mut cpu) = new_cpu()?;
+            let mut session = Session::join(addr, &rom, &cpu)?;
+            play(&mut session, &mut cpu, 40, guest_input)?;
+            play(&mut session, &mut cpu, 10, |_| 0)?;
+            Ok((session.rolled_back_frames(), ram_checksum(&cpu)))
+        });
+
+        let (rom, mut cpu) = new_cpu()?;
+        let mut session = Session::accept(&listener, &rom, &cpu, latency)?;
+        thread::sleep(Duration::from_millis(200));
+        play(&mut session, &mut cpu, 40, host_input)?;
+        play(&mut session, &mut cpu, 10, |_| 0)?;
+
+        // Once the inputs all arrived, both end up where running them offline does.
+        let (_, mut expected) = new_cpu()?;
+        for frame in 0..50 {
+            let (host, guest) = if frame < 40 {
+                (host_input(frame), guest_input(frame))
+            } else {
+                (0, 0)
+            };
+            expected.controllers[0].set_state(ControllerState(host));
+            expected.controllers[1].set_state(ControllerState(guest));
+            expected.run_frame();
+        }
+
+        let (rolled_back_frames, guest_checksum) = guest.join().unwrap()?;
+        assert!(rolled_back_frames > 0);
+        assert_eq!(ram_checksum(&cpu), ram_checksum(&expected));
+        assert_eq!(guest_checksum, ram_checksum(&expected));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_different_settings() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let guest = thread::spawn(move || -> Result<()> {
+            let (rom, mut cpu) = new_cpu()?;
+            cpu.overclock_scanlines = 20;
+            Session::join(addr, &rom, &cpu).map(|_| ())
+        });
+
+        let (rom, cpu) = new_cpu()?;
+        let err = Session::accept(&listener, &rom, &cpu, Latency::default())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("overclocking"));
+        let err = guest.join().unwrap().err().unwrap();
+        assert!(err.to_string().starts_with("The host rejected us"));
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+/// Callbacks for watching the console run, e.g. to build tracers, debuggers or agents outside of
+/// the crate.
+///
+/// Each kind of callback is only looked at when one is registered, so the console runs at full
+/// speed without any.
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// The instruction about to be executed, with the registers before it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+    pub stack_pointer: u8,
+
+    /// CPU cycles since power up.
+    pub cycles: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A read or write by the CPU, with its side effects. Reads report the value read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: u16,
+    pub value: u8,
+}
+
+type InstructionCallback = Box<dyn FnMut(&Instruction) + Send>;
+type FrameCallback = Box<dyn FnMut(&[u8]) + Send>;
+type MemoryAccessCallback = Box<dyn FnMut(&MemoryAccess) + Send>;
+
+#[derive(Default)]
+pub struct Observers {
+    instruction: Vec<InstructionCallback>,
+    frame: Vec<FrameCallback>,
+    memory_access: Vec<MemoryAccessCallback>,
+}
+
+impl Observers {
+    /// Call `callback` before each instruction is executed.
+    pub fn on_instruction<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Instruction) + Send + 'static,
+    {
+        self.instruction.push(Box::new(callback));
+    }
+
+    /// Call `callback` with each completed frame of palette indices.
+    pub fn on_frame<F>(&mut self, callback: F)
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.frame.push(Box::new(callback));
+    }
+
+    /// Call `callback` for every read and write the CPU makes.
+    pub fn on_memory_access<F>(&mut self, callback: F)
+    where
+        F: FnMut(&MemoryAccess) + Send + 'static,
+    {
+        self.memory_access.push(Box::new(callback));
+    }
+
+    pub fn watches_instructions(&self) -> bool {
+        !self.instruction.is_empty()
+    }
+
+    pub fn watches_frames(&self) -> bool {
+        !self.frame.is_empty()
+    }
+
+    pub fn watches_memory(&self) -> bool {
+        !self.memory_access.is_empty()
+    }
+
+    pub fn instruction(&mut self, instruction: &Instruction) {
+        for callback in &mut self.instruction {
+            callback(instruction);
+        }
+    }
+
+    pub fn frame(&mut self, frame: &[u8]) {
+        for callback in &mut self.frame {
+            callback(frame);
+        }
+    }
+
+    pub fn memory_access(&mut self, access: &MemoryAccess) {
+        for callback in &mut self.memory_access {
+            callback(access);
+        }
+    }
+}
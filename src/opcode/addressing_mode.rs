@@ -1,5 +1,8 @@
 use crate::cpu::Cpu;
+use crate::opcode::table::Addressing;
 use crate::opcode::*;
+use alloc::format;
+use alloc::string::{String, ToString};
 
 /// AddRegister to be used with AddressMode.
 pub enum AddRegister {
@@ -14,7 +17,7 @@ pub enum AddRegister {
 }
 
 pub enum AddressMode {
-    _Accumulate,
+    Accumulator,
 
     Immediate {
         value: u8,
@@ -41,6 +44,10 @@ pub enum AddressMode {
     },
 
     /// Access the location to extract the real jump location from.
+    ///
+    /// With X the pointer is in the zero page at the operand plus X, with Y it's in the zero page
+    /// at the operand and Y is added to it. Without a register it's JMP's pointer anywhere in
+    /// memory.
     Indirect {
         register: AddRegister,
         address_to_read_indirect: u16,
@@ -48,6 +55,44 @@ pub enum AddressMode {
 }
 
 impl AddressMode {
+    /// The operand of the instruction at the program counter, addressed the way the opcode
+    /// table says. None for implied instructions, they have no operand.
+    pub fn new(addressing: Addressing, cpu: &Cpu) -> Option<Self> {
+        let pc = cpu.program_counter;
+        let value = cpu.peek(pc.wrapping_add(1));
+        let address = || bytes_to_addr(value, cpu.peek(pc.wrapping_add(2)));
+        let zero_page = |register| AddressMode::ZeroPage {
+            register,
+            offset: value,
+        };
+        let absolute = |register| AddressMode::Absolute {
+            register,
+            address: address(),
+        };
+        let indirect = |register, address_to_read_indirect| AddressMode::Indirect {
+            register,
+            address_to_read_indirect,
+        };
+
+        Some(match addressing {
+            Addressing::Implied => return None,
+            Addressing::Accumulator => AddressMode::Accumulator,
+            Addressing::Immediate => AddressMode::Immediate { value },
+            Addressing::Relative => AddressMode::Relative {
+                offset: value as i8,
+            },
+            Addressing::ZeroPage => zero_page(AddRegister::None),
+            Addressing::ZeroPageX => zero_page(AddRegister::X),
+            Addressing::ZeroPageY => zero_page(AddRegister::Y),
+            Addressing::Absolute => absolute(AddRegister::None),
+            Addressing::AbsoluteX => absolute(AddRegister::X),
+            Addressing::AbsoluteY => absolute(AddRegister::Y),
+            Addressing::Indirect => indirect(AddRegister::None, address()),
+            Addressing::IndirectX => indirect(AddRegister::X, value as u16),
+            Addressing::IndirectY => indirect(AddRegister::Y, value as u16),
+        })
+    }
+
     /// Offset into memory to lookup.
     pub fn to_addr(&self, cpu: &Cpu) -> Option<u16> {
         match &self {
@@ -58,44 +103,187 @@ impl AddressMode {
                 AddRegister::None => Some(*offset as u16),
 
                 // Intentionally wrap over.
-                AddRegister::X => Some((cpu.x + *offset) as u16),
-                AddRegister::Y => Some((cpu.y + *offset) as u16),
+                AddRegister::X => Some(cpu.x.wrapping_add(*offset) as u16),
+                AddRegister::Y => Some(cpu.y.wrapping_add(*offset) as u16),
             },
             AddressMode::Absolute { register, address } => match register {
                 AddRegister::None => Some(*address),
 
                 // Intentionally wrap over.
-                AddRegister::X => Some((cpu.x as u16 + *address) as u16),
-                AddRegister::Y => Some((cpu.y as u16 + *address) as u16),
+                AddRegister::X => Some(address.wrapping_add(cpu.x as u16)),
+                AddRegister::Y => Some(address.wrapping_add(cpu.y as u16)),
             },
             AddressMode::Indirect {
-                register: _,
-                address_to_read_indirect: _,
-            } => {
-                panic!("Unsupported!");
-            }
+                register,
+                address_to_read_indirect: pointer,
+            } => match register {
+                AddRegister::X => Some(read_zero_page_pointer(
+                    cpu,
+                    cpu.x.wrapping_add(*pointer as u8),
+                )),
+                AddRegister::Y => {
+                    Some(read_zero_page_pointer(cpu, *pointer as u8).wrapping_add(cpu.y as u16))
+                }
+
+                // The 6502 doesn't carry into the pointer's high byte, JMP ($02FF) reads the high
+                // byte of the address from $0200.
+                AddRegister::None => {
+                    let high = (pointer & 0xFF00) | (*pointer as u8).wrapping_add(1) as u16;
+                    Some(bytes_to_addr(cpu.peek(*pointer), cpu.peek(high)))
+                }
+            },
             _ => None,
         }
     }
 
-    pub fn to_value(&self, cpu: &Cpu) -> u8 {
+    pub fn to_value(&self, cpu: &mut Cpu) -> u8 {
         match &self {
-            AddressMode::_Accumulate => cpu.a,
+            AddressMode::Accumulator => cpu.a,
             AddressMode::Immediate { value } => *value,
-            _ => cpu.memory[self.to_addr(cpu).unwrap() as usize],
+            _ => {
+                let addr = self.to_addr(cpu).unwrap();
+                cpu.read(addr)
+            }
+        }
+    }
+
+    /// Whether indexing crossed into another page, reads take a cycle more then.
+    pub fn page_crossed(&self, cpu: &Cpu) -> bool {
+        let base = match &self {
+            AddressMode::Absolute {
+                register: AddRegister::X | AddRegister::Y,
+                address,
+            } => *address,
+            AddressMode::Indirect {
+                register: AddRegister::Y,
+                address_to_read_indirect: pointer,
+            } => read_zero_page_pointer(cpu, *pointer as u8),
+            _ => return false,
+        };
+
+        is_on_different_pages(base, self.to_addr(cpu).unwrap())
+    }
+
+    /// Length of the whole instruction in bytes.
+    pub fn bytes(&self) -> u16 {
+        match &self {
+            AddressMode::Accumulator => 1,
+            AddressMode::Absolute { .. }
+            | AddressMode::Indirect {
+                register: AddRegister::None,
+                ..
+            } => 3,
+            _ => 2,
+        }
+    }
+
+    /// Cycles taken by instructions reading their operand, e.g. LDA.
+    pub fn read_cycles(&self, cpu: &Cpu) -> u64 {
+        let cycles = match &self {
+            AddressMode::Accumulator | AddressMode::Immediate { .. } => 2,
+            AddressMode::ZeroPage {
+                register: AddRegister::None,
+                ..
+            } => 3,
+            AddressMode::ZeroPage { .. } | AddressMode::Absolute { .. } => 4,
+            AddressMode::Indirect {
+                register: AddRegister::Y,
+                ..
+            } => 5,
+            AddressMode::Indirect { .. } => 6,
+            AddressMode::Relative { .. } => panic!("Unexpected!"),
+        };
+
+        cycles + self.page_crossed(cpu) as u64
+    }
+
+    /// Cycles taken by instructions writing to their operand, e.g. STA. Indexing always takes
+    /// the extra cycle.
+    pub fn write_cycles(&self) -> u64 {
+        match &self {
+            AddressMode::ZeroPage {
+                register: AddRegister::None,
+                ..
+            } => 3,
+            AddressMode::ZeroPage { .. }
+            | AddressMode::Absolute {
+                register: AddRegister::None,
+                ..
+            } => 4,
+            AddressMode::Absolute { .. } => 5,
+            AddressMode::Indirect { .. } => 6,
+            _ => panic!("Unexpected!"),
+        }
+    }
+
+    /// Cycles taken by instructions reading their operand and writing it back changed, e.g. INC.
+    pub fn read_modify_write_cycles(&self) -> u64 {
+        match &self {
+            AddressMode::Accumulator => 2,
+            AddressMode::Indirect { .. } => 8,
+            _ => self.write_cycles() + 2,
+        }
+    }
+
+    /// Where the operand is and its value, the way nestest's log shows them after the
+    /// instruction, e.g. " @ 0300 = 89" for "LDA $0300,Y". Empty when there's nothing in memory
+    /// to show.
+    pub fn annotation(&self, cpu: &Cpu) -> String {
+        let addr = match self.to_addr(cpu) {
+            Some(addr) => addr,
+            None => return String::new(),
+        };
+        // Nintendulator, which wrote nestest's log, shows the APU and I/O registers as FF rather
+        // than reading them.
+        let value = match addr {
+            0x4000..=0x401F => 0xFF,
+            _ => cpu.peek(addr),
+        };
+
+        match &self {
+            AddressMode::ZeroPage {
+                register: AddRegister::None,
+                ..
+            } => format!(" = {:02X}", value),
+            AddressMode::ZeroPage { .. } => format!(" @ {:02X} = {:02X}", addr, value),
+            AddressMode::Absolute {
+                register: AddRegister::None,
+                ..
+            } => format!(" = {:02X}", value),
+            AddressMode::Absolute { .. } => format!(" @ {:04X} = {:02X}", addr, value),
+            AddressMode::Indirect {
+                register: AddRegister::X,
+                address_to_read_indirect: pointer,
+            } => format!(
+                " @ {:02X} = {:04X} = {:02X}",
+                cpu.x.wrapping_add(*pointer as u8),
+                addr,
+                value
+            ),
+            AddressMode::Indirect {
+                register: AddRegister::Y,
+                address_to_read_indirect: pointer,
+            } => format!(
+                " = {:04X} @ {:04X} = {:02X}",
+                read_zero_page_pointer(cpu, *pointer as u8),
+                addr,
+                value
+            ),
+            AddressMode::Indirect { .. } => format!(" = {:04X}", addr),
+            _ => String::new(),
         }
     }
 
     /// Convert the address mode to a string.
     pub fn to_string(&self, cpu: &Cpu) -> String {
         match &self {
-            AddressMode::_Accumulate => return "A".to_string(),
+            AddressMode::Accumulator => return "A".to_string(),
             AddressMode::Immediate { value } => return format!("#${:02X}", value),
             _ => (),
         };
 
         let addr = self.to_addr(cpu).unwrap();
-        let value = cpu.memory[addr as usize];
+        let value = cpu.peek(addr);
         match &self {
             AddressMode::Relative { offset: _ } => format!("${:04X}", addr),
             AddressMode::ZeroPage {
@@ -117,7 +305,7 @@ impl AddressMode {
 
     pub fn value_to_string(&self) -> String {
         match &self {
-            AddressMode::_Accumulate => "A".to_string(),
+            AddressMode::Accumulator => "A".to_string(),
             AddressMode::Relative { offset: value } => {
                 format!("{:02X}", *value as u8)
             }
@@ -145,3 +333,11 @@ impl AddressMode {
         }
     }
 }
+
+/// The address stored at `pointer` in the zero page, the high byte wraps around to $00.
+fn read_zero_page_pointer(cpu: &Cpu, pointer: u8) -> u16 {
+    bytes_to_addr(
+        cpu.peek(pointer as u16),
+        cpu.peek(pointer.wrapping_add(1) as u16),
+    )
+}
@@ -0,0 +1,340 @@
+use crate::cpu::{Cpu, ProcessorStatus};
+use crate::opcode::addressing_mode::AddressMode;
+use crate::opcode::*;
+use alloc::string::String;
+
+/// Add with carry, subtract with carry, compare and the logical operations, combining the
+/// accumulator or another register with the operand.
+pub struct Arithmetic {
+    kind: Kind,
+
+    /// Addressing mode.
+    mode: AddressMode,
+}
+
+pub(super) enum Kind {
+    Add,
+    Subtract,
+
+    /// Compare a register with the memory.
+    Compare(Register),
+
+    And,
+    Or,
+    ExclusiveOr,
+}
+
+impl Kind {
+    /// Combine the register with the value, setting the flags.
+    pub(super) fn apply(&self, cpu: &mut Cpu, value: u8) {
+        match self {
+            Kind::Add => add_with_carry(cpu, value),
+            Kind::Subtract => subtract_with_carry(cpu, value),
+            Kind::Compare(register) => {
+                let register = match register {
+                    Register::A => cpu.a,
+                    Register::X => cpu.x,
+                    Register::Y => cpu.y,
+                };
+                compare(&mut cpu.status, register, value);
+            }
+            Kind::And => {
+                cpu.a &= value;
+                cpu.status.update_load(cpu.a);
+            }
+            Kind::Or => {
+                cpu.a |= value;
+                cpu.status.update_load(cpu.a);
+            }
+            Kind::ExclusiveOr => {
+                cpu.a ^= value;
+                cpu.status.update_load(cpu.a);
+            }
+        }
+    }
+}
+
+impl Arithmetic {
+    pub fn new(opcode: u8, cpu: &Cpu) -> Option<Self> {
+        let kind = match opcode {
+            0x01 | 0x05 | 0x09 | 0x0D | 0x11 | 0x15 | 0x19 | 0x1D => Kind::Or,
+            0x21 | 0x25 | 0x29 | 0x2D | 0x31 | 0x35 | 0x39 | 0x3D => Kind::And,
+            0x41 | 0x45 | 0x49 | 0x4D | 0x51 | 0x55 | 0x59 | 0x5D => Kind::ExclusiveOr,
+            0x61 | 0x65 | 0x69 | 0x6D | 0x71 | 0x75 | 0x79 | 0x7D => Kind::Add,
+            // $EB is an unofficial copy of SBC immediate.
+            0xE1 | 0xE5 | 0xE9 | 0xED | 0xF1 | 0xF5 | 0xF9 | 0xFD | 0xEB => Kind::Subtract,
+            0xC1 | 0xC5 | 0xC9 | 0xCD | 0xD1 | 0xD5 | 0xD9 | 0xDD => Kind::Compare(Register::A),
+            0xE0 | 0xE4 | 0xEC => Kind::Compare(Register::X),
+            0xC0 | 0xC4 | 0xCC => Kind::Compare(Register::Y),
+            _ => return None,
+        };
+
+        Some(Arithmetic {
+            kind,
+            mode: AddressMode::new(table::info(opcode).addressing, cpu)?,
+        })
+    }
+
+    fn get_cycles(&self, cpu: &Cpu) -> u64 {
+        self.mode.read_cycles(cpu)
+    }
+}
+
+/// Add the value and the carry to the accumulator.
+///
+/// Overflow is set when the signed result doesn't fit, i.e. both operands have the same sign and
+/// the result has the other one.
+fn add_with_carry(cpu: &mut Cpu, value: u8) {
+    #[cfg(feature = "mos6502")]
+    if cpu.decimal_mode && cpu.status.decimal {
+        return decimal_add(cpu, value);
+    }
+
+    binary_add(cpu, value);
+}
+
+fn binary_add(cpu: &mut Cpu, value: u8) {
+    let sum = cpu.a as u16 + value as u16 + cpu.status.carry as u16;
+    let result = sum as u8;
+
+    cpu.status.carry = sum > 0xFF;
+    cpu.status.overflow = (cpu.a ^ result) & (value ^ result) & ProcessorStatus::NEGATIVE_MASK != 0;
+    cpu.a = result;
+    cpu.status.update_load(result);
+}
+
+/// Subtracting is adding the complement, the carry is set when nothing was borrowed.
+fn subtract_with_carry(cpu: &mut Cpu, value: u8) {
+    #[cfg(feature = "mos6502")]
+    if cpu.decimal_mode && cpu.status.decimal {
+        return decimal_subtract(cpu, value);
+    }
+
+    binary_add(cpu, !value);
+}
+
+/// Add digit by digit, each nibble a decimal digit. Like on the NMOS 6502, zero comes from the
+/// binary sum, negative and overflow from the sum before the high digit is adjusted.
+/// See http://www.6502.org/tutorials/decimal_mode.html.
+#[cfg(feature = "mos6502")]
+fn decimal_add(cpu: &mut Cpu, value: u8) {
+    let (a, carry) = (cpu.a as u16, cpu.status.carry as u16);
+    let value = value as u16;
+
+    let mut low = (a & 0x0F) + (value & 0x0F) + carry;
+    if low > 0x09 {
+        low = ((low + 0x06) & 0x0F) + 0x10;
+    }
+    let mut sum = (a & 0xF0) + (value & 0xF0) + low;
+
+    cpu.status.zero = (a + value + carry) as u8 == 0;
+    cpu.status.negative = sum & 0x80 != 0;
+    cpu.status.overflow = (a ^ sum) & (value ^ sum) & 0x80 != 0;
+
+    if sum > 0x9F {
+        sum += 0x60;
+    }
+    cpu.status.carry = sum > 0xFF;
+    cpu.a = sum as u8;
+}
+
+/// Subtract digit by digit. Every flag is the binary subtraction's on the NMOS 6502.
+#[cfg(feature = "mos6502")]
+fn decimal_subtract(cpu: &mut Cpu, value: u8) {
+    let (a, borrow) = (cpu.a as i16, !cpu.status.carry as i16);
+    let value = value as i16;
+
+    let mut low = (a & 0x0F) - (value & 0x0F) - borrow;
+    if low < 0 {
+        low = ((low - 0x06) & 0x0F) - 0x10;
+    }
+    let mut difference = (a & 0xF0) - (value & 0xF0) + low;
+    if difference < 0 {
+        difference -= 0x60;
+    }
+
+    binary_add(cpu, !(value as u8));
+    cpu.a = difference as u8;
+}
+
+/// Set the flags as if subtracting the value from the register, without the carry.
+fn compare(status: &mut ProcessorStatus, register: u8, value: u8) {
+    status.carry = register >= value;
+    status.update_load(register.wrapping_sub(value));
+}
+
+impl Operation for Arithmetic {
+    fn execute(&self, cpu: &mut Cpu) {
+        cpu.program_counter += self.mode.bytes();
+        cpu.cycles += self.get_cycles(cpu);
+        let value = self.mode.to_value(cpu);
+
+        self.kind.apply(cpu, value);
+    }
+
+    fn dump(&self, cpu: &Cpu) -> String {
+        dump_instruction(cpu, Some(&self.mode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::test_util::nrom;
+    use alloc::vec;
+    use proptest::prelude::*;
+
+    /// A console about to execute `program` at $C000.
+    fn cpu(program: &[u8]) -> Cpu {
+        Cpu::new(nrom(program))
+    }
+
+    fn execute(cpu: &mut Cpu) {
+        let operation = next(cpu).unwrap();
+        cpu.step(operation);
+    }
+
+    /// Flags and result of the reference model, computed on wider integers.
+    #[derive(Debug, PartialEq)]
+    struct Outcome {
+        result: u8,
+        carry: bool,
+        zero: bool,
+        overflow: bool,
+        negative: bool,
+    }
+
+    impl Outcome {
+        fn of(cpu: &Cpu, result: u8) -> Self {
+            Outcome {
+                result,
+                carry: cpu.status.carry,
+                zero: cpu.status.zero,
+                overflow: cpu.status.overflow,
+                negative: cpu.status.negative,
+            }
+        }
+    }
+
+    fn reference_add(a: u8, value: u8, carry: bool) -> Outcome {
+        let unsigned = a as u16 + value as u16 + carry as u16;
+        let signed = a as i8 as i16 + value as i8 as i16 + carry as i16;
+        let result = unsigned as u8;
+
+        Outcome {
+            result,
+            carry: unsigned > 0xFF,
+            zero: result == 0,
+            overflow: !(-128..=127).contains(&signed),
+            negative: result >= 0x80,
+        }
+    }
+
+    fn reference_subtract(a: u8, value: u8, carry: bool) -> Outcome {
+        let borrow = !carry as i16;
+        let unsigned = a as i16 - value as i16 - borrow;
+        let signed = a as i8 as i16 - value as i8 as i16 - borrow;
+        let result = unsigned as u8;
+
+        Outcome {
+            result,
+            carry: unsigned >= 0,
+            zero: result == 0,
+            overflow: !(-128..=127).contains(&signed),
+            negative: result >= 0x80,
+        }
+    }
+
+    #[test]
+    fn test_overflow_examples() {
+        // From http://www.6502.org/tutorials/vflag.html.
+        assert!(reference_add(0x50, 0x50, false).overflow);
+        assert!(!reference_add(0x50, 0xD0, false).overflow);
+        assert!(reference_add(0xD0, 0x90, false).overflow);
+        assert!(reference_subtract(0x50, 0xB0, true).overflow);
+        assert!(!reference_subtract(0x50, 0x70, true).overflow);
+    }
+
+    #[cfg(feature = "mos6502")]
+    #[test]
+    fn test_decimal() {
+        let run = |opcode, a, value, carry, decimal_mode| {
+            let mut cpu = cpu(&[opcode, value]);
+            cpu.decimal_mode = decimal_mode;
+            cpu.status.decimal = true;
+            cpu.a = a;
+            cpu.status.carry = carry;
+            execute(&mut cpu);
+            (cpu.a, cpu.status.carry, cpu.status.zero)
+        };
+
+        // 58 + 46 + 1 = 105.
+        assert_eq!(run(0x69, 0x58, 0x46, true, true), (0x05, true, false));
+        assert_eq!(run(0x69, 0x12, 0x34, false, true), (0x46, false, false));
+        // Zero is from the binary sum, $9A.
+        assert_eq!(run(0x69, 0x99, 0x01, false, true), (0x00, true, false));
+
+        // 46 - 12 = 34 and 12 - 21 = -9, 91 with a borrow.
+        assert_eq!(run(0xE9, 0x46, 0x12, true, true), (0x34, true, false));
+        assert_eq!(run(0xE9, 0x12, 0x21, true, true), (0x91, false, false));
+        assert_eq!(run(0xE9, 0x40, 0x13, false, true), (0x26, true, false));
+
+        // The NES ignores the flag.
+        assert_eq!(run(0x69, 0x58, 0x46, true, false), (0x9F, false, false));
+    }
+
+    proptest! {
+        #[test]
+        fn test_adc(a: u8, value: u8, carry: bool) {
+            let mut cpu = cpu(&[0x69, value]);
+            cpu.a = a;
+            cpu.status.carry = carry;
+            execute(&mut cpu);
+
+            prop_assert_eq!(Outcome::of(&cpu, cpu.a), reference_add(a, value, carry));
+            prop_assert_eq!(cpu.program_counter, 0xC002);
+        }
+
+        #[test]
+        fn test_sbc(a: u8, value: u8, carry: bool) {
+            // From zero page, the operand is read from memory.
+            let mut cpu = cpu(&[0xE5, 0x10]);
+            cpu.memory[0x10] = value;
+            cpu.a = a;
+            cpu.status.carry = carry;
+            execute(&mut cpu);
+
+            prop_assert_eq!(Outcome::of(&cpu, cpu.a), reference_subtract(a, value, carry));
+        }
+
+        #[test]
+        fn test_compare(register: u8, value: u8, carry: bool, opcode in prop::sample::select(vec![0xC9u8, 0xE0, 0xC0])) {
+            let mut cpu = cpu(&[opcode, value]);
+            cpu.a = register;
+            cpu.x = register;
+            cpu.y = register;
+            cpu.status.carry = carry;
+            cpu.status.overflow = carry;
+            execute(&mut cpu);
+
+            // Like subtracting without a borrow in, only the result and overflow are left alone.
+            let expected = reference_subtract(register, value, true);
+            prop_assert_eq!((cpu.a, cpu.x, cpu.y), (register, register, register));
+            prop_assert_eq!(
+                Outcome::of(&cpu, expected.result),
+                Outcome { overflow: carry, ..expected }
+            );
+        }
+
+        #[test]
+        fn test_absolute_indexed_cycles(address: u16, x: u8) {
+            let [low, high] = address.to_le_bytes();
+            let mut cpu = cpu(&[0x7D, low, high]);
+            cpu.x = x;
+
+            let operation = Arithmetic::new(0x7D, &cpu).unwrap();
+            let crossed = address & 0xFF00 != address.wrapping_add(x as u16) & 0xFF00;
+            prop_assert_eq!(operation.get_cycles(&cpu), 4 + crossed as u64);
+        }
+    }
+}
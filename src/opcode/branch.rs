@@ -2,7 +2,9 @@ use crate::cpu::Cpu;
 use crate::opcode::addressing_mode::AddressMode;
 use crate::opcode::Operation;
 use crate::opcode::*;
-use std::string::ToString;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
 
 pub struct Branch {
     branch_type: BranchType,
@@ -69,9 +71,9 @@ impl BranchType {
     }
 }
 
-impl ToString for BranchType {
-    fn to_string(&self) -> String {
-        match &self {
+impl fmt::Display for BranchType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match &self {
             BranchType::Bcs => "BCS",
             BranchType::Bcc => "BCC",
             BranchType::Beq => "BEQ",
@@ -80,8 +82,9 @@ impl ToString for BranchType {
             BranchType::Bpl => "BPL",
             BranchType::Bvs => "BVS",
             BranchType::Bvc => "BVC",
-        }
-        .to_string()
+        };
+
+        write!(f, "{}", name)
     }
 }
 
@@ -91,7 +94,7 @@ impl Branch {
 
     /// Create a new branch from an opcode.
     pub fn new(opcode: u8, cpu: &Cpu) -> Option<Self> {
-        let offset = cpu.memory[(cpu.program_counter + 1) as usize] as i8;
+        let offset = cpu.peek(cpu.program_counter + 1) as i8;
 
         let branch_type = BranchType::from_opcode(opcode)?;
 
@@ -101,9 +104,30 @@ impl Branch {
         })
     }
 
+    /// Whether the flags make the branch taken.
+    fn should_branch(&self, cpu: &Cpu) -> bool {
+        match self.branch_type {
+            BranchType::Bcs => cpu.status.carry,
+            BranchType::Bcc => !cpu.status.carry,
+            BranchType::Beq => cpu.status.zero,
+            BranchType::Bne => !cpu.status.zero,
+            BranchType::Bmi => cpu.status.negative,
+            BranchType::Bpl => !cpu.status.negative,
+            BranchType::Bvs => cpu.status.overflow,
+            BranchType::Bvc => !cpu.status.overflow,
+        }
+    }
+
+    /// Whether the branch is taken back to itself, nothing can change the flags after.
+    pub fn is_trap(&self, cpu: &Cpu) -> bool {
+        self.offset == -(Self::BYTE_COUNT as i8) && self.should_branch(cpu)
+    }
+
     /// Specalitve computation of branch value.
     fn branch_value(&self, cpu: &Cpu) -> u16 {
-        cpu.program_counter + Self::BYTE_COUNT + self.offset as u16
+        cpu.program_counter
+            .wrapping_add(Self::BYTE_COUNT)
+            .wrapping_add(self.offset as u16)
     }
 }
 
@@ -112,18 +136,7 @@ impl Operation for Branch {
         cpu.program_counter += Self::BYTE_COUNT;
         cpu.cycles += 2;
 
-        let should_branch = match self.branch_type {
-            BranchType::Bcs => cpu.status.carry,
-            BranchType::Bcc => !cpu.status.carry,
-            BranchType::Beq => cpu.status.zero,
-            BranchType::Bne => !cpu.status.zero,
-            BranchType::Bmi => cpu.status.negative,
-            BranchType::Bpl => !cpu.status.negative,
-            BranchType::Bvs => cpu.status.overflow,
-            BranchType::Bvc => !cpu.status.overflow,
-        };
-
-        if should_branch {
+        if self.should_branch(cpu) {
             let addr = AddressMode::Relative {
                 offset: self.offset,
             }
@@ -147,7 +160,7 @@ impl Operation for Branch {
             "{:02X} {:02X}     {} ${:04X}   ",
             self.branch_type.to_opcode(),
             self.offset,
-            self.branch_type.to_string(),
+            self.branch_type,
             self.branch_value(cpu),
         )
     }
@@ -1,6 +1,8 @@
 use crate::cpu::Cpu;
 use crate::opcode::Operation;
-use std::string::ToString;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
 
 /// Flag type.
 pub enum Flag {
@@ -58,9 +60,9 @@ impl Flag {
     }
 }
 
-impl ToString for Flag {
-    fn to_string(&self) -> String {
-        match &self {
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match &self {
             Flag::Clc => "CLC",
             Flag::Sec => "SEC",
             Flag::Cli => "CLI",
@@ -68,8 +70,9 @@ impl ToString for Flag {
             Flag::Clv => "CLV",
             Flag::Cld => "CLD",
             Flag::Sed => "SED",
-        }
-        .to_string()
+        };
+
+        write!(f, "{}", name)
     }
 }
 
@@ -90,10 +93,6 @@ impl Operation for Flag {
     }
 
     fn dump(&self, _cpu: &Cpu) -> String {
-        format!(
-            "{:02X}        {}        ",
-            self.to_opcode(),
-            self.to_string(),
-        )
+        format!("{:02X}        {}        ", self.to_opcode(), self)
     }
 }
@@ -0,0 +1,83 @@
+use crate::cpu::Cpu;
+use crate::opcode::Operation;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+
+/// Add or subtract one from X or Y, wrapping around. INC and DEC change memory, see
+/// `ReadModifyWrite`.
+pub enum Increment {
+    /// (In)crement (X).
+    Inx,
+
+    /// (In)crement (Y).
+    Iny,
+
+    /// (De)crement (X).
+    Dex,
+
+    /// (De)crement (Y).
+    Dey,
+}
+
+impl Increment {
+    const BYTE_COUNT: u16 = 1;
+    const CYCLE_LENGTH: u64 = 2;
+
+    /// Convert from the opcode to increment type enum.
+    pub fn new(opcode: u8) -> Option<Increment> {
+        match opcode {
+            0xE8 => Some(Increment::Inx),
+            0xC8 => Some(Increment::Iny),
+            0xCA => Some(Increment::Dex),
+            0x88 => Some(Increment::Dey),
+            _ => None,
+        }
+    }
+
+    /// Convert from Increment to opcode.
+    pub fn to_opcode(&self) -> u8 {
+        match &self {
+            Increment::Inx => 0xE8,
+            Increment::Iny => 0xC8,
+            Increment::Dex => 0xCA,
+            Increment::Dey => 0x88,
+        }
+    }
+}
+
+impl fmt::Display for Increment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match &self {
+            Increment::Inx => "INX",
+            Increment::Iny => "INY",
+            Increment::Dex => "DEX",
+            Increment::Dey => "DEY",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl Operation for Increment {
+    fn execute(&self, cpu: &mut Cpu) {
+        cpu.program_counter += Self::BYTE_COUNT;
+        cpu.cycles += Self::CYCLE_LENGTH;
+
+        let register = match &self {
+            Increment::Inx | Increment::Dex => &mut cpu.x,
+            Increment::Iny | Increment::Dey => &mut cpu.y,
+        };
+        *register = match &self {
+            Increment::Inx | Increment::Iny => register.wrapping_add(1),
+            Increment::Dex | Increment::Dey => register.wrapping_sub(1),
+        };
+
+        let value = *register;
+        cpu.status.update_load(value);
+    }
+
+    fn dump(&self, _cpu: &Cpu) -> String {
+        format!("{:02X}        {}        ", self.to_opcode(), self)
+    }
+}
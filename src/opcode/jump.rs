@@ -1,22 +1,21 @@
 use crate::cpu::Cpu;
 use crate::opcode::addressing_mode::{AddRegister, AddressMode};
 use crate::opcode::*;
+use alloc::format;
+use alloc::string::String;
 
 pub struct Jmp {
-    opcode: u8,
-
     // Absolute or Indirect.
     mode: AddressMode,
 }
 
 impl Jmp {
     pub fn new(opcode: u8, cpu: &Cpu) -> Option<Self> {
-        let pc = cpu.program_counter as usize;
-        let address = bytes_to_addr(cpu.memory[pc + 1], cpu.memory[pc + 2]);
+        let pc = cpu.program_counter;
+        let address = bytes_to_addr(cpu.peek(pc + 1), cpu.peek(pc + 2));
         match opcode {
             // Absolute
             0x4C => Some(Jmp {
-                opcode,
                 mode: AddressMode::Absolute {
                     register: AddRegister::None,
                     address,
@@ -24,7 +23,6 @@ impl Jmp {
             }),
 
             0x6C => Some(Jmp {
-                opcode,
                 mode: AddressMode::Indirect {
                     register: AddRegister::None,
                     address_to_read_indirect: address,
@@ -57,12 +55,12 @@ impl Operation for Jmp {
     }
 
     fn dump(&self, cpu: &Cpu) -> String {
-        format!(
-            "{:02X} {}  JMP {}",
-            self.opcode,
-            self.mode.value_to_string(),
-            self.mode.to_string(cpu),
-        )
+        // Only the indirect JMP shows the address it jumps to.
+        let mode = match &self.mode {
+            AddressMode::Indirect { .. } => Some(&self.mode),
+            _ => None,
+        };
+        dump_instruction(cpu, mode)
     }
 }
 
@@ -81,8 +79,8 @@ impl Jsr {
             return None;
         }
 
-        let pc = cpu.program_counter as usize;
-        let address = bytes_to_addr(cpu.memory[pc + 1], cpu.memory[pc + 2]);
+        let pc = cpu.program_counter;
+        let address = bytes_to_addr(cpu.peek(pc + 1), cpu.peek(pc + 2));
         Some(Jsr {
             mode: AddressMode::Absolute {
                 register: AddRegister::None,
@@ -98,7 +96,7 @@ impl Operation for Jsr {
         let return_address = cpu.program_counter + Jsr::BYTES - 1;
 
         // Push onto the stack the return address.
-        cpu.stack.push_addr(&mut cpu.memory, return_address);
+        cpu.push_addr(return_address);
 
         cpu.program_counter = self.mode.to_addr(cpu).unwrap();
 
@@ -134,7 +132,7 @@ impl Rts {
 
 impl Operation for Rts {
     fn execute(&self, cpu: &mut Cpu) {
-        let (pcl, pch) = cpu.stack.pop_addr(&mut cpu.memory);
+        let (pcl, pch) = cpu.pop_addr();
 
         let return_address = bytes_to_addr(pcl, pch);
 
@@ -148,3 +146,72 @@ impl Operation for Rts {
         format!("{:02X}        RTS     ", Self::OPCODE)
     }
 }
+
+/// Software interrupt, enters the IRQ handler like an interrupt with the B flag set in the pushed
+/// status. The byte after the opcode is skipped, the handler returns past it.
+///
+/// A NMI arriving during its first cycles hijacks it to the NMI handler, see `Cpu::step`.
+pub struct Brk {}
+
+impl Brk {
+    pub const OPCODE: u8 = 0x00;
+    const BYTES: u16 = 2;
+    const CYCLES: u64 = 7;
+
+    pub fn new(opcode: u8) -> Option<Self> {
+        if opcode != Brk::OPCODE {
+            return None;
+        }
+
+        Some(Brk {})
+    }
+}
+
+impl Operation for Brk {
+    fn execute(&self, cpu: &mut Cpu) {
+        cpu.push_addr(cpu.program_counter.wrapping_add(Brk::BYTES));
+
+        let mut status = cpu.status.clone();
+        status.b_flag = true;
+        cpu.push(u8::from(status));
+
+        cpu.status.interrupt_disable = true;
+        cpu.program_counter = cpu.read_vector(Cpu::IRQ_VECTOR);
+        cpu.cycles += Brk::CYCLES;
+    }
+
+    fn dump(&self, _cpu: &Cpu) -> String {
+        format!("{:02X}        BRK     ", Self::OPCODE)
+    }
+}
+
+/// Return from an interrupt handler: the status then the address are pulled, unlike RTS the
+/// address isn't incremented.
+pub struct Rti {}
+
+impl Rti {
+    const OPCODE: u8 = 0x40;
+    const CYCLES: u64 = 6;
+
+    pub fn new(opcode: u8) -> Option<Self> {
+        if opcode != Rti::OPCODE {
+            return None;
+        }
+
+        Some(Rti {})
+    }
+}
+
+impl Operation for Rti {
+    fn execute(&self, cpu: &mut Cpu) {
+        cpu.status = cpu.pop().into();
+
+        let (pcl, pch) = cpu.pop_addr();
+        cpu.program_counter = bytes_to_addr(pcl, pch);
+        cpu.cycles += Rti::CYCLES;
+    }
+
+    fn dump(&self, _cpu: &Cpu) -> String {
+        format!("{:02X}        RTI     ", Self::OPCODE)
+    }
+}
@@ -1,20 +1,36 @@
 mod addressing_mode;
+mod arithmetic;
 mod branch;
 mod flag;
+mod increment;
 mod jump;
 mod load;
 mod push_pull;
+mod read_modify_write;
 mod store;
+pub mod table;
+mod transfer;
+mod unofficial;
 
 use crate::cpu::Cpu;
+use crate::disasm;
 use crate::opcode::addressing_mode::{AddRegister, AddressMode};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
 
+pub use arithmetic::*;
 pub use branch::*;
 pub use flag::*;
+pub use increment::*;
 pub use jump::*;
 pub use load::*;
 pub use push_pull::*;
+pub use read_modify_write::*;
 pub use store::*;
+pub use transfer::*;
+pub use unofficial::*;
 
 pub trait Operation {
     /// Execute the opcode.
@@ -24,56 +40,142 @@ pub trait Operation {
     fn dump(&self, cpu: &Cpu) -> String;
 }
 
+/// The byte at the program counter isn't an opcode the CPU implements.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecodeError {
+    pub opcode: u8,
+    pub address: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unknown opcode {:02X} at {:04X}",
+            self.opcode, self.address
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
 /// Read in the next opcode and set up PC.
-pub fn next(cpu: &Cpu) -> Box<dyn Operation> {
-    let pc = cpu.program_counter as usize;
-    let opcode = cpu.memory[pc];
+pub fn next(cpu: &Cpu) -> Result<Box<dyn Operation>, DecodeError> {
+    let pc = cpu.program_counter;
+    let opcode = cpu.peek(pc);
 
     if let Some(branch) = Branch::new(opcode, cpu) {
-        return Box::new(branch);
+        return Ok(Box::new(branch));
     }
 
     if let Some(flag) = Flag::new(opcode) {
-        return Box::new(flag);
+        return Ok(Box::new(flag));
     }
 
     if let Some(load) = Load::new(opcode, cpu) {
-        return Box::new(load);
+        return Ok(Box::new(load));
     }
 
     if let Some(store) = Store::new(opcode, cpu) {
-        return Box::new(store);
+        return Ok(Box::new(store));
     }
 
     if let Some(jmp) = Jmp::new(opcode, cpu) {
-        return Box::new(jmp);
+        return Ok(Box::new(jmp));
     }
 
     if let Some(jsr) = Jsr::new(opcode, cpu) {
-        return Box::new(jsr);
+        return Ok(Box::new(jsr));
     }
 
     if let Some(rts) = Rts::new(opcode) {
-        return Box::new(rts);
+        return Ok(Box::new(rts));
     }
 
-    if let Some(nop) = Nop::new(opcode) {
-        return Box::new(nop);
+    if let Some(brk) = Brk::new(opcode) {
+        return Ok(Box::new(brk));
+    }
+
+    if let Some(rti) = Rti::new(opcode) {
+        return Ok(Box::new(rti));
+    }
+
+    if let Some(nop) = Nop::new(opcode, cpu) {
+        return Ok(Box::new(nop));
     }
 
     if let Some(bit) = Bit::new(opcode, cpu) {
-        return Box::new(bit);
+        return Ok(Box::new(bit));
     }
 
     if let Some(push) = Push::new(opcode) {
-        return Box::new(push);
+        return Ok(Box::new(push));
     }
 
     if let Some(pull) = Pull::new(opcode) {
-        return Box::new(pull);
+        return Ok(Box::new(pull));
+    }
+
+    if let Some(arithmetic) = Arithmetic::new(opcode, cpu) {
+        return Ok(Box::new(arithmetic));
+    }
+
+    if let Some(read_modify_write) = ReadModifyWrite::new(opcode, cpu) {
+        return Ok(Box::new(read_modify_write));
+    }
+
+    if let Some(transfer) = Transfer::new(opcode) {
+        return Ok(Box::new(transfer));
+    }
+
+    if let Some(increment) = Increment::new(opcode) {
+        return Ok(Box::new(increment));
+    }
+
+    if let Some(lax) = Lax::new(opcode, cpu) {
+        return Ok(Box::new(lax));
+    }
+
+    if let Some(sax) = Sax::new(opcode, cpu) {
+        return Ok(Box::new(sax));
+    }
+
+    Err(DecodeError {
+        opcode,
+        address: pc,
+    })
+}
+
+/// Whether the next instruction jumps to itself, `JMP *` or a taken branch to itself: only an
+/// interrupt gets the CPU out. Test ROMs usually end that way, games waiting for the NMI too.
+pub fn is_trap(cpu: &Cpu) -> bool {
+    let pc = cpu.program_counter;
+    let opcode = cpu.peek(pc);
+
+    // JMP absolute.
+    if opcode == 0x4C {
+        return bytes_to_addr(cpu.peek(pc.wrapping_add(1)), cpu.peek(pc.wrapping_add(2))) == pc;
     }
 
-    panic!("Unexpected opcode {:02X}", opcode);
+    Branch::new(opcode, cpu).is_some_and(|branch| branch.is_trap(cpu))
+}
+
+/// The instruction at the program counter as nestest's log shows it, e.g.
+/// "B1 89     LDA ($89),Y = 0300 @ 0300 = 89": the disassembly, then where the operand is in
+/// memory and its value.
+fn dump_instruction(cpu: &Cpu, mode: Option<&AddressMode>) -> String {
+    let line = disasm::decode(|address| cpu.peek(address), cpu.program_counter);
+    let padding = if line.info.official { " " } else { "" };
+    let annotation = mode.map(|mode| mode.annotation(cpu)).unwrap_or_default();
+
+    format!(
+        "{:<8} {}{}{}",
+        line.hex_bytes(),
+        padding,
+        line.instruction_with_labels(|_| None),
+        annotation
+    )
 }
 
 /// Each page is 256 bytes.
@@ -101,41 +203,73 @@ enum Register {
     Y,
 }
 
-impl ToString for Register {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
             Register::A => "A",
             Register::X => "X",
             Register::Y => "Y",
-        }
-        .to_string()
+        };
+
+        write!(f, "{}", name)
     }
 }
 
-struct Nop {}
+/// Stands in for an unknown opcode, skipped like a one byte NOP.
+pub fn skip(error: &DecodeError) -> Box<dyn Operation> {
+    Box::new(Nop {
+        opcode: error.opcode,
+        mode: None,
+    })
+}
+
+/// NOP, and the unofficial NOPs that read their operand and ignore it.
+struct Nop {
+    opcode: u8,
+
+    /// None for the implied ones.
+    mode: Option<AddressMode>,
+}
 
 impl Nop {
-    const OPCODE: u8 = 0xEA;
     const BYTES: u16 = 1;
     const CYCLES: u64 = 2;
 
-    pub fn new(opcode: u8) -> Option<Self> {
-        if opcode != Nop::OPCODE {
-            None
-        } else {
-            Some(Nop {})
+    pub fn new(opcode: u8, cpu: &Cpu) -> Option<Self> {
+        let info = table::info(opcode);
+        if info.mnemonic != "NOP" {
+            return None;
         }
+
+        Some(Nop {
+            opcode,
+            mode: AddressMode::new(info.addressing, cpu),
+        })
     }
 }
 
 impl Operation for Nop {
     fn execute(&self, cpu: &mut Cpu) {
-        cpu.program_counter += Self::BYTES;
-        cpu.cycles += Self::CYCLES;
+        match &self.mode {
+            Some(mode) => {
+                cpu.program_counter += mode.bytes();
+                cpu.cycles += mode.read_cycles(cpu);
+                mode.to_value(cpu);
+            }
+            None => {
+                cpu.program_counter += Self::BYTES;
+                cpu.cycles += Self::CYCLES;
+            }
+        }
     }
 
-    fn dump(&self, _cpu: &Cpu) -> String {
-        format!("{:02X}        NOP     ", Self::OPCODE)
+    fn dump(&self, cpu: &Cpu) -> String {
+        // Unknown opcodes are skipped as NOPs.
+        if table::info(self.opcode).mnemonic != "NOP" {
+            return format!("{:02X}        NOP     ", self.opcode);
+        }
+
+        dump_instruction(cpu, self.mode.as_ref())
     }
 }
 
@@ -143,29 +277,25 @@ impl Operation for Nop {
 ///
 /// And the memory with what is in the accumulator and set flags.
 struct Bit {
-    opcode: u8,
-
     /// Address of the memory to test.
     mode: AddressMode,
 }
 
 impl Bit {
     pub fn new(opcode: u8, cpu: &Cpu) -> Option<Self> {
-        let pc = cpu.program_counter as usize;
-        let value = cpu.memory[pc + 1];
+        let pc = cpu.program_counter;
+        let value = cpu.peek(pc + 1);
 
         match opcode {
             0x24 => Some(Bit {
-                opcode,
                 mode: AddressMode::ZeroPage {
                     register: AddRegister::None,
                     offset: value,
                 },
             }),
             0x2C => {
-                let address = bytes_to_addr(value, cpu.memory[pc + 2]);
+                let address = bytes_to_addr(value, cpu.peek(pc + 2));
                 Some(Bit {
-                    opcode,
                     mode: AddressMode::Absolute {
                         register: AddRegister::None,
                         address,
@@ -210,18 +340,14 @@ impl Operation for Bit {
         cpu.program_counter += self.get_bytes();
         cpu.cycles += self.get_cycles();
 
+        // Negative and overflow are copied from the memory, zero is whether it has no bits in
+        // common with the accumulator.
         let test_value = self.mode.to_value(cpu);
-        let result = test_value & cpu.a;
-
-        cpu.status.update_bit(result);
+        cpu.status.update_bit(test_value);
+        cpu.status.zero = test_value & cpu.a == 0;
     }
 
     fn dump(&self, cpu: &Cpu) -> String {
-        format!(
-            "{:02X} {}     BIT {}   ",
-            self.opcode,
-            self.mode.value_to_string(),
-            self.mode.to_string(cpu),
-        )
+        dump_instruction(cpu, Some(&self.mode))
     }
 }
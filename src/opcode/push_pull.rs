@@ -1,19 +1,22 @@
 use crate::cpu::Cpu;
 use crate::opcode::Operation;
-use std::string::ToString;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
 
 enum Data {
     Accumulator,
     ProcessorStatus,
 }
 
-impl ToString for Data {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for Data {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
             Data::Accumulator => "A",
             Data::ProcessorStatus => "P",
-        }
-        .to_string()
+        };
+
+        write!(f, "{}", name)
     }
 }
 
@@ -50,18 +53,19 @@ impl Operation for Push {
 
         let value = match self.data {
             Data::Accumulator => cpu.a,
-            Data::ProcessorStatus => u8::from(cpu.status.clone()),
+            // Pushed with the B flag set, like BRK.
+            Data::ProcessorStatus => {
+                let mut status = cpu.status.clone();
+                status.b_flag = true;
+                u8::from(status)
+            }
         };
 
-        cpu.stack.push(&mut cpu.memory, value);
+        cpu.push(value);
     }
 
     fn dump(&self, _cpu: &Cpu) -> String {
-        format!(
-            "{:02X}        PH{}     ",
-            self.opcode,
-            self.data.to_string()
-        )
+        format!("{:02X}        PH{}     ", self.opcode, self.data)
     }
 }
 
@@ -96,7 +100,7 @@ impl Operation for Pull {
         cpu.program_counter += Self::BYTES;
         cpu.cycles += Self::CYCLES;
 
-        let value = cpu.stack.pop(&mut cpu.memory);
+        let value = cpu.pop();
 
         match self.data {
             Data::Accumulator => {
@@ -108,10 +112,6 @@ impl Operation for Pull {
     }
 
     fn dump(&self, _cpu: &Cpu) -> String {
-        format!(
-            "{:02X}        PL{}     ",
-            self.opcode,
-            self.data.to_string()
-        )
+        format!("{:02X}        PL{}     ", self.opcode, self.data)
     }
 }
@@ -0,0 +1,126 @@
+use crate::cpu::{Cpu, ProcessorStatus};
+use crate::opcode::addressing_mode::AddressMode;
+use crate::opcode::arithmetic::Kind;
+use crate::opcode::*;
+use alloc::string::String;
+
+/// Read the operand, change it and write it back: shifts, rotates, INC and DEC, on memory or
+/// the accumulator.
+///
+/// The unofficial ones then combine the result with a register like `Arithmetic`, e.g. SLO
+/// shifts left then ORs the result into the accumulator.
+pub struct ReadModifyWrite {
+    modify: Modify,
+
+    /// What the unofficial opcodes do with the result.
+    combine: Option<Kind>,
+
+    /// Addressing mode.
+    mode: AddressMode,
+}
+
+enum Modify {
+    ShiftLeft,
+    ShiftRight,
+
+    /// Shift in the carry.
+    RotateLeft,
+    RotateRight,
+
+    Increment,
+    Decrement,
+}
+
+impl Modify {
+    /// The changed value, setting the flags.
+    fn apply(&self, status: &mut ProcessorStatus, value: u8) -> u8 {
+        let result = match self {
+            Modify::ShiftLeft => {
+                status.carry = value & 0x80 != 0;
+                value << 1
+            }
+            Modify::ShiftRight => {
+                status.carry = value & 0x01 != 0;
+                value >> 1
+            }
+            Modify::RotateLeft => {
+                let carry = status.carry as u8;
+                status.carry = value & 0x80 != 0;
+                value << 1 | carry
+            }
+            Modify::RotateRight => {
+                let carry = (status.carry as u8) << 7;
+                status.carry = value & 0x01 != 0;
+                value >> 1 | carry
+            }
+            Modify::Increment => value.wrapping_add(1),
+            Modify::Decrement => value.wrapping_sub(1),
+        };
+
+        status.update_load(result);
+        result
+    }
+}
+
+impl ReadModifyWrite {
+    pub fn new(opcode: u8, cpu: &Cpu) -> Option<Self> {
+        let (modify, combine) = match opcode {
+            0x0A | 0x06 | 0x16 | 0x0E | 0x1E => (Modify::ShiftLeft, None),
+            0x4A | 0x46 | 0x56 | 0x4E | 0x5E => (Modify::ShiftRight, None),
+            0x2A | 0x26 | 0x36 | 0x2E | 0x3E => (Modify::RotateLeft, None),
+            0x6A | 0x66 | 0x76 | 0x6E | 0x7E => (Modify::RotateRight, None),
+            0xE6 | 0xF6 | 0xEE | 0xFE => (Modify::Increment, None),
+            0xC6 | 0xD6 | 0xCE | 0xDE => (Modify::Decrement, None),
+
+            // SLO, RLA, SRE, RRA, DCP and ISB.
+            0x03 | 0x07 | 0x0F | 0x13 | 0x17 | 0x1B | 0x1F => (Modify::ShiftLeft, Some(Kind::Or)),
+            0x23 | 0x27 | 0x2F | 0x33 | 0x37 | 0x3B | 0x3F => (Modify::RotateLeft, Some(Kind::And)),
+            0x43 | 0x47 | 0x4F | 0x53 | 0x57 | 0x5B | 0x5F => {
+                (Modify::ShiftRight, Some(Kind::ExclusiveOr))
+            }
+            0x63 | 0x67 | 0x6F | 0x73 | 0x77 | 0x7B | 0x7F => {
+                (Modify::RotateRight, Some(Kind::Add))
+            }
+            0xC3 | 0xC7 | 0xCF | 0xD3 | 0xD7 | 0xDB | 0xDF => {
+                (Modify::Decrement, Some(Kind::Compare(Register::A)))
+            }
+            0xE3 | 0xE7 | 0xEF | 0xF3 | 0xF7 | 0xFB | 0xFF => {
+                (Modify::Increment, Some(Kind::Subtract))
+            }
+            _ => return None,
+        };
+
+        Some(ReadModifyWrite {
+            modify,
+            combine,
+            mode: AddressMode::new(table::info(opcode).addressing, cpu)?,
+        })
+    }
+}
+
+impl Operation for ReadModifyWrite {
+    fn execute(&self, cpu: &mut Cpu) {
+        cpu.program_counter += self.mode.bytes();
+        cpu.cycles += self.mode.read_modify_write_cycles();
+
+        if let AddressMode::Accumulator = self.mode {
+            cpu.a = self.modify.apply(&mut cpu.status, cpu.a);
+            return;
+        }
+
+        // The 6502 writes the value back unchanged while it's changing it.
+        let addr = self.mode.to_addr(cpu).unwrap();
+        let value = cpu.read(addr);
+        cpu.write(addr, value);
+        let result = self.modify.apply(&mut cpu.status, value);
+        cpu.write(addr, result);
+
+        if let Some(combine) = &self.combine {
+            combine.apply(cpu, result);
+        }
+    }
+
+    fn dump(&self, cpu: &Cpu) -> String {
+        dump_instruction(cpu, Some(&self.mode))
+    }
+}
@@ -0,0 +1,376 @@
+/// Static information about every opcode, used to decode instructions without executing them.
+/// See http://www.oxyron.de/html/opcodes02.html for the unofficial opcodes.
+use core::fmt;
+
+/// How an instruction finds its operand, determines the length of the instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Addressing {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl Addressing {
+    /// Bytes following the opcode.
+    pub fn operand_bytes(self) -> u16 {
+        match self {
+            Addressing::Implied | Addressing::Accumulator => 0,
+            Addressing::Absolute
+            | Addressing::AbsoluteX
+            | Addressing::AbsoluteY
+            | Addressing::Indirect => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Mnemonic and addressing of an opcode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OpcodeInfo {
+    pub mnemonic: &'static str,
+    pub addressing: Addressing,
+
+    /// Unofficial opcodes work on the NMOS 6502 but were never documented.
+    pub official: bool,
+}
+
+impl OpcodeInfo {
+    /// Length of the whole instruction in bytes.
+    pub fn bytes(&self) -> u16 {
+        1 + self.addressing.operand_bytes()
+    }
+}
+
+impl fmt::Display for OpcodeInfo {
+    /// Unofficial opcodes are marked with a "*" like in nestest's log.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.official {
+            write!(f, "*")?;
+        }
+        write!(f, "{}", self.mnemonic)
+    }
+}
+
+const fn official(mnemonic: &'static str, addressing: Addressing) -> OpcodeInfo {
+    OpcodeInfo {
+        mnemonic,
+        addressing,
+        official: true,
+    }
+}
+
+const fn unofficial(mnemonic: &'static str, addressing: Addressing) -> OpcodeInfo {
+    OpcodeInfo {
+        mnemonic,
+        addressing,
+        official: false,
+    }
+}
+
+use Addressing::*;
+
+/// Indexed by opcode.
+pub const OPCODES: [OpcodeInfo; 256] = [
+    // $00
+    official("BRK", Implied),
+    official("ORA", IndirectX),
+    unofficial("STP", Implied),
+    unofficial("SLO", IndirectX),
+    unofficial("NOP", ZeroPage),
+    official("ORA", ZeroPage),
+    official("ASL", ZeroPage),
+    unofficial("SLO", ZeroPage),
+    official("PHP", Implied),
+    official("ORA", Immediate),
+    official("ASL", Accumulator),
+    unofficial("ANC", Immediate),
+    unofficial("NOP", Absolute),
+    official("ORA", Absolute),
+    official("ASL", Absolute),
+    unofficial("SLO", Absolute),
+    // $10
+    official("BPL", Relative),
+    official("ORA", IndirectY),
+    unofficial("STP", Implied),
+    unofficial("SLO", IndirectY),
+    unofficial("NOP", ZeroPageX),
+    official("ORA", ZeroPageX),
+    official("ASL", ZeroPageX),
+    unofficial("SLO", ZeroPageX),
+    official("CLC", Implied),
+    official("ORA", AbsoluteY),
+    unofficial("NOP", Implied),
+    unofficial("SLO", AbsoluteY),
+    unofficial("NOP", AbsoluteX),
+    official("ORA", AbsoluteX),
+    official("ASL", AbsoluteX),
+    unofficial("SLO", AbsoluteX),
+    // $20
+    official("JSR", Absolute),
+    official("AND", IndirectX),
+    unofficial("STP", Implied),
+    unofficial("RLA", IndirectX),
+    official("BIT", ZeroPage),
+    official("AND", ZeroPage),
+    official("ROL", ZeroPage),
+    unofficial("RLA", ZeroPage),
+    official("PLP", Implied),
+    official("AND", Immediate),
+    official("ROL", Accumulator),
+    unofficial("ANC", Immediate),
+    official("BIT", Absolute),
+    official("AND", Absolute),
+    official("ROL", Absolute),
+    unofficial("RLA", Absolute),
+    // $30
+    official("BMI", Relative),
+    official("AND", IndirectY),
+    unofficial("STP", Implied),
+    unofficial("RLA", IndirectY),
+    unofficial("NOP", ZeroPageX),
+    official("AND", ZeroPageX),
+    official("ROL", ZeroPageX),
+    unofficial("RLA", ZeroPageX),
+    official("SEC", Implied),
+    official("AND", AbsoluteY),
+    unofficial("NOP", Implied),
+    unofficial("RLA", AbsoluteY),
+    unofficial("NOP", AbsoluteX),
+    official("AND", AbsoluteX),
+    official("ROL", AbsoluteX),
+    unofficial("RLA", AbsoluteX),
+    // $40
+    official("RTI", Implied),
+    official("EOR", IndirectX),
+    unofficial("STP", Implied),
+    unofficial("SRE", IndirectX),
+    unofficial("NOP", ZeroPage),
+    official("EOR", ZeroPage),
+    official("LSR", ZeroPage),
+    unofficial("SRE", ZeroPage),
+    official("PHA", Implied),
+    official("EOR", Immediate),
+    official("LSR", Accumulator),
+    unofficial("ALR", Immediate),
+    official("JMP", Absolute),
+    official("EOR", Absolute),
+    official("LSR", Absolute),
+    unofficial("SRE", Absolute),
+    // $50
+    official("BVC", Relative),
+    official("EOR", IndirectY),
+    unofficial("STP", Implied),
+    unofficial("SRE", IndirectY),
+    unofficial("NOP", ZeroPageX),
+    official("EOR", ZeroPageX),
+    official("LSR", ZeroPageX),
+    unofficial("SRE", ZeroPageX),
+    official("CLI", Implied),
+    official("EOR", AbsoluteY),
+    unofficial("NOP", Implied),
+    unofficial("SRE", AbsoluteY),
+    unofficial("NOP", AbsoluteX),
+    official("EOR", AbsoluteX),
+    official("LSR", AbsoluteX),
+    unofficial("SRE", AbsoluteX),
+    // $60
+    official("RTS", Implied),
+    official("ADC", IndirectX),
+    unofficial("STP", Implied),
+    unofficial("RRA", IndirectX),
+    unofficial("NOP", ZeroPage),
+    official("ADC", ZeroPage),
+    official("ROR", ZeroPage),
+    unofficial("RRA", ZeroPage),
+    official("PLA", Implied),
+    official("ADC", Immediate),
+    official("ROR", Accumulator),
+    unofficial("ARR", Immediate),
+    official("JMP", Indirect),
+    official("ADC", Absolute),
+    official("ROR", Absolute),
+    unofficial("RRA", Absolute),
+    // $70
+    official("BVS", Relative),
+    official("ADC", IndirectY),
+    unofficial("STP", Implied),
+    unofficial("RRA", IndirectY),
+    unofficial("NOP", ZeroPageX),
+    official("ADC", ZeroPageX),
+    official("ROR", ZeroPageX),
+    unofficial("RRA", ZeroPageX),
+    official("SEI", Implied),
+    official("ADC", AbsoluteY),
+    unofficial("NOP", Implied),
+    unofficial("RRA", AbsoluteY),
+    unofficial("NOP", AbsoluteX),
+    official("ADC", AbsoluteX),
+    official("ROR", AbsoluteX),
+    unofficial("RRA", AbsoluteX),
+    // $80
+    unofficial("NOP", Immediate),
+    official("STA", IndirectX),
+    unofficial("NOP", Immediate),
+    unofficial("SAX", IndirectX),
+    official("STY", ZeroPage),
+    official("STA", ZeroPage),
+    official("STX", ZeroPage),
+    unofficial("SAX", ZeroPage),
+    official("DEY", Implied),
+    unofficial("NOP", Immediate),
+    official("TXA", Implied),
+    unofficial("XAA", Immediate),
+    official("STY", Absolute),
+    official("STA", Absolute),
+    official("STX", Absolute),
+    unofficial("SAX", Absolute),
+    // $90
+    official("BCC", Relative),
+    official("STA", IndirectY),
+    unofficial("STP", Implied),
+    unofficial("AHX", IndirectY),
+    official("STY", ZeroPageX),
+    official("STA", ZeroPageX),
+    official("STX", ZeroPageY),
+    unofficial("SAX", ZeroPageY),
+    official("TYA", Implied),
+    official("STA", AbsoluteY),
+    official("TXS", Implied),
+    unofficial("TAS", AbsoluteY),
+    unofficial("SHY", AbsoluteX),
+    official("STA", AbsoluteX),
+    unofficial("SHX", AbsoluteY),
+    unofficial("AHX", AbsoluteY),
+    // $A0
+    official("LDY", Immediate),
+    official("LDA", IndirectX),
+    official("LDX", Immediate),
+    unofficial("LAX", IndirectX),
+    official("LDY", ZeroPage),
+    official("LDA", ZeroPage),
+    official("LDX", ZeroPage),
+    unofficial("LAX", ZeroPage),
+    official("TAY", Implied),
+    official("LDA", Immediate),
+    official("TAX", Implied),
+    unofficial("LAX", Immediate),
+    official("LDY", Absolute),
+    official("LDA", Absolute),
+    official("LDX", Absolute),
+    unofficial("LAX", Absolute),
+    // $B0
+    official("BCS", Relative),
+    official("LDA", IndirectY),
+    unofficial("STP", Implied),
+    unofficial("LAX", IndirectY),
+    official("LDY", ZeroPageX),
+    official("LDA", ZeroPageX),
+    official("LDX", ZeroPageY),
+    unofficial("LAX", ZeroPageY),
+    official("CLV", Implied),
+    official("LDA", AbsoluteY),
+    official("TSX", Implied),
+    unofficial("LAS", AbsoluteY),
+    official("LDY", AbsoluteX),
+    official("LDA", AbsoluteX),
+    official("LDX", AbsoluteY),
+    unofficial("LAX", AbsoluteY),
+    // $C0
+    official("CPY", Immediate),
+    official("CMP", IndirectX),
+    unofficial("NOP", Immediate),
+    unofficial("DCP", IndirectX),
+    official("CPY", ZeroPage),
+    official("CMP", ZeroPage),
+    official("DEC", ZeroPage),
+    unofficial("DCP", ZeroPage),
+    official("INY", Implied),
+    official("CMP", Immediate),
+    official("DEX", Implied),
+    unofficial("AXS", Immediate),
+    official("CPY", Absolute),
+    official("CMP", Absolute),
+    official("DEC", Absolute),
+    unofficial("DCP", Absolute),
+    // $D0
+    official("BNE", Relative),
+    official("CMP", IndirectY),
+    unofficial("STP", Implied),
+    unofficial("DCP", IndirectY),
+    unofficial("NOP", ZeroPageX),
+    official("CMP", ZeroPageX),
+    official("DEC", ZeroPageX),
+    unofficial("DCP", ZeroPageX),
+    official("CLD", Implied),
+    official("CMP", AbsoluteY),
+    unofficial("NOP", Implied),
+    unofficial("DCP", AbsoluteY),
+    unofficial("NOP", AbsoluteX),
+    official("CMP", AbsoluteX),
+    official("DEC", AbsoluteX),
+    unofficial("DCP", AbsoluteX),
+    // $E0
+    official("CPX", Immediate),
+    official("SBC", IndirectX),
+    unofficial("NOP", Immediate),
+    unofficial("ISB", IndirectX),
+    official("CPX", ZeroPage),
+    official("SBC", ZeroPage),
+    official("INC", ZeroPage),
+    unofficial("ISB", ZeroPage),
+    official("INX", Implied),
+    official("SBC", Immediate),
+    official("NOP", Implied),
+    unofficial("SBC", Immediate),
+    official("CPX", Absolute),
+    official("SBC", Absolute),
+    official("INC", Absolute),
+    unofficial("ISB", Absolute),
+    // $F0
+    official("BEQ", Relative),
+    official("SBC", IndirectY),
+    unofficial("STP", Implied),
+    unofficial("ISB", IndirectY),
+    unofficial("NOP", ZeroPageX),
+    official("SBC", ZeroPageX),
+    official("INC", ZeroPageX),
+    unofficial("ISB", ZeroPageX),
+    official("SED", Implied),
+    official("SBC", AbsoluteY),
+    unofficial("NOP", Implied),
+    unofficial("ISB", AbsoluteY),
+    unofficial("NOP", AbsoluteX),
+    official("SBC", AbsoluteX),
+    official("INC", AbsoluteX),
+    unofficial("ISB", AbsoluteX),
+];
+
+/// Information about an opcode.
+pub fn info(opcode: u8) -> OpcodeInfo {
+    OPCODES[opcode as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        assert_eq!(OPCODES.iter().filter(|info| info.official).count(), 151);
+
+        assert_eq!(info(0x4C), official("JMP", Absolute));
+        assert_eq!(info(0x6C).bytes(), 3);
+        assert_eq!(info(0xB1), official("LDA", IndirectY));
+        assert_eq!(info(0xEB).to_string(), "*SBC");
+    }
+}
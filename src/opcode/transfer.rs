@@ -0,0 +1,111 @@
+use crate::cpu::Cpu;
+use crate::opcode::Operation;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+
+/// Copy one register to another.
+pub enum Transfer {
+    /// (T)ransfer (A) to (X).
+    Tax,
+
+    /// (T)ransfer (A) to (Y).
+    Tay,
+
+    /// (T)ransfer (X) to (A).
+    Txa,
+
+    /// (T)ransfer (Y) to (A).
+    Tya,
+
+    /// (T)ransfer the (S)tack pointer to (X).
+    Tsx,
+
+    /// (T)ransfer (X) to the (S)tack pointer, the only one leaving the flags alone.
+    Txs,
+}
+
+impl Transfer {
+    const BYTE_COUNT: u16 = 1;
+    const CYCLE_LENGTH: u64 = 2;
+
+    /// Convert from the opcode to transfer type enum.
+    pub fn new(opcode: u8) -> Option<Transfer> {
+        match opcode {
+            0xAA => Some(Transfer::Tax),
+            0xA8 => Some(Transfer::Tay),
+            0x8A => Some(Transfer::Txa),
+            0x98 => Some(Transfer::Tya),
+            0xBA => Some(Transfer::Tsx),
+            0x9A => Some(Transfer::Txs),
+            _ => None,
+        }
+    }
+
+    /// Convert from Transfer to opcode.
+    pub fn to_opcode(&self) -> u8 {
+        match &self {
+            Transfer::Tax => 0xAA,
+            Transfer::Tay => 0xA8,
+            Transfer::Txa => 0x8A,
+            Transfer::Tya => 0x98,
+            Transfer::Tsx => 0xBA,
+            Transfer::Txs => 0x9A,
+        }
+    }
+}
+
+impl fmt::Display for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match &self {
+            Transfer::Tax => "TAX",
+            Transfer::Tay => "TAY",
+            Transfer::Txa => "TXA",
+            Transfer::Tya => "TYA",
+            Transfer::Tsx => "TSX",
+            Transfer::Txs => "TXS",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl Operation for Transfer {
+    fn execute(&self, cpu: &mut Cpu) {
+        cpu.program_counter += Self::BYTE_COUNT;
+        cpu.cycles += Self::CYCLE_LENGTH;
+
+        let value = match &self {
+            Transfer::Tax => {
+                cpu.x = cpu.a;
+                cpu.x
+            }
+            Transfer::Tay => {
+                cpu.y = cpu.a;
+                cpu.y
+            }
+            Transfer::Txa => {
+                cpu.a = cpu.x;
+                cpu.a
+            }
+            Transfer::Tya => {
+                cpu.a = cpu.y;
+                cpu.a
+            }
+            Transfer::Tsx => {
+                cpu.x = cpu.stack.as_stack_offset();
+                cpu.x
+            }
+            Transfer::Txs => {
+                cpu.stack.set_stack_offset(cpu.x);
+                return;
+            }
+        };
+
+        cpu.status.update_load(value);
+    }
+
+    fn dump(&self, _cpu: &Cpu) -> String {
+        format!("{:02X}        {}        ", self.to_opcode(), self)
+    }
+}
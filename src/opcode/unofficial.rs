@@ -0,0 +1,70 @@
+/// Unofficial opcodes loading and storing two registers at once. The ones combining a
+/// read-modify-write with arithmetic are in `ReadModifyWrite`, the unofficial NOPs are `Nop`.
+use crate::cpu::Cpu;
+use crate::opcode::addressing_mode::AddressMode;
+use crate::opcode::*;
+use alloc::string::String;
+
+/// Load both A and X.
+pub struct Lax {
+    /// Addressing mode.
+    mode: AddressMode,
+}
+
+impl Lax {
+    pub fn new(opcode: u8, cpu: &Cpu) -> Option<Self> {
+        match opcode {
+            0xA7 | 0xB7 | 0xAF | 0xBF | 0xA3 | 0xB3 => Some(Lax {
+                mode: AddressMode::new(table::info(opcode).addressing, cpu)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Operation for Lax {
+    fn execute(&self, cpu: &mut Cpu) {
+        cpu.program_counter += self.mode.bytes();
+        cpu.cycles += self.mode.read_cycles(cpu);
+        let value = self.mode.to_value(cpu);
+
+        cpu.a = value;
+        cpu.x = value;
+        cpu.status.update_load(value);
+    }
+
+    fn dump(&self, cpu: &Cpu) -> String {
+        dump_instruction(cpu, Some(&self.mode))
+    }
+}
+
+/// Store A and X ANDed together, the flags are left alone.
+pub struct Sax {
+    /// Addressing mode.
+    mode: AddressMode,
+}
+
+impl Sax {
+    pub fn new(opcode: u8, cpu: &Cpu) -> Option<Self> {
+        match opcode {
+            0x87 | 0x97 | 0x8F | 0x83 => Some(Sax {
+                mode: AddressMode::new(table::info(opcode).addressing, cpu)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Operation for Sax {
+    fn execute(&self, cpu: &mut Cpu) {
+        cpu.program_counter += self.mode.bytes();
+        cpu.cycles += self.mode.write_cycles();
+
+        let addr = self.mode.to_addr(cpu).unwrap();
+        cpu.write(addr, cpu.a & cpu.x);
+    }
+
+    fn dump(&self, cpu: &Cpu) -> String {
+        dump_instruction(cpu, Some(&self.mode))
+    }
+}
@@ -0,0 +1,304 @@
+/// ROM hacks and translations distributed as patches, applied to the ROM file in memory before the
+/// cartridge is built so the original file is left untouched.
+///
+/// Patches apply to the whole file, header included, as they're made against headered ROMs.
+///   IPS: https://zerosoft.zophar.net/ips.php, with the truncation extension.
+///   BPS: https://www.romhacking.net/documents/746/, checked against the CRC32s it carries.
+use crate::ines::crc32;
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+/// Source, target and patch CRC32s at the end of BPS patches.
+const BPS_FOOTER_SIZE: usize = 12;
+
+/// Read a patch and apply it to `rom`, the format is recognised from its contents.
+pub fn apply_file<P: AsRef<Path>>(rom: &[u8], path: P) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let patch = std::fs::read(path)
+        .with_context(|| format!("Failed to read patch \"{}\"", path.display()))?;
+
+    apply(rom, &patch).with_context(|| format!("Failed to apply \"{}\"", path.display()))
+}
+
+/// Apply an IPS or BPS patch to `rom`.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(anyhow!("Not an IPS or BPS patch."))
+    }
+}
+
+/// Reads through a patch, failing on truncated ones.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset.saturating_add(len))
+            .ok_or_else(|| anyhow!("Truncated patch at offset {}.", self.offset))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Big endian, as IPS stores its numbers.
+    fn big_endian(&mut self, len: usize) -> Result<usize> {
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    /// BPS's variable length numbers, 7 bits at a time with the last byte's top bit set.
+    fn number(&mut self) -> Result<usize> {
+        let too_large = |offset| anyhow!("Number too large at offset {}.", offset);
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.byte()?;
+            value = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|bits| value.checked_add(bits))
+                .ok_or_else(|| too_large(self.offset))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift
+                .checked_mul(0x80)
+                .ok_or_else(|| too_large(self.offset))?;
+            value = value
+                .checked_add(shift)
+                .ok_or_else(|| too_large(self.offset))?;
+        }
+    }
+}
+
+/// Records of an offset and the bytes to write there, or a byte repeated, until "EOF" and an
+/// optional size to truncate to.
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let mut target = rom.to_vec();
+    let mut reader = Reader {
+        bytes: patch,
+        offset: IPS_MAGIC.len(),
+    };
+
+    loop {
+        if reader.take(IPS_EOF.len())? == IPS_EOF {
+            break;
+        }
+        reader.offset -= IPS_EOF.len();
+
+        let offset = reader.big_endian(3)?;
+        let (len, value) = match reader.big_endian(2)? {
+            0 => (reader.big_endian(2)?, None),
+            len => (len, Some(reader.take(len)?)),
+        };
+        let rle = match value {
+            Some(_) => 0,
+            None => reader.byte()?,
+        };
+
+        if target.len() < offset + len {
+            target.resize(offset + len, 0);
+        }
+        match value {
+            Some(bytes) => target[offset..offset + len].copy_from_slice(bytes),
+            None => target[offset..offset + len].fill(rle),
+        }
+    }
+
+    if patch.len() - reader.offset >= 3 {
+        target.truncate(reader.big_endian(3)?);
+    }
+    Ok(target)
+}
+
+/// Actions building the target from copies of the source, of the target so far and of data in
+/// the patch.
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_SIZE {
+        return Err(anyhow!("Truncated patch."));
+    }
+
+    let footer = &patch[patch.len() - BPS_FOOTER_SIZE..];
+    let checksum = |offset: usize| {
+        u32::from_le_bytes([
+            footer[offset],
+            footer[offset + 1],
+            footer[offset + 2],
+            footer[offset + 3],
+        ])
+    };
+    let (source_crc, target_crc, patch_crc) = (checksum(0), checksum(4), checksum(8));
+
+    if crc32(&patch[..patch.len() - 4]) != patch_crc {
+        return Err(anyhow!("The patch is corrupt, its CRC32 doesn't match."));
+    }
+    if crc32(rom) != source_crc {
+        return Err(anyhow!(
+            "The patch is for another ROM, expected CRC32 {:08X} but the ROM's is {:08X}.",
+            source_crc,
+            crc32(rom)
+        ));
+    }
+
+    let mut reader = Reader {
+        bytes: &patch[..patch.len() - BPS_FOOTER_SIZE],
+        offset: BPS_MAGIC.len(),
+    };
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.take(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(anyhow!(
+            "The patch is for a {} byte ROM, this one is {} bytes.",
+            source_size,
+            rom.len()
+        ));
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+    while reader.offset < reader.bytes.len() {
+        let action = reader.number()?;
+        let len = (action >> 2) + 1;
+        if target_size - target.len() < len {
+            return Err(anyhow!("The patch writes past the end of the ROM."));
+        }
+
+        match action & 0b11 {
+            // SourceRead: the source at the same place.
+            0 => {
+                let start = target.len();
+                let bytes = rom
+                    .get(start..start + len)
+                    .ok_or_else(|| anyhow!("The patch reads past the end of the ROM."))?;
+                target.extend_from_slice(bytes);
+            }
+            // TargetRead: bytes from the patch.
+            1 => target.extend_from_slice(reader.take(len)?),
+            // SourceCopy: the source anywhere, relative to the last copy.
+            2 => {
+                source_offset = relative(source_offset, reader.number()?)?;
+                let bytes = rom
+                    .get(source_offset..source_offset + len)
+                    .ok_or_else(|| anyhow!("The patch reads past the end of the ROM."))?;
+                target.extend_from_slice(bytes);
+                source_offset += len;
+            }
+            // TargetCopy: the target so far, byte by byte as the copy may overlap its output.
+            _ => {
+                target_offset = relative(target_offset, reader.number()?)?;
+                for _ in 0..len {
+                    let byte = *target
+                        .get(target_offset)
+                        .ok_or_else(|| anyhow!("The patch copies a byte not written yet."))?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size || crc32(&target) != target_crc {
+        return Err(anyhow!("The patched ROM doesn't match the patch's CRC32."));
+    }
+    Ok(target)
+}
+
+/// Move `offset` by a BPS relative offset, the lowest bit is the sign.
+fn relative(offset: usize, value: usize) -> Result<usize> {
+    let distance = value >> 1;
+    let moved = if value & 1 != 0 {
+        offset.checked_sub(distance)
+    } else {
+        offset.checked_add(distance)
+    };
+    moved.ok_or_else(|| anyhow!("The patch copies from before the start."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ips() -> Result<()> {
+        let rom = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        // 2 bytes at 1, 3 bytes of $FF at 5 and past the end.
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xAA, 0xBB]);
+        patch.extend_from_slice(&[0, 0, 7, 0, 0, 0, 3, 0xFF]);
+        patch.extend_from_slice(b"EOF");
+
+        assert_eq!(
+            apply(&rom, &patch)?,
+            vec![0, 0xAA, 0xBB, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF]
+        );
+
+        // Truncated to 4 bytes.
+        patch.extend_from_slice(&[0, 0, 4]);
+        assert_eq!(apply(&rom, &patch)?, vec![0, 0xAA, 0xBB, 0]);
+
+        assert!(apply(&rom, b"PATCH\x00\x00").is_err());
+        assert!(apply(&rom, b"NES\x1A").is_err());
+        Ok(())
+    }
+
+    /// A BPS patch of `actions` from `source` to `target`.
+    fn bps(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+        let mut patch = b"BPS1".to_vec();
+        patch.extend_from_slice(&[0x80 | source.len() as u8, 0x80 | target.len() as u8, 0x80]);
+        patch.extend_from_slice(actions);
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_bps() -> Result<()> {
+        let source = b"ABCDEFGH";
+        let target = b"ABxyxyxyGH";
+        let patch = bps(
+            source,
+            target,
+            &[
+                0x80 | (1 << 2),     // SourceRead 2: "AB"
+                0x80 | (1 << 2 | 1), // TargetRead 2: "xy"
+                b'x',
+                b'y',
+                0x80 | (3 << 2 | 3), // TargetCopy 4 from 2: "xyxy"
+                0x80 | (2 << 1),
+                0x80 | (1 << 2 | 2), // SourceCopy 2 from 6: "GH"
+                0x80 | (6 << 1),
+            ],
+        );
+        assert_eq!(apply(source, &patch)?, target.to_vec());
+
+        // Another ROM.
+        assert!(apply(b"ABCDEFGX", &patch).is_err());
+
+        // Corrupted.
+        let mut corrupt = patch.clone();
+        corrupt[8] ^= 1;
+        assert!(apply(source, &corrupt).is_err());
+        Ok(())
+    }
+}
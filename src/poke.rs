@@ -0,0 +1,100 @@
+/// Memory pokes, writes to the CPU's bus made from outside the game.
+///
+/// Unlike cheats they go through the bus and change memory, like the game writing it: a poke
+/// to a register reaches the PPU, APU or mapper. Made once at power on to try something quickly,
+/// or after every frame to hold memory at a value, e.g. to stand in for hardware that isn't
+/// emulated yet.
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+use core::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Poke {
+    pub address: u16,
+    pub value: u8,
+}
+
+impl FromStr for Poke {
+    type Err = String;
+
+    /// "AAAA=VV" in hex, the address optionally starting with a '$'.
+    fn from_str(poke: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid poke \"{}\", expected e.g. \"$0075=09\".", poke);
+
+        let (address, value) = poke.split_once('=').ok_or_else(invalid)?;
+        let address = address.trim();
+        let address = address.strip_prefix('$').unwrap_or(address);
+        let value = value.trim();
+        if address.is_empty() || address.len() > 4 || value.is_empty() || value.len() > 2 {
+            return Err(invalid());
+        }
+
+        Ok(Poke {
+            address: u16::from_str_radix(address, 16).map_err(|_| invalid())?,
+            value: u8::from_str_radix(value, 16).map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for Poke {
+    /// e.g. "$0075=09".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "${:04X}={:02X}", self.address, self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Cpu, Stop};
+    use crate::ines;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn test_parse() {
+        let poke: Poke = "$0075=09".parse().unwrap();
+        assert_eq!(
+            poke,
+            Poke {
+                address: 0x0075,
+                value: 0x09
+            }
+        );
+        assert_eq!(poke.to_string(), "$0075=09");
+        assert_eq!(
+            "6000 = ff".parse(),
+            Ok(Poke {
+                address: 0x6000,
+                value: 0xFF
+            })
+        );
+
+        for poke in &[
+            "0075", "0075=", "=09", "10000=00", "0075=100", "$=01", "G000=01",
+        ] {
+            assert!(poke.parse::<Poke>().is_err(), "{}", poke);
+        }
+    }
+
+    #[test]
+    fn test_every_frame() -> anyhow::Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+        cpu.pokes = vec!["0300=42".parse().unwrap()];
+
+        // JMP $0200, spinning until the frame is over.
+        cpu.memory[0x0200..0x0203].copy_from_slice(&[0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+
+        assert_eq!(cpu.run_frame(), Stop::FrameComplete);
+        assert_eq!(cpu.memory[0x0300], 0x42);
+
+        // Written again once the frame is over, whatever the game did.
+        cpu.memory[0x0300] = 0;
+        assert_eq!(cpu.run_frame(), Stop::FrameComplete);
+        assert_eq!(cpu.memory[0x0300], 0x42);
+        Ok(())
+    }
+}
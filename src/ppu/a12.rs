@@ -0,0 +1,91 @@
+use alloc::boxed::Box;
+use serde::{Deserialize, Serialize};
+
+/// Filtering of the PPU address line A12.
+///
+/// MMC3 style mappers clock their scanline counter on rising edges of A12. The mapper ignores
+/// edges when A12 has not been low for long enough, this filters the rapid toggles that happen
+/// between background and sprite fetches so only one edge is seen per scanline.
+/// See http://wiki.nesdev.com/w/index.php/MMC3#IRQ_Specifics.
+const A12_MASK: u16 = 0x1000;
+
+/// Dots A12 must stay low before a rising edge is counted, 3 CPU cycles.
+const FILTER_DOTS: u64 = 3 * crate::ppu::DOTS_PER_CPU_CYCLE;
+
+/// Callback invoked on every filtered rising edge of A12.
+pub type A12Callback = Box<dyn FnMut() + Send>;
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct A12Filter {
+    /// Level of A12 on the last access.
+    high: bool,
+
+    /// Dot A12 last went low.
+    low_since: u64,
+}
+
+impl A12Filter {
+    /// Observe an access on the address bus at the given dot.
+    ///
+    /// Returns whether this is a rising edge that passes the filter.
+    pub fn update(&mut self, addr: u16, dot: u64) -> bool {
+        let high = addr & A12_MASK != 0;
+        let was_high = core::mem::replace(&mut self.high, high);
+
+        if high && !was_high {
+            return dot - self.low_since >= FILTER_DOTS;
+        }
+
+        if !high && was_high {
+            self.low_since = dot;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::Mirroring;
+    use crate::ppu::{Ppu, DOTS_PER_SCANLINE, SCANLINES_PER_FRAME};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_filter() {
+        let mut filter = A12Filter::default();
+
+        assert!(filter.update(0x1000, 100));
+        assert!(!filter.update(0x1000, 101));
+
+        // Low for too short.
+        filter.update(0x0000, 102);
+        assert!(!filter.update(0x1000, 102 + FILTER_DOTS - 1));
+
+        filter.update(0x0000, 200);
+        assert!(filter.update(0x1000, 200 + FILTER_DOTS));
+    }
+
+    #[test]
+    fn test_one_edge_per_scanline() {
+        let mut ppu = Ppu::new(vec![], Mirroring::Horizontal);
+
+        // Background from the left table, sprites from the right table.
+        ppu.write_register(0x2000, 0b0000_1000);
+        ppu.write_register(0x2001, 0b0001_1000);
+
+        let edges = Arc::new(AtomicUsize::new(0));
+        let counter = edges.clone();
+        ppu.on_a12_rising_edge(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+
+        for _ in 0..DOTS_PER_SCANLINE as u64 * SCANLINES_PER_FRAME as u64 {
+            ppu.tick();
+        }
+
+        // Every visible scanline and the pre-render scanline.
+        assert_eq!(edges.load(Ordering::Relaxed), 241);
+    }
+}
@@ -0,0 +1,577 @@
+/// This file contains the PPU logic.
+/// Used http://wiki.nesdev.com/w/index.php/PPU as a reference.
+///
+/// The PPU renders a 256x240 picture. Each pixel is stored as an index into the system palette,
+/// frontends convert to RGBA when they need to display it.
+///
+/// The PPU is driven one dot at a time. An NTSC frame is 262 scanlines of 341 dots and the PPU runs
+/// three dots for every CPU cycle. PAL and Dendy frames are 312 scanlines, see `Region`.
+mod a12;
+mod palette;
+mod registers;
+mod render;
+mod sprite;
+mod viewer;
+
+use crate::ines::Mirroring;
+use crate::region::Region;
+use crate::vs_system::VsPpu;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+pub use palette::{to_rgba, to_rgba_with, Palette, SYSTEM_PALETTE};
+pub use viewer::{render_chr, NAMETABLES_HEIGHT, NAMETABLES_WIDTH, PALETTES, PATTERN_TABLE_SIZE};
+
+/// Width of the visible picture in pixels.
+pub const SCREEN_WIDTH: usize = 256;
+
+/// Height of the visible picture in pixels.
+pub const SCREEN_HEIGHT: usize = 240;
+
+/// Number of dots (PPU cycles) in a scanline.
+pub const DOTS_PER_SCANLINE: u16 = 341;
+
+/// Number of scanlines in an NTSC frame, including vertical blank and the pre-render scanline.
+pub const SCANLINES_PER_FRAME: u16 = 262;
+
+/// Number of dots the PPU runs for each CPU cycle.
+pub const DOTS_PER_CPU_CYCLE: u64 = 3;
+
+/// Callback invoked with the finished frame.
+pub type FrameCallback = Box<dyn FnMut(&[u8]) + Send>;
+
+/// State of the PPU.
+#[derive(Deserialize, Serialize)]
+pub struct Ppu {
+    /// PPUCTRL ($2000).
+    ctrl: u8,
+
+    /// PPUMASK ($2001).
+    mask: u8,
+
+    /// PPUSTATUS ($2002). Only the upper 3 bits are used.
+    status: u8,
+
+    /// OAMADDR ($2003).
+    oam_addr: u8,
+
+    /// Object attribute memory, 64 sprites of 4 bytes each.
+    #[serde(with = "crate::byte_array")]
+    oam: [u8; 256],
+
+    /// Current VRAM address (15 bits).
+    ///
+    /// Also holds the scroll position during rendering, see
+    /// http://wiki.nesdev.com/w/index.php/PPU_scrolling.
+    v: u16,
+
+    /// Temporary VRAM address (15 bits).
+    t: u16,
+
+    /// Fine X scroll (3 bits).
+    fine_x: u8,
+
+    /// Shared write toggle for PPUSCROLL and PPUADDR.
+    write_latch: bool,
+
+    /// Internal buffer that delays PPUDATA reads.
+    read_buffer: u8,
+
+    /// Last value driven on the CPU/PPU data bus.
+    open_bus: registers::OpenBus,
+
+    /// Pattern tables. Either CHR ROM or CHR RAM from the cartridge.
+    chr: Vec<u8>,
+
+    /// Whether the pattern tables are writable.
+    chr_is_ram: bool,
+
+    /// Nametable memory, enough for four screens although most cartridges only use two.
+    #[serde(with = "crate::byte_array")]
+    vram: [u8; 4096],
+
+    /// Nametable mirroring from the cartridge.
+    mirroring: Mirroring,
+
+    /// Palette memory.
+    palette: [u8; 32],
+
+    /// Sets the length of the frame and when vertical blank starts.
+    region: Region,
+
+    /// RGB PPU of a VS System cabinet, some swap registers and return an ID.
+    vs_ppu: Option<VsPpu>,
+
+    /// Current scanline, 0-239 are visible, the last one is the pre-render scanline.
+    scanline: u16,
+
+    /// Current dot within the scanline.
+    dot: u16,
+
+    /// Odd frames skip a dot when rendering is enabled.
+    odd_frame: bool,
+
+    /// Set when the PPU requests a NMI, cleared once the CPU acknowledges it.
+    nmi_pending: bool,
+
+    /// Background fetch and shift registers.
+    background: render::Background,
+
+    /// Sprites found for the current and next scanline.
+    sprites: sprite::Sprites,
+
+    /// Picture being drawn.
+    ///
+    /// One byte per pixel, each byte is an index into the system palette.
+    frame: Vec<u8>,
+
+    /// Number of frames completed since power up.
+    frame_count: u64,
+
+    /// Called every time a frame is completed.
+    #[serde(skip)]
+    on_frame_complete: Option<FrameCallback>,
+
+    /// Frames are completed without calling `on_frame_complete`.
+    #[serde(skip)]
+    frame_callback_paused: bool,
+
+    /// Number of dots since power up.
+    dot_count: u64,
+
+    /// Filter for rising edges of A12 on the address bus.
+    a12_filter: a12::A12Filter,
+
+    /// Called on every filtered rising edge of A12, used by the cartridge.
+    #[serde(skip)]
+    on_a12_rising_edge: Option<a12::A12Callback>,
+}
+
+impl Ppu {
+    /// Size of CHR RAM when the cartridge has no CHR ROM.
+    const CHR_RAM_SIZE: usize = 0x2000;
+
+    const VBLANK_MASK: u8 = 0b1000_0000;
+    const SPRITE_ZERO_HIT_MASK: u8 = 0b0100_0000;
+    const SPRITE_OVERFLOW_MASK: u8 = 0b0010_0000;
+
+    const CTRL_NMI_ENABLE_MASK: u8 = 0b1000_0000;
+
+    const MASK_SHOW_BACKGROUND_LEFT: u8 = 0b0000_0010;
+    const MASK_SHOW_SPRITES_LEFT: u8 = 0b0000_0100;
+    const MASK_SHOW_BACKGROUND: u8 = 0b0000_1000;
+    const MASK_SHOW_SPRITES: u8 = 0b0001_0000;
+
+    /// Create a PPU using the pattern tables of a cartridge.
+    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        let chr_is_ram = chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; Self::CHR_RAM_SIZE]
+        } else {
+            chr_rom
+        };
+
+        Ppu {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; 256],
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            write_latch: false,
+            read_buffer: 0,
+            open_bus: registers::OpenBus::default(),
+            chr,
+            chr_is_ram,
+            vram: [0; 4096],
+            mirroring,
+            palette: [0; 32],
+            region: Region::default(),
+            vs_ppu: None,
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            nmi_pending: false,
+            background: render::Background::default(),
+            sprites: sprite::Sprites::default(),
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_count: 0,
+            on_frame_complete: None,
+            frame_callback_paused: false,
+            dot_count: 0,
+            a12_filter: a12::A12Filter::default(),
+            on_a12_rising_edge: None,
+        }
+    }
+
+    /// A PPU as it powers on, with the same cartridge.
+    pub(crate) fn powered_on(&self) -> Ppu {
+        let chr = if self.chr_is_ram {
+            Vec::new()
+        } else {
+            self.chr.clone()
+        };
+        let mut ppu = Ppu::new(chr, self.mirroring);
+        ppu.region = self.region;
+        ppu
+    }
+
+    /// Reset clears what the CPU wrote to the registers, the memories are kept.
+    pub(crate) fn reset(&mut self) {
+        self.ctrl = 0;
+        self.mask = 0;
+        self.t = 0;
+        self.fine_x = 0;
+        self.write_latch = false;
+        self.read_buffer = 0;
+        self.odd_frame = false;
+    }
+
+    /// The last rendered picture.
+    ///
+    /// Row major, one palette index per pixel.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// The last rendered picture converted to RGBA (4 bytes per pixel).
+    pub fn frame_rgba(&self) -> Vec<u8> {
+        to_rgba(&self.frame)
+    }
+
+    /// Number of frames completed since power up.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Register a callback to receive every finished frame.
+    ///
+    /// Replaces any previously registered callback.
+    pub fn on_frame_complete<F>(&mut self, callback: F)
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        self.on_frame_complete = Some(Box::new(callback));
+    }
+
+    /// Stop or resume calling `on_frame_complete`, e.g. for frames that will be thrown away.
+    pub fn pause_frame_callback(&mut self, paused: bool) {
+        self.frame_callback_paused = paused;
+    }
+
+    /// Take over the callbacks of another PPU, e.g. the one a save state replaced.
+    pub fn take_callbacks(&mut self, other: &mut Ppu) {
+        self.on_frame_complete = other.on_frame_complete.take();
+        self.frame_callback_paused = other.frame_callback_paused;
+        self.on_a12_rising_edge = other.on_a12_rising_edge.take();
+    }
+
+    /// Register a callback for rising edges of the A12 address line.
+    ///
+    /// Edges after A12 was only briefly low are filtered out the same way the MMC3 does, the
+    /// cartridge can clock its scanline counter directly from this.
+    pub fn on_a12_rising_edge<F>(&mut self, callback: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.on_a12_rising_edge = Some(Box::new(callback));
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Switch to the frame of another region, from the next scanline on.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.scanline = self.scanline.min(self.pre_render_scanline());
+    }
+
+    pub fn vs_ppu(&self) -> Option<VsPpu> {
+        self.vs_ppu
+    }
+
+    pub fn set_vs_ppu(&mut self, vs_ppu: Option<VsPpu>) {
+        self.vs_ppu = vs_ppu;
+    }
+
+    /// Scanline used to prefetch the first tiles of the next frame.
+    fn pre_render_scanline(&self) -> u16 {
+        self.region.scanlines_per_frame() - 1
+    }
+
+    /// Current scanline.
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    /// Current dot within the scanline.
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// Whether the PPU is requesting a NMI. Acknowledges the request.
+    pub fn poll_nmi(&mut self) -> bool {
+        core::mem::replace(&mut self.nmi_pending, false)
+    }
+
+    /// Either the background or sprites are being rendered.
+    fn is_rendering_enabled(&self) -> bool {
+        self.mask & (Self::MASK_SHOW_BACKGROUND | Self::MASK_SHOW_SPRITES) != 0
+    }
+
+    /// Advance the PPU by a single dot.
+    pub fn tick(&mut self) {
+        if self.scanline < SCREEN_HEIGHT as u16 || self.scanline == self.pre_render_scanline() {
+            self.render_dot();
+        }
+
+        if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+            self.status |= Self::VBLANK_MASK;
+            if self.ctrl & Self::CTRL_NMI_ENABLE_MASK != 0 {
+                self.nmi_pending = true;
+            }
+
+            self.complete_frame();
+        }
+
+        if self.scanline == self.pre_render_scanline() && self.dot == 1 {
+            self.status &=
+                !(Self::VBLANK_MASK | Self::SPRITE_ZERO_HIT_MASK | Self::SPRITE_OVERFLOW_MASK);
+        }
+
+        self.advance_dot();
+        self.dot_count += 1;
+    }
+
+    /// Move to the next dot, wrapping scanlines and frames.
+    fn advance_dot(&mut self) {
+        // The idle dot at the end of the pre-render scanline is skipped on odd frames.
+        let skip_dot = self.region.skips_dot()
+            && self.scanline == self.pre_render_scanline()
+            && self.dot == DOTS_PER_SCANLINE - 2
+            && self.odd_frame
+            && self.is_rendering_enabled();
+
+        self.dot += if skip_dot { 2 } else { 1 };
+
+        if self.dot >= DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+
+            if self.scanline == self.region.scanlines_per_frame() {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    /// Write a single pixel of the frame being drawn.
+    fn set_pixel(&mut self, x: usize, y: usize, palette_index: u8) {
+        self.frame[y * SCREEN_WIDTH + x] = palette_index;
+    }
+
+    /// Mark the current frame as finished and notify any listener.
+    fn complete_frame(&mut self) {
+        self.frame_count += 1;
+        debug!(frame = self.frame_count, "Frame complete");
+
+        if self.frame_callback_paused {
+            return;
+        }
+
+        if let Some(callback) = self.on_frame_complete.as_mut() {
+            callback(&self.frame);
+        }
+    }
+
+    /// Put an address on the PPU address bus, notifying the cartridge of A12 edges.
+    fn set_address_bus(&mut self, addr: u16) {
+        if self.a12_filter.update(addr, self.dot_count) {
+            if let Some(callback) = self.on_a12_rising_edge.as_mut() {
+                callback();
+            }
+        }
+    }
+
+    /// Read from the PPU address space through the address bus, as rendering does.
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.set_address_bus(addr);
+        self.read(addr)
+    }
+
+    /// Read from the PPU address space.
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => self.chr[addr as usize],
+            0x2000..=0x3EFF => self.vram[self.nametable_index(addr)],
+            _ => self.palette[Self::palette_index(addr)],
+        }
+    }
+
+    /// Write to the PPU address space.
+    fn write(&mut self, addr: u16, value: u8) {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.chr_is_ram {
+                    self.chr[addr as usize] = value;
+                }
+            }
+            0x2000..=0x3EFF => {
+                let index = self.nametable_index(addr);
+                self.vram[index] = value;
+            }
+            _ => self.palette[Self::palette_index(addr)] = value & 0x3F,
+        }
+    }
+
+    /// Map a nametable address to an index in vram taking mirroring into account.
+    fn nametable_index(&self, addr: u16) -> usize {
+        // $3000-$3EFF mirrors $2000-$2EFF.
+        let offset = (addr - 0x2000) % 0x1000;
+        let table = offset / 0x400;
+        let within_table = offset % 0x400;
+
+        let physical_table = match self.mirroring {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::FourScreen => table,
+        };
+
+        (physical_table * 0x400 + within_table) as usize
+    }
+
+    /// Map a palette address to an index in palette memory.
+    fn palette_index(addr: u16) -> usize {
+        let index = (addr & 0x1F) as usize;
+
+        // The background colour of the sprite palettes mirror the background palettes.
+        match index {
+            0x10 | 0x14 | 0x18 | 0x1C => index - 0x10,
+            _ => index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn ppu() -> Ppu {
+        Ppu::new(vec![], Mirroring::Horizontal)
+    }
+
+    /// Run the PPU until it reaches the given position.
+    fn run_until(ppu: &mut Ppu, scanline: u16, dot: u16) -> u64 {
+        let mut dots = 0;
+        while ppu.scanline() != scanline || ppu.dot() != dot {
+            ppu.tick();
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn test_frame_rgba() {
+        let mut ppu = ppu();
+        ppu.set_pixel(1, 0, 0x20);
+
+        let rgba = ppu.frame_rgba();
+
+        assert_eq!(rgba.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+        assert_eq!(&rgba[0..4], &[0x80, 0x80, 0x80, 0xFF]);
+        assert_eq!(&rgba[4..8], &[0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_on_frame_complete() {
+        let mut ppu = ppu();
+        let received = Arc::new(AtomicUsize::new(0));
+
+        let counter = received.clone();
+        ppu.on_frame_complete(move |frame| {
+            assert_eq!(frame.len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+
+        ppu.complete_frame();
+        ppu.complete_frame();
+
+        assert_eq!(received.load(Ordering::Relaxed), 2);
+        assert_eq!(ppu.frame_count(), 2);
+    }
+
+    #[test]
+    fn test_frame_length() {
+        let mut ppu = ppu();
+        let full_frame = DOTS_PER_SCANLINE as u64 * SCANLINES_PER_FRAME as u64;
+
+        // Rendering disabled, no dot is skipped.
+        assert_eq!(run_until(&mut ppu, 1, 0), DOTS_PER_SCANLINE as u64);
+        assert_eq!(
+            run_until(&mut ppu, 0, 0),
+            full_frame - DOTS_PER_SCANLINE as u64
+        );
+        assert_eq!(run_until(&mut ppu, 1, 0), DOTS_PER_SCANLINE as u64);
+        assert_eq!(
+            run_until(&mut ppu, 0, 0),
+            full_frame - DOTS_PER_SCANLINE as u64
+        );
+
+        // Rendering enabled, odd frames are one dot shorter.
+        ppu.mask = Ppu::MASK_SHOW_BACKGROUND;
+        ppu.tick();
+        assert_eq!(run_until(&mut ppu, 0, 0), full_frame - 1);
+        ppu.tick();
+        assert_eq!(run_until(&mut ppu, 0, 0), full_frame - 2);
+    }
+
+    #[test]
+    fn test_vblank_nmi() {
+        let mut ppu = ppu();
+        ppu.ctrl = Ppu::CTRL_NMI_ENABLE_MASK;
+
+        let vblank_scanline = ppu.region.vblank_scanline();
+        run_until(&mut ppu, vblank_scanline, 1);
+        assert!(!ppu.poll_nmi());
+
+        ppu.tick();
+        assert!(ppu.poll_nmi());
+        assert!(!ppu.poll_nmi());
+        assert_eq!(ppu.status & Ppu::VBLANK_MASK, Ppu::VBLANK_MASK);
+
+        let pre_render_scanline = ppu.pre_render_scanline();
+        run_until(&mut ppu, pre_render_scanline, 2);
+        assert_eq!(ppu.status & Ppu::VBLANK_MASK, 0);
+    }
+
+    #[test]
+    fn test_regions() {
+        for (region, vblank_scanline) in [(Region::Pal, 241), (Region::Dendy, 291)] {
+            let mut ppu = ppu();
+            ppu.set_region(region);
+            ppu.ctrl = Ppu::CTRL_NMI_ENABLE_MASK;
+            ppu.mask = Ppu::MASK_SHOW_BACKGROUND;
+
+            // 312 scanlines and no dot skipped on odd frames.
+            let full_frame = DOTS_PER_SCANLINE as u64 * 312;
+            run_until(&mut ppu, 0, 0);
+            ppu.tick();
+            assert_eq!(run_until(&mut ppu, 0, 0), full_frame - 1);
+            ppu.tick();
+            assert_eq!(run_until(&mut ppu, 0, 0), full_frame - 1);
+
+            run_until(&mut ppu, vblank_scanline, 2);
+            assert!(ppu.poll_nmi());
+        }
+    }
+}
@@ -0,0 +1,308 @@
+/// CPU facing registers of the PPU.
+///
+/// The eight registers are mapped at $2000-$2007 and mirrored every 8 bytes up to $3FFF.
+/// See http://wiki.nesdev.com/w/index.php/PPU_registers.
+use crate::ppu::Ppu;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+/// PPUCTRL.
+const CTRL: u16 = 0;
+
+/// PPUMASK.
+const MASK: u16 = 1;
+
+/// PPUSTATUS.
+const STATUS: u16 = 2;
+
+/// OAMADDR.
+const OAM_ADDR: u16 = 3;
+
+/// OAMDATA.
+const OAM_DATA: u16 = 4;
+
+/// PPUSCROLL.
+const SCROLL: u16 = 5;
+
+/// PPUADDR.
+const ADDR: u16 = 6;
+
+/// PPUDATA.
+const DATA: u16 = 7;
+
+/// Start of palette memory in the PPU address space.
+const PALETTE_START: u16 = 0x3F00;
+
+/// Frames until a bit on the open bus decays to 0, roughly 600ms.
+const OPEN_BUS_DECAY_FRAMES: u64 = 36;
+
+/// The data bus between the CPU and the PPU.
+///
+/// Reading a write only register, or bits a register doesn't drive, returns whatever was last on
+/// the bus. The bus is capacitive and the bits slowly decay to 0 when not refreshed.
+/// See http://wiki.nesdev.com/w/index.php/Open_bus_behavior#PPU_open_bus.
+#[derive(Default, Deserialize, Serialize)]
+pub struct OpenBus {
+    value: u8,
+
+    /// Frame each bit was last refreshed on.
+    refreshed_at: [u64; 8],
+}
+
+impl OpenBus {
+    /// Drive the masked bits of the bus with the value.
+    fn refresh(&mut self, value: u8, mask: u8, frame: u64) {
+        for (bit, refreshed_at) in self.refreshed_at.iter_mut().enumerate() {
+            if mask & (1 << bit) != 0 {
+                *refreshed_at = frame;
+            }
+        }
+
+        self.value = (self.value & !mask) | (value & mask);
+    }
+
+    /// Current value of the bus with decayed bits cleared.
+    fn value(&self, frame: u64) -> u8 {
+        let mut value = self.value;
+        for (bit, refreshed_at) in self.refreshed_at.iter().enumerate() {
+            if frame - refreshed_at >= OPEN_BUS_DECAY_FRAMES {
+                value &= !(1 << bit);
+            }
+        }
+
+        value
+    }
+}
+
+impl Ppu {
+    const CTRL_NAMETABLE_MASK: u8 = 0b0000_0011;
+    const CTRL_INCREMENT_MASK: u8 = 0b0000_0100;
+
+    /// Read a register as the CPU would, with all the side effects.
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        let (value, driven) = match addr & 0x7 {
+            STATUS => {
+                let value = self.status_value();
+
+                self.status &= !Ppu::VBLANK_MASK;
+                self.write_latch = false;
+
+                value
+            }
+            OAM_DATA => (self.read_oam_data(), 0xFF),
+            DATA => {
+                let (value, driven) = self.read_data();
+
+                // Refill the buffer, palette reads get the nametable "underneath" the palette.
+                let addr = self.v & 0x3FFF;
+                self.read_buffer = if addr >= PALETTE_START {
+                    self.read(addr - 0x1000)
+                } else {
+                    self.read(addr)
+                };
+
+                self.increment_vram_addr();
+                (value, driven)
+            }
+            // Write only registers.
+            _ => (0, 0),
+        };
+
+        self.open_bus.refresh(value, driven, self.frame_count);
+        self.open_bus.value(self.frame_count)
+    }
+
+    /// Read a register without any side effects, used when logging.
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        let (value, driven) = match addr & 0x7 {
+            STATUS => self.status_value(),
+            OAM_DATA => (self.read_oam_data(), 0xFF),
+            DATA => self.read_data(),
+            _ => (0, 0),
+        };
+
+        (value & driven) | (self.open_bus.value(self.frame_count) & !driven)
+    }
+
+    /// Value of PPUSTATUS and the bits it drives, the RC2C05s return their ID in the low bits.
+    fn status_value(&self) -> (u8, u8) {
+        match self.vs_ppu.and_then(|ppu| ppu.status_id()) {
+            Some(id) => (self.status & 0xC0 | id, 0xFF),
+            None => (self.status & 0xE0, 0xE0),
+        }
+    }
+
+    /// Value of OAMDATA, the unimplemented attribute bits always read back as 0.
+    fn read_oam_data(&self) -> u8 {
+        let value = self.oam[self.oam_addr as usize];
+        if self.oam_addr % 4 == 2 {
+            value & 0xE3
+        } else {
+            value
+        }
+    }
+
+    /// Value returned by PPUDATA and the bits it drives.
+    ///
+    /// Reads are delayed through an internal buffer except for palette memory which is returned
+    /// immediately, the upper 2 bits of a palette read come from the open bus.
+    fn read_data(&self) -> (u8, u8) {
+        let addr = self.v & 0x3FFF;
+        if addr >= PALETTE_START {
+            (self.read(addr), 0x3F)
+        } else {
+            (self.read_buffer, 0xFF)
+        }
+    }
+
+    /// Write a register as the CPU would.
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        trace!("${:04X} = ${:02X}", addr, value);
+        self.open_bus.refresh(value, 0xFF, self.frame_count);
+
+        let mut register = addr & 0x7;
+        if register <= MASK && self.vs_ppu.is_some_and(|ppu| ppu.swaps_ctrl_and_mask()) {
+            register ^= 1;
+        }
+
+        match register {
+            CTRL => {
+                let nmi_was_enabled = self.ctrl & Ppu::CTRL_NMI_ENABLE_MASK != 0;
+                self.ctrl = value;
+
+                self.t = (self.t & !0x0C00) | (((value & Ppu::CTRL_NAMETABLE_MASK) as u16) << 10);
+
+                // Enabling NMI during vertical blank immediately triggers a NMI.
+                if !nmi_was_enabled
+                    && value & Ppu::CTRL_NMI_ENABLE_MASK != 0
+                    && self.status & Ppu::VBLANK_MASK != 0
+                {
+                    self.nmi_pending = true;
+                }
+            }
+            MASK => self.mask = value,
+            OAM_ADDR => self.oam_addr = value,
+            OAM_DATA => {
+                self.oam[self.oam_addr as usize] = value;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            SCROLL => {
+                if !self.write_latch {
+                    self.t = (self.t & !0x001F) | (value >> 3) as u16;
+                    self.fine_x = value & 0x07;
+                } else {
+                    self.t = (self.t & !0x73E0)
+                        | (((value & 0x07) as u16) << 12)
+                        | (((value & 0xF8) as u16) << 2);
+                }
+                self.write_latch = !self.write_latch;
+            }
+            ADDR => {
+                if !self.write_latch {
+                    self.t = (self.t & 0x00FF) | (((value & 0x3F) as u16) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
+
+                    // Outside of rendering the address bus follows v.
+                    self.set_address_bus(self.v);
+                }
+                self.write_latch = !self.write_latch;
+            }
+            DATA => {
+                self.write(self.v, value);
+                self.increment_vram_addr();
+            }
+            // Read only register.
+            _ => (),
+        }
+    }
+
+    /// Move to the next VRAM address after an access through PPUDATA.
+    fn increment_vram_addr(&mut self) {
+        let increment = if self.ctrl & Ppu::CTRL_INCREMENT_MASK != 0 {
+            32
+        } else {
+            1
+        };
+
+        self.v = (self.v + increment) & 0x7FFF;
+        self.set_address_bus(self.v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::Mirroring;
+    use crate::vs_system::VsPpu;
+
+    fn set_vram_addr(ppu: &mut Ppu, addr: u16) {
+        ppu.write_register(ADDR, (addr >> 8) as u8);
+        ppu.write_register(ADDR, addr as u8);
+    }
+
+    #[test]
+    fn test_read_buffer() {
+        let mut ppu = Ppu::new(vec![], Mirroring::Horizontal);
+        ppu.write(0x2000, 0x11);
+        ppu.write(0x2001, 0x22);
+        ppu.write(0x2F00, 0x33);
+        ppu.write(0x3F00, 0x0F);
+
+        // The first read returns the stale buffer.
+        set_vram_addr(&mut ppu, 0x2000);
+        ppu.read_register(DATA);
+        assert_eq!(ppu.read_register(DATA), 0x11);
+        assert_eq!(ppu.read_register(DATA), 0x22);
+
+        // Palette reads are immediate but refill the buffer from the nametable underneath.
+        set_vram_addr(&mut ppu, 0x3F00);
+        assert_eq!(ppu.read_register(DATA) & 0x3F, 0x0F);
+        assert_eq!(ppu.read_buffer, 0x33);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut ppu = Ppu::new(vec![], Mirroring::Horizontal);
+
+        // Write only registers return the last value on the bus.
+        ppu.write_register(CTRL, 0x1F);
+        assert_eq!(ppu.read_register(CTRL), 0x1F);
+        assert_eq!(ppu.peek_register(MASK), 0x1F);
+
+        // The status register only drives the upper 3 bits.
+        ppu.status = Ppu::VBLANK_MASK;
+        assert_eq!(ppu.read_register(STATUS), 0x9F);
+
+        // Palette reads only drive the lower 6 bits.
+        ppu.write(0x3F00, 0x01);
+        set_vram_addr(&mut ppu, 0x3F00);
+        ppu.write_register(OAM_ADDR, 0xC0);
+        assert_eq!(ppu.read_register(DATA), 0xC1);
+
+        // Bits decay when not refreshed.
+        ppu.write_register(CTRL, 0xFF);
+        ppu.frame_count += OPEN_BUS_DECAY_FRAMES - 1;
+        assert_eq!(ppu.read_register(MASK), 0xFF);
+        ppu.frame_count += 1;
+        assert_eq!(ppu.read_register(MASK), 0x00);
+    }
+
+    #[test]
+    fn test_rc2c05() {
+        let mut ppu = Ppu::new(vec![], Mirroring::Horizontal);
+        ppu.set_vs_ppu(Some(VsPpu::Rc2c05(2)));
+
+        // PPUCTRL and PPUMASK swap places.
+        ppu.write_register(CTRL, 0x1E);
+        ppu.write_register(MASK, 0x80);
+        assert_eq!((ppu.ctrl, ppu.mask), (0x80, 0x1E));
+
+        // The ID replaces the low bits of PPUSTATUS.
+        ppu.status = Ppu::VBLANK_MASK | Ppu::SPRITE_OVERFLOW_MASK;
+        assert_eq!(ppu.peek_register(STATUS), 0xBD);
+        ppu.status = 0;
+        assert_eq!(ppu.read_register(STATUS), 0x3D);
+    }
+}
@@ -0,0 +1,302 @@
+/// Rendering pipeline of the PPU.
+///
+/// Follows the fetch pattern in http://wiki.nesdev.com/w/index.php/PPU_rendering. Every 8 dots the
+/// PPU fetches the nametable byte, the attribute byte and the two pattern bytes of a tile which are
+/// then fed into shift registers that produce one pixel per dot.
+use crate::ppu::{Ppu, SCREEN_HEIGHT};
+use serde::{Deserialize, Serialize};
+
+/// Background fetch latches and shift registers.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Background {
+    /// Latched nametable byte for the next tile.
+    next_tile: u8,
+
+    /// Latched palette (2 bits) for the next tile.
+    next_attribute: u8,
+
+    /// Latched low pattern byte for the next tile.
+    next_pattern_low: u8,
+
+    /// Latched high pattern byte for the next tile.
+    next_pattern_high: u8,
+
+    /// Pattern shift registers, the upper 8 bits are the tile being drawn.
+    pattern_low: u16,
+    pattern_high: u16,
+
+    /// Attribute shift registers, expanded to one bit per pixel.
+    attribute_low: u16,
+    attribute_high: u16,
+}
+
+impl Background {
+    /// Move the latched tile into the lower half of the shift registers.
+    fn reload(&mut self) {
+        self.pattern_low = (self.pattern_low & 0xFF00) | self.next_pattern_low as u16;
+        self.pattern_high = (self.pattern_high & 0xFF00) | self.next_pattern_high as u16;
+
+        let low = if self.next_attribute & 0b01 != 0 {
+            0xFF
+        } else {
+            0x00
+        };
+        let high = if self.next_attribute & 0b10 != 0 {
+            0xFF
+        } else {
+            0x00
+        };
+        self.attribute_low = (self.attribute_low & 0xFF00) | low;
+        self.attribute_high = (self.attribute_high & 0xFF00) | high;
+    }
+
+    fn shift(&mut self) {
+        self.pattern_low <<= 1;
+        self.pattern_high <<= 1;
+        self.attribute_low <<= 1;
+        self.attribute_high <<= 1;
+    }
+
+    /// Returns the (palette, pixel) at the given fine x offset.
+    fn pixel(&self, fine_x: u8) -> (u8, u8) {
+        let bit = 0x8000 >> fine_x;
+        let pixel =
+            ((self.pattern_high & bit != 0) as u8) << 1 | (self.pattern_low & bit != 0) as u8;
+        let palette =
+            ((self.attribute_high & bit != 0) as u8) << 1 | (self.attribute_low & bit != 0) as u8;
+
+        (palette, pixel)
+    }
+}
+
+impl Ppu {
+    pub(super) const CTRL_BACKGROUND_TABLE_MASK: u8 = 0b0001_0000;
+
+    /// Perform the work of the current dot on a visible or pre-render scanline.
+    pub(super) fn render_dot(&mut self) {
+        let dot = self.dot;
+
+        if self.is_rendering_enabled() {
+            self.fetch_dot();
+        }
+
+        if (1..=256).contains(&dot) && self.scanline < SCREEN_HEIGHT as u16 {
+            self.draw_pixel();
+        }
+    }
+
+    /// Background fetches and scroll updates, only performed while rendering is enabled.
+    fn fetch_dot(&mut self) {
+        let dot = self.dot;
+
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.background.shift();
+
+            match (dot - 1) % 8 {
+                0 => {
+                    self.background.reload();
+                    self.background.next_tile = self.fetch(0x2000 | (self.v & 0x0FFF));
+                }
+                2 => self.fetch_attribute(),
+                4 => self.background.next_pattern_low = self.fetch(self.background_pattern_addr()),
+                6 => {
+                    self.background.next_pattern_high =
+                        self.fetch(self.background_pattern_addr() + 8)
+                }
+                7 => self.increment_coarse_x(),
+                _ => (),
+            }
+        }
+
+        if self.scanline < SCREEN_HEIGHT as u16 || self.scanline == self.pre_render_scanline() {
+            self.sprite_dot();
+        }
+
+        match dot {
+            256 => self.increment_y(),
+            257 => {
+                self.background.reload();
+
+                // Copy horizontal position from t.
+                self.v = (self.v & !0x041F) | (self.t & 0x041F);
+            }
+            280..=304 if self.scanline == self.pre_render_scanline() => {
+                // Copy vertical position from t.
+                self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+            }
+            _ => (),
+        }
+    }
+
+    /// Output the pixel for the current dot.
+    fn draw_pixel(&mut self) {
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+
+        // The leftmost 8 pixels can be hidden separately, games use it to hide scrolling artifacts.
+        let is_left_column = x < 8;
+        let show_background = self.mask & Ppu::MASK_SHOW_BACKGROUND != 0
+            && (!is_left_column || self.mask & Ppu::MASK_SHOW_BACKGROUND_LEFT != 0);
+        let show_sprites = self.mask & Ppu::MASK_SHOW_SPRITES != 0
+            && (!is_left_column || self.mask & Ppu::MASK_SHOW_SPRITES_LEFT != 0);
+
+        let (background_palette, background_pixel) = if show_background {
+            self.background.pixel(self.fine_x)
+        } else {
+            (0, 0)
+        };
+
+        // A hidden background or sprite pixel is transparent which also prevents sprite 0 hit.
+        let sprite = if show_sprites {
+            self.sprite_pixel(x as u8)
+        } else {
+            None
+        };
+
+        // Sprite palettes are the last four palettes.
+        let (palette, pixel) = match sprite {
+            Some((palette, pixel, behind_background, is_sprite_zero)) => {
+                // Sprite 0 hit never happens on the last column.
+                if is_sprite_zero && background_pixel != 0 && x != 255 {
+                    self.status |= Ppu::SPRITE_ZERO_HIT_MASK;
+                }
+
+                if behind_background && background_pixel != 0 {
+                    (background_palette, background_pixel)
+                } else {
+                    (palette + 4, pixel)
+                }
+            }
+            None => (background_palette, background_pixel),
+        };
+
+        // Transparent pixels show the universal background colour.
+        let palette_addr = if pixel == 0 {
+            0x3F00
+        } else {
+            0x3F00 | (palette << 2 | pixel) as u16
+        };
+
+        let colour = self.read(palette_addr);
+        self.set_pixel(x, y, colour);
+    }
+
+    fn fetch_attribute(&mut self) {
+        let v = self.v;
+        let addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+
+        // Each attribute byte covers 4x4 tiles, select the 2x2 quadrant we're in.
+        let shift = ((v >> 4) & 0x04) | (v & 0x02);
+        self.background.next_attribute = (self.fetch(addr) >> shift) & 0x03;
+    }
+
+    /// Address of the low pattern byte of the latched background tile.
+    fn background_pattern_addr(&self) -> u16 {
+        let table = if self.ctrl & Ppu::CTRL_BACKGROUND_TABLE_MASK != 0 {
+            0x1000
+        } else {
+            0
+        };
+        let fine_y = (self.v >> 12) & 0x07;
+
+        table + self.background.next_tile as u16 * 16 + fine_y
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            // Wrap and switch horizontal nametable.
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            // Wrap and switch vertical nametable.
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            // Attribute memory, wrap without switching.
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::Mirroring;
+
+    /// A PPU with an opaque background and sprite 0 at the given x on the second scanline.
+    fn ppu_with_sprite_zero(x: u8, mask: u8) -> Ppu {
+        // Tile 0 is solid colour 1.
+        let mut chr = vec![0; 0x2000];
+        chr[0..8].copy_from_slice(&[0xFF; 8]);
+
+        let mut ppu = Ppu::new(chr, Mirroring::Horizontal);
+        ppu.oam[0..4].copy_from_slice(&[0, 0, 0, x]);
+        ppu.mask = mask;
+
+        ppu
+    }
+
+    fn sprite_zero_hit(ppu: &mut Ppu) -> bool {
+        while ppu.scanline < 2 {
+            ppu.tick();
+        }
+
+        ppu.status & Ppu::SPRITE_ZERO_HIT_MASK != 0
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let all = Ppu::MASK_SHOW_BACKGROUND
+            | Ppu::MASK_SHOW_SPRITES
+            | Ppu::MASK_SHOW_BACKGROUND_LEFT
+            | Ppu::MASK_SHOW_SPRITES_LEFT;
+
+        assert!(sprite_zero_hit(&mut ppu_with_sprite_zero(0, all)));
+        assert!(sprite_zero_hit(&mut ppu_with_sprite_zero(100, all)));
+
+        // Disabling either layer prevents the hit.
+        let mut ppu = ppu_with_sprite_zero(100, all & !Ppu::MASK_SHOW_BACKGROUND);
+        assert!(!sprite_zero_hit(&mut ppu));
+        let mut ppu = ppu_with_sprite_zero(100, all & !Ppu::MASK_SHOW_SPRITES);
+        assert!(!sprite_zero_hit(&mut ppu));
+    }
+
+    #[test]
+    fn test_left_column_masking() {
+        let shown = Ppu::MASK_SHOW_BACKGROUND | Ppu::MASK_SHOW_SPRITES;
+
+        // Sprite entirely within the left column while either is masked.
+        let mut ppu = ppu_with_sprite_zero(0, shown | Ppu::MASK_SHOW_SPRITES_LEFT);
+        assert!(!sprite_zero_hit(&mut ppu));
+        let mut ppu = ppu_with_sprite_zero(0, shown | Ppu::MASK_SHOW_BACKGROUND_LEFT);
+        assert!(!sprite_zero_hit(&mut ppu));
+
+        // Partially outside of the left column still hits.
+        assert!(sprite_zero_hit(&mut ppu_with_sprite_zero(4, shown)));
+
+        // Masked pixels show the backdrop.
+        let mut ppu = ppu_with_sprite_zero(100, shown);
+        ppu.palette[0] = 0x0F;
+        ppu.palette[1] = 0x20;
+        sprite_zero_hit(&mut ppu);
+        assert_eq!(ppu.frame()[256 + 7], 0x0F);
+        assert_eq!(ppu.frame()[256 + 8], 0x20);
+    }
+}
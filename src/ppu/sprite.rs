@@ -0,0 +1,290 @@
+/// Sprite evaluation, fetching and rendering.
+///
+/// See http://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation. Evaluation of the sprites for the
+/// next scanline happens in one go at the end of the visible dots, pattern fetches are performed at
+/// the dots the hardware does them (257-320) so mappers watching the address bus see them.
+use crate::ppu::Ppu;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of sprites on a single scanline.
+const SPRITES_PER_SCANLINE: usize = 8;
+
+/// Tile fetched for unused sprite slots.
+const DUMMY_TILE: u8 = 0xFF;
+
+/// A sprite selected to be drawn on the current scanline.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+pub struct Sprite {
+    /// X position of the left column.
+    x: u8,
+
+    /// Attribute byte (palette, priority and flips).
+    attributes: u8,
+
+    /// Low pattern bits, already flipped so the leftmost pixel is the highest bit.
+    pattern_low: u8,
+
+    /// High pattern bits, already flipped so the leftmost pixel is the highest bit.
+    pattern_high: u8,
+
+    /// Whether this is sprite 0 in OAM.
+    is_sprite_zero: bool,
+}
+
+/// Secondary OAM, the sprites found during evaluation.
+#[derive(Default, Deserialize, Serialize)]
+pub struct Sprites {
+    /// Sprites for the scanline being fetched: (oam index, row within the sprite).
+    next: Vec<(usize, u8)>,
+
+    /// Sprites being drawn on the current scanline.
+    current: Vec<Sprite>,
+
+    /// Sprites fetched so far for the next scanline.
+    fetched: Vec<Sprite>,
+}
+
+/// A sprite pixel: (palette, pixel, behind background, is sprite 0).
+pub type SpritePixel = (u8, u8, bool, bool);
+
+impl Ppu {
+    const CTRL_SPRITE_TABLE_MASK: u8 = 0b0000_1000;
+    const CTRL_SPRITE_SIZE_MASK: u8 = 0b0010_0000;
+
+    pub(super) const ATTRIBUTE_PALETTE_MASK: u8 = 0b0000_0011;
+    pub(super) const ATTRIBUTE_PRIORITY_MASK: u8 = 0b0010_0000;
+    pub(super) const ATTRIBUTE_FLIP_HORIZONTAL_MASK: u8 = 0b0100_0000;
+    pub(super) const ATTRIBUTE_FLIP_VERTICAL_MASK: u8 = 0b1000_0000;
+
+    /// Height of sprites in pixels, either 8 or 16.
+    pub(super) fn sprite_height(&self) -> u16 {
+        if self.ctrl & Ppu::CTRL_SPRITE_SIZE_MASK != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Sprite work for the current dot, only performed while rendering is enabled.
+    pub(super) fn sprite_dot(&mut self) {
+        match self.dot {
+            256 => self.evaluate_sprites(),
+            257..=320 => {
+                // OAMADDR is reset during sprite fetches.
+                self.oam_addr = 0;
+
+                let slot = ((self.dot - 257) / 8) as usize;
+                if (self.dot - 257) % 8 == 7 {
+                    self.fetch_sprite(slot);
+                }
+
+                if self.dot == 320 {
+                    self.sprites.current = core::mem::take(&mut self.sprites.fetched);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Find the sprites that are on the next scanline.
+    fn evaluate_sprites(&mut self) {
+        self.sprites.next.clear();
+
+        // No sprites are drawn on the first scanline.
+        if self.scanline == self.pre_render_scanline() {
+            return;
+        }
+
+        let height = self.sprite_height();
+        for index in 0..64 {
+            // Sprites are delayed by a scanline, Y holds the scanline before the top row.
+            let y = self.oam[index * 4] as u16;
+            if self.scanline < y || self.scanline - y >= height {
+                continue;
+            }
+
+            if self.sprites.next.len() == SPRITES_PER_SCANLINE {
+                self.status |= Ppu::SPRITE_OVERFLOW_MASK;
+                break;
+            }
+
+            self.sprites.next.push((index, (self.scanline - y) as u8));
+        }
+    }
+
+    /// Fetch the pattern of a sprite slot for the next scanline.
+    fn fetch_sprite(&mut self, slot: usize) {
+        let (index, row) = match self.sprites.next.get(slot) {
+            Some(&found) => found,
+            None => {
+                // Unused slots still fetch a tile, mappers count these.
+                let addr = self.sprite_pattern_addr(DUMMY_TILE, 0);
+                self.fetch(addr);
+                self.fetch(addr + 8);
+                return;
+            }
+        };
+
+        let tile = self.oam[index * 4 + 1];
+        let attributes = self.oam[index * 4 + 2];
+        let x = self.oam[index * 4 + 3];
+
+        let row = if attributes & Ppu::ATTRIBUTE_FLIP_VERTICAL_MASK != 0 {
+            self.sprite_height() as u8 - 1 - row
+        } else {
+            row
+        };
+
+        let addr = self.sprite_pattern_addr(tile, row);
+        let mut pattern_low = self.fetch(addr);
+        let mut pattern_high = self.fetch(addr + 8);
+
+        if attributes & Ppu::ATTRIBUTE_FLIP_HORIZONTAL_MASK != 0 {
+            pattern_low = pattern_low.reverse_bits();
+            pattern_high = pattern_high.reverse_bits();
+        }
+
+        self.sprites.fetched.push(Sprite {
+            x,
+            attributes,
+            pattern_low,
+            pattern_high,
+            is_sprite_zero: index == 0,
+        });
+    }
+
+    /// Address of the low pattern byte for the given row of a sprite tile.
+    pub(super) fn sprite_pattern_addr(&self, tile: u8, row: u8) -> u16 {
+        if self.sprite_height() == 16 {
+            // Bit 0 of the tile selects the table, the top half uses the even tile and the bottom
+            // half the odd tile following it.
+            let table = (tile as u16 & 0x01) * 0x1000;
+            let tile = (tile & 0xFE) as u16 + (row >= 8) as u16;
+
+            table + tile * 16 + (row & 0x07) as u16
+        } else {
+            let table = if self.ctrl & Ppu::CTRL_SPRITE_TABLE_MASK != 0 {
+                0x1000
+            } else {
+                0
+            };
+
+            table + tile as u16 * 16 + row as u16
+        }
+    }
+
+    /// The first opaque sprite pixel at the given x, if any.
+    pub(super) fn sprite_pixel(&self, x: u8) -> Option<SpritePixel> {
+        for sprite in &self.sprites.current {
+            let column = x.wrapping_sub(sprite.x);
+            if x < sprite.x || column >= 8 {
+                continue;
+            }
+
+            let bit = 0x80 >> column;
+            let pixel = ((sprite.pattern_high & bit != 0) as u8) << 1
+                | (sprite.pattern_low & bit != 0) as u8;
+
+            if pixel != 0 {
+                return Some((
+                    sprite.attributes & Ppu::ATTRIBUTE_PALETTE_MASK,
+                    pixel,
+                    sprite.attributes & Ppu::ATTRIBUTE_PRIORITY_MASK != 0,
+                    sprite.is_sprite_zero,
+                ));
+            }
+        }
+
+        None
+    }
+
+    /// Copy a page of CPU memory into OAM.
+    pub fn oam_dma(&mut self, page: &[u8]) {
+        for &value in page {
+            self.oam[self.oam_addr as usize] = value;
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::Mirroring;
+
+    #[test]
+    fn test_tall_sprite_pattern_addr() {
+        let mut ppu = Ppu::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.ctrl = Ppu::CTRL_SPRITE_SIZE_MASK;
+
+        // Even tiles use the left table, the sprite table bit is ignored.
+        ppu.ctrl |= Ppu::CTRL_SPRITE_TABLE_MASK;
+        assert_eq!(ppu.sprite_pattern_addr(0x42, 0), 0x0420);
+        assert_eq!(ppu.sprite_pattern_addr(0x42, 7), 0x0427);
+        assert_eq!(ppu.sprite_pattern_addr(0x42, 8), 0x0430);
+        assert_eq!(ppu.sprite_pattern_addr(0x42, 15), 0x0437);
+
+        // Odd tiles use the right table and pair with the tile before them.
+        assert_eq!(ppu.sprite_pattern_addr(0x43, 0), 0x1420);
+        assert_eq!(ppu.sprite_pattern_addr(0x43, 9), 0x1431);
+    }
+
+    /// A PPU in 8x16 mode whose tiles have a different low pattern byte on every row: $1r and
+    /// $2r for tiles $42 and $43 of the left table, $3r and $4r for the right one.
+    fn tall_sprite_ppu() -> Ppu {
+        let mut chr = vec![0; 0x2000];
+        for row in 0..8 {
+            chr[0x0420 + row] = 0x10 + row as u8;
+            chr[0x0430 + row] = 0x20 + row as u8;
+            chr[0x1420 + row] = 0x30 + row as u8;
+            chr[0x1430 + row] = 0x40 + row as u8;
+        }
+
+        let mut ppu = Ppu::new(chr, Mirroring::Horizontal);
+        ppu.ctrl = Ppu::CTRL_SPRITE_SIZE_MASK;
+        ppu
+    }
+
+    /// The low pattern byte fetched for sprite 0 while evaluating `scanline`.
+    fn fetch_pattern_low(ppu: &mut Ppu, scanline: u16) -> u8 {
+        ppu.sprites.fetched.clear();
+        ppu.scanline = scanline;
+        ppu.evaluate_sprites();
+        ppu.fetch_sprite(0);
+        ppu.sprites.fetched[0].pattern_low
+    }
+
+    #[test]
+    fn test_tall_sprite_halves() {
+        let mut ppu = tall_sprite_ppu();
+
+        // The sprite is at Y 9, rows 0-7 are the top tile and rows 8-15 the bottom one.
+        ppu.oam[0..4].copy_from_slice(&[9, 0x42, 0, 0]);
+        assert_eq!(fetch_pattern_low(&mut ppu, 9), 0x10);
+        assert_eq!(fetch_pattern_low(&mut ppu, 16), 0x17);
+        assert_eq!(fetch_pattern_low(&mut ppu, 17), 0x20);
+        assert_eq!(fetch_pattern_low(&mut ppu, 24), 0x27);
+
+        // Bit 0 selects the right table even though PPUCTRL selects the left one, the bottom half
+        // is still the next tile.
+        ppu.oam[1] = 0x43;
+        assert_eq!(fetch_pattern_low(&mut ppu, 9), 0x30);
+        assert_eq!(fetch_pattern_low(&mut ppu, 17), 0x40);
+        assert_eq!(fetch_pattern_low(&mut ppu, 24), 0x47);
+    }
+
+    #[test]
+    fn test_tall_sprite_vertical_flip() {
+        let mut ppu = tall_sprite_ppu();
+        ppu.oam[0..4].copy_from_slice(&[9, 0x42, Ppu::ATTRIBUTE_FLIP_VERTICAL_MASK, 0]);
+
+        // Flipped, the first row is the last row of the bottom tile and the last row is the first
+        // row of the top tile.
+        assert_eq!(fetch_pattern_low(&mut ppu, 9), 0x27);
+        assert_eq!(fetch_pattern_low(&mut ppu, 10), 0x26);
+        assert_eq!(fetch_pattern_low(&mut ppu, 16), 0x20);
+        assert_eq!(fetch_pattern_low(&mut ppu, 17), 0x17);
+        assert_eq!(fetch_pattern_low(&mut ppu, 24), 0x10);
+    }
+}
@@ -0,0 +1,418 @@
+/// Views of the PPU's memory for diagnosing rendering bugs, independent of what's on screen.
+///
+/// Images are palette indices like the frame, drawn with the current palettes.
+use crate::ppu::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Size of the four nametables laid out in a 2x2 grid.
+pub const NAMETABLES_WIDTH: usize = 512;
+pub const NAMETABLES_HEIGHT: usize = 480;
+
+/// Size of a pattern table, 16x16 tiles.
+pub const PATTERN_TABLE_SIZE: usize = 128;
+
+/// Number of palettes, the first four for the background and the last four for sprites.
+pub const PALETTES: u8 = 8;
+
+/// A sprite in OAM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OamEntry {
+    /// Position in OAM, sprite 0 is used for sprite 0 hit.
+    pub index: usize,
+
+    pub x: u8,
+
+    /// One less than the first scanline the sprite is on.
+    pub y: u8,
+
+    pub tile: u8,
+
+    /// Palette, priority and flips.
+    pub attributes: u8,
+}
+
+impl OamEntry {
+    /// One of the four sprite palettes.
+    pub fn palette(&self) -> u8 {
+        self.attributes & Ppu::ATTRIBUTE_PALETTE_MASK
+    }
+
+    pub fn behind_background(&self) -> bool {
+        self.attributes & Ppu::ATTRIBUTE_PRIORITY_MASK != 0
+    }
+
+    pub fn flip_horizontal(&self) -> bool {
+        self.attributes & Ppu::ATTRIBUTE_FLIP_HORIZONTAL_MASK != 0
+    }
+
+    pub fn flip_vertical(&self) -> bool {
+        self.attributes & Ppu::ATTRIBUTE_FLIP_VERTICAL_MASK != 0
+    }
+}
+
+impl fmt::Display for OamEntry {
+    /// e.g. "#01  X:128 Y: 64  Tile:$A2  Palette:1  back  H-".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{:02}  X:{:3} Y:{:3}  Tile:${:02X}  Palette:{}  {} {}{}",
+            self.index,
+            self.x,
+            self.y,
+            self.tile,
+            self.palette(),
+            if self.behind_background() {
+                "back "
+            } else {
+                "front"
+            },
+            if self.flip_horizontal() { 'H' } else { '-' },
+            if self.flip_vertical() { 'V' } else { '-' },
+        )
+    }
+}
+
+/// CHR data as a sheet of tiles 16 wide, `PATTERN_TABLE_SIZE` pixels, so each 4 KiB pattern table
+/// is a square below the previous one. Pixels are drawn with `colours`, one for each of the 4 values
+/// of a tile's pixels.
+pub fn render_chr(chr: &[u8], colours: [u8; 4]) -> Vec<u8> {
+    let tiles = chr.len() / 16;
+    let rows = tiles.div_ceil(16);
+    let mut image = vec![colours[0]; PATTERN_TABLE_SIZE * rows * 8];
+
+    for (tile, pattern) in chr.chunks_exact(16).enumerate() {
+        let x = (tile % 16) * 8;
+        let y = (tile / 16) * 8;
+
+        for row in 0..8 {
+            let (low, high) = (pattern[row], pattern[row + 8]);
+            for column in 0..8 {
+                let bit = 7 - column;
+                let pixel = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+                image[(y + row) * PATTERN_TABLE_SIZE + x + column] = colours[pixel as usize];
+            }
+        }
+    }
+
+    image
+}
+
+impl Ppu {
+    /// The four nametables in a 2x2 grid, with the background pattern table and attributes.
+    ///
+    /// Mirrored nametables show the same picture twice.
+    pub fn render_nametables(&self) -> Vec<u8> {
+        let mut image = vec![0; NAMETABLES_WIDTH * NAMETABLES_HEIGHT];
+        let pattern_table = if self.ctrl & Ppu::CTRL_BACKGROUND_TABLE_MASK != 0 {
+            0x1000
+        } else {
+            0
+        };
+
+        for nametable in 0..4 {
+            let base = 0x2000 + nametable as u16 * 0x400;
+            let left = (nametable % 2) * 256;
+            let top = (nametable / 2) * 240;
+
+            for row in 0..30 {
+                for column in 0..32 {
+                    let tile = self.read(base + row * 32 + column);
+
+                    // Each attribute byte covers 4x4 tiles, 2 bits for each 2x2 quadrant.
+                    let attribute = self.read(base + 0x3C0 + (row / 4) * 8 + column / 4);
+                    let shift = (row & 0x02) << 1 | (column & 0x02);
+                    let palette = (attribute >> shift) & 0x03;
+
+                    self.draw_tile(
+                        &mut image,
+                        NAMETABLES_WIDTH,
+                        left + column as usize * 8,
+                        top + row as usize * 8,
+                        pattern_table + tile as u16 * 16,
+                        palette,
+                    );
+                }
+            }
+        }
+
+        image
+    }
+
+    /// Where the top left of the screen is in the nametables image, from the scroll the next frame
+    /// starts with.
+    pub fn scroll(&self) -> (usize, usize) {
+        let t = self.t as usize;
+        let x = (t >> 10 & 1) * 256 + (t & 0x1F) * 8 + self.fine_x as usize;
+        let y = (t >> 11 & 1) * 240 + (t >> 5 & 0x1F) * 8 + (t >> 12 & 0x07);
+        (x, y)
+    }
+
+    /// Outline the screen on the nametables image in `colour`, wrapping around its edges like the
+    /// scroll does.
+    pub fn draw_viewport(&self, image: &mut [u8], colour: u8) {
+        let (left, top) = self.scroll();
+        let mut plot = |x: usize, y: usize| {
+            // Scrolling into the attributes at rows 30 and 31 is ignored, the outline stays on
+            // the picture.
+            let x = (left + x) % NAMETABLES_WIDTH;
+            let y = (top + y) % NAMETABLES_HEIGHT;
+            image[y * NAMETABLES_WIDTH + x] = colour;
+        };
+
+        for x in 0..SCREEN_WIDTH {
+            plot(x, 0);
+            plot(x, SCREEN_HEIGHT - 1);
+        }
+        for y in 0..SCREEN_HEIGHT {
+            plot(0, y);
+            plot(SCREEN_WIDTH - 1, y);
+        }
+    }
+
+    /// One of the two pattern tables as 16x16 tiles, drawn with one of the eight palettes.
+    pub fn render_pattern_table(&self, table: u16, palette: u8) -> Vec<u8> {
+        let mut image = vec![0; PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE];
+
+        for tile in 0..256 {
+            self.draw_tile(
+                &mut image,
+                PATTERN_TABLE_SIZE,
+                (tile % 16) * 8,
+                (tile / 16) * 8,
+                table * 0x1000 + tile as u16 * 16,
+                palette,
+            );
+        }
+
+        image
+    }
+
+    /// Palette memory, the 4 colours of each of the 8 palettes. The backdrop entries of the sprite
+    /// palettes read back the background palettes' like they do for the CPU.
+    pub fn palette_ram(&self) -> [u8; 32] {
+        let mut palettes = [0; 32];
+        for (i, colour) in palettes.iter_mut().enumerate() {
+            *colour = self.read(0x3F00 + i as u16);
+        }
+
+        palettes
+    }
+
+    /// The pattern tables as the PPU sees them, CHR ROM or the current contents of CHR RAM.
+    pub fn chr(&self) -> &[u8] {
+        &self.chr
+    }
+
+    /// All 64 sprites in OAM, in priority order.
+    pub fn oam_entries(&self) -> Vec<OamEntry> {
+        self.oam
+            .chunks(4)
+            .enumerate()
+            .map(|(index, bytes)| OamEntry {
+                index,
+                y: bytes[0],
+                tile: bytes[1],
+                attributes: bytes[2],
+                x: bytes[3],
+            })
+            .collect()
+    }
+
+    /// The sprites in OAM drawn on a transparent screen, as if each was in front of the background
+    /// and none were dropped for being past the eighth on a scanline.
+    ///
+    /// Sprites earlier in OAM are drawn over later ones like the PPU does.
+    pub fn render_sprites(&self) -> Vec<Option<u8>> {
+        let mut image = vec![None; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let height = self.sprite_height() as usize;
+
+        for sprite in self.oam_entries().iter().rev() {
+            for row in 0..height {
+                // Sprites are drawn a scanline below their Y.
+                let y = sprite.y as usize + 1 + row;
+                if y >= SCREEN_HEIGHT {
+                    break;
+                }
+
+                let pattern_row = if sprite.flip_vertical() {
+                    height - 1 - row
+                } else {
+                    row
+                };
+                let addr = self.sprite_pattern_addr(sprite.tile, pattern_row as u8);
+                let (low, high) = (self.read(addr), self.read(addr + 8));
+
+                for column in 0..8 {
+                    let x = sprite.x as usize + column;
+                    if x >= SCREEN_WIDTH {
+                        break;
+                    }
+
+                    let bit = if sprite.flip_horizontal() {
+                        column
+                    } else {
+                        7 - column
+                    };
+                    let pixel = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+                    if pixel != 0 {
+                        let palette_addr = 0x3F10 | (sprite.palette() << 2 | pixel) as u16;
+                        image[y * SCREEN_WIDTH + x] = Some(self.read(palette_addr));
+                    }
+                }
+            }
+        }
+
+        image
+    }
+
+    /// Draw the 8x8 tile whose pattern starts at `pattern_addr` with its top left at (x, y).
+    fn draw_tile(
+        &self,
+        image: &mut [u8],
+        width: usize,
+        x: usize,
+        y: usize,
+        pattern_addr: u16,
+        palette: u8,
+    ) {
+        for row in 0..8 {
+            let low = self.read(pattern_addr + row);
+            let high = self.read(pattern_addr + row + 8);
+
+            for column in 0..8 {
+                let bit = 7 - column;
+                let pixel = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+
+                // Transparent pixels show the universal background colour.
+                let palette_addr = if pixel == 0 {
+                    0x3F00
+                } else {
+                    0x3F00 | (palette << 2 | pixel) as u16
+                };
+
+                image[(y + row as usize) * width + x + column as usize] = self.read(palette_addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::Mirroring;
+
+    /// A PPU whose tile 1 is a solid block of colour 3.
+    fn ppu() -> Ppu {
+        let mut chr = vec![0; 0x2000];
+        for byte in &mut chr[16..32] {
+            *byte = 0xFF;
+        }
+
+        let mut ppu = Ppu::new(chr, Mirroring::Vertical);
+        ppu.write(0x3F00, 0x0F);
+        ppu.write(0x3F03, 0x16);
+        ppu.write(0x3F07, 0x2A);
+        ppu
+    }
+
+    #[test]
+    fn test_nametables() {
+        let mut ppu = ppu();
+
+        // Tile 1 at the second tile of the second row, with palette 1 for that quadrant.
+        ppu.write(0x2021, 0x01);
+        ppu.write(0x23C0, 0x01);
+
+        let image = ppu.render_nametables();
+        assert_eq!(image[8 * NAMETABLES_WIDTH + 8], 0x2A);
+        assert_eq!(image[8 * NAMETABLES_WIDTH + 7], 0x0F);
+
+        // Vertical mirroring shows the same nametable at $2800.
+        assert_eq!(image[(240 + 8) * NAMETABLES_WIDTH + 8], 0x2A);
+        assert_eq!(image[8 * NAMETABLES_WIDTH + 256 + 8], 0x0F);
+    }
+
+    #[test]
+    fn test_viewport() {
+        let mut ppu = ppu();
+        let mut image = ppu.render_nametables();
+        ppu.draw_viewport(&mut image, 0x30);
+        assert_eq!(image[0], 0x30);
+        assert_eq!(image[239 * NAMETABLES_WIDTH + 255], 0x30);
+        assert_eq!(image[NAMETABLES_WIDTH + 1], 0x0F);
+
+        // Scrolled 8 pixels right in the right nametable, 16 down: wraps back to the left one.
+        ppu.t = 0x0400 | 0x02 << 5 | 0x01;
+        assert_eq!(ppu.scroll(), (264, 16));
+
+        let mut image = ppu.render_nametables();
+        ppu.draw_viewport(&mut image, 0x30);
+        assert_eq!(image[16 * NAMETABLES_WIDTH + 264], 0x30);
+        assert_eq!(image[16 * NAMETABLES_WIDTH + 7], 0x30);
+        assert_eq!(image[16 * NAMETABLES_WIDTH + 8], 0x0F);
+    }
+
+    #[test]
+    fn test_pattern_table() {
+        let ppu = ppu();
+
+        let image = ppu.render_pattern_table(0, 0);
+        assert_eq!(image[0], 0x0F);
+        assert_eq!(image[7 * PATTERN_TABLE_SIZE + 15], 0x16);
+        assert_eq!(image[7 * PATTERN_TABLE_SIZE + 16], 0x0F);
+    }
+
+    #[test]
+    fn test_chr() {
+        let ppu = ppu();
+        let colours = [0x0F, 0x00, 0x10, 0x30];
+
+        let image = render_chr(ppu.chr(), colours);
+        assert_eq!(image.len(), PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE * 2);
+        assert_eq!(image[0], 0x0F);
+        assert_eq!(image[7 * PATTERN_TABLE_SIZE + 15], 0x30);
+
+        // A partial tile is left out, a partial row of tiles is padded.
+        let image = render_chr(&ppu.chr()[..16 * 17 + 8], colours);
+        assert_eq!(image.len(), PATTERN_TABLE_SIZE * 16);
+        assert_eq!(image[8 * PATTERN_TABLE_SIZE + 8], 0x0F);
+    }
+
+    #[test]
+    fn test_sprites() {
+        let mut ppu = ppu();
+        ppu.write(0x3F13, 0x16);
+        ppu.write(0x3F17, 0x2A);
+
+        // Sprite 1 at (16, 9) is drawn over sprite 2 overlapping it, sprite 3 is off the edge.
+        ppu.oam.fill(0xFF);
+        ppu.oam[4..8].copy_from_slice(&[8, 0x01, 0x00, 16]);
+        ppu.oam[8..12].copy_from_slice(&[12, 0x01, 0x01, 20]);
+        ppu.oam[12..16].copy_from_slice(&[0, 0x01, 0x01, 252]);
+
+        let image = ppu.render_sprites();
+        assert_eq!(image[9 * SCREEN_WIDTH + 16], Some(0x16));
+        assert_eq!(image[14 * SCREEN_WIDTH + 20], Some(0x16));
+        assert_eq!(image[16 * SCREEN_WIDTH + 27], Some(0x2A));
+        assert_eq!(image[8 * SCREEN_WIDTH + 16], None);
+        assert_eq!(image[SCREEN_WIDTH + 255], Some(0x2A));
+        assert_eq!(image[2 * SCREEN_WIDTH], None);
+    }
+
+    #[test]
+    fn test_palettes_and_oam() {
+        let mut ppu = ppu();
+        let palettes = ppu.palette_ram();
+        assert_eq!(&palettes[..4], &[0x0F, 0, 0, 0x16]);
+        assert_eq!(palettes[0x10], 0x0F);
+
+        ppu.oam[4..8].copy_from_slice(&[0x40, 0xA2, 0b0110_0001, 0x80]);
+        let sprite = ppu.oam_entries()[1];
+        assert_eq!(
+            sprite.to_string(),
+            "#01  X:128 Y: 64  Tile:$A2  Palette:1  back  H-"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+/// Television standard the console was built for. Besides the master clock and how it's divided
+/// between the CPU and the PPU, it sets the number of scanlines in a frame, where vertical blank
+/// starts and the period tables of the APU. See https://www.nesdev.org/wiki/Cycle_reference_chart.
+///
+/// Dendy is the common Famiclone in Russia: a PAL frame and master clock, but with the CPU divided
+/// so there are three dots per cycle like NTSC, vertical blank 50 scanlines later so NTSC games
+/// have time for their NMI, and the NTSC APU.
+use crate::clock::Timing;
+use crate::ppu::DOTS_PER_SCANLINE;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    /// How the master clock is divided.
+    pub fn timing(self) -> Timing {
+        match self {
+            Region::Ntsc => Timing::NTSC,
+            Region::Pal => Timing::PAL,
+            Region::Dendy => Timing::DENDY,
+        }
+    }
+
+    /// Scanlines in a frame, including vertical blank and the pre-render scanline.
+    pub fn scanlines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// Scanline where vertical blank starts.
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// Whether odd frames skip a dot when rendering is enabled, only NTSC PPUs do.
+    pub fn skips_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    /// The APU uses the PAL period tables and frame counter sequence.
+    pub fn pal_apu(self) -> bool {
+        self == Region::Pal
+    }
+
+    /// Frames per second, with rendering enabled.
+    pub fn frame_rate(self) -> f64 {
+        let timing = self.timing();
+        let mut dots = DOTS_PER_SCANLINE as f64 * self.scanlines_per_frame() as f64;
+        if self.skips_dot() {
+            dots -= 0.5;
+        }
+
+        timing.master_hz as f64 / timing.ppu_divider as f64 / dots
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region \"{}\".", name)),
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Region::Ntsc => "NTSC",
+            Region::Pal => "PAL",
+            Region::Dendy => "Dendy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_rate() {
+        assert_eq!((Region::Ntsc.frame_rate() * 1000.0).round(), 60099.0);
+        assert_eq!((Region::Pal.frame_rate() * 1000.0).round(), 50007.0);
+        assert_eq!(Region::Dendy.frame_rate(), Region::Pal.frame_rate());
+        assert_eq!((Region::Pal.timing().cpu_hz() / 1000.0).round(), 1663.0);
+        assert_eq!((Region::Dendy.timing().cpu_hz() / 1000.0).round(), 1773.0);
+    }
+}
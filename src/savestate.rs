@@ -0,0 +1,314 @@
+/// Save states, a snapshot of the whole console that can be restored exactly.
+///
+/// The snapshot can be taken in between any two instructions, so it includes the PPU's progress
+/// through the frame and any interrupts waiting to be serviced. The frontend's callbacks and
+/// settings aren't part of the state and are kept when loading, and neither is the ROM: states
+/// only load into the ROM they were taken with.
+use crate::cpu::Cpu;
+#[cfg(feature = "scripting")]
+use crate::script;
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::info;
+
+/// Start of every save state.
+const MAGIC: &[u8; 8] = b"NESSAVE\x1A";
+
+/// Bumped whenever the serialized state changes, older states can't be loaded.
+const VERSION: u32 = 12;
+
+/// The magic, the version and the ROM's CRC32.
+const HEADER_SIZE: usize = MAGIC.len() + 4 + 4;
+
+/// Number of numbered save slots per game.
+pub const SLOTS: usize = 10;
+
+impl Cpu {
+    /// Snapshot the console: the magic, the version, the ROM's CRC32, the serialized state and the
+    /// memory below the ROM.
+    pub fn save_state(&self) -> Result<Vec<u8>> {
+        let mut state = Vec::new();
+        self.save_state_into(&mut state)?;
+
+        Ok(state)
+    }
+
+    /// Same as `save_state()`, reusing the allocation of `state` for frequent snapshots.
+    pub fn save_state_into(&self, state: &mut Vec<u8>) -> Result<()> {
+        state.clear();
+        state.extend_from_slice(MAGIC);
+        state.extend_from_slice(&VERSION.to_le_bytes());
+        state.extend_from_slice(&self.rom_checksum.to_le_bytes());
+        bincode::serialize_into(&mut *state, self)?;
+        state.extend_from_slice(self.saved_memory());
+
+        Ok(())
+    }
+
+    /// Restore a snapshot from `save_state()`.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        if state.len() < HEADER_SIZE || &state[..MAGIC.len()] != MAGIC {
+            return Err(anyhow!("Not a save state."));
+        }
+
+        let mut version = [0; 4];
+        version.copy_from_slice(&state[MAGIC.len()..MAGIC.len() + 4]);
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(anyhow!(
+                "Unsupported save state version {}, expected {}.",
+                version,
+                VERSION
+            ));
+        }
+
+        let mut rom_checksum = [0; 4];
+        rom_checksum.copy_from_slice(&state[MAGIC.len() + 4..HEADER_SIZE]);
+        let rom_checksum = u32::from_le_bytes(rom_checksum);
+        if rom_checksum != self.rom_checksum {
+            return Err(anyhow!(
+                "Save state is for another ROM, its CRC32 is {:08X} rather than {:08X}.",
+                rom_checksum,
+                self.rom_checksum
+            ));
+        }
+
+        let mut reader = &state[HEADER_SIZE..];
+        let mut state: Cpu = bincode::deserialize_from(&mut reader)?;
+        // Anything after the memory is padding, e.g. libretro's.
+        let memory = self.saved_memory().len();
+        if reader.len() < memory {
+            return Err(anyhow!(
+                "Save state has {} bytes of memory, expected {}.",
+                reader.len(),
+                memory
+            ));
+        }
+        std::mem::swap(self, &mut state);
+
+        // `state` is now the replaced console, keep what the frontend set up. Memory stays where
+        // it was for frontends holding on to it, e.g. libretro's, with the ROM left as it is.
+        std::mem::swap(&mut self.memory, &mut state.memory);
+        self.memory[..memory].copy_from_slice(&reader[..memory]);
+        self.ppu.take_callbacks(&mut state.ppu);
+        self.apu.take_callbacks(&mut state.apu);
+        self.zapper = state.zapper.take();
+        self.cheats = std::mem::take(&mut state.cheats);
+        self.pokes = std::mem::take(&mut state.pokes);
+        self.breakpoints = std::mem::take(&mut state.breakpoints);
+        self.call_stack = std::mem::take(&mut state.call_stack);
+        self.symbols = std::mem::take(&mut state.symbols);
+        self.tracer = std::mem::take(&mut state.tracer);
+        self.profiler = std::mem::take(&mut state.profiler);
+        self.events = std::mem::take(&mut state.events);
+        self.watches = std::mem::take(&mut state.watches);
+        self.observers = std::mem::take(&mut state.observers);
+        self.scheduling = state.scheduling;
+        self.overclock_scanlines = state.overclock_scanlines;
+        self.unknown_opcode = state.unknown_opcode;
+        self.unofficial_opcode = state.unofficial_opcode;
+        self.ram_pattern = state.ram_pattern;
+        self.detect_traps = state.detect_traps;
+        self.cycle_limit = state.cycle_limit;
+        self.flat_memory = state.flat_memory;
+        self.rom_checksum = state.rom_checksum;
+        #[cfg(feature = "scripting")]
+        {
+            self.script = state.script.take();
+        }
+
+        Ok(())
+    }
+}
+
+/// Where to load a save state from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SaveStateSource {
+    /// One of the game's numbered slots.
+    Slot(usize),
+
+    /// Any save state file.
+    File(PathBuf),
+}
+
+impl FromStr for SaveStateSource {
+    type Err = String;
+
+    /// Either "slotN" or the path to a file.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.strip_prefix("slot") {
+            Some(slot) => match slot.parse::<usize>() {
+                Ok(slot) if slot < SLOTS => Ok(SaveStateSource::Slot(slot)),
+                _ => Err(format!(
+                    "Invalid slot \"{}\", slots go from 0 to {}.",
+                    slot,
+                    SLOTS - 1
+                )),
+            },
+            None => Ok(SaveStateSource::File(value.into())),
+        }
+    }
+}
+
+/// The numbered save states of a game, stored next to the ROM as "<rom>.ss0" to "<rom>.ss9".
+pub struct SaveSlots {
+    rom_path: PathBuf,
+}
+
+impl SaveSlots {
+    pub fn new(rom_path: &str) -> Self {
+        SaveSlots {
+            rom_path: rom_path.into(),
+        }
+    }
+
+    /// Keep the slots in `dir` instead of next to the ROM.
+    pub fn in_directory(self, dir: &Path) -> Self {
+        let file_name = self.rom_path.file_name().unwrap_or_default();
+        SaveSlots {
+            rom_path: dir.join(file_name),
+        }
+    }
+
+    pub fn path(&self, slot: usize) -> PathBuf {
+        self.rom_path.with_extension(format!("ss{}", slot))
+    }
+
+    pub fn save(&self, cpu: &mut Cpu, slot: usize) -> Result<()> {
+        let path = self.path(slot);
+        std::fs::write(&path, cpu.save_state()?)
+            .with_context(|| format!("Failed to write \"{}\"", path.display()))?;
+
+        info!("Saved state to slot {}", slot);
+
+        #[cfg(feature = "scripting")]
+        cpu.script_event(script::Event::SaveState(slot));
+        Ok(())
+    }
+
+    pub fn load(&self, cpu: &mut Cpu, source: &SaveStateSource) -> Result<()> {
+        let path = match source {
+            SaveStateSource::Slot(slot) => self.path(*slot),
+            SaveStateSource::File(path) => path.clone(),
+        };
+
+        let state = std::fs::read(&path)
+            .with_context(|| format!("Failed to read \"{}\"", path.display()))?;
+        cpu.load_state(&state)
+            .with_context(|| format!("Failed to load \"{}\"", path.display()))?;
+
+        info!("Loaded state from \"{}\"", path.display());
+
+        #[cfg(feature = "scripting")]
+        cpu.script_event(script::Event::LoadState(match source {
+            SaveStateSource::Slot(slot) => Some(*slot),
+            SaveStateSource::File(_) => None,
+        }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::Entry;
+    use crate::ines;
+    use crate::opcode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Run instructions, returning a trace of the state after each.
+    fn run(cpu: &mut Cpu, instructions: usize) -> Vec<(u16, u64, u16, u16, u8)> {
+        (0..instructions)
+            .map(|_| {
+                let operation = opcode::next(cpu).unwrap();
+                cpu.step(operation);
+                (
+                    cpu.program_counter,
+                    cpu.cycles,
+                    cpu.ppu.scanline(),
+                    cpu.ppu.dot(),
+                    cpu.memory[0x0400],
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        // Copy PPUSTATUS to RAM in a loop, with rendering on.
+        cpu.memory[0x0200..0x0209]
+            .copy_from_slice(&[0xAD, 0x02, 0x20, 0x8D, 0x00, 0x04, 0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+        cpu.write(0x2001, 0x1E);
+
+        let frames = Arc::new(AtomicUsize::new(0));
+        cpu.ppu.on_frame_complete({
+            let frames = frames.clone();
+            move |_| {
+                frames.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+
+        // Part way through a frame.
+        run(&mut cpu, 1234);
+        let state = cpu.save_state()?;
+
+        let expected = run(&mut cpu, 20000);
+        let expected_frame = cpu.ppu.frame().to_vec();
+        let expected_frames = frames.load(Ordering::Relaxed);
+        assert!(expected_frames > 0);
+
+        // The call stack is kept like the breakpoints, it isn't saved.
+        cpu.call_stack.interrupt(Entry::Nmi, 0xC000, 0x0200);
+        cpu.load_state(&state)?;
+        assert_eq!(cpu.call_stack.depth(), 1);
+        assert_eq!(run(&mut cpu, 20000), expected);
+        assert_eq!(cpu.ppu.frame(), &expected_frame[..]);
+
+        // The callback is still registered.
+        assert_eq!(frames.load(Ordering::Relaxed), expected_frames * 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_slots() {
+        let slots = SaveSlots::new("roms/game.nes");
+        assert_eq!(slots.path(3), PathBuf::from("roms/game.ss3"));
+        let slots = slots.in_directory(Path::new("states"));
+        assert_eq!(slots.path(3), PathBuf::from("states/game.ss3"));
+
+        assert_eq!("slot3".parse(), Ok(SaveStateSource::Slot(3)));
+        assert_eq!(
+            "game.ss3".parse(),
+            Ok(SaveStateSource::File("game.ss3".into()))
+        );
+        assert!("slot10".parse::<SaveStateSource>().is_err());
+    }
+
+    #[test]
+    fn test_invalid_state() -> Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        let mut state = cpu.save_state()?;
+        assert!(cpu.load_state(&state[1..]).is_err());
+
+        state[MAGIC.len()] += 1;
+        assert!(cpu.load_state(&state).is_err());
+
+        // A state from another game.
+        let mut nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        nes_file.prg_rom[0] ^= 0xFF;
+        let state = Cpu::new(nes_file).save_state()?;
+        let err = cpu.load_state(&state).unwrap_err();
+        assert!(err.to_string().contains("another ROM"));
+
+        Ok(())
+    }
+}
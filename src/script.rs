@@ -0,0 +1,407 @@
+/// Scripts in Rhai (https://rhai.rs) that react to the emulation and can drive it, e.g. to show a
+/// game's hidden state or automate inputs.
+///
+/// A script can define any of these callbacks:
+///   on_frame_start(), on_frame_end()
+///   on_read(address, value), on_write(address, value), for the watched addresses
+///   on_save_state(slot), on_load_state(slot), the slot is -1 for other files
+/// and call into the emulator with:
+///   read(address), write(address, value)
+///   press(player, button), release(player, button), e.g. press(1, "start")
+///   text(x, y, string), drawn over the next frame
+///   frame_count(), watch_read(address), watch_write(address)
+///
+/// In deterministic mode Rhai's `timestamp()` fails, scripts can't depend on the host's clock.
+/// Callbacks run in between instructions, accesses are reported after the instruction making them.
+/// Reads see RAM and ROM as they were when the callback started, the registers aren't readable
+/// since reading them has side effects. Writes and button presses are made once it returns.
+/// Callbacks can keep state in between calls in `this`, a map shared by all of them.
+use crate::controller::ControllerState;
+use crate::cpu::Cpu;
+use anyhow::{anyhow, Context, Result};
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::error;
+
+/// Something that happened to the console that scripts can react to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    FrameStart,
+    FrameEnd,
+    SaveState(usize),
+
+    /// Loaded from a slot, or from another file.
+    LoadState(Option<usize>),
+}
+
+/// Text drawn over the picture, at a position on the screen.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Text {
+    pub x: i32,
+    pub y: i32,
+    pub text: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Access {
+    Read,
+    Write,
+}
+
+/// What the script's functions see of the console, and what they asked for during a callback.
+struct Bridge {
+    memory: Vec<u8>,
+    frame_count: u64,
+    writes: Vec<(u16, u8)>,
+
+    /// Player, button and whether it's pressed.
+    buttons: Vec<(usize, u8, bool)>,
+
+    texts: Vec<Text>,
+    watches: Vec<(Access, u16)>,
+}
+
+pub struct ScriptHost {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+
+    /// Bound to `this` in the callbacks.
+    this: Dynamic,
+
+    bridge: Arc<Mutex<Bridge>>,
+
+    /// Names of the callbacks the script defines.
+    callbacks: HashSet<String>,
+
+    watched_reads: Vec<bool>,
+    watched_writes: Vec<bool>,
+
+    /// Watched accesses of the current instruction.
+    accesses: Vec<(Access, u16, u8)>,
+
+    /// Text drawn during the last frame, shown over the next one.
+    overlay: Arc<Mutex<Vec<Text>>>,
+}
+
+impl ScriptHost {
+    pub fn load(path: &str, deterministic: bool) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read \"{}\"", path))?;
+
+        ScriptHost::new(&source, deterministic)
+            .with_context(|| format!("Failed to load \"{}\"", path))
+    }
+
+    /// Compile the script and run its top level, e.g. to watch addresses.
+    pub fn new(source: &str, deterministic: bool) -> Result<Self> {
+        let bridge = Arc::new(Mutex::new(Bridge {
+            memory: vec![0; 0x10000],
+            frame_count: 0,
+            writes: Vec::new(),
+            buttons: Vec::new(),
+            texts: Vec::new(),
+            watches: Vec::new(),
+        }));
+
+        let mut engine = Engine::new();
+        register_functions(&mut engine, &bridge);
+        if deterministic {
+            // Found before the built in one.
+            engine.register_fn("timestamp", || -> ScriptResult<Instant> {
+                Err("timestamp() is unavailable in deterministic mode".into())
+            });
+        }
+
+        let ast = engine.compile(source).map_err(|err| anyhow!("{}", err))?;
+        let callbacks = ast
+            .iter_functions()
+            .map(|function| function.name.to_string())
+            .collect();
+
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|err| anyhow!("{}", err))?;
+
+        let mut host = ScriptHost {
+            engine,
+            ast,
+            scope,
+            this: Dynamic::from_map(Map::new()),
+            bridge,
+            callbacks,
+            watched_reads: vec![false; 0x10000],
+            watched_writes: vec![false; 0x10000],
+            accesses: Vec::new(),
+            overlay: Arc::default(),
+        };
+        host.update_watches();
+
+        Ok(host)
+    }
+
+    /// Text to draw over the frame, updated at the end of each frame.
+    pub fn overlay(&self) -> Arc<Mutex<Vec<Text>>> {
+        self.overlay.clone()
+    }
+
+    /// Remember a read by the CPU if the script watches the address.
+    pub fn record_read(&mut self, address: u16, value: u8) {
+        if self.watched_reads[address as usize] {
+            self.accesses.push((Access::Read, address, value));
+        }
+    }
+
+    /// Remember a write by the CPU if the script watches the address.
+    pub fn record_write(&mut self, address: u16, value: u8) {
+        if self.watched_writes[address as usize] {
+            self.accesses.push((Access::Write, address, value));
+        }
+    }
+
+    pub fn has_accesses(&self) -> bool {
+        !self.accesses.is_empty()
+    }
+
+    /// Report the watched accesses of the last instruction.
+    fn run_accesses(&mut self, cpu: &mut Cpu) {
+        for (access, address, value) in std::mem::take(&mut self.accesses) {
+            let name = match access {
+                Access::Read => "on_read",
+                Access::Write => "on_write",
+            };
+            self.call(cpu, name, (address as i64, value as i64));
+        }
+    }
+
+    fn run_event(&mut self, cpu: &mut Cpu, event: Event) {
+        match event {
+            Event::FrameStart => self.call(cpu, "on_frame_start", ()),
+            Event::FrameEnd => {
+                self.call(cpu, "on_frame_end", ());
+
+                let texts = std::mem::take(&mut self.bridge.lock().unwrap().texts);
+                *self.overlay.lock().unwrap() = texts;
+            }
+            Event::SaveState(slot) => self.call(cpu, "on_save_state", (slot as i64,)),
+            Event::LoadState(slot) => {
+                let slot = slot.map_or(-1, |slot| slot as i64);
+                self.call(cpu, "on_load_state", (slot,))
+            }
+        }
+    }
+
+    /// Call one of the script's callbacks if it's defined, then do what it asked for.
+    fn call(&mut self, cpu: &mut Cpu, name: &str, args: impl FuncArgs) {
+        if !self.callbacks.contains(name) {
+            return;
+        }
+
+        {
+            let mut bridge = self.bridge.lock().unwrap();
+            bridge.memory.copy_from_slice(&cpu.memory[..]);
+            bridge.frame_count = cpu.ppu.frame_count();
+        }
+
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.this);
+        if let Err(err) = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut self.scope,
+            &self.ast,
+            name,
+            args,
+        ) {
+            error!("Script {} failed: {}", name, err);
+        }
+
+        let (writes, buttons) = {
+            let mut bridge = self.bridge.lock().unwrap();
+            (
+                std::mem::take(&mut bridge.writes),
+                std::mem::take(&mut bridge.buttons),
+            )
+        };
+
+        for (address, value) in writes {
+            cpu.write(address, value);
+        }
+
+        for (player, button, pressed) in buttons {
+            let mut state = cpu.controllers[player].state();
+            state.set(button, pressed);
+            cpu.controllers[player].set_state(state);
+        }
+
+        self.update_watches();
+    }
+
+    fn update_watches(&mut self) {
+        for (access, address) in self.bridge.lock().unwrap().watches.drain(..) {
+            match access {
+                Access::Read => self.watched_reads[address as usize] = true,
+                Access::Write => self.watched_writes[address as usize] = true,
+            }
+        }
+    }
+}
+
+/// The emulator's side of the script API.
+fn register_functions(engine: &mut Engine, bridge: &Arc<Mutex<Bridge>>) {
+    let b = bridge.clone();
+    engine.register_fn("read", move |address: i64| -> ScriptResult<i64> {
+        Ok(b.lock().unwrap().memory[to_address(address)? as usize] as i64)
+    });
+
+    let b = bridge.clone();
+    engine.register_fn(
+        "write",
+        move |address: i64, value: i64| -> ScriptResult<()> {
+            let value = u8::try_from(value).map_err(|_| format!("Invalid byte {}", value))?;
+            b.lock().unwrap().writes.push((to_address(address)?, value));
+            Ok(())
+        },
+    );
+
+    for &(name, pressed) in &[("press", true), ("release", false)] {
+        let b = bridge.clone();
+        engine.register_fn(name, move |player: i64, button: &str| -> ScriptResult<()> {
+            let player = match player {
+                1 | 2 => player as usize - 1,
+                _ => return Err(format!("Invalid player {}, expected 1 or 2", player).into()),
+            };
+            let button = ControllerState::button(button)
+                .ok_or_else(|| format!("Unknown button \"{}\"", button))?;
+
+            b.lock().unwrap().buttons.push((player, button, pressed));
+            Ok(())
+        });
+    }
+
+    let b = bridge.clone();
+    engine.register_fn("text", move |x: i64, y: i64, text: &str| {
+        b.lock().unwrap().texts.push(Text {
+            x: x as i32,
+            y: y as i32,
+            text: text.to_string(),
+        });
+    });
+
+    let b = bridge.clone();
+    engine.register_fn("frame_count", move || b.lock().unwrap().frame_count as i64);
+
+    for &(name, access) in &[("watch_read", Access::Read), ("watch_write", Access::Write)] {
+        let b = bridge.clone();
+        engine.register_fn(name, move |address: i64| -> ScriptResult<()> {
+            b.lock()
+                .unwrap()
+                .watches
+                .push((access, to_address(address)?));
+            Ok(())
+        });
+    }
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn to_address(address: i64) -> ScriptResult<u16> {
+    u16::try_from(address).map_err(|_| format!("Invalid address {}", address).into())
+}
+
+impl Cpu {
+    /// Let the script react to an event, if there's one.
+    pub fn script_event(&mut self, event: Event) {
+        if let Some(mut script) = self.script.take() {
+            script.run_event(self, event);
+            self.script = Some(script);
+        }
+    }
+
+    /// Report the watched accesses of the last instruction to the script.
+    pub fn script_accesses(&mut self) {
+        if !self
+            .script
+            .as_ref()
+            .is_some_and(|script| script.has_accesses())
+        {
+            return;
+        }
+
+        if let Some(mut script) = self.script.take() {
+            script.run_accesses(self);
+            self.script = Some(script);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger;
+    use crate::ines;
+
+    const SCRIPT: &str = r#"
+        watch_write(0x0400);
+
+        fn on_frame_start() {
+            write(0x0300, 0x42);
+            press(1, "start");
+            text(8, 8, "FRAME " + frame_count());
+        }
+
+        fn on_write(address, value) {
+            this.last = value;
+            write(0x0301, value + 1);
+        }
+
+        fn on_frame_end() {
+            write(0x0302, this.last + read(0x0301));
+            release(1, "start");
+            press(2, "a");
+        }
+    "#;
+
+    #[test]
+    fn test_script() -> Result<()> {
+        let nes_file = ines::NesFile::new("test/nestest.nes".to_string())?;
+        let mut cpu = Cpu::new(nes_file);
+
+        // Copy $0300 to $0400 in a loop.
+        cpu.memory[0x0200..0x0209]
+            .copy_from_slice(&[0xAD, 0x00, 0x03, 0x8D, 0x00, 0x04, 0x4C, 0x00, 0x02]);
+        cpu.program_counter = 0x0200;
+
+        let script = ScriptHost::new(SCRIPT, false)?;
+        let overlay = script.overlay();
+        cpu.script = Some(Box::new(script));
+
+        debugger::run_frame(&mut cpu);
+        assert_eq!(cpu.memory[0x0400], 0x42);
+        assert_eq!(cpu.memory[0x0301], 0x43);
+        assert_eq!(cpu.memory[0x0302], 0x85);
+        assert_eq!(cpu.controllers[0].state(), ControllerState(0));
+        assert_eq!(
+            cpu.controllers[1].state(),
+            ControllerState(ControllerState::A)
+        );
+        assert_eq!(
+            *overlay.lock().unwrap(),
+            vec![Text {
+                x: 8,
+                y: 8,
+                text: "FRAME 0".to_string()
+            }]
+        );
+
+        assert!(ScriptHost::new("fn on_frame_start( {", false).is_err());
+        assert!(ScriptHost::new("let start = timestamp();", false).is_ok());
+        assert!(ScriptHost::new("let start = timestamp();", true).is_err());
+
+        Ok(())
+    }
+}
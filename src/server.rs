@@ -0,0 +1,403 @@
+/// Control server for test harnesses and other tools, driving the emulator over TCP without
+/// linking to it.
+///
+/// Requests are JSON-RPC 2.0 (https://www.jsonrpc.org/specification), one per line, and each gets
+/// a response on its own line. Requests without an id are notifications and get no response.
+///   load_rom {path}
+///   step {frames}, frames defaults to 1, stops early at breakpoints
+///   read_memory {address, length}, length defaults to 1, without side effects on registers
+///   write_memory {address, data}
+///   set_input {player, buttons}, e.g. {"player": 0, "buttons": ["a", "right"]}, held until set
+///     again
+///   screenshot, the last frame as a base64 PNG
+///   save_state, load_state {state}, states are base64
+///
+/// e.g. {"jsonrpc": "2.0", "id": 1, "method": "step", "params": {"frames": 60}}
+use crate::controller::ControllerState;
+use crate::cpu::Stop;
+use crate::ines::NesFile;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::video::image;
+use crate::Nes;
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+/// Error codes from the specification.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// The command was understood but failed, e.g. the ROM couldn't be read.
+const COMMAND_FAILED: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    method: String,
+
+    #[serde(default)]
+    params: Value,
+
+    id: Option<Value>,
+}
+
+struct Error {
+    code: i64,
+    message: String,
+}
+
+impl Error {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Error {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        Error::new(COMMAND_FAILED, err)
+    }
+}
+
+#[derive(Deserialize)]
+struct LoadRom {
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct StepFrames {
+    #[serde(default = "one")]
+    frames: u32,
+}
+
+#[derive(Deserialize)]
+struct ReadMemory {
+    address: u16,
+
+    #[serde(default = "one")]
+    length: u32,
+}
+
+#[derive(Deserialize)]
+struct WriteMemory {
+    address: u16,
+    data: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct SetInput {
+    player: usize,
+    buttons: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct LoadState {
+    state: String,
+}
+
+fn one() -> u32 {
+    1
+}
+
+pub struct Server {
+    nes: Nes,
+}
+
+impl Server {
+    pub fn new(nes: Nes) -> Self {
+        Server { nes }
+    }
+
+    /// Serve clients one after the other, forever.
+    pub fn serve(&mut self, listener: TcpListener) -> Result<()> {
+        info!("Serving on {}", listener.local_addr()?);
+
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let peer = stream.peer_addr()?;
+            info!("Client {} connected", peer);
+
+            // A client going away shouldn't stop the server.
+            match self.serve_client(stream) {
+                Ok(()) => info!("Client {} disconnected", peer),
+                Err(err) => warn!("Client {} failed: {}", peer, err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Answer the client's requests until it disconnects.
+    pub fn serve_client(&mut self, stream: TcpStream) -> Result<()> {
+        let mut writer = stream.try_clone()?;
+
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(response) = self.handle(&line) {
+                writeln!(writer, "{}", response)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run a request, returns the response unless it's a notification.
+    pub fn handle(&mut self, request: &str) -> Option<String> {
+        let request: Request = match serde_json::from_str::<Value>(request) {
+            Ok(value) => match serde_json::from_value(value) {
+                Ok(request) => request,
+                Err(err) => {
+                    return Some(response(Value::Null, Err(Error::new(INVALID_REQUEST, err))))
+                }
+            },
+            Err(err) => return Some(response(Value::Null, Err(Error::new(PARSE_ERROR, err)))),
+        };
+
+        let result = if request.jsonrpc == "2.0" {
+            self.run(&request.method, request.params)
+        } else {
+            Err(Error::new(
+                INVALID_REQUEST,
+                "Only JSON-RPC 2.0 is supported.",
+            ))
+        };
+
+        request.id.map(|id| response(id, result))
+    }
+
+    fn run(&mut self, method: &str, params: Value) -> Result<Value, Error> {
+        match method {
+            "load_rom" => {
+                let LoadRom { path } = parse(params)?;
+                self.nes.insert_cartridge(NesFile::new(path)?);
+                Ok(Value::Null)
+            }
+            "step" => {
+                let StepFrames { frames } = parse(params)?;
+                self.cartridge()?;
+
+                let mut stepped = 0;
+                while stepped < frames {
+                    stepped += 1;
+                    match self.nes.step_frame() {
+                        Stop::FrameComplete => {}
+                        Stop::CycleLimit => break,
+                        Stop::Breakpoint => {
+                            return Ok(json!({ "frames": stepped, "breakpoint": true }))
+                        }
+                        Stop::UnknownOpcode(err) => return Err(Error::new(COMMAND_FAILED, err)),
+                        Stop::Trapped => {
+                            return Ok(
+                                json!({ "frames": stepped, "breakpoint": false, "trapped": true }),
+                            )
+                        }
+                    }
+                }
+                Ok(json!({ "frames": stepped, "breakpoint": false }))
+            }
+            "read_memory" => {
+                let ReadMemory { address, length } = parse(params)?;
+                let cpu = self.cartridge()?;
+                Ok(json!(cpu.peek_range(address, length as usize)))
+            }
+            "write_memory" => {
+                let WriteMemory { address, data } = parse(params)?;
+                let cpu = self.nes.cpu_mut().ok_or_else(no_cartridge)?;
+                for (offset, value) in data.into_iter().enumerate() {
+                    cpu.write(address.wrapping_add(offset as u16), value);
+                }
+                Ok(Value::Null)
+            }
+            "set_input" => {
+                let SetInput { player, buttons } = parse(params)?;
+                if player > 1 {
+                    return Err(Error::new(INVALID_PARAMS, "The player must be 0 or 1."));
+                }
+
+                let mut state = ControllerState::default();
+                for name in &buttons {
+                    let button = ControllerState::button(name).ok_or_else(|| {
+                        Error::new(INVALID_PARAMS, format!("Unknown button \"{}\".", name))
+                    })?;
+                    state.set(button, true);
+                }
+                self.nes.set_input(player, state);
+                Ok(Value::Null)
+            }
+            "screenshot" => {
+                let mut png = Vec::new();
+                image::write_png(&mut png, SCREEN_WIDTH, SCREEN_HEIGHT, self.nes.frame())?;
+                Ok(json!({
+                    "width": SCREEN_WIDTH,
+                    "height": SCREEN_HEIGHT,
+                    "png": base64::encode(png),
+                }))
+            }
+            "save_state" => {
+                let state = self.cartridge()?.save_state()?;
+                Ok(json!({ "state": base64::encode(state) }))
+            }
+            "load_state" => {
+                let LoadState { state } = parse(params)?;
+                let state = base64::decode(state)
+                    .map_err(|err| Error::new(INVALID_PARAMS, format!("Invalid state: {}", err)))?;
+                let cpu = self.nes.cpu_mut().ok_or_else(no_cartridge)?;
+                cpu.load_state(&state)?;
+                Ok(Value::Null)
+            }
+            _ => Err(Error::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method \"{}\".", method),
+            )),
+        }
+    }
+
+    fn cartridge(&self) -> Result<&crate::cpu::Cpu, Error> {
+        self.nes.cpu().ok_or_else(no_cartridge)
+    }
+}
+
+fn no_cartridge() -> Error {
+    anyhow!("No ROM is loaded.").into()
+}
+
+/// Parameters of a method, missing ones are the same as none.
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, Error> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|err| Error::new(INVALID_PARAMS, err))
+}
+
+fn response(id: Value, result: Result<Value, Error>) -> String {
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    };
+    response.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::test_util::spinning_nrom;
+    use std::thread;
+
+    fn call(server: &mut Server, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response = server.handle(&request.to_string()).unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut server = Server::new(Nes::new());
+        let response = call(&mut server, "step", Value::Null);
+        assert_eq!(response["error"]["code"], COMMAND_FAILED);
+
+        server.nes.insert_cartridge(spinning_nrom());
+
+        let response = call(&mut server, "step", json!({ "frames": 3 }));
+        assert_eq!(
+            response["result"],
+            json!({ "frames": 3, "breakpoint": false })
+        );
+
+        call(
+            &mut server,
+            "write_memory",
+            json!({ "address": 0x10, "data": [1, 2, 3] }),
+        );
+        let response = call(
+            &mut server,
+            "read_memory",
+            json!({ "address": 0x0F, "length": 5 }),
+        );
+        assert_eq!(response["result"], json!([0, 1, 2, 3, 0]));
+
+        let state = call(&mut server, "save_state", Value::Null)["result"]["state"].clone();
+        call(
+            &mut server,
+            "write_memory",
+            json!({ "address": 0x10, "data": [9] }),
+        );
+        call(&mut server, "load_state", json!({ "state": state }));
+        let response = call(&mut server, "read_memory", json!({ "address": 0x10 }));
+        assert_eq!(response["result"], json!([1]));
+
+        call(
+            &mut server,
+            "set_input",
+            json!({ "player": 0, "buttons": ["a", "start"] }),
+        );
+        call(&mut server, "step", Value::Null);
+        let cpu = server.nes.cpu().unwrap();
+        assert_eq!(
+            cpu.controllers[0].state(),
+            ControllerState(ControllerState::A | ControllerState::START)
+        );
+        let response = call(
+            &mut server,
+            "set_input",
+            json!({ "player": 0, "buttons": ["turbo"] }),
+        );
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+
+        let response = call(&mut server, "screenshot", Value::Null);
+        let png = base64::decode(response["result"]["png"].as_str().unwrap()).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+
+        let response = call(&mut server, "reset", Value::Null);
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+        let response = server.handle("{").unwrap();
+        assert!(response.contains(&PARSE_ERROR.to_string()));
+
+        // Notifications get no response.
+        let notification = json!({ "jsonrpc": "2.0", "method": "step" });
+        assert_eq!(server.handle(&notification.to_string()), None);
+    }
+
+    #[test]
+    fn test_serve_client() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        let client = thread::spawn(move || -> Result<String> {
+            let mut stream = TcpStream::connect(addr)?;
+            writeln!(
+                stream,
+                r#"{{"jsonrpc": "2.0", "id": 7, "method": "read_memory", "params": {{"address": 49152}}}}"#
+            )?;
+
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line)?;
+            Ok(line)
+        });
+
+        let mut server = Server::new(Nes::new());
+        server.nes.insert_cartridge(spinning_nrom());
+        let (stream, _) = listener.accept()?;
+        server.serve_client(stream)?;
+
+        let response: Value = serde_json::from_str(&client.join().unwrap()?)?;
+        assert_eq!(
+            response,
+            json!({ "jsonrpc": "2.0", "id": 7, "result": [0x4C] })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+/// Performance statistics, how fast the emulator runs compared to the console and how steadily.
+///
+/// Frontends record how long emulating each frame took, the report gives the frame rate against
+/// the console's, percentiles of the frame times and the health of the audio buffer. A frame
+/// rate under the console's or audio underruns mean the machine can't keep up, a high
+/// 99th percentile with a fine median means it stutters now and then.
+use crate::audio::{AudioHealth, SampleBuffer};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The frame rate is measured over this much of the recent past.
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+/// Frame times kept for the percentiles, about 10 seconds at the console's frame rate.
+const FRAME_TIMES: usize = 600;
+
+/// Frame times at a few percentiles, the slowest frames are the ones noticed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Percentiles {
+    fn new(mut times: Vec<Duration>) -> Self {
+        if times.is_empty() {
+            return Percentiles::default();
+        }
+
+        times.sort_unstable();
+        let at = |percentile: usize| times[(times.len() - 1) * percentile / 100];
+        Percentiles {
+            p50: at(50),
+            p95: at(95),
+            p99: at(99),
+            max: at(100),
+        }
+    }
+}
+
+/// The statistics at one point in time, see `Stats::report()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Report {
+    /// Frames run in the last second.
+    pub fps: f64,
+
+    /// Frames per second since the start.
+    pub average_fps: f64,
+
+    /// Emulated time per real time over the last second, 1 when running at the console's speed.
+    pub speed: f64,
+
+    /// Time spent emulating each of the recent frames, not counting waiting to pace them.
+    pub frame_time: Percentiles,
+
+    pub audio: Option<AudioHealth>,
+}
+
+impl fmt::Display for Report {
+    /// e.g. "60.1 fps (100%), 1.2/2.5/4.0 ms p50/p95/p99, audio 48% full, 0 underruns".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "{:.1} fps ({:.0}%), {:.1}/{:.1}/{:.1} ms p50/p95/p99",
+            self.fps,
+            self.speed * 100.0,
+            ms(self.frame_time.p50),
+            ms(self.frame_time.p95),
+            ms(self.frame_time.p99)
+        )?;
+        if let Some(audio) = &self.audio {
+            write!(
+                f,
+                ", audio {:.0}% full, {} underruns",
+                audio.fill() * 100.0,
+                audio.underruns
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Stats {
+    /// Frames per second of the console.
+    frame_rate: f64,
+
+    start: Instant,
+    frames: u64,
+
+    /// When the recent frames finished, the oldest first.
+    finished: VecDeque<Instant>,
+
+    /// How long the last `FRAME_TIMES` frames took to emulate, the oldest first.
+    frame_times: VecDeque<Duration>,
+
+    audio: Option<SampleBuffer>,
+}
+
+impl Stats {
+    /// Statistics for a console running at `frame_rate` frames per second, see
+    /// `Region::frame_rate()`.
+    pub fn new(frame_rate: f64) -> Self {
+        Stats {
+            frame_rate,
+            start: Instant::now(),
+            frames: 0,
+            finished: VecDeque::new(),
+            frame_times: VecDeque::with_capacity(FRAME_TIMES),
+            audio: None,
+        }
+    }
+
+    /// Report the health of the buffer feeding the audio device too.
+    pub fn watch_audio(&mut self, buffer: SampleBuffer) {
+        self.audio = Some(buffer);
+    }
+
+    /// Count a frame that took `frame_time` to emulate, call after each frame.
+    pub fn record_frame(&mut self, frame_time: Duration) {
+        let now = Instant::now();
+        self.frames += 1;
+
+        self.finished.push_back(now);
+        while self
+            .finished
+            .front()
+            .is_some_and(|&finished| now.duration_since(finished) > FPS_WINDOW)
+        {
+            self.finished.pop_front();
+        }
+
+        if self.frame_times.len() == FRAME_TIMES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    pub fn report(&self) -> Report {
+        let now = Instant::now();
+
+        // Less than a second has passed at the start.
+        let window = now.duration_since(self.start).min(FPS_WINDOW);
+        let recent = self
+            .finished
+            .iter()
+            .filter(|&&finished| now.duration_since(finished) <= FPS_WINDOW)
+            .count();
+        let fps = per_second(recent as u64, window);
+
+        Report {
+            fps,
+            average_fps: per_second(self.frames, now.duration_since(self.start)),
+            speed: fps / self.frame_rate,
+            frame_time: Percentiles::new(self.frame_times.iter().copied().collect()),
+            audio: self.audio.as_ref().map(SampleBuffer::health),
+        }
+    }
+}
+
+fn per_second(count: u64, time: Duration) -> f64 {
+    if time.is_zero() {
+        return 0.0;
+    }
+    count as f64 / time.as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let times = (1..=100).map(Duration::from_millis).collect();
+        let percentiles = Percentiles::new(times);
+        assert_eq!(percentiles.p50, Duration::from_millis(50));
+        assert_eq!(percentiles.p95, Duration::from_millis(95));
+        assert_eq!(percentiles.p99, Duration::from_millis(99));
+        assert_eq!(percentiles.max, Duration::from_millis(100));
+
+        assert_eq!(Percentiles::new(Vec::new()), Percentiles::default());
+    }
+
+    #[test]
+    fn test_report() {
+        let mut stats = Stats::new(60.0);
+        let buffer = SampleBuffer::with_latency(1000, Duration::from_millis(4));
+        stats.watch_audio(buffer.clone());
+        buffer.push(0.0);
+
+        for _ in 0..10 {
+            stats.record_frame(Duration::from_millis(2));
+        }
+        let report = stats.report();
+        assert!(report.fps > 0.0);
+        assert_eq!(report.speed, report.fps / 60.0);
+        assert_eq!(report.frame_time.p99, Duration::from_millis(2));
+        assert_eq!(report.audio.map(|audio| audio.fill()), Some(0.25));
+    }
+}
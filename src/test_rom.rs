@@ -0,0 +1,379 @@
+/// Runs test ROMs that report their results in cartridge RAM, like blargg's, without a frontend.
+/// See https://www.nesdev.org/wiki/Emulator_tests.
+///
+/// Once $6001-$6003 hold the signature DE B0 61, $6000 is the status: $80 while running, $81 when
+/// the reset button has to be pressed, otherwise the result code, 0 for passed. $6004 holds a
+/// NUL-terminated message with the details.
+///
+/// Older ROMs without cartridge RAM, e.g. vbl_nmi_timing and sprite_hit_tests, only write the
+/// result code to $F8 once done, 1 for passed. They're run with `run_result_code`.
+use crate::cpu::{Cpu, Stop};
+use crate::ines::NesFile;
+use crate::opcode::DecodeError;
+use alloc::string::String;
+use core::fmt;
+
+const STATUS: usize = 0x6000;
+const SIGNATURE: usize = 0x6001;
+const MESSAGE: usize = 0x6004;
+
+/// Result code of the older ROMs, 0 until they're done.
+const RESULT_CODE: usize = 0xF8;
+
+const VALID_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+const RUNNING: u8 = 0x80;
+const NEEDS_RESET: u8 = 0x81;
+
+/// Cycles to wait before pressing reset, the ROMs ask for at least 100ms.
+const RESET_DELAY: u64 = 200_000;
+
+/// Enough for most single tests, the multi-test ROMs take around 50 million.
+pub const DEFAULT_CYCLE_BUDGET: u64 = 100_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+
+    /// The result code, specific to each ROM.
+    Failed(u8),
+
+    /// The budget ran out before there was a result.
+    TimedOut,
+
+    /// The CPU reached an opcode it doesn't implement.
+    Crashed(DecodeError),
+}
+
+/// How a test ROM ended.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub outcome: Outcome,
+
+    /// What the ROM wrote at $6004, usually the name of the test and why it failed.
+    pub message: String,
+
+    pub cycles: u64,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.outcome == Outcome::Passed
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.outcome {
+            Outcome::Passed => write!(f, "Passed")?,
+            Outcome::Failed(code) => write!(f, "Failed with code {}", code)?,
+            Outcome::TimedOut => write!(f, "Timed out")?,
+            Outcome::Crashed(err) => write!(f, "Crashed, {}", err)?,
+        }
+        write!(f, " after {} cycles", self.cycles)?;
+
+        let message = self.message.trim();
+        if !message.is_empty() {
+            write!(f, ":\n{}", message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Power on with the cartridge and run until the ROM reports a result, or `cycle_budget` cycles
+/// have been run.
+pub fn run(cartridge: NesFile, cycle_budget: u64) -> Report {
+    let mut cpu = Cpu::new(cartridge);
+
+    let mut reset_at = None;
+    while cpu.cycles < cycle_budget {
+        if let Stop::UnknownOpcode(err) = cpu.run_frame() {
+            return report(&cpu, Outcome::Crashed(err));
+        }
+
+        if cpu.memory[SIGNATURE..SIGNATURE + 3] != VALID_SIGNATURE {
+            continue;
+        }
+
+        match cpu.memory[STATUS] {
+            RUNNING => {}
+            NEEDS_RESET => match reset_at {
+                None => reset_at = Some(cpu.cycles + RESET_DELAY),
+                Some(cycles) if cpu.cycles >= cycles => {
+                    reset_at = None;
+                    cpu.reset();
+                }
+                Some(_) => {}
+            },
+            0 => return report(&cpu, Outcome::Passed),
+            code => return report(&cpu, Outcome::Failed(code)),
+        }
+    }
+
+    report(&cpu, Outcome::TimedOut)
+}
+
+/// Like `run`, for the older ROMs that report at $F8.
+pub fn run_result_code(cartridge: NesFile, cycle_budget: u64) -> Report {
+    let mut cpu = Cpu::new(cartridge);
+
+    while cpu.cycles < cycle_budget {
+        if let Stop::UnknownOpcode(err) = cpu.run_frame() {
+            return report(&cpu, Outcome::Crashed(err));
+        }
+
+        match cpu.memory[RESULT_CODE] {
+            0 => {}
+            1 => return report(&cpu, Outcome::Passed),
+            code => return report(&cpu, Outcome::Failed(code)),
+        }
+    }
+
+    report(&cpu, Outcome::TimedOut)
+}
+
+fn report(cpu: &Cpu, outcome: Outcome) -> Report {
+    let message = if cpu.memory[SIGNATURE..SIGNATURE + 3] == VALID_SIGNATURE {
+        cpu.memory[MESSAGE..STATUS + 0x1000]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| byte as char)
+            .collect()
+    } else {
+        String::new()
+    };
+
+    Report {
+        outcome,
+        message,
+        cycles: cpu.cycles,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::test_util::nrom;
+    use alloc::vec::Vec;
+    use alloc::{format, vec};
+    use std::path::PathBuf;
+
+    /// Where the suites are, a checkout of https://github.com/christopherpow/nes-test-roms. CI
+    /// checks it out and sets it, the suites are skipped without it but fail when it's set and a
+    /// ROM is missing.
+    ///
+    /// Only NROM is supported, so the suites are the ROMs built one test per ROM on NROM, e.g.
+    /// `rom_singles`, rather than the combined ones on MMC1. Tests of opcodes the CPU doesn't
+    /// implement, the unstable unofficial ones, are left out.
+    const ROMS: &str = "NES_TEST_ROMS";
+
+    /// Asks for a reset, then writes `status` after the next vblank.
+    fn cartridge(status: u8) -> NesFile {
+        let mut program = vec![
+            0xA5, 0x10, // LDA $10, set once reset.
+            0xD0, 0x00, // BNE to after the reset, patched below.
+        ];
+        let mut store = |value: u8, addr: u16| {
+            let [low, high] = addr.to_le_bytes();
+            program.extend_from_slice(&[0xA9, value, 0x8D, low, high]);
+        };
+
+        store(RUNNING, 0x6000);
+        for (i, &byte) in VALID_SIGNATURE.iter().chain(b"Done\n\0").enumerate() {
+            store(byte, 0x6001 + i as u16);
+        }
+        store(1, 0x0010);
+        store(NEEDS_RESET, 0x6000);
+
+        let spin = (0xC000 + program.len() as u16).to_le_bytes();
+        program.extend_from_slice(&[0x4C, spin[0], spin[1]]);
+        program[3] = program.len() as u8 - 4;
+
+        program.extend_from_slice(&[
+            0xAD, 0x02, 0x20, // LDA $2002, waits for vblank.
+            0x10, 0xFB, // BPL back to the LDA.
+            0xA9, status, 0x8D, 0x00, 0x60, // Write the status.
+        ]);
+        let spin = (0xC000 + program.len() as u16).to_le_bytes();
+        program.extend_from_slice(&[0x4C, spin[0], spin[1]]);
+
+        nrom(&program)
+    }
+
+    /// Writes `code` to $F8 after the first vblank.
+    fn result_code_cartridge(code: u8) -> NesFile {
+        nrom(&[
+            0xAD, 0x02, 0x20, // LDA $2002, waits for vblank.
+            0x10, 0xFB, // BPL back to the LDA.
+            0xA9, code, 0x85, 0xF8, // Write the result code.
+            0x4C, 0x09, 0xC0, // Spin.
+        ])
+    }
+
+    #[test]
+    fn test_run() {
+        let report = run(cartridge(0), DEFAULT_CYCLE_BUDGET);
+        assert!(report.passed());
+        assert_eq!(report.message, "Done\n");
+        assert!(report.cycles > RESET_DELAY);
+        assert!(report.to_string().starts_with("Passed after "));
+
+        let report = run(cartridge(3), DEFAULT_CYCLE_BUDGET);
+        assert_eq!(report.outcome, Outcome::Failed(3));
+        assert!(report.to_string().ends_with("cycles:\nDone"));
+
+        let report = run(cartridge(0), 10_000);
+        assert_eq!(report.outcome, Outcome::TimedOut);
+
+        // $02 isn't implemented.
+        let report = run(nrom(&[0xEA, 0x02]), DEFAULT_CYCLE_BUDGET);
+        assert_eq!(
+            report.outcome,
+            Outcome::Crashed(DecodeError {
+                opcode: 0x02,
+                address: 0xC001
+            })
+        );
+        assert!(report
+            .to_string()
+            .starts_with("Crashed, Unknown opcode 02 at C001 after "));
+    }
+
+    #[test]
+    fn test_run_result_code() {
+        let report = run_result_code(result_code_cartridge(1), DEFAULT_CYCLE_BUDGET);
+        assert!(report.passed());
+        assert_eq!(report.message, "");
+
+        let report = run_result_code(result_code_cartridge(4), DEFAULT_CYCLE_BUDGET);
+        assert_eq!(report.outcome, Outcome::Failed(4));
+    }
+
+    /// Run each ROM of a suite, failing with the reports of the ones that didn't pass.
+    fn run_suite(roms: &[&str]) {
+        run_suite_with(run, roms);
+    }
+
+    fn run_suite_with(runner: fn(NesFile, u64) -> Report, roms: &[&str]) {
+        let dir = match std::env::var_os(ROMS) {
+            Some(dir) => PathBuf::from(dir),
+            None => {
+                std::eprintln!("Skipped, {} isn't set.", ROMS);
+                return;
+            }
+        };
+
+        let failures: Vec<String> = roms
+            .iter()
+            .filter_map(|rom| {
+                let path = dir.join(rom);
+                if !path.is_file() {
+                    return Some(format!("{}: Missing from {}", rom, dir.display()));
+                }
+                let report = match NesFile::new(path.display().to_string()) {
+                    Ok(cartridge) => runner(cartridge, DEFAULT_CYCLE_BUDGET),
+                    Err(err) => return Some(format!("{}: {}", rom, err)),
+                };
+                (!report.passed()).then(|| format!("{}: {}", rom, report))
+            })
+            .collect();
+
+        assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+    }
+
+    #[test]
+    fn test_cpu_suite() {
+        // 03-immediate, 07-abs_xy and 09-ind_y test unstable unofficial opcodes.
+        run_suite(&[
+            "instr_test-v5/rom_singles/01-basics.nes",
+            "instr_test-v5/rom_singles/02-implied.nes",
+            "instr_test-v5/rom_singles/04-zero_page.nes",
+            "instr_test-v5/rom_singles/05-zp_xy.nes",
+            "instr_test-v5/rom_singles/06-absolute.nes",
+            "instr_test-v5/rom_singles/08-ind_x.nes",
+            "instr_test-v5/rom_singles/10-branches.nes",
+            "instr_test-v5/rom_singles/11-stack.nes",
+            "instr_test-v5/rom_singles/12-jmp_jsr.nes",
+            "instr_test-v5/rom_singles/13-rts.nes",
+            "instr_test-v5/rom_singles/14-rti.nes",
+            "instr_test-v5/rom_singles/15-brk.nes",
+            "instr_test-v5/rom_singles/16-special.nes",
+            // 03-dummy_reads and 04-dummy_reads_apu need the CPU's dummy reads, which aren't
+            // emulated.
+            "instr_misc/rom_singles/01-abs_x_wrap.nes",
+            "instr_misc/rom_singles/02-branch_wrap.nes",
+            // 1-instr_timing times every opcode, the unstable ones included.
+            "instr_timing/rom_singles/2-branch_timing.nes",
+            "cpu_interrupts_v2/rom_singles/1-cli_latency.nes",
+            "cpu_interrupts_v2/rom_singles/2-nmi_and_brk.nes",
+            "cpu_interrupts_v2/rom_singles/3-nmi_and_irq.nes",
+            "cpu_interrupts_v2/rom_singles/4-irq_and_dma.nes",
+            "cpu_interrupts_v2/rom_singles/5-branch_delays_irq.nes",
+            "cpu_reset/registers.nes",
+            "cpu_reset/ram_after_reset.nes",
+        ]);
+    }
+
+    #[test]
+    fn test_ppu_suite() {
+        run_suite(&["oam_read/oam_read.nes", "oam_stress/oam_stress.nes"]);
+    }
+
+    #[test]
+    fn test_apu_suite() {
+        run_suite(&[
+            "apu_test/rom_singles/1-len_ctr.nes",
+            "apu_test/rom_singles/2-len_table.nes",
+            "apu_test/rom_singles/3-irq_flag.nes",
+            "apu_test/rom_singles/4-jitter.nes",
+            "apu_test/rom_singles/5-len_timing.nes",
+            "apu_test/rom_singles/6-irq_flag_timing.nes",
+            "apu_test/rom_singles/7-dmc_basics.nes",
+            "apu_test/rom_singles/8-dmc_rates.nes",
+            "apu_reset/4015_cleared.nes",
+        ]);
+    }
+
+    // The suites below predate cartridge RAM reports, except ppu_open_bus, and are NROM builds.
+    // A ROM on another mapper fails its suite with the mapper's number.
+    #[test]
+    fn test_vbl_nmi_timing_suite() {
+        run_suite_with(
+            run_result_code,
+            &[
+                "vbl_nmi_timing/1.frame_basics.nes",
+                "vbl_nmi_timing/2.vbl_timing.nes",
+                "vbl_nmi_timing/3.even_odd_frames.nes",
+                "vbl_nmi_timing/4.vbl_clear_timing.nes",
+                "vbl_nmi_timing/5.nmi_suppression.nes",
+                "vbl_nmi_timing/6.nmi_disable.nes",
+                "vbl_nmi_timing/7.nmi_timing.nes",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_sprite_hit_suite() {
+        run_suite_with(
+            run_result_code,
+            &[
+                "sprite_hit_tests_2005.10.05/01.basics.nes",
+                "sprite_hit_tests_2005.10.05/02.alignment.nes",
+                "sprite_hit_tests_2005.10.05/03.corners.nes",
+                "sprite_hit_tests_2005.10.05/04.flip.nes",
+                "sprite_hit_tests_2005.10.05/05.left_clip.nes",
+                "sprite_hit_tests_2005.10.05/06.right_edge.nes",
+                "sprite_hit_tests_2005.10.05/07.screen_bottom.nes",
+                "sprite_hit_tests_2005.10.05/08.double_height.nes",
+                "sprite_hit_tests_2005.10.05/09.timing_basics.nes",
+                "sprite_hit_tests_2005.10.05/10.timing_order.nes",
+                "sprite_hit_tests_2005.10.05/11.edge_timing.nes",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_ppu_open_bus() {
+        run_suite(&["ppu_open_bus/ppu_open_bus.nes"]);
+    }
+}
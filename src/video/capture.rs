@@ -0,0 +1,242 @@
+/// Captures the last few seconds of frames as an animated GIF.
+///
+/// The frames are kept as palette indices which map directly onto a GIF palette of the 64 system
+/// colours, so there's no colour quantization. To keep the file small only every other frame is
+/// kept, frames that didn't change are merged and changed frames only store the region that
+/// changed.
+use crate::ppu::{Palette, SCREEN_HEIGHT, SCREEN_WIDTH, SYSTEM_PALETTE};
+use anyhow::Result;
+use gif::{DisposalMethod, Encoder, Frame, Repeat};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Only one of every this many frames is kept.
+const FRAME_STEP: u64 = 2;
+
+pub struct GifCapture {
+    /// The most recent frames, oldest first.
+    frames: VecDeque<Vec<u8>>,
+
+    /// Maximum number of frames kept.
+    capacity: usize,
+
+    /// Frames seen, including the ones that weren't kept.
+    frame_count: u64,
+
+    /// Frames per second of the console, for the delays.
+    frame_rate: f64,
+
+    /// Colours of the GIF, the system palette unless another one is on screen.
+    palette: Palette,
+}
+
+impl GifCapture {
+    /// Capture holding the last `seconds` of frames of a console running at `frame_rate`.
+    pub fn new(seconds: f64, frame_rate: f64) -> Self {
+        let capacity = (seconds * frame_rate / FRAME_STEP as f64).ceil().max(1.0) as usize;
+
+        GifCapture {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+            frame_count: 0,
+            frame_rate,
+            palette: SYSTEM_PALETTE,
+        }
+    }
+
+    /// Save the GIF with other colours, the frames already captured too.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Record a completed frame of palette indices.
+    pub fn push(&mut self, frame: &[u8]) {
+        self.frame_count += 1;
+        if !self.frame_count.is_multiple_of(FRAME_STEP) {
+            return;
+        }
+
+        // Reuse the oldest frame's allocation once full.
+        let mut buffer = if self.frames.len() == self.capacity {
+            self.frames.pop_front().unwrap()
+        } else {
+            Vec::with_capacity(frame.len())
+        };
+        buffer.clear();
+        buffer.extend_from_slice(frame);
+
+        self.frames.push_back(buffer);
+    }
+
+    /// Write the captured frames to a GIF file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    /// Encode the captured frames as a looping GIF.
+    pub fn write<W: Write>(&self, writer: W) -> Result<()> {
+        let palette: Vec<u8> = self
+            .palette
+            .iter()
+            .flat_map(|&(r, g, b)| vec![r, g, b])
+            .collect();
+
+        let mut encoder =
+            Encoder::new(writer, SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16, &palette)?;
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        // Each frame is written once the next change shows how long it was displayed for.
+        let mut pending: Option<(Frame, usize)> = None;
+        let mut previous: Option<&Vec<u8>> = None;
+
+        for (i, frame) in self.frames.iter().enumerate() {
+            let region = match previous {
+                None => Some((0, 0, SCREEN_WIDTH, SCREEN_HEIGHT)),
+                Some(previous) => changed_region(previous, frame),
+            };
+
+            if let Some((left, top, width, height)) = region {
+                if let Some((mut pending, start)) = pending.take() {
+                    pending.delay = delay(start, i, self.frame_rate);
+                    encoder.write_frame(&pending)?;
+                }
+
+                pending = Some((cropped_frame(frame, left, top, width, height), i));
+                previous = Some(frame);
+            }
+        }
+
+        if let Some((mut pending, start)) = pending {
+            pending.delay = delay(start, self.frames.len(), self.frame_rate);
+            encoder.write_frame(&pending)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Delay in hundredths of a second for a frame displayed from kept frame `start` until `end`.
+///
+/// Rounding the absolute times keeps the error from adding up over the clip.
+fn delay(start: usize, end: usize, frame_rate: f64) -> u16 {
+    let centiseconds =
+        |frame: usize| (frame as f64 * FRAME_STEP as f64 * 100.0 / frame_rate).round() as u16;
+
+    centiseconds(end) - centiseconds(start)
+}
+
+/// Bounding box (left, top, width, height) of the pixels that differ, `None` if none do.
+fn changed_region(previous: &[u8], frame: &[u8]) -> Option<(usize, usize, usize, usize)> {
+    let mut left = SCREEN_WIDTH;
+    let mut right = 0;
+    let mut top = SCREEN_HEIGHT;
+    let mut bottom = 0;
+
+    for (y, (previous, row)) in previous
+        .chunks(SCREEN_WIDTH)
+        .zip(frame.chunks(SCREEN_WIDTH))
+        .enumerate()
+    {
+        if previous == row {
+            continue;
+        }
+
+        top = top.min(y);
+        bottom = y;
+
+        let first = previous.iter().zip(row).position(|(a, b)| a != b).unwrap();
+        let last = previous.iter().zip(row).rposition(|(a, b)| a != b).unwrap();
+        left = left.min(first);
+        right = right.max(last);
+    }
+
+    if top == SCREEN_HEIGHT {
+        None
+    } else {
+        Some((left, top, right - left + 1, bottom - top + 1))
+    }
+}
+
+/// GIF frame holding a region of the picture, drawn over the previous frames.
+fn cropped_frame(
+    frame: &[u8],
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+) -> Frame<'static> {
+    let mut buffer = Vec::with_capacity(width * height);
+    for row in frame.chunks(SCREEN_WIDTH).skip(top).take(height) {
+        buffer.extend(row[left..left + width].iter().map(|index| index & 0x3F));
+    }
+
+    Frame {
+        left: left as u16,
+        top: top as u16,
+        width: width as u16,
+        height: height as u16,
+        dispose: DisposalMethod::Keep,
+        buffer: Cow::Owned(buffer),
+        ..Frame::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::Region;
+
+    #[test]
+    fn test_rolling_buffer() {
+        let mut capture = GifCapture::new(1.0, Region::Ntsc.frame_rate());
+
+        for i in 0..200 {
+            capture.push(&vec![i as u8; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        }
+
+        assert_eq!(capture.frames.len(), capture.capacity);
+        assert_eq!(capture.frames.back().unwrap()[0], 199);
+    }
+
+    #[test]
+    fn test_changed_region() {
+        let previous = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut frame = previous.clone();
+        assert_eq!(changed_region(&previous, &frame), None);
+
+        frame[10 * SCREEN_WIDTH + 20] = 1;
+        frame[12 * SCREEN_WIDTH + 5] = 1;
+        assert_eq!(changed_region(&previous, &frame), Some((5, 10, 16, 3)));
+    }
+
+    #[test]
+    fn test_write() -> Result<()> {
+        let mut capture = GifCapture::new(1.0, Region::Ntsc.frame_rate());
+
+        // A static picture followed by a single changed pixel.
+        let mut frame = vec![0x0F; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for _ in 0..20 {
+            capture.push(&frame);
+        }
+        frame[0] = 0x30;
+        for _ in 0..20 {
+            capture.push(&frame);
+        }
+
+        let mut gif = vec![];
+        capture.write(&mut gif)?;
+
+        let mut decoder = gif::DecodeOptions::new().read_info(&gif[..])?;
+        let mut frames = vec![];
+        while let Some(frame) = decoder.read_next_frame()? {
+            frames.push((frame.width, frame.height, frame.delay));
+        }
+
+        // 10 kept frames each, rounding the absolute times spreads the error across frames.
+        assert_eq!(frames, vec![(256, 240, 33), (1, 1, 34)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+/// A tiny 3x5 pixel font for drawing text over the picture, e.g. from scripts.
+///
+/// Text is drawn onto the PPU's frame of palette indices before it's filtered, so it's scaled and
+/// filtered along with the game.
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Width and height of a glyph, characters are a pixel further apart.
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+
+/// Colours of the text and of its shadow, which keeps it readable on any background.
+const TEXT_COLOUR: u8 = 0x30;
+const SHADOW_COLOUR: u8 = 0x0F;
+
+/// Rows of the glyph, top first, the high bit of the 3 is the left pixel. Lower case letters use
+/// the upper case glyphs and anything unknown is drawn as a box.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b011, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '$' => [0b011, 0b110, 0b010, 0b011, 0b110],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '?' => [0b111, 0b001, 0b010, 0b000, 0b010],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '[' => [0b011, 0b010, 0b010, 0b010, 0b011],
+        ']' => [0b110, 0b010, 0b010, 0b010, 0b110],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '&' => [0b010, 0b101, 0b010, 0b101, 0b011],
+        '|' => [0b010, 0b010, 0b010, 0b010, 0b010],
+        '^' => [0b010, 0b101, 0b000, 0b000, 0b000],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        _ => [0b111, 0b101, 0b101, 0b101, 0b111],
+    }
+}
+
+/// Draw `text` onto a frame with its top left at (x, y), clipped to the screen. Lines are split
+/// at '\n'.
+pub fn draw_text(frame: &mut [u8], x: i32, y: i32, text: &str) {
+    for (line, characters) in text.split('\n').enumerate() {
+        let top = y + (line * (GLYPH_HEIGHT + 2)) as i32;
+        for (column, c) in characters.chars().enumerate() {
+            let left = x + (column * (GLYPH_WIDTH + 1)) as i32;
+            draw_glyph(frame, left + 1, top + 1, c, SHADOW_COLOUR);
+            draw_glyph(frame, left, top, c, TEXT_COLOUR);
+        }
+    }
+}
+
+fn draw_glyph(frame: &mut [u8], x: i32, y: i32, c: char, colour: u8) {
+    for (row, bits) in glyph(c).iter().enumerate() {
+        for column in 0..GLYPH_WIDTH {
+            if bits & (0b100 >> column) == 0 {
+                continue;
+            }
+
+            let (px, py) = (x + column as i32, y + row as i32);
+            if (0..SCREEN_WIDTH as i32).contains(&px) && (0..SCREEN_HEIGHT as i32).contains(&py) {
+                frame[py as usize * SCREEN_WIDTH + px as usize] = colour;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_text() {
+        let mut frame = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        draw_text(&mut frame, 10, 20, "1");
+
+        // The top of the 1 is the middle pixel, its shadow is down and to the right.
+        assert_eq!(frame[20 * SCREEN_WIDTH + 10], 0);
+        assert_eq!(frame[20 * SCREEN_WIDTH + 11], TEXT_COLOUR);
+        assert_eq!(frame[21 * SCREEN_WIDTH + 12], SHADOW_COLOUR);
+
+        // Clipped at the edges.
+        draw_text(&mut frame, -2, SCREEN_HEIGHT as i32 - 2, "HI");
+        draw_text(&mut frame, SCREEN_WIDTH as i32 - 1, -4, "8");
+    }
+}
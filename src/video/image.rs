@@ -0,0 +1,143 @@
+/// Still images of palette indices, e.g. the PPU's debug views.
+use crate::ppu::{Palette, SYSTEM_PALETTE};
+use anyhow::Result;
+use png::{BitDepth, ColorType, Encoder};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Write an image of palette indices to a PNG file.
+pub fn save_png<P: AsRef<Path>>(path: P, width: usize, height: usize, image: &[u8]) -> Result<()> {
+    write_png(BufWriter::new(File::create(path)?), width, height, image)
+}
+
+/// Encode an image of palette indices as an indexed PNG with the system palette, so the colours
+/// are exact.
+pub fn write_png<W: Write>(writer: W, width: usize, height: usize, image: &[u8]) -> Result<()> {
+    write_png_with(writer, width, height, image, &SYSTEM_PALETTE)
+}
+
+/// Like `save_png()` with the colours of another palette, e.g. the one on screen.
+pub fn save_png_with<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    image: &[u8],
+    palette: &Palette,
+) -> Result<()> {
+    write_png_with(
+        BufWriter::new(File::create(path)?),
+        width,
+        height,
+        image,
+        palette,
+    )
+}
+
+pub fn write_png_with<W: Write>(
+    writer: W,
+    width: usize,
+    height: usize,
+    image: &[u8],
+    palette: &Palette,
+) -> Result<()> {
+    let indices: Vec<u8> = image.iter().map(|index| index & 0x3F).collect();
+    write_indexed(writer, width, height, &indices, false, palette)
+}
+
+/// Write an image of palette indices with transparent pixels to a PNG file, to lay over another
+/// image.
+pub fn save_png_layer<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    image: &[Option<u8>],
+) -> Result<()> {
+    write_png_layer(BufWriter::new(File::create(path)?), width, height, image)
+}
+
+/// Like `write_png`, `None` pixels are transparent.
+pub fn write_png_layer<W: Write>(
+    writer: W,
+    width: usize,
+    height: usize,
+    image: &[Option<u8>],
+) -> Result<()> {
+    let indices: Vec<u8> = image
+        .iter()
+        .map(|index| index.map_or(TRANSPARENT, |index| index & 0x3F))
+        .collect();
+    write_indexed(writer, width, height, &indices, true, &SYSTEM_PALETTE)
+}
+
+/// Index of the transparent entry after the system palette.
+const TRANSPARENT: u8 = 0x40;
+
+fn write_indexed<W: Write>(
+    writer: W,
+    width: usize,
+    height: usize,
+    indices: &[u8],
+    transparent: bool,
+    colours: &Palette,
+) -> Result<()> {
+    let mut palette: Vec<u8> = colours
+        .iter()
+        .flat_map(|&(r, g, b)| vec![r, g, b])
+        .collect();
+
+    let mut encoder = Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(ColorType::Indexed);
+    encoder.set_depth(BitDepth::Eight);
+    if transparent {
+        palette.extend_from_slice(&[0, 0, 0]);
+        let mut alpha = vec![0xFF; TRANSPARENT as usize];
+        alpha.push(0);
+        encoder.set_trns(alpha);
+    }
+    encoder.set_palette(palette);
+
+    encoder.write_header()?.write_image_data(indices)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_png() -> Result<()> {
+        let image: Vec<u8> = (0..64).collect();
+        let mut png = Vec::new();
+        write_png(&mut png, 8, 8, &image)?;
+
+        let decoder = png::Decoder::new(&png[..]);
+        let mut reader = decoder.read_info()?;
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut decoded)?;
+
+        assert_eq!(reader.info().color_type, ColorType::Indexed);
+        assert_eq!(decoded, image);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_png_layer() -> Result<()> {
+        let image = [Some(0x16), None, Some(0x56), None];
+        let mut png = Vec::new();
+        write_png_layer(&mut png, 2, 2, &image)?;
+
+        let decoder = png::Decoder::new(&png[..]);
+        let mut reader = decoder.read_info()?;
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut decoded)?;
+
+        assert_eq!(decoded, vec![0x16, TRANSPARENT, 0x16, TRANSPARENT]);
+        let alpha = reader.info().trns.as_ref().unwrap();
+        assert_eq!((alpha[0x16], alpha[TRANSPARENT as usize]), (0xFF, 0));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+/// Draws the buttons held on each controller over the picture, for recordings and working on
+/// movies.
+///
+/// Each controller is a small pad in the bottom left: the D-pad, Select and Start, then B and A.
+/// Held buttons are lit, the others dim. Like the font it's drawn onto the frame of palette indices,
+/// so it's filtered and captured along with the game.
+use crate::controller::ControllerState;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::video::font;
+
+/// Colours of held and released buttons, and of their shadow.
+const HELD_COLOUR: u8 = 0x30;
+const RELEASED_COLOUR: u8 = 0x00;
+const SHADOW_COLOUR: u8 = 0x0F;
+
+/// Where each button is drawn relative to the pad's top left, as (x, y, width, height).
+const BUTTONS: [(u8, (usize, usize, usize, usize)); 8] = [
+    (ControllerState::UP, (4, 0, 3, 3)),
+    (ControllerState::LEFT, (0, 4, 3, 3)),
+    (ControllerState::RIGHT, (8, 4, 3, 3)),
+    (ControllerState::DOWN, (4, 8, 3, 3)),
+    (ControllerState::SELECT, (14, 5, 4, 2)),
+    (ControllerState::START, (20, 5, 4, 2)),
+    (ControllerState::B, (27, 4, 3, 3)),
+    (ControllerState::A, (32, 4, 3, 3)),
+];
+
+/// Size of a pad.
+const PAD_WIDTH: usize = 35;
+const PAD_HEIGHT: usize = 11;
+
+/// The pads sit above the bottom 8 scanlines, which most televisions hide.
+const MARGIN: usize = 8;
+
+/// Room left of each pad for the player's number.
+const LABEL_WIDTH: usize = font::GLYPH_WIDTH + 3;
+
+/// Horizontal distance between the players' pads.
+const PAD_SPACING: usize = LABEL_WIDTH + PAD_WIDTH + 8;
+
+/// Draw a pad for each controller, player 1 first.
+pub fn draw_input(frame: &mut [u8], states: &[ControllerState]) {
+    let top = SCREEN_HEIGHT - MARGIN - PAD_HEIGHT;
+
+    for (player, state) in states.iter().enumerate() {
+        let left = MARGIN + player * PAD_SPACING;
+        let label_top = top + (PAD_HEIGHT - font::GLYPH_HEIGHT) / 2;
+        font::draw_text(
+            frame,
+            left as i32,
+            label_top as i32,
+            &(player + 1).to_string(),
+        );
+
+        for &(button, (x, y, width, height)) in &BUTTONS {
+            let (x, y) = (left + LABEL_WIDTH + x, top + y);
+            let colour = if state.0 & button != 0 {
+                HELD_COLOUR
+            } else {
+                RELEASED_COLOUR
+            };
+            fill(frame, x + 1, y + 1, width, height, SHADOW_COLOUR);
+            fill(frame, x, y, width, height, colour);
+        }
+    }
+}
+
+/// Fill a rectangle, clipped to the screen.
+fn fill(frame: &mut [u8], x: usize, y: usize, width: usize, height: usize, colour: u8) {
+    for py in y..(y + height).min(SCREEN_HEIGHT) {
+        for px in x..(x + width).min(SCREEN_WIDTH) {
+            frame[py * SCREEN_WIDTH + px] = colour;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_input() {
+        let mut frame = vec![0x21; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let held = ControllerState(ControllerState::A | ControllerState::LEFT);
+        draw_input(&mut frame, &[held, ControllerState::default()]);
+
+        let top = SCREEN_HEIGHT - MARGIN - PAD_HEIGHT;
+        let pixel = |player: usize, (x, y): (usize, usize)| {
+            frame[(top + y) * SCREEN_WIDTH + MARGIN + player * PAD_SPACING + LABEL_WIDTH + x]
+        };
+
+        // A and left are lit on player 1's pad only, B isn't.
+        assert_eq!(pixel(0, (33, 5)), HELD_COLOUR);
+        assert_eq!(pixel(0, (1, 5)), HELD_COLOUR);
+        assert_eq!(pixel(0, (28, 5)), RELEASED_COLOUR);
+        assert_eq!(pixel(1, (33, 5)), RELEASED_COLOUR);
+
+        // Shadowed, and the rest of the frame is left alone.
+        assert_eq!(pixel(0, (35, 7)), SHADOW_COLOUR);
+        assert_eq!(frame[0], 0x21);
+    }
+}
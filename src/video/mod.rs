@@ -0,0 +1,109 @@
+/// Conversion of the PPU frame buffer into pictures to display.
+pub mod capture;
+pub mod font;
+pub mod image;
+pub mod input_display;
+mod ntsc;
+pub mod palette;
+
+use crate::ppu::{self, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::str::FromStr;
+
+pub use ntsc::NtscFilter;
+
+/// How frames are converted from palette indices to RGBA.
+pub enum VideoFilter {
+    /// Look up each pixel in the palette, native resolution.
+    Rgb,
+
+    /// Simulate the composite video signal.
+    Ntsc(Box<NtscFilter>),
+
+    /// Look up each pixel in another palette, e.g. an RGB PPU's or one picked by the user.
+    Palette(Box<ppu::Palette>),
+}
+
+impl VideoFilter {
+    /// Dimensions of the filtered picture.
+    pub fn output_size(&self) -> (usize, usize) {
+        match self {
+            VideoFilter::Rgb | VideoFilter::Palette(_) => (SCREEN_WIDTH, SCREEN_HEIGHT),
+            VideoFilter::Ntsc(filter) => (filter.width(), SCREEN_HEIGHT),
+        }
+    }
+
+    /// Convert a frame of palette indices into a RGBA picture of `output_size()`.
+    pub fn apply(&mut self, frame: &[u8]) -> Vec<u8> {
+        match self {
+            VideoFilter::Rgb => ppu::to_rgba(frame),
+            VideoFilter::Ntsc(filter) => filter.apply(frame).to_vec(),
+            VideoFilter::Palette(palette) => ppu::to_rgba_with(frame, palette),
+        }
+    }
+
+    /// Colours of the picture, none for the NTSC filter which decodes them from the signal.
+    pub fn palette(&self) -> Option<&ppu::Palette> {
+        match self {
+            VideoFilter::Rgb => Some(&ppu::SYSTEM_PALETTE),
+            VideoFilter::Ntsc(_) => None,
+            VideoFilter::Palette(palette) => Some(palette),
+        }
+    }
+
+    /// Draw with other colours from the next frame on. The NTSC filter decodes its colours from
+    /// the signal, it returns false and is left as it is.
+    pub fn set_palette(&mut self, palette: ppu::Palette) -> bool {
+        match self {
+            VideoFilter::Rgb | VideoFilter::Palette(_) => {
+                *self = VideoFilter::Palette(Box::new(palette));
+                true
+            }
+            VideoFilter::Ntsc(_) => false,
+        }
+    }
+}
+
+impl FromStr for VideoFilter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "rgb" => Ok(VideoFilter::Rgb),
+            "ntsc" => Ok(VideoFilter::Ntsc(Box::new(NtscFilter::new(
+                ntsc::DEFAULT_OUTPUT_WIDTH,
+            )))),
+            _ => Err(format!("Unknown video filter \"{}\".", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_size() {
+        let frame = vec![0x16; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        for name in &["rgb", "ntsc"] {
+            let mut filter = VideoFilter::from_str(name).unwrap();
+            let (width, height) = filter.output_size();
+            assert_eq!(filter.apply(&frame).len(), width * height * 4);
+        }
+    }
+
+    #[test]
+    fn test_ntsc_greys() {
+        // Hue 0 has no chroma, the picture should be an even grey.
+        let frame = vec![0x10; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut filter = NtscFilter::new(ntsc::DEFAULT_OUTPUT_WIDTH);
+
+        let rgba = filter.apply(&frame);
+        let center = (120 * ntsc::DEFAULT_OUTPUT_WIDTH + 300) * 4;
+        let (r, g, b) = (rgba[center], rgba[center + 1], rgba[center + 2]);
+
+        assert!(r > 0x40);
+        assert!((r as i32 - g as i32).abs() <= 2);
+        assert!((r as i32 - b as i32).abs() <= 2);
+    }
+}
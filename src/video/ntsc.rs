@@ -0,0 +1,171 @@
+/// NTSC composite video filter.
+///
+/// The PPU outputs a composite signal rather than RGB. This recreates the signal the PPU would
+/// generate for each pixel and decodes it back as a television would, which reproduces the colour
+/// fringing and dot crawl artifacts games were designed around.
+///
+/// Based on http://wiki.nesdev.com/w/index.php/NTSC_video, in the spirit of blargg's nes_ntsc.
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::f32::consts::PI;
+
+/// Signal samples generated per pixel.
+const SAMPLES_PER_PIXEL: usize = 8;
+
+/// The colour subcarrier repeats every 12 samples.
+const SUBCARRIER_PERIOD: usize = 12;
+
+/// Each scanline is 341 dots, which shifts the subcarrier phase by this many samples.
+const SCANLINE_PHASE_SHIFT: usize = 341 * SAMPLES_PER_PIXEL % SUBCARRIER_PERIOD;
+
+/// Output width matching blargg's filter, close to the 8:7 pixel aspect ratio.
+pub const DEFAULT_OUTPUT_WIDTH: usize = 602;
+
+/// Voltage levels relative to sync, the low and high levels of the square wave for each luma.
+pub(super) const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+pub(super) const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+pub(super) const BLACK: f32 = 0.518;
+pub(super) const WHITE: f32 = 1.962;
+
+/// Gamma of the decoded signal compared to a display.
+pub(super) const GAMMA: f32 = 2.2 / 1.8;
+
+pub struct NtscFilter {
+    /// Width of the output picture, the height is unchanged.
+    width: usize,
+
+    /// Normalised signal for every colour and sample phase, indexed by [colour][phase].
+    signal_table: Vec<[f32; SUBCARRIER_PERIOD]>,
+
+    /// Subcarrier used to demodulate the I and Q components, indexed by phase.
+    cos_table: [f32; SUBCARRIER_PERIOD],
+    sin_table: [f32; SUBCARRIER_PERIOD],
+
+    /// Signal samples of the scanline being decoded.
+    scanline: Vec<f32>,
+
+    /// Alternates the starting phase each frame like the real PPU which produces dot crawl.
+    frame_phase: usize,
+
+    /// Decoded RGBA picture.
+    output: Vec<u8>,
+}
+
+impl NtscFilter {
+    pub fn new(width: usize) -> Self {
+        let signal_table = (0..64)
+            .map(|colour| {
+                let mut samples = [0.0; SUBCARRIER_PERIOD];
+                for (phase, sample) in samples.iter_mut().enumerate() {
+                    *sample = (Self::signal(colour, phase) - BLACK) / (WHITE - BLACK);
+                }
+                samples
+            })
+            .collect();
+
+        let mut cos_table = [0.0; SUBCARRIER_PERIOD];
+        let mut sin_table = [0.0; SUBCARRIER_PERIOD];
+        for phase in 0..SUBCARRIER_PERIOD {
+            let angle = 2.0 * PI * phase as f32 / SUBCARRIER_PERIOD as f32;
+            cos_table[phase] = angle.cos();
+            sin_table[phase] = angle.sin();
+        }
+
+        NtscFilter {
+            width,
+            signal_table,
+            cos_table,
+            sin_table,
+            scanline: vec![0.0; SCREEN_WIDTH * SAMPLES_PER_PIXEL],
+            frame_phase: 0,
+            output: vec![0; width * SCREEN_HEIGHT * 4],
+        }
+    }
+
+    /// Width of the output picture.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Signal level the PPU generates for a colour at the given subcarrier phase.
+    fn signal(colour: u8, phase: usize) -> f32 {
+        let hue = (colour & 0x0F) as usize;
+
+        // Hues 14 and 15 are always black.
+        let luma = if hue > 13 { 1 } else { (colour >> 4) as usize };
+
+        let mut low = SIGNAL_LOW[luma];
+        let mut high = SIGNAL_HIGH[luma];
+
+        // Hue 0 is a flat high signal (greys), hues 13 and above are a flat low signal.
+        if hue == 0 {
+            low = high;
+        } else if hue > 12 {
+            high = low;
+        }
+
+        if (hue + phase) % SUBCARRIER_PERIOD < SUBCARRIER_PERIOD / 2 {
+            high
+        } else {
+            low
+        }
+    }
+
+    /// Filter a frame of palette indices into a RGBA picture of `width()` x 240.
+    pub fn apply(&mut self, frame: &[u8]) -> &[u8] {
+        let total_samples = self.scanline.len();
+
+        for y in 0..SCREEN_HEIGHT {
+            let line_phase = self.frame_phase + y * SCANLINE_PHASE_SHIFT;
+
+            // Encode the scanline.
+            for x in 0..SCREEN_WIDTH {
+                let colour = (frame[y * SCREEN_WIDTH + x] & 0x3F) as usize;
+                for sample in 0..SAMPLES_PER_PIXEL {
+                    let phase = (line_phase + x * SAMPLES_PER_PIXEL + sample) % SUBCARRIER_PERIOD;
+                    self.scanline[x * SAMPLES_PER_PIXEL + sample] =
+                        self.signal_table[colour][phase];
+                }
+            }
+
+            // Decode it by demodulating a subcarrier period around each output pixel.
+            for x in 0..self.width {
+                let center = x * total_samples / self.width;
+                let begin = center.saturating_sub(SUBCARRIER_PERIOD / 2);
+                let end = (center + SUBCARRIER_PERIOD / 2).min(total_samples);
+
+                let (mut luma, mut i, mut q) = (0.0, 0.0, 0.0);
+                for p in begin..end {
+                    let level = self.scanline[p] / SUBCARRIER_PERIOD as f32;
+                    let phase = (line_phase + p) % SUBCARRIER_PERIOD;
+
+                    luma += level;
+                    i += level * self.cos_table[phase];
+                    q += level * self.sin_table[phase];
+                }
+
+                let r = luma + 0.946_882 * i + 0.623_557 * q;
+                let g = luma - 0.274_788 * i - 0.635_691 * q;
+                let b = luma - 1.108_545 * i + 1.709_007 * q;
+
+                let pixel = (y * self.width + x) * 4;
+                self.output[pixel..pixel + 4].copy_from_slice(&[
+                    Self::to_component(r),
+                    Self::to_component(g),
+                    Self::to_component(b),
+                    0xFF,
+                ]);
+            }
+        }
+
+        // A frame is an odd number of samples long so the phase shifts every frame.
+        self.frame_phase = (self.frame_phase + SCANLINE_PHASE_SHIFT) % SUBCARRIER_PERIOD;
+
+        &self.output
+    }
+
+    /// Gamma correct and convert to a 8 bit colour component.
+    fn to_component(value: f32) -> u8 {
+        let corrected = if value <= 0.0 { 0.0 } else { value.powf(GAMMA) };
+        (corrected * 255.0).clamp(0.0, 255.0) as u8
+    }
+}
@@ -0,0 +1,537 @@
+/// Palettes the picture can be drawn with, the built-in ones and .pal files.
+///
+/// The PPU outputs a composite signal rather than RGB so there is no one right palette, each is
+/// someone's take on how the colours looked on a television. .pal files hold the 64 colours as RGB
+/// triplets, files with the 8 emphasis variants after them are read too but only the first 64
+/// colours are used.
+use super::ntsc::{BLACK, GAMMA, SIGNAL_HIGH, SIGNAL_LOW, WHITE};
+use crate::ppu::{Palette, SYSTEM_PALETTE};
+use crate::region::Region;
+use anyhow::{anyhow, bail, Result};
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The built-in palettes by name, "default" is `SYSTEM_PALETTE`.
+pub const BUILT_IN: [(&str, &Palette); 4] = [
+    ("default", &SYSTEM_PALETTE),
+    ("nestopia", &NESTOPIA),
+    ("fceux", &FCEUX),
+    ("composite", &COMPOSITE),
+];
+
+/// Nestopia's default palette, from its YUV decoder.
+const NESTOPIA: Palette = [
+    (0x66, 0x66, 0x66),
+    (0x00, 0x2A, 0x88),
+    (0x14, 0x12, 0xA7),
+    (0x3B, 0x00, 0xA4),
+    (0x5C, 0x00, 0x7E),
+    (0x6E, 0x00, 0x40),
+    (0x6C, 0x06, 0x00),
+    (0x56, 0x1D, 0x00),
+    (0x33, 0x35, 0x00),
+    (0x0B, 0x48, 0x00),
+    (0x00, 0x52, 0x00),
+    (0x00, 0x4F, 0x08),
+    (0x00, 0x40, 0x4D),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xAD, 0xAD, 0xAD),
+    (0x15, 0x5F, 0xD9),
+    (0x42, 0x40, 0xFF),
+    (0x75, 0x27, 0xFE),
+    (0xA0, 0x1A, 0xCC),
+    (0xB7, 0x1E, 0x7B),
+    (0xB5, 0x31, 0x20),
+    (0x99, 0x4E, 0x00),
+    (0x6B, 0x6D, 0x00),
+    (0x38, 0x87, 0x00),
+    (0x0C, 0x93, 0x00),
+    (0x00, 0x8F, 0x32),
+    (0x00, 0x7C, 0x8D),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xFF, 0xFE, 0xFF),
+    (0x64, 0xB0, 0xFF),
+    (0x92, 0x90, 0xFF),
+    (0xC6, 0x76, 0xFF),
+    (0xF3, 0x6A, 0xFF),
+    (0xFE, 0x6E, 0xCC),
+    (0xFE, 0x81, 0x70),
+    (0xEA, 0x9E, 0x22),
+    (0xBC, 0xBE, 0x00),
+    (0x88, 0xD8, 0x00),
+    (0x5C, 0xE4, 0x30),
+    (0x45, 0xE0, 0x82),
+    (0x48, 0xCD, 0xDE),
+    (0x4F, 0x4F, 0x4F),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xFF, 0xFE, 0xFF),
+    (0xC0, 0xDF, 0xFF),
+    (0xD3, 0xD2, 0xFF),
+    (0xE8, 0xC8, 0xFF),
+    (0xFB, 0xC2, 0xFF),
+    (0xFE, 0xC4, 0xEA),
+    (0xFE, 0xCC, 0xC5),
+    (0xF7, 0xD8, 0xA5),
+    (0xE4, 0xE5, 0x94),
+    (0xCF, 0xEF, 0x96),
+    (0xBD, 0xF4, 0xAB),
+    (0xB3, 0xF3, 0xCC),
+    (0xB5, 0xEB, 0xF2),
+    (0xB8, 0xB8, 0xB8),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+];
+
+/// FCEUX's default palette, brighter and more saturated.
+const FCEUX: Palette = [
+    (0x74, 0x74, 0x74),
+    (0x24, 0x18, 0x8C),
+    (0x00, 0x00, 0xA8),
+    (0x44, 0x00, 0x9C),
+    (0x8C, 0x00, 0x74),
+    (0xA8, 0x00, 0x10),
+    (0xA4, 0x00, 0x00),
+    (0x7C, 0x08, 0x00),
+    (0x40, 0x2C, 0x00),
+    (0x00, 0x44, 0x00),
+    (0x00, 0x50, 0x00),
+    (0x00, 0x3C, 0x14),
+    (0x18, 0x3C, 0x5C),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xBC, 0xBC, 0xBC),
+    (0x00, 0x70, 0xEC),
+    (0x20, 0x38, 0xEC),
+    (0x80, 0x00, 0xF0),
+    (0xBC, 0x00, 0xBC),
+    (0xE4, 0x00, 0x58),
+    (0xD8, 0x28, 0x00),
+    (0xC8, 0x4C, 0x0C),
+    (0x88, 0x70, 0x00),
+    (0x00, 0x94, 0x00),
+    (0x00, 0xA8, 0x00),
+    (0x00, 0x90, 0x38),
+    (0x00, 0x80, 0x88),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xFC, 0xFC, 0xFC),
+    (0x3C, 0xBC, 0xFC),
+    (0x5C, 0x94, 0xFC),
+    (0xCC, 0x88, 0xFC),
+    (0xF4, 0x78, 0xFC),
+    (0xFC, 0x74, 0xB4),
+    (0xFC, 0x74, 0x60),
+    (0xFC, 0x98, 0x38),
+    (0xF0, 0xBC, 0x3C),
+    (0x80, 0xD0, 0x10),
+    (0x4C, 0xDC, 0x48),
+    (0x58, 0xF8, 0x98),
+    (0x00, 0xE8, 0xD8),
+    (0x78, 0x78, 0x78),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xFC, 0xFC, 0xFC),
+    (0xA8, 0xE4, 0xFC),
+    (0xC4, 0xD4, 0xFC),
+    (0xD4, 0xC8, 0xFC),
+    (0xFC, 0xC4, 0xFC),
+    (0xFC, 0xC4, 0xD8),
+    (0xFC, 0xBC, 0xB0),
+    (0xFC, 0xD8, 0xA8),
+    (0xFC, 0xE4, 0xA0),
+    (0xE0, 0xFC, 0xA0),
+    (0xA8, 0xF0, 0xBC),
+    (0xB0, 0xFC, 0xCC),
+    (0x9C, 0xFC, 0xF0),
+    (0xC4, 0xC4, 0xC4),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+];
+
+/// Decoded from the composite signal levels of the NTSC filter, with the colour burst in phase with
+/// hue 8. Darker and less saturated than the others, like on a television without its colour
+/// turned up. `PaletteGenerator`'s NTSC defaults make the same colours.
+const COMPOSITE: Palette = [
+    (0x53, 0x53, 0x53),
+    (0x06, 0x16, 0x5B),
+    (0x17, 0x0B, 0x67),
+    (0x2B, 0x05, 0x5B),
+    (0x3B, 0x03, 0x3C),
+    (0x41, 0x06, 0x17),
+    (0x3B, 0x0E, 0x00),
+    (0x2B, 0x19, 0x00),
+    (0x17, 0x25, 0x00),
+    (0x06, 0x2E, 0x00),
+    (0x00, 0x30, 0x00),
+    (0x00, 0x2C, 0x17),
+    (0x00, 0x22, 0x3C),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xA0, 0xA0, 0xA0),
+    (0x28, 0x42, 0xAC),
+    (0x45, 0x30, 0xBE),
+    (0x64, 0x25, 0xAC),
+    (0x7D, 0x21, 0x7E),
+    (0x86, 0x27, 0x45),
+    (0x7D, 0x35, 0x13),
+    (0x64, 0x48, 0x00),
+    (0x45, 0x5A, 0x00),
+    (0x28, 0x68, 0x00),
+    (0x14, 0x6C, 0x13),
+    (0x0E, 0x65, 0x45),
+    (0x14, 0x55, 0x7E),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xFF, 0xFF, 0xFF),
+    (0x75, 0x94, 0xFF),
+    (0x98, 0x7F, 0xFF),
+    (0xBC, 0x71, 0xFF),
+    (0xD8, 0x6D, 0xD9),
+    (0xE2, 0x74, 0x98),
+    (0xD8, 0x85, 0x5B),
+    (0xBC, 0x9B, 0x32),
+    (0x98, 0xB1, 0x25),
+    (0x75, 0xC0, 0x32),
+    (0x5C, 0xC5, 0x5B),
+    (0x54, 0xBD, 0x98),
+    (0x5C, 0xAB, 0xD9),
+    (0x3C, 0x3C, 0x3C),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+    (0xFF, 0xFF, 0xFF),
+    (0xC4, 0xD2, 0xFF),
+    (0xD3, 0xC9, 0xFF),
+    (0xE3, 0xC2, 0xFF),
+    (0xEF, 0xC0, 0xEF),
+    (0xF3, 0xC3, 0xD3),
+    (0xEF, 0xCB, 0xB8),
+    (0xE3, 0xD5, 0xA4),
+    (0xD3, 0xDE, 0x9D),
+    (0xC4, 0xE5, 0xA4),
+    (0xB9, 0xE7, 0xB8),
+    (0xB5, 0xE3, 0xD3),
+    (0xB9, 0xDC, 0xEF),
+    (0xA9, 0xA9, 0xA9),
+    (0x00, 0x00, 0x00),
+    (0x00, 0x00, 0x00),
+];
+
+/// Size of a .pal file with the 64 colours, and with their 8 emphasis variants.
+const PAL_SIZE: usize = 64 * 3;
+const PAL_SIZE_WITH_EMPHASIS: usize = PAL_SIZE * 8;
+
+/// Where a palette comes from, one of `BUILT_IN` by name, a .pal file or made by a
+/// `PaletteGenerator`, named "generated".
+#[derive(Clone, Debug, PartialEq)]
+pub enum PaletteSource {
+    BuiltIn(usize),
+    File(PathBuf),
+    Generated(PaletteGenerator),
+}
+
+impl PaletteSource {
+    /// The colours, read from the file every time so changes to it show up when switching back.
+    pub fn load(&self) -> Result<Palette> {
+        match self {
+            PaletteSource::BuiltIn(index) => Ok(*BUILT_IN[*index].1),
+            PaletteSource::File(path) => {
+                let bytes = std::fs::read(path)
+                    .map_err(|err| anyhow!("Failed to read \"{}\": {}", path.display(), err))?;
+                parse_pal(&bytes)
+            }
+            PaletteSource::Generated(generator) => Ok(generator.generate()),
+        }
+    }
+}
+
+impl FromStr for PaletteSource {
+    type Err = String;
+
+    /// A built-in palette's name, "generated" with the NTSC defaults or the path of a .pal file.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("generated") {
+            return Ok(PaletteSource::Generated(PaletteGenerator::new(
+                Region::Ntsc,
+            )));
+        }
+
+        if let Some(index) = BUILT_IN
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(PaletteSource::BuiltIn(index));
+        }
+
+        if s.to_lowercase().ends_with(".pal") {
+            return Ok(PaletteSource::File(PathBuf::from(s)));
+        }
+
+        let names: Vec<&str> = BUILT_IN.iter().map(|(name, _)| *name).collect();
+        Err(format!(
+            "Unknown palette \"{}\", expected {}, generated or a .pal file.",
+            s,
+            names.join(", ")
+        ))
+    }
+}
+
+impl fmt::Display for PaletteSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaletteSource::BuiltIn(index) => f.write_str(BUILT_IN[*index].0),
+            PaletteSource::File(path) => write!(f, "{}", path.display()),
+            PaletteSource::Generated(_) => f.write_str("generated"),
+        }
+    }
+}
+
+/// Read the colours of a .pal file.
+pub fn parse_pal(bytes: &[u8]) -> Result<Palette> {
+    if bytes.len() != PAL_SIZE && bytes.len() != PAL_SIZE_WITH_EMPHASIS {
+        bail!(
+            "A .pal file is {} or {} bytes, got {}.",
+            PAL_SIZE,
+            PAL_SIZE_WITH_EMPHASIS,
+            bytes.len()
+        );
+    }
+
+    let mut palette = [(0, 0, 0); 64];
+    for (colour, rgb) in palette.iter_mut().zip(bytes.chunks(3)) {
+        *colour = (rgb[0], rgb[1], rgb[2]);
+    }
+
+    Ok(palette)
+}
+
+/// Makes the 64 colours from the signal the PPU generates for each, the way a television decodes
+/// it, with the knobs of its picture controls. See https://www.nesdev.org/wiki/NTSC_video.
+///
+/// Each colour is a square wave between two of the PPU's voltage levels, its average is the
+/// brightness and its phase against the colour burst the hue. Hue 0 is a flat high level, a grey,
+/// and hues 13 and up a flat low one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaletteGenerator {
+    /// Turns every hue, in degrees.
+    pub hue: f64,
+
+    /// Scales the colour, 0 for greys.
+    pub saturation: f64,
+
+    /// Added to every level, black is 0 and white 1.
+    pub brightness: f64,
+
+    /// Scales every level away from black.
+    pub contrast: f64,
+
+    /// The decoded levels are raised to this power for the display, above 1 darkens the mid tones.
+    pub gamma: f64,
+}
+
+/// The hues are 12 steps around the colour wheel.
+const HUE_STEP: f64 = 30.0;
+
+/// Hue 8 is in phase with the colour burst, which sits at 180° from the B-Y axis.
+const BURST_HUE: u8 = 8;
+const BURST_ANGLE: f64 = 180.0;
+
+/// PAL PPUs generate each hue half a step from where an NTSC PPU does.
+const PAL_HUE_SHIFT: f64 = -HUE_STEP / 2.0;
+
+impl PaletteGenerator {
+    /// The knobs left alone for the region's PPU. Dendies use a PAL-like PPU too.
+    pub fn new(region: Region) -> Self {
+        PaletteGenerator {
+            hue: match region {
+                Region::Ntsc => 0.0,
+                Region::Pal | Region::Dendy => PAL_HUE_SHIFT,
+            },
+            saturation: 1.0,
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: GAMMA as f64,
+        }
+    }
+
+    pub fn generate(&self) -> Palette {
+        let mut palette = [(0, 0, 0); 64];
+        for (colour, rgb) in palette.iter_mut().enumerate() {
+            *rgb = self.colour(colour as u8);
+        }
+        palette
+    }
+
+    fn colour(&self, colour: u8) -> (u8, u8, u8) {
+        let hue = colour & 0x0F;
+
+        // Hues 14 and 15 are always black.
+        let luma = if hue > 13 { 1 } else { (colour >> 4) as usize };
+        let level = |volts: f32| (volts - BLACK) as f64 / (WHITE - BLACK) as f64;
+        let (low, high) = match hue {
+            0 => (level(SIGNAL_HIGH[luma]), level(SIGNAL_HIGH[luma])),
+            1..=12 => (level(SIGNAL_LOW[luma]), level(SIGNAL_HIGH[luma])),
+            _ => (level(SIGNAL_LOW[luma]), level(SIGNAL_LOW[luma])),
+        };
+
+        // The square wave's average, and its fundamental as demodulated against the subcarrier.
+        let y = (low + high) / 2.0 * self.contrast + self.brightness;
+        let chroma = (high - low) / PI * self.saturation * self.contrast;
+        let angle =
+            (BURST_ANGLE + (hue as f64 - BURST_HUE as f64) * HUE_STEP + self.hue).to_radians();
+        let (u, v) = (chroma * angle.cos(), chroma * angle.sin());
+
+        let r = y + 1.140 * v;
+        let g = y - 0.395 * u - 0.581 * v;
+        let b = y + 2.032 * u;
+
+        (self.component(r), self.component(g), self.component(b))
+    }
+
+    /// Gamma correct and convert to a 8 bit colour component.
+    fn component(&self, value: f64) -> u8 {
+        let corrected = if value <= 0.0 {
+            0.0
+        } else {
+            value.powf(self.gamma)
+        };
+        (corrected * 255.0).round().clamp(0.0, 255.0) as u8
+    }
+}
+
+/// The palettes switched between while running, the built-in ones then the file or generated
+/// palette given if any.
+pub struct PaletteCycle {
+    sources: Vec<PaletteSource>,
+    current: usize,
+}
+
+impl PaletteCycle {
+    /// Start from `current`, or the default palette.
+    pub fn new(current: Option<PaletteSource>) -> Self {
+        let mut sources: Vec<PaletteSource> =
+            (0..BUILT_IN.len()).map(PaletteSource::BuiltIn).collect();
+        if let Some(source @ (PaletteSource::File(_) | PaletteSource::Generated(_))) = &current {
+            sources.push(source.clone());
+        }
+
+        let current = current
+            .and_then(|current| sources.iter().position(|source| *source == current))
+            .unwrap_or(0);
+
+        PaletteCycle { sources, current }
+    }
+
+    /// Switch to the next palette and load it.
+    pub fn switch(&mut self) -> Result<(&PaletteSource, Palette)> {
+        self.current = (self.current + 1) % self.sources.len();
+        let source = &self.sources[self.current];
+        Ok((source, source.load()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("nestopia".parse(), Ok(PaletteSource::BuiltIn(1)));
+        assert_eq!("FCEUX".parse(), Ok(PaletteSource::BuiltIn(2)));
+        assert_eq!(
+            "palettes/smooth.pal".parse(),
+            Ok(PaletteSource::File(PathBuf::from("palettes/smooth.pal")))
+        );
+        assert!("vivid".parse::<PaletteSource>().is_err());
+
+        // Black and white are where every palette has them.
+        for (name, palette) in &BUILT_IN {
+            assert!(palette[0x0F].0 < 0x10, "{}", name);
+            assert!(palette[0x20].0 > 0xF0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_parse_pal() -> Result<()> {
+        let mut bytes: Vec<u8> = (0..PAL_SIZE_WITH_EMPHASIS).map(|i| i as u8).collect();
+        let palette = parse_pal(&bytes)?;
+        assert_eq!(palette[0], (0, 1, 2));
+        assert_eq!(palette[63], (189, 190, 191));
+
+        assert_eq!(parse_pal(&bytes[..PAL_SIZE])?, palette);
+        bytes.push(0);
+        assert!(parse_pal(&bytes).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generator() {
+        let ntsc = PaletteGenerator::new(Region::Ntsc).generate();
+        for (generated, table) in ntsc.iter().zip(COMPOSITE.iter()) {
+            let close = |a: u8, b: u8| (a as i16 - b as i16).abs() <= 1;
+            assert!(
+                close(generated.0, table.0)
+                    && close(generated.1, table.1)
+                    && close(generated.2, table.2),
+                "{:?} {:?}",
+                generated,
+                table
+            );
+        }
+
+        let greys = PaletteGenerator {
+            saturation: 0.0,
+            ..PaletteGenerator::new(Region::Ntsc)
+        }
+        .generate();
+        assert!(greys.iter().all(|&(r, g, b)| r == g && g == b));
+
+        let brighter = PaletteGenerator {
+            brightness: 0.1,
+            ..PaletteGenerator::new(Region::Ntsc)
+        }
+        .generate();
+        assert!(brighter[0x00].0 > ntsc[0x00].0);
+
+        let pal = PaletteGenerator::new(Region::Pal).generate();
+        assert_ne!(pal[0x16], ntsc[0x16]);
+        assert_eq!(pal[0x10], ntsc[0x10]);
+
+        assert!(matches!(
+            "generated".parse(),
+            Ok(PaletteSource::Generated(_))
+        ));
+    }
+
+    #[test]
+    fn test_cycle() -> Result<()> {
+        let mut cycle = PaletteCycle::new(Some(PaletteSource::BuiltIn(2)));
+        assert_eq!(cycle.switch()?.0, &PaletteSource::BuiltIn(3));
+        assert_eq!(
+            cycle.switch()?,
+            (&PaletteSource::BuiltIn(0), SYSTEM_PALETTE)
+        );
+
+        // A file comes after the built-in palettes.
+        let file = PaletteSource::File(PathBuf::from("missing.pal"));
+        let mut cycle = PaletteCycle::new(Some(file.clone()));
+        assert_eq!(cycle.switch()?.0, &PaletteSource::BuiltIn(0));
+        for _ in 0..3 {
+            cycle.switch()?;
+        }
+        assert!(cycle.switch().is_err());
+
+        Ok(())
+    }
+}
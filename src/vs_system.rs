@@ -0,0 +1,289 @@
+use crate::ppu::Palette;
+/// Nintendo's VS UniSystem, the arcade cabinets running NES games with a coin slot and DIP switches
+/// for the operator's settings. See https://www.nesdev.org/wiki/Vs._System.
+///
+/// The cabinets used RGB PPUs instead of the NES's composite one. Each model has its own colours,
+/// and some scramble them or swap registers so a game only runs on the PPU it was sold with.
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// Colours of the RGB PPUs, 3 bits per channel written as octal digits, e.g. 0o700 is red.
+const RGB_PALETTE: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022,
+    0o000, 0o000, 0o000, 0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140,
+    0o040, 0o053, 0o044, 0o000, 0o000, 0o000, 0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740,
+    0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000, 0o777, 0o567, 0o657, 0o757,
+    0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+/// Colours of the RP2C04-0001 to -0004, the same colours in a different order on each.
+const RP2C04_PALETTES: [[u16; 64]; 4] = [
+    [
+        0o755, 0o637, 0o700, 0o447, 0o044, 0o120, 0o222, 0o704, 0o777, 0o333, 0o750, 0o503, 0o403,
+        0o660, 0o320, 0o777, 0o357, 0o653, 0o310, 0o360, 0o467, 0o657, 0o764, 0o027, 0o760, 0o276,
+        0o000, 0o200, 0o666, 0o444, 0o707, 0o014, 0o003, 0o567, 0o757, 0o070, 0o077, 0o022, 0o053,
+        0o507, 0o000, 0o420, 0o747, 0o510, 0o407, 0o006, 0o740, 0o000, 0o000, 0o140, 0o555, 0o031,
+        0o572, 0o326, 0o770, 0o630, 0o020, 0o036, 0o040, 0o111, 0o773, 0o737, 0o430, 0o473,
+    ],
+    [
+        0o000, 0o750, 0o430, 0o572, 0o473, 0o737, 0o044, 0o567, 0o700, 0o407, 0o773, 0o747, 0o777,
+        0o637, 0o467, 0o040, 0o020, 0o357, 0o510, 0o666, 0o053, 0o360, 0o200, 0o447, 0o222, 0o707,
+        0o003, 0o276, 0o657, 0o320, 0o000, 0o326, 0o403, 0o764, 0o740, 0o757, 0o036, 0o310, 0o555,
+        0o006, 0o507, 0o760, 0o333, 0o120, 0o027, 0o000, 0o660, 0o777, 0o653, 0o111, 0o070, 0o630,
+        0o022, 0o014, 0o704, 0o140, 0o000, 0o077, 0o420, 0o770, 0o755, 0o503, 0o031, 0o444,
+    ],
+    [
+        0o507, 0o737, 0o473, 0o555, 0o040, 0o777, 0o567, 0o120, 0o014, 0o000, 0o764, 0o320, 0o704,
+        0o666, 0o653, 0o467, 0o447, 0o044, 0o503, 0o027, 0o140, 0o430, 0o630, 0o053, 0o333, 0o326,
+        0o000, 0o006, 0o700, 0o510, 0o747, 0o755, 0o637, 0o020, 0o003, 0o770, 0o111, 0o750, 0o740,
+        0o777, 0o360, 0o403, 0o357, 0o707, 0o036, 0o444, 0o000, 0o310, 0o077, 0o200, 0o572, 0o757,
+        0o420, 0o070, 0o660, 0o222, 0o031, 0o000, 0o657, 0o773, 0o407, 0o276, 0o760, 0o022,
+    ],
+    [
+        0o430, 0o326, 0o044, 0o660, 0o000, 0o755, 0o014, 0o630, 0o555, 0o310, 0o070, 0o003, 0o764,
+        0o770, 0o040, 0o572, 0o737, 0o200, 0o027, 0o747, 0o000, 0o222, 0o510, 0o740, 0o653, 0o053,
+        0o447, 0o140, 0o403, 0o000, 0o473, 0o357, 0o503, 0o031, 0o420, 0o006, 0o407, 0o507, 0o333,
+        0o704, 0o022, 0o666, 0o036, 0o020, 0o111, 0o773, 0o444, 0o707, 0o757, 0o777, 0o320, 0o700,
+        0o760, 0o276, 0o777, 0o467, 0o000, 0o750, 0o637, 0o567, 0o360, 0o657, 0o077, 0o120,
+    ],
+];
+
+/// IDs the RC2C05-01 to -04 return in PPUSTATUS, games check them. The -05 has none.
+const RC2C05_IDS: [Option<u8>; 5] = [Some(0x1B), Some(0x3D), Some(0x1C), Some(0x1B), None];
+
+/// The cabinet's PPU.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum VsPpu {
+    /// RP2C03 and RC2C03, the NES's colours in RGB.
+    Rp2c03,
+
+    /// RP2C04-0001 to -0004, numbered from 1.
+    Rp2c04(u8),
+
+    /// RC2C05-01 to -05, numbered from 1: PPUCTRL and PPUMASK swap places and PPUSTATUS holds an
+    /// ID.
+    Rc2c05(u8),
+}
+
+impl VsPpu {
+    /// From the low nibble of byte 13 of a NES 2.0 header.
+    pub fn from_header(value: u8) -> Option<Self> {
+        match value & 0x0F {
+            0 | 1 | 6 | 7 => Some(VsPpu::Rp2c03),
+            model @ 2..=5 => Some(VsPpu::Rp2c04(model - 1)),
+            model @ 8..=12 => Some(VsPpu::Rc2c05(model - 7)),
+            _ => None,
+        }
+    }
+
+    /// RGB of each colour index.
+    pub fn palette(&self) -> Palette {
+        let colours = match *self {
+            VsPpu::Rp2c04(model) => &RP2C04_PALETTES[model as usize - 1],
+            VsPpu::Rp2c03 | VsPpu::Rc2c05(_) => &RGB_PALETTE,
+        };
+
+        let channel = |value: u16| ((value & 0o7) * 255 / 7) as u8;
+        let mut palette = [(0, 0, 0); 64];
+        for (rgb, &colour) in palette.iter_mut().zip(colours) {
+            *rgb = (channel(colour >> 6), channel(colour >> 3), channel(colour));
+        }
+        palette
+    }
+
+    /// What replaces the low 6 bits of PPUSTATUS, sprite overflow included.
+    pub(crate) fn status_id(&self) -> Option<u8> {
+        match *self {
+            VsPpu::Rc2c05(model) => RC2C05_IDS[model as usize - 1],
+            _ => None,
+        }
+    }
+
+    pub(crate) fn swaps_ctrl_and_mask(&self) -> bool {
+        matches!(self, VsPpu::Rc2c05(_))
+    }
+}
+
+impl FromStr for VsPpu {
+    type Err = String;
+
+    /// "2c03", "2c04-1" to "2c04-4" or "2c05-1" to "2c05-5".
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Unknown VS System PPU \"{}\", expected 2c03, 2c04-1 to 4 or 2c05-1 to 5.",
+                name
+            )
+        };
+
+        let name = name.to_ascii_lowercase();
+        let (chip, model) = match name.split_once('-') {
+            Some((chip, model)) => (chip, Some(model.parse::<u8>().map_err(|_| invalid())?)),
+            None => (name.as_str(), None),
+        };
+        match (
+            chip.trim_start_matches("rp").trim_start_matches("rc"),
+            model,
+        ) {
+            ("2c03", None) => Ok(VsPpu::Rp2c03),
+            ("2c04", Some(model @ 1..=4)) => Ok(VsPpu::Rp2c04(model)),
+            ("2c05", Some(model @ 1..=5)) => Ok(VsPpu::Rc2c05(model)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for VsPpu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VsPpu::Rp2c03 => write!(f, "RP2C03"),
+            VsPpu::Rp2c04(model) => write!(f, "RP2C04-000{}", model),
+            VsPpu::Rc2c05(model) => write!(f, "RC2C05-0{}", model),
+        }
+    }
+}
+
+/// The cabinet's inputs besides the controllers, read through $4016 and $4017.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct VsSystem {
+    pub ppu: VsPpu,
+
+    /// Switch 1 in bit 0 to switch 8 in bit 7, on when set. Their meaning is up to the game, e.g.
+    /// the difficulty or the coins per credit.
+    pub dip_switches: u8,
+
+    /// The operator's button adding a credit without a coin.
+    pub service: bool,
+
+    /// Cycle until which each coin slot's switch is closed.
+    coins: [u64; 2],
+}
+
+impl VsSystem {
+    /// How long a coin takes to drop past the switch, about 4 frames. Games ignore shorter or
+    /// longer pulses.
+    pub const COIN_CYCLES: u64 = 4 * 29781;
+
+    const SERVICE_MASK: u8 = 0b0000_0100;
+    const COIN_MASKS: [u8; 2] = [0b0010_0000, 0b0100_0000];
+
+    pub fn new(ppu: VsPpu) -> Self {
+        VsSystem {
+            ppu,
+            dip_switches: 0,
+            service: false,
+            coins: [0; 2],
+        }
+    }
+
+    /// Drop a coin in the first or second slot, `cycles` being the CPU's.
+    pub fn insert_coin(&mut self, slot: usize, cycles: u64) {
+        self.coins[slot] = cycles + Self::COIN_CYCLES;
+    }
+
+    /// Bits of $4016 besides the first controller's: the service button, DIP switches 1 and 2
+    /// and the coins.
+    pub(crate) fn read_4016(&self, cycles: u64) -> u8 {
+        let mut value = (self.dip_switches & 0b11) << 3;
+        if self.service {
+            value |= Self::SERVICE_MASK;
+        }
+        for (&until, mask) in self.coins.iter().zip(Self::COIN_MASKS) {
+            if cycles < until {
+                value |= mask;
+            }
+        }
+        value
+    }
+
+    /// Bits of $4017 besides the second controller's: DIP switches 3 to 8.
+    pub(crate) fn read_4017(&self) -> u8 {
+        self.dip_switches & 0b1111_1100
+    }
+}
+
+/// Settings of the DIP switches, as in `VsSystem::dip_switches`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DipSwitches(pub u8);
+
+impl FromStr for DipSwitches {
+    type Err = String;
+
+    /// "1" for on and "0" for off, switch 1 first, e.g. "01000000" only turns switch 2 on.
+    /// Switches left out are off.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.len() > 8 {
+            return Err(format!("Expected at most 8 DIP switches in \"{}\".", value));
+        }
+
+        value
+            .chars()
+            .enumerate()
+            .try_fold(0, |switches, (i, switch)| match switch {
+                '0' => Ok(switches),
+                '1' => Ok(switches | 1 << i),
+                _ => Err(format!(
+                    "Invalid DIP switch \"{}\" in \"{}\", expected 0 or 1.",
+                    switch, value
+                )),
+            })
+            .map(DipSwitches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palettes() {
+        let palette = VsPpu::Rp2c03.palette();
+        assert_eq!(palette[0x16], (0xFF, 0, 0));
+        assert_eq!(palette[0x30], (0xFF, 0xFF, 0xFF));
+        assert_eq!(palette[0x01], (0, 0x24, 0x91));
+
+        // The RP2C04s share their colours in a different order.
+        let mut sorted = RP2C04_PALETTES[0];
+        sorted.sort_unstable();
+        for colours in &RP2C04_PALETTES[1..] {
+            let mut colours = *colours;
+            colours.sort_unstable();
+            assert_eq!(colours, sorted);
+        }
+        assert_eq!(VsPpu::Rp2c04(1).palette()[0x02], (0xFF, 0, 0));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("2c03".parse(), Ok(VsPpu::Rp2c03));
+        assert_eq!("RP2C04-3".parse(), Ok(VsPpu::Rp2c04(3)));
+        assert_eq!("2c05-5".parse(), Ok(VsPpu::Rc2c05(5)));
+        assert!("2c04-5".parse::<VsPpu>().is_err());
+        assert!("2c02".parse::<VsPpu>().is_err());
+
+        assert_eq!(VsPpu::from_header(0x03), Some(VsPpu::Rp2c04(2)));
+        assert_eq!(VsPpu::from_header(0x08), Some(VsPpu::Rc2c05(1)));
+        assert_eq!(VsPpu::Rc2c05(1).to_string(), "RC2C05-01");
+
+        assert_eq!("01000001".parse(), Ok(DipSwitches(0x82)));
+        assert_eq!("1".parse(), Ok(DipSwitches(0x01)));
+        assert!("000000001".parse::<DipSwitches>().is_err());
+        assert!("2".parse::<DipSwitches>().is_err());
+    }
+
+    #[test]
+    fn test_inputs() {
+        let mut vs = VsSystem::new(VsPpu::Rp2c03);
+        vs.dip_switches = 0b1000_0011;
+        assert_eq!(vs.read_4016(0), 0b0001_1000);
+        assert_eq!(vs.read_4017(), 0b1000_0000);
+
+        vs.insert_coin(1, 100);
+        vs.service = true;
+        assert_eq!(vs.read_4016(100), 0b0101_1100);
+        assert_eq!(vs.read_4016(100 + VsSystem::COIN_CYCLES) & 0b0110_0000, 0);
+    }
+}
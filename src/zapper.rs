@@ -0,0 +1,130 @@
+/// Zapper light gun, read through $4017 in place of the second controller.
+///
+/// The gun's photodiode sees the spot it is aimed at as the electron beam draws it, and keeps
+/// reporting light for a short while after. Games flash white targets and check the sensor.
+/// See http://wiki.nesdev.com/w/index.php/Zapper.
+use crate::ppu::{Ppu, SCREEN_HEIGHT, SCREEN_WIDTH, SYSTEM_PALETTE};
+
+#[derive(Default)]
+pub struct Zapper {
+    /// Point on the screen the gun is aimed at, `None` when aimed away from the screen.
+    position: Option<(usize, usize)>,
+
+    trigger: bool,
+}
+
+impl Zapper {
+    /// The upper bits keep the last value on the bus.
+    const OPEN_BUS: u8 = 0x40;
+
+    /// Cleared while light is sensed.
+    const LIGHT_NOT_SENSED_MASK: u8 = 0b0000_1000;
+
+    /// Set while the trigger is pulled.
+    const TRIGGER_MASK: u8 = 0b0001_0000;
+
+    /// Scanlines the sensor keeps reporting light after the beam passes.
+    const LIGHT_SCANLINES: usize = 26;
+
+    /// Pixels around the aimed point the sensor sees.
+    const SENSOR_RADIUS: usize = 2;
+
+    /// Luminance a pixel needs to register, only bright colours do.
+    const LIGHT_THRESHOLD: u32 = 0xA0;
+
+    pub fn set_position(&mut self, position: Option<(usize, usize)>) {
+        self.position = position;
+    }
+
+    pub fn set_trigger(&mut self, trigger: bool) {
+        self.trigger = trigger;
+    }
+
+    /// Value read from $4017, the Zapper has no side effects on reads.
+    pub fn read(&self, ppu: &Ppu) -> u8 {
+        let light = if self.senses_light(ppu) {
+            0
+        } else {
+            Self::LIGHT_NOT_SENSED_MASK
+        };
+        let trigger = if self.trigger { Self::TRIGGER_MASK } else { 0 };
+
+        Self::OPEN_BUS | light | trigger
+    }
+
+    /// Whether the beam recently drew something bright where the gun is aimed.
+    fn senses_light(&self, ppu: &Ppu) -> bool {
+        let (x, y) = match self.position {
+            Some(position) => position,
+            None => return false,
+        };
+
+        // Where the beam is, the dot being drawn is one behind the PPU's dot.
+        let scanline = ppu.scanline() as usize;
+        let beam_x = (ppu.dot() as usize).saturating_sub(1);
+        let passed = scanline > y || (scanline == y && beam_x > x);
+        if !passed || scanline >= y + Self::LIGHT_SCANLINES {
+            return false;
+        }
+
+        let frame = ppu.frame();
+        let rows = y.saturating_sub(Self::SENSOR_RADIUS)
+            ..=(y + Self::SENSOR_RADIUS).min(SCREEN_HEIGHT - 1);
+        let columns =
+            x.saturating_sub(Self::SENSOR_RADIUS)..=(x + Self::SENSOR_RADIUS).min(SCREEN_WIDTH - 1);
+
+        rows.flat_map(|row| columns.clone().map(move |column| (row, column)))
+            .any(|(row, column)| {
+                luminance(frame[row * SCREEN_WIDTH + column]) >= Self::LIGHT_THRESHOLD
+            })
+    }
+}
+
+/// Perceived brightness of a palette index, 0 to 255.
+fn luminance(index: u8) -> u32 {
+    let (r, g, b) = SYSTEM_PALETTE[(index & 0x3F) as usize];
+    (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ines::Mirroring;
+
+    /// Run the PPU until the beam reaches the dot.
+    fn run_to(ppu: &mut Ppu, scanline: u16, dot: u16) {
+        while ppu.scanline() != scanline || ppu.dot() != dot {
+            ppu.tick();
+        }
+    }
+
+    #[test]
+    fn test_light_sensing() {
+        // A white backdrop, rendering is off so the backdrop fills the screen.
+        let mut ppu = Ppu::new(vec![], Mirroring::Horizontal);
+        ppu.write_register(0x2006, 0x3F);
+        ppu.write_register(0x2006, 0x00);
+        ppu.write_register(0x2007, 0x30);
+
+        let mut zapper = Zapper::default();
+        zapper.set_position(Some((100, 100)));
+        zapper.set_trigger(true);
+
+        // Before the beam reaches the point.
+        run_to(&mut ppu, 90, 0);
+        assert_eq!(zapper.read(&ppu), 0x40 | 0x08 | 0x10);
+
+        // Just after.
+        run_to(&mut ppu, 101, 0);
+        assert_eq!(zapper.read(&ppu), 0x40 | 0x10);
+
+        // The sensor stops responding.
+        run_to(&mut ppu, 130, 0);
+        assert_eq!(zapper.read(&ppu) & 0x08, 0x08);
+
+        // Aimed off screen.
+        zapper.set_position(None);
+        run_to(&mut ppu, 101, 0);
+        assert_eq!(zapper.read(&ppu) & 0x08, 0x08);
+    }
+}
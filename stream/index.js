@@ -0,0 +1,132 @@
+// Plays a game streamed by `nes <rom> --stream <address>`, see src/frontend/stream.rs for the
+// messages. The keyboard is the first controller.
+
+// Same keys as the native frontend, by KeyboardEvent.code, in the order of the button bits.
+const KEYS = ["KeyX", "KeyZ", "ShiftRight", "Enter", "ArrowUp", "ArrowDown", "ArrowLeft", "ArrowRight"];
+
+// Audio is scheduled this far ahead to ride out uneven delivery.
+const AUDIO_LATENCY = 0.1;
+
+const FRAME = 0;
+const AUDIO = 1;
+
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+const status = document.getElementById("status");
+
+let socket = null;
+let audio = null;
+let image = null;
+let palette = null;
+let sampleRate = 0;
+let buttons = 0;
+let nextAudioTime = 0;
+
+// Frames are decompressed asynchronously, this keeps them in order.
+let decoding = Promise.resolve();
+
+function onKey(event, pressed) {
+  const button = KEYS.indexOf(event.code);
+  if (button < 0) {
+    return;
+  }
+
+  event.preventDefault();
+  const previous = buttons;
+  if (pressed) {
+    buttons |= 1 << button;
+  } else {
+    buttons &= ~(1 << button);
+  }
+
+  if (buttons !== previous && socket !== null && socket.readyState === WebSocket.OPEN) {
+    socket.send(new Uint8Array([0, buttons]));
+  }
+}
+
+document.addEventListener("keydown", (event) => onKey(event, true));
+document.addEventListener("keyup", (event) => onKey(event, false));
+
+async function drawFrame(compressed) {
+  const stream = new Blob([compressed]).stream().pipeThrough(new DecompressionStream("deflate"));
+  const indices = new Uint8Array(await new Response(stream).arrayBuffer());
+
+  for (let i = 0; i < indices.length; i++) {
+    const colour = (indices[i] & 0x3f) * 3;
+    image.data[i * 4] = palette[colour];
+    image.data[i * 4 + 1] = palette[colour + 1];
+    image.data[i * 4 + 2] = palette[colour + 2];
+    image.data[i * 4 + 3] = 255;
+  }
+  context.putImageData(image, 0, 0);
+}
+
+function playSamples(data) {
+  const samples = new Int16Array(data);
+  if (samples.length === 0) {
+    return;
+  }
+
+  const buffer = audio.createBuffer(1, samples.length, sampleRate);
+  const channel = buffer.getChannelData(0);
+  for (let i = 0; i < samples.length; i++) {
+    channel[i] = samples[i] / 32768;
+  }
+
+  const source = audio.createBufferSource();
+  source.buffer = buffer;
+  source.connect(audio.destination);
+
+  // Start over when playback fell behind, e.g. after the tab was in the background.
+  if (nextAudioTime < audio.currentTime) {
+    nextAudioTime = audio.currentTime + AUDIO_LATENCY;
+  }
+  source.start(nextAudioTime);
+  nextAudioTime += buffer.duration;
+}
+
+function onMessage(event) {
+  if (typeof event.data === "string") {
+    const hello = JSON.parse(event.data);
+    canvas.width = hello.width;
+    canvas.height = hello.height;
+    image = context.createImageData(hello.width, hello.height);
+    palette = hello.palette;
+    sampleRate = hello.sample_rate;
+    return;
+  }
+
+  const kind = new Uint8Array(event.data, 0, 1)[0];
+  const payload = event.data.slice(1);
+  if (kind === FRAME) {
+    decoding = decoding.then(() => drawFrame(payload));
+  } else if (kind === AUDIO) {
+    playSamples(payload);
+  }
+}
+
+document.getElementById("connect").addEventListener("click", () => {
+  // Browsers only start audio after a user gesture, the click is one.
+  if (audio === null) {
+    audio = new AudioContext();
+  }
+
+  if (socket !== null) {
+    socket.close();
+  }
+
+  const connection = new WebSocket(document.getElementById("address").value);
+  connection.binaryType = "arraybuffer";
+  connection.addEventListener("open", () => {
+    status.textContent = "Connected";
+    connection.send(new Uint8Array([0, buttons]));
+  });
+  connection.addEventListener("close", () => {
+    // Not when replaced by a new connection.
+    if (connection === socket) {
+      status.textContent = "Disconnected";
+    }
+  });
+  connection.addEventListener("message", onMessage);
+  socket = connection;
+});
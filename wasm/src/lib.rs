@@ -0,0 +1,100 @@
+/// The emulator in a browser, bindings for JavaScript through wasm-bindgen.
+///
+/// Build with `wasm-pack build --target web wasm` from the repository root, then serve the
+/// repository (e.g. `python3 -m http.server`) and open wasm/www/index.html. The page imports the
+/// generated wasm/pkg/nes_wasm.js.
+use nes::audio::Resampler;
+use nes::controller::ControllerState;
+use nes::ines::NesFile;
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes::Nes;
+use std::sync::Arc;
+use std::sync::Mutex;
+use wasm_bindgen::prelude::*;
+
+/// A console for the page to drive, one frame at a time.
+#[wasm_bindgen]
+pub struct WebNes {
+    nes: Nes,
+
+    /// Rate of the page's audio, what the APU output is resampled to.
+    sample_rate: u32,
+
+    /// Samples produced since the page last took them, at most a second's worth.
+    samples: Arc<Mutex<Vec<f32>>>,
+}
+
+#[wasm_bindgen]
+impl WebNes {
+    /// Audio is produced at `sample_rate`, usually the `sampleRate` of the page's AudioContext.
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate: u32) -> WebNes {
+        WebNes {
+            nes: Nes::new(),
+            sample_rate,
+            samples: Arc::default(),
+        }
+    }
+
+    /// Insert a cartridge from the contents of an iNES file and power on.
+    #[wasm_bindgen(js_name = loadRom)]
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), JsValue> {
+        let cartridge = NesFile::from_bytes(rom).map_err(|err| JsValue::from(err.to_string()))?;
+        self.nes.insert_cartridge(cartridge);
+        self.samples.lock().unwrap().clear();
+
+        let samples = self.samples.clone();
+        let limit = self.sample_rate as usize;
+        if let Some(cpu) = self.nes.cpu_mut() {
+            let mut resampler = Resampler::new(cpu.apu.sample_rate(), self.sample_rate);
+            cpu.apu.on_sample(move |sample| {
+                if let Some(sample) = resampler.push(sample) {
+                    let mut samples = samples.lock().unwrap();
+                    if samples.len() < limit {
+                        samples.push(sample);
+                    }
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Run until the next frame is complete, nothing happens without a cartridge.
+    #[wasm_bindgen(js_name = stepFrame)]
+    pub fn step_frame(&mut self) {
+        self.nes.step_frame();
+    }
+
+    /// The last complete frame as RGBA, `width()` by `height()`, ready for an ImageData.
+    #[wasm_bindgen(js_name = frameRgba)]
+    pub fn frame_rgba(&self) -> Vec<u8> {
+        self.nes.frame_rgba()
+    }
+
+    /// The audio produced since the last call, mono at the rate given to the constructor.
+    #[wasm_bindgen(js_name = takeSamples)]
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut *self.samples.lock().unwrap())
+    }
+
+    /// Hold the buttons on the controller of player 0 or 1. Each bit is a button, from bit 0: A,
+    /// B, Select, Start, Up, Down, Left and Right.
+    #[wasm_bindgen(js_name = setButtons)]
+    pub fn set_buttons(&mut self, player: usize, buttons: u8) -> Result<(), JsValue> {
+        if player > 1 {
+            return Err(JsValue::from(format!("Invalid player {}", player)));
+        }
+
+        self.nes.set_input(player, ControllerState(buttons));
+        Ok(())
+    }
+
+    pub fn width() -> usize {
+        SCREEN_WIDTH
+    }
+
+    pub fn height() -> usize {
+        SCREEN_HEIGHT
+    }
+}
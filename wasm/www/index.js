@@ -0,0 +1,109 @@
+// Runs the emulator on a canvas, with the keyboard as the first controller and Web Audio for
+// the sound. Needs the bindings built by wasm-pack into wasm/pkg.
+import init, { WebNes } from "../pkg/nes_wasm.js";
+
+const FRAME_RATE = 60.0988;
+
+// Same keys as the native frontend, by KeyboardEvent.code, in the order of the button bits.
+const KEYS = ["KeyX", "KeyZ", "ShiftRight", "Enter", "ArrowUp", "ArrowDown", "ArrowLeft", "ArrowRight"];
+
+// Audio is scheduled this far ahead to ride out uneven frames.
+const AUDIO_LATENCY = 0.05;
+
+await init();
+
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+const image = context.createImageData(WebNes.width(), WebNes.height());
+
+let audio = null;
+let nes = null;
+let buttons = 0;
+let nextAudioTime = 0;
+
+function onKey(event, pressed) {
+  const button = KEYS.indexOf(event.code);
+  if (button < 0) {
+    return;
+  }
+
+  event.preventDefault();
+  if (pressed) {
+    buttons |= 1 << button;
+  } else {
+    buttons &= ~(1 << button);
+  }
+}
+
+document.addEventListener("keydown", (event) => onKey(event, true));
+document.addEventListener("keyup", (event) => onKey(event, false));
+
+function playSamples() {
+  const samples = nes.takeSamples();
+  if (samples.length === 0) {
+    return;
+  }
+
+  const buffer = audio.createBuffer(1, samples.length, audio.sampleRate);
+  buffer.copyToChannel(samples, 0);
+
+  const source = audio.createBufferSource();
+  source.buffer = buffer;
+  source.connect(audio.destination);
+
+  // Start over when playback fell behind, e.g. after the tab was in the background.
+  if (nextAudioTime < audio.currentTime) {
+    nextAudioTime = audio.currentTime + AUDIO_LATENCY;
+  }
+  source.start(nextAudioTime);
+  nextAudioTime += buffer.duration;
+}
+
+let lastTime = null;
+let owed = 0;
+
+function frame(time) {
+  if (lastTime !== null) {
+    // At most a few frames are caught up on, the rest is dropped.
+    owed = Math.min(owed + ((time - lastTime) / 1000) * FRAME_RATE, 4);
+  }
+  lastTime = time;
+
+  nes.setButtons(0, buttons);
+  while (owed >= 1) {
+    nes.stepFrame();
+    owed -= 1;
+  }
+
+  image.data.set(nes.frameRgba());
+  context.putImageData(image, 0, 0);
+  playSamples();
+
+  requestAnimationFrame(frame);
+}
+
+document.getElementById("rom").addEventListener("change", async (event) => {
+  const file = event.target.files[0];
+  if (!file) {
+    return;
+  }
+
+  // Browsers only start audio after a user gesture, choosing the file is one.
+  if (audio === null) {
+    audio = new AudioContext();
+  }
+
+  const loaded = new WebNes(audio.sampleRate);
+  try {
+    loaded.loadRom(new Uint8Array(await file.arrayBuffer()));
+  } catch (error) {
+    alert(`Failed to load ${file.name}: ${error}`);
+    return;
+  }
+
+  const running = nes !== null;
+  nes = loaded;
+  if (!running) {
+    requestAnimationFrame(frame);
+  }
+});